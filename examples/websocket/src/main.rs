@@ -1,100 +1,160 @@
 use futures::prelude::*;
-use gotham::hyper::{upgrade::OnUpgrade, Body, HeaderMap, Response, StatusCode};
+use gotham::handler::{Handler, HandlerFuture, NewHandler};
+use gotham::hyper::header::AUTHORIZATION;
+use gotham::hyper::{upgrade::OnUpgrade, Body, HeaderMap, Response};
 use gotham::state::{request_id, FromState, State};
-
-mod ws;
+use gotham::websocket as ws;
+use std::io;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::time;
 
 fn main() {
     pretty_env_logger::init();
 
     let addr = "127.0.0.1:7878";
     println!("Listening on http://{}/", addr);
+    println!("Press Enter to shut down");
+
+    let drain = ws::Drain::new()
+        .timeout(Duration::from_secs(5))
+        .close_frame(ws::CloseCode::Away, "echo server shutting down");
+    let runtime = Runtime::new().expect("Failed to create runtime");
+    runtime.spawn(gotham::init_server(
+        addr,
+        Echo {
+            drain: drain.clone(),
+        },
+    ));
+
+    let _ = io::stdin().read_line(&mut String::new());
+    println!("Draining websocket connections");
+    drain.start();
+
+    runtime.block_on(async move {
+        drain.drained().await;
+        // give the connections a moment to send their close frames
+        time::sleep(Duration::from_millis(100)).await;
+    });
+}
 
-    gotham::start(addr, || Ok(handler));
+/// The echo server, whose handlers share the shutdown token `drain`.
+#[derive(Clone)]
+struct Echo {
+    drain: ws::Drain,
 }
 
-fn handler(mut state: State) -> (State, Response<Body>) {
-    let headers = HeaderMap::take_from(&mut state);
-    let on_upgrade = OnUpgrade::try_take_from(&mut state);
+impl NewHandler for Echo {
+    type Instance = Self;
+
+    fn new_handler(&self) -> gotham::anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
 
-    match on_upgrade {
-        Some(on_upgrade) if ws::requested(&headers) => {
-            let (response, ws) = match ws::accept(&headers, on_upgrade) {
-                Ok(res) => res,
-                Err(_) => return (state, bad_request()),
+impl Handler for Echo {
+    fn handle(self, state: State) -> Pin<Box<HandlerFuture>> {
+        handler(state, self.drain)
+    }
+}
+
+/// The per-connection limits of the echo server.
+fn limits() -> ws::Limits {
+    ws::Limits::new()
+        .max_message_size(64 * 1024)
+        .max_frame_size(16 * 1024)
+        .rate_limit(20, Duration::from_secs(1))
+        .keep_alive(Duration::from_secs(30), Duration::from_secs(60))
+}
+
+/// The upgrade policy of the echo server: only pages served by the example itself may connect,
+/// and clients which send credentials have to present the expected bearer token.
+fn upgrade_policy() -> ws::Upgrade {
+    ws::Upgrade::new()
+        .allow_origin("http://127.0.0.1:7878")
+        .protocols(&["echo"])
+        .limits(limits())
+        .authenticate(|headers| {
+            let authorized = match headers.get(AUTHORIZATION) {
+                Some(value) => value == "Bearer gotham",
+                None => true,
             };
+            future::ready(authorized).boxed()
+        })
+}
 
-            let req_id = request_id(&state).to_owned();
+fn handler(mut state: State, drain: ws::Drain) -> Pin<Box<HandlerFuture>> {
+    let headers = HeaderMap::take_from(&mut state);
+    let on_upgrade = OnUpgrade::try_take_from(&mut state);
 
-            tokio::spawn(async move {
-                match ws.await {
-                    Ok(ws) => connected(req_id, ws).await,
-                    Err(err) => {
-                        eprintln!("websocket init error: {}", err);
-                        Err(())
+    async move {
+        match on_upgrade {
+            Some(on_upgrade) if ws::requested(&headers) => {
+                let (response, ws) = match upgrade_policy().accept(headers, on_upgrade).await {
+                    Ok(res) => res,
+                    Err(rejection) => return Ok((state, rejection)),
+                };
+
+                let req_id = request_id(&state).to_owned();
+
+                tokio::spawn(async move {
+                    match ws.await {
+                        Ok(ws) => connected(req_id, ws, drain).await,
+                        Err(err) => {
+                            eprintln!("websocket init error: {}", err);
+                            Err(())
+                        }
                     }
-                }
-            });
+                });
 
-            (state, response)
+                Ok((state, response))
+            }
+            _ => Ok((state, Response::new(Body::from(INDEX_HTML)))),
         }
-        _ => (state, Response::new(Body::from(INDEX_HTML))),
     }
+    .boxed()
 }
 
-async fn connected<S>(req_id: String, stream: S) -> Result<(), ()>
+async fn connected<S>(req_id: String, stream: S, drain: ws::Drain) -> Result<(), ()>
 where
-    S: Stream<Item = Result<ws::Message, ws::Error>> + Sink<ws::Message, Error = ws::Error>,
+    S: Stream<Item = Result<ws::Message, ws::Error>> + Sink<ws::Message, Error = ws::Error> + Unpin,
 {
-    let (mut sink, mut stream) = stream.split();
-
     println!("Client {} connected", req_id);
 
-    while let Some(message) = stream
-        .next()
-        .await
-        .transpose()
-        .map_err(|error| println!("Websocket receive error: {}", error))?
-    {
+    ws::serve(stream, &limits(), &drain, |message| {
         println!("{}: {:?}", req_id, message);
-        match sink.send(message).await {
-            Ok(()) => (),
-            // this error indicates a successfully closed connection
-            Err(ws::Error::ConnectionClosed) => break,
-            Err(error) => {
-                println!("Websocket send error: {}", error);
-                return Err(());
-            }
-        }
-    }
+        Some(message)
+    })
+    .await
+    .map_err(|error| println!("Websocket error: {}", error))?;
 
     println!("Client {} disconnected", req_id);
     Ok(())
 }
 
-fn bad_request() -> Response<Body> {
-    Response::builder()
-        .status(StatusCode::BAD_REQUEST)
-        .body(Body::empty())
-        .unwrap()
-}
-
 const INDEX_HTML: &str = include_str!("index.html");
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::ws::{Message, Role};
     use gotham::hyper::{
-        header::{HeaderValue, CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE},
-        upgrade,
+        header::{
+            HeaderValue, CONNECTION, ORIGIN, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY,
+            SEC_WEBSOCKET_PROTOCOL, UPGRADE,
+        },
+        upgrade, StatusCode,
     };
     use gotham::plain::test::TestServer;
     use gotham::test::Server;
-    use tokio_tungstenite::WebSocketStream;
+    use gotham::websocket::{CloseCode, Message, Role, WebSocketStream};
 
     fn create_test_server() -> TestServer {
-        TestServer::new(|| Ok(handler)).expect("Failed to create TestServer")
+        create_draining_test_server(ws::Drain::new())
+    }
+
+    fn create_draining_test_server(drain: ws::Drain) -> TestServer {
+        TestServer::new(Echo { drain }).expect("Failed to create TestServer")
     }
 
     #[test]
@@ -189,4 +249,131 @@ mod test {
         let body = response.read_body().expect("Failed to read response body");
         assert!(body.is_empty());
     }
+
+    fn assert_closed_with(messages: Vec<Message>, expected: CloseCode) {
+        assert_drained_with(ws::Drain::new(), messages, expected);
+    }
+
+    fn assert_drained_with(drain: ws::Drain, messages: Vec<Message>, expected: CloseCode) {
+        let server = create_draining_test_server(drain.clone());
+        let client = server.client();
+
+        let mut request = client.get("ws://127.0.0.1:10000");
+        let headers = request.headers_mut();
+        headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+        headers.insert(SEC_WEBSOCKET_KEY, HeaderValue::from_static("QmF0bWFu"));
+        let response = request.perform().expect("Failed to perform request.");
+
+        server.run_future(async move {
+            let response: Response<_> = response.into();
+            let upgraded = upgrade::on(response)
+                .await
+                .expect("Failed to upgrade client websocket.");
+            let mut websocket_stream =
+                WebSocketStream::from_raw_socket(upgraded, Role::Client, None).await;
+
+            for message in messages {
+                if websocket_stream.send(message).await.is_err() {
+                    break;
+                }
+            }
+            drain.start();
+
+            loop {
+                match websocket_stream.next().await {
+                    Some(Ok(Message::Close(Some(frame)))) => {
+                        assert_eq!(frame.code, expected);
+                        break;
+                    }
+                    Some(Ok(_)) => continue,
+                    other => panic!("Expected close frame, got {:?}", other),
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn should_close_connection_on_oversized_message() {
+        let message = Message::Binary(vec![0; 64 * 1024 + 1]);
+        assert_closed_with(vec![message], CloseCode::Size);
+    }
+
+    #[test]
+    fn should_close_connection_after_drain_timeout() {
+        let drain = ws::Drain::new()
+            .timeout(Duration::from_millis(10))
+            .close_frame(CloseCode::Restart, "restarting");
+        let message = Message::Text("Hello".to_string());
+        assert_drained_with(drain, vec![message], CloseCode::Restart);
+    }
+
+    #[test]
+    fn should_close_connection_when_exceeding_rate_limit() {
+        let messages = (0..21).map(|i| Message::Text(i.to_string())).collect();
+        assert_closed_with(messages, CloseCode::Policy);
+    }
+
+    fn upgrade_status(origin: &'static str, authorization: Option<&'static str>) -> StatusCode {
+        let server = create_test_server();
+        let client = server.client();
+
+        let mut request = client.get("ws://127.0.0.1:10000");
+        let headers = request.headers_mut();
+        headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+        headers.insert(SEC_WEBSOCKET_KEY, HeaderValue::from_static("QmF0bWFu"));
+        headers.insert(ORIGIN, HeaderValue::from_static(origin));
+        if let Some(authorization) = authorization {
+            headers.insert(AUTHORIZATION, HeaderValue::from_static(authorization));
+        }
+
+        request
+            .perform()
+            .expect("Failed to perform request.")
+            .status()
+    }
+
+    #[test]
+    fn should_reject_upgrades_from_foreign_origins() {
+        assert_eq!(
+            upgrade_status("http://evil.example", None),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            upgrade_status("http://127.0.0.1:7878", None),
+            StatusCode::SWITCHING_PROTOCOLS
+        );
+    }
+
+    #[test]
+    fn should_reject_upgrades_with_invalid_credentials() {
+        assert_eq!(
+            upgrade_status("http://127.0.0.1:7878", Some("Bearer nope")),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            upgrade_status("http://127.0.0.1:7878", Some("Bearer gotham")),
+            StatusCode::SWITCHING_PROTOCOLS
+        );
+    }
+
+    #[test]
+    fn should_negotiate_subprotocol() {
+        let server = create_test_server();
+        let client = server.client();
+
+        let mut request = client.get("ws://127.0.0.1:10000");
+        let headers = request.headers_mut();
+        headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+        headers.insert(SEC_WEBSOCKET_KEY, HeaderValue::from_static("QmF0bWFu"));
+        headers.insert(
+            SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static("chat, echo"),
+        );
+
+        let response = request.perform().expect("Failed to perform request.");
+        assert_eq!(
+            response.headers().get(SEC_WEBSOCKET_PROTOCOL).unwrap(),
+            "echo"
+        );
+    }
 }
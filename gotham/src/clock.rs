@@ -0,0 +1,218 @@
+//! Defines the `Clock` used by the framework to read the current time, so that it can be replaced
+//! in tests or in environments with special timekeeping requirements.
+//!
+//! Framework components which depend on the current time, such as request timing, session expiry
+//! and request capturing, read it from the `SharedClock` stored in `State`, falling back to the
+//! system clock. A `SharedClock` is put into `State` by a `StateMiddleware`, or by
+//! `TestServer::with_clock`:
+//!
+//! ```rust
+//! # extern crate gotham;
+//! # extern crate hyper;
+//! #
+//! # use std::time::{Duration, UNIX_EPOCH};
+//! #
+//! # use gotham::clock::{self, ManualClock, SharedClock};
+//! # use gotham::router::builder::*;
+//! # use gotham::state::State;
+//! # use gotham::test::TestServer;
+//! #
+//! fn handler(state: State) -> (State, String) {
+//!     let now = clock::now(&state).duration_since(UNIX_EPOCH).unwrap();
+//!     (state, format!("{}", now.as_secs()))
+//! }
+//!
+//! # fn main() {
+//! let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_000_000_000));
+//! let router = build_simple_router(|route| route.get("/").to(handler));
+//! let test_server = TestServer::new(router)
+//!     .unwrap()
+//!     .with_clock(SharedClock::new(clock.clone()));
+//!
+//! clock.advance(Duration::from_secs(1));
+//! let response = test_server.client().get("http://localhost/").perform().unwrap();
+//! assert_eq!(response.read_utf8_body().unwrap(), "1000000001");
+//! # }
+//! ```
+
+use std::fmt::{self, Debug};
+use std::panic::RefUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::state::{FromState, State, StateData};
+
+/// A source of the current time.
+pub trait Clock: Send + Sync + RefUnwindSafe {
+    /// Returns the current time.
+    fn now(&self) -> SystemTime;
+
+    /// Returns the time elapsed since `earlier`, or zero if `earlier` is in the future, e.g.
+    /// because the clock was adjusted.
+    fn elapsed_since(&self, earlier: SystemTime) -> Duration {
+        self.now().duration_since(earlier).unwrap_or_default()
+    }
+
+    /// Returns the current instant of a monotonic clock, for measuring durations which must not
+    /// be affected by adjustments of the time returned by `now`.
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// The `Clock` which reads the system time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A `Clock` which only moves when it is set or advanced, for deterministic tests.
+///
+/// `ManualClock` is a cheaply cloneable handle, so a test can keep a clone to advance the clock
+/// which was handed to the framework. Its monotonic `instant` only moves when the clock is
+/// advanced.
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    time: Arc<Mutex<ManualTime>>,
+}
+
+#[derive(Debug)]
+struct ManualTime {
+    now: SystemTime,
+    instant: Instant,
+}
+
+impl ManualClock {
+    /// Creates a clock which is stopped at `now`.
+    pub fn new(now: SystemTime) -> ManualClock {
+        ManualClock {
+            time: Arc::new(Mutex::new(ManualTime {
+                now,
+                instant: Instant::now(),
+            })),
+        }
+    }
+
+    /// Sets the clock to `now`, which may also move it backwards.
+    pub fn set(&self, now: SystemTime) {
+        self.lock().now = now;
+    }
+
+    /// Moves the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        let mut time = self.lock();
+        time.now += duration;
+        time.instant += duration;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ManualTime> {
+        match self.time.lock() {
+            Ok(time) => time,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        self.lock().now
+    }
+
+    fn instant(&self) -> Instant {
+        self.lock().instant
+    }
+}
+
+/// A cheaply cloneable handle to a `Clock`, which is stored in `State` for the framework to read
+/// the current time from.
+#[derive(Clone)]
+pub struct SharedClock {
+    clock: Arc<dyn Clock>,
+}
+
+impl SharedClock {
+    /// Creates a handle to `clock`.
+    pub fn new<C>(clock: C) -> SharedClock
+    where
+        C: Clock + 'static,
+    {
+        SharedClock {
+            clock: Arc::new(clock),
+        }
+    }
+
+    /// Returns the clock stored in `state`, or the system clock if there is none.
+    pub fn from_state(state: &State) -> SharedClock {
+        SharedClock::try_borrow_from(state)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+impl Default for SharedClock {
+    fn default() -> SharedClock {
+        SharedClock::new(SystemClock)
+    }
+}
+
+impl Debug for SharedClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SharedClock").field(&self.now()).finish()
+    }
+}
+
+impl Clock for SharedClock {
+    fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    fn instant(&self) -> Instant {
+        self.clock.instant()
+    }
+}
+
+impl StateData for SharedClock {}
+
+/// Returns the current time of the clock stored in `state`, or the system time if there is none.
+pub fn now(state: &State) -> SystemTime {
+    match SharedClock::try_borrow_from(state) {
+        Some(clock) => clock.now(),
+        None => SystemTime::now(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn manual_clock_moves_when_told() {
+        let clock = ManualClock::new(UNIX_EPOCH);
+        let shared = SharedClock::new(clock.clone());
+
+        let instant = shared.instant();
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(shared.now(), UNIX_EPOCH + Duration::from_secs(5));
+        assert_eq!(shared.instant() - instant, Duration::from_secs(5));
+        assert_eq!(shared.elapsed_since(UNIX_EPOCH), Duration::from_secs(5));
+
+        let instant = shared.instant();
+        clock.set(UNIX_EPOCH);
+        assert_eq!(shared.instant(), instant);
+        assert_eq!(
+            shared.elapsed_since(UNIX_EPOCH + Duration::from_secs(5)),
+            Duration::ZERO
+        );
+
+        let mut state = State::new();
+        assert!(now(&state) > UNIX_EPOCH);
+        state.put(shared);
+        assert_eq!(now(&state), UNIX_EPOCH);
+    }
+}
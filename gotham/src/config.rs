@@ -0,0 +1,628 @@
+//! Declarative configuration of a Gotham server, which can be loaded from a file.
+//!
+//! A `ServerSpec` describes the addresses to listen on (optionally with TLS), connection limits,
+//! directories and files to serve, and redirects. It implements `Deserialize`, so it can be read
+//! with any serde format crate, such as `toml` or `serde_yaml`:
+//!
+//! ```toml
+//! threads = 4
+//!
+//! [[binds]]
+//! addr = "0.0.0.0:443"
+//! tls = { cert = "/etc/app/cert.pem", key = "/etc/app/key.pem" }
+//!
+//! [limits]
+//! keep_alive = false
+//! max_buf_size = 65536
+//!
+//! [[mounts]]
+//! path = "/static"
+//! dir = "/srv/app/static"
+//! cache_control = "public, max-age=3600"
+//! gzip = true
+//!
+//! [[redirects]]
+//! from = "/docs"
+//! to = "https://docs.example.com/"
+//! ```
+//!
+//! Setting `sd_notify = true` at the top level reports readiness to systemd once all addresses
+//! are bound, for services with `Type=notify`.
+//!
+//! The application routes are then drawn alongside the routes of the spec:
+//!
+//! ```rust,no_run
+//! # extern crate gotham;
+//! #
+//! # use gotham::config::ServerSpec;
+//! # use gotham::router::builder::*;
+//! # use gotham::state::State;
+//! #
+//! fn index(state: State) -> (State, &'static str) {
+//!     (state, "Hello, world!")
+//! }
+//!
+//! # fn load() -> ServerSpec { ServerSpec::default() }
+//! # fn main() -> std::io::Result<()> {
+//! // e.g. `toml::from_str(&std::fs::read_to_string("server.toml")?)?`
+//! let spec: ServerSpec = load();
+//!
+//! let router = spec.router(|route| {
+//!     route.get("/").to(index);
+//! });
+//!
+//! spec.start(router)
+//! # }
+//! ```
+
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+
+use futures::prelude::*;
+use hyper::server::conn::Http;
+use hyper::{Method, Uri};
+use log::{error, info};
+use serde_derive::Deserialize;
+use tokio::net::TcpListener;
+
+use crate::handler::assets::FileOptions;
+use crate::handler::{Handler, HandlerFuture, NewHandler};
+use crate::helpers::http::response::SafeRedirect;
+use crate::notify;
+use crate::pipeline::chain::PipelineHandleChain;
+use crate::router::builder::{build_simple_router, DefineSingleRoute, DrawRoutes, RouterBuilder};
+use crate::router::Router;
+use crate::state::State;
+use crate::{bind_server_with_protocol, new_runtime, tcp_listeners};
+
+#[cfg(feature = "rustls")]
+use tokio_rustls::TlsAcceptor;
+
+/// The configuration of a whole Gotham server.
+///
+/// See the [module documentation](index.html) for the file format.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSpec {
+    /// The addresses to listen on.
+    pub binds: Vec<BindSpec>,
+    /// The number of worker threads, defaulting to the number of CPUs.
+    pub threads: Option<usize>,
+    /// The limits applied to every connection.
+    pub limits: LimitsSpec,
+    /// Directories and files served as static assets.
+    pub mounts: Vec<MountSpec>,
+    /// Paths which redirect to another location.
+    pub redirects: Vec<RedirectSpec>,
+    /// Notifies the process supervisor once all addresses are bound, for systemd services with
+    /// `Type=notify`, see `gotham::notify`.
+    pub sd_notify: bool,
+}
+
+/// An address to listen on.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BindSpec {
+    /// The address, e.g. `0.0.0.0:8080` or `localhost:7878`. A listener is bound to every
+    /// address a host name resolves to.
+    pub addr: String,
+    /// Serves HTTPS rather than plain HTTP on this address, if present.
+    pub tls: Option<TlsSpec>,
+    /// Whether IPv6 listeners accept only IPv6 connections. By default they do if IPv4 addresses
+    /// are bound for the same `addr`, and follow the system default otherwise.
+    pub v6only: Option<bool>,
+}
+
+/// The certificate chain and private key used to serve HTTPS.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TlsSpec {
+    /// The PEM file containing the certificate chain.
+    pub cert: PathBuf,
+    /// The PEM file containing the private key, in PKCS #8 or RSA format.
+    pub key: PathBuf,
+}
+
+/// The limits applied to every connection, leaving the defaults of hyper in place when unset.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsSpec {
+    /// Whether HTTP/1 connections are kept alive between requests.
+    pub keep_alive: Option<bool>,
+    /// The maximum size of the buffer used to read HTTP/1 requests, bounding the request head.
+    pub max_buf_size: Option<usize>,
+    /// The maximum number of concurrent streams of an HTTP/2 connection.
+    pub max_concurrent_streams: Option<u32>,
+}
+
+/// A directory or file served as static assets.
+///
+/// Exactly one of `dir` and `file` must be set.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MountSpec {
+    /// The path the assets are served at.
+    pub path: String,
+    /// The directory serving every file below `path`.
+    pub dir: Option<PathBuf>,
+    /// The file served at `path`.
+    pub file: Option<PathBuf>,
+    /// The `Cache-Control` header sent with the assets.
+    pub cache_control: Option<String>,
+    /// Serves precompressed `.gz` files to clients accepting them.
+    pub gzip: bool,
+    /// Serves precompressed `.br` files to clients accepting them.
+    pub brotli: bool,
+}
+
+/// A path which redirects to another location.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RedirectSpec {
+    /// The path of the redirect.
+    pub from: String,
+    /// The location redirected to.
+    pub to: String,
+    /// Responds with `308 Permanent Redirect` if `true` (the default), and with
+    /// `307 Temporary Redirect` otherwise.
+    #[serde(default = "permanent_by_default")]
+    pub permanent: bool,
+}
+
+fn permanent_by_default() -> bool {
+    true
+}
+
+impl ServerSpec {
+    /// Draws the static mounts and redirects of the spec onto `route`, which allows them to share
+    /// the pipelines of the application routes.
+    ///
+    /// # Panics
+    ///
+    /// If a mount sets neither or both of `dir` and `file`.
+    pub fn draw_routes<C, P>(&self, route: &mut RouterBuilder<C, P>)
+    where
+        C: PipelineHandleChain<P> + Copy + Send + Sync + 'static,
+        P: std::panic::RefUnwindSafe + Send + Sync + 'static,
+    {
+        for mount in &self.mounts {
+            match (&mount.dir, &mount.file) {
+                (Some(dir), None) => {
+                    let path = format!("{}/*", mount.path.trim_end_matches('/'));
+                    route.get(&path).to_dir(mount.options(dir));
+                }
+                (None, Some(file)) => route.get(&mount.path).to_file(mount.options(file)),
+                _ => panic!(
+                    "mount of {} must set exactly one of `dir` and `file`",
+                    mount.path
+                ),
+            }
+        }
+
+        for redirect in &self.redirects {
+            let methods = vec![
+                Method::GET,
+                Method::HEAD,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+                Method::OPTIONS,
+            ];
+            route
+                .request(methods, &redirect.from)
+                .to_new_handler(Redirect::new(&redirect.to, redirect.permanent));
+        }
+    }
+
+    /// Builds a `Router` without pipelines, serving the routes of the spec along with the
+    /// application routes drawn by `f`.
+    pub fn router<F>(&self, f: F) -> Router
+    where
+        F: FnOnce(&mut RouterBuilder<(), ()>),
+    {
+        build_simple_router(|route| {
+            self.draw_routes(route);
+            f(route);
+        })
+    }
+
+    /// Creates the connection settings applying the limits of the spec.
+    fn protocol(&self) -> Http {
+        let mut protocol = Http::new();
+        if let Some(keep_alive) = self.limits.keep_alive {
+            protocol.http1_keep_alive(keep_alive);
+        }
+        if let Some(max_buf_size) = self.limits.max_buf_size {
+            protocol.max_buf_size(max_buf_size);
+        }
+        if let Some(max_concurrent_streams) = self.limits.max_concurrent_streams {
+            protocol.http2_max_concurrent_streams(max_concurrent_streams);
+        }
+        protocol
+    }
+
+    /// Binds all addresses of the spec, loading their TLS certificates. The listeners report the
+    /// bound addresses via `Listener::local_addr`.
+    ///
+    /// Fails if an address cannot be resolved or bound, or if a certificate cannot be loaded.
+    pub async fn bind(&self) -> io::Result<Vec<Listener>> {
+        let mut listeners = Vec::with_capacity(self.binds.len());
+        for bind in &self.binds {
+            listeners.extend(Listener::bind(bind).await?);
+        }
+        Ok(listeners)
+    }
+
+    /// Serves `new_handler` on all `listeners` until the runtime shuts down.
+    pub async fn serve<NH>(&self, listeners: Vec<Listener>, new_handler: NH)
+    where
+        NH: NewHandler + Clone + 'static,
+    {
+        let servers = listeners.into_iter().map(|listener| {
+            let server = listener.serve(self.protocol(), new_handler.clone());
+            tokio::spawn(server)
+        });
+
+        for server in future::join_all(servers).await {
+            if let Err(e) = server {
+                error!(target: "gotham::start", "Listener failed: {}", e);
+            }
+        }
+    }
+
+    /// Starts a Gotham application on all addresses of the spec, with the configured number of
+    /// threads.
+    ///
+    /// Only returns if an address cannot be bound, or a certificate cannot be loaded.
+    pub fn start<NH>(&self, new_handler: NH) -> io::Result<()>
+    where
+        NH: NewHandler + Clone + 'static,
+    {
+        self.start_with_ready(new_handler, |_| ())
+    }
+
+    /// Starts a Gotham application like `start`, calling `ready` with the bound addresses once
+    /// all of them are bound, before connections are accepted. If `sd_notify` is set, the process
+    /// supervisor is notified after `ready` returns.
+    ///
+    /// ```rust,no_run
+    /// # extern crate gotham;
+    /// #
+    /// # use gotham::config::ServerSpec;
+    /// #
+    /// # fn main() -> std::io::Result<()> {
+    /// # let spec = ServerSpec::default();
+    /// let router = spec.router(|_route| {});
+    ///
+    /// spec.start_with_ready(router, |addrs| {
+    ///     for addr in addrs {
+    ///         println!("listening on {}", addr);
+    ///     }
+    /// })
+    /// # }
+    /// ```
+    pub fn start_with_ready<NH, F>(&self, new_handler: NH, ready: F) -> io::Result<()>
+    where
+        NH: NewHandler + Clone + 'static,
+        F: FnOnce(&[SocketAddr]),
+    {
+        let runtime = new_runtime(self.threads.unwrap_or_else(num_cpus::get));
+        runtime.block_on(async {
+            let listeners = self.bind().await?;
+            let addrs = listeners
+                .iter()
+                .map(Listener::local_addr)
+                .collect::<io::Result<Vec<_>>>()?;
+            ready(&addrs);
+
+            if self.sd_notify {
+                if let Err(e) = notify::ready() {
+                    error!(target: "gotham::start", "Failed to notify the supervisor: {}", e);
+                }
+            }
+
+            self.serve(listeners, new_handler).await;
+            Ok(())
+        })
+    }
+}
+
+impl MountSpec {
+    fn options(&self, path: &Path) -> FileOptions {
+        let mut options = FileOptions::new(path);
+        if let Some(cache_control) = &self.cache_control {
+            options.with_cache_control(cache_control);
+        }
+        options
+            .with_gzip(self.gzip)
+            .with_brotli(self.brotli)
+            .build()
+    }
+}
+
+/// A bound address of a `ServerSpec`, ready to serve connections.
+pub struct Listener {
+    listener: TcpListener,
+    #[cfg(feature = "rustls")]
+    tls: Option<TlsAcceptor>,
+}
+
+impl Listener {
+    async fn bind(spec: &BindSpec) -> io::Result<Vec<Listener>> {
+        #[cfg(feature = "rustls")]
+        let tls = match &spec.tls {
+            Some(tls) => Some(tls.acceptor()?),
+            None => None,
+        };
+
+        #[cfg(not(feature = "rustls"))]
+        {
+            if spec.tls.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("TLS on {} requires the `rustls` feature", spec.addr),
+                ));
+            }
+        }
+
+        let listeners = tcp_listeners(spec.addr.as_str(), spec.v6only)
+            .await
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", spec.addr, e)))?;
+
+        Ok(listeners
+            .into_iter()
+            .map(|listener| Listener {
+                listener,
+                #[cfg(feature = "rustls")]
+                tls: tls.clone(),
+            })
+            .collect())
+    }
+
+    /// Returns the local address of the listener, e.g. to find the port bound for port `0`.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    async fn serve<NH>(self, protocol: Http, new_handler: NH)
+    where
+        NH: NewHandler + 'static,
+    {
+        let addr = self.listener.local_addr().unwrap();
+
+        #[cfg(feature = "rustls")]
+        {
+            if let Some(tls) = self.tls {
+                info!(target: "gotham::start", " Gotham listening on https://{}", addr);
+                let wrap = move |socket| {
+                    tls.accept(socket).map_err(|e| {
+                        error!(target: "gotham::tls", "TLS handshake error: {:?}", e);
+                    })
+                };
+                bind_server_with_protocol(vec![self.listener], new_handler, wrap, protocol).await
+            }
+        }
+
+        info!(target: "gotham::start", " Gotham listening on http://{}", addr);
+        let wrap = |socket| future::ok(socket);
+        bind_server_with_protocol(vec![self.listener], new_handler, wrap, protocol).await
+    }
+}
+
+#[cfg(feature = "rustls")]
+impl TlsSpec {
+    fn acceptor(&self) -> io::Result<TlsAcceptor> {
+        use std::sync::Arc;
+        use tokio_rustls::rustls::internal::pemfile::{
+            certs, pkcs8_private_keys, rsa_private_keys,
+        };
+        use tokio_rustls::rustls::{NoClientAuth, ServerConfig};
+
+        let invalid = |what: &str, path: &Path| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no valid {} in {}", what, path.display()),
+            )
+        };
+        let read = |path: &Path| File::open(path).map(BufReader::new);
+
+        let certs =
+            certs(&mut read(&self.cert)?).map_err(|_| invalid("certificate", &self.cert))?;
+        let mut keys = pkcs8_private_keys(&mut read(&self.key)?).unwrap_or_default();
+        if keys.is_empty() {
+            keys = rsa_private_keys(&mut read(&self.key)?).unwrap_or_default();
+        }
+        let key = keys
+            .into_iter()
+            .next()
+            .ok_or_else(|| invalid("private key", &self.key))?;
+
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config
+            .set_single_cert(certs, key)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+#[derive(Clone)]
+struct Redirect {
+    location: String,
+    permanent: bool,
+    safe_redirect: SafeRedirect,
+}
+
+impl Redirect {
+    /// Creates the redirect to `location`, allowing its host as it comes from the configuration
+    /// rather than from the request.
+    fn new(location: &str, permanent: bool) -> Redirect {
+        let safe_redirect = match location.parse::<Uri>() {
+            Ok(uri) => uri
+                .host()
+                .map(|host| SafeRedirect::new().with_allowed_host(host))
+                .unwrap_or_default(),
+            Err(_) => SafeRedirect::new(),
+        };
+
+        Redirect {
+            location: location.to_owned(),
+            permanent,
+            safe_redirect,
+        }
+    }
+}
+
+impl NewHandler for Redirect {
+    type Instance = Self;
+
+    fn new_handler(&self) -> anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl Handler for Redirect {
+    fn handle(self, state: State) -> Pin<Box<HandlerFuture>> {
+        let response = if self.permanent {
+            self.safe_redirect.permanent(&state, &self.location)
+        } else {
+            self.safe_redirect.temporary(&state, &self.location)
+        };
+        future::ok((state, response)).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::TestServer;
+    use hyper::header::LOCATION;
+    use hyper::StatusCode;
+
+    const SPEC: &str = r#"
+        threads = 2
+
+        [[binds]]
+        addr = "127.0.0.1:0"
+
+        [limits]
+        keep_alive = false
+
+        [[mounts]]
+        path = "/assets/"
+        dir = "resources/test/assets"
+        cache_control = "no-cache"
+
+        [[redirects]]
+        from = "/old"
+        to = "/new"
+
+        [[redirects]]
+        from = "/maintenance"
+        to = "https://status.example.com/"
+        permanent = false
+    "#;
+
+    #[test]
+    fn deserializes_server_spec() {
+        let spec: ServerSpec = toml::from_str(SPEC).unwrap();
+
+        assert_eq!(spec.threads, Some(2));
+        assert_eq!(
+            spec.binds,
+            vec![BindSpec {
+                addr: "127.0.0.1:0".to_owned(),
+                tls: None,
+                v6only: None,
+            }]
+        );
+        assert_eq!(spec.limits.keep_alive, Some(false));
+        assert_eq!(spec.limits.max_buf_size, None);
+        assert_eq!(
+            spec.mounts[0].dir,
+            Some(PathBuf::from("resources/test/assets"))
+        );
+        assert!(spec.redirects[0].permanent);
+        assert!(!spec.redirects[1].permanent);
+        assert!(!spec.sd_notify);
+
+        assert!(toml::from_str::<ServerSpec>("[[upstreams]]\nurl = \"http://a\"").is_err());
+    }
+
+    #[test]
+    fn serves_mounts_and_redirects_alongside_application_routes() {
+        let spec: ServerSpec = toml::from_str(SPEC).unwrap();
+        let router = spec.router(|route| {
+            route.get("/new").to(|state| (state, "new"));
+        });
+        let test_server = TestServer::new(router).unwrap();
+        let client = test_server.client();
+
+        let response = client
+            .get("http://localhost/assets/file.txt")
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["cache-control"], "no-cache");
+
+        let response = client.get("http://localhost/old").perform().unwrap();
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(response.headers()[LOCATION], "/new");
+
+        let response = client
+            .post("http://localhost/maintenance", "", mime::TEXT_PLAIN)
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(response.headers()[LOCATION], "https://status.example.com/");
+
+        let response = client.get("http://localhost/new").perform().unwrap();
+        assert_eq!(response.read_utf8_body().unwrap(), "new");
+    }
+
+    #[test]
+    fn binds_listeners() {
+        let mut spec: ServerSpec = toml::from_str(SPEC).unwrap();
+        let runtime = new_runtime(1);
+
+        let listeners = runtime.block_on(spec.bind()).unwrap();
+        assert_eq!(listeners.len(), 1);
+        assert_ne!(listeners[0].local_addr().unwrap().port(), 0);
+
+        spec.binds[0].tls = Some(TlsSpec {
+            cert: "src/tls/cert.pem".into(),
+            key: "src/tls/key.pem".into(),
+        });
+        assert!(runtime.block_on(spec.bind()).is_ok());
+
+        spec.binds[0].tls = Some(TlsSpec {
+            cert: "src/tls/cert.pem".into(),
+            key: "src/tls/cert.pem".into(),
+        });
+        let err = runtime.block_on(spec.bind()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn binds_ipv4_and_ipv6_on_the_same_port() {
+        let runtime = new_runtime(1);
+        let addrs: Vec<SocketAddr> = vec!["0.0.0.0:0".parse().unwrap(), "[::]:0".parse().unwrap()];
+
+        let listeners = runtime.block_on(tcp_listeners(&addrs[..], None)).unwrap();
+        let bound: Vec<_> = listeners.iter().map(|l| l.local_addr().unwrap()).collect();
+        assert!(bound[0].is_ipv4() && bound[1].is_ipv6());
+        assert_eq!(bound[0].port(), bound[1].port());
+        drop(listeners);
+
+        // a dual-stack IPv6 listener conflicts with the IPv4 one
+        let err = runtime
+            .block_on(tcp_listeners(&addrs[..], Some(false)))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+    }
+}
@@ -0,0 +1,202 @@
+//! Defines the environment an application is deployed in, used to keep development routes and
+//! middleware out of production.
+
+use std::env;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use log::warn;
+
+/// The name of the environment variable read by `Environment::current`.
+pub const GOTHAM_ENV: &str = "GOTHAM_ENV";
+
+/// The environment an application is deployed in.
+///
+/// Combined with `DrawRoutes::when` and `PipelineBuilder::add_when`, this determines which routes
+/// and middleware are part of an application:
+///
+/// ```rust
+/// # extern crate gotham;
+/// #
+/// # use gotham::environment::Environment;
+/// # use gotham::middleware::logger::RequestLogger;
+/// # use gotham::pipeline::new_pipeline;
+/// # use gotham::pipeline::single::single_pipeline;
+/// # use gotham::router::Router;
+/// # use gotham::router::builder::*;
+/// # use gotham::state::State;
+/// #
+/// # fn index(state: State) -> (State, &'static str) { (state, "index") }
+/// # fn debug_handler(state: State) -> (State, &'static str) { (state, "debug") }
+/// #
+/// fn router(env: Environment) -> Router {
+///     let (chain, pipelines) = single_pipeline(
+///         new_pipeline()
+///             .add_when(env.is_dev(), RequestLogger::new(log::Level::Debug))
+///             .build(),
+///     );
+///
+///     build_router(chain, pipelines, |route| {
+///         route.get("/").to(index);
+///         route.when(env.is_dev(), |route| {
+///             route.get("/__debug").to(debug_handler);
+///         });
+///     })
+/// }
+/// #
+/// # fn main() {
+/// #   let _ = router(Environment::current());
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Environment {
+    /// Local development, where debugging aids are enabled.
+    Development,
+    /// Automated tests.
+    Test,
+    /// A production deployment.
+    Production,
+}
+
+impl Environment {
+    /// Reads the environment from the `GOTHAM_ENV` environment variable, which accepts
+    /// `development` (or `dev`), `test` and `production` (or `prod`).
+    ///
+    /// Falls back to `Environment::Production` if the variable is unset or invalid, so that
+    /// development routes and middleware must be enabled explicitly.
+    pub fn current() -> Environment {
+        match env::var(GOTHAM_ENV) {
+            Ok(value) => value.parse().unwrap_or_else(|e| {
+                warn!("{}, assuming production", e);
+                Environment::Production
+            }),
+            Err(_) => Environment::Production,
+        }
+    }
+
+    /// Returns `true` for `Environment::Development`.
+    pub fn is_dev(self) -> bool {
+        self == Environment::Development
+    }
+
+    /// Returns `true` for `Environment::Test`.
+    pub fn is_test(self) -> bool {
+        self == Environment::Test
+    }
+
+    /// Returns `true` for `Environment::Production`.
+    pub fn is_production(self) -> bool {
+        self == Environment::Production
+    }
+}
+
+impl Display for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Environment::Development => "development",
+            Environment::Test => "test",
+            Environment::Production => "production",
+        })
+    }
+}
+
+impl FromStr for Environment {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Environment> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "development" | "dev" => Ok(Environment::Development),
+            "test" => Ok(Environment::Test),
+            "production" | "prod" => Ok(Environment::Production),
+            _ => Err(anyhow::anyhow!("unknown {} value {:?}", GOTHAM_ENV, s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::prelude::*;
+    use hyper::{Body, Response, StatusCode};
+    use std::pin::Pin;
+
+    use crate::handler::HandlerFuture;
+    use crate::middleware::{Middleware, NewMiddleware};
+    use crate::pipeline::new_pipeline;
+    use crate::pipeline::single::single_pipeline;
+    use crate::router::builder::*;
+    use crate::router::Router;
+    use crate::state::State;
+    use crate::test::TestServer;
+
+    #[test]
+    fn parses_environment_names() {
+        assert_eq!(
+            "dev".parse::<Environment>().unwrap(),
+            Environment::Development
+        );
+        assert_eq!(" Test ".parse::<Environment>().unwrap(), Environment::Test);
+        assert_eq!(
+            "PRODUCTION".parse::<Environment>().unwrap(),
+            Environment::Production
+        );
+        assert!("staging".parse::<Environment>().is_err());
+        assert_eq!(Environment::Development.to_string(), "development");
+    }
+
+    #[derive(Clone, Copy)]
+    struct Teapot;
+
+    impl NewMiddleware for Teapot {
+        type Instance = Self;
+
+        fn new_middleware(&self) -> anyhow::Result<Self> {
+            Ok(*self)
+        }
+    }
+
+    impl Middleware for Teapot {
+        fn call<Chain>(self, state: State, _chain: Chain) -> Pin<Box<HandlerFuture>>
+        where
+            Chain: FnOnce(State) -> Pin<Box<HandlerFuture>> + Send + 'static,
+        {
+            let response = Response::builder()
+                .status(StatusCode::IM_A_TEAPOT)
+                .body(Body::empty())
+                .unwrap();
+            future::ok((state, response)).boxed()
+        }
+    }
+
+    fn router(env: Environment) -> Router {
+        let (chain, pipelines) =
+            single_pipeline(new_pipeline().add_when(env.is_test(), Teapot).build());
+
+        build_router(chain, pipelines, |route| {
+            route.get("/").to(|state| (state, "index"));
+            route.when(env.is_dev(), |route| {
+                route.get("/__debug").to(|state| (state, "debug"));
+            });
+        })
+    }
+
+    fn status(env: Environment, uri: &str) -> StatusCode {
+        let test_server = TestServer::new(router(env)).unwrap();
+        test_server.client().get(uri).perform().unwrap().status()
+    }
+
+    #[test]
+    fn draws_routes_and_middleware_by_environment() {
+        let debug = "http://localhost/__debug";
+        assert_eq!(status(Environment::Development, debug), StatusCode::OK);
+        assert_eq!(
+            status(Environment::Production, debug),
+            StatusCode::NOT_FOUND
+        );
+
+        let index = "http://localhost/";
+        assert_eq!(status(Environment::Test, index), StatusCode::IM_A_TEAPOT);
+        assert_eq!(status(Environment::Production, index), StatusCode::OK);
+    }
+}
@@ -2,6 +2,7 @@
 //! type is populated by the `Router` while traversing the tree, and the `Route` implementation
 //! performs deserialization before dispatching to the `Handler`.
 
+use std::borrow::Cow;
 use std::error::Error;
 use std::fmt::{self, Display};
 use std::marker::PhantomData;
@@ -14,7 +15,7 @@ use serde::de::{
 use serde::forward_to_deserialize_any;
 
 use crate::helpers::http::request::query_string::QueryStringMapping;
-use crate::router::tree::segment::SegmentMapping;
+use crate::router::tree::segment::{GlobSegments, SegmentMapping};
 
 /// Describes the error cases which can result from deserializing a `ExtractorDeserializer` into a
 /// `PathExtractor` provided by the application.
@@ -67,6 +68,10 @@ pub(crate) enum ExtractorError {
     /// Multiple values were present, but the target type expected only a single value.
     MultipleValues,
 
+    /// The path segments captured by a glob segment were joined into a single value, but one of
+    /// them would traverse out of the captured path, e.g. a `..` segment.
+    UnsafePathSegment(String),
+
     /// An invalid internal state occurred where the deserializer attempted to access a value but
     /// there was no current item. This should never occur because the attempt to access a value
     /// implies that the deserializer already retrieved the key from the current item.
@@ -77,6 +82,12 @@ pub(crate) enum ExtractorError {
     /// a request for `/resource/abc` would result in a parse error trying to convert to `i32`.
     ParseError(String),
 
+    /// A field of the target type had no value, e.g. a missing query parameter.
+    MissingField(&'static str),
+
+    /// The value of a field could not be deserialized, for the reason given by the inner error.
+    InvalidField(String, Box<ExtractorError>),
+
     /// An error occurred, and a `Deserialize` impl provided a custom error message. This is used
     /// in the implementation of the `serde::de::Error` trait for external types to provide
     /// informative error messages.
@@ -89,7 +100,12 @@ pub(crate) enum ExtractorError {
 
 impl Display for ExtractorError {
     fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
-        out.write_fmt(format_args!("{:?}", self))
+        match self {
+            ExtractorError::UnsafePathSegment(segment) => {
+                out.write_fmt(format_args!("unsafe path segment {:?}", segment))
+            }
+            _ => out.write_fmt(format_args!("{:?}", self)),
+        }
     }
 }
 
@@ -106,6 +122,10 @@ impl de::Error for ExtractorError {
     {
         ExtractorError::Custom(format!("{}", t))
     }
+
+    fn missing_field(field: &'static str) -> ExtractorError {
+        ExtractorError::MissingField(field)
+    }
 }
 
 /// Implements one `Deserializer` function (`$trait_fn`) to parse a single value using the
@@ -223,16 +243,23 @@ where
     D: ExtractorDataSource<'a>,
 {
     data_source: D,
+    globs: GlobSegments,
+    name_fields: bool,
     phantom: PhantomData<&'a str>,
 }
 
-fn from_data_source<'de, D, T>(data_source: D) -> Result<T, ExtractorError>
+/// Deserializes a value of type `T` from `data_source`. The values of the keys in `globs`, which
+/// are the path segments captured by a glob segment, are joined with `/` when deserialized into a
+/// single string.
+fn from_data_source<'de, D, T>(data_source: D, globs: GlobSegments) -> Result<T, ExtractorError>
 where
     T: Deserialize<'de>,
     D: ExtractorDataSource<'de>,
 {
     let deserializer = ExtractorDeserializer {
         data_source,
+        globs,
+        name_fields: false,
         phantom: PhantomData,
     };
 
@@ -240,14 +267,20 @@ where
 }
 
 /// Deserializes a value of type `T`, from a set of path segments extracted while walking the route
-/// tree.
-pub(crate) fn from_segment_mapping<'de, T>(sm: SegmentMapping<'de>) -> Result<T, ExtractorError>
+/// tree, where the values of `globs` are the path segments captured by glob segments.
+pub(crate) fn from_segment_mapping<'de, T>(
+    sm: SegmentMapping<'de>,
+    globs: GlobSegments,
+) -> Result<T, ExtractorError>
 where
     T: Deserialize<'de>,
 {
-    from_data_source(IteratorAdaptor {
-        iter: sm.into_iter(),
-    })
+    from_data_source(
+        IteratorAdaptor {
+            iter: sm.into_iter(),
+        },
+        globs,
+    )
 }
 
 /// Deserializes a value of type `T` from a set of query parameters.
@@ -258,7 +291,24 @@ where
     T: Deserialize<'de>,
 {
     let iter = qsm.iter().map(|(k, v)| (k.as_str(), v));
-    from_data_source(IteratorAdaptor { iter })
+    from_data_source(IteratorAdaptor { iter }, GlobSegments::default())
+}
+
+/// Deserializes a value of type `T` from the fields of a form. Errors deserializing the value of
+/// a field are wrapped in `ExtractorError::InvalidField`, which names the field.
+pub(crate) fn from_form_mapping<'de, T>(qsm: &'de QueryStringMapping) -> Result<T, ExtractorError>
+where
+    T: Deserialize<'de>,
+{
+    let iter = qsm.iter().map(|(k, v)| (k.as_str(), v));
+    let deserializer = ExtractorDeserializer {
+        data_source: IteratorAdaptor { iter },
+        globs: GlobSegments::default(),
+        name_fields: true,
+        phantom: PhantomData,
+    };
+
+    T::deserialize(deserializer)
 }
 
 /// Implements a `Deserializer` for the full set of extracted path segments. This is the top level
@@ -278,6 +328,8 @@ where
     {
         visitor.visit_map(ExtractorDeserializerAccess {
             data_source: self.data_source,
+            globs: self.globs,
+            name_fields: self.name_fields,
             current: None,
             phantom: PhantomData,
         })
@@ -371,6 +423,8 @@ where
     D: ExtractorDataSource<'a>,
 {
     data_source: D,
+    globs: GlobSegments,
+    name_fields: bool,
     current: Option<(&'a str, D::ValueIterator)>,
     phantom: PhantomData<&'a str>,
 }
@@ -407,11 +461,17 @@ where
         V: DeserializeSeed<'de>,
     {
         match self.current.take() {
-            Some((_k, values)) => {
+            Some((k, values)) => {
                 let deserializer = DeserializeValues {
                     values: values.into_iter().map(convert_to_string_ref),
+                    join_values: self.globs.contains(k),
                 };
-                seed.deserialize(deserializer)
+                match seed.deserialize(deserializer) {
+                    Err(e) if self.name_fields => {
+                        Err(ExtractorError::InvalidField(k.to_owned(), Box::new(e)))
+                    }
+                    result => result,
+                }
             }
             None => Err(ExtractorError::NoCurrentItem),
         }
@@ -455,6 +515,7 @@ where
     I: Iterator<Item = &'de str>,
 {
     values: I,
+    join_values: bool,
 }
 
 /// Convert the value from a single-item list of percent-decoded strings by using
@@ -472,6 +533,41 @@ where
     })
 }
 
+/// Extracts a single value like `extract_single_value`, but joins multiple values with `/` when
+/// `join_values` is set for the segments captured by a glob. The captured segments must not
+/// traverse out of the joined path, as it is typically used as a file path, even if there is only
+/// one of them.
+fn extract_joined_value<'de, I>(
+    values: I,
+    join_values: bool,
+) -> Result<Cow<'de, str>, ExtractorError>
+where
+    I: Iterator<Item = &'de str>,
+{
+    let values: Vec<&str> = values.collect();
+    if values.len() > 1 && !join_values {
+        return Err(ExtractorError::MultipleValues);
+    }
+
+    // a single segment is checked too, as an encoded `/` may hide a traversal within it
+    if join_values {
+        if let Some(unsafe_segment) = values
+            .iter()
+            .find(|value| matches!(**value, "." | "..") || value.contains(&['/', '\\'][..]))
+        {
+            return Err(ExtractorError::UnsafePathSegment(
+                unsafe_segment.to_string(),
+            ));
+        }
+    }
+
+    match values.as_slice() {
+        [] => Err(ExtractorError::NoValues),
+        [value] => Ok(Cow::Borrowed(value)),
+        _ => Ok(Cow::Owned(values.join("/"))),
+    }
+}
+
 fn extract_single_value<'de, I>(mut values: I) -> Result<&'de str, ExtractorError>
 where
     I: Iterator<Item = &'de str>,
@@ -501,10 +597,23 @@ where
     single_value_type!(deserialize_u64, visit_u64);
     single_value_type!(deserialize_f32, visit_f32);
     single_value_type!(deserialize_f64, visit_f64);
-    single_value_type!(deserialize_string, visit_string);
-    single_value_type!(deserialize_byte_buf, visit_string);
     single_value_type!(deserialize_char, visit_char);
 
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let val = extract_joined_value(self.values, self.join_values)?;
+        visitor.visit_string(val.into_owned())
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_string(visitor)
+    }
+
     fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
@@ -542,8 +651,10 @@ where
     where
         V: Visitor<'de>,
     {
-        let val = extract_single_value(self.values)?;
-        visitor.visit_borrowed_str(val)
+        match extract_joined_value(self.values, self.join_values)? {
+            Cow::Borrowed(val) => visitor.visit_borrowed_str(val),
+            Cow::Owned(val) => visitor.visit_string(val),
+        }
     }
 
     fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
@@ -634,6 +745,7 @@ where
             Some(val) => {
                 let val = seed.deserialize(DeserializeValues {
                     values: vec![val].into_iter(),
+                    join_values: false,
                 })?;
                 Ok(Some(val))
             }
@@ -759,7 +871,7 @@ mod tests {
         sm.insert("char_val", vec![&char_val]);
         sm.insert("optional_val", vec![&optional_val]);
 
-        let p = from_segment_mapping::<SimpleValues>(sm).unwrap();
+        let p = from_segment_mapping::<SimpleValues>(sm, GlobSegments::default()).unwrap();
 
         assert_eq!(p.bool_val, true);
         assert_eq!(p.i8_val, 15);
@@ -899,7 +1011,7 @@ mod tests {
         let mut sm = SegmentMapping::new();
         sm.insert("bytes_val", vec![&bytes_val]);
 
-        let p = from_segment_mapping::<WithByteBuf>(sm).unwrap();
+        let p = from_segment_mapping::<WithByteBuf>(sm, GlobSegments::default()).unwrap();
 
         assert_eq!(&p.bytes_val[..], b"bytes");
     }
@@ -962,7 +1074,7 @@ mod tests {
         let mut sm = SegmentMapping::new();
         sm.insert("bytes_val", vec![&bytes_val]);
 
-        let p = from_segment_mapping::<WithBorrowedBytes>(sm).unwrap();
+        let p = from_segment_mapping::<WithBorrowedBytes>(sm, GlobSegments::default()).unwrap();
 
         assert_eq!(&p.bytes_val[..], b"borrowed_bytes");
     }
@@ -1022,7 +1134,7 @@ mod tests {
         let mut sm = SegmentMapping::new();
         sm.insert("str_val", vec![&str_val]);
 
-        let p = from_segment_mapping::<WithBorrowedString>(sm).unwrap();
+        let p = from_segment_mapping::<WithBorrowedString>(sm, GlobSegments::default()).unwrap();
 
         assert_eq!(p.str_val, "borrowed_str");
     }
@@ -1060,7 +1172,7 @@ mod tests {
         let mut sm = SegmentMapping::new();
         sm.insert("enum_val", vec![&enum_val]);
 
-        let p = from_segment_mapping::<WithEnum>(sm).unwrap();
+        let p = from_segment_mapping::<WithEnum>(sm, GlobSegments::default()).unwrap();
 
         assert_eq!(p.enum_val, MyEnumType::B);
     }
@@ -1097,7 +1209,7 @@ mod tests {
             vec![&seq_val_1, &seq_val_2, &seq_val_3, &seq_val_4, &seq_val_5],
         );
 
-        let p = from_segment_mapping::<WithSeq>(sm).unwrap();
+        let p = from_segment_mapping::<WithSeq>(sm, GlobSegments::default()).unwrap();
 
         assert_eq!(p.seq_val, vec![15, 16, 17, 18, 19]);
     }
@@ -1121,6 +1233,80 @@ mod tests {
         assert_eq!(p.seq_val, vec![15, 16, 17, 18, 19]);
     }
 
+    #[derive(Deserialize)]
+    struct WithGlob {
+        path: std::path::PathBuf,
+        rest: String,
+    }
+
+    #[test]
+    fn joined_glob_path_tests() {
+        let globs = GlobSegments::from_template("/assets/*path/:rest");
+        let css = PercentDecoded::new("css").unwrap();
+        let file = PercentDecoded::new("site.css").unwrap();
+        let parent = PercentDecoded::new("..").unwrap();
+
+        let mut sm = SegmentMapping::new();
+        sm.insert("path", vec![&css, &file]);
+        sm.insert("rest", vec![&file]);
+
+        let p = from_segment_mapping::<WithGlob>(sm, globs.clone()).unwrap();
+
+        assert_eq!(p.path, std::path::PathBuf::from("css/site.css"));
+        assert_eq!(p.rest, "site.css");
+
+        let mut sm = SegmentMapping::new();
+        sm.insert("path", vec![&css, &parent, &parent]);
+        sm.insert("rest", vec![&file]);
+
+        match from_segment_mapping::<WithGlob>(sm, globs.clone()) {
+            Err(ExtractorError::UnsafePathSegment(segment)) => assert_eq!(segment, ".."),
+            _ => panic!("expected the path to be rejected"),
+        }
+
+        // a single segment hiding the traversal behind encoded separators, which are allowed in
+        // other segments
+        let encoded = PercentDecoded::new("..%2F..%2Fsecret").unwrap();
+        let mut sm = SegmentMapping::new();
+        sm.insert("path", vec![&css]);
+        sm.insert("rest", vec![&encoded]);
+        assert_eq!(
+            from_segment_mapping::<WithGlob>(sm, globs.clone())
+                .unwrap()
+                .rest,
+            "../../secret"
+        );
+
+        let mut sm = SegmentMapping::new();
+        sm.insert("path", vec![&encoded]);
+        sm.insert("rest", vec![&file]);
+
+        match from_segment_mapping::<WithGlob>(sm, globs.clone()) {
+            Err(ExtractorError::UnsafePathSegment(segment)) => {
+                assert_eq!(segment, "../../secret")
+            }
+            _ => panic!("expected the path to be rejected"),
+        }
+    }
+
+    #[test]
+    fn multiple_values_query_tests() {
+        let mut qsm = QueryStringMapping::new();
+        qsm.insert(
+            "path".to_owned(),
+            vec![
+                FormUrlDecoded::new("a").unwrap(),
+                FormUrlDecoded::new("b").unwrap(),
+            ],
+        );
+        qsm.insert("rest".to_owned(), vec![FormUrlDecoded::new("c").unwrap()]);
+
+        match from_query_string_mapping::<WithGlob>(&qsm) {
+            Err(ExtractorError::MultipleValues) => (),
+            _ => panic!("expected multiple query values to be rejected"),
+        }
+    }
+
     #[derive(Deserialize, Eq, PartialEq, Debug)]
     struct IntWrapper(i32);
 
@@ -1136,7 +1322,7 @@ mod tests {
         let mut sm = SegmentMapping::new();
         sm.insert("wrapped_int_val", vec![&wrapped_int_val]);
 
-        let p = from_segment_mapping::<WithNewtypeStruct>(sm).unwrap();
+        let p = from_segment_mapping::<WithNewtypeStruct>(sm, GlobSegments::default()).unwrap();
 
         assert_eq!(p.wrapped_int_val, IntWrapper(100));
     }
@@ -75,6 +75,61 @@ use crate::state::{State, StateData};
 /// #   let body = response.read_utf8_body().unwrap();
 /// #   assert_eq!(body, "id = 1551, slug = ten-reasons-serde-is-amazing");
 /// # }
+/// ```
+///
+/// # Glob segments
+///
+/// A glob segment such as `*rest` captures the remaining path segments. They can be extracted
+/// into a `Vec<String>` field, or into a `String` or `PathBuf` field, which receives the segments
+/// joined with `/`. The value is rejected with a `400 Bad Request` if a captured segment is `.` or
+/// `..` or contains a percent-encoded path separator, so that it can't escape the captured path,
+/// even if the glob captured a single segment.
+///
+/// ```rust
+/// # extern crate gotham;
+/// # #[macro_use]
+/// # extern crate gotham_derive;
+/// # extern crate hyper;
+/// # #[macro_use]
+/// # extern crate serde_derive;
+/// #
+/// # use std::path::PathBuf;
+/// # use hyper::StatusCode;
+/// # use gotham::state::{FromState, State};
+/// # use gotham::router::builder::*;
+/// # use gotham::test::TestServer;
+/// #
+/// #[derive(Deserialize, StateData, StaticResponseExtender)]
+/// struct AssetPath {
+///     path: PathBuf,
+/// }
+///
+/// fn asset(mut state: State) -> (State, String) {
+///     let AssetPath { path } = AssetPath::take_from(&mut state);
+///     (state, format!("serving {}", path.display()))
+/// }
+///
+/// # fn main() {
+/// let router = build_simple_router(|route| {
+///     route
+///         .get("/assets/*path")
+///         .with_path_extractor::<AssetPath>()
+///         .to(asset);
+/// });
+///
+/// let test_server = TestServer::new(router).unwrap();
+/// let client = test_server.client();
+///
+/// let response = client.get("http://localhost/assets/css/site.css").perform().unwrap();
+/// assert_eq!(response.read_utf8_body().unwrap(), "serving css/site.css");
+///
+/// let response = client.get("http://localhost/assets/css/../../secret").perform().unwrap();
+/// assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+///
+/// let response = client.get("http://localhost/assets/..%2F..%2Fsecret").perform().unwrap();
+/// assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+/// # }
+/// ```
 pub trait PathExtractor<B>:
     for<'de> Deserialize<'de> + StaticResponseExtender<ResBody = B> + StateData
 where
@@ -23,6 +23,7 @@ use tokio::io::{AsyncRead, ReadBuf};
 
 use self::accepted_encoding::accepted_encodings;
 use crate::handler::{Handler, HandlerError, HandlerFuture, NewHandler};
+use crate::middleware::precondition::if_none_match;
 use crate::router::response::extender::StaticResponseExtender;
 use crate::state::{FromState, State, StateData};
 
@@ -322,8 +323,8 @@ fn normalize_path(path: &Path) -> PathBuf {
 fn not_modified(metadata: &Metadata, headers: &HeaderMap) -> bool {
     // If-None-Match header takes precedence over If-Modified-Since
     match headers.get(IF_NONE_MATCH) {
-        Some(_) => entity_tag(&metadata)
-            .map(|etag| headers.get_all(IF_NONE_MATCH).iter().any(|v| v == &etag))
+        Some(_) => entity_tag(metadata)
+            .and_then(|etag| if_none_match(headers, &etag))
             .unwrap_or(false),
         _ => headers
             .get(IF_MODIFIED_SINCE)
@@ -359,6 +360,14 @@ pub struct FilePathExtractor {
     parts: Vec<String>,
 }
 
+#[cfg(feature = "image-transform")]
+impl FilePathExtractor {
+    /// Returns the segments of the matched path.
+    pub(crate) fn parts(&self) -> &[String] {
+        &self.parts
+    }
+}
+
 impl StateData for FilePathExtractor {}
 
 impl StaticResponseExtender for FilePathExtractor {
@@ -1,15 +1,22 @@
 use futures::future::FusedFuture;
 use std::fmt::{Debug, Display};
 use std::future::Future;
+use std::io;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
+use hyper::header::{HeaderMap, HeaderValue, IntoHeaderName, CONTENT_LANGUAGE, RETRY_AFTER};
 use hyper::{Body, Response, StatusCode};
 use log::{trace, warn};
 
-use crate::handler::IntoResponse;
-use crate::helpers::http::response::create_empty_response;
-use crate::state::{request_id, State};
+use crate::handler::formatter::format_error;
+use crate::handler::validation::validation_response;
+use crate::handler::{
+    ErrorFormatter, ErrorMessageCatalog, IntoResponse, ProblemDetails, ValidationError,
+};
+use crate::helpers::http::response::{create_empty_response, create_response};
+use crate::state::{request_id, FromState, State};
 
 /// Describes an error which occurred during handler execution, and allows the creation of a HTTP
 /// `Response`.
@@ -23,11 +30,97 @@ pub struct HandlerError {
     //   fn set_customized_response_body<F: FnOnce(&State) -> R, R: IntoResponse>(&mut self, state: &State, f: F)
     // or by method of trait (MapHandlerErrorToCustomizedResponse):
     //   fn map_err_to_response<F: FnOnce(&State) -> R, R: IntoResponse>(self, state: &State, f: F) -> Result<T, HandlerError>
-    customized_response_body: Option<Box<Response<Body>>>,
+    customized_response_body: Option<CustomizedResponse>,
+    // When `true`, and no customized response body is set, the response is generated as an RFC 7807
+    // `application/problem+json` body. Set by `with_problem_details`.
+    problem_details: bool,
+    // `false` while the status code is the default chosen by `From<E>`, so that an
+    // `ErrorStatusMap` may still replace it. Set whenever the status code is chosen explicitly.
+    status_explicit: bool,
+    // Boxed, so that the rarely used metadata doesn't grow the `Err` variant of every
+    // `HandlerResult`.
+    metadata: Box<Metadata>,
+}
+
+// Optional metadata of a `HandlerError`.
+#[derive(Debug, Default)]
+struct Metadata {
+    // Headers added to the generated response, whichever way it is generated.
+    headers: HeaderMap,
+    // Whether the request may succeed when retried. Set by `set_retryable` and `with_retry_after`.
+    retryable: bool,
+    // The delay sent as `Retry-After` header when the error is retryable.
+    retry_after: Option<Duration>,
+    // The key of the message looked up in the `ErrorMessageCatalog` for the response body. Set by
+    // `with_message_key`.
+    message_key: Option<String>,
+}
+
+// A customized response, which is either rendered when it is set, or from the `State` once the
+// `HandlerError` is converted into a response.
+enum CustomizedResponse {
+    Rendered(Box<Response<Body>>),
+    Deferred(Box<DeferredResponse>),
+}
+
+type DeferredResponse = dyn FnOnce(&State) -> Response<Body> + Send + Sync;
+
+impl CustomizedResponse {
+    // Renders `body` once the error is converted into a response.
+    fn deferred<R>(body: R) -> CustomizedResponse
+    where
+        R: IntoResponse + Send + Sync + 'static,
+    {
+        CustomizedResponse::Deferred(Box::new(move |state: &State| body.into_response(state)))
+    }
+
+    // Deferred bodies are served with the status code of the `HandlerError` as it is when
+    // rendered, so that a status code set after the body is kept.
+    fn render(self, state: &State, status_code: StatusCode) -> Response<Body> {
+        match self {
+            CustomizedResponse::Rendered(response) => *response,
+            CustomizedResponse::Deferred(render) => {
+                let mut response = render(state);
+                *response.status_mut() = status_code;
+                response
+            }
+        }
+    }
+}
+
+impl Debug for CustomizedResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CustomizedResponse::Rendered(response) => {
+                f.debug_tuple("Rendered").field(response).finish()
+            }
+            CustomizedResponse::Deferred(_) => f.write_str("Deferred"),
+        }
+    }
 }
 
 /// Convert a generic `anyhow::Error` into a `HandlerError`, similar as you would a concrete error
 /// type with `into_handler_error()`.
+///
+/// The status code is classified from the cause, defaulting to `500 Internal Server Error`:
+///
+/// | Cause | Status code |
+/// |-------|-------------|
+/// | `ValidationError` | `422 Unprocessable Entity` |
+/// | `hyper::Error` which is a timeout | `408 Request Timeout` |
+/// | `hyper::Error` caused by an unparsable message | `400 Bad Request` |
+/// | `hyper::Error` caused by a closed or incomplete message | `499 Client Closed Request` |
+/// | `hyper::Error` caused by misuse of hyper | `500 Internal Server Error` |
+/// | any other `hyper::Error` | `502 Bad Gateway` |
+/// | `io::Error` of kind `TimedOut` | `408 Request Timeout` |
+/// | `io::Error` of kind `BrokenPipe`, `ConnectionReset` or `ConnectionAborted` | `499 Client Closed Request` |
+/// | `io::Error` of kind `ConnectionRefused` | `502 Bad Gateway` |
+///
+/// `499 Client Closed Request` is not a registered status code, but the convention introduced by
+/// nginx for requests which the client abandoned before the response was sent.
+///
+/// The classified status code is not explicit, so it is replaced by the status code registered
+/// for the cause in an `ErrorStatusMap`, e.g. one passed to `Router::with_error_status_map`.
 impl<E> From<E> for HandlerError
 where
     E: Into<anyhow::Error> + Display,
@@ -35,14 +128,150 @@ where
     fn from(error: E) -> HandlerError {
         trace!(" converting Error to HandlerError: {}", error);
 
+        let cause = error.into();
+        let status_code = classify(&cause);
+
         HandlerError {
-            status_code: StatusCode::INTERNAL_SERVER_ERROR,
-            cause: error.into(),
+            status_code,
+            cause,
             customized_response_body: None,
+            problem_details: false,
+            status_explicit: false,
+            metadata: Box::default(),
         }
     }
 }
 
+/// The status code of `499 Client Closed Request`, as used by nginx.
+fn client_closed_request() -> StatusCode {
+    StatusCode::from_u16(499).expect("499 is a valid status code")
+}
+
+/// Classifies the status code of a `HandlerError` from its cause, see `From<E>`.
+fn classify(cause: &anyhow::Error) -> StatusCode {
+    if cause.is::<ValidationError>() {
+        StatusCode::UNPROCESSABLE_ENTITY
+    } else if let Some(err) = cause.downcast_ref::<hyper::Error>() {
+        if err.is_timeout() {
+            StatusCode::REQUEST_TIMEOUT
+        } else if err.is_parse() {
+            StatusCode::BAD_REQUEST
+        } else if err.is_canceled()
+            || err.is_closed()
+            || err.is_incomplete_message()
+            || err.is_body_write_aborted()
+        {
+            client_closed_request()
+        } else if err.is_user() {
+            StatusCode::INTERNAL_SERVER_ERROR
+        } else {
+            StatusCode::BAD_GATEWAY
+        }
+    } else if let Some(err) = cause.downcast_ref::<io::Error>() {
+        match err.kind() {
+            io::ErrorKind::TimedOut => StatusCode::REQUEST_TIMEOUT,
+            io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted => client_closed_request(),
+            io::ErrorKind::ConnectionRefused => StatusCode::BAD_GATEWAY,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
+/// A status code and a response body, which can be returned as an error from a handler and is
+/// converted into a `HandlerError` by the `?` operator.
+///
+/// The body is rendered with the `State` of the request once the error is converted into a
+/// response, and the status code of the rendered response is replaced by the given one. A plain
+/// `(StatusCode, R)` tuple can't be converted this way, as it would overlap with the conversion
+/// of arbitrary errors into a `HandlerError`.
+///
+/// ```rust
+/// # extern crate gotham;
+/// # extern crate hyper;
+/// #
+/// # use gotham::handler::{ErrorResponse, HandlerError};
+/// # use gotham::router::builder::*;
+/// # use gotham::state::State;
+/// # use gotham::test::TestServer;
+/// # use hyper::StatusCode;
+/// #
+/// fn check_quota(used: u32) -> Result<(), ErrorResponse<&'static str>> {
+///     if used < 10 {
+///         Ok(())
+///     } else {
+///         Err(ErrorResponse(StatusCode::TOO_MANY_REQUESTS, "quota exceeded"))
+///     }
+/// }
+///
+/// async fn handler(_state: &mut State) -> Result<&'static str, HandlerError> {
+///     check_quota(10)?;
+///     Ok("welcome")
+/// }
+///
+/// # fn main() {
+/// let test_server = TestServer::new(build_simple_router(|route| {
+///     route.get("/").to_async_borrowing(handler);
+/// }))
+/// .unwrap();
+///
+/// let response = test_server.client().get("http://localhost/").perform().unwrap();
+/// assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+/// assert_eq!(response.read_utf8_body().unwrap(), "quota exceeded");
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ErrorResponse<R>(pub StatusCode, pub R);
+
+impl<R> From<ErrorResponse<R>> for HandlerError
+where
+    R: IntoResponse + Send + Sync + 'static,
+{
+    fn from(ErrorResponse(status_code, body): ErrorResponse<R>) -> HandlerError {
+        HandlerError::from_response(status_code, body)
+    }
+}
+
+/// Returns early from a handler with a `HandlerError` of the given status code, whose cause is
+/// created from the remaining arguments like `anyhow!`.
+///
+/// The enclosing function must return a `Result` whose error type can be created from a
+/// `HandlerError`.
+///
+/// ```rust
+/// # #[macro_use]
+/// # extern crate gotham;
+/// # extern crate hyper;
+/// #
+/// # use gotham::handler::HandlerError;
+/// # use hyper::StatusCode;
+/// #
+/// fn find_widget(id: u64) -> Result<&'static str, HandlerError> {
+///     if id != 1 {
+///         bail_with_status!(StatusCode::NOT_FOUND, "no widget with id {}", id);
+///     }
+///     Ok("sprocket")
+/// }
+///
+/// # fn main() {
+/// let err = find_widget(2).unwrap_err();
+/// assert_eq!(err.status(), StatusCode::NOT_FOUND);
+/// assert_eq!(err.cause().to_string(), "no widget with id 2");
+/// # }
+/// ```
+#[macro_export]
+macro_rules! bail_with_status {
+    ($status:expr, $($arg:tt)+) => {
+        return ::std::result::Result::Err(::std::convert::From::from(
+            $crate::handler::HandlerError::from($crate::anyhow::anyhow!($($arg)+))
+                .with_status($status),
+        ))
+    };
+}
+
 // pub trait CusTrait<T>{
 //     fn cus_trait(&self);
 // }
@@ -65,6 +294,30 @@ impl HandlerError {
         self.status_code
     }
 
+    /// Creates a `HandlerError` which is served as the given status code and response body.
+    ///
+    /// The body is rendered with the `State` of the request once the error is converted into a
+    /// response, and the status code of the rendered response is replaced by `status_code`, or by
+    /// the status code set afterwards, e.g. with `with_status`.
+    pub fn from_response<R>(status_code: StatusCode, body: R) -> HandlerError
+    where
+        R: IntoResponse + Send + Sync + 'static,
+    {
+        trace!(
+            " converting customized response to HandlerError: {}",
+            status_code
+        );
+
+        HandlerError {
+            status_code,
+            cause: anyhow::anyhow!("handler responded with {}", status_code),
+            customized_response_body: Some(CustomizedResponse::deferred(body)),
+            problem_details: false,
+            status_explicit: true,
+            metadata: Box::default(),
+        }
+    }
+
     /// Customize the response body when error occurs, when it is not `None`, it will be served as response.
     pub fn set_customized_response_body<F: FnOnce(&State) -> R, R: IntoResponse>(
         &mut self,
@@ -73,10 +326,27 @@ impl HandlerError {
     ) {
         let body = f(state).into_response(state);
         self.status_code = body.status(); // update status_code by the customized response.
-        self.customized_response_body = Some(Box::new(body));
+        self.status_explicit = true;
+        self.customized_response_body = Some(CustomizedResponse::Rendered(Box::new(body)));
         // self
     }
 
+    /// Async variant of `set_customized_response_body`, for error bodies which have to be
+    /// rendered by a template engine or fetched from a cache.
+    ///
+    /// The closure receives the `State` synchronously and returns the future which produces the
+    /// response body. The `State` is borrowed mutably, which keeps the returned future `Send` so
+    /// that it can be awaited within a handler.
+    pub async fn set_customized_response_body_async<F, Fut, R>(&mut self, state: &mut State, f: F)
+    where
+        F: FnOnce(&State) -> Fut,
+        Fut: Future<Output = R>,
+        R: IntoResponse,
+    {
+        let body = f(state).await;
+        self.set_customized_response_body(state, move |_| body);
+    }
+
     // pub fn map_customized_response_body<F: FnOnce(E, &State) -> R, R: IntoResponse, E: Into<anyhow::Error> + Display>(&mut self, err: E, state: &State, f: F) {
     //     let body = f(err, state).into_response(state);
     //     self.status_code = body.status(); // update status_code by the customized response.
@@ -84,6 +354,12 @@ impl HandlerError {
     //     // self
     // }
 
+    /// Returns `true` if a customized response body has been set for this `HandlerError`, which
+    /// will be served instead of the default response.
+    pub fn has_customized_response_body(&self) -> bool {
+        self.customized_response_body.is_some()
+    }
+
     /// Sets the HTTP status code of the response which is generated by the `IntoResponse`
     /// implementation.
     ///
@@ -121,10 +397,335 @@ impl HandlerError {
     pub fn with_status(self, status_code: StatusCode) -> HandlerError {
         HandlerError {
             status_code,
+            status_explicit: true,
             ..self
         }
     }
 
+    /// Creates a `HandlerError` with the status code `400 Bad Request`.
+    pub fn bad_request<E>(error: E) -> HandlerError
+    where
+        E: Into<anyhow::Error> + Display,
+    {
+        HandlerError::from(error).with_status(StatusCode::BAD_REQUEST)
+    }
+
+    /// Creates a `HandlerError` with the status code `401 Unauthorized`.
+    pub fn unauthorized<E>(error: E) -> HandlerError
+    where
+        E: Into<anyhow::Error> + Display,
+    {
+        HandlerError::from(error).with_status(StatusCode::UNAUTHORIZED)
+    }
+
+    /// Creates a `HandlerError` with the status code `403 Forbidden`.
+    pub fn forbidden<E>(error: E) -> HandlerError
+    where
+        E: Into<anyhow::Error> + Display,
+    {
+        HandlerError::from(error).with_status(StatusCode::FORBIDDEN)
+    }
+
+    /// Creates a `HandlerError` with the status code `404 Not Found`.
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # use gotham::anyhow::anyhow;
+    /// # use gotham::handler::HandlerError;
+    /// # use gotham::hyper::StatusCode;
+    /// fn find_widget(id: u64) -> Result<&'static str, HandlerError> {
+    ///     match id {
+    ///         1 => Ok("sprocket"),
+    ///         _ => Err(HandlerError::not_found(anyhow!("no widget with id {}", id))),
+    ///     }
+    /// }
+    ///
+    /// # fn main() {
+    /// assert_eq!(find_widget(2).unwrap_err().status(), StatusCode::NOT_FOUND);
+    /// # }
+    /// ```
+    pub fn not_found<E>(error: E) -> HandlerError
+    where
+        E: Into<anyhow::Error> + Display,
+    {
+        HandlerError::from(error).with_status(StatusCode::NOT_FOUND)
+    }
+
+    /// Creates a `HandlerError` with the status code `409 Conflict`.
+    pub fn conflict<E>(error: E) -> HandlerError
+    where
+        E: Into<anyhow::Error> + Display,
+    {
+        HandlerError::from(error).with_status(StatusCode::CONFLICT)
+    }
+
+    /// Requests that the response generated by the `IntoResponse` implementation is an RFC 7807
+    /// `application/problem+json` body, as produced by `into_problem_details`, rather than an empty
+    /// body.
+    ///
+    /// A customized response body, if set, still takes precedence.
+    ///
+    /// ```rust
+    /// # extern crate futures;
+    /// # extern crate gotham;
+    /// # extern crate hyper;
+    /// #
+    /// # use std::pin::Pin;
+    /// #
+    /// # use futures::prelude::*;
+    /// # use gotham::anyhow::anyhow;
+    /// # use gotham::handler::{HandlerError, HandlerFuture, APPLICATION_PROBLEM_JSON};
+    /// # use gotham::state::State;
+    /// # use gotham::test::TestServer;
+    /// # use hyper::header::CONTENT_TYPE;
+    /// # use hyper::StatusCode;
+    /// #
+    /// fn handler(state: State) -> Pin<Box<HandlerFuture>> {
+    ///     let handler_error = HandlerError::from(anyhow!("widget 42 does not exist"))
+    ///         .with_status(StatusCode::NOT_FOUND)
+    ///         .with_problem_details();
+    ///
+    ///     future::err((state, handler_error)).boxed()
+    /// }
+    ///
+    /// # fn main() {
+    /// #
+    /// let test_server = TestServer::new(|| Ok(handler)).unwrap();
+    /// let response = test_server.client().get("http://example.com/").perform().unwrap();
+    /// assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    /// assert_eq!(
+    ///     response.headers().get(CONTENT_TYPE).unwrap(),
+    ///     APPLICATION_PROBLEM_JSON
+    /// );
+    ///
+    /// let body = response.read_utf8_body().unwrap();
+    /// assert!(body.contains(r#""detail":"widget 42 does not exist""#));
+    /// #
+    /// # }
+    /// ```
+    pub fn with_problem_details(self) -> HandlerError {
+        HandlerError {
+            problem_details: true,
+            ..self
+        }
+    }
+
+    /// Converts this `HandlerError` into an RFC 7807 `ProblemDetails` value.
+    ///
+    /// The `title` and `status` are taken from the status code, the `detail` from the chain of
+    /// causes and the `instance` from the request id.
+    ///
+    pub fn into_problem_details(self, state: &State) -> ProblemDetails {
+        ProblemDetails::new(self.status_code)
+            .with_detail(format!("{:#}", self.cause))
+            .with_instance(request_id(state))
+    }
+
+    /// Wraps the cause of this `HandlerError` with additional context, keeping the status code and
+    /// any customized response body.
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # use gotham::anyhow::anyhow;
+    /// # use gotham::handler::HandlerError;
+    /// # use gotham::hyper::StatusCode;
+    /// # fn main() {
+    /// let err = HandlerError::from(anyhow!("connection refused"))
+    ///     .with_status(StatusCode::SERVICE_UNAVAILABLE)
+    ///     .context("loading user profile");
+    ///
+    /// assert_eq!(err.status(), StatusCode::SERVICE_UNAVAILABLE);
+    /// assert_eq!(format!("{}", err.cause()), "loading user profile");
+    /// assert_eq!(format!("{:#}", err.cause()), "loading user profile: connection refused");
+    /// # }
+    /// ```
+    pub fn context<C>(self, context: C) -> HandlerError
+    where
+        C: Display + Send + Sync + 'static,
+    {
+        HandlerError {
+            cause: self.cause.context(context),
+            ..self
+        }
+    }
+
+    /// Wraps the cause of this `HandlerError` with additional context which is lazily evaluated,
+    /// keeping the status code and any customized response body.
+    pub fn with_context<F>(self, f: F) -> HandlerError
+    where
+        F: FnOnce() -> String,
+    {
+        let context = f();
+        self.context(context)
+    }
+
+    /// Returns the underlying cause of this `HandlerError`.
+    pub fn cause(&self) -> &anyhow::Error {
+        &self.cause
+    }
+
+    /// Adds a header to the response which is generated for this `HandlerError`, e.g.
+    /// `Retry-After` or `WWW-Authenticate`.
+    ///
+    /// The header is appended, so calling this repeatedly with the same name sends multiple values.
+    ///
+    /// ```rust
+    /// # extern crate futures;
+    /// # extern crate gotham;
+    /// # extern crate hyper;
+    /// #
+    /// # use std::pin::Pin;
+    /// #
+    /// # use futures::prelude::*;
+    /// # use gotham::anyhow::anyhow;
+    /// # use gotham::handler::{HandlerError, HandlerFuture};
+    /// # use gotham::state::State;
+    /// # use gotham::test::TestServer;
+    /// # use hyper::header::{HeaderValue, RETRY_AFTER};
+    /// # use hyper::StatusCode;
+    /// #
+    /// fn handler(state: State) -> Pin<Box<HandlerFuture>> {
+    ///     let handler_error = HandlerError::from(anyhow!("down for maintenance"))
+    ///         .with_status(StatusCode::SERVICE_UNAVAILABLE)
+    ///         .with_header(RETRY_AFTER, HeaderValue::from_static("120"));
+    ///
+    ///     future::err((state, handler_error)).boxed()
+    /// }
+    ///
+    /// # fn main() {
+    /// #
+    /// let test_server = TestServer::new(|| Ok(handler)).unwrap();
+    /// let response = test_server.client().get("http://example.com/").perform().unwrap();
+    /// assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    /// assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "120");
+    /// #
+    /// # }
+    /// ```
+    pub fn with_header<K>(mut self, name: K, value: HeaderValue) -> HandlerError
+    where
+        K: IntoHeaderName,
+    {
+        self.metadata.headers.append(name, value);
+        self
+    }
+
+    /// Adds all of the given headers to the response which is generated for this `HandlerError`.
+    pub fn with_headers(mut self, headers: HeaderMap) -> HandlerError {
+        append_headers(&mut self.metadata.headers, headers);
+        self
+    }
+
+    /// Returns the headers which are added to the response generated for this `HandlerError`.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.metadata.headers
+    }
+
+    /// Removes the headers which would be added to the generated response.
+    pub(crate) fn take_headers(&mut self) -> HeaderMap {
+        std::mem::take(&mut self.metadata.headers)
+    }
+
+    /// Marks whether the failed request may succeed when it is retried, e.g. because a rate limit
+    /// was exceeded or an upstream service is temporarily unavailable.
+    pub fn set_retryable(&mut self, retryable: bool) {
+        self.metadata.retryable = retryable;
+    }
+
+    /// Returns `true` if the failed request may succeed when it is retried.
+    pub fn is_retryable(&self) -> bool {
+        self.metadata.retryable
+    }
+
+    /// Marks the error as retryable after the given delay, which is sent to the client as
+    /// `Retry-After` header, rounded up to whole seconds. A `Retry-After` header added via
+    /// `with_header`, or set by a customized response, takes precedence.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # extern crate hyper;
+    /// #
+    /// # use std::time::Duration;
+    /// #
+    /// # use gotham::anyhow::anyhow;
+    /// # use gotham::handler::{HandlerError, HandlerResult};
+    /// # use gotham::router::builder::*;
+    /// # use gotham::state::State;
+    /// # use gotham::test::TestServer;
+    /// # use hyper::header::RETRY_AFTER;
+    /// # use hyper::StatusCode;
+    /// #
+    /// async fn handler(state: State) -> HandlerResult {
+    ///     let err = HandlerError::from(anyhow!("rate limit exceeded"))
+    ///         .with_status(StatusCode::TOO_MANY_REQUESTS)
+    ///         .with_retry_after(Duration::from_millis(1500));
+    ///     Err((state, err))
+    /// }
+    ///
+    /// # fn main() {
+    /// let router = build_simple_router(|route| route.get("/").to_async(handler));
+    /// let test_server = TestServer::new(router).unwrap();
+    /// let response = test_server.client().get("http://example.com/").perform().unwrap();
+    /// assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    /// assert_eq!(response.headers()[RETRY_AFTER], "2");
+    /// # }
+    /// ```
+    pub fn with_retry_after(mut self, delay: Duration) -> HandlerError {
+        self.metadata.retryable = true;
+        self.metadata.retry_after = Some(delay);
+        self
+    }
+
+    /// Returns the delay after which the failed request may be retried, if it is retryable and a
+    /// delay was given.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.metadata
+            .retry_after
+            .filter(|_| self.metadata.retryable)
+    }
+
+    /// Sets the key of a localized message, which is looked up in the `ErrorMessageCatalog` stored
+    /// in `State` when the error is converted into a response.
+    ///
+    /// The message is rendered by the `ErrorFormatter` of the `Router`, or sent as a `text/plain`
+    /// body if there is none, or as the `detail` of problem details, along with a
+    /// `Content-Language` header. A customized response body takes precedence, and the response
+    /// is generated as usual if there is no catalog or no message for the key. See
+    /// `ErrorMessageCatalog` for an example.
+    pub fn with_message_key<K>(mut self, key: K) -> HandlerError
+    where
+        K: Into<String>,
+    {
+        self.metadata.message_key = Some(key.into());
+        self
+    }
+
+    /// Returns the key of the localized message set by `with_message_key`.
+    pub fn message_key(&self) -> Option<&str> {
+        self.metadata.message_key.as_deref()
+    }
+
+    /// Returns the localized message set by `with_message_key`, resolved against the
+    /// `ErrorMessageCatalog` in `state`. Used by an `ErrorFormatter` to render the message.
+    pub fn localized_message<'s>(&self, state: &'s State) -> Option<&'s str> {
+        self.resolve_message(state).map(|(_, text)| text)
+    }
+
+    /// Resolves the localized message against the catalog in `state`, returning its language and
+    /// text.
+    fn resolve_message<'s>(&self, state: &'s State) -> Option<(&'s str, &'s str)> {
+        let key = self.metadata.message_key.as_deref()?;
+        let headers = HeaderMap::try_borrow_from(state)?;
+        ErrorMessageCatalog::try_borrow_from(state)?.resolve(key, headers)
+    }
+
+    /// Returns `true` if the status code was chosen explicitly, rather than being the default
+    /// `500 Internal Server Error` assigned when the error was converted.
+    pub(crate) fn is_status_explicit(&self) -> bool {
+        self.status_explicit
+    }
+
     /// Attempt to downcast the cause by reference.
     pub fn downcast_cause_ref<E>(&self) -> Option<&E>
     where
@@ -138,12 +739,111 @@ impl HandlerError {
     where
         E: Display + Debug + Send + Sync + 'static,
     {
-        self.cause.downcast_mut()
+        self.cause.downcast_mut()
+    }
+
+    /// Replaces the status code and response body of this `HandlerError` if its cause is an `E`,
+    /// leaving it untouched otherwise.
+    ///
+    /// The cause is kept, so that it is still available for logging and reporting. This allows
+    /// errors of a library to be translated in a single place, such as a middleware, rather than
+    /// in every handler.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # extern crate hyper;
+    /// #
+    /// # use std::io;
+    /// # use std::pin::Pin;
+    /// #
+    /// # use futures::prelude::*;
+    /// # use gotham::handler::{HandlerError, HandlerFuture};
+    /// # use gotham::middleware::Middleware;
+    /// # use gotham::state::State;
+    /// # use hyper::StatusCode;
+    /// #
+    /// #[derive(Clone, NewMiddleware)]
+    /// struct TranslateIoErrors;
+    ///
+    /// impl Middleware for TranslateIoErrors {
+    ///     fn call<Chain>(self, state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    ///     where
+    ///         Chain: FnOnce(State) -> Pin<Box<HandlerFuture>> + Send + 'static,
+    ///     {
+    ///         chain(state)
+    ///             .map_err(|(state, err)| {
+    ///                 let err = err.map_cause(|e: &io::Error| match e.kind() {
+    ///                     io::ErrorKind::NotFound => (StatusCode::NOT_FOUND, "no such file"),
+    ///                     _ => (StatusCode::SERVICE_UNAVAILABLE, "storage unavailable"),
+    ///                 });
+    ///                 (state, err)
+    ///             })
+    ///             .boxed()
+    ///     }
+    /// }
+    /// #
+    /// # #[macro_use]
+    /// # extern crate gotham_derive;
+    /// # fn main() {
+    /// let err = HandlerError::from(io::Error::from(io::ErrorKind::NotFound))
+    ///     .map_cause(|_: &std::fmt::Error| (StatusCode::BAD_REQUEST, "unrelated"))
+    ///     .map_cause(|_: &io::Error| (StatusCode::NOT_FOUND, "no such file"));
+    /// assert_eq!(err.status(), StatusCode::NOT_FOUND);
+    /// # let _ = TranslateIoErrors;
+    /// # }
+    /// ```
+    pub fn map_cause<E, F, R>(mut self, f: F) -> HandlerError
+    where
+        E: Display + Debug + Send + Sync + 'static,
+        F: FnOnce(&E) -> (StatusCode, R),
+        R: IntoResponse + Send + Sync + 'static,
+    {
+        if let Some(cause) = self.cause.downcast_ref::<E>() {
+            let (status_code, body) = f(cause);
+            trace!(" replacing response of {} cause: {}", status_code, cause);
+
+            self.status_code = status_code;
+            self.status_explicit = true;
+            self.customized_response_body = Some(CustomizedResponse::deferred(body));
+        }
+
+        self
     }
 }
 
 impl IntoResponse for HandlerError {
     fn into_response(self, state: &State) -> Response<Body> {
+        self.render(state, None)
+    }
+}
+
+impl HandlerError {
+    /// Like `into_response`, but renders the full cause chain as a plain text body, unless a
+    /// customized body or problem details were requested. Used by `Router` when
+    /// `expose_error_details` is enabled.
+    pub(crate) fn into_detailed_response(mut self, state: &State) -> Response<Body> {
+        if self.customized_response_body.is_none() && !self.problem_details {
+            let status = self.status_code;
+            let body = format!("{:?}", self.cause);
+            self.set_customized_response_body(state, |_| (status, mime::TEXT_PLAIN_UTF_8, body));
+        }
+
+        self.into_response(state)
+    }
+
+    /// Like `into_response`, but renders errors without a customized body or problem details with
+    /// `formatter`. Used by `Router` when an `ErrorFormatter` is registered.
+    pub(crate) fn into_formatted_response(
+        self,
+        state: &State,
+        formatter: &dyn ErrorFormatter,
+    ) -> Response<Body> {
+        self.render(state, Some(formatter))
+    }
+
+    fn render(mut self, state: &State, formatter: Option<&dyn ErrorFormatter>) -> Response<Body> {
         warn!(
             "[{}] HandlerError is generating {} {} response: {}",
             request_id(state),
@@ -154,10 +854,73 @@ impl IntoResponse for HandlerError {
             self.cause
         );
 
-        if let Some(rsp) = self.customized_response_body {
-            *rsp
+        let headers = self.take_headers();
+        let retry_after = self.retry_after();
+        let message = self.resolve_message(state);
+
+        let mut response = if let Some(customized) = self.customized_response_body.take() {
+            customized.render(state, self.status_code)
+        } else if self.problem_details {
+            let details = self.into_problem_details(state);
+            let details = match message {
+                Some((_, text)) => details.with_detail(text),
+                None => details,
+            };
+            with_content_language(details.into_response(state), message)
+        } else if let Some(errors) = self.cause.downcast_ref::<ValidationError>() {
+            validation_response(state, self.status_code, errors)
+        } else if let Some(formatter) = formatter {
+            with_content_language(format_error(state, &self, formatter), message)
+        } else if let Some((_, text)) = message {
+            let response = create_response(
+                state,
+                self.status_code,
+                mime::TEXT_PLAIN_UTF_8,
+                text.to_owned(),
+            );
+            with_content_language(response, message)
         } else {
             create_empty_response(state, self.status_code)
+        };
+
+        append_headers(response.headers_mut(), headers);
+        insert_retry_after(response.headers_mut(), retry_after);
+        response
+    }
+}
+
+/// Sets the `Content-Language` header to the language of a localized message.
+fn with_content_language(
+    mut response: Response<Body>,
+    message: Option<(&str, &str)>,
+) -> Response<Body> {
+    if let Some(language) = message.and_then(|(language, _)| language.parse().ok()) {
+        response.headers_mut().insert(CONTENT_LANGUAGE, language);
+    }
+    response
+}
+
+/// Sets the `Retry-After` header to `retry_after`, rounded up to whole seconds, unless `target`
+/// already has one.
+pub(crate) fn insert_retry_after(target: &mut HeaderMap, retry_after: Option<Duration>) {
+    if let Some(delay) = retry_after {
+        if !target.contains_key(RETRY_AFTER) {
+            let seconds = delay.as_secs() + u64::from(delay.subsec_nanos() > 0);
+            target.insert(RETRY_AFTER, seconds.into());
+        }
+    }
+}
+
+/// Appends all values of `headers` to `target`, keeping existing values.
+pub(crate) fn append_headers(target: &mut HeaderMap, headers: HeaderMap) {
+    let mut name = None;
+    for (key, value) in headers {
+        // `HeaderMap::into_iter` only yields the name for the first value of each header.
+        if key.is_some() {
+            name = key;
+        }
+        if let Some(ref name) = name {
+            target.append(name, value);
         }
     }
 }
@@ -189,6 +952,70 @@ impl IntoResponse for HandlerError {
 pub trait MapHandlerError<T> {
     /// Equivalent of `map_err(|err| HandlerError::from(err).with_status(status_code))`.
     fn map_err_with_status(self, status_code: StatusCode) -> Result<T, HandlerError>;
+
+    /// Equivalent of
+    /// `map_err(|err| HandlerError::from(err).with_status(status_code).context(context))`.
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # use gotham::anyhow::anyhow;
+    /// # use gotham::handler::{HandlerError, MapHandlerError};
+    /// # use gotham::hyper::StatusCode;
+    /// fn handler() -> Result<(), HandlerError> {
+    ///     let result: Result<(), _> = Err(anyhow!("no such row"));
+    ///     result.context_with_status(StatusCode::NOT_FOUND, "loading widget 42")?;
+    ///     unreachable!()
+    /// }
+    ///
+    /// # fn main() {
+    /// let err = handler().unwrap_err();
+    /// assert_eq!(err.status(), StatusCode::NOT_FOUND);
+    /// assert_eq!(format!("{:#}", err.cause()), "loading widget 42: no such row");
+    /// # }
+    /// ```
+    fn context_with_status<C>(self, status_code: StatusCode, context: C) -> Result<T, HandlerError>
+    where
+        C: Display + Send + Sync + 'static;
+
+    /// Equivalent of `map_err_with_status(StatusCode::BAD_REQUEST)`.
+    fn map_err_bad_request(self) -> Result<T, HandlerError>
+    where
+        Self: Sized,
+    {
+        self.map_err_with_status(StatusCode::BAD_REQUEST)
+    }
+
+    /// Equivalent of `map_err_with_status(StatusCode::UNAUTHORIZED)`.
+    fn map_err_unauthorized(self) -> Result<T, HandlerError>
+    where
+        Self: Sized,
+    {
+        self.map_err_with_status(StatusCode::UNAUTHORIZED)
+    }
+
+    /// Equivalent of `map_err_with_status(StatusCode::FORBIDDEN)`.
+    fn map_err_forbidden(self) -> Result<T, HandlerError>
+    where
+        Self: Sized,
+    {
+        self.map_err_with_status(StatusCode::FORBIDDEN)
+    }
+
+    /// Equivalent of `map_err_with_status(StatusCode::NOT_FOUND)`.
+    fn map_err_not_found(self) -> Result<T, HandlerError>
+    where
+        Self: Sized,
+    {
+        self.map_err_with_status(StatusCode::NOT_FOUND)
+    }
+
+    /// Equivalent of `map_err_with_status(StatusCode::CONFLICT)`.
+    fn map_err_conflict(self) -> Result<T, HandlerError>
+    where
+        Self: Sized,
+    {
+        self.map_err_with_status(StatusCode::CONFLICT)
+    }
 }
 
 impl<T, E> MapHandlerError<T> for Result<T, E>
@@ -202,9 +1029,20 @@ where
                 status_code,
                 cause: err.into(),
                 customized_response_body: None,
+                problem_details: false,
+                status_explicit: true,
+                metadata: Box::default(),
             }
         })
     }
+
+    fn context_with_status<C>(self, status_code: StatusCode, context: C) -> Result<T, HandlerError>
+    where
+        C: Display + Send + Sync + 'static,
+    {
+        self.map_err_with_status(status_code)
+            .map_err(|err| err.context(context))
+    }
 }
 
 /// more concrete version of Result<T,E> with E=handlerError
@@ -213,9 +1051,18 @@ impl<T> MapHandlerError<T> for Result<T, HandlerError> {
         self.map_err(|mut err| {
             trace!(" converting Error to HandlerError: {:?}", err);
             err.status_code = status_code;
+            err.status_explicit = true;
             err
         })
     }
+
+    fn context_with_status<C>(self, status_code: StatusCode, context: C) -> Result<T, HandlerError>
+    where
+        C: Display + Send + Sync + 'static,
+    {
+        self.map_err_with_status(status_code)
+            .map_err(|err| err.context(context))
+    }
 }
 
 /// # customize response for HandlerError
@@ -305,7 +1152,9 @@ where
             let mut handler_error = HandlerError::from(e);
             let rsp = body.into_response(state);
             handler_error.status_code = rsp.status(); // update status_code by the customized response.
-            handler_error.customized_response_body = Some(Box::new(rsp));
+            handler_error.status_explicit = true;
+            handler_error.customized_response_body =
+                Some(CustomizedResponse::Rendered(Box::new(rsp)));
             handler_error
         })
     }
@@ -369,6 +1218,89 @@ where
         })
     }
 }
+/// Async variant of `MapHandlerErrorWithCustomizedResponse`, for error bodies which have to be
+/// rendered by a template engine or fetched from a cache.
+///
+/// ```rust
+/// # extern crate futures;
+/// # extern crate gotham;
+/// # extern crate mime;
+/// #
+/// # use gotham::anyhow::anyhow;
+/// # use gotham::handler::{HandlerError, IntoResponse, MapHandlerErrorWithCustomizedResponseAsync};
+/// # use gotham::hyper::StatusCode;
+/// # use gotham::router::builder::*;
+/// # use gotham::state::State;
+/// # use gotham::test::TestServer;
+/// #
+/// async fn render_error_page() -> String {
+///     "<h1>Something went wrong</h1>".to_owned()
+/// }
+///
+/// async fn handler(state: &mut State) -> Result<impl IntoResponse, HandlerError> {
+///     let result: Result<&str, _> = Err(anyhow!("just a test"));
+///     let body = result
+///         .map_err_with_customized_response_async(state, |_state| async {
+///             let page = render_error_page().await;
+///             (StatusCode::SERVICE_UNAVAILABLE, mime::TEXT_HTML_UTF_8, page)
+///         })
+///         .await?;
+///     Ok(body)
+/// }
+/// #
+/// # fn main() {
+/// #   let test_server = TestServer::new(build_simple_router(|route| {
+/// #       route.get("/").to_async_borrowing(handler);
+/// #   }))
+/// #   .unwrap();
+/// #   let response = test_server.client().get("http://localhost/").perform().unwrap();
+/// #   assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+/// #   assert_eq!(response.read_utf8_body().unwrap(), "<h1>Something went wrong</h1>");
+/// # }
+/// ```
+pub trait MapHandlerErrorWithCustomizedResponseAsync<T> {
+    /// Equivalent of `map_err_with_customized_response`, but awaits the future returned by `f`
+    /// to produce the customized response.
+    fn map_err_with_customized_response_async<'a, F, Fut, R>(
+        self,
+        state: &'a mut State,
+        f: F,
+    ) -> Pin<Box<dyn Future<Output = Result<T, HandlerError>> + Send + 'a>>
+    where
+        F: FnOnce(&State) -> Fut + Send + 'a,
+        Fut: Future<Output = R> + Send + 'a,
+        R: IntoResponse + 'a;
+}
+
+impl<T, E> MapHandlerErrorWithCustomizedResponseAsync<T> for Result<T, E>
+where
+    T: Send + 'static,
+    E: Into<anyhow::Error> + Display + Send + 'static,
+{
+    fn map_err_with_customized_response_async<'a, F, Fut, R>(
+        self,
+        state: &'a mut State,
+        f: F,
+    ) -> Pin<Box<dyn Future<Output = Result<T, HandlerError>> + Send + 'a>>
+    where
+        F: FnOnce(&State) -> Fut + Send + 'a,
+        Fut: Future<Output = R> + Send + 'a,
+        R: IntoResponse + 'a,
+    {
+        Box::pin(async move {
+            match self {
+                Ok(value) => Ok(value),
+                Err(err) => {
+                    trace!(" map_err_with_customized_response_async by error: {}", err);
+                    let mut e = HandlerError::from(err);
+                    e.set_customized_response_body_async(state, f).await;
+                    Err(e)
+                }
+            }
+        })
+    }
+}
+
 // impl<T> MapHandlerErrorToResponse<T> for Result<T, HandlerError>
 // {
 //     fn map_err_to_response<F: FnOnce(&State) -> R, R: IntoResponse>(self, state: &State, f: F) -> Result<T, HandlerError> {
@@ -479,6 +1411,139 @@ where
     }
 }
 
+// The future for `map_err_to_customized_response` on futures.
+#[pin_project::pin_project(
+    project = MapErrToCustomizedResponseProj,
+    project_replace = MapErrToCustomizedResponseProjOwn
+)]
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub enum MapErrToCustomizedResponse<'a, F, C> {
+    Incomplete {
+        #[pin]
+        future: F,
+        state: &'a mut State,
+        f: C,
+    },
+    Complete,
+}
+
+impl<'a, F, C> MapErrToCustomizedResponse<'a, F, C> {
+    fn new(future: F, state: &'a mut State, f: C) -> Self {
+        Self::Incomplete { future, state, f }
+    }
+}
+
+impl<'a, F, C, T, E, R> FusedFuture for MapErrToCustomizedResponse<'a, F, C>
+where
+    F: Future<Output = Result<T, E>>,
+    E: Into<anyhow::Error> + Display,
+    C: FnOnce(E, &State) -> (E, R),
+    R: IntoResponse,
+{
+    fn is_terminated(&self) -> bool {
+        matches!(self, Self::Complete)
+    }
+}
+
+impl<'a, F, C, T, E, R> Future for MapErrToCustomizedResponse<'a, F, C>
+where
+    F: Future<Output = Result<T, E>>,
+    E: Into<anyhow::Error> + Display,
+    C: FnOnce(E, &State) -> (E, R),
+    R: IntoResponse,
+{
+    type Output = Result<T, HandlerError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.as_mut().project() {
+            MapErrToCustomizedResponseProj::Incomplete { future, .. } => {
+                let output = match future.poll(cx) {
+                    Poll::Ready(output) => output,
+                    Poll::Pending => return Poll::Pending,
+                };
+                match self.project_replace(MapErrToCustomizedResponse::Complete) {
+                    MapErrToCustomizedResponseProjOwn::Incomplete { state, f, .. } => {
+                        Poll::Ready(output.map_err_to_customized_response(state, f))
+                    }
+                    MapErrToCustomizedResponseProjOwn::Complete => unreachable!(),
+                }
+            }
+            MapErrToCustomizedResponseProj::Complete => panic!(
+                "MapErrToCustomizedResponse must not be polled after it returned `Poll::Ready`"
+            ),
+        }
+    }
+}
+
+/// The future counterpart of `MapHandlerErrorToCustomizedResponse`, which allows the result of
+/// an asynchronous operation to be awaited directly in an async handler.
+///
+/// The `State` is borrowed mutably, even though the closure only receives a shared reference, so
+/// the returned future stays `Send` and can be used with `to_async_borrowing`.
+/// ```rust
+/// # extern crate futures;
+/// # extern crate gotham;
+/// # extern crate mime;
+/// # use gotham::anyhow::anyhow;
+/// # use gotham::handler::{HandlerError, MapHandlerErrorToCustomizedResponseFuture};
+/// # use gotham::hyper::{Body, Response, StatusCode};
+/// # use gotham::router::builder::*;
+/// # use gotham::state::State;
+/// # use gotham::test::TestServer;
+/// async fn handler(state: &mut State) -> Result<Response<Body>, HandlerError> {
+///     async { Err(anyhow!("upstream unavailable")) }
+///         .map_err_to_customized_response(state, |err, _state| {
+///             (err, (StatusCode::BAD_GATEWAY, mime::TEXT_PLAIN, "try again later"))
+///         })
+///         .await
+/// }
+///
+/// # fn main() {
+/// let test_server = TestServer::new(build_simple_router(|route| {
+///     route.get("/").to_async_borrowing(handler);
+/// }))
+/// .unwrap();
+///
+/// let response = test_server.client().get("http://localhost/").perform().unwrap();
+/// assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+/// assert_eq!(response.read_utf8_body().unwrap(), "try again later");
+/// # }
+/// ```
+pub trait MapHandlerErrorToCustomizedResponseFuture<T, E>
+where
+    E: Into<anyhow::Error> + Display,
+{
+    /// Equivalent of awaiting the future and calling `map_err_to_customized_response` on its
+    /// output.
+    fn map_err_to_customized_response<C, R>(
+        self,
+        state: &mut State,
+        f: C,
+    ) -> MapErrToCustomizedResponse<'_, Self, C>
+    where
+        Self: Sized,
+        C: FnOnce(E, &State) -> (E, R),
+        R: IntoResponse;
+}
+
+impl<T, E, F> MapHandlerErrorToCustomizedResponseFuture<T, E> for F
+where
+    E: Into<anyhow::Error> + Display,
+    F: Future<Output = Result<T, E>>,
+{
+    fn map_err_to_customized_response<C, R>(
+        self,
+        state: &mut State,
+        f: C,
+    ) -> MapErrToCustomizedResponse<'_, Self, C>
+    where
+        C: FnOnce(E, &State) -> (E, R),
+        R: IntoResponse,
+    {
+        MapErrToCustomizedResponse::new(self, state, f)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -493,6 +1558,354 @@ mod test {
         Err(DummyError.into())
     }
 
+    #[test]
+    fn test_error_response_is_rendered_with_state() {
+        fn quota() -> Result<(), HandlerError> {
+            Err(ErrorResponse(
+                StatusCode::OK,
+                (mime::TEXT_PLAIN, "quota exceeded"),
+            ))?;
+            bail_with_status!(StatusCode::NOT_FOUND, "unreachable {}", 1)
+        }
+
+        let mut state = State::new();
+        state.put(hyper::HeaderMap::new());
+        state.put(hyper::Method::HEAD);
+        crate::state::set_request_id(&mut state);
+
+        let err = quota().unwrap_err();
+        assert!(err.has_customized_response_body());
+
+        let response = err.into_response(&state);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(hyper::header::CONTENT_TYPE).unwrap(),
+            "text/plain"
+        );
+
+        // a status code set by the handler after the body is kept
+        let err = quota()
+            .map_err_with_status(StatusCode::TOO_MANY_REQUESTS)
+            .unwrap_err();
+        assert!(err.has_customized_response_body());
+
+        let response = err.into_response(&state);
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            response.headers().get(hyper::header::CONTENT_TYPE).unwrap(),
+            "text/plain"
+        );
+    }
+
+    #[test]
+    fn test_map_cause_replaces_matching_errors_only() {
+        let mut state = State::new();
+        state.put(hyper::HeaderMap::new());
+        state.put(hyper::Method::GET);
+        crate::state::set_request_id(&mut state);
+
+        let err = HandlerError::from(DummyError)
+            .context("loading widget")
+            .map_cause(|_: &io::Error| (StatusCode::NOT_FOUND, "not found"));
+        assert_eq!(err.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(!err.has_customized_response_body());
+
+        let err = err
+            .with_header(RETRY_AFTER, HeaderValue::from_static("5"))
+            .map_cause(|e: &DummyError| (StatusCode::BAD_GATEWAY, e.to_string()));
+        assert_eq!(err.status(), StatusCode::BAD_GATEWAY);
+        assert!(err.downcast_cause_ref::<DummyError>().is_some());
+
+        let response = err.into_response(&state);
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(response.headers()[RETRY_AFTER], "5");
+        let body = futures::executor::block_on(hyper::body::to_bytes(response.into_body()));
+        assert_eq!(&body.unwrap()[..], b"Dummy Error");
+    }
+
+    #[test]
+    fn test_message_key_localizes_problem_details() {
+        let mut headers = hyper::HeaderMap::new();
+        headers.insert(
+            hyper::header::ACCEPT_LANGUAGE,
+            HeaderValue::from_static("fr"),
+        );
+
+        let mut state = State::new();
+        state.put(headers);
+        state.put(hyper::Method::GET);
+        crate::state::set_request_id(&mut state);
+
+        let err = || {
+            HandlerError::not_found(DummyError)
+                .with_message_key("widget.not_found")
+                .with_problem_details()
+        };
+        let body = |response: Response<Body>| {
+            let body = futures::executor::block_on(hyper::body::to_bytes(response.into_body()));
+            String::from_utf8(body.unwrap().to_vec()).unwrap()
+        };
+
+        let response = err().into_response(&state);
+        assert!(!response.headers().contains_key(CONTENT_LANGUAGE));
+        assert!(body(response).contains(r#""detail":"Dummy Error""#));
+
+        state.put(
+            ErrorMessageCatalog::new("en")
+                .with_message("en", "widget.not_found", "Unknown widget")
+                .with_message("fr", "widget.not_found", "Widget inconnu"),
+        );
+        let response = err().into_response(&state);
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[CONTENT_LANGUAGE], "fr");
+        assert!(body(response).contains(r#""detail":"Widget inconnu""#));
+    }
+
+    #[test]
+    fn test_message_key_is_rendered_by_formatter() {
+        let mut headers = hyper::HeaderMap::new();
+        headers.insert(
+            hyper::header::ACCEPT_LANGUAGE,
+            HeaderValue::from_static("en-us"),
+        );
+        headers.insert(
+            hyper::header::ACCEPT,
+            HeaderValue::from_static("application/json"),
+        );
+
+        let mut state = State::new();
+        state.put(headers);
+        state.put(hyper::Method::GET);
+        crate::state::set_request_id(&mut state);
+        state.put(ErrorMessageCatalog::new("en-US").with_message(
+            "en-US",
+            "widget.not_found",
+            "Unknown widget",
+        ));
+
+        let response = HandlerError::not_found(DummyError)
+            .with_message_key("widget.not_found")
+            .into_formatted_response(&state, &crate::handler::DefaultErrorFormatter);
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[CONTENT_LANGUAGE], "en-US");
+        assert_eq!(
+            response.headers()[hyper::header::CONTENT_TYPE],
+            "application/json"
+        );
+        let body = futures::executor::block_on(hyper::body::to_bytes(response.into_body()));
+        let body: serde_json::Value = serde_json::from_slice(&body.unwrap()).unwrap();
+        assert_eq!(body["message"], "Unknown widget");
+    }
+
+    #[test]
+    fn test_classifies_status_of_well_known_causes() {
+        let status = |kind| HandlerError::from(io::Error::new(kind, "io")).status();
+        assert_eq!(status(io::ErrorKind::TimedOut), StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(status(io::ErrorKind::BrokenPipe).as_u16(), 499);
+        assert_eq!(
+            status(io::ErrorKind::ConnectionRefused),
+            StatusCode::BAD_GATEWAY
+        );
+        assert_eq!(
+            status(io::ErrorKind::NotFound),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+
+        let err = HandlerError::from(io::Error::new(io::ErrorKind::TimedOut, "io"))
+            .context("reading request body");
+        assert_eq!(err.status(), StatusCode::REQUEST_TIMEOUT);
+        assert!(!err.is_status_explicit());
+
+        // the request body is cut off when the client aborts the request
+        let (sender, body) = Body::channel();
+        sender.abort();
+        let err = futures::executor::block_on(hyper::body::to_bytes(body)).unwrap_err();
+        assert_eq!(HandlerError::from(err).status().as_u16(), 499);
+    }
+
+    #[test]
+    fn test_metadata_does_not_grow_handler_error() {
+        // the `Err` variant of `HandlerResult` is returned through every handler and middleware
+        assert!(std::mem::size_of::<HandlerError>() <= 48);
+    }
+
+    #[test]
+    fn test_retryable_errors_send_retry_after() {
+        let mut state = State::new();
+        state.put(hyper::HeaderMap::new());
+        crate::state::set_request_id(&mut state);
+
+        let mut err = HandlerError::from(DummyError).with_retry_after(Duration::from_secs(30));
+        assert!(err.is_retryable());
+        assert_eq!(err.retry_after(), Some(Duration::from_secs(30)));
+        let response = err.into_response(&state);
+        assert_eq!(response.headers()[RETRY_AFTER], "30");
+
+        err = HandlerError::from(DummyError).with_retry_after(Duration::from_secs(30));
+        err.set_retryable(false);
+        assert_eq!(err.retry_after(), None);
+        let response = err.into_response(&state);
+        assert!(!response.headers().contains_key(RETRY_AFTER));
+
+        let response = HandlerError::from(DummyError)
+            .with_header(
+                RETRY_AFTER,
+                HeaderValue::from_static("Fri, 31 Dec 1999 23:59:59 GMT"),
+            )
+            .with_retry_after(Duration::from_secs(30))
+            .into_response(&state);
+        assert_eq!(response.headers().get_all(RETRY_AFTER).iter().count(), 1);
+        assert_eq!(
+            response.headers()[RETRY_AFTER],
+            "Fri, 31 Dec 1999 23:59:59 GMT"
+        );
+    }
+
+    #[test]
+    fn test_validation_errors_render_as_json() {
+        let mut state = State::new();
+        state.put(hyper::HeaderMap::new());
+        state.put(hyper::Method::POST);
+        crate::state::set_request_id(&mut state);
+
+        let mut errors = ValidationError::new();
+        errors.add("email", "invalid format");
+        let err = HandlerError::from(errors).context("creating user");
+        assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let response = err.into_response(&state);
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = futures::executor::block_on(hyper::body::to_bytes(response.into_body()));
+        assert_eq!(
+            &body.unwrap()[..],
+            br#"{"errors":{"email":["invalid format"]}}"#
+        );
+    }
+
+    #[test]
+    fn test_status_code_class_shorthands() {
+        assert_eq!(
+            HandlerError::bad_request(DummyError).status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            HandlerError::unauthorized(DummyError).status(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let err: Result<(), _> = Err(DummyError);
+        let err = err.map_err_conflict().unwrap_err();
+        assert_eq!(err.status(), StatusCode::CONFLICT);
+        assert!(err.is_status_explicit());
+
+        let err = error_prone().map_err_forbidden().unwrap_err();
+        assert_eq!(err.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn test_context_keeps_status_and_customized_body() {
+        let mut state = State::new();
+        state.put(hyper::HeaderMap::new());
+        state.put(hyper::Method::GET);
+        crate::state::set_request_id(&mut state);
+
+        let err: Result<(), _> = Err(DummyError);
+        let err = err
+            .map_err_with_customized_response(&state, |_| {
+                (StatusCode::CONFLICT, mime::TEXT_PLAIN, "conflict")
+            })
+            .context_with_status(StatusCode::CONFLICT, "saving widget")
+            .unwrap_err()
+            .with_context(|| format!("request {}", 1));
+
+        assert_eq!(err.status(), StatusCode::CONFLICT);
+        assert!(err.has_customized_response_body());
+        assert!(err.downcast_cause_ref::<DummyError>().is_some());
+        assert_eq!(
+            format!("{:#}", err.cause()),
+            "request 1: saving widget: Dummy Error"
+        );
+    }
+
+    #[test]
+    fn test_headers_are_added_to_every_response_kind() {
+        use hyper::header::{RETRY_AFTER, WWW_AUTHENTICATE};
+
+        let mut state = State::new();
+        state.put(hyper::HeaderMap::new());
+        state.put(hyper::Method::GET);
+        crate::state::set_request_id(&mut state);
+
+        let mut extra = HeaderMap::new();
+        extra.append(WWW_AUTHENTICATE, HeaderValue::from_static("Basic"));
+        extra.append(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+
+        let with_headers = |err: HandlerError| {
+            err.with_header(RETRY_AFTER, HeaderValue::from_static("5"))
+                .with_headers(extra.clone())
+        };
+
+        let mut customized = HandlerError::from(DummyError);
+        customized.set_customized_response_body(&state, |_| {
+            (StatusCode::UNAUTHORIZED, mime::TEXT_PLAIN, "denied")
+        });
+
+        for err in [
+            HandlerError::from(DummyError),
+            HandlerError::from(DummyError).with_problem_details(),
+            customized,
+        ] {
+            let response = with_headers(err).into_response(&state);
+            assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "5");
+            assert_eq!(
+                response
+                    .headers()
+                    .get_all(WWW_AUTHENTICATE)
+                    .iter()
+                    .collect::<Vec<_>>(),
+                vec!["Basic", "Bearer"]
+            );
+        }
+    }
+
+    #[test]
+    fn test_set_customized_response_body_async() {
+        let mut state = State::new();
+        state.put(hyper::HeaderMap::new());
+        state.put(hyper::Method::GET);
+        crate::state::set_request_id(&mut state);
+
+        let mut err = HandlerError::from(DummyError);
+        futures::executor::block_on(
+            err.set_customized_response_body_async(&mut state, |_| async {
+                (StatusCode::BAD_GATEWAY, mime::TEXT_PLAIN, "cached page")
+            }),
+        );
+
+        assert_eq!(err.status(), StatusCode::BAD_GATEWAY);
+        assert!(err.has_customized_response_body());
+    }
+
+    #[test]
+    fn test_into_problem_details() {
+        let mut state = State::new();
+        state.put(hyper::HeaderMap::new());
+        let request_id = crate::state::set_request_id(&mut state).to_owned();
+
+        let err = HandlerError::from(anyhow::Error::new(DummyError).context("loading widget"))
+            .with_status(StatusCode::SERVICE_UNAVAILABLE);
+        let problem = err.into_problem_details(&state);
+
+        assert_eq!(problem.problem_type, "about:blank");
+        assert_eq!(problem.title, "Service Unavailable");
+        assert_eq!(problem.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            problem.detail.as_deref(),
+            Some("loading widget: Dummy Error")
+        );
+        assert_eq!(problem.instance, Some(request_id));
+    }
+
     #[test]
     fn test_error_downcast() {
         let mut err = error_prone().unwrap_err();
@@ -0,0 +1,477 @@
+//! Defines a typed Atom or RSS feed, e.g. for blog or changelog endpoints, which handlers return
+//! as their response.
+//!
+//! The response has `ETag` and `Last-Modified` headers, and conditional requests whose
+//! `If-None-Match` or `If-Modified-Since` header matches the feed are answered with
+//! `304 Not Modified`, so that feed readers polling the endpoint don't download it again.
+//!
+//! ```rust
+//! # extern crate gotham;
+//! # extern crate chrono;
+//! # extern crate hyper;
+//! #
+//! # use chrono::{TimeZone, Utc};
+//! # use gotham::handler::feed::{Feed, FeedEntry};
+//! # use gotham::router::builder::*;
+//! # use gotham::state::State;
+//! # use gotham::test::TestServer;
+//! # use hyper::header::CONTENT_TYPE;
+//! #
+//! fn changelog(state: State) -> (State, Feed) {
+//!     let released = Utc.with_ymd_and_hms(2020, 6, 1, 12, 0, 0).unwrap();
+//!     let feed = Feed::atom("Changelog", "https://example.com/changelog").with_entry(
+//!         FeedEntry::new("Version 1.0", "https://example.com/changelog/1.0", released)
+//!             .with_summary("The first stable release."),
+//!     );
+//!     (state, feed)
+//! }
+//!
+//! # fn main() {
+//! let router = build_simple_router(|route| {
+//!     route.get("/changelog.atom").to(changelog);
+//! });
+//!
+//! let test_server = TestServer::new(router).unwrap();
+//! let response = test_server
+//!     .client()
+//!     .get("http://localhost/changelog.atom")
+//!     .perform()
+//!     .unwrap();
+//! assert_eq!(
+//!     response.headers().get(CONTENT_TYPE).unwrap(),
+//!     "application/atom+xml; charset=utf-8"
+//! );
+//! assert!(response
+//!     .read_utf8_body()
+//!     .unwrap()
+//!     .contains("<entry><title>Version 1.0</title>"));
+//! # }
+//! ```
+
+use std::fmt::Write;
+use std::time::UNIX_EPOCH;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use httpdate::{fmt_http_date, parse_http_date};
+use hyper::header::{ETAG, IF_MODIFIED_SINCE, LAST_MODIFIED};
+use hyper::{Body, HeaderMap, Response, StatusCode};
+use mime::Mime;
+
+use crate::handler::sitemap::escape;
+use crate::handler::IntoResponse;
+use crate::helpers::http::response::{create_empty_response, create_response};
+use crate::middleware::precondition::{content_etag, if_none_match};
+use crate::state::{FromState, State};
+
+/// The media type of Atom feeds.
+pub const APPLICATION_ATOM_XML: &str = "application/atom+xml; charset=utf-8";
+
+/// The media type of RSS feeds.
+pub const APPLICATION_RSS_XML: &str = "application/rss+xml; charset=utf-8";
+
+/// The format a `Feed` is served in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeedFormat {
+    /// An Atom feed, as defined in RFC 4287.
+    Atom,
+    /// An RSS 2.0 feed.
+    Rss,
+}
+
+/// An entry of a `Feed`, e.g. a blog post.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeedEntry {
+    /// The title of the entry.
+    pub title: String,
+    /// The absolute URL of the entry.
+    pub link: String,
+    /// The permanent, unique identifier of the entry. Defaults to the link.
+    pub id: Option<String>,
+    /// When the entry was last updated.
+    pub updated: DateTime<Utc>,
+    /// A short summary of the entry.
+    pub summary: Option<String>,
+    /// The content of the entry, as HTML.
+    pub content: Option<String>,
+    /// The author of the entry.
+    pub author: Option<String>,
+}
+
+impl FeedEntry {
+    /// Creates the entry linking to `link`.
+    pub fn new(title: &str, link: &str, updated: DateTime<Utc>) -> FeedEntry {
+        FeedEntry {
+            title: title.to_owned(),
+            link: link.to_owned(),
+            id: None,
+            updated,
+            summary: None,
+            content: None,
+            author: None,
+        }
+    }
+
+    /// Sets the permanent, unique identifier of the entry.
+    pub fn with_id(self, id: &str) -> FeedEntry {
+        FeedEntry {
+            id: Some(id.to_owned()),
+            ..self
+        }
+    }
+
+    /// Sets the short summary of the entry.
+    pub fn with_summary(self, summary: &str) -> FeedEntry {
+        FeedEntry {
+            summary: Some(summary.to_owned()),
+            ..self
+        }
+    }
+
+    /// Sets the content of the entry, as HTML.
+    pub fn with_content(self, content: &str) -> FeedEntry {
+        FeedEntry {
+            content: Some(content.to_owned()),
+            ..self
+        }
+    }
+
+    /// Sets the author of the entry.
+    pub fn with_author(self, author: &str) -> FeedEntry {
+        FeedEntry {
+            author: Some(author.to_owned()),
+            ..self
+        }
+    }
+
+    fn id(&self) -> &str {
+        self.id.as_deref().unwrap_or(&self.link)
+    }
+}
+
+/// An Atom or RSS feed, which is served as the response of a handler, see the module
+/// documentation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Feed {
+    /// The format the feed is served in.
+    pub format: FeedFormat,
+    /// The title of the feed.
+    pub title: String,
+    /// The absolute URL of the site the feed belongs to.
+    pub link: String,
+    /// The permanent, unique identifier of the feed. Defaults to the link.
+    pub id: Option<String>,
+    /// A description of the feed.
+    pub description: Option<String>,
+    /// The author of the feed, which applies to entries without an author.
+    pub author: Option<String>,
+    /// When the feed was last updated. Defaults to the latest update of the entries.
+    pub updated: Option<DateTime<Utc>>,
+    /// The entries of the feed, usually the latest first.
+    pub entries: Vec<FeedEntry>,
+}
+
+impl Feed {
+    /// Creates an Atom feed for the site at `link`.
+    pub fn atom(title: &str, link: &str) -> Feed {
+        Feed::new(FeedFormat::Atom, title, link)
+    }
+
+    /// Creates an RSS feed for the site at `link`.
+    pub fn rss(title: &str, link: &str) -> Feed {
+        Feed::new(FeedFormat::Rss, title, link)
+    }
+
+    /// Creates a feed in `format` for the site at `link`.
+    pub fn new(format: FeedFormat, title: &str, link: &str) -> Feed {
+        Feed {
+            format,
+            title: title.to_owned(),
+            link: link.to_owned(),
+            id: None,
+            description: None,
+            author: None,
+            updated: None,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Sets the permanent, unique identifier of the feed.
+    pub fn with_id(self, id: &str) -> Feed {
+        Feed {
+            id: Some(id.to_owned()),
+            ..self
+        }
+    }
+
+    /// Sets the description of the feed.
+    pub fn with_description(self, description: &str) -> Feed {
+        Feed {
+            description: Some(description.to_owned()),
+            ..self
+        }
+    }
+
+    /// Sets the author of the feed.
+    pub fn with_author(self, author: &str) -> Feed {
+        Feed {
+            author: Some(author.to_owned()),
+            ..self
+        }
+    }
+
+    /// Sets when the feed was last updated.
+    pub fn with_updated(self, updated: DateTime<Utc>) -> Feed {
+        Feed {
+            updated: Some(updated),
+            ..self
+        }
+    }
+
+    /// Adds an entry to the feed.
+    pub fn with_entry(mut self, entry: FeedEntry) -> Feed {
+        self.entries.push(entry);
+        self
+    }
+
+    /// Returns when the feed was last updated, if it has been set or the feed has entries.
+    pub fn last_updated(&self) -> Option<DateTime<Utc>> {
+        self.updated
+            .or_else(|| self.entries.iter().map(|entry| entry.updated).max())
+    }
+
+    /// Renders the feed as XML.
+    pub fn render(&self) -> String {
+        match self.format {
+            FeedFormat::Atom => self.render_atom(),
+            FeedFormat::Rss => self.render_rss(),
+        }
+    }
+
+    fn render_atom(&self) -> String {
+        let mut out = String::from(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <feed xmlns=\"http://www.w3.org/2005/Atom\">\n",
+        );
+        let updated = self
+            .last_updated()
+            .unwrap_or_else(|| DateTime::from(UNIX_EPOCH));
+        let _ = writeln!(out, "<title>{}</title>", escape(&self.title));
+        let _ = writeln!(out, "<link href=\"{}\"/>", escape(&self.link));
+        let id = self.id.as_deref().unwrap_or(&self.link);
+        let _ = writeln!(out, "<id>{}</id>", escape(id));
+        let _ = writeln!(out, "<updated>{}</updated>", atom_date(updated));
+        if let Some(description) = &self.description {
+            let _ = writeln!(out, "<subtitle>{}</subtitle>", escape(description));
+        }
+        if let Some(author) = &self.author {
+            let _ = writeln!(out, "<author><name>{}</name></author>", escape(author));
+        }
+
+        for entry in &self.entries {
+            let _ = write!(
+                out,
+                "<entry><title>{}</title><link href=\"{}\"/><id>{}</id><updated>{}</updated>",
+                escape(&entry.title),
+                escape(&entry.link),
+                escape(entry.id()),
+                atom_date(entry.updated)
+            );
+            if let Some(author) = &entry.author {
+                let _ = write!(out, "<author><name>{}</name></author>", escape(author));
+            }
+            if let Some(summary) = &entry.summary {
+                let _ = write!(out, "<summary>{}</summary>", escape(summary));
+            }
+            if let Some(content) = &entry.content {
+                let _ = write!(out, "<content type=\"html\">{}</content>", escape(content));
+            }
+            out.push_str("</entry>\n");
+        }
+        out.push_str("</feed>\n");
+        out
+    }
+
+    fn render_rss(&self) -> String {
+        let mut out = String::from(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <rss version=\"2.0\"><channel>\n",
+        );
+        let _ = writeln!(out, "<title>{}</title>", escape(&self.title));
+        let _ = writeln!(out, "<link>{}</link>", escape(&self.link));
+        let description = self.description.as_deref().unwrap_or(&self.title);
+        let _ = writeln!(out, "<description>{}</description>", escape(description));
+        if let Some(updated) = self.last_updated() {
+            let _ = writeln!(
+                out,
+                "<lastBuildDate>{}</lastBuildDate>",
+                updated.to_rfc2822()
+            );
+        }
+
+        for entry in &self.entries {
+            let _ = write!(
+                out,
+                "<item><title>{}</title><link>{}</link><guid isPermaLink=\"{}\">{}</guid>\
+                 <pubDate>{}</pubDate>",
+                escape(&entry.title),
+                escape(&entry.link),
+                entry.id.is_none(),
+                escape(entry.id()),
+                entry.updated.to_rfc2822()
+            );
+            if let Some(author) = entry.author.as_ref().or(self.author.as_ref()) {
+                let _ = write!(out, "<author>{}</author>", escape(author));
+            }
+            if let Some(description) = entry.content.as_ref().or(entry.summary.as_ref()) {
+                let _ = write!(out, "<description>{}</description>", escape(description));
+            }
+            out.push_str("</item>\n");
+        }
+        out.push_str("</channel></rss>\n");
+        out
+    }
+}
+
+impl IntoResponse for Feed {
+    fn into_response(self, state: &State) -> Response<Body> {
+        let body = self.render();
+        let etag = content_etag(body.as_bytes());
+        let last_modified = self.last_updated();
+
+        let mut response = if not_modified(HeaderMap::borrow_from(state), &etag, last_modified) {
+            create_empty_response(state, StatusCode::NOT_MODIFIED)
+        } else {
+            let mime: Mime = match self.format {
+                FeedFormat::Atom => APPLICATION_ATOM_XML.parse().unwrap(),
+                FeedFormat::Rss => APPLICATION_RSS_XML.parse().unwrap(),
+            };
+            create_response(state, StatusCode::OK, mime, body)
+        };
+
+        let headers = response.headers_mut();
+        headers.insert(ETAG, etag.parse().unwrap());
+        if let Some(last_modified) = last_modified {
+            headers.insert(
+                LAST_MODIFIED,
+                fmt_http_date(last_modified.into()).parse().unwrap(),
+            );
+        }
+        response
+    }
+}
+
+/// Checks whether the client's copy of the feed is current, based on the request headers.
+fn not_modified(headers: &HeaderMap, etag: &str, last_modified: Option<DateTime<Utc>>) -> bool {
+    // If-None-Match header takes precedence over If-Modified-Since
+    if let Some(matched) = if_none_match(headers, etag) {
+        return matched;
+    }
+
+    let since = headers
+        .get(IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| parse_http_date(value).ok());
+    match (since, last_modified) {
+        // HTTP dates are precise to the second
+        (Some(since), Some(last_modified)) => {
+            last_modified.timestamp() <= DateTime::<Utc>::from(since).timestamp()
+        }
+        _ => false,
+    }
+}
+
+fn atom_date(date: DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+    use hyper::header::{CONTENT_TYPE, IF_NONE_MATCH};
+
+    use crate::router::builder::{build_simple_router, DefineSingleRoute, DrawRoutes};
+    use crate::test::TestServer;
+
+    fn feed(format: FeedFormat) -> Feed {
+        let updated = Utc.with_ymd_and_hms(2020, 6, 1, 12, 0, 0).unwrap();
+        Feed::new(format, "News & Notes", "https://example.com/")
+            .with_author("Jo")
+            .with_entry(
+                FeedEntry::new("First <post>", "https://example.com/1", updated)
+                    .with_id("urn:post:1")
+                    .with_content("<p>Hello</p>"),
+            )
+    }
+
+    #[test]
+    fn renders_atom_and_rss() {
+        let atom = feed(FeedFormat::Atom).render();
+        assert!(atom.contains("<title>News &amp; Notes</title>"));
+        assert!(atom.contains("<updated>2020-06-01T12:00:00Z</updated>"));
+        assert!(atom.contains(
+            "<entry><title>First &lt;post&gt;</title><link href=\"https://example.com/1\"/>\
+             <id>urn:post:1</id><updated>2020-06-01T12:00:00Z</updated>\
+             <content type=\"html\">&lt;p&gt;Hello&lt;/p&gt;</content></entry>"
+        ));
+
+        let rss = feed(FeedFormat::Rss).render();
+        assert!(rss.contains("<description>News &amp; Notes</description>"));
+        assert!(rss.contains(
+            "<item><title>First &lt;post&gt;</title><link>https://example.com/1</link>\
+             <guid isPermaLink=\"false\">urn:post:1</guid>\
+             <pubDate>Mon, 1 Jun 2020 12:00:00 +0000</pubDate><author>Jo</author>\
+             <description>&lt;p&gt;Hello&lt;/p&gt;</description></item>"
+        ));
+    }
+
+    #[test]
+    fn answers_conditional_requests() {
+        let router = build_simple_router(|route| {
+            route
+                .get("/feed.rss")
+                .to(|state| (state, feed(FeedFormat::Rss)));
+        });
+        let test_server = TestServer::new(router).unwrap();
+
+        let response = test_server
+            .client()
+            .get("http://localhost/feed.rss")
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            APPLICATION_RSS_XML
+        );
+        assert_eq!(
+            response.headers().get(LAST_MODIFIED).unwrap(),
+            "Mon, 01 Jun 2020 12:00:00 GMT"
+        );
+        let etag = response.headers().get(ETAG).unwrap().clone();
+
+        let response = test_server
+            .client()
+            .get("http://localhost/feed.rss")
+            .with_header(IF_NONE_MATCH, etag)
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let conditional = |since: &str| {
+            test_server
+                .client()
+                .get("http://localhost/feed.rss")
+                .with_header(IF_MODIFIED_SINCE, since.parse().unwrap())
+                .perform()
+                .unwrap()
+                .status()
+        };
+        assert_eq!(
+            conditional("Mon, 01 Jun 2020 12:00:00 GMT"),
+            StatusCode::NOT_MODIFIED
+        );
+        assert_eq!(conditional("Mon, 01 Jun 2020 11:59:59 GMT"), StatusCode::OK);
+    }
+}
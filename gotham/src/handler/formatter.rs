@@ -0,0 +1,197 @@
+use std::cmp::Ordering;
+use std::panic::RefUnwindSafe;
+use std::pin::Pin;
+
+use hyper::header::{HeaderMap, ACCEPT};
+use hyper::{Body, Response};
+use mime::Mime;
+use serde_json::json;
+
+use crate::handler::sitemap::escape;
+use crate::handler::{HandlerError, HandlerFuture};
+use crate::helpers::http::response::create_response;
+use crate::router::route::dispatch::Dispatcher;
+use crate::router::Router;
+use crate::state::{FromState, State, StateData};
+
+/// The formats an `ErrorFormatter` can render error bodies in.
+///
+/// Routes and scopes may declare the format of their errors, regardless of the `Accept` header,
+/// via `DefineSingleRoute::with_error_format` and `DrawRoutes::set_error_format`. The declared
+/// format is put into `State` by the `Router`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// `application/json`, or any media type with a `+json` suffix.
+    Json,
+    /// `text/html`.
+    Html,
+    /// `text/plain`, which is also used when the client expresses no preference.
+    Text,
+}
+
+impl StateData for ErrorFormat {}
+
+impl ErrorFormat {
+    /// Selects the format preferred by the `Accept` header of the request, falling back to
+    /// `ErrorFormat::Text` when the header is absent or lists no supported media type.
+    pub fn negotiate(headers: &HeaderMap) -> ErrorFormat {
+        let mut candidates: Vec<(f32, ErrorFormat)> = headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|item| item.trim().parse::<Mime>().ok())
+            .filter_map(|mime| {
+                let q = mime
+                    .get_param("q")
+                    .and_then(|q| q.as_str().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                ErrorFormat::from_mime(&mime).map(|format| (q, format))
+            })
+            .filter(|(q, _)| *q > 0.0)
+            .collect();
+
+        // a stable sort keeps the order of the header for media types with equal quality
+        candidates.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+        candidates
+            .first()
+            .map(|(_, format)| *format)
+            .unwrap_or(ErrorFormat::Text)
+    }
+
+    fn from_mime(mime: &Mime) -> Option<ErrorFormat> {
+        match (mime.type_(), mime.subtype(), mime.suffix()) {
+            (mime::APPLICATION, mime::JSON, _) | (mime::APPLICATION, _, Some(mime::JSON)) => {
+                Some(ErrorFormat::Json)
+            }
+            (mime::TEXT, mime::HTML, _) => Some(ErrorFormat::Html),
+            (mime::TEXT, mime::PLAIN, _) | (mime::TEXT, mime::STAR, _) => Some(ErrorFormat::Text),
+            (mime::STAR, mime::STAR, _) => Some(ErrorFormat::Text),
+            _ => None,
+        }
+    }
+}
+
+/// Renders the body of error responses in the format requested by the client.
+///
+/// An `ErrorFormatter` is registered via `Router::with_error_formatter`, and used for every
+/// `HandlerError` which has neither a customized response body nor problem details, and for
+/// requests which match no route. Custom HTML error pages are rendered by implementing this
+/// trait, and `HandlerError::localized_message` returns the message to show, if any.
+pub trait ErrorFormatter: Send + Sync + RefUnwindSafe {
+    /// Creates the response for `err`, in the given format.
+    fn format(&self, state: &State, err: &HandlerError, format: ErrorFormat) -> Response<Body>;
+}
+
+/// The `ErrorFormatter` which renders the status code and its canonical reason, e.g.
+/// `{"error":"Not Found","status":404}` for JSON, along with the localized message of the
+/// error, if any.
+///
+/// The cause of the error is never included, as it may reveal implementation details.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultErrorFormatter;
+
+impl ErrorFormatter for DefaultErrorFormatter {
+    fn format(&self, state: &State, err: &HandlerError, format: ErrorFormat) -> Response<Body> {
+        let status = err.status();
+        let reason = status.canonical_reason().unwrap_or("(unregistered)");
+        let message = err.localized_message(state);
+
+        match format {
+            ErrorFormat::Json => {
+                let mut body = json!({ "status": status.as_u16(), "error": reason });
+                if let Some(message) = message {
+                    body["message"] = message.into();
+                }
+                create_response(state, status, mime::APPLICATION_JSON, body.to_string())
+            }
+            ErrorFormat::Html => {
+                let title = format!("{} {}", status.as_u16(), reason);
+                let paragraph = message
+                    .map(|message| format!("<p>{}</p>", escape(message)))
+                    .unwrap_or_default();
+                let body = format!(
+                    "<!DOCTYPE html>\n<html><head><title>{0}</title></head>\
+                     <body><h1>{0}</h1>{1}</body></html>\n",
+                    title, paragraph
+                );
+                create_response(state, status, mime::TEXT_HTML_UTF_8, body)
+            }
+            ErrorFormat::Text => {
+                let body = match message {
+                    Some(message) => message.to_owned(),
+                    None => format!("{} {}", status.as_u16(), reason),
+                };
+                create_response(state, status, mime::TEXT_PLAIN_UTF_8, body)
+            }
+        }
+    }
+}
+
+/// Renders `err` with `formatter`, in the format declared by the route or scope, or else the one
+/// negotiated from the request headers in `state`.
+pub(crate) fn format_error(
+    state: &State,
+    err: &HandlerError,
+    formatter: &dyn ErrorFormatter,
+) -> Response<Body> {
+    let format = match ErrorFormat::try_borrow_from(state) {
+        Some(format) => *format,
+        None => HeaderMap::try_borrow_from(state)
+            .map(ErrorFormat::negotiate)
+            .unwrap_or(ErrorFormat::Text),
+    };
+
+    formatter.format(state, err, format)
+}
+
+/// A `Dispatcher` which declares the error format of its route before dispatching, see
+/// `DefineSingleRoute::with_error_format`.
+pub(crate) struct ErrorFormatDispatcher {
+    pub(crate) dispatcher: Box<dyn Dispatcher + Send + Sync>,
+    pub(crate) format: ErrorFormat,
+}
+
+impl Dispatcher for ErrorFormatDispatcher {
+    fn dispatch(&self, mut state: State) -> Pin<Box<HandlerFuture>> {
+        state.put(self.format);
+        self.dispatcher.dispatch(state)
+    }
+
+    fn pipelines(&self) -> Option<Vec<Vec<&'static str>>> {
+        self.dispatcher.pipelines()
+    }
+
+    fn router(&self) -> Option<&Router> {
+        self.dispatcher.router()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn negotiate(accept: &'static str) -> ErrorFormat {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static(accept));
+        ErrorFormat::negotiate(&headers)
+    }
+
+    #[test]
+    fn negotiates_error_format_from_accept_header() {
+        assert_eq!(ErrorFormat::negotiate(&HeaderMap::new()), ErrorFormat::Text);
+        assert_eq!(negotiate("application/json"), ErrorFormat::Json);
+        assert_eq!(negotiate("application/vnd.api+json"), ErrorFormat::Json);
+        assert_eq!(
+            negotiate("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"),
+            ErrorFormat::Html
+        );
+        assert_eq!(
+            negotiate("text/html;q=0.5, application/json"),
+            ErrorFormat::Json
+        );
+        assert_eq!(negotiate("application/json;q=0, */*"), ErrorFormat::Text);
+        assert_eq!(negotiate("image/png"), ErrorFormat::Text);
+    }
+}
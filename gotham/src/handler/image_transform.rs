@@ -0,0 +1,655 @@
+//! Defines a handler serving resized and converted images, e.g. thumbnails of images uploaded by
+//! users. Requires the `image-transform` feature.
+//!
+//! The transform is given by the query string of the request: `w` and `h` bound the width and
+//! height, `fmt` names the output format (`png`, `jpeg`, `webp` or `avif`) and `q` the quality of
+//! lossy formats, from 1 to 100. Transforms are expensive, so each query string must be signed with
+//! the secret of the `ImageTransformHandler` in the `sig` parameter, which
+//! `ImageTransformHandler::signed_query` adds when the URL is rendered. Requests with a missing
+//! or invalid signature are answered with `403 Forbidden`.
+//!
+//! ```rust
+//! # extern crate gotham;
+//! # extern crate hyper;
+//! #
+//! # use hyper::StatusCode;
+//! # use gotham::handler::assets::FilePathExtractor;
+//! # use gotham::handler::image_transform::{DirImageSource, ImageTransformHandler, OutputFormat, Transform};
+//! # use gotham::router::builder::*;
+//! # use gotham::test::TestServer;
+//! #
+//! # fn main() {
+//! let handler = ImageTransformHandler::new(DirImageSource::new("resources/test/images"), b"secret");
+//! // e.g. rendered into `<img src="/images/avatars/ann.png?w=64&fmt=webp&sig=...">`
+//! let query = handler.signed_query(
+//!     "avatars/ann.png",
+//!     &Transform::new().with_width(64).with_format(OutputFormat::Webp),
+//! );
+//! assert!(query.starts_with("w=64&fmt=webp&sig="));
+//!
+//! let router = build_simple_router(|route| {
+//!     route
+//!         .get("/images/*")
+//!         .with_path_extractor::<FilePathExtractor>()
+//!         .to_new_handler(handler);
+//! });
+//!
+//! let test_server = TestServer::new(router).unwrap();
+//! let response = test_server
+//!     .client()
+//!     .get("http://localhost/images/avatars/ann.png?w=4000")
+//!     .perform()
+//!     .unwrap();
+//! assert_eq!(response.status(), StatusCode::FORBIDDEN);
+//!
+//! let response = test_server
+//!     .client()
+//!     .get(format!("http://localhost/images/avatars/ann.png?{}", query))
+//!     .perform()
+//!     .unwrap();
+//! assert_eq!(response.status(), StatusCode::NOT_FOUND);
+//! # }
+//! ```
+
+use std::future::Future;
+use std::io::{self, Cursor};
+use std::panic::RefUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::prelude::*;
+use hmac::{Hmac, Mac};
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE};
+use hyper::{Body, Response, StatusCode, Uri};
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, ImageReader, Limits};
+use log::trace;
+use sha2::{Digest, Sha256};
+
+use crate::handler::assets::FilePathExtractor;
+use crate::handler::{Handler, HandlerError, HandlerFuture, NewHandler};
+use crate::helpers::http::request::query_string;
+use crate::state::{request_id, FromState, State};
+
+/// The future returned by `ImageSource::load`.
+pub type ImageSourceFuture = Pin<Box<dyn Future<Output = anyhow::Result<Option<Bytes>>> + Send>>;
+
+/// Provides the source images transformed by an `ImageTransformHandler`.
+pub trait ImageSource: RefUnwindSafe + Send + Sync {
+    /// Loads the encoded image `key`, which is the path requested below the route, e.g.
+    /// `avatars/ann.png`. Returns `None` if there is no such image.
+    fn load(&self, key: &str) -> ImageSourceFuture;
+}
+
+/// An `ImageSource` reading images from a directory.
+#[derive(Clone, Debug)]
+pub struct DirImageSource {
+    root: PathBuf,
+}
+
+impl DirImageSource {
+    /// Creates the source reading images below `root`.
+    pub fn new<P>(root: P) -> DirImageSource
+    where
+        P: Into<PathBuf>,
+    {
+        DirImageSource { root: root.into() }
+    }
+}
+
+impl ImageSource for DirImageSource {
+    fn load(&self, key: &str) -> ImageSourceFuture {
+        let path = self.root.join(key);
+        async move {
+            match tokio::fs::read(path).await {
+                Ok(data) => Ok(Some(Bytes::from(data))),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        }
+        .boxed()
+    }
+}
+
+/// The formats an `ImageTransformHandler` can convert images into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// PNG, which is lossless.
+    Png,
+    /// JPEG, whose quality is 80 unless given.
+    Jpeg,
+    /// WebP, which is encoded losslessly.
+    Webp,
+    /// AVIF, whose quality is 80 unless given.
+    Avif,
+}
+
+impl OutputFormat {
+    /// Returns the format named `name` in a query string, e.g. `webp`.
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name {
+            "png" => Some(OutputFormat::Png),
+            "jpeg" | "jpg" => Some(OutputFormat::Jpeg),
+            "webp" => Some(OutputFormat::Webp),
+            "avif" => Some(OutputFormat::Avif),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the format in a query string.
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpeg",
+            OutputFormat::Webp => "webp",
+            OutputFormat::Avif => "avif",
+        }
+    }
+
+    /// Returns the media type of images in the format.
+    pub fn mime(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "image/png",
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Webp => "image/webp",
+            OutputFormat::Avif => "image/avif",
+        }
+    }
+
+    /// Returns the format images in `format` are served in if no format is requested.
+    fn of_source(format: Option<ImageFormat>) -> OutputFormat {
+        match format {
+            Some(ImageFormat::Jpeg) => OutputFormat::Jpeg,
+            Some(ImageFormat::WebP) => OutputFormat::Webp,
+            Some(ImageFormat::Avif) => OutputFormat::Avif,
+            _ => OutputFormat::Png,
+        }
+    }
+}
+
+/// The transform of an image requested via the query string, see the module documentation.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Transform {
+    width: Option<u32>,
+    height: Option<u32>,
+    format: Option<OutputFormat>,
+    quality: Option<u8>,
+}
+
+impl Transform {
+    /// Creates the transform which keeps the image as it is.
+    pub fn new() -> Transform {
+        Transform::default()
+    }
+
+    /// Scales the image down to at most `width` pixels wide, keeping its aspect ratio.
+    pub fn with_width(self, width: u32) -> Transform {
+        Transform {
+            width: Some(width),
+            ..self
+        }
+    }
+
+    /// Scales the image down to at most `height` pixels high, keeping its aspect ratio.
+    pub fn with_height(self, height: u32) -> Transform {
+        Transform {
+            height: Some(height),
+            ..self
+        }
+    }
+
+    /// Converts the image to `format`, instead of the format of the source image.
+    pub fn with_format(self, format: OutputFormat) -> Transform {
+        Transform {
+            format: Some(format),
+            ..self
+        }
+    }
+
+    /// Encodes the image with `quality`, from 1 to 100, if the format is lossy.
+    pub fn with_quality(self, quality: u8) -> Transform {
+        Transform {
+            quality: Some(quality),
+            ..self
+        }
+    }
+
+    /// Parses the transform and the signature of a query string.
+    fn parse(query: Option<&str>) -> Option<(Transform, Option<String>)> {
+        let mapping = query_string::split(query);
+        let value = |name: &str| {
+            mapping
+                .get(name)
+                .and_then(|values| values.first())
+                .map(|value| value.as_ref())
+        };
+
+        let transform = Transform {
+            width: value("w").map(str::parse).transpose().ok()?,
+            height: value("h").map(str::parse).transpose().ok()?,
+            format: match value("fmt") {
+                Some(name) => Some(OutputFormat::from_name(name)?),
+                None => None,
+            },
+            quality: value("q").map(str::parse).transpose().ok()?,
+        };
+        let valid = transform.width != Some(0)
+            && transform.height != Some(0)
+            && transform.quality.is_none_or(|q| (1..=100).contains(&q));
+        if valid {
+            Some((transform, value("sig").map(str::to_owned)))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the query string of the transform, without a signature.
+    fn query(&self) -> String {
+        let mut params = Vec::new();
+        if let Some(width) = self.width {
+            params.push(format!("w={}", width));
+        }
+        if let Some(height) = self.height {
+            params.push(format!("h={}", height));
+        }
+        if let Some(format) = self.format {
+            params.push(format!("fmt={}", format.name()));
+        }
+        if let Some(quality) = self.quality {
+            params.push(format!("q={}", quality));
+        }
+        params.join("&")
+    }
+
+    /// Applies the transform to the encoded image `data`.
+    fn apply(&self, data: &[u8], limits: Limits) -> anyhow::Result<(OutputFormat, Vec<u8>)> {
+        let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+        reader.limits(limits);
+        let source_format = reader.format();
+        let mut image = reader.decode()?;
+
+        // images are scaled down to fit the bounds, but never enlarged
+        let width = self.width.unwrap_or(u32::MAX).min(image.width());
+        let height = self.height.unwrap_or(u32::MAX).min(image.height());
+        if width < image.width() || height < image.height() {
+            image = image.resize(width, height, FilterType::Lanczos3);
+        }
+
+        let format = self
+            .format
+            .unwrap_or_else(|| OutputFormat::of_source(source_format));
+        let quality = self.quality.unwrap_or(80);
+        let mut encoded = Cursor::new(Vec::new());
+        match format {
+            OutputFormat::Png => image.write_to(&mut encoded, ImageFormat::Png)?,
+            OutputFormat::Webp => image.write_to(&mut encoded, ImageFormat::WebP)?,
+            OutputFormat::Jpeg => {
+                // JPEG has no alpha channel
+                let image = DynamicImage::ImageRgb8(image.to_rgb8());
+                image.write_with_encoder(JpegEncoder::new_with_quality(&mut encoded, quality))?
+            }
+            OutputFormat::Avif => {
+                let encoder = AvifEncoder::new_with_speed_quality(&mut encoded, 8, quality);
+                image.write_with_encoder(encoder)?
+            }
+        }
+        Ok((format, encoded.into_inner()))
+    }
+}
+
+/// A handler serving transformed images from an `ImageSource`, see the module documentation.
+///
+/// The handler serves the image named by the path matched by the glob segment of the route, and
+/// is routed with the `FilePathExtractor`, like `DirHandler`. Requests for paths leaving the
+/// source, e.g. via `..`, are answered with `404 Not Found`, and transforms which are invalid or
+/// exceed the maximum dimension with `400 Bad Request`.
+#[derive(Clone)]
+pub struct ImageTransformHandler {
+    source: Arc<dyn ImageSource>,
+    secret: Arc<[u8]>,
+    cache_dir: Option<PathBuf>,
+    max_dimension: u32,
+    max_source_dimension: u32,
+    max_source_alloc: u64,
+    cache_control: String,
+}
+
+impl ImageTransformHandler {
+    /// Creates the handler for images of `source`, whose transforms are signed with `secret`.
+    pub fn new<S>(source: S, secret: &[u8]) -> ImageTransformHandler
+    where
+        S: ImageSource + 'static,
+    {
+        ImageTransformHandler {
+            source: Arc::new(source),
+            secret: Arc::from(secret),
+            cache_dir: None,
+            max_dimension: 4096,
+            max_source_dimension: 16384,
+            max_source_alloc: 256 * 1024 * 1024,
+            cache_control: "public, max-age=86400".to_owned(),
+        }
+    }
+
+    /// Stores transformed images in `dir`, and serves them from there when they are requested
+    /// again. Cached images are not invalidated when their source image changes.
+    pub fn with_cache_dir<P>(self, dir: P) -> ImageTransformHandler
+    where
+        P: Into<PathBuf>,
+    {
+        ImageTransformHandler {
+            cache_dir: Some(dir.into()),
+            ..self
+        }
+    }
+
+    /// Rejects transforms with a width or height above `max_dimension`, 4096 by default.
+    pub fn with_max_dimension(self, max_dimension: u32) -> ImageTransformHandler {
+        ImageTransformHandler {
+            max_dimension,
+            ..self
+        }
+    }
+
+    /// Refuses to decode source images with a width or height above `max_dimension`, 16384 by
+    /// default, or for which the decoder would allocate more than `max_alloc` bytes, 256 MiB by
+    /// default. Such images are answered with `500 Internal Server Error`.
+    pub fn with_source_limits(self, max_dimension: u32, max_alloc: u64) -> ImageTransformHandler {
+        ImageTransformHandler {
+            max_source_dimension: max_dimension,
+            max_source_alloc: max_alloc,
+            ..self
+        }
+    }
+
+    /// Sets the `Cache-Control` header of transformed images, `public, max-age=86400` by default.
+    pub fn with_cache_control(self, cache_control: &str) -> ImageTransformHandler {
+        ImageTransformHandler {
+            cache_control: cache_control.to_owned(),
+            ..self
+        }
+    }
+
+    /// Returns the signed query string requesting `transform` of the image `key`, to be appended
+    /// to the URL of the image.
+    pub fn signed_query(&self, key: &str, transform: &Transform) -> String {
+        let query = transform.query();
+        let sig = base64::encode_config(
+            self.mac(key, &query).finalize().into_bytes(),
+            base64::URL_SAFE_NO_PAD,
+        );
+        if query.is_empty() {
+            format!("sig={}", sig)
+        } else {
+            format!("{}&sig={}", query, sig)
+        }
+    }
+
+    fn mac(&self, key: &str, query: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts any key");
+        mac.update(key.as_bytes());
+        mac.update(b"?");
+        mac.update(query.as_bytes());
+        mac
+    }
+
+    fn verify(&self, key: &str, transform: &Transform, sig: Option<&str>) -> bool {
+        let sig = match sig.and_then(|sig| base64::decode_config(sig, base64::URL_SAFE_NO_PAD).ok())
+        {
+            Some(sig) => sig,
+            None => return false,
+        };
+        self.mac(key, &transform.query()).verify_slice(&sig).is_ok()
+    }
+
+    /// Returns the limits for decoding source images.
+    fn source_limits(&self) -> Limits {
+        let mut limits = Limits::default();
+        limits.max_image_width = Some(self.max_source_dimension);
+        limits.max_image_height = Some(self.max_source_dimension);
+        limits.max_alloc = Some(self.max_source_alloc);
+        limits
+    }
+
+    /// Returns the path of the cached result of `transform` of the image `key`.
+    fn cache_path(&self, key: &str, transform: &Transform) -> Option<PathBuf> {
+        let dir = self.cache_dir.as_ref()?;
+        let digest = Sha256::digest(format!("{}?{}", key, transform.query()).as_bytes());
+        let name: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+        Some(dir.join(name))
+    }
+
+    async fn serve(&self, state: &mut State) -> Result<Response<Body>, HandlerError> {
+        let parts = FilePathExtractor::borrow_from(state).parts();
+        // parts are decoded, so a part containing `/` would name another directory
+        if parts.iter().any(|part| {
+            part.is_empty()
+                || part == "."
+                || part == ".."
+                || part.contains('/')
+                || part.contains('\\')
+        }) {
+            return Err(error(StatusCode::NOT_FOUND, "invalid image path"));
+        }
+        let key = parts.join("/");
+
+        let (transform, sig) = Transform::parse(Uri::borrow_from(state).query())
+            .ok_or_else(|| error(StatusCode::BAD_REQUEST, "invalid image transform"))?;
+        if !self.verify(&key, &transform, sig.as_deref()) {
+            trace!(
+                "[{}] invalid signature of image transform",
+                request_id(state)
+            );
+            return Err(error(
+                StatusCode::FORBIDDEN,
+                "invalid image transform signature",
+            ));
+        }
+        let dimensions = [transform.width, transform.height];
+        if dimensions.iter().flatten().any(|d| *d > self.max_dimension) {
+            return Err(error(StatusCode::BAD_REQUEST, "image dimension too large"));
+        }
+
+        let cache_path = self.cache_path(&key, &transform);
+        if let Some(path) = &cache_path {
+            if let Some((format, data)) = read_cached(path).await {
+                trace!("[{}] serving cached image {}", request_id(state), key);
+                return Ok(self.response(format, data));
+            }
+        }
+
+        let data = match self.source.load(&key).await? {
+            Some(data) => data,
+            None => return Err(error(StatusCode::NOT_FOUND, "image not found")),
+        };
+        let limits = self.source_limits();
+        let (format, data) = tokio::task::spawn_blocking(move || transform.apply(&data, limits))
+            .await
+            .map_err(anyhow::Error::from)??;
+        trace!("[{}] transformed image {}", request_id(state), key);
+
+        if let Some(path) = &cache_path {
+            if let Err(e) = write_cached(path, format, &data).await {
+                trace!(
+                    "[{}] failed to cache image {}: {}",
+                    request_id(state),
+                    key,
+                    e
+                );
+            }
+        }
+        Ok(self.response(format, data))
+    }
+
+    fn response(&self, format: OutputFormat, data: Vec<u8>) -> Response<Body> {
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, format.mime())
+            .header(CACHE_CONTROL, self.cache_control.as_str())
+            .body(Body::from(data))
+            .unwrap()
+    }
+}
+
+/// Reads a cached image, whose format is stored in the extension of the file.
+async fn read_cached(path: &Path) -> Option<(OutputFormat, Vec<u8>)> {
+    for format in &[
+        OutputFormat::Png,
+        OutputFormat::Jpeg,
+        OutputFormat::Webp,
+        OutputFormat::Avif,
+    ] {
+        if let Ok(data) = tokio::fs::read(path.with_extension(format.name())).await {
+            return Some((*format, data));
+        }
+    }
+    None
+}
+
+/// Caches an image, via a temporary file so that concurrent requests never read a partial file.
+async fn write_cached(path: &Path, format: OutputFormat, data: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let tmp = path.with_extension(format!("{}.{}", uuid::Uuid::new_v4(), "tmp"));
+    tokio::fs::write(&tmp, data).await?;
+    tokio::fs::rename(&tmp, path.with_extension(format.name())).await
+}
+
+fn error(status: StatusCode, message: &'static str) -> HandlerError {
+    HandlerError::from(anyhow::anyhow!(message)).with_status(status)
+}
+
+impl NewHandler for ImageTransformHandler {
+    type Instance = Self;
+
+    fn new_handler(&self) -> anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl Handler for ImageTransformHandler {
+    fn handle(self, mut state: State) -> Pin<Box<HandlerFuture>> {
+        async move {
+            match self.serve(&mut state).await {
+                Ok(response) => Ok((state, response)),
+                Err(err) => Err((state, err)),
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::{GenericImageView, Rgba, RgbaImage};
+
+    use crate::router::builder::{build_simple_router, DefineSingleRoute, DrawRoutes};
+    use crate::test::TestServer;
+
+    #[test]
+    fn parses_and_signs_transforms() {
+        let transform = Transform::new()
+            .with_width(200)
+            .with_format(OutputFormat::Webp)
+            .with_quality(70);
+        assert_eq!(transform.query(), "w=200&fmt=webp&q=70");
+        assert_eq!(
+            Transform::parse(Some("w=200&fmt=webp&q=70&sig=abc")),
+            Some((transform.clone(), Some("abc".to_owned())))
+        );
+        assert_eq!(Transform::parse(Some("w=0")), None);
+        assert_eq!(Transform::parse(Some("q=101")), None);
+        assert_eq!(Transform::parse(Some("fmt=bmp")), None);
+
+        let handler = ImageTransformHandler::new(DirImageSource::new("."), b"secret");
+        let query = handler.signed_query("a.png", &transform);
+        let (parsed, sig) = Transform::parse(Some(&query)).unwrap();
+        assert!(handler.verify("a.png", &parsed, sig.as_deref()));
+        assert!(!handler.verify("b.png", &parsed, sig.as_deref()));
+        assert!(!handler.verify("a.png", &parsed.with_width(300), sig.as_deref()));
+    }
+
+    #[test]
+    fn serves_and_caches_transformed_images() {
+        let root = std::env::temp_dir().join(format!("gotham-images-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(root.join("source")).unwrap();
+        RgbaImage::from_pixel(40, 20, Rgba([255, 0, 0, 255]))
+            .save(root.join("source/red.png"))
+            .unwrap();
+
+        let handler =
+            ImageTransformHandler::new(DirImageSource::new(root.join("source")), b"secret")
+                .with_cache_dir(root.join("cache"))
+                .with_max_dimension(100);
+        let transform = Transform::new()
+            .with_width(10)
+            .with_format(OutputFormat::Jpeg)
+            .with_quality(50);
+        let query = handler.signed_query("red.png", &transform);
+        let too_large = handler.signed_query("red.png", &Transform::new().with_width(200));
+        let missing = handler.signed_query("blue.png", &transform);
+
+        let router = build_simple_router(|route| {
+            route
+                .get("/images/*")
+                .with_path_extractor::<FilePathExtractor>()
+                .to_new_handler(handler);
+        });
+        let test_server = TestServer::new(router).unwrap();
+        let get = |uri: String| test_server.client().get(uri).perform().unwrap();
+
+        for _ in 0..2 {
+            let response = get(format!("http://localhost/images/red.png?{}", query));
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[CONTENT_TYPE], "image/jpeg");
+            let image = image::load_from_memory(&response.read_body().unwrap()).unwrap();
+            assert_eq!(image.dimensions(), (10, 5));
+        }
+        assert_eq!(std::fs::read_dir(root.join("cache")).unwrap().count(), 1);
+
+        let response = get("http://localhost/images/red.png?w=10&fmt=jpeg&q=50".to_owned());
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = get(format!("http://localhost/images/red.png?{}", too_large));
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = get(format!("http://localhost/images/blue.png?{}", missing));
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn limits_source_images_and_paths() {
+        let root = std::env::temp_dir().join(format!("gotham-images-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        RgbaImage::from_pixel(40, 20, Rgba([255, 0, 0, 255]))
+            .save(root.join("sub/red.png"))
+            .unwrap();
+
+        let handler = ImageTransformHandler::new(DirImageSource::new(&root), b"secret")
+            .with_source_limits(30, 1024 * 1024);
+        let query = handler.signed_query("sub/red.png", &Transform::new());
+
+        let router = build_simple_router(|route| {
+            route
+                .get("/images/*")
+                .with_path_extractor::<FilePathExtractor>()
+                .to_new_handler(handler);
+        });
+        let test_server = TestServer::new(router).unwrap();
+        let get = |uri: String| test_server.client().get(uri).perform().unwrap();
+
+        let response = get(format!("http://localhost/images/sub/red.png?{}", query));
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let response = get(format!("http://localhost/images/sub%2Fred.png?{}", query));
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,179 @@
+//! Defines a catalog of localized error messages, in the language negotiated from the
+//! `Accept-Language` header of the request.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use hyper::header::{HeaderMap, ACCEPT_LANGUAGE};
+
+use crate::state::StateData;
+
+/// Localized error messages, looked up by key and language when a `HandlerError` created with
+/// `HandlerError::with_message_key` is converted into a response.
+///
+/// The catalog is installed in `State` by middleware, typically a `StateMiddleware`, and is a
+/// cheaply cloneable handle. The language is negotiated from the `Accept-Language` header of the
+/// request, falling back to the default language of the catalog.
+///
+/// ```rust
+/// # extern crate gotham;
+/// # extern crate hyper;
+/// #
+/// # use gotham::anyhow::anyhow;
+/// # use gotham::handler::{ErrorMessageCatalog, HandlerError};
+/// # use gotham::middleware::state::StateMiddleware;
+/// # use gotham::pipeline::new_pipeline;
+/// # use gotham::pipeline::single::single_pipeline;
+/// # use gotham::router::builder::*;
+/// # use gotham::state::State;
+/// # use gotham::test::TestServer;
+/// # use hyper::header::{ACCEPT_LANGUAGE, CONTENT_LANGUAGE};
+/// # use hyper::StatusCode;
+/// #
+/// async fn user(_state: &mut State) -> Result<String, HandlerError> {
+///     Err(HandlerError::not_found(anyhow!("no user with id 42")).with_message_key("user.not_found"))
+/// }
+///
+/// # fn main() {
+/// let catalog = ErrorMessageCatalog::new("en")
+///     .with_message("en", "user.not_found", "The user does not exist.")
+///     .with_message("de", "user.not_found", "Der Benutzer existiert nicht.");
+///
+/// let (chain, pipelines) =
+///     single_pipeline(new_pipeline().add(StateMiddleware::new(catalog)).build());
+/// let router = build_router(chain, pipelines, |route| {
+///     route.get("/users/:id").to_async_borrowing(user);
+/// });
+///
+/// let test_server = TestServer::new(router).unwrap();
+/// let response = test_server
+///     .client()
+///     .get("http://localhost/users/42")
+///     .with_header(ACCEPT_LANGUAGE, "de-CH, en;q=0.5".parse().unwrap())
+///     .perform()
+///     .unwrap();
+///
+/// assert_eq!(response.status(), StatusCode::NOT_FOUND);
+/// assert_eq!(response.headers().get(CONTENT_LANGUAGE).unwrap(), "de");
+/// assert_eq!(response.read_utf8_body().unwrap(), "Der Benutzer existiert nicht.");
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ErrorMessageCatalog {
+    default_language: String,
+    messages: Arc<HashMap<String, Translations>>,
+}
+
+// The messages of a key by lowercased language, along with the language tag as it was added.
+type Translations = HashMap<String, (String, String)>;
+
+impl ErrorMessageCatalog {
+    /// Creates an empty catalog, which uses messages of `default_language` when the request
+    /// accepts none of the languages of a message.
+    pub fn new(default_language: &str) -> ErrorMessageCatalog {
+        ErrorMessageCatalog {
+            default_language: default_language.to_owned(),
+            messages: Arc::new(HashMap::new()),
+        }
+    }
+
+    /// Adds the message for `key` in `language`, e.g. `en` or `pt-BR`, replacing any previous one.
+    pub fn with_message<M>(mut self, language: &str, key: &str, message: M) -> ErrorMessageCatalog
+    where
+        M: Into<String>,
+    {
+        Arc::make_mut(&mut self.messages)
+            .entry(key.to_owned())
+            .or_default()
+            .insert(
+                language.to_ascii_lowercase(),
+                (language.to_owned(), message.into()),
+            );
+        self
+    }
+
+    /// Returns the message for `key` in the language preferred by the `Accept-Language` header of
+    /// `headers`, along with that language as it was added to the catalog, e.g. `pt-BR`.
+    ///
+    /// A language range such as `de-CH` also matches messages in `de`. Returns `None` if the key is
+    /// unknown, or neither an accepted nor the default language has a message for it.
+    pub fn resolve(&self, key: &str, headers: &HeaderMap) -> Option<(&str, &str)> {
+        let translations = self.messages.get(key)?;
+        let lookup = |language: &str| translations.get(&language.to_ascii_lowercase());
+
+        accepted_languages(headers)
+            .iter()
+            .find_map(|range| {
+                if range == "*" {
+                    return lookup(&self.default_language);
+                }
+                lookup(range).or_else(|| {
+                    range
+                        .split_once('-')
+                        .and_then(|(primary, _)| lookup(primary))
+                })
+            })
+            .or_else(|| lookup(&self.default_language))
+            .map(|(language, message)| (language.as_str(), message.as_str()))
+    }
+}
+
+impl StateData for ErrorMessageCatalog {}
+
+/// Lists the language ranges of the `Accept-Language` header, lowercased and ordered by quality.
+fn accepted_languages(headers: &HeaderMap) -> Vec<String> {
+    let mut ranges: Vec<(f32, String)> = headers
+        .get_all(ACCEPT_LANGUAGE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let range = parts.next()?.trim();
+            let q = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if range.is_empty() || q <= 0.0 {
+                None
+            } else {
+                Some((q, range.to_ascii_lowercase()))
+            }
+        })
+        .collect();
+
+    // a stable sort keeps the order of the header for ranges with equal quality
+    ranges.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+    ranges.into_iter().map(|(_, range)| range).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(accept_language: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_LANGUAGE, accept_language.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn negotiates_message_language() {
+        let catalog = ErrorMessageCatalog::new("en")
+            .with_message("en", "greeting", "hello")
+            .with_message("fr", "greeting", "bonjour")
+            .with_message("pt-BR", "greeting", "olá");
+
+        let resolve = |accept_language| catalog.resolve("greeting", &headers(accept_language));
+        assert_eq!(resolve("fr-CA"), Some(("fr", "bonjour")));
+        assert_eq!(resolve("de, pt-br;q=0.8, fr;q=0.5"), Some(("pt-BR", "olá")));
+        assert_eq!(resolve("fr;q=0, de"), Some(("en", "hello")));
+        assert_eq!(resolve("*"), Some(("en", "hello")));
+        assert_eq!(
+            catalog.resolve("greeting", &HeaderMap::new()),
+            Some(("en", "hello"))
+        );
+        assert_eq!(catalog.resolve("farewell", &headers("en")), None);
+    }
+}
@@ -17,15 +17,34 @@ use mime::{self, Mime};
 use crate::helpers::http::response;
 use crate::state::State;
 
-mod error;
+pub(crate) mod error;
+pub(crate) mod formatter;
+mod messages;
+mod problem_details;
+mod reporter;
+mod validation;
 
 /// Defines handlers for serving static assets.
 pub mod assets;
 
+#[cfg(feature = "image-transform")]
+pub mod image_transform;
+
+pub mod feed;
+pub mod robots;
+pub mod sitemap;
+pub mod well_known;
+
 pub use self::error::{
-    HandlerError, MapHandlerError, MapHandlerErrorFuture, MapHandlerErrorToCustomizedResponse,
-    MapHandlerErrorWithCustomizedResponse,
+    ErrorResponse, HandlerError, MapHandlerError, MapHandlerErrorFuture,
+    MapHandlerErrorToCustomizedResponse, MapHandlerErrorToCustomizedResponseFuture,
+    MapHandlerErrorWithCustomizedResponse, MapHandlerErrorWithCustomizedResponseAsync,
 };
+pub use self::formatter::{DefaultErrorFormatter, ErrorFormat, ErrorFormatter};
+pub use self::messages::ErrorMessageCatalog;
+pub use self::problem_details::{ProblemDetails, APPLICATION_PROBLEM_JSON};
+pub use self::reporter::{ErrorReporter, JsonErrorReporter};
+pub use self::validation::ValidationError;
 
 /// A type alias for the results returned by async fns that can be passed to to_async.
 pub type HandlerResult = std::result::Result<(State, Response<Body>), (State, HandlerError)>;
@@ -0,0 +1,147 @@
+use hyper::{Body, Response, StatusCode};
+use log::error;
+use mime::Mime;
+use serde_derive::Serialize;
+
+use crate::handler::IntoResponse;
+use crate::helpers::http::response::{create_empty_response, create_response};
+use crate::state::{request_id, State};
+
+/// The media type of a problem details response body, as defined in RFC 7807.
+pub const APPLICATION_PROBLEM_JSON: &str = "application/problem+json";
+
+/// A problem details object, as described by [RFC 7807][rfc], which is served as an
+/// `application/problem+json` response body.
+///
+/// A `ProblemDetails` value is usually created from a `HandlerError` via
+/// `HandlerError::into_problem_details`, or implicitly by calling
+/// `HandlerError::with_problem_details` before the error is returned from a handler.
+///
+/// [rfc]: https://tools.ietf.org/html/rfc7807
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProblemDetails {
+    /// A URI reference which identifies the problem type. Defaults to `about:blank`.
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    /// A short, human-readable summary of the problem type.
+    pub title: String,
+    /// The HTTP status code generated for this occurrence of the problem.
+    #[serde(serialize_with = "serialize_status")]
+    pub status: StatusCode,
+    /// A human-readable explanation specific to this occurrence of the problem.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// A URI reference which identifies this occurrence of the problem.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+}
+
+fn serialize_status<S>(status: &StatusCode, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_u16(status.as_u16())
+}
+
+impl ProblemDetails {
+    /// Creates a `ProblemDetails` of the default `about:blank` type for the given status code,
+    /// using the canonical reason of the status code as the title.
+    pub fn new(status: StatusCode) -> ProblemDetails {
+        ProblemDetails {
+            problem_type: "about:blank".to_owned(),
+            title: status
+                .canonical_reason()
+                .unwrap_or("(unregistered)")
+                .to_owned(),
+            status,
+            detail: None,
+            instance: None,
+        }
+    }
+
+    /// Sets the URI reference which identifies the problem type.
+    pub fn with_type<S>(self, problem_type: S) -> ProblemDetails
+    where
+        S: Into<String>,
+    {
+        ProblemDetails {
+            problem_type: problem_type.into(),
+            ..self
+        }
+    }
+
+    /// Sets the human-readable summary of the problem type.
+    pub fn with_title<S>(self, title: S) -> ProblemDetails
+    where
+        S: Into<String>,
+    {
+        ProblemDetails {
+            title: title.into(),
+            ..self
+        }
+    }
+
+    /// Sets the human-readable explanation of this occurrence of the problem.
+    pub fn with_detail<S>(self, detail: S) -> ProblemDetails
+    where
+        S: Into<String>,
+    {
+        ProblemDetails {
+            detail: Some(detail.into()),
+            ..self
+        }
+    }
+
+    /// Sets the URI reference which identifies this occurrence of the problem.
+    pub fn with_instance<S>(self, instance: S) -> ProblemDetails
+    where
+        S: Into<String>,
+    {
+        ProblemDetails {
+            instance: Some(instance.into()),
+            ..self
+        }
+    }
+}
+
+impl IntoResponse for ProblemDetails {
+    fn into_response(self, state: &State) -> Response<Body> {
+        match serde_json::to_vec(&self) {
+            Ok(body) => {
+                let mime: Mime = APPLICATION_PROBLEM_JSON.parse().unwrap();
+                create_response(state, self.status, mime, body)
+            }
+            Err(e) => {
+                error!(
+                    "[{}] failed to serialize problem details: {:?}",
+                    request_id(state),
+                    e
+                );
+                create_empty_response(state, self.status)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_rfc7807_members() {
+        let problem = ProblemDetails::new(StatusCode::NOT_FOUND)
+            .with_detail("no such widget")
+            .with_instance("urn:request:abc");
+
+        assert_eq!(
+            serde_json::to_string(&problem).unwrap(),
+            r#"{"type":"about:blank","title":"Not Found","status":404,"detail":"no such widget","instance":"urn:request:abc"}"#
+        );
+
+        let problem = ProblemDetails::new(StatusCode::BAD_REQUEST).with_type("/problems/invalid");
+        assert_eq!(
+            serde_json::to_string(&problem).unwrap(),
+            r#"{"type":"/problems/invalid","title":"Bad Request","status":400}"#
+        );
+    }
+}
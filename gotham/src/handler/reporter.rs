@@ -0,0 +1,107 @@
+use std::panic::RefUnwindSafe;
+
+use hyper::{Method, Uri};
+use log::{error, warn};
+use serde_json::{json, Value};
+
+use crate::handler::HandlerError;
+use crate::state::{request_id, FromState, State};
+
+/// Receives every `HandlerError` which bubbles up to a `Router`, before it is converted into a
+/// response.
+///
+/// An `ErrorReporter` is registered via `Router::with_error_reporter`, and can be used to emit
+/// structured logs, send events to an error tracking service, or collect metrics. The `State` of
+/// the failed request gives access to the request id, method, path and any other request data.
+///
+/// ```rust
+/// # extern crate gotham;
+/// #
+/// # use gotham::handler::{ErrorReporter, HandlerError, HandlerResult};
+/// # use gotham::router::builder::*;
+/// # use gotham::state::{request_id, State};
+/// #
+/// struct StderrReporter;
+///
+/// impl ErrorReporter for StderrReporter {
+///     fn report(&self, state: &State, err: &HandlerError) {
+///         eprintln!("[{}] {} {:#}", request_id(state), err.status(), err.cause());
+///     }
+/// }
+///
+/// async fn handler(state: State) -> HandlerResult {
+///     Err((state, std::io::Error::last_os_error().into()))
+/// }
+///
+/// # fn main() {
+/// let router = build_simple_router(|route| {
+///     route.get("/").to_async(handler);
+/// })
+/// .with_error_reporter(StderrReporter);
+/// # let _ = router;
+/// # }
+/// ```
+pub trait ErrorReporter: Send + Sync + RefUnwindSafe {
+    /// Reports a `HandlerError` raised while processing the request represented by `state`.
+    fn report(&self, state: &State, err: &HandlerError);
+}
+
+/// An `ErrorReporter` which logs every error as a single line JSON object, including the request
+/// id, method, path, status code and error chain of the failed request.
+///
+/// The object is logged at the `error` level for server errors and at the `warn` level otherwise.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonErrorReporter;
+
+impl JsonErrorReporter {
+    fn to_json(state: &State, err: &HandlerError) -> Value {
+        json!({
+            "request_id": request_id(state),
+            "method": Method::try_borrow_from(state).map(Method::as_str),
+            "path": Uri::try_borrow_from(state).map(Uri::path),
+            "status": err.status().as_u16(),
+            "error": format!("{:#}", err.cause()),
+        })
+    }
+}
+
+impl ErrorReporter for JsonErrorReporter {
+    fn report(&self, state: &State, err: &HandlerError) {
+        let report = JsonErrorReporter::to_json(state, err);
+        if err.status().is_server_error() {
+            error!("{}", report);
+        } else {
+            warn!("{}", report);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::{HeaderMap, StatusCode};
+
+    #[test]
+    fn json_report_contains_request_details() {
+        let mut state = State::new();
+        state.put(HeaderMap::new());
+        state.put(Method::DELETE);
+        state.put("/widgets/1?force=true".parse::<Uri>().unwrap());
+        crate::state::set_request_id(&mut state);
+
+        let err = HandlerError::from(anyhow::anyhow!("locked"))
+            .context("deleting widget")
+            .with_status(StatusCode::CONFLICT);
+
+        assert_eq!(
+            JsonErrorReporter::to_json(&state, &err),
+            json!({
+                "request_id": request_id(&state),
+                "method": "DELETE",
+                "path": "/widgets/1",
+                "status": 409,
+                "error": "deleting widget: locked",
+            })
+        );
+    }
+}
@@ -0,0 +1,196 @@
+//! Defines a handler serving a configurable `robots.txt`.
+//!
+//! ```rust
+//! # extern crate gotham;
+//! #
+//! # use gotham::handler::robots::{RobotsTxt, UserAgentGroup};
+//! # use gotham::router::builder::*;
+//! # use gotham::test::TestServer;
+//! #
+//! # fn main() {
+//! let robots = RobotsTxt::new()
+//!     .with_group(UserAgentGroup::new("*").allow("/").disallow("/admin"))
+//!     .with_sitemap("https://example.com/sitemap.xml");
+//!
+//! let router = build_simple_router(|route| {
+//!     route.get("/robots.txt").to_new_handler(robots);
+//! });
+//!
+//! let test_server = TestServer::new(router).unwrap();
+//! let response = test_server
+//!     .client()
+//!     .get("http://localhost/robots.txt")
+//!     .perform()
+//!     .unwrap();
+//! assert_eq!(
+//!     response.read_utf8_body().unwrap(),
+//!     "User-agent: *\nAllow: /\nDisallow: /admin\n\nSitemap: https://example.com/sitemap.xml\n"
+//! );
+//! # }
+//! ```
+
+use std::fmt::{self, Display};
+use std::pin::Pin;
+
+use futures::prelude::*;
+use hyper::StatusCode;
+
+use crate::handler::{Handler, HandlerFuture, NewHandler};
+use crate::helpers::http::response::create_response;
+use crate::state::State;
+
+/// The rules of `robots.txt` which apply to a group of crawlers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UserAgentGroup {
+    user_agents: Vec<String>,
+    rules: Vec<(&'static str, String)>,
+    crawl_delay: Option<u32>,
+}
+
+impl UserAgentGroup {
+    /// Creates the group for the crawler `user_agent`, or for all crawlers with `*`.
+    pub fn new(user_agent: &str) -> UserAgentGroup {
+        UserAgentGroup {
+            user_agents: vec![user_agent.to_owned()],
+            rules: Vec::new(),
+            crawl_delay: None,
+        }
+    }
+
+    /// Adds another crawler to the group.
+    pub fn user_agent(mut self, user_agent: &str) -> UserAgentGroup {
+        self.user_agents.push(user_agent.to_owned());
+        self
+    }
+
+    /// Allows crawling the paths starting with `path`.
+    pub fn allow(mut self, path: &str) -> UserAgentGroup {
+        self.rules.push(("Allow", path.to_owned()));
+        self
+    }
+
+    /// Disallows crawling the paths starting with `path`.
+    pub fn disallow(mut self, path: &str) -> UserAgentGroup {
+        self.rules.push(("Disallow", path.to_owned()));
+        self
+    }
+
+    /// Asks the crawlers to wait `seconds` between requests. Not all crawlers honour this.
+    pub fn with_crawl_delay(self, seconds: u32) -> UserAgentGroup {
+        UserAgentGroup {
+            crawl_delay: Some(seconds),
+            ..self
+        }
+    }
+}
+
+impl Display for UserAgentGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for user_agent in &self.user_agents {
+            writeln!(f, "User-agent: {}", user_agent)?;
+        }
+        for (rule, path) in &self.rules {
+            writeln!(f, "{}: {}", rule, path)?;
+        }
+        if let Some(seconds) = self.crawl_delay {
+            writeln!(f, "Crawl-delay: {}", seconds)?;
+        }
+        Ok(())
+    }
+}
+
+/// A handler serving `robots.txt`, see the module documentation.
+///
+/// Without any group, all crawlers may crawl the whole site.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RobotsTxt {
+    groups: Vec<UserAgentGroup>,
+    sitemaps: Vec<String>,
+}
+
+impl RobotsTxt {
+    /// Creates an empty `robots.txt`.
+    pub fn new() -> RobotsTxt {
+        RobotsTxt::default()
+    }
+
+    /// Creates the `robots.txt` which disallows crawling the whole site, e.g. for staging
+    /// deployments.
+    pub fn disallow_all() -> RobotsTxt {
+        RobotsTxt::new().with_group(UserAgentGroup::new("*").disallow("/"))
+    }
+
+    /// Adds the rules for a group of crawlers.
+    pub fn with_group(mut self, group: UserAgentGroup) -> RobotsTxt {
+        self.groups.push(group);
+        self
+    }
+
+    /// Lists the absolute URL of a sitemap, e.g. served by `SitemapHandler`.
+    pub fn with_sitemap(mut self, url: &str) -> RobotsTxt {
+        self.sitemaps.push(url.to_owned());
+        self
+    }
+}
+
+impl Display for RobotsTxt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, group) in self.groups.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", group)?;
+        }
+        if !self.groups.is_empty() && !self.sitemaps.is_empty() {
+            writeln!(f)?;
+        }
+        for url in &self.sitemaps {
+            writeln!(f, "Sitemap: {}", url)?;
+        }
+        Ok(())
+    }
+}
+
+impl NewHandler for RobotsTxt {
+    type Instance = Self;
+
+    fn new_handler(&self) -> anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl Handler for RobotsTxt {
+    fn handle(self, state: State) -> Pin<Box<HandlerFuture>> {
+        let body = self.to_string();
+        let response = create_response(&state, StatusCode::OK, mime::TEXT_PLAIN_UTF_8, body);
+        future::ok((state, response)).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_groups_and_sitemaps() {
+        let robots = RobotsTxt::new()
+            .with_group(
+                UserAgentGroup::new("Googlebot")
+                    .user_agent("Bingbot")
+                    .disallow("/private"),
+            )
+            .with_group(UserAgentGroup::new("*").disallow("/").with_crawl_delay(10))
+            .with_sitemap("https://example.com/sitemap.xml");
+
+        assert_eq!(
+            robots.to_string(),
+            "User-agent: Googlebot\nUser-agent: Bingbot\nDisallow: /private\n\n\
+             User-agent: *\nDisallow: /\nCrawl-delay: 10\n\n\
+             Sitemap: https://example.com/sitemap.xml\n"
+        );
+        assert_eq!(
+            RobotsTxt::disallow_all().to_string(),
+            "User-agent: *\nDisallow: /\n"
+        );
+    }
+}
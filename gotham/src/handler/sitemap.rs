@@ -0,0 +1,430 @@
+//! Defines a handler generating `sitemap.xml` from named routes and the URLs of content, e.g.
+//! blog posts loaded from a database.
+//!
+//! A sitemap may list at most 50,000 URLs, so larger sitemaps are split into pages, which are
+//! served as `?page=1`, `?page=2` and so on. The sitemap itself is then a sitemap index listing
+//! the pages.
+//!
+//! ```rust
+//! # extern crate gotham;
+//! #
+//! # use gotham::handler::sitemap::{SitemapHandler, SitemapUrl, UrlProviderFuture};
+//! # use gotham::router::builder::*;
+//! # use gotham::state::State;
+//! # use gotham::test::TestServer;
+//! #
+//! fn posts(_state: &State) -> UrlProviderFuture {
+//!     Box::pin(async {
+//!         // e.g. loaded from a database
+//!         Ok(vec![SitemapUrl::new("/posts/hello-world").with_priority(0.8)])
+//!     })
+//! }
+//!
+//! # fn main() {
+//! let sitemap = SitemapHandler::new("https://example.com")
+//!     .with_route("home")
+//!     .with_provider(posts);
+//!
+//! let router = build_simple_router(|route| {
+//!     route.get("/").named("home").to(|state| (state, "home"));
+//!     route.get("/sitemap.xml").to_new_handler(sitemap);
+//! });
+//!
+//! let test_server = TestServer::new(router).unwrap();
+//! let response = test_server
+//!     .client()
+//!     .get("http://localhost/sitemap.xml")
+//!     .perform()
+//!     .unwrap();
+//! let body = response.read_utf8_body().unwrap();
+//! assert!(body.contains("<url><loc>https://example.com/</loc></url>"));
+//! assert!(body.contains(
+//!     "<url><loc>https://example.com/posts/hello-world</loc><priority>0.8</priority></url>"
+//! ));
+//! # }
+//! ```
+
+use std::fmt::Write;
+use std::future::Future;
+use std::panic::RefUnwindSafe;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures::prelude::*;
+use hyper::{StatusCode, Uri};
+use log::trace;
+
+use crate::handler::{Handler, HandlerError, HandlerFuture, NewHandler};
+use crate::helpers::http::request::query_string;
+use crate::helpers::http::response::create_response;
+use crate::router::UrlFor;
+use crate::state::{request_id, FromState, State};
+
+/// The most URLs a single sitemap may list.
+const MAX_PAGE_SIZE: usize = 50_000;
+
+/// The future returned by `UrlProvider::urls`.
+pub type UrlProviderFuture = Pin<Box<dyn Future<Output = anyhow::Result<Vec<SitemapUrl>>> + Send>>;
+
+/// Provides URLs listed in a sitemap, besides the named routes of the `SitemapHandler`.
+///
+/// The returned future can't borrow `State`, so implementations copy the request data they need
+/// before returning it.
+pub trait UrlProvider: RefUnwindSafe + Send + Sync {
+    /// Returns the URLs to list in the sitemap.
+    fn urls(&self, state: &State) -> UrlProviderFuture;
+}
+
+impl<F> UrlProvider for F
+where
+    F: Fn(&State) -> UrlProviderFuture + RefUnwindSafe + Send + Sync,
+{
+    fn urls(&self, state: &State) -> UrlProviderFuture {
+        self(state)
+    }
+}
+
+/// How often the page at a URL is expected to change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeFrequency {
+    /// The page changes each time it is accessed.
+    Always,
+    /// The page changes hourly.
+    Hourly,
+    /// The page changes daily.
+    Daily,
+    /// The page changes weekly.
+    Weekly,
+    /// The page changes monthly.
+    Monthly,
+    /// The page changes yearly.
+    Yearly,
+    /// The page is archived, and never changes.
+    Never,
+}
+
+impl ChangeFrequency {
+    fn as_str(self) -> &'static str {
+        match self {
+            ChangeFrequency::Always => "always",
+            ChangeFrequency::Hourly => "hourly",
+            ChangeFrequency::Daily => "daily",
+            ChangeFrequency::Weekly => "weekly",
+            ChangeFrequency::Monthly => "monthly",
+            ChangeFrequency::Yearly => "yearly",
+            ChangeFrequency::Never => "never",
+        }
+    }
+}
+
+/// A URL listed in a sitemap.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SitemapUrl {
+    loc: String,
+    last_modified: Option<DateTime<Utc>>,
+    change_frequency: Option<ChangeFrequency>,
+    priority: Option<f32>,
+}
+
+impl SitemapUrl {
+    /// Creates the entry for `loc`, which is either an absolute URL or a path, which is
+    /// appended to the base URL of the `SitemapHandler`.
+    pub fn new(loc: &str) -> SitemapUrl {
+        SitemapUrl {
+            loc: loc.to_owned(),
+            last_modified: None,
+            change_frequency: None,
+            priority: None,
+        }
+    }
+
+    /// Sets when the page was last modified.
+    pub fn with_last_modified(self, last_modified: DateTime<Utc>) -> SitemapUrl {
+        SitemapUrl {
+            last_modified: Some(last_modified),
+            ..self
+        }
+    }
+
+    /// Sets how often the page is expected to change.
+    pub fn with_change_frequency(self, change_frequency: ChangeFrequency) -> SitemapUrl {
+        SitemapUrl {
+            change_frequency: Some(change_frequency),
+            ..self
+        }
+    }
+
+    /// Sets the priority of the page relative to the other pages of the site, from 0.0 to 1.0.
+    pub fn with_priority(self, priority: f32) -> SitemapUrl {
+        SitemapUrl {
+            priority: Some(priority.clamp(0.0, 1.0)),
+            ..self
+        }
+    }
+
+    fn write(&self, out: &mut String, base_url: &str) {
+        out.push_str("<url><loc>");
+        out.push_str(&escape(&absolute(base_url, &self.loc)));
+        out.push_str("</loc>");
+        if let Some(last_modified) = self.last_modified {
+            let last_modified = last_modified.to_rfc3339_opts(SecondsFormat::Secs, true);
+            let _ = write!(out, "<lastmod>{}</lastmod>", last_modified);
+        }
+        if let Some(change_frequency) = self.change_frequency {
+            let _ = write!(
+                out,
+                "<changefreq>{}</changefreq>",
+                change_frequency.as_str()
+            );
+        }
+        if let Some(priority) = self.priority {
+            let _ = write!(out, "<priority>{:.1}</priority>", priority);
+        }
+        out.push_str("</url>\n");
+    }
+}
+
+/// A handler serving the sitemap of a site, see the module documentation.
+///
+/// The sitemap lists the named routes added via `with_route`, followed by the URLs of the
+/// providers in the order they were added. Named routes must not have path parameters; the URLs
+/// of routes with parameters are listed by a provider instead.
+#[derive(Clone)]
+pub struct SitemapHandler {
+    base_url: String,
+    routes: Vec<String>,
+    providers: Vec<Arc<dyn UrlProvider>>,
+    page_size: usize,
+}
+
+impl SitemapHandler {
+    /// Creates the handler for the site at `base_url`, e.g. `https://example.com`, which
+    /// prefixes the paths in the sitemap, as sitemaps list absolute URLs.
+    pub fn new(base_url: &str) -> SitemapHandler {
+        SitemapHandler {
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            routes: Vec::new(),
+            providers: Vec::new(),
+            page_size: MAX_PAGE_SIZE,
+        }
+    }
+
+    /// Lists the route named `name`, see `DefineSingleRoute::named`.
+    pub fn with_route(mut self, name: &str) -> SitemapHandler {
+        self.routes.push(name.to_owned());
+        self
+    }
+
+    /// Lists the URLs of `provider`.
+    pub fn with_provider<P>(mut self, provider: P) -> SitemapHandler
+    where
+        P: UrlProvider + 'static,
+    {
+        self.providers.push(Arc::new(provider));
+        self
+    }
+
+    /// Splits sitemaps with more than `page_size` URLs into pages, instead of 50,000 URLs, which
+    /// is the most a sitemap may list.
+    pub fn with_page_size(self, page_size: usize) -> SitemapHandler {
+        SitemapHandler {
+            page_size: page_size.clamp(1, MAX_PAGE_SIZE),
+            ..self
+        }
+    }
+
+    async fn urls(&self, state: &mut State) -> Result<Vec<SitemapUrl>, HandlerError> {
+        let mut urls = Vec::new();
+        if !self.routes.is_empty() {
+            let url_for = UrlFor::borrow_from(state);
+            for name in &self.routes {
+                let path = url_for.url_for(name, &()).map_err(anyhow::Error::from)?;
+                urls.push(SitemapUrl::new(&path));
+            }
+        }
+
+        let futures: Vec<_> = self
+            .providers
+            .iter()
+            .map(|provider| provider.urls(state))
+            .collect();
+        for provided in future::try_join_all(futures).await? {
+            urls.extend(provided);
+        }
+        Ok(urls)
+    }
+
+    async fn render(&self, state: &mut State) -> Result<String, HandlerError> {
+        let page = query_string::split(Uri::borrow_from(state).query())
+            .get("page")
+            .and_then(|values| values.first())
+            .map(|page| page.as_ref().parse::<usize>());
+        let urls = self.urls(state).await?;
+        let pages = urls.len().div_ceil(self.page_size);
+
+        let urls = match page {
+            None if pages <= 1 => &urls[..],
+            None => {
+                trace!(
+                    "[{}] sitemap of {} urls split into {} pages",
+                    request_id(state),
+                    urls.len(),
+                    pages
+                );
+                let path = Uri::borrow_from(state).path().to_owned();
+                return Ok(self.index(&path, pages));
+            }
+            Some(Ok(page)) if page >= 1 && page <= pages => {
+                let start = (page - 1) * self.page_size;
+                &urls[start..(start + self.page_size).min(urls.len())]
+            }
+            Some(_) => {
+                let err = HandlerError::from(anyhow::anyhow!("no such sitemap page"));
+                return Err(err.with_status(StatusCode::NOT_FOUND));
+            }
+        };
+
+        let mut out = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+        );
+        for url in urls {
+            url.write(&mut out, &self.base_url);
+        }
+        out.push_str("</urlset>\n");
+        Ok(out)
+    }
+
+    /// Renders the sitemap index listing the `pages` of the sitemap at `path`.
+    fn index(&self, path: &str, pages: usize) -> String {
+        let mut out = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+        );
+        for page in 1..=pages {
+            let loc = format!("{}{}?page={}", self.base_url, path, page);
+            let _ = writeln!(out, "<sitemap><loc>{}</loc></sitemap>", escape(&loc));
+        }
+        out.push_str("</sitemapindex>\n");
+        out
+    }
+}
+
+impl NewHandler for SitemapHandler {
+    type Instance = Self;
+
+    fn new_handler(&self) -> anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl Handler for SitemapHandler {
+    fn handle(self, mut state: State) -> Pin<Box<HandlerFuture>> {
+        async move {
+            match self.render(&mut state).await {
+                Ok(body) => {
+                    let mime = "application/xml; charset=utf-8".parse().unwrap();
+                    let response = create_response(&state, StatusCode::OK, mime, body);
+                    Ok((state, response))
+                }
+                Err(err) => Err((state, err)),
+            }
+        }
+        .boxed()
+    }
+}
+
+/// Prefixes `loc` with `base_url`, unless it is an absolute URL.
+fn absolute(base_url: &str, loc: &str) -> String {
+    if loc.starts_with("http://") || loc.starts_with("https://") {
+        loc.to_owned()
+    } else if loc.starts_with('/') {
+        format!("{}{}", base_url, loc)
+    } else {
+        format!("{}/{}", base_url, loc)
+    }
+}
+
+/// Escapes the characters which are not allowed in XML text.
+pub(crate) fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+
+    use crate::router::builder::{build_simple_router, DefineSingleRoute, DrawRoutes};
+    use crate::test::TestServer;
+
+    fn posts(_: &State) -> UrlProviderFuture {
+        Box::pin(async {
+            Ok((1..=3)
+                .map(|id| SitemapUrl::new(&format!("/posts/{}?a=1&b=2", id)))
+                .collect())
+        })
+    }
+
+    #[test]
+    fn renders_sitemap_entries() {
+        let mut out = String::new();
+        SitemapUrl::new("https://cdn.example.com/a")
+            .with_last_modified(Utc.with_ymd_and_hms(2020, 1, 2, 3, 4, 5).unwrap())
+            .with_change_frequency(ChangeFrequency::Weekly)
+            .with_priority(1.5)
+            .write(&mut out, "https://example.com");
+        assert_eq!(
+            out,
+            "<url><loc>https://cdn.example.com/a</loc><lastmod>2020-01-02T03:04:05Z</lastmod>\
+             <changefreq>weekly</changefreq><priority>1.0</priority></url>\n"
+        );
+    }
+
+    #[test]
+    fn paginates_sitemaps_into_an_index() {
+        let sitemap = SitemapHandler::new("https://example.com/")
+            .with_route("about")
+            .with_provider(posts)
+            .with_page_size(3);
+        let router = build_simple_router(|route| {
+            route.get("/about").named("about").to(|state| (state, ""));
+            route.get("/sitemap.xml").to_new_handler(sitemap);
+        });
+        let test_server = TestServer::new(router).unwrap();
+        let get = |uri: &str| {
+            let response = test_server.client().get(uri).perform().unwrap();
+            (response.status(), response.read_utf8_body().unwrap())
+        };
+
+        let (status, body) = get("http://localhost/sitemap.xml");
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("<sitemapindex"));
+        assert!(body.contains("<loc>https://example.com/sitemap.xml?page=2</loc>"));
+        assert!(!body.contains("page=3"));
+
+        let (_, body) = get("http://localhost/sitemap.xml?page=1");
+        assert!(body.contains("<url><loc>https://example.com/about</loc></url>"));
+        assert!(body.contains("<loc>https://example.com/posts/2?a=1&amp;b=2</loc>"));
+        assert!(!body.contains("/posts/3"));
+
+        let (_, body) = get("http://localhost/sitemap.xml?page=2");
+        assert!(body.contains("/posts/3"));
+
+        let (status, _) = get("http://localhost/sitemap.xml?page=3");
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+
+use hyper::{Body, Response, StatusCode};
+use log::error;
+use serde_derive::Serialize;
+
+use crate::handler::IntoResponse;
+use crate::helpers::http::response::{create_empty_response, create_response};
+use crate::state::{request_id, State};
+
+/// An error which aggregates validation messages for the individual fields of a request.
+///
+/// Converting a `ValidationError` into a `HandlerError`, e.g. via the `?` operator, results in a
+/// `422 Unprocessable Entity` response, whose body lists the messages as JSON:
+///
+/// ```json
+/// {"errors":{"email":["invalid format"]}}
+/// ```
+///
+/// Messages can be added from manual checks via `add`, and from failed extractions (such as
+/// deserializing the request body) via `add_error`.
+///
+/// ```rust
+/// # extern crate gotham;
+/// # extern crate hyper;
+/// #
+/// # use gotham::handler::{HandlerError, ValidationError};
+/// # use hyper::StatusCode;
+/// #
+/// fn validate(email: &str, age: &str) -> Result<(), HandlerError> {
+///     let mut errors = ValidationError::new();
+///
+///     if !email.contains('@') {
+///         errors.add("email", "invalid format");
+///     }
+///
+///     if let Err(e) = age.parse::<u8>() {
+///         errors.add_error("age", e);
+///     }
+///
+///     errors.into_result()?;
+///     Ok(())
+/// }
+///
+/// # fn main() {
+/// let err = validate("gotham", "old").unwrap_err();
+/// assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ValidationError {
+    errors: BTreeMap<String, Vec<String>>,
+}
+
+impl ValidationError {
+    /// Creates a `ValidationError` without any messages.
+    pub fn new() -> ValidationError {
+        ValidationError::default()
+    }
+
+    /// Adds a message for the given field.
+    pub fn add<F, M>(&mut self, field: F, message: M) -> &mut ValidationError
+    where
+        F: Into<String>,
+        M: Into<String>,
+    {
+        self.errors
+            .entry(field.into())
+            .or_default()
+            .push(message.into());
+        self
+    }
+
+    /// Adds the message of an error, such as a failed deserialization, for the given field.
+    pub fn add_error<F, E>(&mut self, field: F, error: E) -> &mut ValidationError
+    where
+        F: Into<String>,
+        E: Display,
+    {
+        self.add(field, error.to_string())
+    }
+
+    /// Adds all messages of `other`.
+    pub fn extend(&mut self, other: ValidationError) -> &mut ValidationError {
+        for (field, messages) in other.errors {
+            self.errors.entry(field).or_default().extend(messages);
+        }
+        self
+    }
+
+    /// Returns `true` if no messages have been added.
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Returns the messages added for the given field.
+    pub fn messages(&self, field: &str) -> &[String] {
+        self.errors.get(field).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Returns `Ok(())` if no messages have been added, or the `ValidationError` otherwise.
+    pub fn into_result(self) -> Result<(), ValidationError> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("validation failed")?;
+
+        let mut separator = ": ";
+        for (field, messages) in &self.errors {
+            for message in messages {
+                write!(f, "{}{}: {}", separator, field, message)?;
+                separator = "; ";
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl IntoResponse for ValidationError {
+    fn into_response(self, state: &State) -> Response<Body> {
+        validation_response(state, StatusCode::UNPROCESSABLE_ENTITY, &self)
+    }
+}
+
+/// Renders the messages of `errors` as the JSON body of a response with the given status code.
+pub(crate) fn validation_response(
+    state: &State,
+    status: StatusCode,
+    errors: &ValidationError,
+) -> Response<Body> {
+    match serde_json::to_vec(errors) {
+        Ok(body) => create_response(state, status, mime::APPLICATION_JSON, body),
+        Err(e) => {
+            error!(
+                "[{}] failed to serialize validation errors: {:?}",
+                request_id(state),
+                e
+            );
+            create_empty_response(state, status)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregates_messages_per_field() {
+        let mut errors = ValidationError::new();
+        assert!(errors.clone().into_result().is_ok());
+
+        errors
+            .add("email", "invalid format")
+            .add("name", "required");
+
+        let mut other = ValidationError::new();
+        other.add_error("email", "too long");
+        errors.extend(other);
+
+        assert_eq!(errors.messages("email"), ["invalid format", "too long"]);
+        assert!(errors.messages("age").is_empty());
+        assert_eq!(
+            errors.to_string(),
+            "validation failed: email: invalid format; email: too long; name: required"
+        );
+        assert_eq!(
+            serde_json::to_string(&errors).unwrap(),
+            r#"{"errors":{"email":["invalid format","too long"],"name":["required"]}}"#
+        );
+    }
+}
@@ -0,0 +1,439 @@
+//! Defines a handler serving the documents under `/.well-known/`, as registered in RFC 8615.
+//!
+//! The `WellKnownHandler` is drawn once on the router, for all paths under `/.well-known/`, and
+//! serves the documents registered on it by their name, e.g. `security.txt`, with the content
+//! type of the document. Requests for other names are answered with `404 Not Found`.
+//!
+//! ```rust
+//! # extern crate gotham;
+//! # extern crate chrono;
+//! # extern crate hyper;
+//! #
+//! # use chrono::{TimeZone, Utc};
+//! # use gotham::handler::well_known::{SecurityTxt, WellKnownHandler};
+//! # use gotham::router::builder::*;
+//! # use gotham::test::TestServer;
+//! # use hyper::header::LOCATION;
+//! # use hyper::StatusCode;
+//! #
+//! # fn main() {
+//! let expires = Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap();
+//! let well_known = WellKnownHandler::new()
+//!     .with_security_txt(SecurityTxt::new("mailto:security@example.com", expires))
+//!     .with_change_password("/account/password")
+//!     .with_json("assetlinks.json", &serde_json::json!([]));
+//!
+//! let router = build_simple_router(|route| {
+//!     route.get("/.well-known/*").to_new_handler(well_known);
+//! });
+//!
+//! let test_server = TestServer::new(router).unwrap();
+//! let response = test_server
+//!     .client()
+//!     .get("http://localhost/.well-known/security.txt")
+//!     .perform()
+//!     .unwrap();
+//! assert_eq!(
+//!     response.read_utf8_body().unwrap(),
+//!     "Contact: mailto:security@example.com\nExpires: 2030-01-01T00:00:00Z\n"
+//! );
+//!
+//! let response = test_server
+//!     .client()
+//!     .get("http://localhost/.well-known/change-password")
+//!     .perform()
+//!     .unwrap();
+//! assert_eq!(response.status(), StatusCode::FOUND);
+//! assert_eq!(response.headers().get(LOCATION).unwrap(), "/account/password");
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::future::Future;
+use std::panic::RefUnwindSafe;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures::prelude::*;
+use hyper::header::{HeaderValue, ACCESS_CONTROL_ALLOW_ORIGIN, LOCATION};
+use hyper::{StatusCode, Uri};
+use mime::Mime;
+use serde_derive::Serialize;
+
+use crate::handler::{Handler, HandlerError, HandlerFuture, NewHandler};
+use crate::helpers::http::request::query_string;
+use crate::helpers::http::response::{create_empty_response, create_response};
+use crate::state::{FromState, State};
+
+/// The media type of WebFinger responses, as defined in RFC 7033.
+pub const APPLICATION_JRD_JSON: &str = "application/jrd+json";
+
+/// The contents of `security.txt`, as defined in RFC 9116.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SecurityTxt {
+    contacts: Vec<String>,
+    expires: DateTime<Utc>,
+    fields: Vec<(&'static str, String)>,
+}
+
+impl SecurityTxt {
+    /// Creates the `security.txt` naming the `contact` URI for reporting vulnerabilities, e.g.
+    /// `mailto:security@example.com`, which is valid until `expires`.
+    pub fn new(contact: &str, expires: DateTime<Utc>) -> SecurityTxt {
+        SecurityTxt {
+            contacts: vec![contact.to_owned()],
+            expires,
+            fields: Vec::new(),
+        }
+    }
+
+    /// Adds another contact URI, in order of preference.
+    pub fn with_contact(mut self, contact: &str) -> SecurityTxt {
+        self.contacts.push(contact.to_owned());
+        self
+    }
+
+    /// Adds the URI of the key for encrypting reports.
+    pub fn with_encryption(self, uri: &str) -> SecurityTxt {
+        self.with_field("Encryption", uri)
+    }
+
+    /// Adds the URI of the page acknowledging reporters.
+    pub fn with_acknowledgments(self, uri: &str) -> SecurityTxt {
+        self.with_field("Acknowledgments", uri)
+    }
+
+    /// Sets the languages reports are preferably written in, e.g. `en, de`.
+    pub fn with_preferred_languages(self, languages: &str) -> SecurityTxt {
+        self.with_field("Preferred-Languages", languages)
+    }
+
+    /// Adds the URI `security.txt` is canonically served at.
+    pub fn with_canonical(self, uri: &str) -> SecurityTxt {
+        self.with_field("Canonical", uri)
+    }
+
+    /// Adds the URI of the vulnerability disclosure policy.
+    pub fn with_policy(self, uri: &str) -> SecurityTxt {
+        self.with_field("Policy", uri)
+    }
+
+    /// Adds the URI of security related job openings.
+    pub fn with_hiring(self, uri: &str) -> SecurityTxt {
+        self.with_field("Hiring", uri)
+    }
+
+    fn with_field(mut self, name: &'static str, value: &str) -> SecurityTxt {
+        self.fields.push((name, value.to_owned()));
+        self
+    }
+}
+
+impl Display for SecurityTxt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for contact in &self.contacts {
+            writeln!(f, "Contact: {}", contact)?;
+        }
+        let expires = self.expires.to_rfc3339_opts(SecondsFormat::Secs, true);
+        writeln!(f, "Expires: {}", expires)?;
+        for (name, value) in &self.fields {
+            writeln!(f, "{}: {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// A link of a WebFinger resource.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct WebFingerLink {
+    /// The relation type of the link, e.g. `self`.
+    pub rel: String,
+    /// The media type of the linked resource.
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    /// The URI of the linked resource.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub href: Option<String>,
+}
+
+/// The JSON Resource Descriptor describing a WebFinger resource, as defined in RFC 7033.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Jrd {
+    /// The URI of the resource, e.g. `acct:alice@example.com`.
+    pub subject: String,
+    /// Other URIs identifying the resource.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    /// Properties of the resource, by their URI.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub properties: HashMap<String, Option<String>>,
+    /// The links of the resource.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<WebFingerLink>,
+}
+
+/// The future returned by `WebFingerResolver::resolve`.
+pub type WebFingerFuture = Pin<Box<dyn Future<Output = anyhow::Result<Option<Jrd>>> + Send>>;
+
+/// Resolves WebFinger resources, e.g. by looking up the account of an `acct:` URI.
+pub trait WebFingerResolver: RefUnwindSafe + Send + Sync {
+    /// Returns the descriptor of `resource`, or `None` if the resource is unknown.
+    fn resolve(&self, resource: &str) -> WebFingerFuture;
+}
+
+impl<F> WebFingerResolver for F
+where
+    F: Fn(&str) -> WebFingerFuture + RefUnwindSafe + Send + Sync,
+{
+    fn resolve(&self, resource: &str) -> WebFingerFuture {
+        self(resource)
+    }
+}
+
+#[derive(Clone)]
+enum Document {
+    Static(Mime, Bytes),
+    Redirect(String),
+    WebFinger(Arc<dyn WebFingerResolver>),
+}
+
+/// A handler serving the documents under `/.well-known/`, see the module documentation.
+#[derive(Clone, Default)]
+pub struct WellKnownHandler {
+    documents: HashMap<String, Document>,
+}
+
+impl WellKnownHandler {
+    /// Creates the handler without any documents.
+    pub fn new() -> WellKnownHandler {
+        WellKnownHandler::default()
+    }
+
+    /// Serves `body` as the document `name`, with the content type `mime`.
+    pub fn with_document<B>(mut self, name: &str, mime: Mime, body: B) -> WellKnownHandler
+    where
+        B: Into<Bytes>,
+    {
+        let document = Document::Static(mime, body.into());
+        self.documents.insert(name.to_owned(), document);
+        self
+    }
+
+    /// Serves `value`, serialized as JSON, as the document `name`, e.g. `assetlinks.json` or
+    /// `apple-app-site-association`.
+    ///
+    /// # Panics
+    ///
+    /// If `value` can't be serialized.
+    pub fn with_json<T>(self, name: &str, value: &T) -> WellKnownHandler
+    where
+        T: serde::Serialize + ?Sized,
+    {
+        let body = serde_json::to_vec(value).expect("well-known document can't be serialized");
+        self.with_document(name, mime::APPLICATION_JSON, body)
+    }
+
+    /// Serves `security_txt` as `security.txt`.
+    pub fn with_security_txt(self, security_txt: SecurityTxt) -> WellKnownHandler {
+        let body = security_txt.to_string();
+        self.with_document("security.txt", mime::TEXT_PLAIN_UTF_8, body)
+    }
+
+    /// Redirects `change-password` to `location`, the page where users change their password.
+    pub fn with_change_password(mut self, location: &str) -> WellKnownHandler {
+        let document = Document::Redirect(location.to_owned());
+        self.documents
+            .insert("change-password".to_owned(), document);
+        self
+    }
+
+    /// Serves `webfinger`, resolving the requested resource with `resolver`.
+    ///
+    /// The links of the resource are filtered by the `rel` query parameters of the request.
+    pub fn with_webfinger<R>(mut self, resolver: R) -> WellKnownHandler
+    where
+        R: WebFingerResolver + 'static,
+    {
+        let document = Document::WebFinger(Arc::new(resolver));
+        self.documents.insert("webfinger".to_owned(), document);
+        self
+    }
+}
+
+/// Returns the name of the requested document, the path segment following `.well-known`.
+fn document_name(path: &str) -> &str {
+    let name = match path.find("/.well-known/") {
+        Some(i) => &path[i + "/.well-known/".len()..],
+        None => path.rsplit('/').next().unwrap_or(""),
+    };
+    name.trim_end_matches('/')
+}
+
+async fn webfinger(
+    state: &mut State,
+    resolver: &dyn WebFingerResolver,
+) -> Result<hyper::Response<hyper::Body>, HandlerError> {
+    let query = query_string::split(Uri::borrow_from(state).query());
+    let resource = match query.get("resource").and_then(|values| values.first()) {
+        Some(resource) => resource.as_ref().to_owned(),
+        None => {
+            let err = HandlerError::bad_request(anyhow::anyhow!("missing resource parameter"));
+            return Err(err);
+        }
+    };
+    let rels: Vec<String> = query
+        .get("rel")
+        .map(|rels| rels.iter().map(|rel| rel.as_ref().to_owned()).collect())
+        .unwrap_or_default();
+
+    let mut jrd = match resolver.resolve(&resource).await? {
+        Some(jrd) => jrd,
+        None => return Ok(create_empty_response(state, StatusCode::NOT_FOUND)),
+    };
+    if !rels.is_empty() {
+        jrd.links.retain(|link| rels.contains(&link.rel));
+    }
+
+    let body = serde_json::to_vec(&jrd)?;
+    let mime = APPLICATION_JRD_JSON.parse().unwrap();
+    let mut response = create_response(state, StatusCode::OK, mime, body);
+    // RFC 7033 requires WebFinger to be usable from any origin
+    response
+        .headers_mut()
+        .insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+    Ok(response)
+}
+
+impl NewHandler for WellKnownHandler {
+    type Instance = Self;
+
+    fn new_handler(&self) -> anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl Handler for WellKnownHandler {
+    fn handle(self, mut state: State) -> Pin<Box<HandlerFuture>> {
+        let name = document_name(Uri::borrow_from(&state).path());
+        let response = match self.documents.get(name) {
+            Some(Document::Static(mime, body)) => {
+                create_response(&state, StatusCode::OK, mime.clone(), body.clone())
+            }
+            Some(Document::Redirect(location)) => {
+                let mut response = create_empty_response(&state, StatusCode::FOUND);
+                match HeaderValue::from_str(location) {
+                    Ok(location) => {
+                        response.headers_mut().insert(LOCATION, location);
+                    }
+                    Err(_) => *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR,
+                }
+                response
+            }
+            Some(Document::WebFinger(resolver)) => {
+                let resolver = resolver.clone();
+                return async move {
+                    match webfinger(&mut state, resolver.as_ref()).await {
+                        Ok(response) => Ok((state, response)),
+                        Err(err) => Err((state, err)),
+                    }
+                }
+                .boxed();
+            }
+            None => create_empty_response(&state, StatusCode::NOT_FOUND),
+        };
+        future::ok((state, response)).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hyper::header::CONTENT_TYPE;
+
+    use crate::router::builder::{build_simple_router, DefineSingleRoute, DrawRoutes};
+    use crate::test::TestServer;
+
+    fn resolve(resource: &str) -> WebFingerFuture {
+        let jrd = if resource == "acct:alice@example.com" {
+            let link = |rel: &str| WebFingerLink {
+                rel: rel.to_owned(),
+                media_type: None,
+                href: Some(format!("https://example.com/alice#{}", rel)),
+            };
+            Some(Jrd {
+                subject: resource.to_owned(),
+                links: vec![link("self"), link("profile")],
+                ..Jrd::default()
+            })
+        } else {
+            None
+        };
+        future::ok(jrd).boxed()
+    }
+
+    #[test]
+    fn renders_security_txt() {
+        let expires = DateTime::parse_from_rfc3339("2030-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let security_txt = SecurityTxt::new("mailto:security@example.com", expires)
+            .with_contact("https://example.com/security")
+            .with_preferred_languages("en, de")
+            .with_policy("https://example.com/policy");
+        assert_eq!(
+            security_txt.to_string(),
+            "Contact: mailto:security@example.com\nContact: https://example.com/security\n\
+             Expires: 2030-01-01T00:00:00Z\nPreferred-Languages: en, de\n\
+             Policy: https://example.com/policy\n"
+        );
+    }
+
+    #[test]
+    fn serves_registered_documents() {
+        let well_known = WellKnownHandler::new()
+            .with_json("assetlinks.json", &serde_json::json!([{"relation": []}]))
+            .with_webfinger(resolve);
+        let router = build_simple_router(|route| {
+            route.get("/.well-known/*").to_new_handler(well_known);
+        });
+        let test_server = TestServer::new(router).unwrap();
+        let get = |uri: &str| test_server.client().get(uri).perform().unwrap();
+
+        let response = get("http://localhost/.well-known/assetlinks.json");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+        assert_eq!(response.read_utf8_body().unwrap(), r#"[{"relation":[]}]"#);
+
+        let response = get(
+            "http://localhost/.well-known/webfinger?resource=acct%3Aalice%40example.com&rel=self",
+        );
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            APPLICATION_JRD_JSON
+        );
+        assert_eq!(
+            response.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "*"
+        );
+        assert_eq!(
+            response.read_utf8_body().unwrap(),
+            r#"{"subject":"acct:alice@example.com","links":[{"rel":"self","href":"https://example.com/alice#self"}]}"#
+        );
+
+        let response =
+            get("http://localhost/.well-known/webfinger?resource=acct%3Abob%40example.com");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = get("http://localhost/.well-known/webfinger");
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = get("http://localhost/.well-known/host-meta");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
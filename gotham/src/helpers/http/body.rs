@@ -0,0 +1,101 @@
+//! Helpers for reading request and response bodies without buffering more than a limit.
+
+use std::error::Error;
+use std::fmt;
+
+use bytes::Bytes;
+use futures::prelude::*;
+use hyper::{Body, StatusCode};
+
+use crate::handler::HandlerError;
+
+/// The error of a request body which exceeds a limit.
+#[derive(Debug)]
+pub(crate) struct BodyTooLarge(pub(crate) u64);
+
+impl fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "request body exceeds the limit of {} bytes", self.0)
+    }
+}
+
+impl Error for BodyTooLarge {}
+
+/// Reads `body` until more than `limit` bytes were received, returning the first `limit` bytes,
+/// whether the body was longer, and a body yielding the full content again. Longer bodies aren't
+/// buffered beyond the chunk exceeding the limit, but streamed on.
+pub(crate) async fn read_prefix(
+    mut body: Body,
+    limit: usize,
+) -> Result<(Bytes, bool, Body), hyper::Error> {
+    let mut chunks = Vec::new();
+    let mut read = 0;
+    while read <= limit {
+        match body.next().await {
+            Some(chunk) => {
+                let chunk = chunk?;
+                read += chunk.len();
+                chunks.push(chunk);
+            }
+            None => {
+                let captured = Bytes::from(chunks.concat());
+                return Ok((captured.clone(), false, Body::from(captured)));
+            }
+        }
+    }
+
+    let mut captured = Vec::with_capacity(limit);
+    for chunk in &chunks {
+        let remaining = limit - captured.len();
+        captured.extend_from_slice(&chunk[..chunk.len().min(remaining)]);
+    }
+
+    let body = stream::iter(chunks.into_iter().map(Ok)).chain(body);
+    Ok((Bytes::from(captured), true, Body::wrap_stream(body)))
+}
+
+/// Reads the whole of `body`, failing with `413 Payload Too Large` as soon as it exceeds `limit`
+/// bytes.
+pub(crate) async fn read_limited(body: Body, limit: usize) -> Result<Bytes, HandlerError> {
+    match read_prefix(body, limit).await {
+        Ok((bytes, false, _)) => Ok(bytes),
+        Ok((_, true, _)) => Err(HandlerError::from(BodyTooLarge(limit as u64))
+            .with_status(StatusCode::PAYLOAD_TOO_LARGE)),
+        Err(e) => Err(HandlerError::from(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_only_the_prefix() {
+        let chunks = vec!["abc", "def", "ghi"];
+        let body = Body::wrap_stream(stream::iter(chunks.into_iter().map(Ok::<_, hyper::Error>)));
+
+        let (captured, truncated, body) =
+            futures::executor::block_on(read_prefix(body, 4)).unwrap();
+        assert_eq!(&captured[..], b"abcd");
+        assert!(truncated);
+
+        let body = futures::executor::block_on(hyper::body::to_bytes(body)).unwrap();
+        assert_eq!(&body[..], b"abcdefghi");
+
+        let (captured, truncated, _) =
+            futures::executor::block_on(read_prefix(Body::from("abc"), 4)).unwrap();
+        assert_eq!(&captured[..], b"abc");
+        assert!(!truncated);
+    }
+
+    #[test]
+    fn rejects_bodies_above_the_limit() {
+        let read = |body: &'static str| futures::executor::block_on(read_limited(body.into(), 4));
+
+        assert_eq!(&read("abcd").unwrap()[..], b"abcd");
+        assert_eq!(
+            read("abcde").unwrap_err().status(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+}
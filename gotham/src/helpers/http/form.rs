@@ -0,0 +1,345 @@
+//! Helpers for server-rendered HTML forms, which bind a submitted form to a serde struct and
+//! validate it. If the form is invalid, the submitted values and the errors of each field are
+//! added to the `TemplateContext` to render the form again.
+//!
+//! ```rust
+//! # extern crate gotham;
+//! # extern crate hyper;
+//! # extern crate mime;
+//! # #[macro_use]
+//! # extern crate serde_derive;
+//! #
+//! # use hyper::{Body, Response, StatusCode};
+//! # use gotham::handler::HandlerError;
+//! # use gotham::helpers::http::form::{FieldErrors, Form, Validate};
+//! # use gotham::helpers::http::response::create_response;
+//! # use gotham::middleware::template_context::TemplateContext;
+//! # use gotham::router::builder::*;
+//! # use gotham::state::State;
+//! # use gotham::test::TestServer;
+//! #
+//! #[derive(Deserialize)]
+//! struct Signup {
+//!     email: String,
+//!     age: u8,
+//! }
+//!
+//! impl Validate for Signup {
+//!     fn validate(&self, errors: &mut FieldErrors) {
+//!         if !self.email.contains('@') {
+//!             errors.add("email", "is not an email address");
+//!         }
+//!     }
+//! }
+//!
+//! async fn signup(state: &mut State) -> Result<Response<Body>, HandlerError> {
+//!     let form = Form::<Signup>::from_request(state).await?;
+//!     let res = match form.into_valid() {
+//!         Ok(signup) => {
+//!             let body = format!("welcome {} ({})", signup.email, signup.age);
+//!             create_response(state, StatusCode::OK, mime::TEXT_PLAIN, body)
+//!         }
+//!         Err(form) => {
+//!             let mut context = TemplateContext::new();
+//!             form.add_to(&mut context);
+//!             // e.g. `tera.render("signup.html", &tera::Context::from_value(context)?)`
+//!             let body = serde_json::to_string(context.values()).unwrap();
+//!             create_response(state, StatusCode::UNPROCESSABLE_ENTITY, mime::APPLICATION_JSON, body)
+//!         }
+//!     };
+//!     Ok(res)
+//! }
+//!
+//! # fn main() {
+//! let router = build_simple_router(|route| {
+//!     route.post("/signup").to_async_borrowing(signup);
+//! });
+//!
+//! let test_server = TestServer::new(router).unwrap();
+//! let response = test_server
+//!     .client()
+//!     .post(
+//!         "http://localhost/signup",
+//!         "email=ann&age=old",
+//!         mime::APPLICATION_WWW_FORM_URLENCODED,
+//!     )
+//!     .perform()
+//!     .unwrap();
+//! assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+//! assert_eq!(
+//!     response.read_utf8_body().unwrap(),
+//!     r#"{"form":{"errors":{"age":["is invalid"]},"values":{"age":"old","email":"ann"}}}"#
+//! );
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, HeaderMap, StatusCode};
+use log::trace;
+use serde::de::DeserializeOwned;
+use serde_derive::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::extractor::internal::{self, ExtractorError};
+use crate::handler::HandlerError;
+use crate::helpers::http::body::read_limited;
+use crate::helpers::http::request::query_string;
+use crate::middleware::template_context::TemplateContext;
+use crate::state::{request_id, FromState, State};
+
+/// The size of the largest form body read by `Form::from_request`.
+const DEFAULT_MAX_FORM_SIZE: usize = 1024 * 1024;
+
+/// Validates a value bound to a form, beyond what its type already guarantees.
+pub trait Validate {
+    /// Adds an error to `errors` for each invalid field.
+    fn validate(&self, errors: &mut FieldErrors);
+}
+
+/// The error messages of the fields of a form, by the name of the field.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct FieldErrors {
+    errors: BTreeMap<String, Vec<String>>,
+}
+
+impl FieldErrors {
+    /// Creates an empty set of errors.
+    pub fn new() -> FieldErrors {
+        FieldErrors::default()
+    }
+
+    /// Adds the error `message` for the field `field`.
+    pub fn add(&mut self, field: &str, message: &str) {
+        self.errors
+            .entry(field.to_owned())
+            .or_default()
+            .push(message.to_owned());
+    }
+
+    /// Returns the error messages of `field`.
+    pub fn get(&self, field: &str) -> &[String] {
+        self.errors.get(field).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Returns `true` if no field has an error.
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// A submitted form, bound to the type `T`.
+///
+/// The submitted values are kept as they were entered, to fill in the fields when the form is
+/// rendered again. Binding stops at the first field which can't be deserialized, and values which
+/// were bound are only validated if every field could be deserialized.
+#[derive(Clone, Debug)]
+pub struct Form<T> {
+    values: Map<String, Value>,
+    errors: FieldErrors,
+    value: Option<T>,
+}
+
+impl<T> Form<T>
+where
+    T: DeserializeOwned + Validate,
+{
+    /// Binds the `application/x-www-form-urlencoded` form in `body`.
+    pub fn bind(body: &[u8]) -> Form<T> {
+        let mapping = query_string::split(Some(&String::from_utf8_lossy(body)));
+        let values = mapping
+            .iter()
+            .map(|(field, values)| {
+                let mut values: Vec<Value> = values
+                    .iter()
+                    .map(|value| Value::from(value.as_ref()))
+                    .collect();
+                let value = match values.len() {
+                    1 => values.remove(0),
+                    _ => Value::from(values),
+                };
+                (field.clone(), value)
+            })
+            .collect();
+
+        let mut errors = FieldErrors::new();
+        let value = match internal::from_form_mapping::<T>(&mapping) {
+            Ok(value) => {
+                value.validate(&mut errors);
+                Some(value)
+            }
+            Err(ExtractorError::MissingField(field)) => {
+                errors.add(field, "is required");
+                None
+            }
+            Err(ExtractorError::InvalidField(field, e)) => {
+                trace!("form field {} could not be bound: {}", field, e);
+                errors.add(&field, "is invalid");
+                None
+            }
+            Err(e) => {
+                trace!("form could not be bound: {}", e);
+                errors.add("", "is invalid");
+                None
+            }
+        };
+
+        Form {
+            values,
+            errors,
+            value,
+        }
+    }
+
+    /// Reads the body of the request in `state` and binds it. Fails with `415 Unsupported Media
+    /// Type` unless the body is an `application/x-www-form-urlencoded` form, and with `413 Payload
+    /// Too Large` if the body exceeds 1 MiB.
+    pub async fn from_request(state: &mut State) -> Result<Form<T>, HandlerError> {
+        Form::from_request_with_max_size(state, DEFAULT_MAX_FORM_SIZE).await
+    }
+
+    /// Reads the body of the request in `state` and binds it like `from_request`, failing with
+    /// `413 Payload Too Large` if the body exceeds `max_size` bytes.
+    pub async fn from_request_with_max_size(
+        state: &mut State,
+        max_size: usize,
+    ) -> Result<Form<T>, HandlerError> {
+        let is_form = HeaderMap::borrow_from(state)
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<mime::Mime>().ok())
+            .is_some_and(|mime| mime.essence_str() == "application/x-www-form-urlencoded");
+        if !is_form {
+            trace!("[{}] request body is not a form", request_id(state));
+            let err = HandlerError::from(anyhow::anyhow!("request body is not a form"));
+            return Err(err.with_status(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+        }
+
+        let body = read_limited(Body::take_from(state), max_size)
+            .await
+            .map_err(|e| match e.status() {
+                StatusCode::PAYLOAD_TOO_LARGE => e,
+                _ => e.with_status(StatusCode::BAD_REQUEST),
+            })?;
+        Ok(Form::bind(&body))
+    }
+}
+
+impl<T> Form<T> {
+    /// Returns `true` if the form was bound and has no errors.
+    pub fn is_valid(&self) -> bool {
+        self.value.is_some() && self.errors.is_empty()
+    }
+
+    /// Returns the bound value if the form is valid, or else the form, to render it again.
+    pub fn into_valid(self) -> Result<T, Form<T>> {
+        match self.value {
+            Some(value) if self.errors.is_empty() => Ok(value),
+            value => Err(Form { value, ..self }),
+        }
+    }
+
+    /// Returns the submitted values by the name of their field. Fields submitted multiple times
+    /// have an array of values.
+    pub fn values(&self) -> &Map<String, Value> {
+        &self.values
+    }
+
+    /// Returns the errors of the fields. Errors which don't belong to a field, e.g. if the body
+    /// can't be bound at all, are listed for the empty field name.
+    pub fn errors(&self) -> &FieldErrors {
+        &self.errors
+    }
+
+    /// Adds the submitted values and the errors to `context`, as `form.values` and `form.errors`.
+    pub fn add_to(&self, context: &mut TemplateContext) {
+        context.insert(
+            "form",
+            json!({ "values": self.values, "errors": self.errors }),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_derive::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct Signup {
+        email: String,
+        age: u8,
+        #[serde(default)]
+        topics: Vec<String>,
+    }
+
+    impl Validate for Signup {
+        fn validate(&self, errors: &mut FieldErrors) {
+            if !self.email.contains('@') {
+                errors.add("email", "is not an email address");
+            }
+            if self.age < 18 {
+                errors.add("age", "must be 18 or older");
+            }
+        }
+    }
+
+    #[test]
+    fn binds_and_validates_forms() {
+        let form = Form::<Signup>::bind(b"email=ann%40example.com&age=30&topics=a&topics=b");
+        assert!(form.is_valid());
+        let signup = form.into_valid().unwrap();
+        assert_eq!(signup.email, "ann@example.com");
+        assert_eq!(signup.topics, vec!["a", "b"]);
+
+        let form = Form::<Signup>::bind(b"email=ann&age=12");
+        assert!(!form.is_valid());
+        assert_eq!(form.errors().get("email"), ["is not an email address"]);
+        assert_eq!(form.errors().get("age"), ["must be 18 or older"]);
+        assert!(form.into_valid().is_err());
+    }
+
+    #[test]
+    fn reports_fields_which_cannot_be_bound() {
+        let form = Form::<Signup>::bind(b"email=ann%40example.com&age=old");
+        assert_eq!(form.errors().get("age"), ["is invalid"]);
+        assert!(form.errors().get("email").is_empty());
+
+        let form = Form::<Signup>::bind(b"age=30&topics=a&topics=b");
+        assert_eq!(form.errors().get("email"), ["is required"]);
+
+        let mut context = TemplateContext::new();
+        form.add_to(&mut context);
+        assert_eq!(
+            context.get("form"),
+            Some(&json!({
+                "values": { "age": "30", "topics": ["a", "b"] },
+                "errors": { "email": ["is required"] },
+            }))
+        );
+    }
+
+    #[test]
+    fn limits_the_size_of_form_bodies() {
+        let read = |body: &'static str| {
+            let mut state = State::new();
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                CONTENT_TYPE,
+                "application/x-www-form-urlencoded".parse().unwrap(),
+            );
+            state.put(headers);
+            state.put(Body::from(body));
+            futures::executor::block_on(Form::<Signup>::from_request_with_max_size(&mut state, 24))
+        };
+
+        assert_eq!(
+            read("email=ann%40example.com&age=30").unwrap_err().status(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert!(read("email=a%40b.c&age=30").unwrap().is_valid());
+    }
+}
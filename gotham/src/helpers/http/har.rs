@@ -0,0 +1,78 @@
+//! Helpers for rendering HTTP messages in the HAR (HTTP Archive) format.
+//!
+//! See <https://w3c.github.io/web-performance/specs/HAR/Overview.html>.
+
+use hyper::header::HeaderMap;
+use serde_json::{json, Value};
+
+/// Wraps `entries` into a HAR log document.
+pub(crate) fn log(entries: Vec<Value>) -> Value {
+    json!({
+        "log": {
+            "version": "1.2",
+            "creator": { "name": "gotham", "version": env!("CARGO_PKG_VERSION") },
+            "entries": entries,
+        }
+    })
+}
+
+/// Lists name/value pairs, such as headers, as HAR records.
+pub(crate) fn records<'a, I>(pairs: I) -> Vec<Value>
+where
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    pairs
+        .into_iter()
+        .map(|(name, value)| json!({ "name": name, "value": value }))
+        .collect()
+}
+
+/// Lists the headers of `headers` as HAR records, replacing invalid UTF-8.
+pub(crate) fn header_records(headers: &HeaderMap) -> Vec<Value> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            json!({
+                "name": name.as_str(),
+                "value": String::from_utf8_lossy(value.as_bytes()),
+            })
+        })
+        .collect()
+}
+
+/// Lists the query parameters of `url` as HAR records.
+pub(crate) fn query_string(url: &str) -> Vec<Value> {
+    let query = match url.split_once('?') {
+        Some((_, query)) => query,
+        None => return vec![],
+    };
+
+    records(query.split('&').filter(|pair| !pair.is_empty()).map(
+        |pair| match pair.split_once('=') {
+            Some((name, value)) => (name, value),
+            None => (pair, ""),
+        },
+    ))
+}
+
+/// Renders a message body as HAR text, replacing invalid UTF-8.
+pub(crate) fn text(body: &[u8]) -> String {
+    String::from_utf8_lossy(body).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_query_parameters() {
+        assert_eq!(query_string("http://localhost/"), Vec::<Value>::new());
+        assert_eq!(
+            query_string("http://localhost/?a=1&&flag"),
+            vec![
+                json!({ "name": "a", "value": "1" }),
+                json!({ "name": "flag", "value": "" }),
+            ]
+        );
+    }
+}
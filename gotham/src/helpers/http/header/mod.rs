@@ -1,8 +1,113 @@
 //! Headers recognised by Gotham which do not exist in the standard headers
 //! provided by the Hyper library.
 
+use hyper::header::{HeaderMap, HeaderName};
+
+use crate::state::{FromState, State, StateData};
+
 /// Marks the identifier of a request to a Gotham server.
 pub const X_REQUEST_ID: &str = "x-request-id";
 
 /// Marks the execution time of a Gotham request.
 pub const X_RUNTIME_DURATION: &str = "x-runtime-duration";
+
+/// Overrides the method of a `POST` request, see `gotham::router::MethodOverride`.
+pub const X_HTTP_METHOD_OVERRIDE: &str = "x-http-method-override";
+
+/// The default prefix of internal response headers, see `InternalHeaders`.
+pub const X_INTERNAL_PREFIX: &str = "x-internal-";
+
+/// The namespace of internal response headers, which handlers and middleware use to pass data
+/// towards middleware further out in the pipeline, e.g. the user id for an access log.
+///
+/// Gotham removes every response header whose name starts with the prefix before the response
+/// is written to the client, whether the response was created by a handler or from an error. The
+/// prefix is `x-internal-` unless an `InternalHeaders` value is put into `State`, typically by a
+/// `StateMiddleware`:
+///
+/// ```rust
+/// # extern crate gotham;
+/// # extern crate hyper;
+/// #
+/// # use gotham::helpers::http::header::InternalHeaders;
+/// # use gotham::middleware::state::StateMiddleware;
+/// # use gotham::pipeline::new_pipeline;
+/// # use gotham::pipeline::single::single_pipeline;
+/// # use gotham::router::builder::*;
+/// # use gotham::state::State;
+/// # use gotham::test::TestServer;
+/// # use hyper::{Body, Response};
+/// #
+/// fn handler(state: State) -> (State, Response<Body>) {
+///     let response = Response::builder()
+///         .header("x-app-user-id", "42")
+///         .body(Body::from("hello"))
+///         .unwrap();
+///     (state, response)
+/// }
+///
+/// # fn main() {
+/// let internal_headers = InternalHeaders::with_prefix("X-App-");
+/// let (chain, pipelines) =
+///     single_pipeline(new_pipeline().add(StateMiddleware::new(internal_headers)).build());
+/// let router = build_router(chain, pipelines, |route| {
+///     route.get("/").to(handler);
+/// });
+///
+/// let test_server = TestServer::new(router).unwrap();
+/// let response = test_server.client().get("http://localhost/").perform().unwrap();
+/// assert!(!response.headers().contains_key("x-app-user-id"));
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct InternalHeaders {
+    prefix: String,
+}
+
+impl InternalHeaders {
+    /// Creates the namespace of headers whose name starts with `prefix`, ignoring case.
+    pub fn with_prefix(prefix: &str) -> InternalHeaders {
+        InternalHeaders {
+            prefix: prefix.to_ascii_lowercase(),
+        }
+    }
+
+    /// Returns the namespace stored in `state`, or the default one if there is none.
+    pub fn from_state(state: &State) -> InternalHeaders {
+        InternalHeaders::try_borrow_from(state)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Returns the lowercased prefix of the namespace.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Returns `true` if `name` belongs to the namespace.
+    pub fn is_internal(&self, name: &HeaderName) -> bool {
+        // header names are always lowercase
+        name.as_str().starts_with(&self.prefix)
+    }
+
+    /// Removes all headers which belong to the namespace from `headers`.
+    pub fn strip(&self, headers: &mut HeaderMap) {
+        let internal: Vec<HeaderName> = headers
+            .keys()
+            .filter(|name| self.is_internal(name))
+            .cloned()
+            .collect();
+
+        for name in internal {
+            headers.remove(name);
+        }
+    }
+}
+
+impl Default for InternalHeaders {
+    fn default() -> InternalHeaders {
+        InternalHeaders::with_prefix(X_INTERNAL_PREFIX)
+    }
+}
+
+impl StateData for InternalHeaders {}
@@ -0,0 +1,403 @@
+//! Helpers for building `Link` headers (RFC 8288) and HAL / JSON:API style link objects.
+//!
+//! A `Links` value collects the links of a resource once, and can then be rendered into a `Link`
+//! response header, the `_links` object of a HAL document or the `links` object of a JSON:API
+//! document, so the different representations never disagree.
+//!
+//! ```rust
+//! # extern crate gotham;
+//! # extern crate hyper;
+//! #
+//! # use gotham::helpers::http::link::Links;
+//! # use hyper::header::LINK;
+//! # use hyper::{Body, Response};
+//! #
+//! # fn main() {
+//! let links = Links::new()
+//!     .self_link("/widgets?page=2")
+//!     .paginate("/widgets", "page", 2, 3);
+//!
+//! let mut response = Response::new(Body::empty());
+//! links.apply(&mut response);
+//!
+//! assert_eq!(
+//!     response.headers().get(LINK).unwrap(),
+//!     "</widgets?page=2>; rel=\"self\", </widgets?page=1>; rel=\"first\", \
+//!      </widgets?page=1>; rel=\"prev\", </widgets?page=3>; rel=\"next\", \
+//!      </widgets?page=3>; rel=\"last\""
+//! );
+//! # }
+//! ```
+//!
+//! Links to named routes are built with the `UrlFor` the router puts into `State`, via
+//! `Link::route` or `Links::route`.
+
+use hyper::header::{HeaderValue, LINK};
+use hyper::Response;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::router::{UrlFor, UrlForError};
+
+/// A single link, consisting of a target URI, a relation type and optional target attributes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Link {
+    uri: String,
+    rel: String,
+    params: Vec<(String, String)>,
+}
+
+impl Link {
+    /// Creates a link to `uri` with the relation type `rel`.
+    pub fn new<U, R>(uri: U, rel: R) -> Link
+    where
+        U: Into<String>,
+        R: Into<String>,
+    {
+        Link {
+            uri: uri.into(),
+            rel: rel.into(),
+            params: vec![],
+        }
+    }
+
+    /// Creates a link with the relation type `rel` to the route named `name`, whose path is built
+    /// by `url_for` from the fields of `params`, see `UrlFor::url_for`.
+    pub fn route<T, R>(
+        url_for: &UrlFor,
+        name: &str,
+        params: &T,
+        rel: R,
+    ) -> Result<Link, UrlForError>
+    where
+        T: Serialize + ?Sized,
+        R: Into<String>,
+    {
+        Ok(Link::new(url_for.url_for(name, params)?, rel))
+    }
+
+    /// Adds a target attribute, such as `title` or `type`, to the link.
+    pub fn with_param<K, V>(mut self, key: K, value: V) -> Link
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.params.push((key.into(), value.into()));
+        self
+    }
+
+    /// Returns the target URI of the link.
+    pub fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    /// Returns the relation type of the link.
+    pub fn rel(&self) -> &str {
+        &self.rel
+    }
+
+    fn to_header_string(&self) -> String {
+        let mut link = format!("<{}>; rel=\"{}\"", self.uri, self.rel);
+
+        for (key, value) in &self.params {
+            link.push_str("; ");
+            link.push_str(key);
+            link.push_str("=\"");
+            link.push_str(&value.replace('\\', "\\\\").replace('"', "\\\""));
+            link.push('"');
+        }
+
+        link
+    }
+}
+
+/// An ordered collection of `Link` values belonging to a single resource.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Links {
+    links: Vec<Link>,
+}
+
+impl Links {
+    /// Creates an empty collection of links.
+    pub fn new() -> Links {
+        Links::default()
+    }
+
+    /// Adds a `Link` to the collection.
+    pub fn link(mut self, link: Link) -> Links {
+        self.links.push(link);
+        self
+    }
+
+    /// Adds a link with the relation type `rel` to the route named `name`, see `Link::route`.
+    pub fn route<T, R>(
+        self,
+        url_for: &UrlFor,
+        name: &str,
+        params: &T,
+        rel: R,
+    ) -> Result<Links, UrlForError>
+    where
+        T: Serialize + ?Sized,
+        R: Into<String>,
+    {
+        Ok(self.link(Link::route(url_for, name, params, rel)?))
+    }
+
+    /// Adds a link with the relation type `self`.
+    pub fn self_link<U>(self, uri: U) -> Links
+    where
+        U: Into<String>,
+    {
+        self.link(Link::new(uri, "self"))
+    }
+
+    /// Adds a link with the relation type `next`.
+    pub fn next<U>(self, uri: U) -> Links
+    where
+        U: Into<String>,
+    {
+        self.link(Link::new(uri, "next"))
+    }
+
+    /// Adds a link with the relation type `prev`.
+    pub fn prev<U>(self, uri: U) -> Links
+    where
+        U: Into<String>,
+    {
+        self.link(Link::new(uri, "prev"))
+    }
+
+    /// Adds a link with the relation type `describedby`.
+    pub fn described_by<U>(self, uri: U) -> Links
+    where
+        U: Into<String>,
+    {
+        self.link(Link::new(uri, "describedby"))
+    }
+
+    /// Adds the `first`, `prev`, `next` and `last` links for a paginated collection at `base_uri`,
+    /// where the one-based page number is passed in the `param` query string parameter.
+    ///
+    /// The `prev` and `next` links are omitted on the first and last page respectively.
+    pub fn paginate(self, base_uri: &str, param: &str, page: u64, last_page: u64) -> Links {
+        let separator = if base_uri.contains('?') { '&' } else { '?' };
+        let page_uri = |page: u64| format!("{}{}{}={}", base_uri, separator, param, page);
+
+        let mut links = self.link(Link::new(page_uri(1), "first"));
+
+        if page > 1 {
+            links = links.prev(page_uri(page - 1));
+        }
+
+        if page < last_page {
+            links = links.next(page_uri(page + 1));
+        }
+
+        links.link(Link::new(page_uri(last_page.max(1)), "last"))
+    }
+
+    /// Returns the links in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = &Link> {
+        self.links.iter()
+    }
+
+    /// Renders the links as the value of a `Link` header, or `None` if there are no links.
+    pub fn to_header_value(&self) -> Option<HeaderValue> {
+        if self.links.is_empty() {
+            return None;
+        }
+
+        let value = self
+            .links
+            .iter()
+            .map(Link::to_header_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        HeaderValue::from_str(&value).ok()
+    }
+
+    /// Appends the `Link` header to the response.
+    pub fn apply<B>(&self, response: &mut Response<B>) {
+        if let Some(value) = self.to_header_value() {
+            response.headers_mut().append(LINK, value);
+        }
+    }
+
+    /// Renders the links as a HAL `_links` object, e.g. `{"self": {"href": "/widgets/1"}}`.
+    ///
+    /// Relation types which occur more than once are rendered as an array of link objects.
+    pub fn to_hal(&self) -> Value {
+        let mut object = Map::new();
+
+        for link in &self.links {
+            let mut hal = Map::new();
+            hal.insert("href".to_owned(), Value::String(link.uri.clone()));
+            for (key, value) in &link.params {
+                hal.insert(key.clone(), Value::String(value.clone()));
+            }
+
+            push_rel(&mut object, &link.rel, Value::Object(hal));
+        }
+
+        Value::Object(object)
+    }
+
+    /// Renders the links as a JSON:API `links` object, e.g. `{"self": "/widgets/1"}`.
+    ///
+    /// Links with target attributes are rendered as link objects with `href` and `meta` members.
+    pub fn to_json_api(&self) -> Value {
+        let mut object = Map::new();
+
+        for link in &self.links {
+            let value = if link.params.is_empty() {
+                Value::String(link.uri.clone())
+            } else {
+                let meta = link
+                    .params
+                    .iter()
+                    .map(|(key, value)| (key.clone(), Value::String(value.clone())))
+                    .collect();
+
+                let mut json_api = Map::new();
+                json_api.insert("href".to_owned(), Value::String(link.uri.clone()));
+                json_api.insert("meta".to_owned(), Value::Object(meta));
+                Value::Object(json_api)
+            };
+
+            object.insert(link.rel.clone(), value);
+        }
+
+        Value::Object(object)
+    }
+}
+
+fn push_rel(object: &mut Map<String, Value>, rel: &str, value: Value) {
+    match object.remove(rel) {
+        None => {
+            object.insert(rel.to_owned(), value);
+        }
+        Some(Value::Array(mut values)) => {
+            values.push(value);
+            object.insert(rel.to_owned(), Value::Array(values));
+        }
+        Some(existing) => {
+            object.insert(rel.to_owned(), Value::Array(vec![existing, value]));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::{Body, StatusCode};
+    use serde_json::json;
+
+    use crate::router::builder::{build_simple_router, DefineSingleRoute, DrawRoutes};
+    use crate::state::{FromState, State};
+    use crate::test::TestServer;
+
+    #[test]
+    fn renders_link_header_with_params() {
+        let links = Links::new()
+            .link(Link::new("/schema", "describedby").with_param("title", "Widget \"v2\""));
+
+        assert_eq!(
+            links.to_header_value().unwrap(),
+            "</schema>; rel=\"describedby\"; title=\"Widget \\\"v2\\\"\""
+        );
+        assert!(Links::new().to_header_value().is_none());
+    }
+
+    #[test]
+    fn paginates_first_and_last_pages() {
+        let rels = |links: Links| {
+            links
+                .iter()
+                .map(|link| format!("{} {}", link.rel(), link.uri()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            rels(Links::new().paginate("/w?sort=name", "p", 1, 2)),
+            vec![
+                "first /w?sort=name&p=1",
+                "next /w?sort=name&p=2",
+                "last /w?sort=name&p=2"
+            ]
+        );
+        assert_eq!(
+            rels(Links::new().paginate("/w", "p", 2, 2)),
+            vec!["first /w?p=1", "prev /w?p=1", "last /w?p=2"]
+        );
+    }
+
+    #[test]
+    fn renders_hal_and_json_api_links() {
+        let links = Links::new()
+            .self_link("/widgets/1")
+            .link(Link::new("/parts/1", "item"))
+            .link(Link::new("/parts/2", "item").with_param("title", "Spring"));
+
+        assert_eq!(
+            links.to_hal(),
+            json!({
+                "self": { "href": "/widgets/1" },
+                "item": [
+                    { "href": "/parts/1" },
+                    { "href": "/parts/2", "title": "Spring" }
+                ]
+            })
+        );
+
+        assert_eq!(
+            Links::new()
+                .self_link("/widgets/1")
+                .described_by("/schema")
+                .link(Link::new("/widgets/2", "next").with_param("count", "10"))
+                .to_json_api(),
+            json!({
+                "self": "/widgets/1",
+                "describedby": "/schema",
+                "next": { "href": "/widgets/2", "meta": { "count": "10" } }
+            })
+        );
+    }
+
+    #[test]
+    fn builds_links_to_named_routes() {
+        fn widget(state: State) -> (State, Response<Body>) {
+            let url_for = UrlFor::borrow_from(&state);
+            let links = Links::new()
+                .route(url_for, "widget", &json!({ "id": 1 }), "self")
+                .and_then(|links| links.route(url_for, "widgets", &(), "collection"))
+                .unwrap();
+            assert_eq!(
+                Link::route(url_for, "unknown", &(), "related"),
+                Err(UrlForError::UnknownRoute("unknown".to_owned()))
+            );
+
+            let mut response = Response::new(Body::empty());
+            links.apply(&mut response);
+            (state, response)
+        }
+
+        let router = build_simple_router(|route| {
+            route.get("/widgets").named("widgets").to(widget);
+            route.get("/widgets/:id").named("widget").to(widget);
+        });
+
+        let test_server = TestServer::new(router).unwrap();
+        let response = test_server
+            .client()
+            .get("http://localhost/widgets/1")
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[LINK],
+            "</widgets/1>; rel=\"self\", </widgets>; rel=\"collection\""
+        );
+    }
+}
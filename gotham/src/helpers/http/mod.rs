@@ -1,17 +1,28 @@
 //! Helpers for HTTP request handling and response generation
 
+pub(crate) mod body;
+pub mod form;
+pub(crate) mod har;
 pub mod header;
+pub mod link;
+#[cfg(feature = "object-store")]
+pub mod object_storage;
 pub mod request;
 pub mod response;
+pub mod upload;
 
 use log::trace;
 use percent_encoding::percent_decode;
 use std;
+use std::borrow::Cow;
 
 /// Represents data that has been successfully percent decoded and is valid UTF-8
 #[derive(Clone, PartialEq, Debug)]
 pub struct PercentDecoded {
     val: String,
+    // the data as it was provided, only kept if it differs from the decoded value, as most
+    // segments contain nothing to decode
+    raw: Option<String>,
 }
 
 impl PercentDecoded {
@@ -25,8 +36,13 @@ impl PercentDecoded {
         match percent_decode(raw.as_bytes()).decode_utf8() {
             Ok(pd) => {
                 trace!(" percent_decode: {}, src: {}", pd, raw);
+                let raw = match pd {
+                    Cow::Borrowed(_) => None,
+                    Cow::Owned(_) => Some(raw.to_owned()),
+                };
                 Some(PercentDecoded {
                     val: pd.into_owned(),
+                    raw,
                 })
             }
             Err(_) => {
@@ -35,6 +51,22 @@ impl PercentDecoded {
             }
         }
     }
+
+    /// Wraps a value decoded from `raw` in a way other than `PercentDecoded::new`, e.g. according
+    /// to a `PathDecoding` policy.
+    pub(crate) fn with_value(raw: &str, val: String) -> Self {
+        let raw = if raw == val {
+            None
+        } else {
+            Some(raw.to_owned())
+        };
+        PercentDecoded { val, raw }
+    }
+
+    /// Returns the data as it was provided, before decoding.
+    pub(crate) fn raw(&self) -> &str {
+        self.raw.as_deref().unwrap_or(&self.val)
+    }
 }
 
 impl AsRef<str> for PercentDecoded {
@@ -94,6 +126,11 @@ mod tests {
     fn ensure_valid_percent_decode() {
         let pd = PercentDecoded::new("%41+%42%2B%63%20%64").unwrap();
         assert_eq!("A+B+c d", pd.as_ref());
+        assert_eq!("%41+%42%2B%63%20%64", pd.raw());
+
+        let pd = PercentDecoded::new("plain").unwrap();
+        assert_eq!(pd.raw, None);
+        assert_eq!("plain", pd.raw());
     }
 
     #[test]
@@ -0,0 +1,553 @@
+//! Helpers for keeping uploaded files in an object store, e.g. S3, Google Cloud Storage or Azure
+//! Blob Storage, via the `object_store` crate. Requires the `object-store` feature.
+//!
+//! Uploads are streamed to the store chunk by chunk, from a raw request body or from the files
+//! of a `multipart/form-data` body, without buffering them in memory. Downloads are streamed from
+//! the store and support single `Range` requests. The stores of the cloud providers are enabled
+//! via the features of the `object_store` crate, e.g. `object_store = { version = "0.12",
+//! features = ["aws"] }`.
+//!
+//! ```rust
+//! # extern crate gotham;
+//! # extern crate hyper;
+//! # extern crate mime;
+//! #
+//! # use std::sync::Arc;
+//! # use hyper::header::{HeaderValue, RANGE};
+//! # use hyper::{Body, Response, StatusCode};
+//! # use gotham::handler::HandlerError;
+//! # use gotham::helpers::http::object_storage::ObjectStorage;
+//! # use gotham::middleware::state::StateMiddleware;
+//! # use gotham::object_store::memory::InMemory;
+//! # use gotham::object_store::path::Path;
+//! # use gotham::pipeline::new_pipeline;
+//! # use gotham::pipeline::single::single_pipeline;
+//! # use gotham::router::builder::*;
+//! # use gotham::state::{FromState, State};
+//! # use gotham::test::TestServer;
+//! #
+//! async fn upload(state: &mut State) -> Result<Response<Body>, HandlerError> {
+//!     let storage = ObjectStorage::borrow_from(state).clone();
+//!     let object = storage.store_body(state, &Path::from("report.txt")).await?;
+//!     let body = format!("stored {} bytes", object.size());
+//!     Ok(gotham::helpers::http::response::create_response(
+//!         state,
+//!         StatusCode::CREATED,
+//!         mime::TEXT_PLAIN,
+//!         body,
+//!     ))
+//! }
+//!
+//! async fn download(state: &mut State) -> Result<Response<Body>, HandlerError> {
+//!     let storage = ObjectStorage::borrow_from(state).clone();
+//!     storage.serve(state, &Path::from("report.txt")).await
+//! }
+//!
+//! # fn main() {
+//! let storage = ObjectStorage::new(Arc::new(InMemory::new()));
+//! let (chain, pipelines) =
+//!     single_pipeline(new_pipeline().add(StateMiddleware::new(storage)).build());
+//! let router = build_router(chain, pipelines, |route| {
+//!     route.put("/report").to_async_borrowing(upload);
+//!     route.get("/report").to_async_borrowing(download);
+//! });
+//!
+//! let test_server = TestServer::new(router).unwrap();
+//! let response = test_server
+//!     .client()
+//!     .put("http://localhost/report", "hello world", mime::TEXT_PLAIN)
+//!     .perform()
+//!     .unwrap();
+//! assert_eq!(response.read_utf8_body().unwrap(), "stored 11 bytes");
+//!
+//! let response = test_server
+//!     .client()
+//!     .get("http://localhost/report")
+//!     .with_header(RANGE, HeaderValue::from_static("bytes=6-"))
+//!     .perform()
+//!     .unwrap();
+//! assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+//! assert_eq!(response.read_utf8_body().unwrap(), "world");
+//! # }
+//! ```
+
+use std::ops::Range;
+use std::panic::RefUnwindSafe;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use futures::prelude::*;
+use hyper::header::{
+    HeaderMap, HeaderValue, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+    LAST_MODIFIED, RANGE,
+};
+use hyper::{Body, Method, Response, StatusCode};
+use log::trace;
+use mime::Mime;
+use object_store::path::Path;
+use object_store::signer::Signer;
+use object_store::{GetOptions, GetRange, ObjectMeta, ObjectStore, WriteMultipart};
+
+use crate::handler::HandlerError;
+use crate::helpers::http::response::create_empty_response;
+use crate::state::{request_id, FromState, State, StateData};
+
+/// The number of chunks of an upload which are sent to the store concurrently.
+const UPLOAD_CONCURRENCY: usize = 8;
+
+/// An object written to the store by `ObjectStorage`.
+#[derive(Clone, Debug)]
+pub struct StoredObject {
+    path: Path,
+    size: u64,
+    field: Option<String>,
+    file_name: Option<String>,
+    content_type: Option<Mime>,
+}
+
+impl StoredObject {
+    /// Returns the path of the object in the store.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the size of the object in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the name of the multipart field the object was uploaded in, if any.
+    pub fn field(&self) -> Option<&str> {
+        self.field.as_deref()
+    }
+
+    /// Returns the file name the client sent for the object, if any.
+    pub fn file_name(&self) -> Option<&str> {
+        self.file_name.as_deref()
+    }
+
+    /// Returns the content type the client sent for the object, if any.
+    pub fn content_type(&self) -> Option<&Mime> {
+        self.content_type.as_ref()
+    }
+}
+
+/// Streams uploads to, and downloads from, an `ObjectStore`, see the module documentation.
+///
+/// `ObjectStorage` is cheap to clone, so it can be shared with handlers via a `StateMiddleware`.
+#[derive(Clone)]
+pub struct ObjectStorage {
+    store: Arc<dyn ObjectStore>,
+}
+
+// Requests share the store, but a panicking request can't leave it in an inconsistent state.
+impl RefUnwindSafe for ObjectStorage {}
+
+impl StateData for ObjectStorage {}
+
+impl ObjectStorage {
+    /// Creates the helper for `store`.
+    pub fn new(store: Arc<dyn ObjectStore>) -> ObjectStorage {
+        ObjectStorage { store }
+    }
+
+    /// Returns the underlying store.
+    pub fn store(&self) -> &Arc<dyn ObjectStore> {
+        &self.store
+    }
+
+    /// Streams the request body in `state` to the object `path`, replacing an existing object.
+    pub async fn store_body(
+        &self,
+        state: &mut State,
+        path: &Path,
+    ) -> Result<StoredObject, HandlerError> {
+        let content_type = HeaderMap::borrow_from(state)
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok());
+        let body = Body::take_from(state)
+            .map_err(|e| HandlerError::from(e).with_status(StatusCode::BAD_REQUEST));
+
+        let size = self.upload(path, body).await?;
+        trace!("[{}] stored {} bytes in {}", request_id(state), size, path);
+        Ok(StoredObject {
+            path: path.clone(),
+            size,
+            field: None,
+            file_name: None,
+            content_type,
+        })
+    }
+
+    /// Streams the fields of the `multipart/form-data` request body in `state` to the store.
+    ///
+    /// `name` is called with the name and the file name of each field, and returns the path to
+    /// store the field at, or `None` to skip the field. The file name is chosen by the client, so
+    /// it must not be used as a path without checking it. Fails with `415 Unsupported Media Type`
+    /// unless the body is a multipart form, and with `400 Bad Request` if it is malformed.
+    pub async fn store_multipart<F>(
+        &self,
+        state: &mut State,
+        mut name: F,
+    ) -> Result<Vec<StoredObject>, HandlerError>
+    where
+        F: FnMut(&str, Option<&str>) -> Option<Path>,
+    {
+        let boundary = HeaderMap::borrow_from(state)
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| multer::parse_boundary(value).ok());
+        let boundary = match boundary {
+            Some(boundary) => boundary,
+            None => {
+                trace!(
+                    "[{}] request body is not a multipart form",
+                    request_id(state)
+                );
+                let err =
+                    HandlerError::from(anyhow::anyhow!("request body is not a multipart form"));
+                return Err(err.with_status(StatusCode::UNSUPPORTED_MEDIA_TYPE));
+            }
+        };
+
+        let bad_request =
+            |e: multer::Error| HandlerError::from(e).with_status(StatusCode::BAD_REQUEST);
+        let mut multipart = multer::Multipart::new(Body::take_from(state), boundary);
+        let mut objects = Vec::new();
+        while let Some(field) = multipart.next_field().await.map_err(bad_request)? {
+            let field_name = field.name().unwrap_or("").to_owned();
+            let file_name = field.file_name().map(str::to_owned);
+            let path = match name(&field_name, file_name.as_deref()) {
+                Some(path) => path,
+                None => continue,
+            };
+            let content_type = field.content_type().cloned();
+
+            let size = self.upload(&path, field.map_err(bad_request)).await?;
+            trace!(
+                "[{}] stored {} bytes of field {} in {}",
+                request_id(state),
+                size,
+                field_name,
+                path
+            );
+            objects.push(StoredObject {
+                path,
+                size,
+                field: Some(field_name),
+                file_name,
+                content_type,
+            });
+        }
+        Ok(objects)
+    }
+
+    /// Writes the chunks of `body` to the object `path`, and returns its size.
+    async fn upload<S>(&self, path: &Path, body: S) -> Result<u64, HandlerError>
+    where
+        S: Stream<Item = Result<bytes::Bytes, HandlerError>>,
+    {
+        let mut writer = WriteMultipart::new(self.store.put_multipart(path).await?);
+        futures::pin_mut!(body);
+
+        let mut size = 0;
+        while let Some(chunk) = body.next().await {
+            let written = match chunk {
+                Ok(chunk) => writer
+                    .wait_for_capacity(UPLOAD_CONCURRENCY)
+                    .await
+                    .map(|()| chunk)
+                    .map_err(HandlerError::from),
+                Err(e) => Err(e),
+            };
+            match written {
+                Ok(chunk) => {
+                    size += chunk.len() as u64;
+                    writer.put(chunk);
+                }
+                Err(e) => {
+                    if let Err(abort) = writer.abort().await {
+                        trace!(" failed to abort the upload to {}: {}", path, abort);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        writer.finish().await?;
+        Ok(size)
+    }
+
+    /// Streams the object `path` as the response to the request in `state`.
+    ///
+    /// A request for a single byte range is answered with `206 Partial Content`, or with
+    /// `416 Range Not Satisfiable` if the range is outside the object. Other `Range` headers are
+    /// ignored, and the whole object is sent. Fails with `404 Not Found` if there is no object
+    /// at `path`.
+    pub async fn serve(
+        &self,
+        state: &mut State,
+        path: &Path,
+    ) -> Result<Response<Body>, HandlerError> {
+        let meta = self.store.head(path).await.map_err(not_found)?;
+        let range = HeaderMap::borrow_from(state)
+            .get(RANGE)
+            .and_then(|value| value.to_str().ok())
+            .map_or(ByteRange::Full, |value| ByteRange::parse(value, meta.size));
+
+        let range = match range {
+            ByteRange::Full => 0..meta.size,
+            ByteRange::Partial(range) => range,
+            ByteRange::Unsatisfiable => {
+                trace!(
+                    "[{}] range of {} is not satisfiable",
+                    request_id(state),
+                    path
+                );
+                let mut res = create_empty_response(state, StatusCode::RANGE_NOT_SATISFIABLE);
+                let content_range = format!("bytes */{}", meta.size);
+                res.headers_mut().insert(
+                    CONTENT_RANGE,
+                    HeaderValue::from_str(&content_range).unwrap(),
+                );
+                return Ok(res);
+            }
+        };
+        let partial = range.end - range.start < meta.size;
+
+        let body = if range.is_empty() {
+            Body::empty()
+        } else {
+            let options = GetOptions {
+                // The object must not change between reading its metadata and its content.
+                if_match: meta.e_tag.clone(),
+                range: Some(GetRange::Bounded(range.clone())),
+                ..GetOptions::default()
+            };
+            let result = self
+                .store
+                .get_opts(path, options)
+                .await
+                .map_err(not_found)?;
+            Body::wrap_stream(result.into_stream())
+        };
+
+        let status = if partial {
+            StatusCode::PARTIAL_CONTENT
+        } else {
+            StatusCode::OK
+        };
+        let mut res = create_empty_response(state, status);
+        *res.body_mut() = body;
+        let headers = res.headers_mut();
+        add_metadata(headers, path, &meta);
+        headers.insert(CONTENT_LENGTH, (range.end - range.start).into());
+        if partial {
+            let content_range = format!("bytes {}-{}/{}", range.start, range.end - 1, meta.size);
+            headers.insert(
+                CONTENT_RANGE,
+                HeaderValue::from_str(&content_range).unwrap(),
+            );
+        }
+        Ok(res)
+    }
+}
+
+/// Returns a URL granting access to the object `path` via `method` for `expires_in`, without
+/// the credentials of the store, e.g. to let a client upload a large file directly to the store.
+pub async fn presigned_url<S>(
+    signer: &S,
+    method: &Method,
+    path: &Path,
+    expires_in: Duration,
+) -> anyhow::Result<String>
+where
+    S: Signer + ?Sized,
+{
+    let method = http1::Method::from_bytes(method.as_str().as_bytes())?;
+    let url = signer.signed_url(method, path, expires_in).await?;
+    Ok(url.to_string())
+}
+
+/// Fails with `404 Not Found` if the object doesn't exist.
+fn not_found(e: object_store::Error) -> HandlerError {
+    let status = match e {
+        object_store::Error::NotFound { .. } => StatusCode::NOT_FOUND,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    HandlerError::from(e).with_status(status)
+}
+
+fn add_metadata(headers: &mut HeaderMap, path: &Path, meta: &ObjectMeta) {
+    let mime = mime_guess::from_path(path.as_ref()).first_or_octet_stream();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_str(mime.as_ref()).unwrap());
+    headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+    let last_modified = httpdate::fmt_http_date(SystemTime::from(meta.last_modified));
+    headers.insert(
+        LAST_MODIFIED,
+        HeaderValue::from_str(&last_modified).unwrap(),
+    );
+    if let Some(e_tag) = &meta.e_tag {
+        let e_tag = if e_tag.starts_with('"') || e_tag.starts_with("W/") {
+            e_tag.clone()
+        } else {
+            format!("\"{}\"", e_tag)
+        };
+        if let Ok(value) = HeaderValue::from_str(&e_tag) {
+            headers.insert(ETAG, value);
+        }
+    }
+}
+
+/// The bytes of an object requested via a `Range` header.
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    Full,
+    Partial(Range<u64>),
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    /// Parses a `Range` header for an object of `size` bytes. Headers which are invalid or
+    /// request multiple ranges are ignored, as permitted by RFC 7233.
+    fn parse(value: &str, size: u64) -> ByteRange {
+        let spec = match value.trim().strip_prefix("bytes=") {
+            Some(spec) if !spec.contains(',') => spec.trim(),
+            _ => return ByteRange::Full,
+        };
+        let (first, last) = match spec.split_once('-') {
+            Some(bounds) => bounds,
+            None => return ByteRange::Full,
+        };
+
+        let range = match (first.parse::<u64>(), last.parse::<u64>()) {
+            (Ok(first), Ok(last)) if first <= last => first..(last + 1).min(size),
+            (Ok(first), Err(_)) if last.is_empty() => first..size,
+            (Err(_), Ok(suffix)) if first.is_empty() => size.saturating_sub(suffix)..size,
+            _ => return ByteRange::Full,
+        };
+        if range.start >= range.end {
+            ByteRange::Unsatisfiable
+        } else {
+            ByteRange::Partial(range)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use object_store::memory::InMemory;
+
+    use crate::middleware::state::StateMiddleware;
+    use crate::pipeline::new_pipeline;
+    use crate::pipeline::single::single_pipeline;
+    use crate::router::builder::{build_router, DefineSingleRoute, DrawRoutes};
+    use crate::test::TestServer;
+
+    #[test]
+    fn parses_byte_ranges() {
+        assert_eq!(ByteRange::parse("bytes=0-4", 10), ByteRange::Partial(0..5));
+        assert_eq!(
+            ByteRange::parse("bytes=5-100", 10),
+            ByteRange::Partial(5..10)
+        );
+        assert_eq!(ByteRange::parse("bytes=7-", 10), ByteRange::Partial(7..10));
+        assert_eq!(ByteRange::parse("bytes=-3", 10), ByteRange::Partial(7..10));
+        assert_eq!(ByteRange::parse("bytes=10-", 10), ByteRange::Unsatisfiable);
+        assert_eq!(ByteRange::parse("bytes=-0", 10), ByteRange::Unsatisfiable);
+        assert_eq!(ByteRange::parse("bytes=0-1,4-5", 10), ByteRange::Full);
+        assert_eq!(ByteRange::parse("bytes=5-1", 10), ByteRange::Full);
+        assert_eq!(ByteRange::parse("items=0-1", 10), ByteRange::Full);
+    }
+
+    #[test]
+    fn streams_multipart_uploads_and_downloads() {
+        async fn upload(state: &mut State) -> Result<Response<Body>, HandlerError> {
+            let storage = ObjectStorage::borrow_from(state).clone();
+            let objects = storage
+                .store_multipart(state, |field, _| match field {
+                    "avatar" => Some(Path::from("avatars/ann.png")),
+                    _ => None,
+                })
+                .await?;
+            assert_eq!(objects.len(), 1);
+            assert_eq!(objects[0].file_name(), Some("me.png"));
+            assert_eq!(objects[0].content_type(), Some(&mime::IMAGE_PNG));
+            assert_eq!(objects[0].size(), 10);
+            Ok(create_empty_response(state, StatusCode::CREATED))
+        }
+
+        async fn download(state: &mut State) -> Result<Response<Body>, HandlerError> {
+            let storage = ObjectStorage::borrow_from(state).clone();
+            storage.serve(state, &Path::from("avatars/ann.png")).await
+        }
+
+        let storage = ObjectStorage::new(Arc::new(InMemory::new()));
+        let (chain, pipelines) =
+            single_pipeline(new_pipeline().add(StateMiddleware::new(storage)).build());
+        let router = build_router(chain, pipelines, |route| {
+            route.post("/avatar").to_async_borrowing(upload);
+            route.get("/avatar").to_async_borrowing(download);
+        });
+        let test_server = TestServer::new(router).unwrap();
+
+        let response = test_server
+            .client()
+            .get("http://localhost/avatar")
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let body = "--XX\r\n\
+                    Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+                    Ann\r\n\
+                    --XX\r\n\
+                    Content-Disposition: form-data; name=\"avatar\"; filename=\"me.png\"\r\n\
+                    Content-Type: image/png\r\n\r\n\
+                    0123456789\r\n\
+                    --XX--\r\n";
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost/avatar",
+                body,
+                "multipart/form-data; boundary=XX".parse::<Mime>().unwrap(),
+            )
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = test_server
+            .client()
+            .get("http://localhost/avatar")
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), "image/png");
+        assert_eq!(response.headers().get(ACCEPT_RANGES).unwrap(), "bytes");
+        assert_eq!(response.read_utf8_body().unwrap(), "0123456789");
+
+        let get_range = |range: &'static str| {
+            test_server
+                .client()
+                .get("http://localhost/avatar")
+                .with_header(RANGE, HeaderValue::from_static(range))
+                .perform()
+                .unwrap()
+        };
+        let response = get_range("bytes=2-4");
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            response.headers().get(CONTENT_RANGE).unwrap(),
+            "bytes 2-4/10"
+        );
+        assert_eq!(response.read_utf8_body().unwrap(), "234");
+
+        let response = get_range("bytes=20-");
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers().get(CONTENT_RANGE).unwrap(), "bytes */10");
+    }
+}
@@ -1,6 +1,6 @@
 //! Helpers for HTTP response generation
 
-use hyper::header::{CONTENT_TYPE, LOCATION};
+use hyper::header::CONTENT_TYPE;
 use hyper::{Body, Method, Response, StatusCode};
 use mime::Mime;
 use std::borrow::Cow;
@@ -8,6 +8,11 @@ use std::borrow::Cow;
 use crate::helpers::http::header::X_REQUEST_ID;
 use crate::state::{request_id, FromState, State};
 
+mod safe_redirect;
+
+pub(crate) use self::safe_redirect::is_local_path;
+pub use self::safe_redirect::SafeRedirect;
+
 /// Creates a `Response` object and populates it with a set of default headers that help to improve
 /// security and conformance to best practice.
 ///
@@ -126,6 +131,9 @@ pub fn create_empty_response(state: &State, status: StatusCode) -> Response<Body
 /// Produces a simple empty `Response` with a `Location` header and a 308
 /// status.
 ///
+/// The location must be on the current site, see `SafeRedirect`, or the response is an empty
+/// `400 Bad Request` instead.
+///
 /// # Examples
 ///
 /// ```rust
@@ -161,15 +169,15 @@ pub fn create_permanent_redirect<L: Into<Cow<'static, str>>>(
     state: &State,
     location: L,
 ) -> Response<Body> {
-    let mut res = create_empty_response(state, StatusCode::PERMANENT_REDIRECT);
-    res.headers_mut()
-        .insert(LOCATION, location.into().to_string().parse().unwrap());
-    res
+    SafeRedirect::new().permanent(state, &location.into())
 }
 
 /// Produces a simple empty `Response` with a `Location` header and a 307
 /// status.
 ///
+/// The location must be on the current site, see `SafeRedirect`, or the response is an empty
+/// `400 Bad Request` instead.
+///
 /// # Examples
 ///
 /// ```rust
@@ -205,8 +213,5 @@ pub fn create_temporary_redirect<L: Into<Cow<'static, str>>>(
     state: &State,
     location: L,
 ) -> Response<Body> {
-    let mut res = create_empty_response(state, StatusCode::TEMPORARY_REDIRECT);
-    res.headers_mut()
-        .insert(LOCATION, location.into().to_string().parse().unwrap());
-    res
+    SafeRedirect::new().temporary(state, &location.into())
 }
@@ -0,0 +1,232 @@
+//! Defines `SafeRedirect`, which guards against open redirects.
+
+use hyper::header::{HeaderValue, LOCATION};
+use hyper::{Body, Response, StatusCode, Uri};
+use log::warn;
+
+use crate::helpers::http::response::create_empty_response;
+use crate::router::route::matcher::host::{request_host, HostMatcher};
+use crate::state::{request_id, State};
+
+/// Issues redirects only to targets on the current site or on an allow-list of hosts, so that a
+/// target taken from the request, e.g. a `next` query parameter, can't send users to a phishing
+/// site.
+///
+/// A target is safe if it is:
+///
+/// - an absolute path such as `/orders?page=2`, which browsers resolve against the current
+///   origin, or
+/// - an `http` or `https` URL for the host of the current request, or for an allowed host.
+///
+/// Everything else is unsafe, including scheme-relative targets such as `//other.example`, paths
+/// with backslashes or control characters, which some browsers normalize into the former, and
+/// other schemes such as `javascript:`. Redirects to unsafe targets are answered with
+/// `400 Bad Request` instead.
+///
+/// ```rust
+/// # extern crate gotham;
+/// # extern crate hyper;
+/// #
+/// # use hyper::header::LOCATION;
+/// # use hyper::{Body, Response, StatusCode};
+/// # use gotham::helpers::http::response::SafeRedirect;
+/// # use gotham::state::State;
+/// # use gotham::test::TestServer;
+/// #
+/// fn handler(state: State) -> (State, Response<Body>) {
+///     let target = state
+///         .borrow::<hyper::Uri>()
+///         .query()
+///         .and_then(|query| query.strip_prefix("next="))
+///         .unwrap_or("/")
+///         .to_owned();
+///
+///     let response = SafeRedirect::new()
+///         .with_allowed_host("*.example.com")
+///         .see_other(&state, &target);
+///     (state, response)
+/// }
+///
+/// # fn main() {
+/// let test_server = TestServer::new(|| Ok(handler)).unwrap();
+/// let client = test_server.client();
+///
+/// let response = client.get("http://localhost/?next=/orders").perform().unwrap();
+/// assert_eq!(response.status(), StatusCode::SEE_OTHER);
+/// assert_eq!(response.headers()[LOCATION], "/orders");
+///
+/// let response = client
+///     .get("http://localhost/?next=https://accounts.example.com/")
+///     .perform()
+///     .unwrap();
+/// assert_eq!(response.status(), StatusCode::SEE_OTHER);
+///
+/// let response = client
+///     .get("http://localhost/?next=//evil.example/")
+///     .perform()
+///     .unwrap();
+/// assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SafeRedirect {
+    allowed_hosts: Vec<HostMatcher>,
+}
+
+impl SafeRedirect {
+    /// Creates a `SafeRedirect` allowing targets on the current site only.
+    pub fn new() -> SafeRedirect {
+        SafeRedirect::default()
+    }
+
+    /// Allows targets on `host`, or on any subdomain if `host` starts with `*.`, like a
+    /// `HostMatcher`.
+    pub fn with_allowed_host(mut self, host: &str) -> SafeRedirect {
+        self.allowed_hosts.push(HostMatcher::new(host));
+        self
+    }
+
+    /// Returns `true` if redirecting the request in `state` to `target` is safe.
+    pub fn is_safe(&self, state: &State, target: &str) -> bool {
+        if is_local_path(target) {
+            return true;
+        }
+
+        let uri = match target.parse::<Uri>() {
+            Ok(uri) => uri,
+            Err(_) => return false,
+        };
+
+        let http = matches!(uri.scheme_str(), Some("http") | Some("https"));
+        let host = match uri.authority() {
+            // user information can disguise the host, e.g. `https://example.com@evil.example`
+            Some(authority) if http && !authority.as_str().contains('@') => authority.host(),
+            _ => return false,
+        };
+
+        request_host(state).is_some_and(|current| current.eq_ignore_ascii_case(host))
+            || self
+                .allowed_hosts
+                .iter()
+                .any(|allowed| allowed.matches(host))
+    }
+
+    /// Creates a redirect to `target` with the given status, or an empty `400 Bad Request`
+    /// response if `target` is not safe.
+    pub fn redirect(&self, state: &State, status: StatusCode, target: &str) -> Response<Body> {
+        let location = match HeaderValue::from_str(target) {
+            Ok(location) if self.is_safe(state, target) => location,
+            _ => {
+                warn!(
+                    "[{}] refusing to redirect to unsafe target {:?}",
+                    request_id(state),
+                    target
+                );
+                return create_empty_response(state, StatusCode::BAD_REQUEST);
+            }
+        };
+
+        let mut response = create_empty_response(state, status);
+        response.headers_mut().insert(LOCATION, location);
+        response
+    }
+
+    /// Creates a `303 See Other` redirect to `target`, which is followed with a `GET` request,
+    /// e.g. after a form was submitted. See `redirect`.
+    pub fn see_other(&self, state: &State, target: &str) -> Response<Body> {
+        self.redirect(state, StatusCode::SEE_OTHER, target)
+    }
+
+    /// Creates a `307 Temporary Redirect` to `target`, which repeats the request method. See
+    /// `redirect`.
+    pub fn temporary(&self, state: &State, target: &str) -> Response<Body> {
+        self.redirect(state, StatusCode::TEMPORARY_REDIRECT, target)
+    }
+
+    /// Creates a `308 Permanent Redirect` to `target`, which repeats the request method. See
+    /// `redirect`.
+    pub fn permanent(&self, state: &State, target: &str) -> Response<Body> {
+        self.redirect(state, StatusCode::PERMANENT_REDIRECT, target)
+    }
+}
+
+/// Returns `true` if `target` is an absolute path which browsers can't mistake for a URL of
+/// another site.
+pub(crate) fn is_local_path(target: &str) -> bool {
+    target.starts_with('/')
+        && !target.starts_with("//")
+        && !target.contains('\\')
+        && !target.chars().any(char::is_control)
+        && target.parse::<Uri>().is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hyper::header::{HeaderMap, HOST};
+
+    fn is_safe(redirect: &SafeRedirect, target: &str) -> bool {
+        let mut safe = false;
+        State::with_new(|state| {
+            let mut headers = HeaderMap::new();
+            headers.insert(HOST, "app.example.com:8080".parse().unwrap());
+            state.put(headers);
+            state.put("/login".parse::<Uri>().unwrap());
+            safe = redirect.is_safe(state, target);
+        });
+        safe
+    }
+
+    #[test]
+    fn validates_redirect_targets() {
+        let redirect = SafeRedirect::new().with_allowed_host("*.partner.example");
+
+        for target in &[
+            "/",
+            "/orders?page=2",
+            "/@evil.example",
+            "https://app.example.com/orders",
+            "http://APP.example.com:8080/",
+            "https://shop.partner.example/cart",
+        ] {
+            assert!(is_safe(&redirect, target), "{}", target);
+        }
+
+        for target in &[
+            "",
+            "orders",
+            "//evil.example/",
+            "/\\evil.example/",
+            "/\t/evil.example/",
+            "https://evil.example/",
+            "https://app.example.com@evil.example/",
+            "https://partner.example/",
+            "javascript:alert(1)",
+            "ftp://app.example.com/",
+        ] {
+            assert!(!is_safe(&redirect, target), "{}", target);
+        }
+    }
+
+    #[test]
+    fn redirect_helpers_refuse_unsafe_targets() {
+        use crate::helpers::http::response::{
+            create_permanent_redirect, create_temporary_redirect,
+        };
+
+        State::with_new(|state| {
+            state.put(HeaderMap::new());
+            state.put("/login".parse::<Uri>().unwrap());
+            crate::state::set_request_id(state);
+
+            let response = create_permanent_redirect(state, "/home");
+            assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+            assert_eq!(response.headers()[LOCATION], "/home");
+
+            let response = create_temporary_redirect(state, "//evil.example/");
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            assert!(!response.headers().contains_key(LOCATION));
+        });
+    }
+}
@@ -0,0 +1,449 @@
+//! Helpers for resumable uploads, which send a resource in chunks via `PUT` or `PATCH` requests
+//! carrying a `Content-Range` header.
+//!
+//! Each chunk is appended to an `UploadStore` at the offset it names. Until the upload is
+//! complete, requests are answered with `308 Resume Incomplete` (or another status, see
+//! `ResumableUpload::with_incomplete_status`) and a `Range` header naming the bytes stored so
+//! far, as used by the resumable upload protocol of Google Cloud Storage. A request with the
+//! `Content-Range` `bytes */{total}` or `bytes */*` and an empty body asks for the status of an
+//! upload without sending data, e.g. to resume it after a connection was lost.
+//!
+//! ```rust
+//! # extern crate gotham;
+//! # extern crate hyper;
+//! # extern crate mime;
+//! #
+//! # use hyper::header::{HeaderValue, CONTENT_RANGE, RANGE};
+//! # use hyper::{Body, Response, StatusCode};
+//! # use gotham::handler::HandlerError;
+//! # use gotham::helpers::http::upload::{MemoryUploadStore, ResumableUpload};
+//! # use gotham::middleware::state::StateMiddleware;
+//! # use gotham::pipeline::new_pipeline;
+//! # use gotham::pipeline::single::single_pipeline;
+//! # use gotham::router::builder::*;
+//! # use gotham::state::{FromState, State};
+//! # use gotham::test::TestServer;
+//! #
+//! async fn upload(state: &mut State) -> Result<Response<Body>, HandlerError> {
+//!     let upload = ResumableUpload::borrow_from(state).clone();
+//!     upload.receive(state, "video.mp4").await
+//! }
+//!
+//! # fn main() {
+//! let store = MemoryUploadStore::new();
+//! let middleware = StateMiddleware::new(ResumableUpload::new(store.clone()));
+//! let (chain, pipelines) = single_pipeline(new_pipeline().add(middleware).build());
+//! let router = build_router(chain, pipelines, |route| {
+//!     route.put("/upload").to_async_borrowing(upload);
+//! });
+//!
+//! let test_server = TestServer::new(router).unwrap();
+//! let response = test_server
+//!     .client()
+//!     .put("http://localhost/upload", "hello ", mime::APPLICATION_OCTET_STREAM)
+//!     .with_header(CONTENT_RANGE, HeaderValue::from_static("bytes 0-5/11"))
+//!     .perform()
+//!     .unwrap();
+//! assert_eq!(response.status().as_u16(), 308);
+//! assert_eq!(response.headers().get(RANGE).unwrap(), "bytes=0-5");
+//!
+//! let response = test_server
+//!     .client()
+//!     .put("http://localhost/upload", "world", mime::APPLICATION_OCTET_STREAM)
+//!     .with_header(CONTENT_RANGE, HeaderValue::from_static("bytes 6-10/11"))
+//!     .perform()
+//!     .unwrap();
+//! assert_eq!(response.status(), StatusCode::OK);
+//! assert_eq!(store.get("video.mp4").unwrap(), b"hello world");
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::panic::RefUnwindSafe;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use futures::prelude::*;
+use hyper::header::{HeaderMap, HeaderValue, CONTENT_RANGE, RANGE};
+use hyper::{Body, Response, StatusCode};
+use log::trace;
+
+use crate::handler::HandlerError;
+use crate::helpers::http::body::read_limited;
+use crate::helpers::http::response::create_empty_response;
+use crate::state::{request_id, FromState, State, StateData};
+
+/// The size of the largest chunk buffered by `ResumableUpload::receive`.
+const DEFAULT_MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+/// The future returned by the methods of an `UploadStore`.
+pub type UploadFuture<T> = Pin<Box<dyn Future<Output = anyhow::Result<T>> + Send>>;
+
+/// Stores the bytes of resumable uploads, identified by a key chosen by the application.
+pub trait UploadStore: RefUnwindSafe + Send + Sync {
+    /// Returns the number of bytes stored for the upload `key`, which is `0` for an upload
+    /// which has not been started.
+    fn len(&self, key: &str) -> UploadFuture<u64>;
+
+    /// Appends `data` to the upload `key`. `offset` is always the length returned by `len`.
+    fn append(&self, key: &str, offset: u64, data: Bytes) -> UploadFuture<()>;
+
+    /// Called once all `total` bytes of the upload `key` are stored, e.g. to move the upload to
+    /// its final location. Only the request storing the last bytes calls it, not status requests
+    /// or retries of the last chunk afterwards. Does nothing by default.
+    fn complete(&self, key: &str, total: u64) -> UploadFuture<()> {
+        let _ = (key, total);
+        future::ok(()).boxed()
+    }
+}
+
+/// An `UploadStore` keeping uploads in memory, e.g. for tests.
+#[derive(Clone, Default)]
+pub struct MemoryUploadStore {
+    uploads: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+}
+
+impl MemoryUploadStore {
+    /// Creates an empty store.
+    pub fn new() -> MemoryUploadStore {
+        MemoryUploadStore::default()
+    }
+
+    /// Returns the bytes stored for the upload `key`, if it has been started.
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.uploads.lock().unwrap().get(key).cloned()
+    }
+}
+
+impl UploadStore for MemoryUploadStore {
+    fn len(&self, key: &str) -> UploadFuture<u64> {
+        let len = self.uploads.lock().unwrap().get(key).map_or(0, Vec::len);
+        future::ok(len as u64).boxed()
+    }
+
+    fn append(&self, key: &str, offset: u64, data: Bytes) -> UploadFuture<()> {
+        let mut uploads = self.uploads.lock().unwrap();
+        let upload = uploads.entry(key.to_owned()).or_default();
+        let result = if upload.len() as u64 == offset {
+            upload.extend_from_slice(&data);
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(
+                "upload {} is not at offset {}",
+                key,
+                offset
+            ))
+        };
+        future::ready(result).boxed()
+    }
+}
+
+/// A parsed `Content-Range` header, e.g. `bytes 0-1023/4096`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContentRange {
+    range: Option<(u64, u64)>,
+    total: Option<u64>,
+}
+
+impl ContentRange {
+    /// Parses the value of a `Content-Range` header in `bytes` units.
+    pub fn parse(value: &str) -> Option<ContentRange> {
+        let value = value.trim().strip_prefix("bytes ")?;
+        let (range, total) = value.split_once('/')?;
+
+        let total = match total.trim() {
+            "*" => None,
+            total => Some(total.parse().ok()?),
+        };
+        let range = match range.trim() {
+            "*" => None,
+            range => {
+                let (first, last) = range.split_once('-')?;
+                let (first, last) = (first.parse().ok()?, last.parse().ok()?);
+                if first > last || total.is_some_and(|total| last >= total) {
+                    return None;
+                }
+                Some((first, last))
+            }
+        };
+
+        Some(ContentRange { range, total })
+    }
+
+    /// Returns the first and the last byte of the range, both inclusive, unless the range is `*`.
+    pub fn range(&self) -> Option<(u64, u64)> {
+        self.range
+    }
+
+    /// Returns the total length of the resource, unless it is `*`, i.e. not known yet.
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+}
+
+/// Receives the chunks of resumable uploads into an `UploadStore`, see the module documentation.
+///
+/// `ResumableUpload` is cheap to clone, so it can be shared with handlers via a
+/// `StateMiddleware`.
+#[derive(Clone)]
+pub struct ResumableUpload {
+    store: Arc<dyn UploadStore>,
+    incomplete_status: StatusCode,
+    max_chunk_size: usize,
+}
+
+impl StateData for ResumableUpload {}
+
+impl ResumableUpload {
+    /// Creates the helper storing uploads in `store`.
+    pub fn new<S>(store: S) -> ResumableUpload
+    where
+        S: UploadStore + 'static,
+    {
+        ResumableUpload {
+            store: Arc::new(store),
+            incomplete_status: StatusCode::PERMANENT_REDIRECT,
+            max_chunk_size: DEFAULT_MAX_CHUNK_SIZE,
+        }
+    }
+
+    /// Answers requests for incomplete uploads with `status` instead of `308 Resume Incomplete`,
+    /// e.g. `206 Partial Content` for clients which treat a `308` as a redirect.
+    pub fn with_incomplete_status(self, status: StatusCode) -> ResumableUpload {
+        ResumableUpload {
+            incomplete_status: status,
+            ..self
+        }
+    }
+
+    /// Sets the size of the largest chunk which is accepted, 16 MiB by default. Larger chunks are
+    /// answered with `413 Payload Too Large` without being buffered completely.
+    pub fn with_max_chunk_size(self, max_chunk_size: usize) -> ResumableUpload {
+        ResumableUpload {
+            max_chunk_size,
+            ..self
+        }
+    }
+
+    /// Receives the chunk of the upload `key` in the request body of `state`, and returns the
+    /// response to the request.
+    ///
+    /// Chunks starting before the end of the stored bytes are accepted, and the bytes which are
+    /// already stored are skipped. Chunks starting after the end are not stored, and the response
+    /// names the bytes stored so far, so that the client resends the missing bytes. A request
+    /// without a `Content-Range` header sends the whole upload at once.
+    ///
+    /// Fails with `400 Bad Request` if the `Content-Range` header is invalid or doesn't match the
+    /// length of the body, with `413 Payload Too Large` if the body exceeds the maximum chunk size,
+    /// and with `416 Range Not Satisfiable` if more bytes are stored than the total length of the
+    /// upload.
+    pub async fn receive(
+        &self,
+        state: &mut State,
+        key: &str,
+    ) -> Result<Response<Body>, HandlerError> {
+        let content_range = match HeaderMap::borrow_from(state).get(CONTENT_RANGE) {
+            Some(value) => {
+                let content_range = value.to_str().ok().and_then(ContentRange::parse);
+                Some(content_range.ok_or_else(|| {
+                    trace!("[{}] invalid Content-Range header", request_id(state));
+                    bad_request("invalid Content-Range header")
+                })?)
+            }
+            None => None,
+        };
+
+        let data = read_limited(Body::take_from(state), self.max_chunk_size)
+            .await
+            .map_err(|e| match e.status() {
+                StatusCode::PAYLOAD_TOO_LARGE => e,
+                _ => e.with_status(StatusCode::BAD_REQUEST),
+            })?;
+        // an empty upload sent at once is complete without appending anything
+        let whole = content_range.is_none();
+        let content_range = content_range.unwrap_or(ContentRange {
+            range: match data.len() as u64 {
+                0 => None,
+                len => Some((0, len - 1)),
+            },
+            total: Some(data.len() as u64),
+        });
+
+        let mut stored = self.store.len(key).await?;
+        let mut appended = false;
+        if let Some((first, last)) = content_range.range {
+            // the length of a range covering all of `u64` doesn't fit into it
+            if (last - first).checked_add(1) != Some(data.len() as u64) {
+                return Err(bad_request(
+                    "Content-Range doesn't match the length of the body",
+                ));
+            }
+            if first > stored {
+                trace!(
+                    "[{}] upload {} is at {}, not at {}",
+                    request_id(state),
+                    key,
+                    stored,
+                    first
+                );
+            } else if last >= stored {
+                let data = data.slice((stored - first) as usize..);
+                let len = data.len() as u64;
+                self.store.append(key, stored, data).await?;
+                stored += len;
+                appended = true;
+            }
+        } else if !data.is_empty() {
+            return Err(bad_request(
+                "Content-Range doesn't match the length of the body",
+            ));
+        }
+
+        match content_range.total {
+            Some(total) if stored > total => {
+                let err = HandlerError::from(anyhow::anyhow!(
+                    "upload {} is longer than {} bytes",
+                    key,
+                    total
+                ));
+                Err(err.with_status(StatusCode::RANGE_NOT_SATISFIABLE))
+            }
+            Some(total) if stored == total => {
+                if appended || (whole && total == 0) {
+                    self.store.complete(key, total).await?;
+                    trace!("[{}] upload {} is complete", request_id(state), key);
+                }
+                Ok(create_empty_response(state, StatusCode::OK))
+            }
+            _ => {
+                let mut res = create_empty_response(state, self.incomplete_status);
+                if stored > 0 {
+                    let range = format!("bytes=0-{}", stored - 1);
+                    res.headers_mut()
+                        .insert(RANGE, HeaderValue::from_str(&range).unwrap());
+                }
+                Ok(res)
+            }
+        }
+    }
+}
+
+fn bad_request(message: &'static str) -> HandlerError {
+    HandlerError::from(anyhow::anyhow!(message)).with_status(StatusCode::BAD_REQUEST)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::middleware::state::StateMiddleware;
+    use crate::pipeline::new_pipeline;
+    use crate::pipeline::single::single_pipeline;
+    use crate::router::builder::{build_router, DefineSingleRoute, DrawRoutes};
+    use crate::test::TestServer;
+
+    #[test]
+    fn parses_content_ranges() {
+        let parse = |value| ContentRange::parse(value).map(|r| (r.range(), r.total()));
+        assert_eq!(parse("bytes 0-9/100"), Some((Some((0, 9)), Some(100))));
+        assert_eq!(parse("bytes 10-19/*"), Some((Some((10, 19)), None)));
+        assert_eq!(parse("bytes */100"), Some((None, Some(100))));
+        assert_eq!(parse("bytes */*"), Some((None, None)));
+        assert_eq!(parse("bytes 9-0/100"), None);
+        assert_eq!(parse("bytes 0-100/100"), None);
+        assert_eq!(parse("items 0-9/100"), None);
+    }
+
+    /// Counts the calls of `complete`.
+    #[derive(Clone, Default)]
+    struct CountingStore {
+        store: MemoryUploadStore,
+        completed: Arc<AtomicUsize>,
+    }
+
+    impl UploadStore for CountingStore {
+        fn len(&self, key: &str) -> UploadFuture<u64> {
+            self.store.len(key)
+        }
+
+        fn append(&self, key: &str, offset: u64, data: Bytes) -> UploadFuture<()> {
+            self.store.append(key, offset, data)
+        }
+
+        fn complete(&self, _key: &str, _total: u64) -> UploadFuture<()> {
+            self.completed.fetch_add(1, Ordering::SeqCst);
+            future::ok(()).boxed()
+        }
+    }
+
+    #[test]
+    fn resumes_uploads() {
+        async fn upload(state: &mut State) -> Result<Response<Body>, HandlerError> {
+            let upload = ResumableUpload::borrow_from(state).clone();
+            upload.receive(state, "file").await
+        }
+
+        let counting = CountingStore::default();
+        let store = counting.store.clone();
+        let resumable = ResumableUpload::new(counting.clone())
+            .with_incomplete_status(StatusCode::PARTIAL_CONTENT)
+            .with_max_chunk_size(4);
+        let middleware = StateMiddleware::new(resumable);
+        let (chain, pipelines) = single_pipeline(new_pipeline().add(middleware).build());
+        let router = build_router(chain, pipelines, |route| {
+            route.put("/upload").to_async_borrowing(upload);
+        });
+        let test_server = TestServer::new(router).unwrap();
+        let put = |body: &'static str, content_range: &'static str| {
+            test_server
+                .client()
+                .put(
+                    "http://localhost/upload",
+                    body,
+                    mime::APPLICATION_OCTET_STREAM,
+                )
+                .with_header(CONTENT_RANGE, HeaderValue::from_static(content_range))
+                .perform()
+                .unwrap()
+        };
+
+        let response = put("", "bytes */*");
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert!(response.headers().get(RANGE).is_none());
+
+        let response = put("abcd", "bytes 0-3/*");
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers().get(RANGE).unwrap(), "bytes=0-3");
+
+        // A gap is not stored, an overlap is skipped.
+        let response = put("gh", "bytes 6-7/*");
+        assert_eq!(response.headers().get(RANGE).unwrap(), "bytes=0-3");
+        let response = put("cdef", "bytes 2-5/*");
+        assert_eq!(response.headers().get(RANGE).unwrap(), "bytes=0-5");
+
+        let response = put("abc", "bytes 6-7/8");
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = put("", "bytes 0-18446744073709551615/*");
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = put("ghijk", "bytes 6-10/11");
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let response = put("", "bytes */5");
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+
+        let response = put("gh", "bytes 6-7/8");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(store.get("file").unwrap(), b"abcdefgh");
+        assert_eq!(counting.completed.load(Ordering::SeqCst), 1);
+
+        // Neither status requests nor retries of the last chunk complete the upload again.
+        let response = put("", "bytes */8");
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = put("gh", "bytes 6-7/8");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(counting.completed.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,10 +1,11 @@
 //! Defines types for timing requests and emitting timing information.
 use chrono::prelude::*;
 use std::fmt::{self, Display, Formatter};
+use std::time::SystemTime;
 
 /// Timer struct used to record execution times of requests.
 ///
-/// The `elapsed` function returns the elapsed time in an easy to format way,
+/// The `elapsed_until` function returns the elapsed time in an easy to format way,
 /// suitable for use with requset logging middlewares.
 #[derive(Clone, Copy)]
 pub struct Timer {
@@ -12,14 +13,17 @@ pub struct Timer {
 }
 
 impl Timer {
-    /// Begins measuring from the current time.
-    pub fn new() -> Timer {
-        Timer { start: Utc::now() }
+    /// Begins measuring from `start`, e.g. the current time of a `Clock`.
+    pub fn started_at(start: SystemTime) -> Timer {
+        Timer {
+            start: DateTime::from(start),
+        }
     }
 
-    /// Finishes measuring, and returns the elapsed time as a `Timing` value.
-    pub fn elapsed(&self) -> Timing {
-        let duration = Utc::now()
+    /// Finishes measuring at `end`, e.g. the current time of a `Clock`, and returns the elapsed
+    /// time as a `Timing` value.
+    pub fn elapsed_until(&self, end: SystemTime) -> Timing {
+        let duration = DateTime::<Utc>::from(end)
             .signed_duration_since(self.start)
             .num_microseconds();
 
@@ -0,0 +1,467 @@
+//! Types for serving and consuming documents in the [JSON:API][spec] media type.
+//!
+//! The `Document` and `Resource` types describe the JSON:API envelope, so handlers only have to
+//! provide the attributes and relationships of their resources. `JsonApiQuery` parses the
+//! `include` and `fields[TYPE]` query string parameters, which can be used to load related
+//! resources and to apply sparse fieldsets via `Resource::sparse`.
+//!
+//! ```rust
+//! # extern crate gotham;
+//! # extern crate hyper;
+//! # #[macro_use]
+//! # extern crate serde_derive;
+//! #
+//! # use gotham::json_api::{Document, JsonApiQuery, Resource, ResourceIdentifier};
+//! # use gotham::router::builder::*;
+//! # use gotham::state::State;
+//! # use gotham::test::TestServer;
+//! # use hyper::StatusCode;
+//! #
+//! #[derive(Serialize)]
+//! struct Article {
+//!     title: String,
+//!     body: String,
+//! }
+//!
+//! fn show_article(state: State) -> (State, Document<Resource<serde_json::Value>>) {
+//!     let query = JsonApiQuery::from_state(&state);
+//!     let article = Resource::new("articles", "1", Article {
+//!         title: "JSON:API paints my bikeshed!".to_owned(),
+//!         body: "The shortest article. Ever.".to_owned(),
+//!     })
+//!     .with_relationship("author", ResourceIdentifier::new("people", "9"));
+//!
+//!     (state, Document::new(article.sparse(&query)))
+//! }
+//!
+//! # fn main() {
+//! let test_server = TestServer::new(build_simple_router(|route| {
+//!     route.get("/articles/1").to(show_article);
+//! }))
+//! .unwrap();
+//!
+//! let response = test_server
+//!     .client()
+//!     .get("http://localhost/articles/1?fields[articles]=title")
+//!     .perform()
+//!     .unwrap();
+//! assert_eq!(response.status(), StatusCode::OK);
+//!
+//! let body = response.read_utf8_body().unwrap();
+//! assert_eq!(
+//!     body,
+//!     r#"{"data":{"type":"articles","id":"1","attributes":{"title":"JSON:API paints my bikeshed!"}}}"#
+//! );
+//! # }
+//! ```
+//!
+//! [spec]: https://jsonapi.org/format/
+
+use std::collections::{BTreeMap, HashMap};
+
+use hyper::{Body, Response, StatusCode, Uri};
+use log::error;
+use mime::Mime;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::handler::{HandlerError, IntoResponse, MapHandlerError};
+use crate::helpers::http::request::query_string;
+use crate::helpers::http::response::{create_empty_response, create_response};
+use crate::state::{request_id, FromState, State};
+
+/// The JSON:API media type.
+pub const APPLICATION_VND_API_JSON: &str = "application/vnd.api+json";
+
+/// Identifies a single resource by its type and id.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ResourceIdentifier {
+    /// The type of the resource.
+    #[serde(rename = "type")]
+    pub resource_type: String,
+    /// The id of the resource.
+    pub id: String,
+}
+
+impl ResourceIdentifier {
+    /// Creates a `ResourceIdentifier` for the resource of type `resource_type` with the given id.
+    pub fn new<T, I>(resource_type: T, id: I) -> ResourceIdentifier
+    where
+        T: Into<String>,
+        I: Into<String>,
+    {
+        ResourceIdentifier {
+            resource_type: resource_type.into(),
+            id: id.into(),
+        }
+    }
+}
+
+/// The resource linkage of a relationship: either a to-one or a to-many relationship.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Linkage {
+    /// A to-one relationship, which is `null` when empty.
+    One(Option<ResourceIdentifier>),
+    /// A to-many relationship.
+    Many(Vec<ResourceIdentifier>),
+}
+
+impl From<ResourceIdentifier> for Linkage {
+    fn from(identifier: ResourceIdentifier) -> Linkage {
+        Linkage::One(Some(identifier))
+    }
+}
+
+impl From<Option<ResourceIdentifier>> for Linkage {
+    fn from(identifier: Option<ResourceIdentifier>) -> Linkage {
+        Linkage::One(identifier)
+    }
+}
+
+impl From<Vec<ResourceIdentifier>> for Linkage {
+    fn from(identifiers: Vec<ResourceIdentifier>) -> Linkage {
+        Linkage::Many(identifiers)
+    }
+}
+
+/// A relationship object, linking a resource to other resources.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Relationship {
+    /// The resource linkage.
+    pub data: Linkage,
+    /// Links related to the relationship, e.g. `self` and `related`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub links: Option<Value>,
+}
+
+/// A resource object with attributes of type `A`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Resource<A> {
+    /// The type of the resource.
+    #[serde(rename = "type")]
+    pub resource_type: String,
+    /// The id of the resource. Only optional when the client creates a new resource.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// The attributes of the resource.
+    pub attributes: A,
+    /// The relationships of the resource, keyed by name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub relationships: BTreeMap<String, Relationship>,
+    /// Links related to the resource, e.g. `self`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub links: Option<Value>,
+}
+
+impl<A> Resource<A> {
+    /// Creates a resource object of type `resource_type` with the given id and attributes.
+    pub fn new<T, I>(resource_type: T, id: I, attributes: A) -> Resource<A>
+    where
+        T: Into<String>,
+        I: Into<String>,
+    {
+        Resource {
+            resource_type: resource_type.into(),
+            id: Some(id.into()),
+            attributes,
+            relationships: BTreeMap::new(),
+            links: None,
+        }
+    }
+
+    /// Adds a relationship with the given resource linkage.
+    pub fn with_relationship<N, L>(mut self, name: N, linkage: L) -> Resource<A>
+    where
+        N: Into<String>,
+        L: Into<Linkage>,
+    {
+        self.relationships.insert(
+            name.into(),
+            Relationship {
+                data: linkage.into(),
+                links: None,
+            },
+        );
+        self
+    }
+
+    /// Sets the links of the resource, e.g. the result of `Links::to_json_api`.
+    pub fn with_links(self, links: Value) -> Resource<A> {
+        Resource {
+            links: Some(links),
+            ..self
+        }
+    }
+
+    /// Returns the `ResourceIdentifier` of this resource, if it has an id.
+    pub fn identifier(&self) -> Option<ResourceIdentifier> {
+        self.id
+            .as_ref()
+            .map(|id| ResourceIdentifier::new(self.resource_type.clone(), id.clone()))
+    }
+}
+
+impl<A> Resource<A>
+where
+    A: serde::Serialize,
+{
+    /// Applies the sparse fieldset requested for this resource type, removing all attributes and
+    /// relationships which were not requested.
+    ///
+    /// When no fieldset was requested for the resource type, all fields are kept.
+    pub fn sparse(self, query: &JsonApiQuery) -> Resource<Value> {
+        let mut attributes = serde_json::to_value(self.attributes).unwrap_or(Value::Null);
+        let mut relationships = self.relationships;
+
+        if let Some(fields) = query.fields(&self.resource_type) {
+            if let Value::Object(ref mut map) = attributes {
+                map.retain(|name, _| fields.iter().any(|field| field == name));
+            }
+            relationships.retain(|name, _| fields.iter().any(|field| field == name));
+        }
+
+        Resource {
+            resource_type: self.resource_type,
+            id: self.id,
+            attributes,
+            relationships,
+            links: self.links,
+        }
+    }
+}
+
+/// A top-level JSON:API document, where `D` is the primary data: a `Resource`, a `Vec` of
+/// resources, or resource identifiers.
+///
+/// The `IntoResponse` implementation serves the document with the JSON:API media type.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Document<D> {
+    /// The primary data of the document.
+    pub data: D,
+    /// Resources related to the primary data, as requested with the `include` parameter.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub included: Vec<Resource<Value>>,
+    /// Links related to the primary data, e.g. for pagination.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub links: Option<Value>,
+    /// Non-standard meta information.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Value>,
+}
+
+impl<D> Document<D> {
+    /// Creates a document with the given primary data.
+    pub fn new(data: D) -> Document<D> {
+        Document {
+            data,
+            included: vec![],
+            links: None,
+            meta: None,
+        }
+    }
+
+    /// Adds a related resource to the `included` member of the document.
+    pub fn include(mut self, resource: Resource<Value>) -> Document<D> {
+        self.included.push(resource);
+        self
+    }
+
+    /// Sets the top-level links of the document.
+    pub fn with_links(self, links: Value) -> Document<D> {
+        Document {
+            links: Some(links),
+            ..self
+        }
+    }
+
+    /// Sets the top-level meta information of the document.
+    pub fn with_meta(self, meta: Value) -> Document<D> {
+        Document {
+            meta: Some(meta),
+            ..self
+        }
+    }
+}
+
+impl<D> Document<D>
+where
+    D: for<'de> serde::Deserialize<'de>,
+{
+    /// Parses a JSON:API document from a request body, failing with `400 Bad Request` when the
+    /// body is not a valid document.
+    pub fn from_slice(body: &[u8]) -> Result<Document<D>, HandlerError> {
+        serde_json::from_slice(body).map_err_with_status(StatusCode::BAD_REQUEST)
+    }
+}
+
+impl<D> IntoResponse for Document<D>
+where
+    D: serde::Serialize,
+{
+    fn into_response(self, state: &State) -> Response<Body> {
+        match serde_json::to_vec(&self) {
+            Ok(body) => {
+                let mime: Mime = APPLICATION_VND_API_JSON.parse().unwrap();
+                create_response(state, StatusCode::OK, mime, body)
+            }
+            Err(e) => {
+                error!(
+                    "[{}] failed to serialize JSON:API document: {:?}",
+                    request_id(state),
+                    e
+                );
+                create_empty_response(state, StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
+
+/// The JSON:API specific query string parameters of a request.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct JsonApiQuery {
+    include: Vec<Vec<String>>,
+    fields: HashMap<String, Vec<String>>,
+}
+
+impl JsonApiQuery {
+    /// Parses the `include` and `fields[TYPE]` parameters from the query string of the request.
+    pub fn from_state(state: &State) -> JsonApiQuery {
+        JsonApiQuery::from_query(Uri::borrow_from(state).query())
+    }
+
+    /// Parses the `include` and `fields[TYPE]` parameters from a raw query string.
+    pub fn from_query(query: Option<&str>) -> JsonApiQuery {
+        let mut json_api_query = JsonApiQuery::default();
+
+        for (key, values) in query_string::split(query) {
+            let values = values
+                .iter()
+                .flat_map(|value| value.as_ref().split(','))
+                .map(str::trim)
+                .filter(|value| !value.is_empty());
+
+            if key == "include" {
+                json_api_query
+                    .include
+                    .extend(values.map(|path| path.split('.').map(str::to_owned).collect()));
+            } else if key.starts_with("fields[") && key.ends_with(']') {
+                let resource_type = key["fields[".len()..key.len() - 1].to_owned();
+                json_api_query
+                    .fields
+                    .entry(resource_type)
+                    .or_insert_with(Vec::new)
+                    .extend(values.map(str::to_owned));
+            }
+        }
+
+        json_api_query
+    }
+
+    /// Returns the relationship paths requested with the `include` parameter, e.g.
+    /// `include=comments.author` yields `[["comments", "author"]]`.
+    pub fn include(&self) -> &[Vec<String>] {
+        &self.include
+    }
+
+    /// Returns `true` if the relationship `name` of the primary data was requested to be included,
+    /// either directly or as the first segment of a longer path.
+    pub fn includes(&self, name: &str) -> bool {
+        self.include
+            .iter()
+            .any(|path| path.first().map(String::as_str) == Some(name))
+    }
+
+    /// Returns the sparse fieldset requested for the resource type, if any.
+    pub fn fields(&self, resource_type: &str) -> Option<&[String]> {
+        self.fields.get(resource_type).map(Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Article {
+        title: String,
+        body: String,
+    }
+
+    #[test]
+    fn parses_include_and_fields() {
+        let query = JsonApiQuery::from_query(Some(
+            "include=author,comments.author&fields%5Barticles%5D=title,author&fields[people]=name",
+        ));
+
+        assert_eq!(
+            query.include(),
+            &[
+                vec!["author".to_owned()],
+                vec!["comments".to_owned(), "author".to_owned()]
+            ]
+        );
+        assert!(query.includes("comments"));
+        assert!(!query.includes("tags"));
+        assert_eq!(
+            query.fields("articles"),
+            Some(&["title".to_owned(), "author".to_owned()][..])
+        );
+        assert_eq!(query.fields("people"), Some(&["name".to_owned()][..]));
+        assert_eq!(query.fields("comments"), None);
+    }
+
+    #[test]
+    fn serializes_compound_document() {
+        let query = JsonApiQuery::from_query(Some("fields[articles]=title,author"));
+        let article = Resource::new(
+            "articles",
+            "1",
+            Article {
+                title: "Rails is Omakase".to_owned(),
+                body: "...".to_owned(),
+            },
+        )
+        .with_relationship("author", ResourceIdentifier::new("people", "9"))
+        .with_relationship("comments", vec![ResourceIdentifier::new("comments", "5")])
+        .sparse(&query);
+
+        let author = Resource::new("people", "9", json!({ "name": "dgeb" }));
+        let document = Document::new(article).include(author);
+
+        assert_eq!(
+            serde_json::to_value(&document).unwrap(),
+            json!({
+                "data": {
+                    "type": "articles",
+                    "id": "1",
+                    "attributes": { "title": "Rails is Omakase" },
+                    "relationships": {
+                        "author": { "data": { "type": "people", "id": "9" } }
+                    }
+                },
+                "included": [
+                    { "type": "people", "id": "9", "attributes": { "name": "dgeb" } }
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn parses_new_resource_document() {
+        let body = br#"{"data":{"type":"articles","attributes":{"title":"t","body":"b"},
+            "relationships":{"author":{"data":null}}}}"#;
+        let document = Document::<Resource<Article>>::from_slice(body).unwrap();
+
+        assert_eq!(document.data.id, None);
+        assert_eq!(document.data.attributes.title, "t");
+        assert_eq!(
+            document.data.relationships["author"].data,
+            Linkage::One(None)
+        );
+
+        let err = Document::<Resource<Article>>::from_slice(b"{}").unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+    }
+}
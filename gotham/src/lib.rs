@@ -26,10 +26,14 @@
 // See Rust issue #34537 <https://github.com/rust-lang/rust/issues/34537>
 #![deny(private_in_public)]
 
+pub mod clock;
+pub mod config;
+pub mod environment;
 pub mod extractor;
 pub mod handler;
 pub mod helpers;
 pub mod middleware;
+pub mod notify;
 pub mod pipeline;
 pub mod router;
 pub mod service;
@@ -45,19 +49,32 @@ pub mod plain;
 #[cfg(feature = "rustls")]
 pub mod tls;
 
+#[cfg(feature = "json-api")]
+pub mod json_api;
+
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
 /// Re-export anyhow
 pub use anyhow;
 /// Re-export hyper
 pub use hyper;
 
+/// Re-export object_store
+#[cfg(feature = "object-store")]
+pub use object_store;
+
 /// Re-export rustls
 #[cfg(feature = "rustls")]
 pub use tokio_rustls::rustls;
 
 use futures::prelude::*;
 use hyper::server::conn::Http;
-use std::net::ToSocketAddrs;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
 use std::sync::Arc;
+use std::task::Poll;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::{TcpListener, TcpStream};
 
@@ -78,17 +95,59 @@ fn new_runtime(threads: usize) -> Runtime {
         .unwrap()
 }
 
-async fn tcp_listener<A>(addr: A) -> std::io::Result<TcpListener>
+/// Binds a listener to every address `addr` resolves to, e.g. to both `127.0.0.1` and `::1` for
+/// `localhost`, all on the same port.
+///
+/// IPv6 listeners accept only IPv6 connections if `v6only` is `Some(true)`, and IPv4 connections
+/// too if it is `Some(false)`. If it is `None`, they accept only IPv6 connections when an IPv4
+/// address is bound alongside them, so that both can bind the same port, and follow the system
+/// default otherwise.
+pub(crate) async fn tcp_listeners<A>(addr: A, v6only: Option<bool>) -> io::Result<Vec<TcpListener>>
 where
-    A: ToSocketAddrs + 'static,
+    A: ToSocketAddrs,
 {
-    let addr = addr
-        .to_socket_addrs()
-        .expect("unable to parse listener address")
-        .next()
-        .expect("unable to resolve listener address");
+    let mut addrs: Vec<SocketAddr> = Vec::new();
+    for addr in addr.to_socket_addrs()? {
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            "unable to resolve listener address",
+        ));
+    }
 
-    TcpListener::bind(addr).await
+    let v6only = v6only.or_else(|| Some(true).filter(|_| addrs.iter().any(SocketAddr::is_ipv4)));
+
+    let mut listeners: Vec<TcpListener> = Vec::with_capacity(addrs.len());
+    for mut addr in addrs {
+        // with port 0, the further addresses are bound to the port chosen for the first one
+        if let (0, Some(first)) = (addr.port(), listeners.first()) {
+            addr.set_port(first.local_addr()?.port());
+        }
+
+        let listener = bind_listener(addr, v6only)
+            .map_err(|e| io::Error::new(e.kind(), format!("unable to bind {}: {}", addr, e)))?;
+        listeners.push(listener);
+    }
+    Ok(listeners)
+}
+
+fn bind_listener(addr: SocketAddr, v6only: Option<bool>) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if let (true, Some(v6only)) = (addr.is_ipv6(), v6only) {
+        socket.set_only_v6(v6only)?;
+    }
+    // like `TcpListener::bind`, which allows restarting while old connections are in TIME_WAIT
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
 }
 
 /// Returns a `Future` used to spawn a Gotham application.
@@ -108,11 +167,41 @@ where
     Wrapped: Unpin + AsyncRead + AsyncWrite + Send + 'static,
     Wrap: Fn(TcpStream) -> F,
 {
-    let protocol = Arc::new(Http::new());
+    bind_server_with_protocol(vec![listener], new_handler, wrap, Http::new()).await
+}
+
+/// Like `bind_server`, but serves connections from all `listeners` with the given, possibly tuned,
+/// `Http` settings.
+pub(crate) async fn bind_server_with_protocol<NH, F, Wrapped, Wrap>(
+    listeners: Vec<TcpListener>,
+    new_handler: NH,
+    wrap: Wrap,
+    protocol: Http,
+) -> !
+where
+    NH: NewHandler + 'static,
+    F: Future<Output = Result<Wrapped, ()>> + Unpin + Send + 'static,
+    Wrapped: Unpin + AsyncRead + AsyncWrite + Send + 'static,
+    Wrap: Fn(TcpStream) -> F,
+{
+    let protocol = Arc::new(protocol);
     let gotham_service = GothamService::new(new_handler);
 
+    let mut next = 0;
     loop {
-        let (socket, addr) = match listener.accept().await {
+        let accepted = future::poll_fn(|cx| {
+            // start polling at a different listener each time, so that none is starved
+            for offset in 0..listeners.len() {
+                let index = (next + offset) % listeners.len();
+                if let Poll::Ready(accepted) = listeners[index].poll_accept(cx) {
+                    next = index + 1;
+                    return Poll::Ready(accepted);
+                }
+            }
+            Poll::Pending
+        });
+
+        let (socket, addr) = match accepted.await {
             Ok(ok) => ok,
             Err(err) => {
                 log::error!("Socket Error: {}", err);
@@ -0,0 +1,133 @@
+//! Defines the `AdaptiveLimit`, which adjusts the limit of a `ConcurrencyLimiter` to the observed
+//! latency of requests.
+
+use std::time::Duration;
+
+/// An adaptive concurrency limit, adjusted by additive increase and multiplicative decrease
+/// (AIMD) in the style of Netflix's concurrency-limits.
+///
+/// Each completed request is a sample. Samples which took longer than the latency threshold, or
+/// whose response is `503 Service Unavailable` or `504 Gateway Timeout`, indicate overload and
+/// shrink the limit by the backoff ratio. Any other sample grows the limit by one, provided at
+/// least half of the limit was in use, so that the limit doesn't grow while the server is idle.
+/// The limit always stays between the minimum and maximum limits.
+///
+/// ```rust
+/// # extern crate gotham;
+/// #
+/// # use std::time::Duration;
+/// # use gotham::middleware::concurrency::{AdaptiveLimit, ConcurrencyLimiter};
+/// #
+/// # fn main() {
+/// let limiter = ConcurrencyLimiter::adaptive(
+///     AdaptiveLimit::new(Duration::from_millis(250))
+///         .with_initial_limit(50)
+///         .with_max_limit(500),
+/// );
+/// assert_eq!(limiter.metrics().limit(), 50);
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct AdaptiveLimit {
+    initial: usize,
+    min: usize,
+    max: usize,
+    latency_threshold: Duration,
+    backoff_ratio: f64,
+}
+
+impl AdaptiveLimit {
+    /// Creates an adaptive limit treating requests slower than `latency_threshold` as a sign of
+    /// overload. The limit starts at 20, ranges from 1 to 1000 and is backed off to 90% on
+    /// overload.
+    pub fn new(latency_threshold: Duration) -> AdaptiveLimit {
+        AdaptiveLimit {
+            initial: 20,
+            min: 1,
+            max: 1000,
+            latency_threshold,
+            backoff_ratio: 0.9,
+        }
+    }
+
+    /// Sets the limit before any requests were observed. It is clamped to the minimum and
+    /// maximum limits.
+    pub fn with_initial_limit(self, initial: usize) -> AdaptiveLimit {
+        AdaptiveLimit { initial, ..self }
+    }
+
+    /// Sets the lowest limit, which must be at least one, so that requests are always admitted.
+    ///
+    /// # Panics
+    ///
+    /// If `min` is zero.
+    pub fn with_min_limit(self, min: usize) -> AdaptiveLimit {
+        assert!(min > 0, "at least one request must be allowed");
+        AdaptiveLimit { min, ..self }
+    }
+
+    /// Sets the highest limit.
+    pub fn with_max_limit(self, max: usize) -> AdaptiveLimit {
+        AdaptiveLimit { max, ..self }
+    }
+
+    /// Sets the factor the limit is multiplied with on overload, which is clamped to the range
+    /// from 0.5 to 0.99.
+    pub fn with_backoff_ratio(self, backoff_ratio: f64) -> AdaptiveLimit {
+        AdaptiveLimit {
+            backoff_ratio: backoff_ratio.clamp(0.5, 0.99),
+            ..self
+        }
+    }
+
+    /// Returns the limit before any requests were observed.
+    pub(crate) fn initial_limit(&self) -> usize {
+        self.clamp(self.initial)
+    }
+
+    /// Returns the new limit after a request completed, which took `latency` and ended in
+    /// `overload` if its response indicated it, while `in_flight` requests were running.
+    pub(crate) fn update(
+        &self,
+        limit: usize,
+        in_flight: usize,
+        latency: Duration,
+        overload: bool,
+    ) -> usize {
+        if overload || latency > self.latency_threshold {
+            self.clamp((limit as f64 * self.backoff_ratio) as usize)
+        } else if in_flight * 2 >= limit {
+            self.clamp(limit + 1)
+        } else {
+            limit
+        }
+    }
+
+    fn clamp(&self, limit: usize) -> usize {
+        limit.min(self.max).max(self.min)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn increases_additively_and_decreases_multiplicatively() {
+        let limit = AdaptiveLimit::new(Duration::from_millis(100))
+            .with_initial_limit(10)
+            .with_min_limit(2)
+            .with_max_limit(11)
+            .with_backoff_ratio(0.5);
+        let fast = Duration::from_millis(10);
+        let slow = Duration::from_millis(200);
+
+        assert_eq!(limit.initial_limit(), 10);
+        assert_eq!(limit.update(10, 5, fast, false), 11);
+        assert_eq!(limit.update(11, 10, fast, false), 11);
+        assert_eq!(limit.update(10, 4, fast, false), 10);
+        assert_eq!(limit.update(10, 10, slow, false), 5);
+        assert_eq!(limit.update(5, 5, fast, true), 2);
+        assert_eq!(limit.update(2, 2, slow, false), 2);
+    }
+}
@@ -0,0 +1,585 @@
+//! Limits the number of requests handled concurrently, queuing the others by priority class.
+//!
+//! During overload, the `ConcurrencyLimiter` queues requests beyond the limit and admits them
+//! as running requests complete, choosing between the queues of the priority classes by weighted
+//! fair queuing. Routes declare their class with `DefineSingleRoute::with_priority`, so that
+//! health checks and interactive traffic keep being served behind bulk API calls. Requests are
+//! `Interactive` unless their route declares otherwise.
+//!
+//! The limit is either static, see `ConcurrencyLimiter::new`, or adjusted to the observed latency
+//! of requests, see `ConcurrencyLimiter::adaptive` and `AdaptiveLimit`. The current limit and
+//! counts of admitted and rejected requests are available from `ConcurrencyLimiter::metrics`.
+//!
+//! ```rust
+//! # extern crate gotham;
+//! # extern crate hyper;
+//! #
+//! # use hyper::StatusCode;
+//! # use gotham::middleware::concurrency::{ConcurrencyLimiter, PriorityClass};
+//! # use gotham::pipeline::new_pipeline;
+//! # use gotham::pipeline::single::single_pipeline;
+//! # use gotham::router::builder::*;
+//! # use gotham::state::State;
+//! # use gotham::test::TestServer;
+//! #
+//! # fn health(state: State) -> (State, &'static str) { (state, "ok") }
+//! # fn export(state: State) -> (State, &'static str) { (state, "export") }
+//! #
+//! # fn main() {
+//! let limiter = ConcurrencyLimiter::new(64)
+//!     .with_max_queued(1024)
+//!     .with_weights(8, 1);
+//!
+//! let (chain, pipelines) = single_pipeline(new_pipeline().add(limiter).build());
+//! let router = build_router(chain, pipelines, |route| {
+//!     route.get("/health").to(health);
+//!     route
+//!         .post("/export")
+//!         .with_priority(PriorityClass::Batch)
+//!         .to(export);
+//! });
+//!
+//! let test_server = TestServer::new(router).unwrap();
+//! let response = test_server.client().get("http://localhost/health").perform().unwrap();
+//! assert_eq!(response.status(), StatusCode::OK);
+//! # }
+//! ```
+
+mod adaptive;
+
+pub use self::adaptive::AdaptiveLimit;
+
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use futures::channel::oneshot;
+use futures::prelude::*;
+use hyper::StatusCode;
+use log::trace;
+
+use crate::clock::{Clock, SharedClock};
+use crate::handler::{HandlerFuture, HandlerResult};
+use crate::helpers::http::response::create_empty_response;
+use crate::middleware::{Middleware, NewMiddleware};
+use crate::router::route::dispatch::Dispatcher;
+use crate::router::Router;
+use crate::state::{request_id, FromState, State, StateData};
+
+/// The priority class of a request, which decides how soon it is admitted when requests are
+/// queued by a `ConcurrencyLimiter`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PriorityClass {
+    /// Requests a client is waiting for, such as page loads and health checks. This is the
+    /// default.
+    #[default]
+    Interactive,
+
+    /// Bulk requests which can tolerate delays, such as exports and imports.
+    Batch,
+}
+
+impl PriorityClass {
+    fn index(self) -> usize {
+        match self {
+            PriorityClass::Interactive => 0,
+            PriorityClass::Batch => 1,
+        }
+    }
+}
+
+impl StateData for PriorityClass {}
+
+/// The middleware limiting concurrent requests, see the module documentation.
+///
+/// Clones share the same limit, so a single `ConcurrencyLimiter` can be added to several
+/// pipelines. Requests which find the queue full are answered with `503 Service Unavailable`.
+#[derive(Clone)]
+pub struct ConcurrencyLimiter {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    limit: usize,
+    adaptive: Option<AdaptiveLimit>,
+    max_queued: usize,
+    weights: [u32; 2],
+    queues: Mutex<Queues>,
+}
+
+#[derive(Default)]
+struct Queues {
+    limit: usize,
+    running: usize,
+    waiting: [VecDeque<(oneshot::Sender<Permit>, SharedClock)>; 2],
+    credits: [i64; 2],
+    admitted: u64,
+    rejected: u64,
+}
+
+/// A snapshot of the state of a `ConcurrencyLimiter`, see `ConcurrencyLimiter::metrics`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConcurrencyMetrics {
+    limit: usize,
+    running: usize,
+    queued: [usize; 2],
+    admitted: u64,
+    rejected: u64,
+}
+
+impl ConcurrencyMetrics {
+    /// Returns the current limit of concurrent requests.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Returns the number of requests being handled.
+    pub fn running(&self) -> usize {
+        self.running
+    }
+
+    /// Returns the number of requests of `class` waiting to be handled.
+    pub fn queued(&self, class: PriorityClass) -> usize {
+        self.queued[class.index()]
+    }
+
+    /// Returns the number of requests admitted so far, immediately or after being queued.
+    pub fn admitted(&self) -> u64 {
+        self.admitted
+    }
+
+    /// Returns the number of requests rejected so far, because the queue was full.
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+}
+
+impl ConcurrencyLimiter {
+    /// Creates a limiter which handles up to `max_concurrent` requests at a time, and queues any
+    /// number of requests beyond that.
+    ///
+    /// # Panics
+    ///
+    /// If `max_concurrent` is zero.
+    pub fn new(max_concurrent: usize) -> ConcurrencyLimiter {
+        assert!(max_concurrent > 0, "at least one request must be allowed");
+
+        ConcurrencyLimiter::with_limit(max_concurrent, None)
+    }
+
+    /// Creates a limiter whose limit is adjusted to the observed latency of requests by
+    /// `adaptive`, and which queues any number of requests beyond the limit.
+    pub fn adaptive(adaptive: AdaptiveLimit) -> ConcurrencyLimiter {
+        ConcurrencyLimiter::with_limit(adaptive.initial_limit(), Some(adaptive))
+    }
+
+    fn with_limit(limit: usize, adaptive: Option<AdaptiveLimit>) -> ConcurrencyLimiter {
+        ConcurrencyLimiter {
+            inner: Arc::new(Inner {
+                limit,
+                adaptive,
+                max_queued: usize::MAX,
+                weights: [4, 1],
+                queues: Mutex::new(Queues {
+                    limit,
+                    ..Queues::default()
+                }),
+            }),
+        }
+    }
+
+    /// Limits the number of queued requests, across all classes. Further requests are answered
+    /// with `503 Service Unavailable` until the queue drains.
+    pub fn with_max_queued(self, max_queued: usize) -> ConcurrencyLimiter {
+        self.configure(|inner| inner.max_queued = max_queued)
+    }
+
+    /// Sets the share of admissions each class receives while both have queued requests. By
+    /// default, four interactive requests are admitted for each batch request. Weights of zero
+    /// are treated as one, so that no class starves.
+    pub fn with_weights(self, interactive: u32, batch: u32) -> ConcurrencyLimiter {
+        self.configure(|inner| inner.weights = [interactive.max(1), batch.max(1)])
+    }
+
+    /// Returns the number of requests currently being handled.
+    pub fn running(&self) -> usize {
+        self.inner.lock().running
+    }
+
+    /// Returns the number of requests of `class` currently waiting to be handled.
+    pub fn queued(&self, class: PriorityClass) -> usize {
+        self.inner.lock().waiting[class.index()].len()
+    }
+
+    /// Returns a snapshot of the current limit, the running and queued requests, and the counts
+    /// of admitted and rejected requests.
+    pub fn metrics(&self) -> ConcurrencyMetrics {
+        let queues = self.inner.lock();
+        ConcurrencyMetrics {
+            limit: queues.limit,
+            running: queues.running,
+            queued: [queues.waiting[0].len(), queues.waiting[1].len()],
+            admitted: queues.admitted,
+            rejected: queues.rejected,
+        }
+    }
+
+    fn configure<F>(self, f: F) -> ConcurrencyLimiter
+    where
+        F: FnOnce(&mut Inner),
+    {
+        let mut inner = Inner {
+            limit: self.inner.limit,
+            adaptive: self.inner.adaptive.clone(),
+            max_queued: self.inner.max_queued,
+            weights: self.inner.weights,
+            queues: Mutex::new(Queues {
+                limit: self.inner.limit,
+                ..Queues::default()
+            }),
+        };
+        f(&mut inner);
+
+        ConcurrencyLimiter {
+            inner: Arc::new(inner),
+        }
+    }
+}
+
+impl Inner {
+    fn lock(&self) -> MutexGuard<'_, Queues> {
+        // the queues remain consistent when a panic occurs while they are locked
+        match self.queues.lock() {
+            Ok(queues) => queues,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+/// The admission of a request, which is passed on to the next queued request when dropped. The
+/// latency of the request is measured with the `clock` of its `State`.
+struct Permit {
+    inner: Option<Arc<Inner>>,
+    clock: SharedClock,
+    admitted_at: Instant,
+    sample: Option<(Duration, bool)>,
+}
+
+impl Permit {
+    fn new(inner: &Arc<Inner>, clock: SharedClock) -> Permit {
+        Permit {
+            inner: Some(inner.clone()),
+            admitted_at: clock.instant(),
+            clock,
+            sample: None,
+        }
+    }
+
+    /// Records the latency of the request and whether its `result` indicates overload, which
+    /// adjusts an adaptive limit once the permit is dropped.
+    fn complete(&mut self, result: &HandlerResult) {
+        let overload = match result {
+            Ok((_, response)) => matches!(
+                response.status(),
+                StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+            ),
+            Err((_, err)) => matches!(
+                err.status(),
+                StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+            ),
+        };
+        self.finish(overload);
+    }
+
+    /// Records the latency of the request, which indicated overload or not.
+    fn finish(&mut self, overload: bool) {
+        let latency = self
+            .clock
+            .instant()
+            .saturating_duration_since(self.admitted_at);
+        self.sample = Some((latency, overload));
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(inner) = self.inner.take() {
+            release(&inner, self.sample);
+        }
+    }
+}
+
+/// Frees the slot of a completed request, adjusting an adaptive limit by its `sample`, and admits
+/// queued requests while the limit allows. Requests are chosen by smooth weighted round robin
+/// between the classes with queued requests.
+fn release(inner: &Arc<Inner>, sample: Option<(Duration, bool)>) {
+    let mut queues = inner.lock();
+
+    if let (Some(adaptive), Some((latency, overload))) = (&inner.adaptive, sample) {
+        let limit = adaptive.update(queues.limit, queues.running, latency, overload);
+        if limit != queues.limit {
+            trace!(
+                "concurrency limit changed from {} to {}",
+                queues.limit,
+                limit
+            );
+            queues.limit = limit;
+        }
+    }
+
+    queues.running -= 1;
+
+    while queues.running < queues.limit {
+        let candidates: Vec<usize> = (0..2)
+            .filter(|&index| !queues.waiting[index].is_empty())
+            .collect();
+
+        if candidates.is_empty() {
+            return;
+        }
+
+        let total: i64 = candidates
+            .iter()
+            .map(|&index| i64::from(inner.weights[index]))
+            .sum();
+        for &index in &candidates {
+            queues.credits[index] += i64::from(inner.weights[index]);
+        }
+        let chosen = candidates
+            .into_iter()
+            .max_by_key(|&index| (queues.credits[index], std::cmp::Reverse(index)))
+            .unwrap();
+        queues.credits[chosen] -= total;
+
+        let (waiter, clock) = queues.waiting[chosen].pop_front().unwrap();
+
+        // the receiver can only release the permit once the queues are unlocked
+        match waiter.send(Permit::new(inner, clock)) {
+            Ok(()) => {
+                queues.running += 1;
+                queues.admitted += 1;
+            }
+            // the request was cancelled while queued, so the slot goes to the next one
+            Err(mut permit) => permit.inner = None,
+        }
+    }
+}
+
+enum Admission {
+    Admitted(Permit),
+    Queued(oneshot::Receiver<Permit>),
+    Rejected,
+}
+
+impl ConcurrencyLimiter {
+    fn admit(&self, class: PriorityClass, clock: SharedClock) -> Admission {
+        let mut queues = self.inner.lock();
+        let queued: usize = queues.waiting.iter().map(VecDeque::len).sum();
+
+        if queues.running < queues.limit && queued == 0 {
+            queues.running += 1;
+            queues.admitted += 1;
+            Admission::Admitted(Permit::new(&self.inner, clock))
+        } else if queued >= self.inner.max_queued {
+            queues.rejected += 1;
+            Admission::Rejected
+        } else {
+            let (sender, receiver) = oneshot::channel();
+            queues.waiting[class.index()].push_back((sender, clock));
+            Admission::Queued(receiver)
+        }
+    }
+}
+
+impl Middleware for ConcurrencyLimiter {
+    fn call<Chain>(self, state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>> + Send + 'static,
+    {
+        let class = PriorityClass::try_borrow_from(&state)
+            .copied()
+            .unwrap_or_default();
+
+        let queued = match self.admit(class, SharedClock::from_state(&state)) {
+            Admission::Admitted(mut permit) => {
+                return async move {
+                    let result = chain(state).await;
+                    permit.complete(&result);
+                    result
+                }
+                .boxed();
+            }
+            Admission::Queued(receiver) => receiver,
+            Admission::Rejected => {
+                trace!("[{}] concurrency limit queue is full", request_id(&state));
+                let response = create_empty_response(&state, StatusCode::SERVICE_UNAVAILABLE);
+                return future::ok((state, response)).boxed();
+            }
+        };
+
+        trace!("[{}] queued as {:?}", request_id(&state), class);
+
+        async move {
+            match queued.await {
+                Ok(mut permit) => {
+                    let result = chain(state).await;
+                    permit.complete(&result);
+                    result
+                }
+                Err(oneshot::Canceled) => {
+                    let response = create_empty_response(&state, StatusCode::SERVICE_UNAVAILABLE);
+                    Ok((state, response))
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+impl NewMiddleware for ConcurrencyLimiter {
+    type Instance = ConcurrencyLimiter;
+
+    fn new_middleware(&self) -> anyhow::Result<ConcurrencyLimiter> {
+        Ok(self.clone())
+    }
+}
+
+/// A `Dispatcher` which declares the priority class of its route before dispatching, see
+/// `DefineSingleRoute::with_priority`.
+pub(crate) struct PriorityDispatcher {
+    pub(crate) dispatcher: Box<dyn Dispatcher + Send + Sync>,
+    pub(crate) class: PriorityClass,
+}
+
+impl Dispatcher for PriorityDispatcher {
+    fn dispatch(&self, mut state: State) -> Pin<Box<HandlerFuture>> {
+        state.put(self.class);
+        self.dispatcher.dispatch(state)
+    }
+
+    fn pipelines(&self) -> Option<Vec<Vec<&'static str>>> {
+        self.dispatcher.pipelines()
+    }
+
+    fn router(&self) -> Option<&Router> {
+        self.dispatcher.router()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::UNIX_EPOCH;
+
+    use crate::clock::ManualClock;
+
+    fn queue(limiter: &ConcurrencyLimiter, class: PriorityClass) -> oneshot::Receiver<Permit> {
+        match limiter.admit(class, SharedClock::default()) {
+            Admission::Queued(receiver) => receiver,
+            _ => panic!("expected the request to be queued"),
+        }
+    }
+
+    #[test]
+    fn admits_queued_requests_by_weight() {
+        let limiter = ConcurrencyLimiter::new(1)
+            .with_max_queued(8)
+            .with_weights(2, 1);
+
+        let mut permit = match limiter.admit(PriorityClass::Interactive, SharedClock::default()) {
+            Admission::Admitted(permit) => permit,
+            _ => panic!("expected the request to be admitted"),
+        };
+
+        let mut queued = Vec::new();
+        for i in 0..4 {
+            queued.push((format!("batch{}", i), queue(&limiter, PriorityClass::Batch)));
+        }
+        for i in 0..4 {
+            let receiver = queue(&limiter, PriorityClass::Interactive);
+            queued.push((format!("interactive{}", i), receiver));
+        }
+        assert!(matches!(
+            limiter.admit(PriorityClass::Batch, SharedClock::default()),
+            Admission::Rejected
+        ));
+        assert_eq!(limiter.queued(PriorityClass::Interactive), 4);
+
+        // a cancelled request gives up its place in the queue
+        drop(queued.remove(1));
+
+        let mut order = Vec::new();
+        while !queued.is_empty() {
+            drop(permit);
+            let (index, admitted) = queued
+                .iter_mut()
+                .enumerate()
+                .find_map(|(index, (_, receiver))| match receiver.try_recv() {
+                    Ok(Some(admitted)) => Some((index, admitted)),
+                    _ => None,
+                })
+                .expect("a queued request should have been admitted");
+            order.push(queued.remove(index).0);
+            permit = admitted;
+        }
+        drop(permit);
+
+        assert_eq!(
+            order,
+            vec![
+                "interactive0",
+                "batch0",
+                "interactive1",
+                "interactive2",
+                "interactive3",
+                "batch2",
+                "batch3",
+            ]
+        );
+        assert_eq!(limiter.running(), 0);
+    }
+
+    #[test]
+    fn adapts_limit_to_latency() {
+        let limiter = ConcurrencyLimiter::adaptive(
+            AdaptiveLimit::new(Duration::from_secs(1))
+                .with_initial_limit(2)
+                .with_backoff_ratio(0.5),
+        );
+
+        let clock = ManualClock::new(UNIX_EPOCH);
+        let admit =
+            || match limiter.admit(PriorityClass::Interactive, SharedClock::new(clock.clone())) {
+                Admission::Admitted(permit) => permit,
+                _ => panic!("expected the request to be admitted"),
+            };
+
+        let mut first = admit();
+        let _second = admit();
+        let queued = match limiter.admit(PriorityClass::Batch, SharedClock::new(clock.clone())) {
+            Admission::Queued(receiver) => receiver,
+            _ => panic!("expected the request to be queued"),
+        };
+
+        // a fast request with the limit in use grows the limit, admitting the queued request
+        clock.advance(Duration::from_millis(10));
+        first.finish(false);
+        drop(first);
+        let mut third = queued.now_or_never().unwrap().unwrap();
+        assert_eq!(limiter.metrics().limit(), 3);
+
+        // a slow request shrinks it
+        clock.advance(Duration::from_secs(2));
+        third.finish(false);
+        drop(third);
+
+        let metrics = limiter.metrics();
+        assert_eq!(metrics.limit(), 1);
+        assert_eq!(metrics.running(), 1);
+        assert_eq!(metrics.queued(PriorityClass::Batch), 0);
+        assert_eq!(metrics.admitted(), 3);
+        assert_eq!(metrics.rejected(), 0);
+    }
+}
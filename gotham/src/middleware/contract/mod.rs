@@ -0,0 +1,916 @@
+//! Defines a middleware which validates requests, and optionally responses, against an OpenAPI
+//! document, for applications whose API contract is written before their implementation.
+
+mod schema;
+
+use std::collections::{BTreeMap, HashMap};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use futures::prelude::*;
+use hyper::body::HttpBody;
+use hyper::header::{HeaderMap, CONTENT_TYPE};
+use hyper::{Body, Method, Response, StatusCode, Uri};
+use log::warn;
+use serde_json::Value;
+
+use crate::handler::{HandlerError, HandlerFuture, ValidationError};
+use crate::helpers::http::body::{read_limited, read_prefix};
+use crate::helpers::http::request::path::split_path_segments;
+use crate::helpers::http::request::query_string;
+use crate::helpers::http::PercentDecoded;
+use crate::middleware::{Middleware, NewMiddleware};
+use crate::state::{request_id, FromState, State, StateData};
+
+use self::schema::{resolve, validate};
+
+/// The operation recorded for violations by requests which match no operation of the document.
+pub const UNDOCUMENTED_OPERATION: &str = "(undocumented)";
+
+const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+
+const METHODS: [&str; 8] = [
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+/// An OpenAPI 3 document, parsed into the operations which requests are validated against.
+///
+/// Only JSON request and response bodies are validated, against the subset of JSON Schema which
+/// is commonly used in OpenAPI documents. Local `$ref` values, such as
+/// `#/components/schemas/User`, are resolved.
+#[derive(Clone, Debug)]
+pub struct OpenApiSpec {
+    document: Arc<Value>,
+    operations: Arc<Vec<Operation>>,
+}
+
+#[derive(Debug)]
+struct Operation {
+    method: Method,
+    template: String,
+    segments: Vec<PathSegment>,
+    parameters: Vec<Parameter>,
+    request_body: Option<RequestBody>,
+    responses: Vec<(String, Option<Value>)>,
+}
+
+impl Operation {
+    /// Returns whether the JSON request body is validated against a schema.
+    fn has_request_schema(&self) -> bool {
+        matches!(
+            self.request_body,
+            Some(RequestBody {
+                schema: Some(_),
+                ..
+            })
+        )
+    }
+
+    /// Returns the schema of the documented response for `status`, or `None` if the status isn't
+    /// documented.
+    fn response_schema(&self, status: StatusCode) -> Option<&Option<Value>> {
+        let exact = status.as_str().to_owned();
+        let range = format!("{}XX", status.as_u16() / 100);
+        ["DEFAULT", range.as_str(), exact.as_str()]
+            .iter()
+            .rev()
+            .find_map(|key| self.responses.iter().find(|(status, _)| status == key))
+            .map(|(_, schema)| schema)
+    }
+}
+
+#[derive(Debug)]
+enum PathSegment {
+    Static(String),
+    Parameter(String),
+}
+
+#[derive(Clone, Debug)]
+struct Parameter {
+    name: String,
+    location: Location,
+    required: bool,
+    schema: Option<Value>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Location {
+    Path,
+    Query,
+    Header,
+}
+
+#[derive(Debug)]
+struct RequestBody {
+    required: bool,
+    schema: Option<Value>,
+}
+
+impl OpenApiSpec {
+    /// Parses an OpenAPI document in JSON format.
+    pub fn from_json(json: &str) -> anyhow::Result<OpenApiSpec> {
+        OpenApiSpec::from_value(serde_json::from_str(json)?)
+    }
+
+    /// Parses an OpenAPI document which was deserialized into a `serde_json::Value`, e.g. from
+    /// YAML by a serde format crate.
+    pub fn from_value(document: Value) -> anyhow::Result<OpenApiSpec> {
+        let paths = document
+            .get("paths")
+            .and_then(Value::as_object)
+            .ok_or_else(|| anyhow::anyhow!("the OpenAPI document has no paths"))?;
+
+        let mut operations = Vec::new();
+        for (template, path_item) in paths {
+            let path_item = resolve(&document, path_item);
+            let shared = parameters(&document, path_item.get("parameters"));
+
+            for method in METHODS.iter() {
+                let operation = match path_item.get(*method) {
+                    Some(operation) => operation,
+                    None => continue,
+                };
+
+                // parameters of the operation override those of the path with the same location
+                let mut parameters = parameters(&document, operation.get("parameters"));
+                for parameter in &shared {
+                    if !parameters
+                        .iter()
+                        .any(|p| p.name == parameter.name && p.location == parameter.location)
+                    {
+                        parameters.push(parameter.clone());
+                    }
+                }
+
+                let request_body = operation.get("requestBody").map(|body| {
+                    let body = resolve(&document, body);
+                    RequestBody {
+                        required: body.get("required") == Some(&Value::Bool(true)),
+                        schema: json_schema(body),
+                    }
+                });
+
+                let responses = operation
+                    .get("responses")
+                    .and_then(Value::as_object)
+                    .map(|responses| {
+                        responses
+                            .iter()
+                            .map(|(status, response)| {
+                                let response = resolve(&document, response);
+                                (status.to_ascii_uppercase(), json_schema(response))
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default();
+
+                operations.push(Operation {
+                    method: method.to_ascii_uppercase().parse()?,
+                    template: template.clone(),
+                    segments: split_path_segments(template)
+                        .map(|segment| {
+                            match segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                                Some(name) => PathSegment::Parameter(name.to_owned()),
+                                None => PathSegment::Static(segment.to_owned()),
+                            }
+                        })
+                        .collect(),
+                    parameters,
+                    request_body,
+                    responses,
+                });
+            }
+        }
+
+        Ok(OpenApiSpec {
+            document: Arc::new(document),
+            operations: Arc::new(operations),
+        })
+    }
+
+    /// Finds the operation for `method` and `path`, along with the values of its path parameters.
+    ///
+    /// Of several matching operations, the one with the fewest path parameters is used, so that
+    /// `/users/me` takes precedence over `/users/{id}`.
+    fn find(&self, method: &Method, path: &str) -> Option<(&Operation, HashMap<String, String>)> {
+        let segments: Vec<PercentDecoded> = split_path_segments(path)
+            .filter_map(PercentDecoded::new)
+            .collect();
+
+        self.operations
+            .iter()
+            .filter(|operation| operation.method == *method)
+            .filter(|operation| operation.segments.len() == segments.len())
+            .filter_map(|operation| {
+                let mut parameters = HashMap::new();
+                for (expected, segment) in operation.segments.iter().zip(&segments) {
+                    match expected {
+                        PathSegment::Static(expected) if expected != segment.as_ref() => {
+                            return None
+                        }
+                        PathSegment::Static(_) => {}
+                        PathSegment::Parameter(name) => {
+                            parameters.insert(name.clone(), segment.as_ref().to_owned());
+                        }
+                    }
+                }
+                Some((operation, parameters))
+            })
+            .min_by_key(|(_, parameters)| parameters.len())
+    }
+
+    /// Validates the parameters of the request in `state` against `operation`.
+    fn validate_parameters(
+        &self,
+        state: &State,
+        operation: &Operation,
+        path_parameters: &HashMap<String, String>,
+        errors: &mut ValidationError,
+    ) {
+        let query = query_string::split(Uri::borrow_from(state).query());
+        let headers = HeaderMap::borrow_from(state);
+
+        for parameter in &operation.parameters {
+            let (field, values): (_, Vec<String>) = match parameter.location {
+                Location::Path => (
+                    "path",
+                    path_parameters
+                        .get(&parameter.name)
+                        .cloned()
+                        .into_iter()
+                        .collect(),
+                ),
+                Location::Query => (
+                    "query",
+                    query
+                        .get(&parameter.name)
+                        .map(|values| values.iter().map(|v| v.as_ref().to_owned()).collect())
+                        .unwrap_or_default(),
+                ),
+                Location::Header => (
+                    "header",
+                    headers
+                        .get_all(parameter.name.as_str())
+                        .iter()
+                        .filter_map(|value| value.to_str().ok())
+                        .map(str::to_owned)
+                        .collect(),
+                ),
+            };
+            let field = format!("{}.{}", field, parameter.name);
+
+            if values.is_empty() {
+                if parameter.required {
+                    errors.add(field, "is required");
+                }
+                continue;
+            }
+
+            if let Some(schema) = &parameter.schema {
+                let value = coerce(&self.document, schema, &values);
+                validate(&self.document, schema, &value, &field, errors);
+            }
+        }
+    }
+
+    /// Validates the JSON request body `body` against `operation`.
+    fn validate_request_body(
+        &self,
+        operation: &Operation,
+        body: &[u8],
+        errors: &mut ValidationError,
+    ) {
+        let request_body = match &operation.request_body {
+            Some(request_body) => request_body,
+            None => return,
+        };
+
+        if body.is_empty() {
+            if request_body.required {
+                errors.add("body", "is required");
+            }
+            return;
+        }
+
+        if let Some(schema) = &request_body.schema {
+            match serde_json::from_slice::<Value>(body) {
+                Ok(value) => validate(&self.document, schema, &value, "body", errors),
+                Err(e) => {
+                    errors.add("body", format!("is not valid JSON: {}", e));
+                }
+            }
+        }
+    }
+
+    /// Validates the status code and JSON body of a response against `operation`.
+    fn validate_response(
+        &self,
+        operation: &Operation,
+        status: StatusCode,
+        body: Option<&[u8]>,
+        errors: &mut ValidationError,
+    ) {
+        let schema = match operation.response_schema(status) {
+            Some(schema) => schema,
+            None => {
+                errors.add("status", format!("{} is not documented", status.as_u16()));
+                return;
+            }
+        };
+
+        if let (Some(schema), Some(body)) = (schema, body) {
+            match serde_json::from_slice::<Value>(body) {
+                Ok(value) => validate(&self.document, schema, &value, "body", errors),
+                Err(e) => {
+                    errors.add("body", format!("is not valid JSON: {}", e));
+                }
+            }
+        }
+    }
+}
+
+/// Parses the `parameters` of a path item or operation.
+fn parameters(document: &Value, parameters: Option<&Value>) -> Vec<Parameter> {
+    parameters
+        .and_then(Value::as_array)
+        .map(|parameters| {
+            parameters
+                .iter()
+                .map(|parameter| resolve(document, parameter))
+                .filter_map(|parameter| {
+                    let location = match parameter.get("in")?.as_str()? {
+                        "path" => Location::Path,
+                        "query" => Location::Query,
+                        "header" => Location::Header,
+                        // cookie parameters are not validated
+                        _ => return None,
+                    };
+                    let name = parameter.get("name")?.as_str()?;
+                    Some(Parameter {
+                        // header names are case insensitive
+                        name: match location {
+                            Location::Header => name.to_ascii_lowercase(),
+                            _ => name.to_owned(),
+                        },
+                        location,
+                        required: location == Location::Path
+                            || parameter.get("required") == Some(&Value::Bool(true)),
+                        schema: parameter.get("schema").cloned(),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Returns the schema of the JSON media type in the `content` of a request body or response.
+fn json_schema(body: &Value) -> Option<Value> {
+    body.get("content")?
+        .as_object()?
+        .iter()
+        .find(|(media_type, _)| is_json(media_type))
+        .and_then(|(_, media_type)| media_type.get("schema").cloned())
+}
+
+fn is_json(media_type: &str) -> bool {
+    let essence = media_type.split(';').next().unwrap_or_default().trim();
+    essence.eq_ignore_ascii_case("application/json") || essence.ends_with("+json")
+}
+
+/// Converts the textual values of a parameter into the JSON value described by `schema`.
+///
+/// Values which can't be converted are kept as strings, so that validating them reports the
+/// mismatching type.
+fn coerce(document: &Value, schema: &Value, values: &[String]) -> Value {
+    let schema = resolve(document, schema);
+
+    if schema.get("type").and_then(Value::as_str) == Some("array") {
+        let items = schema.get("items").unwrap_or(&Value::Bool(true));
+        let values: Vec<&str> = match values {
+            // a single value holds a comma separated list, for the default `form` style
+            [value] => value.split(',').collect(),
+            values => values.iter().map(String::as_str).collect(),
+        };
+        Value::Array(
+            values
+                .into_iter()
+                .map(|value| coerce_value(document, items, value))
+                .collect(),
+        )
+    } else {
+        coerce_value(document, schema, &values[0])
+    }
+}
+
+fn coerce_value(document: &Value, schema: &Value, value: &str) -> Value {
+    let coerced = match resolve(document, schema)
+        .get("type")
+        .and_then(Value::as_str)
+    {
+        Some("integer") => value.parse::<i64>().ok().map(Value::from),
+        Some("number") => value
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map(Value::Number),
+        Some("boolean") => value.parse::<bool>().ok().map(Value::Bool),
+        _ => None,
+    };
+    coerced.unwrap_or_else(|| Value::String(value.to_owned()))
+}
+
+/// Whether a contract violation was found in a request or a response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ContractDirection {
+    /// The request violated the contract.
+    Request,
+    /// The response violated the contract.
+    Response,
+}
+
+/// Counts of the contract violations found by a `ContractMiddleware`, keyed by the path template
+/// of the operation, e.g. `/users/{id}`, and the direction of the violation.
+///
+/// `ContractViolations` is a cheaply cloneable handle to counts shared with the middleware, and
+/// is put into `State` by the middleware, so that it can be exported by a handler.
+#[derive(Clone, Debug, Default)]
+pub struct ContractViolations {
+    counts: Arc<Mutex<BTreeMap<(String, ContractDirection), u64>>>,
+}
+
+impl ContractViolations {
+    /// Creates a `ContractViolations` value without any counts.
+    pub fn new() -> ContractViolations {
+        ContractViolations::default()
+    }
+
+    /// Returns the number of violations found for the given operation and direction.
+    pub fn count(&self, operation: &str, direction: ContractDirection) -> u64 {
+        self.with_counts(|counts| {
+            counts
+                .get(&(operation.to_owned(), direction))
+                .copied()
+                .unwrap_or(0)
+        })
+    }
+
+    /// Returns a snapshot of all counts, ordered by operation and direction.
+    pub fn counts(&self) -> BTreeMap<(String, ContractDirection), u64> {
+        self.with_counts(|counts| counts.clone())
+    }
+
+    fn record(&self, operation: &str, direction: ContractDirection) {
+        self.with_counts(|counts| {
+            *counts.entry((operation.to_owned(), direction)).or_insert(0) += 1;
+        })
+    }
+
+    fn with_counts<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&mut BTreeMap<(String, ContractDirection), u64>) -> T,
+    {
+        // the counts remain consistent when a panic occurs while they are locked
+        let mut counts = match self.counts.lock() {
+            Ok(counts) => counts,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        f(&mut counts)
+    }
+}
+
+impl StateData for ContractViolations {}
+
+/// Middleware which validates requests against the operations of an OpenAPI document.
+///
+/// The path, query and header parameters of a request, and its JSON body, are validated against
+/// the operation matching the method and path of the request. By default, a request which
+/// violates the contract is rejected with a `400 Bad Request` response listing the violations,
+/// in the same format as a `ValidationError`. With `report_only`, such a request is passed on,
+/// and the violation is only logged and counted.
+///
+/// Requests which match no operation are passed on, so that the `Router` responds to them as
+/// usual, and are counted as violations of the `UNDOCUMENTED_OPERATION`. Violations are counted
+/// in `ContractViolations`, which is also put into `State`.
+///
+/// ```rust
+/// # extern crate gotham;
+/// # extern crate hyper;
+/// #
+/// # use gotham::middleware::contract::{ContractMiddleware, OpenApiSpec};
+/// # use gotham::pipeline::new_pipeline;
+/// # use gotham::pipeline::single::single_pipeline;
+/// # use gotham::router::builder::*;
+/// # use gotham::state::State;
+/// # use gotham::test::TestServer;
+/// # use hyper::StatusCode;
+/// #
+/// fn user(state: State) -> (State, &'static str) {
+///     (state, r#"{"id": 1, "name": "Bruce"}"#)
+/// }
+///
+/// # fn main() {
+/// let spec = OpenApiSpec::from_json(r#"{
+///     "openapi": "3.0.3",
+///     "paths": {
+///         "/users/{id}": {
+///             "get": {
+///                 "parameters": [
+///                     {"name": "id", "in": "path", "schema": {"type": "integer", "minimum": 1}}
+///                 ],
+///                 "responses": {"200": {"description": "The user"}}
+///             }
+///         }
+///     }
+/// }"#)
+/// .unwrap();
+///
+/// let (chain, pipelines) =
+///     single_pipeline(new_pipeline().add(ContractMiddleware::new(spec)).build());
+/// let router = build_router(chain, pipelines, |route| {
+///     route.get("/users/:id").to(user);
+/// });
+///
+/// let test_server = TestServer::new(router).unwrap();
+/// let response = test_server.client().get("http://localhost/users/1").perform().unwrap();
+/// assert_eq!(response.status(), StatusCode::OK);
+///
+/// let response = test_server.client().get("http://localhost/users/me").perform().unwrap();
+/// assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+/// assert_eq!(
+///     response.read_utf8_body().unwrap(),
+///     r#"{"errors":{"path.id":["must be of type integer"]}}"#
+/// );
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ContractMiddleware {
+    spec: OpenApiSpec,
+    reject: bool,
+    validate_responses: bool,
+    max_body_size: usize,
+    violations: ContractViolations,
+}
+
+impl ContractMiddleware {
+    /// Creates a `ContractMiddleware` which rejects requests violating `spec`.
+    pub fn new(spec: OpenApiSpec) -> ContractMiddleware {
+        ContractMiddleware {
+            spec,
+            reject: true,
+            validate_responses: false,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            violations: ContractViolations::new(),
+        }
+    }
+
+    /// Passes requests violating the contract on, instead of rejecting them, so that violations
+    /// are only logged and counted, e.g. while a contract is being introduced.
+    pub fn report_only(self) -> ContractMiddleware {
+        ContractMiddleware {
+            reject: false,
+            ..self
+        }
+    }
+
+    /// Validates the status code and JSON body of successful responses too.
+    ///
+    /// Responses which violate the contract are still sent, and the violation is logged and
+    /// counted. Responses created from a `HandlerError` are not validated.
+    pub fn with_response_validation(self) -> ContractMiddleware {
+        ContractMiddleware {
+            validate_responses: true,
+            ..self
+        }
+    }
+
+    /// Limits the request and response bodies which are buffered for validation to
+    /// `max_body_size` bytes, 1 MiB by default.
+    ///
+    /// Larger request bodies are rejected with `413 Payload Too Large`, and larger response bodies
+    /// are sent without validating them. Bodies without a schema are never buffered.
+    pub fn with_max_body_size(self, max_body_size: usize) -> ContractMiddleware {
+        ContractMiddleware {
+            max_body_size,
+            ..self
+        }
+    }
+
+    /// Counts violations into the given `ContractViolations` value, e.g. one which is exported
+    /// outside of a request.
+    pub fn with_violations(self, violations: ContractViolations) -> ContractMiddleware {
+        ContractMiddleware { violations, ..self }
+    }
+
+    /// Returns the `ContractViolations` value counted into.
+    pub fn violations(&self) -> &ContractViolations {
+        &self.violations
+    }
+}
+
+impl Middleware for ContractMiddleware {
+    fn call<Chain>(self, mut state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>> + Send + 'static,
+    {
+        state.put(self.violations.clone());
+
+        async move {
+            let method = Method::borrow_from(&state).clone();
+            let path = Uri::borrow_from(&state).path().to_owned();
+            let (operation, path_parameters) = match self.spec.find(&method, &path) {
+                Some(found) => found,
+                None => {
+                    warn!(
+                        "[{}] {} {} is not documented",
+                        request_id(&state),
+                        method,
+                        path
+                    );
+                    self.violations
+                        .record(UNDOCUMENTED_OPERATION, ContractDirection::Request);
+                    return chain(state).await;
+                }
+            };
+
+            let mut errors = ValidationError::new();
+            self.spec
+                .validate_parameters(&state, operation, &path_parameters, &mut errors);
+
+            // only bodies which are validated against a schema are buffered
+            let body = state.try_take::<Body>().unwrap_or_else(Body::empty);
+            let body = if operation.has_request_schema() {
+                let body = match read_limited(body, self.max_body_size).await {
+                    Ok(body) => body,
+                    Err(e) => return Err((state, e)),
+                };
+                self.spec
+                    .validate_request_body(operation, &body, &mut errors);
+                Body::from(body)
+            } else {
+                if body.is_end_stream() {
+                    self.spec.validate_request_body(operation, &[], &mut errors);
+                }
+                body
+            };
+            state.put(body);
+
+            if !errors.is_empty() {
+                warn!(
+                    "[{}] request violates {} {}: {}",
+                    request_id(&state),
+                    method,
+                    operation.template,
+                    errors
+                );
+                self.violations
+                    .record(&operation.template, ContractDirection::Request);
+                if self.reject {
+                    let err = HandlerError::from(errors).with_status(StatusCode::BAD_REQUEST);
+                    return Err((state, err));
+                }
+            }
+
+            let (state, response) = chain(state).await?;
+            if !self.validate_responses {
+                return Ok((state, response));
+            }
+
+            let is_json = response
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|content_type| content_type.to_str().ok())
+                .is_some_and(is_json);
+            let has_schema = matches!(operation.response_schema(response.status()), Some(Some(_)));
+            let (parts, body) = response.into_parts();
+            let (body, json) = if is_json && has_schema {
+                match read_prefix(body, self.max_body_size).await {
+                    Ok((json, false, body)) => (body, Some(json)),
+                    Ok((_, true, body)) => {
+                        warn!(
+                            "[{}] response body of {} {} exceeds {} bytes and is not validated",
+                            request_id(&state),
+                            method,
+                            operation.template,
+                            self.max_body_size
+                        );
+                        (body, None)
+                    }
+                    Err(e) => return Err((state, e.into())),
+                }
+            } else {
+                (body, None)
+            };
+
+            let mut errors = ValidationError::new();
+            self.spec
+                .validate_response(operation, parts.status, json.as_deref(), &mut errors);
+            if !errors.is_empty() {
+                warn!(
+                    "[{}] response violates {} {}: {}",
+                    request_id(&state),
+                    method,
+                    operation.template,
+                    errors
+                );
+                self.violations
+                    .record(&operation.template, ContractDirection::Response);
+            }
+
+            Ok((state, Response::from_parts(parts, body)))
+        }
+        .boxed()
+    }
+}
+
+impl NewMiddleware for ContractMiddleware {
+    type Instance = Self;
+
+    fn new_middleware(&self) -> anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::helpers::http::response::create_response;
+    use crate::pipeline::new_pipeline;
+    use crate::pipeline::single::single_pipeline;
+    use crate::router::builder::*;
+    use crate::router::Router;
+    use crate::test::TestServer;
+
+    const SPEC: &str = r##"{
+        "openapi": "3.0.3",
+        "paths": {
+            "/widgets": {
+                "post": {
+                    "parameters": [
+                        {"$ref": "#/components/parameters/Dry"},
+                        {"name": "X-Tenant", "in": "header", "required": true}
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {"$ref": "#/components/schemas/Widget"}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "201": {
+                            "description": "Created",
+                            "content": {
+                                "application/json": {
+                                    "schema": {"$ref": "#/components/schemas/Widget"}
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        },
+        "components": {
+            "parameters": {
+                "Dry": {"name": "dry", "in": "query", "schema": {"type": "boolean"}}
+            },
+            "schemas": {
+                "Widget": {
+                    "type": "object",
+                    "required": ["name"],
+                    "properties": {"name": {"type": "string"}}
+                }
+            }
+        }
+    }"##;
+
+    fn create(mut state: State) -> Pin<Box<HandlerFuture>> {
+        let body = Body::take_from(&mut state);
+        async move {
+            let body = hyper::body::to_bytes(body).await.unwrap();
+            // echoes the widget, with an undocumented status code for empty widgets
+            let status = if &body[..] == b"{}" {
+                StatusCode::OK
+            } else {
+                StatusCode::CREATED
+            };
+            let response = create_response(&state, status, mime::APPLICATION_JSON, body);
+            Ok((state, response))
+        }
+        .boxed()
+    }
+
+    fn router(middleware: ContractMiddleware) -> Router {
+        let (chain, pipelines) = single_pipeline(new_pipeline().add(middleware).build());
+        build_router(chain, pipelines, |route| {
+            route.post("/widgets").to(create);
+            route
+                .get("/undocumented")
+                .to(|state| (state, "undocumented"));
+        })
+    }
+
+    #[test]
+    fn rejects_requests_violating_contract() {
+        let middleware = ContractMiddleware::new(OpenApiSpec::from_json(SPEC).unwrap());
+        let violations = middleware.violations().clone();
+        let test_server = TestServer::new(router(middleware)).unwrap();
+        let client = test_server.client();
+        let post = |uri: &str, body: &str, tenant: bool| {
+            let request = client.post(uri, body.to_owned(), mime::APPLICATION_JSON);
+            let request = if tenant {
+                request.with_header("x-tenant", "acme".parse().unwrap())
+            } else {
+                request
+            };
+            request.perform().unwrap()
+        };
+
+        let response = post("http://localhost/widgets?dry=true", r#"{"name":"a"}"#, true);
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.read_utf8_body().unwrap(), r#"{"name":"a"}"#);
+
+        let response = post("http://localhost/widgets?dry=maybe", r#"{"name":1}"#, false);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.read_utf8_body().unwrap(),
+            r#"{"errors":{"body.name":["must be of type string"],"header.x-tenant":["is required"],"query.dry":["must be of type boolean"]}}"#
+        );
+
+        let response = post("http://localhost/widgets", "", true);
+        assert_eq!(
+            response.read_utf8_body().unwrap(),
+            r#"{"errors":{"body":["is required"]}}"#
+        );
+
+        let response = test_server
+            .client()
+            .get("http://localhost/undocumented")
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        assert_eq!(violations.count("/widgets", ContractDirection::Request), 2);
+        assert_eq!(
+            violations.count(UNDOCUMENTED_OPERATION, ContractDirection::Request),
+            1
+        );
+    }
+
+    #[test]
+    fn reports_violating_requests_and_responses() {
+        let middleware = ContractMiddleware::new(OpenApiSpec::from_json(SPEC).unwrap())
+            .report_only()
+            .with_response_validation();
+        let violations = middleware.violations().clone();
+        let test_server = TestServer::new(router(middleware)).unwrap();
+
+        for body in &[r#"{"name":"a"}"#, r#"{"size":1}"#, "{}"] {
+            let response = test_server
+                .client()
+                .post("http://localhost/widgets", *body, mime::APPLICATION_JSON)
+                .with_header("x-tenant", "acme".parse().unwrap())
+                .perform()
+                .unwrap();
+            assert!(response.status().is_success());
+            assert_eq!(response.read_utf8_body().unwrap(), *body);
+        }
+
+        assert_eq!(
+            violations.counts().into_iter().collect::<Vec<_>>(),
+            vec![
+                (("/widgets".to_owned(), ContractDirection::Request), 2),
+                (("/widgets".to_owned(), ContractDirection::Response), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn limits_buffered_bodies() {
+        let middleware = ContractMiddleware::new(OpenApiSpec::from_json(SPEC).unwrap())
+            .with_response_validation()
+            .with_max_body_size(16);
+        let violations = middleware.violations().clone();
+        let test_server = TestServer::new(router(middleware)).unwrap();
+        let post = |body: &str| {
+            test_server
+                .client()
+                .post(
+                    "http://localhost/widgets",
+                    body.to_owned(),
+                    mime::APPLICATION_JSON,
+                )
+                .with_header("x-tenant", "acme".parse().unwrap())
+                .perform()
+                .unwrap()
+        };
+
+        let response = post(r#"{"name":"a"}"#);
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = post(r#"{"name":"a long widget name"}"#);
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(violations.counts().is_empty());
+    }
+}
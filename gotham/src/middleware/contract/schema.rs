@@ -0,0 +1,438 @@
+//! Validates JSON values against the subset of JSON Schema used by OpenAPI documents.
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::handler::ValidationError;
+
+/// Adds a message to `errors` for every way in which `value` violates `schema`, naming the
+/// offending location below `field`, e.g. `body.items[2].name`.
+///
+/// `$ref` values are resolved as JSON pointers into `document`. The supported keywords are
+/// `type` (including the OpenAPI 3.0 `nullable`), `enum`, `const`, `minimum`, `maximum`,
+/// `exclusiveMinimum`, `exclusiveMaximum`, `multipleOf`, `minLength`, `maxLength`, `pattern`,
+/// `items`, `minItems`, `maxItems`, `uniqueItems`, `properties`, `required`,
+/// `additionalProperties`, `minProperties`, `maxProperties`, `allOf`, `anyOf`, `oneOf` and
+/// `not`. Other keywords, such as `format`, are ignored.
+pub(crate) fn validate(
+    document: &Value,
+    schema: &Value,
+    value: &Value,
+    field: &str,
+    errors: &mut ValidationError,
+) {
+    let schema = resolve(document, schema);
+    let schema = match schema.as_object() {
+        Some(schema) => schema,
+        // `true`, `false` and invalid schemas
+        None => {
+            if schema == &Value::Bool(false) {
+                errors.add(field, "is not allowed");
+            }
+            return;
+        }
+    };
+
+    if value.is_null() && schema.get("nullable") == Some(&Value::Bool(true)) {
+        return;
+    }
+
+    if let Some(expected) = schema.get("type") {
+        let matches = match expected {
+            Value::String(expected) => is_type(value, expected),
+            Value::Array(expected) => expected
+                .iter()
+                .filter_map(Value::as_str)
+                .any(|expected| is_type(value, expected)),
+            _ => true,
+        };
+        if !matches {
+            errors.add(field, format!("must be of type {}", type_list(expected)));
+            return;
+        }
+    }
+
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(value) {
+            errors.add(
+                field,
+                format!("must be one of {}", Value::Array(allowed.clone())),
+            );
+        }
+    }
+
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            errors.add(field, format!("must be {}", expected));
+        }
+    }
+
+    match value {
+        Value::Number(number) => {
+            if let Some(number) = number.as_f64() {
+                validate_number(schema, number, field, errors);
+            }
+        }
+        Value::String(string) => validate_string(schema, string, field, errors),
+        Value::Array(items) => validate_array(document, schema, items, field, errors),
+        Value::Object(properties) => validate_object(document, schema, properties, field, errors),
+        _ => {}
+    }
+
+    if let Some(Value::Array(schemas)) = schema.get("allOf") {
+        for schema in schemas {
+            validate(document, schema, value, field, errors);
+        }
+    }
+
+    if let Some(Value::Array(schemas)) = schema.get("anyOf") {
+        if !schemas
+            .iter()
+            .any(|schema| is_valid(document, schema, value))
+        {
+            errors.add(field, "must match at least one schema of anyOf");
+        }
+    }
+
+    if let Some(Value::Array(schemas)) = schema.get("oneOf") {
+        let matching = schemas
+            .iter()
+            .filter(|schema| is_valid(document, schema, value))
+            .count();
+        if matching != 1 {
+            errors.add(field, "must match exactly one schema of oneOf");
+        }
+    }
+
+    if let Some(schema) = schema.get("not") {
+        if is_valid(document, schema, value) {
+            errors.add(field, "must not match the schema of not");
+        }
+    }
+}
+
+/// Returns `true` if `value` satisfies `schema`.
+pub(crate) fn is_valid(document: &Value, schema: &Value, value: &Value) -> bool {
+    let mut errors = ValidationError::new();
+    validate(document, schema, value, "", &mut errors);
+    errors.is_empty()
+}
+
+/// Follows `$ref` values, which are JSON pointers into `document` such as
+/// `#/components/schemas/User`, until a value without `$ref` is reached.
+///
+/// References which can't be resolved, including those to other documents, resolve to `true`,
+/// i.e. a schema which any value satisfies.
+pub(crate) fn resolve<'a>(document: &'a Value, mut value: &'a Value) -> &'a Value {
+    // guards against cyclic references
+    for _ in 0..32 {
+        match value.get("$ref").and_then(Value::as_str) {
+            Some(reference) => match reference
+                .strip_prefix('#')
+                .and_then(|pointer| document.pointer(pointer))
+            {
+                Some(target) => value = target,
+                None => return &Value::Bool(true),
+            },
+            None => return value,
+        }
+    }
+    &Value::Bool(true)
+}
+
+fn is_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => match value {
+            Value::Number(number) => {
+                number.is_i64() || number.is_u64() || number.as_f64().is_some_and(is_integral)
+            }
+            _ => false,
+        },
+        _ => true,
+    }
+}
+
+fn is_integral(number: f64) -> bool {
+    number.is_finite() && number.fract() == 0.0
+}
+
+fn type_list(expected: &Value) -> String {
+    match expected {
+        Value::Array(types) => types
+            .iter()
+            .filter_map(Value::as_str)
+            .collect::<Vec<_>>()
+            .join(" or "),
+        Value::String(expected) => expected.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn validate_number(
+    schema: &serde_json::Map<String, Value>,
+    number: f64,
+    field: &str,
+    errors: &mut ValidationError,
+) {
+    let limit = |keyword: &str| schema.get(keyword).and_then(Value::as_f64);
+    // OpenAPI 3.0 uses boolean flags which make `minimum` and `maximum` exclusive
+    let flag = |keyword: &str| schema.get(keyword) == Some(&Value::Bool(true));
+
+    if let Some(minimum) = limit("minimum") {
+        if flag("exclusiveMinimum") && number <= minimum {
+            errors.add(field, format!("must be greater than {}", minimum));
+        } else if number < minimum {
+            errors.add(field, format!("must be at least {}", minimum));
+        }
+    }
+    if let Some(maximum) = limit("maximum") {
+        if flag("exclusiveMaximum") && number >= maximum {
+            errors.add(field, format!("must be less than {}", maximum));
+        } else if number > maximum {
+            errors.add(field, format!("must be at most {}", maximum));
+        }
+    }
+    if let Some(minimum) = limit("exclusiveMinimum") {
+        if number <= minimum {
+            errors.add(field, format!("must be greater than {}", minimum));
+        }
+    }
+    if let Some(maximum) = limit("exclusiveMaximum") {
+        if number >= maximum {
+            errors.add(field, format!("must be less than {}", maximum));
+        }
+    }
+    if let Some(divisor) = limit("multipleOf") {
+        if divisor > 0.0 && !is_integral(number / divisor) {
+            errors.add(field, format!("must be a multiple of {}", divisor));
+        }
+    }
+}
+
+fn validate_string(
+    schema: &serde_json::Map<String, Value>,
+    string: &str,
+    field: &str,
+    errors: &mut ValidationError,
+) {
+    let length = string.chars().count() as u64;
+
+    if let Some(minimum) = schema.get("minLength").and_then(Value::as_u64) {
+        if length < minimum {
+            errors.add(
+                field,
+                format!("must be at least {} characters long", minimum),
+            );
+        }
+    }
+    if let Some(maximum) = schema.get("maxLength").and_then(Value::as_u64) {
+        if length > maximum {
+            errors.add(
+                field,
+                format!("must be at most {} characters long", maximum),
+            );
+        }
+    }
+    if let Some(pattern) = schema.get("pattern").and_then(Value::as_str) {
+        // invalid patterns are a mistake in the document rather than the value, so are ignored
+        if let Ok(regex) = Regex::new(pattern) {
+            if !regex.is_match(string) {
+                errors.add(field, format!("must match the pattern {}", pattern));
+            }
+        }
+    }
+}
+
+fn validate_array(
+    document: &Value,
+    schema: &serde_json::Map<String, Value>,
+    items: &[Value],
+    field: &str,
+    errors: &mut ValidationError,
+) {
+    let length = items.len() as u64;
+
+    if let Some(minimum) = schema.get("minItems").and_then(Value::as_u64) {
+        if length < minimum {
+            errors.add(field, format!("must contain at least {} items", minimum));
+        }
+    }
+    if let Some(maximum) = schema.get("maxItems").and_then(Value::as_u64) {
+        if length > maximum {
+            errors.add(field, format!("must contain at most {} items", maximum));
+        }
+    }
+    if schema.get("uniqueItems") == Some(&Value::Bool(true)) {
+        let duplicated = items
+            .iter()
+            .enumerate()
+            .any(|(i, item)| items[..i].contains(item));
+        if duplicated {
+            errors.add(field, "must not contain duplicate items");
+        }
+    }
+    if let Some(item_schema) = schema.get("items") {
+        for (i, item) in items.iter().enumerate() {
+            let field = format!("{}[{}]", field, i);
+            validate(document, item_schema, item, &field, errors);
+        }
+    }
+}
+
+fn validate_object(
+    document: &Value,
+    schema: &serde_json::Map<String, Value>,
+    properties: &serde_json::Map<String, Value>,
+    field: &str,
+    errors: &mut ValidationError,
+) {
+    let length = properties.len() as u64;
+    let nested = |name: &str| {
+        if field.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{}.{}", field, name)
+        }
+    };
+
+    if let Some(minimum) = schema.get("minProperties").and_then(Value::as_u64) {
+        if length < minimum {
+            errors.add(
+                field,
+                format!("must contain at least {} properties", minimum),
+            );
+        }
+    }
+    if let Some(maximum) = schema.get("maxProperties").and_then(Value::as_u64) {
+        if length > maximum {
+            errors.add(
+                field,
+                format!("must contain at most {} properties", maximum),
+            );
+        }
+    }
+    if let Some(Value::Array(required)) = schema.get("required") {
+        for name in required.iter().filter_map(Value::as_str) {
+            if !properties.contains_key(name) {
+                errors.add(nested(name), "is required");
+            }
+        }
+    }
+
+    let declared = schema.get("properties").and_then(Value::as_object);
+    for (name, value) in properties {
+        match declared.and_then(|declared| declared.get(name)) {
+            Some(property_schema) => {
+                validate(document, property_schema, value, &nested(name), errors)
+            }
+            None => match schema.get("additionalProperties") {
+                Some(Value::Bool(false)) => {
+                    errors.add(nested(name), "is not allowed");
+                }
+                Some(additional) => validate(document, additional, value, &nested(name), errors),
+                None => {}
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn messages(schema: Value, value: Value) -> Vec<(String, String)> {
+        let document = json!({
+            "components": {
+                "schemas": {
+                    "Tag": {"type": "string", "enum": ["a", "b"]}
+                }
+            }
+        });
+        let mut errors = ValidationError::new();
+        validate(&document, &schema, &value, "body", &mut errors);
+
+        let errors = serde_json::to_value(&errors).unwrap();
+        let mut messages = Vec::new();
+        for (field, field_messages) in errors["errors"].as_object().unwrap() {
+            for message in field_messages.as_array().unwrap() {
+                messages.push((field.clone(), message.as_str().unwrap().to_owned()));
+            }
+        }
+        messages
+    }
+
+    #[test]
+    fn validates_json_schema_keywords() {
+        let schema = json!({
+            "type": "object",
+            "required": ["name", "age"],
+            "additionalProperties": false,
+            "properties": {
+                "name": {"type": "string", "minLength": 2, "pattern": "^[a-z]+$"},
+                "age": {"type": "integer", "minimum": 0, "exclusiveMaximum": 150},
+                "email": {"type": "string", "nullable": true},
+                "tags": {
+                    "type": "array",
+                    "uniqueItems": true,
+                    "items": {"$ref": "#/components/schemas/Tag"}
+                }
+            }
+        });
+
+        assert!(messages(
+            schema.clone(),
+            json!({"name": "bob", "age": 3, "email": null, "tags": ["a", "b"]})
+        )
+        .is_empty());
+
+        assert_eq!(
+            messages(
+                schema.clone(),
+                json!({"name": "B", "age": 150.5, "tags": ["a", "c", "a"], "extra": 1})
+            ),
+            vec![
+                ("body.age".to_owned(), "must be of type integer".to_owned()),
+                ("body.extra".to_owned(), "is not allowed".to_owned()),
+                (
+                    "body.name".to_owned(),
+                    "must be at least 2 characters long".to_owned()
+                ),
+                (
+                    "body.name".to_owned(),
+                    "must match the pattern ^[a-z]+$".to_owned()
+                ),
+                (
+                    "body.tags".to_owned(),
+                    "must not contain duplicate items".to_owned()
+                ),
+                (
+                    "body.tags[1]".to_owned(),
+                    r#"must be one of ["a","b"]"#.to_owned()
+                ),
+            ]
+        );
+
+        assert_eq!(
+            messages(schema, json!([])),
+            vec![("body".to_owned(), "must be of type object".to_owned())]
+        );
+        assert_eq!(
+            messages(
+                json!({"oneOf": [{"type": "integer"}, {"type": "number"}]}),
+                json!(1)
+            ),
+            vec![(
+                "body".to_owned(),
+                "must match exactly one schema of oneOf".to_owned()
+            )]
+        );
+    }
+}
@@ -0,0 +1,634 @@
+//! Cross-Origin Resource Sharing (CORS), which allows browsers to call the application from pages
+//! served by other origins.
+//!
+//! A `CorsConfig` describes the origins, methods and headers which are allowed, and is applied:
+//!
+//! - to a single route, via `DefineSingleRoute::with_cors`
+//! - to the routes of a scope, or of the whole router, via `DrawRoutes::set_cors`
+//! - as the `CorsMiddleware` of a pipeline
+//!
+//! Preflight requests, i.e. `OPTIONS` requests with an `Access-Control-Request-Method` header,
+//! are answered automatically for routes configured via the router builder, which would otherwise
+//! be rejected with `405 Method Not Allowed`. A `CorsMiddleware` only sees the preflight requests
+//! of paths which have an `OPTIONS` route, as routing happens before pipelines are invoked.
+//!
+//! A configuration declared for a route takes precedence over that of its scope, and that of a
+//! nested scope over that of its parent.
+//!
+//! ```rust
+//! # extern crate gotham;
+//! # extern crate hyper;
+//! #
+//! # use hyper::header::{
+//! #     ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_REQUEST_METHOD,
+//! #     ORIGIN,
+//! # };
+//! # use hyper::{Method, StatusCode};
+//! # use gotham::middleware::cors::CorsConfig;
+//! # use gotham::router::builder::*;
+//! # use gotham::state::State;
+//! # use gotham::test::TestServer;
+//! #
+//! # fn handler(state: State) -> (State, &'static str) {
+//! #     (state, "hello")
+//! # }
+//! #
+//! # fn main() {
+//! let router = build_simple_router(|route| {
+//!     route.scope("/api", |route| {
+//!         route.set_cors(CorsConfig::permissive());
+//!         route.get("/users").to(handler);
+//!     });
+//!
+//!     route
+//!         .post("/webhooks")
+//!         .with_cors(
+//!             CorsConfig::new()
+//!                 .with_allowed_origin("https://partner.example")
+//!                 .with_allowed_methods(vec![Method::POST]),
+//!         )
+//!         .to(handler);
+//! });
+//!
+//! let test_server = TestServer::new(router).unwrap();
+//! let client = test_server.client();
+//!
+//! let response = client
+//!     .get("http://localhost/api/users")
+//!     .with_header(ORIGIN, "https://app.example".parse().unwrap())
+//!     .perform()
+//!     .unwrap();
+//! assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+//!
+//! let response = client
+//!     .options("http://localhost/webhooks")
+//!     .with_header(ORIGIN, "https://partner.example".parse().unwrap())
+//!     .with_header(ACCESS_CONTROL_REQUEST_METHOD, "POST".parse().unwrap())
+//!     .perform()
+//!     .unwrap();
+//! assert_eq!(response.status(), StatusCode::NO_CONTENT);
+//! assert_eq!(
+//!     response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN],
+//!     "https://partner.example"
+//! );
+//! assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_METHODS], "POST");
+//! # }
+//! ```
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::prelude::*;
+use hyper::header::{
+    HeaderMap, HeaderName, HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS,
+    ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+    ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+    ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY,
+};
+use hyper::{Body, Method, Response, StatusCode};
+use log::trace;
+
+use crate::handler::error::append_headers;
+use crate::handler::HandlerFuture;
+use crate::helpers::http::response::create_empty_response;
+use crate::middleware::{Middleware, NewMiddleware};
+use crate::router::non_match::RouteNonMatch;
+use crate::router::route::dispatch::Dispatcher;
+use crate::router::route::matcher::RouteMatcher;
+use crate::state::{request_id, FromState, State};
+
+/// Describes which cross-origin requests are allowed, see the module documentation.
+#[derive(Clone, Debug)]
+pub struct CorsConfig {
+    any_origin: bool,
+    origins: Vec<String>,
+    methods: Vec<Method>,
+    any_header: bool,
+    headers: Vec<HeaderName>,
+    exposed_headers: Vec<HeaderName>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> CorsConfig {
+        CorsConfig {
+            any_origin: false,
+            origins: vec![],
+            methods: vec![Method::GET, Method::HEAD, Method::POST],
+            any_header: false,
+            headers: vec![],
+            exposed_headers: vec![],
+            credentials: false,
+            max_age: None,
+        }
+    }
+}
+
+impl CorsConfig {
+    /// Creates a configuration which allows no origins yet, and the methods `GET`, `HEAD` and
+    /// `POST` without any request headers beyond the CORS-safelisted ones.
+    pub fn new() -> CorsConfig {
+        CorsConfig::default()
+    }
+
+    /// Creates a configuration which allows any origin to make requests with the methods `GET`,
+    /// `HEAD`, `POST`, `PUT`, `PATCH` and `DELETE` and any request headers, but without
+    /// credentials.
+    pub fn permissive() -> CorsConfig {
+        CorsConfig {
+            any_origin: true,
+            methods: vec![
+                Method::GET,
+                Method::HEAD,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+            ],
+            any_header: true,
+            ..CorsConfig::default()
+        }
+    }
+
+    /// Allows requests from `origin`, e.g. `https://app.example`, in addition to the origins
+    /// allowed already.
+    pub fn with_allowed_origin(mut self, origin: &str) -> CorsConfig {
+        self.origins.push(origin.trim_end_matches('/').to_owned());
+        self
+    }
+
+    /// Allows requests from any origin.
+    pub fn with_any_origin(self) -> CorsConfig {
+        CorsConfig {
+            any_origin: true,
+            ..self
+        }
+    }
+
+    /// Replaces the methods which cross-origin requests may use.
+    pub fn with_allowed_methods(self, methods: Vec<Method>) -> CorsConfig {
+        CorsConfig { methods, ..self }
+    }
+
+    /// Replaces the request headers which cross-origin requests may send.
+    pub fn with_allowed_headers(self, headers: Vec<HeaderName>) -> CorsConfig {
+        CorsConfig {
+            any_header: false,
+            headers,
+            ..self
+        }
+    }
+
+    /// Allows cross-origin requests to send any request headers.
+    pub fn with_any_header(self) -> CorsConfig {
+        CorsConfig {
+            any_header: true,
+            ..self
+        }
+    }
+
+    /// Sets the response headers which the scripts of other origins may read, beyond the
+    /// CORS-safelisted ones.
+    pub fn with_exposed_headers(self, exposed_headers: Vec<HeaderName>) -> CorsConfig {
+        CorsConfig {
+            exposed_headers,
+            ..self
+        }
+    }
+
+    /// Allows cross-origin requests with credentials, i.e. cookies and `Authorization` headers.
+    /// Responses then name the requesting origin instead of `*`, even if any origin is allowed.
+    pub fn with_credentials(self) -> CorsConfig {
+        CorsConfig {
+            credentials: true,
+            ..self
+        }
+    }
+
+    /// Sets how long browsers may cache the response to a preflight request.
+    pub fn with_max_age(self, max_age: Duration) -> CorsConfig {
+        CorsConfig {
+            max_age: Some(max_age),
+            ..self
+        }
+    }
+
+    /// Returns the value of the `Access-Control-Allow-Origin` header for the request, or `None`
+    /// if it is not a cross-origin request from an allowed origin.
+    fn allow_origin(&self, headers: &HeaderMap) -> Option<HeaderValue> {
+        let origin = headers.get(ORIGIN)?;
+
+        if self.any_origin && !self.credentials {
+            return Some(HeaderValue::from_static("*"));
+        }
+
+        let allowed = self.any_origin
+            || origin
+                .to_str()
+                .is_ok_and(|origin| self.origins.iter().any(|allowed| allowed == origin));
+
+        if allowed {
+            Some(origin.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Returns the headers to add to the response to an actual, i.e. non-preflight, request.
+    fn response_headers(&self, state: &State) -> Option<HeaderMap> {
+        let allow_origin = self.allow_origin(HeaderMap::borrow_from(state))?;
+
+        let mut headers = HeaderMap::new();
+        if allow_origin != "*" {
+            headers.insert(VARY, HeaderValue::from_static("Origin"));
+        }
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+
+        if self.credentials {
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+
+        if !self.exposed_headers.is_empty() {
+            headers.insert(
+                ACCESS_CONTROL_EXPOSE_HEADERS,
+                join(self.exposed_headers.iter().map(HeaderName::as_str)),
+            );
+        }
+
+        Some(headers)
+    }
+
+    /// Creates the response to a preflight request, which only carries CORS headers if the
+    /// origin, method and headers of the request are all allowed.
+    pub(crate) fn preflight_response(&self, state: &State) -> Response<Body> {
+        let mut response = create_empty_response(state, StatusCode::NO_CONTENT);
+        let request_headers = HeaderMap::borrow_from(state);
+        let headers = response.headers_mut();
+        headers.insert(
+            VARY,
+            HeaderValue::from_static(
+                "Origin, Access-Control-Request-Method, Access-Control-Request-Headers",
+            ),
+        );
+
+        let allow_origin = match self.allow_origin(request_headers) {
+            Some(allow_origin) => allow_origin,
+            None => {
+                trace!("[{}] preflight from disallowed origin", request_id(state));
+                return response;
+            }
+        };
+
+        let method_allowed =
+            requested_method(request_headers).is_some_and(|method| self.methods.contains(&method));
+
+        let requested_headers = request_headers
+            .get(ACCESS_CONTROL_REQUEST_HEADERS)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("");
+        let headers_allowed = self.any_header
+            || requested_headers
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .all(|name| {
+                    self.headers
+                        .iter()
+                        .any(|allowed| allowed.as_str().eq_ignore_ascii_case(name))
+                });
+
+        if !method_allowed || !headers_allowed {
+            trace!(
+                "[{}] preflight for disallowed method or headers",
+                request_id(state)
+            );
+            return response;
+        }
+
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        headers.insert(
+            ACCESS_CONTROL_ALLOW_METHODS,
+            join(self.methods.iter().map(Method::as_str)),
+        );
+
+        if self.any_header {
+            if let Some(requested) = request_headers.get(ACCESS_CONTROL_REQUEST_HEADERS) {
+                headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, requested.clone());
+            }
+        } else if !self.headers.is_empty() {
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_HEADERS,
+                join(self.headers.iter().map(HeaderName::as_str)),
+            );
+        }
+
+        if self.credentials {
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+
+        if let Some(max_age) = self.max_age {
+            headers.insert(ACCESS_CONTROL_MAX_AGE, max_age.as_secs().into());
+        }
+
+        response
+    }
+}
+
+/// Joins header values into a comma separated list.
+fn join<'a, I>(values: I) -> HeaderValue
+where
+    I: Iterator<Item = &'a str>,
+{
+    let values: Vec<&str> = values.collect();
+    HeaderValue::from_str(&values.join(", ")).expect("header names and methods are valid values")
+}
+
+/// Returns the method named by the `Access-Control-Request-Method` header, normalized as
+/// described by the fetch specification.
+fn requested_method(headers: &HeaderMap) -> Option<Method> {
+    headers
+        .get(ACCESS_CONTROL_REQUEST_METHOD)?
+        .to_str()
+        .ok()?
+        .to_ascii_uppercase()
+        .parse()
+        .ok()
+}
+
+/// Returns `true` if the request in `state` is a CORS preflight request.
+pub(crate) fn is_preflight(state: &State) -> bool {
+    let headers = HeaderMap::borrow_from(state);
+    *Method::borrow_from(state) == Method::OPTIONS
+        && headers.contains_key(ORIGIN)
+        && headers.contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+/// Adds the CORS headers described by `config` to the response or error produced by `future`,
+/// unless a more specific configuration did so already.
+pub(crate) fn with_cors_headers(
+    config: Arc<CorsConfig>,
+    future: Pin<Box<HandlerFuture>>,
+) -> Pin<Box<HandlerFuture>> {
+    future
+        .then(move |result| match result {
+            Ok((state, mut response)) => {
+                if !response.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN) {
+                    if let Some(headers) = config.response_headers(&state) {
+                        append_headers(response.headers_mut(), headers);
+                    }
+                }
+                future::ok((state, response))
+            }
+            Err((state, mut err)) => {
+                if !err.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN) {
+                    if let Some(headers) = config.response_headers(&state) {
+                        err = err.with_headers(headers);
+                    }
+                }
+                future::err((state, err))
+            }
+        })
+        .boxed()
+}
+
+/// Middleware which answers CORS preflight requests and adds CORS headers to responses.
+///
+/// See the module documentation for details.
+#[derive(Clone)]
+pub struct CorsMiddleware {
+    config: Arc<CorsConfig>,
+}
+
+impl CorsMiddleware {
+    /// Creates a `CorsMiddleware` applying `config`.
+    pub fn new(config: CorsConfig) -> CorsMiddleware {
+        CorsMiddleware {
+            config: Arc::new(config),
+        }
+    }
+
+    /// Creates a `CorsMiddleware` applying a configuration shared with preflight routes.
+    pub(crate) fn from_shared(config: Arc<CorsConfig>) -> CorsMiddleware {
+        CorsMiddleware { config }
+    }
+}
+
+/// `Middleware` trait implementation.
+impl Middleware for CorsMiddleware {
+    /// Answers preflight requests, and adds CORS headers to the responses of other requests.
+    fn call<Chain>(self, state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>>,
+    {
+        if is_preflight(&state) {
+            let response = self.config.preflight_response(&state);
+            return future::ok((state, response)).boxed();
+        }
+
+        with_cors_headers(self.config, chain(state))
+    }
+}
+
+/// `NewMiddleware` trait implementation.
+impl NewMiddleware for CorsMiddleware {
+    type Instance = Self;
+
+    /// Clones the current middleware to a new instance.
+    fn new_middleware(&self) -> anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+/// A `RouteMatcher` for the preflight requests of routes with the given methods, or of any
+/// method if there are none.
+///
+/// Other requests are rejected with `405 Method Not Allowed` and an empty `Allow` list, which
+/// leaves the response of the other routes on the path unchanged.
+#[derive(Clone)]
+pub(crate) struct PreflightRouteMatcher {
+    pub(crate) methods: Vec<Method>,
+}
+
+impl RouteMatcher for PreflightRouteMatcher {
+    fn is_match(&self, state: &State) -> Result<(), RouteNonMatch> {
+        let matched = is_preflight(state)
+            && (self.methods.is_empty()
+                || requested_method(HeaderMap::borrow_from(state))
+                    .is_some_and(|method| self.methods.contains(&method)));
+
+        if matched {
+            Ok(())
+        } else {
+            Err(RouteNonMatch::new(StatusCode::METHOD_NOT_ALLOWED).with_allow_list(&[]))
+        }
+    }
+}
+
+/// A `Dispatcher` which answers preflight requests, bypassing the pipelines of the route.
+pub(crate) struct PreflightDispatcher {
+    pub(crate) config: Arc<CorsConfig>,
+}
+
+impl Dispatcher for PreflightDispatcher {
+    fn dispatch(&self, state: State) -> Pin<Box<HandlerFuture>> {
+        let response = self.config.preflight_response(&state);
+        future::ok((state, response)).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pipeline::single::single_pipeline;
+    use crate::pipeline::single_middleware;
+    use crate::router::builder::{
+        build_router, build_simple_router, DefineSingleRoute, DrawRoutes,
+    };
+    use crate::test::TestServer;
+
+    fn handler(state: State) -> (State, &'static str) {
+        (state, "hello")
+    }
+
+    #[test]
+    fn answers_preflight_requests() {
+        let config = CorsConfig::new()
+            .with_allowed_origin("https://app.example/")
+            .with_allowed_methods(vec![Method::GET, Method::PUT])
+            .with_allowed_headers(vec![HeaderName::from_static("x-token")])
+            .with_credentials()
+            .with_max_age(Duration::from_secs(600));
+
+        let (chain, pipelines) = single_pipeline(single_middleware(CorsMiddleware::new(config)));
+        let router = build_router(chain, pipelines, |route| {
+            route
+                .request(vec![Method::GET, Method::OPTIONS], "/")
+                .to(handler);
+        });
+
+        let test_server = TestServer::new(router).unwrap();
+        let preflight = |origin: &str, method: &str, headers: &str| {
+            test_server
+                .client()
+                .options("http://localhost/")
+                .with_header(ORIGIN, origin.parse().unwrap())
+                .with_header(ACCESS_CONTROL_REQUEST_METHOD, method.parse().unwrap())
+                .with_header(ACCESS_CONTROL_REQUEST_HEADERS, headers.parse().unwrap())
+                .perform()
+                .unwrap()
+        };
+
+        let response = preflight("https://app.example", "put", "X-Token");
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let headers = response.headers();
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], "https://app.example");
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_METHODS], "GET, PUT");
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_HEADERS], "x-token");
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[ACCESS_CONTROL_MAX_AGE], "600");
+
+        for (origin, method, headers) in &[
+            ("https://other.example", "PUT", ""),
+            ("https://app.example", "DELETE", ""),
+            ("https://app.example", "PUT", "x-token, x-other"),
+        ] {
+            let response = preflight(origin, method, headers);
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+            assert!(!response.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+        }
+
+        let response = test_server
+            .client()
+            .get("http://localhost/")
+            .with_header(ORIGIN, "https://app.example".parse().unwrap())
+            .perform()
+            .unwrap();
+        assert_eq!(
+            response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example"
+        );
+        assert_eq!(response.headers()[VARY], "Origin");
+        assert_eq!(response.read_utf8_body().unwrap(), "hello");
+    }
+
+    #[test]
+    fn applies_route_and_scope_configurations() {
+        let router = build_simple_router(|route| {
+            route.get("/public").to(handler);
+            route.scope("/api", |route| {
+                route.set_cors(CorsConfig::permissive());
+                route.get("/users").to(handler);
+                route
+                    .put("/users")
+                    .with_cors(
+                        CorsConfig::new()
+                            .with_allowed_origin("https://admin.example")
+                            .with_allowed_methods(vec![Method::PUT]),
+                    )
+                    .to(handler);
+            });
+        });
+
+        let test_server = TestServer::new(router).unwrap();
+        let request = |method: Method, path: &str, requested: Option<&str>| {
+            let client = test_server.client();
+            let uri = format!("http://localhost{}", path);
+            let mut request = match method {
+                Method::OPTIONS => client.options(uri),
+                Method::PUT => client.put(uri, "", mime::TEXT_PLAIN),
+                _ => client.get(uri),
+            };
+            request = request.with_header(ORIGIN, "https://admin.example".parse().unwrap());
+            if let Some(requested) = requested {
+                request =
+                    request.with_header(ACCESS_CONTROL_REQUEST_METHOD, requested.parse().unwrap());
+            }
+            let response = request.perform().unwrap();
+            let allow_origin = response
+                .headers()
+                .get(ACCESS_CONTROL_ALLOW_ORIGIN)
+                .map(|value| value.to_str().unwrap().to_owned());
+            (response.status(), allow_origin)
+        };
+
+        let any = Some(String::from("*"));
+        let admin = Some(String::from("https://admin.example"));
+
+        assert_eq!(
+            request(Method::GET, "/public", None),
+            (StatusCode::OK, None)
+        );
+        assert_eq!(
+            request(Method::OPTIONS, "/public", Some("GET")),
+            (StatusCode::OK, None)
+        );
+        assert_eq!(
+            request(Method::GET, "/api/users", None),
+            (StatusCode::OK, any.clone())
+        );
+        assert_eq!(
+            request(Method::OPTIONS, "/api/users", Some("GET")),
+            (StatusCode::NO_CONTENT, any)
+        );
+        assert_eq!(
+            request(Method::OPTIONS, "/api/users", Some("PUT")),
+            (StatusCode::NO_CONTENT, admin.clone())
+        );
+        assert_eq!(
+            request(Method::PUT, "/api/users", None),
+            (StatusCode::OK, admin)
+        );
+        assert_eq!(
+            request(Method::OPTIONS, "/api/users", None),
+            (StatusCode::OK, None)
+        );
+    }
+}
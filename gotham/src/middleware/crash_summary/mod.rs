@@ -0,0 +1,456 @@
+//! Defines a middleware which summarizes recent handler panics and server errors, so operators
+//! can see what is failing without scraping logs.
+//!
+//! The `CrashSummaryMiddleware` records every panic and every `HandlerError` with a `5xx` status
+//! code raised by subsequent middleware and handlers into a `CrashSummary`. Occurrences with the
+//! same route, kind and message are counted in a single `CrashRecord`, and the summary keeps the
+//! most recently seen records. It implements `Handler`, so it can be routed to as an admin
+//! endpoint serving the records as JSON.
+//!
+//! Backtraces are sampled from the first occurrence of a record, if they are enabled by the
+//! `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` environment variables. Panics are passed on after
+//! they were recorded, so they are still handled by the `Router` or the server.
+//!
+//! ```rust
+//! # extern crate gotham;
+//! #
+//! # use gotham::middleware::crash_summary::{CrashSummary, CrashSummaryMiddleware};
+//! # use gotham::pipeline::new_pipeline;
+//! # use gotham::pipeline::single::single_pipeline;
+//! # use gotham::router::Router;
+//! # use gotham::router::builder::*;
+//! # use gotham::state::State;
+//! #
+//! # fn checkout(state: State) -> (State, &'static str) { (state, "ok") }
+//! #
+//! fn router() -> Router {
+//!     let summary = CrashSummary::new(50);
+//!     let (chain, pipelines) = single_pipeline(
+//!         new_pipeline()
+//!             .add(CrashSummaryMiddleware::new(summary.clone()))
+//!             .build(),
+//!     );
+//!
+//!     build_router(chain, pipelines, |route| {
+//!         route.post("/checkout").to(checkout);
+//!         // this route should be protected, as error messages may contain internal details
+//!         route.get("/admin/crashes").to_new_handler(summary);
+//!     })
+//! }
+//! #
+//! # fn main() { let _ = router(); }
+//! ```
+
+use std::any::Any;
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, Once};
+
+use chrono::{DateTime, Utc};
+use futures::prelude::*;
+use hyper::StatusCode;
+use serde_json::{json, Value};
+
+use crate::clock::{Clock, SharedClock};
+use crate::handler::{Handler, HandlerError, HandlerFuture, NewHandler};
+use crate::helpers::http::response::create_response;
+use crate::middleware::metrics::UNMATCHED_ROUTE;
+use crate::middleware::{Middleware, NewMiddleware};
+use crate::router::{panic_message, MatchedRoute};
+use crate::state::{FromState, State};
+
+/// What kind of failure a `CrashRecord` counts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrashKind {
+    /// A panic of a handler or middleware.
+    Panic,
+
+    /// A `HandlerError` with the given server error status code.
+    Error(StatusCode),
+}
+
+/// The occurrences of a failure with the same route, kind and message, see `CrashSummary`.
+#[derive(Clone, Debug)]
+pub struct CrashRecord {
+    route: String,
+    kind: CrashKind,
+    message: String,
+    count: u64,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    backtrace: Option<String>,
+}
+
+impl CrashRecord {
+    /// Returns the template of the route the failure occurred for.
+    pub fn route(&self) -> &str {
+        &self.route
+    }
+
+    /// Returns the kind of the failure.
+    pub fn kind(&self) -> CrashKind {
+        self.kind
+    }
+
+    /// Returns the panic message, or the message of the error chain.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Returns the number of occurrences.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the time of the first occurrence.
+    pub fn first_seen(&self) -> DateTime<Utc> {
+        self.first_seen
+    }
+
+    /// Returns the time of the last occurrence.
+    pub fn last_seen(&self) -> DateTime<Utc> {
+        self.last_seen
+    }
+
+    /// Returns the backtrace sampled from the first occurrence, if backtraces are enabled.
+    pub fn backtrace(&self) -> Option<&str> {
+        self.backtrace.as_deref()
+    }
+
+    /// Renders the record as a JSON object.
+    pub fn to_json(&self) -> Value {
+        let (kind, status) = match self.kind {
+            CrashKind::Panic => ("panic", StatusCode::INTERNAL_SERVER_ERROR),
+            CrashKind::Error(status) => ("error", status),
+        };
+
+        json!({
+            "route": self.route,
+            "kind": kind,
+            "status": status.as_u16(),
+            "message": self.message,
+            "count": self.count,
+            "first_seen": self.first_seen.to_rfc3339(),
+            "last_seen": self.last_seen.to_rfc3339(),
+            "backtrace": self.backtrace,
+        })
+    }
+}
+
+/// A bounded summary of the most recently seen failures.
+///
+/// The summary is a cheaply cloneable handle, shared by a `CrashSummaryMiddleware` and the
+/// `Handler` serving the records.
+#[derive(Clone)]
+pub struct CrashSummary {
+    // ordered from least to most recently seen
+    records: Arc<Mutex<Vec<CrashRecord>>>,
+    capacity: usize,
+}
+
+impl CrashSummary {
+    /// Creates a summary which keeps the given number of records, dropping the least recently
+    /// seen ones.
+    pub fn new(capacity: usize) -> CrashSummary {
+        CrashSummary {
+            records: Arc::new(Mutex::new(Vec::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// Returns the records, from most to least recently seen.
+    pub fn records(&self) -> Vec<CrashRecord> {
+        self.lock().iter().rev().cloned().collect()
+    }
+
+    /// Removes all records from the summary.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Renders the records as JSON, from most to least recently seen.
+    pub fn to_json(&self) -> Value {
+        let records: Vec<Value> = self.lock().iter().rev().map(CrashRecord::to_json).collect();
+        json!({ "crashes": records })
+    }
+
+    fn record(
+        &self,
+        route: &str,
+        kind: CrashKind,
+        message: String,
+        now: DateTime<Utc>,
+        backtrace: Option<String>,
+    ) {
+        let mut records = self.lock();
+        if self.capacity == 0 {
+            return;
+        }
+
+        let existing = records
+            .iter()
+            .position(|r| r.route == route && r.kind == kind && r.message == message);
+
+        let record = match existing {
+            Some(index) => {
+                let mut record = records.remove(index);
+                record.count += 1;
+                record.last_seen = now;
+                record
+            }
+            None => {
+                while records.len() >= self.capacity {
+                    records.remove(0);
+                }
+                CrashRecord {
+                    route: route.to_owned(),
+                    kind,
+                    message,
+                    count: 1,
+                    first_seen: now,
+                    last_seen: now,
+                    backtrace,
+                }
+            }
+        };
+
+        records.push(record);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<CrashRecord>> {
+        // the records remain consistent when a panic occurs while they are locked
+        match self.records.lock() {
+            Ok(records) => records,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl NewHandler for CrashSummary {
+    type Instance = Self;
+
+    fn new_handler(&self) -> anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+/// Serves the records as JSON.
+impl Handler for CrashSummary {
+    fn handle(self, state: State) -> Pin<Box<HandlerFuture>> {
+        let body = self.to_json().to_string();
+        let response = create_response(&state, StatusCode::OK, mime::APPLICATION_JSON, body);
+        future::ok((state, response)).boxed()
+    }
+}
+
+thread_local! {
+    // the backtrace of the last panic on this thread, captured by the panic hook
+    static PANIC_BACKTRACE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Installs a panic hook capturing the backtraces of panics for `CrashSummaryMiddleware`, before
+/// calling the previously installed hook.
+fn install_panic_hook() {
+    static INSTALL: Once = Once::new();
+
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let backtrace = captured(&Backtrace::capture());
+            PANIC_BACKTRACE.with(|cell| *cell.borrow_mut() = backtrace);
+            previous(info);
+        }));
+    });
+}
+
+fn captured(backtrace: &Backtrace) -> Option<String> {
+    match backtrace.status() {
+        BacktraceStatus::Captured => Some(backtrace.to_string()),
+        _ => None,
+    }
+}
+
+/// Middleware which records the panics and server errors passing through it in a
+/// `CrashSummary`, keyed by the template of the matched route.
+///
+/// Failures are recorded as they are raised by subsequent middleware and handlers, so this
+/// middleware should be added to the pipeline before any middleware whose failures should be
+/// recorded too.
+#[derive(Clone)]
+pub struct CrashSummaryMiddleware {
+    summary: CrashSummary,
+}
+
+impl CrashSummaryMiddleware {
+    /// Creates a middleware recording into `summary`.
+    pub fn new(summary: CrashSummary) -> CrashSummaryMiddleware {
+        install_panic_hook();
+        CrashSummaryMiddleware { summary }
+    }
+
+    /// Returns the summary recorded into.
+    pub fn summary(&self) -> &CrashSummary {
+        &self.summary
+    }
+
+    fn record_panic(&self, route: &str, clock: &SharedClock, payload: &(dyn Any + Send)) {
+        let backtrace = PANIC_BACKTRACE.with(|cell| cell.borrow_mut().take());
+        self.summary.record(
+            route,
+            CrashKind::Panic,
+            panic_message(payload).to_owned(),
+            DateTime::from(clock.now()),
+            backtrace,
+        );
+    }
+
+    fn record_error(&self, route: &str, clock: &SharedClock, err: &HandlerError) {
+        self.summary.record(
+            route,
+            CrashKind::Error(err.status()),
+            format!("{:#}", err.cause()),
+            DateTime::from(clock.now()),
+            captured(err.cause().backtrace()),
+        );
+    }
+}
+
+impl Middleware for CrashSummaryMiddleware {
+    fn call<Chain>(self, state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>> + Send + 'static,
+    {
+        let route = MatchedRoute::try_borrow_from(&state)
+            .map(MatchedRoute::template)
+            .unwrap_or(UNMATCHED_ROUTE)
+            .to_owned();
+        let clock = SharedClock::from_state(&state);
+
+        let future = match panic::catch_unwind(AssertUnwindSafe(move || chain(state))) {
+            Ok(future) => future,
+            Err(payload) => {
+                self.record_panic(&route, &clock, &*payload);
+                panic::resume_unwind(payload);
+            }
+        };
+
+        async move {
+            match AssertUnwindSafe(future).catch_unwind().await {
+                Ok(Err((state, err))) => {
+                    if err.status().is_server_error() {
+                        self.record_error(&route, &clock, &err);
+                    }
+                    Err((state, err))
+                }
+                Ok(result) => result,
+                Err(payload) => {
+                    self.record_panic(&route, &clock, &*payload);
+                    panic::resume_unwind(payload)
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+impl NewMiddleware for CrashSummaryMiddleware {
+    type Instance = Self;
+
+    fn new_middleware(&self) -> anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::clock::ManualClock;
+    use crate::handler::HandlerResult;
+    use crate::middleware::state::StateMiddleware;
+    use crate::pipeline::new_pipeline;
+    use crate::pipeline::single::single_pipeline;
+    use crate::router::builder::*;
+    use crate::test::TestServer;
+
+    async fn unavailable(state: State) -> HandlerResult {
+        let err = HandlerError::from(anyhow::anyhow!("database is down"))
+            .with_status(StatusCode::SERVICE_UNAVAILABLE);
+        Err((state, err))
+    }
+
+    async fn conflict(state: State) -> HandlerResult {
+        Err((state, HandlerError::conflict(anyhow::anyhow!("conflict"))))
+    }
+
+    fn crash(_state: State) -> (State, &'static str) {
+        panic!("out of widgets")
+    }
+
+    fn panic_handler(_state: &State, payload: Box<dyn Any + Send>) -> HandlerError {
+        HandlerError::from(anyhow::anyhow!("{}", panic_message(&*payload)))
+    }
+
+    #[test]
+    fn summarizes_panics_and_server_errors() {
+        let summary = CrashSummary::new(2);
+        let clock = ManualClock::new(UNIX_EPOCH);
+        let (chain, pipelines) = single_pipeline(
+            new_pipeline()
+                .add(StateMiddleware::new(SharedClock::new(clock.clone())))
+                .add(CrashSummaryMiddleware::new(summary.clone()))
+                .build(),
+        );
+        let router = build_router(chain, pipelines, |route| {
+            route.get("/widgets/:id").to(crash);
+            route.get("/orders").to_async(unavailable);
+            route.get("/conflict").to_async(conflict);
+            route.get("/crashes").to_new_handler(summary.clone());
+        })
+        .with_panic_handler(panic_handler);
+
+        let test_server = TestServer::new(router).unwrap();
+        let client = test_server.client();
+
+        for uri in &[
+            "http://localhost/widgets/1",
+            "http://localhost/orders",
+            "http://localhost/conflict",
+            "http://localhost/widgets/2",
+        ] {
+            let response = client.get(*uri).perform().unwrap();
+            assert!(response.status().is_server_error() || response.status().is_client_error());
+            clock.advance(Duration::from_secs(1));
+        }
+
+        let records = summary.records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].route(), "/widgets/:id");
+        assert_eq!(records[0].kind(), CrashKind::Panic);
+        assert_eq!(records[0].message(), "out of widgets");
+        assert_eq!(records[0].count(), 2);
+        assert_eq!(records[0].last_seen().timestamp(), 3);
+        assert_eq!(records[0].first_seen().timestamp(), 0);
+        assert_eq!(
+            records[1].kind(),
+            CrashKind::Error(StatusCode::SERVICE_UNAVAILABLE)
+        );
+        assert_eq!(records[1].message(), "database is down");
+
+        // a new record evicts the least recently seen one
+        summary.record("/", CrashKind::Panic, "new".to_owned(), Utc::now(), None);
+        let records = summary.records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].route(), "/widgets/:id");
+
+        let response = client.get("http://localhost/crashes").perform().unwrap();
+        let body: Value = serde_json::from_slice(&response.read_body().unwrap()).unwrap();
+        assert_eq!(body["crashes"][1]["kind"], "panic");
+        assert_eq!(body["crashes"][1]["count"], 2);
+        assert_eq!(body["crashes"][1]["status"], 500);
+    }
+}
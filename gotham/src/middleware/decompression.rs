@@ -0,0 +1,332 @@
+//! Defines a middleware which decompresses request bodies. Requires the `decompression` feature.
+//!
+//! Request bodies with a `Content-Encoding` of `gzip`, `deflate` or `br` are decompressed before
+//! subsequent middleware, extractors and handlers read them, which therefore see the plain body
+//! and no `Content-Encoding` header. The decompressed body is limited in size, so that small,
+//! highly compressed bodies ("zip bombs") can't exhaust the memory of the server.
+//!
+//! ```rust
+//! # extern crate gotham;
+//! # extern crate hyper;
+//! #
+//! # use gotham::middleware::decompression::DecompressionMiddleware;
+//! # use gotham::pipeline::new_pipeline;
+//! # use gotham::pipeline::single::single_pipeline;
+//! # use gotham::router::builder::*;
+//! # use gotham::test::TestServer;
+//! # use hyper::header::{HeaderValue, CONTENT_ENCODING};
+//! # use hyper::StatusCode;
+//! #
+//! # fn main() {
+//! let decompression = DecompressionMiddleware::new().with_max_size(1024 * 1024);
+//! let (chain, pipelines) = single_pipeline(new_pipeline().add(decompression).build());
+//! let router = build_router(chain, pipelines, |route| {
+//!     route.post("/").to(|state| (state, "uploaded"));
+//! });
+//!
+//! let test_server = TestServer::new(router).unwrap();
+//! let response = test_server
+//!     .client()
+//!     .post("http://localhost/", "data", mime::TEXT_PLAIN)
+//!     .with_header(CONTENT_ENCODING, HeaderValue::from_static("compress"))
+//!     .perform()
+//!     .unwrap();
+//! assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+//! assert_eq!(response.headers().get("accept-encoding").unwrap(), "gzip, deflate, br");
+//! # }
+//! ```
+
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::io::{self, Write};
+use std::pin::Pin;
+
+use brotli_decompressor::DecompressorWriter;
+use flate2::write::{GzDecoder, ZlibDecoder};
+use futures::prelude::*;
+use hyper::header::{HeaderMap, HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH};
+use hyper::{Body, StatusCode};
+use log::trace;
+
+use crate::handler::{HandlerError, HandlerFuture};
+use crate::middleware::{Middleware, NewMiddleware};
+use crate::state::{request_id, FromState, State};
+
+/// The default limit of the size of decompressed request bodies, 10 MiB.
+const DEFAULT_MAX_SIZE: usize = 10 * 1024 * 1024;
+
+/// The encodings which are decompressed, as listed in the `Accept-Encoding` header of
+/// `415 Unsupported Media Type` responses.
+const SUPPORTED_ENCODINGS: &str = "gzip, deflate, br";
+
+/// Middleware which decompresses request bodies, see the module documentation.
+///
+/// Requests with any other encoding than `identity` or the supported ones, including several
+/// encodings applied in sequence, are rejected with `415 Unsupported Media Type`. Bodies which
+/// can't be decompressed are rejected with `400 Bad Request`, and bodies exceeding the limit
+/// after decompression with `413 Payload Too Large`.
+#[derive(Clone, Copy, Debug)]
+pub struct DecompressionMiddleware {
+    max_size: usize,
+}
+
+impl DecompressionMiddleware {
+    /// Creates the middleware, which limits decompressed request bodies to 10 MiB.
+    pub fn new() -> DecompressionMiddleware {
+        DecompressionMiddleware {
+            max_size: DEFAULT_MAX_SIZE,
+        }
+    }
+
+    /// Limits decompressed request bodies to `max_size` bytes.
+    pub fn with_max_size(self, max_size: usize) -> DecompressionMiddleware {
+        DecompressionMiddleware { max_size }
+    }
+}
+
+impl Default for DecompressionMiddleware {
+    fn default() -> DecompressionMiddleware {
+        DecompressionMiddleware::new()
+    }
+}
+
+/// The error of a decompressed body exceeding the limit.
+#[derive(Debug)]
+struct TooLarge(usize);
+
+impl Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "decompressed body exceeds {} bytes", self.0)
+    }
+}
+
+impl Error for TooLarge {}
+
+/// Collects the decompressed body, up to the limit.
+struct Limited {
+    body: Vec<u8>,
+    max_size: usize,
+}
+
+impl Write for Limited {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.body.len() + buf.len() > self.max_size {
+            return Err(io::Error::other(TooLarge(self.max_size)));
+        }
+        self.body.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+enum Decoder {
+    Gzip(GzDecoder<Limited>),
+    Deflate(ZlibDecoder<Limited>),
+    Brotli(Box<DecompressorWriter<Limited>>),
+}
+
+impl Decoder {
+    /// Creates the decoder for `encoding`, if it is supported.
+    fn new(encoding: &str, max_size: usize) -> Option<Decoder> {
+        let limited = Limited {
+            body: Vec::new(),
+            max_size,
+        };
+        match encoding {
+            "gzip" | "x-gzip" => Some(Decoder::Gzip(GzDecoder::new(limited))),
+            "deflate" => Some(Decoder::Deflate(ZlibDecoder::new(limited))),
+            "br" => Some(Decoder::Brotli(Box::new(DecompressorWriter::new(
+                limited, 0,
+            )))),
+            _ => None,
+        }
+    }
+
+    fn write_all(&mut self, chunk: &[u8]) -> io::Result<()> {
+        match self {
+            Decoder::Gzip(decoder) => decoder.write_all(chunk),
+            Decoder::Deflate(decoder) => decoder.write_all(chunk),
+            Decoder::Brotli(decoder) => decoder.write_all(chunk),
+        }
+    }
+
+    /// Returns the decompressed body, once the whole body has been written.
+    fn finish(self) -> io::Result<Vec<u8>> {
+        let limited = match self {
+            Decoder::Gzip(decoder) => decoder.finish()?,
+            Decoder::Deflate(decoder) => decoder.finish()?,
+            Decoder::Brotli(decoder) => decoder.into_inner().map_err(|_| {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete brotli stream")
+            })?,
+        };
+        Ok(limited.body)
+    }
+}
+
+/// Converts an error decompressing the body into the error rejecting the request.
+fn rejection(err: io::Error) -> HandlerError {
+    let too_large = err.get_ref().is_some_and(|err| err.is::<TooLarge>());
+    let status = if too_large {
+        StatusCode::PAYLOAD_TOO_LARGE
+    } else {
+        StatusCode::BAD_REQUEST
+    };
+    HandlerError::from(err).with_status(status)
+}
+
+impl Middleware for DecompressionMiddleware {
+    fn call<Chain>(self, mut state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>> + Send + 'static,
+    {
+        let encoding = match HeaderMap::borrow_from(&state).get(CONTENT_ENCODING) {
+            Some(encoding) => encoding.to_str().unwrap_or("").trim().to_ascii_lowercase(),
+            None => return chain(state),
+        };
+        if encoding == "identity" {
+            HeaderMap::borrow_mut_from(&mut state).remove(CONTENT_ENCODING);
+            return chain(state);
+        }
+
+        let mut decoder = match Decoder::new(&encoding, self.max_size) {
+            Some(decoder) => decoder,
+            None => {
+                let err = HandlerError::from(anyhow::anyhow!(
+                    "unsupported content encoding {:?}",
+                    encoding
+                ))
+                .with_status(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+                .with_header(
+                    ACCEPT_ENCODING,
+                    HeaderValue::from_static(SUPPORTED_ENCODINGS),
+                );
+                return future::err((state, err)).boxed();
+            }
+        };
+
+        async move {
+            let mut body = state.try_take::<Body>().unwrap_or_else(Body::empty);
+            while let Some(chunk) = body.next().await {
+                let written = match chunk {
+                    Ok(chunk) => decoder.write_all(&chunk),
+                    Err(e) => return Err((state, HandlerError::bad_request(e))),
+                };
+                if let Err(e) = written {
+                    return Err((state, rejection(e)));
+                }
+            }
+            let body = match decoder.finish() {
+                Ok(body) => body,
+                Err(e) => return Err((state, rejection(e))),
+            };
+
+            trace!(
+                "[{}] {} request body decompressed to {} bytes",
+                request_id(&state),
+                encoding,
+                body.len()
+            );
+            let headers = HeaderMap::borrow_mut_from(&mut state);
+            headers.remove(CONTENT_ENCODING);
+            headers.insert(CONTENT_LENGTH, HeaderValue::from(body.len()));
+            state.put(Body::from(body));
+            chain(state).await
+        }
+        .boxed()
+    }
+}
+
+impl NewMiddleware for DecompressionMiddleware {
+    type Instance = DecompressionMiddleware;
+
+    fn new_middleware(&self) -> anyhow::Result<DecompressionMiddleware> {
+        Ok(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+
+    use crate::pipeline::new_pipeline;
+    use crate::pipeline::single::single_pipeline;
+    use crate::router::builder::{build_router, DefineSingleRoute, DrawRoutes};
+    use crate::router::Router;
+    use crate::test::TestServer;
+
+    async fn echo(state: &mut State) -> Result<Vec<u8>, HandlerError> {
+        assert_eq!(HeaderMap::borrow_from(state).get(CONTENT_ENCODING), None);
+        let body = hyper::body::to_bytes(Body::take_from(state)).await?;
+        Ok(body.to_vec())
+    }
+
+    fn router(max_size: usize) -> Router {
+        let decompression = DecompressionMiddleware::new().with_max_size(max_size);
+        let (chain, pipelines) = single_pipeline(new_pipeline().add(decompression).build());
+        build_router(chain, pipelines, |route| {
+            route.post("/").to_async_borrowing(echo);
+        })
+    }
+
+    fn post(
+        test_server: &TestServer,
+        body: Vec<u8>,
+        encoding: &'static str,
+    ) -> (StatusCode, Vec<u8>) {
+        let response = test_server
+            .client()
+            .post("http://localhost/", body, mime::APPLICATION_OCTET_STREAM)
+            .with_header(CONTENT_ENCODING, HeaderValue::from_static(encoding))
+            .perform()
+            .unwrap();
+        (response.status(), response.read_body().unwrap())
+    }
+
+    #[test]
+    fn decompresses_request_bodies() {
+        let test_server = TestServer::new(router(1024)).unwrap();
+
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(b"hello gzip").unwrap();
+        let gzip = gzip.finish().unwrap();
+        let response = post(&test_server, gzip, "gzip");
+        assert_eq!(response, (StatusCode::OK, b"hello gzip".to_vec()));
+
+        let mut deflate = ZlibEncoder::new(Vec::new(), Compression::default());
+        deflate.write_all(b"hello deflate").unwrap();
+        let deflate = deflate.finish().unwrap();
+        let response = post(&test_server, deflate, "deflate");
+        assert_eq!(response, (StatusCode::OK, b"hello deflate".to_vec()));
+
+        // "hello\n", compressed with brotli
+        let brotli = b"\x8f\x02\x80\x68\x65\x6c\x6c\x6f\x0a\x03".to_vec();
+        let response = post(&test_server, brotli, "br");
+        assert_eq!(response, (StatusCode::OK, b"hello\n".to_vec()));
+
+        let response = post(&test_server, b"plain".to_vec(), "identity");
+        assert_eq!(response, (StatusCode::OK, b"plain".to_vec()));
+    }
+
+    #[test]
+    fn rejects_invalid_and_oversized_bodies() {
+        let test_server = TestServer::new(router(1024)).unwrap();
+
+        let (status, _) = post(&test_server, b"not gzip".to_vec(), "gzip");
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let mut bomb = GzEncoder::new(Vec::new(), Compression::best());
+        bomb.write_all(&[0; 1025]).unwrap();
+        let bomb = bomb.finish().unwrap();
+        let (status, _) = post(&test_server, bomb, "gzip");
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        let (status, _) = post(&test_server, b"data".to_vec(), "gzip, br");
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+}
@@ -0,0 +1,222 @@
+//! Middleware which maps well-known error types to HTTP status codes.
+//!
+//! A `HandlerError` created from an arbitrary error via `From<E>` (e.g. using the `?` operator)
+//! defaults to `500 Internal Server Error`. Adding an `ErrorStatusMap` to a pipeline assigns the
+//! registered status code to such errors instead, so `.with_status()` doesn't have to be repeated
+//! at every call site which may fail with a well-known domain error.
+//!
+//! Errors which were given a status code explicitly, e.g. via `with_status` or
+//! `map_err_with_status`, are left untouched.
+//!
+//! ```rust
+//! # extern crate gotham;
+//! # extern crate hyper;
+//! #
+//! # use gotham::handler::HandlerResult;
+//! # use gotham::middleware::error_status::ErrorStatusMap;
+//! # use gotham::pipeline::new_pipeline;
+//! # use gotham::pipeline::single::single_pipeline;
+//! # use gotham::router::builder::*;
+//! # use gotham::state::State;
+//! # use gotham::test::TestServer;
+//! # use hyper::StatusCode;
+//! #
+//! #[derive(Debug)]
+//! struct NotFound;
+//!
+//! impl std::fmt::Display for NotFound {
+//!     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+//!         f.write_str("not found")
+//!     }
+//! }
+//!
+//! impl std::error::Error for NotFound {}
+//!
+//! async fn handler(state: State) -> HandlerResult {
+//!     Err((state, NotFound.into()))
+//! }
+//!
+//! # fn main() {
+//! let errors = ErrorStatusMap::new().register::<NotFound>(StatusCode::NOT_FOUND);
+//! let (chain, pipelines) = single_pipeline(new_pipeline().add(errors).build());
+//!
+//! let test_server = TestServer::new(build_router(chain, pipelines, |route| {
+//!     route.get("/").to_async(handler);
+//! }))
+//! .unwrap();
+//!
+//! let response = test_server.client().get("http://localhost/").perform().unwrap();
+//! assert_eq!(response.status(), StatusCode::NOT_FOUND);
+//! # }
+//! ```
+use std::fmt::{Debug, Display};
+use std::panic::RefUnwindSafe;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures::prelude::*;
+use hyper::StatusCode;
+use log::trace;
+
+use crate::handler::{HandlerError, HandlerFuture};
+use crate::middleware::{Middleware, NewMiddleware};
+use crate::state::{request_id, State};
+
+type ErrorClassifier = dyn Fn(&HandlerError) -> Option<StatusCode> + Send + Sync + RefUnwindSafe;
+
+/// A registry mapping concrete error types to the status codes which are used when a
+/// `HandlerError` is caused by them.
+///
+/// Besides being added to a pipeline, an `ErrorStatusMap` can be passed to
+/// `Router::with_error_status_map`, to override the status codes classified by `HandlerError` for
+/// well-known causes like `hyper::Error` and `io::Error`.
+///
+/// See the module documentation for details.
+#[derive(Clone, Default)]
+pub struct ErrorStatusMap {
+    entries: Arc<Vec<Arc<ErrorClassifier>>>,
+}
+
+impl ErrorStatusMap {
+    /// Creates an empty `ErrorStatusMap`.
+    pub fn new() -> ErrorStatusMap {
+        ErrorStatusMap::default()
+    }
+
+    /// Registers the status code used for errors caused by the type `E`.
+    ///
+    /// Registrations are consulted in order, so the first matching registration wins.
+    pub fn register<E>(self, status: StatusCode) -> ErrorStatusMap
+    where
+        E: Display + Debug + Send + Sync + 'static,
+    {
+        self.register_with(move |_: &E| Some(status))
+    }
+
+    /// Registers a function choosing the status code used for errors caused by the type `E`, or
+    /// `None` to leave them to later registrations, e.g. depending on the kind of an `io::Error`:
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # extern crate hyper;
+    /// #
+    /// # use std::io;
+    /// #
+    /// # use gotham::middleware::error_status::ErrorStatusMap;
+    /// # use hyper::StatusCode;
+    /// #
+    /// # fn main() {
+    /// let errors = ErrorStatusMap::new().register_with(|err: &io::Error| match err.kind() {
+    ///     io::ErrorKind::NotFound => Some(StatusCode::NOT_FOUND),
+    ///     _ => None,
+    /// });
+    ///
+    /// let err = io::Error::new(io::ErrorKind::NotFound, "no such file");
+    /// assert_eq!(errors.apply(err.into()).status(), StatusCode::NOT_FOUND);
+    /// # }
+    /// ```
+    ///
+    /// Registrations are consulted in order, so the first registration returning a status code
+    /// wins.
+    pub fn register_with<E, F>(self, classify: F) -> ErrorStatusMap
+    where
+        E: Display + Debug + Send + Sync + 'static,
+        F: Fn(&E) -> Option<StatusCode> + Send + Sync + RefUnwindSafe + 'static,
+    {
+        let mut entries = Arc::try_unwrap(self.entries).unwrap_or_else(|arc| (*arc).clone());
+        entries.push(Arc::new(move |err: &HandlerError| {
+            err.downcast_cause_ref::<E>().and_then(&classify)
+        }));
+
+        ErrorStatusMap {
+            entries: Arc::new(entries),
+        }
+    }
+
+    /// Returns the status code registered for the cause of `err`, if any.
+    pub fn status_for(&self, err: &HandlerError) -> Option<StatusCode> {
+        self.entries.iter().find_map(|classify| classify(err))
+    }
+
+    /// Assigns the registered status code to `err`, unless its status code was set explicitly.
+    pub fn apply(&self, err: HandlerError) -> HandlerError {
+        if err.is_status_explicit() {
+            return err;
+        }
+
+        match self.status_for(&err) {
+            Some(status) => err.with_status(status),
+            None => err,
+        }
+    }
+}
+
+/// `Middleware` trait implementation.
+impl Middleware for ErrorStatusMap {
+    /// Maps the status code of errors returned by the rest of the chain.
+    fn call<Chain>(self, state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>>,
+    {
+        chain(state)
+            .or_else(move |(state, err)| {
+                let err = self.apply(err);
+                trace!(
+                    "[{}] error status after mapping: {}",
+                    request_id(&state),
+                    err.status()
+                );
+                future::err((state, err))
+            })
+            .boxed()
+    }
+}
+
+/// `NewMiddleware` trait implementation.
+impl NewMiddleware for ErrorStatusMap {
+    type Instance = Self;
+
+    /// Clones the current middleware to a new instance.
+    fn new_middleware(&self) -> anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::MapHandlerError;
+    use std::io;
+    use thiserror::Error;
+
+    #[derive(Debug, Error)]
+    #[error("conflict")]
+    struct Conflict;
+
+    #[test]
+    fn maps_registered_errors_with_default_status() {
+        let map = ErrorStatusMap::new()
+            .register::<Conflict>(StatusCode::CONFLICT)
+            .register::<io::Error>(StatusCode::SERVICE_UNAVAILABLE);
+
+        let err = map.apply(HandlerError::from(Conflict).context("saving widget"));
+        assert_eq!(err.status(), StatusCode::CONFLICT);
+
+        let err = map.apply(io::Error::other("down").into());
+        assert_eq!(err.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let err = map.apply(anyhow::anyhow!("unknown").into());
+        assert_eq!(err.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let err = map.apply(io::Error::new(io::ErrorKind::TimedOut, "slow").into());
+        assert_eq!(err.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let explicit: Result<(), _> = Err(Conflict);
+        let err = map.apply(
+            explicit
+                .map_err_with_status(StatusCode::INTERNAL_SERVER_ERROR)
+                .unwrap_err(),
+        );
+        assert_eq!(err.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
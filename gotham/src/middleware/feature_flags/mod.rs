@@ -0,0 +1,331 @@
+//! Evaluates feature flags per request, so that handlers and route matchers can enable features
+//! for some users or tenants only.
+//!
+//! `FeatureFlags` is both the middleware, which puts the `Flags` of the request into `State`,
+//! and the factory of `FeatureFlagRouteMatcher` values. Flags are evaluated by a `FlagProvider`
+//! for the `FlagContext` of the request, which by default is the `FlagContext` put into `State`
+//! by an earlier middleware, e.g. after authentication.
+//!
+//! ```rust
+//! # extern crate gotham;
+//! # extern crate hyper;
+//! #
+//! # use hyper::{HeaderMap, StatusCode};
+//! # use gotham::middleware::feature_flags::{flags, FeatureFlags, FlagContext, FlagDefinition, StaticFlagProvider};
+//! # use gotham::pipeline::new_pipeline;
+//! # use gotham::pipeline::single::single_pipeline;
+//! # use gotham::router::builder::*;
+//! # use gotham::state::{FromState, State};
+//! # use gotham::test::TestServer;
+//! #
+//! fn checkout(state: State) -> (State, &'static str) {
+//!     let body = if flags(&state).is_enabled("new_checkout") {
+//!         "new checkout"
+//!     } else {
+//!         "checkout"
+//!     };
+//!     (state, body)
+//! }
+//!
+//! fn beta(state: State) -> (State, &'static str) {
+//!     (state, "beta")
+//! }
+//!
+//! # fn main() {
+//! let provider = StaticFlagProvider::new()
+//!     .with_definition("new_checkout", FlagDefinition::new(false).with_user("ann"))
+//!     .with_definition("beta", FlagDefinition::new(false).with_user("ann"));
+//!
+//! // identifies the user by a header, where an application would authenticate it
+//! let feature_flags = FeatureFlags::new(provider).with_context(|state: &State| {
+//!     let user = HeaderMap::borrow_from(state)
+//!         .get("x-user")
+//!         .and_then(|value| value.to_str().ok());
+//!     match user {
+//!         Some(user) => FlagContext::new().with_user(user),
+//!         None => FlagContext::new(),
+//!     }
+//! });
+//!
+//! let (chain, pipelines) = single_pipeline(new_pipeline().add(feature_flags.clone()).build());
+//! let router = build_router(chain, pipelines, |route| {
+//!     route.get("/checkout").to(checkout);
+//!     route
+//!         .get("/beta")
+//!         .add_route_matcher(feature_flags.route_matcher("beta"))
+//!         .to(beta);
+//! });
+//!
+//! let test_server = TestServer::new(router).unwrap();
+//! let client = test_server.client();
+//!
+//! let response = client
+//!     .get("http://localhost/checkout")
+//!     .with_header("x-user", "ann".parse().unwrap())
+//!     .perform()
+//!     .unwrap();
+//! assert_eq!(response.read_utf8_body().unwrap(), "new checkout");
+//!
+//! let response = client.get("http://localhost/checkout").perform().unwrap();
+//! assert_eq!(response.read_utf8_body().unwrap(), "checkout");
+//!
+//! let response = client.get("http://localhost/beta").perform().unwrap();
+//! assert_eq!(response.status(), StatusCode::NOT_FOUND);
+//! # }
+//! ```
+
+mod provider;
+
+pub use self::provider::{
+    EnvFlagProvider, FlagDefinition, FlagProvider, RemoteFlagProvider, StaticFlagProvider,
+};
+
+use std::panic::RefUnwindSafe;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use hyper::StatusCode;
+use log::trace;
+
+use crate::handler::HandlerFuture;
+use crate::middleware::{Middleware, NewMiddleware};
+use crate::router::route::matcher::RouteMatcher;
+use crate::router::RouteNonMatch;
+use crate::state::{request_id, FromState, State, StateData};
+
+/// Identifies who a request is made for, which flags can be targeted at.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FlagContext {
+    user: Option<String>,
+    tenant: Option<String>,
+}
+
+impl FlagContext {
+    /// Creates an anonymous context.
+    pub fn new() -> FlagContext {
+        FlagContext::default()
+    }
+
+    /// Sets the key of the user the request is made by.
+    pub fn with_user(self, user: &str) -> FlagContext {
+        FlagContext {
+            user: Some(user.to_owned()),
+            ..self
+        }
+    }
+
+    /// Sets the key of the tenant the request is made for.
+    pub fn with_tenant(self, tenant: &str) -> FlagContext {
+        FlagContext {
+            tenant: Some(tenant.to_owned()),
+            ..self
+        }
+    }
+
+    /// Returns the key of the user, if any.
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    /// Returns the key of the tenant, if any.
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+}
+
+impl StateData for FlagContext {}
+
+/// The feature flags of a request, put into `State` by the `FeatureFlags` middleware.
+#[derive(Clone)]
+pub struct Flags {
+    provider: Arc<dyn FlagProvider>,
+    context: FlagContext,
+}
+
+impl Flags {
+    /// Returns `true` if `flag` is enabled for the request. Flags unknown to the provider are
+    /// disabled.
+    pub fn is_enabled(&self, flag: &str) -> bool {
+        self.provider.evaluate(flag, &self.context).unwrap_or(false)
+    }
+
+    /// Returns the context the flags are evaluated for.
+    pub fn context(&self) -> &FlagContext {
+        &self.context
+    }
+}
+
+impl StateData for Flags {}
+
+/// Returns the feature flags of the request.
+///
+/// # Panics
+///
+/// If the `FeatureFlags` middleware has not been invoked for the request.
+pub fn flags(state: &State) -> &Flags {
+    Flags::borrow_from(state)
+}
+
+type ContextFn = dyn Fn(&State) -> FlagContext + Send + Sync + RefUnwindSafe;
+
+/// The middleware evaluating feature flags, see the module documentation.
+#[derive(Clone)]
+pub struct FeatureFlags {
+    provider: Arc<dyn FlagProvider>,
+    context: Arc<ContextFn>,
+}
+
+impl FeatureFlags {
+    /// Creates the middleware for flags evaluated by `provider`.
+    pub fn new<P>(provider: P) -> FeatureFlags
+    where
+        P: FlagProvider + 'static,
+    {
+        FeatureFlags {
+            provider: Arc::new(provider),
+            context: Arc::new(|state: &State| {
+                FlagContext::try_borrow_from(state)
+                    .cloned()
+                    .unwrap_or_default()
+            }),
+        }
+    }
+
+    /// Derives the `FlagContext` of a request with `context`, instead of taking it from `State`.
+    pub fn with_context<F>(self, context: F) -> FeatureFlags
+    where
+        F: Fn(&State) -> FlagContext + Send + Sync + RefUnwindSafe + 'static,
+    {
+        FeatureFlags {
+            context: Arc::new(context),
+            ..self
+        }
+    }
+
+    /// Evaluates the flags of the request in `state`.
+    pub fn flags(&self, state: &State) -> Flags {
+        Flags {
+            provider: self.provider.clone(),
+            context: (self.context)(state),
+        }
+    }
+
+    /// Creates a `RouteMatcher` which only matches requests for which `flag` is enabled, and
+    /// otherwise responds with `404 Not Found`.
+    ///
+    /// Route matching happens before the middleware of the route is invoked. Unless `Flags` were
+    /// put into `State` by the middleware of an outer router, the flag is evaluated for the
+    /// context derived from the request as it was received.
+    pub fn route_matcher(&self, flag: &str) -> FeatureFlagRouteMatcher {
+        FeatureFlagRouteMatcher {
+            flag: flag.to_owned(),
+            feature_flags: self.clone(),
+        }
+    }
+}
+
+impl Middleware for FeatureFlags {
+    fn call<Chain>(self, mut state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>>,
+    {
+        let flags = self.flags(&state);
+        state.put(flags);
+        chain(state)
+    }
+}
+
+impl NewMiddleware for FeatureFlags {
+    type Instance = FeatureFlags;
+
+    fn new_middleware(&self) -> anyhow::Result<FeatureFlags> {
+        Ok(self.clone())
+    }
+}
+
+/// A `RouteMatcher` which succeeds when a feature flag is enabled for the request, see
+/// `FeatureFlags::route_matcher`.
+#[derive(Clone)]
+pub struct FeatureFlagRouteMatcher {
+    flag: String,
+    feature_flags: FeatureFlags,
+}
+
+impl RouteMatcher for FeatureFlagRouteMatcher {
+    fn is_match(&self, state: &State) -> Result<(), RouteNonMatch> {
+        let enabled = match Flags::try_borrow_from(state) {
+            Some(flags) => flags.is_enabled(&self.flag),
+            None => self.feature_flags.flags(state).is_enabled(&self.flag),
+        };
+
+        if enabled {
+            Ok(())
+        } else {
+            trace!(
+                "[{}] feature flag {} is disabled",
+                request_id(state),
+                self.flag
+            );
+            Err(RouteNonMatch::new(StatusCode::NOT_FOUND))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::middleware::state::StateMiddleware;
+    use crate::pipeline::new_pipeline;
+    use crate::pipeline::single::single_pipeline;
+    use crate::router::builder::{build_router, DefineSingleRoute, DrawRoutes};
+    use crate::test::TestServer;
+
+    fn handler(state: State) -> (State, String) {
+        let flags = flags(&state);
+        let body = format!(
+            "{:?} {}",
+            flags.context().tenant(),
+            flags.is_enabled("reports")
+        );
+        (state, body)
+    }
+
+    #[test]
+    fn evaluates_flags_for_context_in_state() {
+        let provider = StaticFlagProvider::new()
+            .with_flag("enabled", true)
+            .with_definition("reports", FlagDefinition::new(false).with_tenant("acme"));
+        let feature_flags = FeatureFlags::new(provider);
+        let context = FlagContext::new().with_tenant("acme");
+
+        let (chain, pipelines) = single_pipeline(
+            new_pipeline()
+                .add(StateMiddleware::new(context))
+                .add(feature_flags.clone())
+                .build(),
+        );
+        let router = build_router(chain, pipelines, |route| {
+            route.get("/").to(handler);
+            route
+                .get("/enabled")
+                .add_route_matcher(feature_flags.route_matcher("enabled"))
+                .to(handler);
+            route
+                .get("/unknown")
+                .add_route_matcher(feature_flags.route_matcher("unknown"))
+                .to(handler);
+        });
+        let test_server = TestServer::new(router).unwrap();
+        let client = test_server.client();
+
+        let response = client.get("http://localhost/").perform().unwrap();
+        assert_eq!(response.read_utf8_body().unwrap(), "Some(\"acme\") true");
+
+        let response = client.get("http://localhost/enabled").perform().unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = client.get("http://localhost/unknown").perform().unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
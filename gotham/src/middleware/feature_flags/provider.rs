@@ -0,0 +1,334 @@
+//! Defines the `FlagProvider` trait and the providers shipped with Gotham.
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::future::Future;
+use std::panic::{AssertUnwindSafe, RefUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use log::warn;
+
+use super::FlagContext;
+
+/// Answers whether a feature flag is enabled for the context of a request.
+///
+/// Evaluation is synchronous, as it happens for every request and in route matchers. Providers
+/// backed by a remote service keep a local copy of the flag definitions instead, which they
+/// refresh in the background, see `RemoteFlagProvider`.
+pub trait FlagProvider: Send + Sync + RefUnwindSafe {
+    /// Evaluates `flag` for `context`, returning `None` if the flag is unknown to the provider.
+    fn evaluate(&self, flag: &str, context: &FlagContext) -> Option<bool>;
+}
+
+/// The definition of a feature flag, which is enabled for a request if any of its conditions
+/// applies.
+#[derive(Clone, Debug, Default)]
+pub struct FlagDefinition {
+    enabled: bool,
+    users: HashSet<String>,
+    tenants: HashSet<String>,
+    percentage: u8,
+}
+
+impl FlagDefinition {
+    /// Creates a flag which is enabled or disabled for everyone.
+    pub fn new(enabled: bool) -> FlagDefinition {
+        FlagDefinition {
+            enabled,
+            ..FlagDefinition::default()
+        }
+    }
+
+    /// Additionally enables the flag for the user `key`.
+    pub fn with_user(mut self, key: &str) -> FlagDefinition {
+        self.users.insert(key.to_owned());
+        self
+    }
+
+    /// Additionally enables the flag for the tenant `key`.
+    pub fn with_tenant(mut self, key: &str) -> FlagDefinition {
+        self.tenants.insert(key.to_owned());
+        self
+    }
+
+    /// Additionally enables the flag for a stable `percentage` of users, or of tenants for
+    /// requests without a user. Values above 100 are treated as 100.
+    pub fn with_percentage(self, percentage: u8) -> FlagDefinition {
+        FlagDefinition {
+            percentage: percentage.min(100),
+            ..self
+        }
+    }
+
+    /// Evaluates the definition of `flag` for `context`.
+    pub fn evaluate(&self, flag: &str, context: &FlagContext) -> bool {
+        if self.enabled {
+            return true;
+        }
+
+        let user = context.user();
+        let tenant = context.tenant();
+
+        if user.is_some_and(|user| self.users.contains(user))
+            || tenant.is_some_and(|tenant| self.tenants.contains(tenant))
+        {
+            return true;
+        }
+
+        match user.or(tenant) {
+            Some(key) if self.percentage > 0 => bucket(flag, key) < u32::from(self.percentage),
+            _ => false,
+        }
+    }
+}
+
+/// Assigns `key` to one of 100 buckets, which is stable across processes and differs per flag.
+fn bucket(flag: &str, key: &str) -> u32 {
+    // FNV-1a, as the std hashers are randomly seeded
+    let hash = flag
+        .bytes()
+        .chain(Some(b':'))
+        .chain(key.bytes())
+        .fold(0x811c_9dc5_u32, |hash, byte| {
+            (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+        });
+    hash % 100
+}
+
+/// A `FlagProvider` with a fixed set of flag definitions.
+#[derive(Clone, Debug, Default)]
+pub struct StaticFlagProvider {
+    flags: HashMap<String, FlagDefinition>,
+}
+
+impl StaticFlagProvider {
+    /// Creates a provider without any flags.
+    pub fn new() -> StaticFlagProvider {
+        StaticFlagProvider::default()
+    }
+
+    /// Defines `flag` as enabled or disabled for everyone.
+    pub fn with_flag(self, flag: &str, enabled: bool) -> StaticFlagProvider {
+        self.with_definition(flag, FlagDefinition::new(enabled))
+    }
+
+    /// Defines `flag` by `definition`.
+    pub fn with_definition(mut self, flag: &str, definition: FlagDefinition) -> StaticFlagProvider {
+        self.flags.insert(flag.to_owned(), definition);
+        self
+    }
+}
+
+impl FlagProvider for StaticFlagProvider {
+    fn evaluate(&self, flag: &str, context: &FlagContext) -> Option<bool> {
+        self.flags
+            .get(flag)
+            .map(|definition| definition.evaluate(flag, context))
+    }
+}
+
+/// A `FlagProvider` reading flags from environment variables when it is created.
+///
+/// The flag `new_checkout` is read from `FEATURE_NEW_CHECKOUT`, and enabled by the values `1`,
+/// `true`, `on` and `yes`, ignoring case. Any other value disables the flag.
+#[derive(Clone, Debug)]
+pub struct EnvFlagProvider {
+    prefix: String,
+    flags: HashMap<String, bool>,
+}
+
+impl EnvFlagProvider {
+    /// Reads the variables starting with `FEATURE_`.
+    pub fn new() -> EnvFlagProvider {
+        EnvFlagProvider::with_prefix("FEATURE_")
+    }
+
+    /// Reads the variables starting with `prefix`.
+    pub fn with_prefix(prefix: &str) -> EnvFlagProvider {
+        EnvFlagProvider::from_vars(prefix, env::vars())
+    }
+
+    fn from_vars<I>(prefix: &str, vars: I) -> EnvFlagProvider
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let flags = vars
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(prefix))
+            .map(|(name, value)| {
+                let enabled = matches!(
+                    value.trim().to_ascii_lowercase().as_str(),
+                    "1" | "true" | "on" | "yes"
+                );
+                (name, enabled)
+            })
+            .collect();
+
+        EnvFlagProvider {
+            prefix: prefix.to_owned(),
+            flags,
+        }
+    }
+
+    fn variable(&self, flag: &str) -> String {
+        let name: String = flag
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+                _ => '_',
+            })
+            .collect();
+        format!("{}{}", self.prefix, name)
+    }
+}
+
+impl Default for EnvFlagProvider {
+    fn default() -> EnvFlagProvider {
+        EnvFlagProvider::new()
+    }
+}
+
+impl FlagProvider for EnvFlagProvider {
+    fn evaluate(&self, flag: &str, _context: &FlagContext) -> Option<bool> {
+        self.flags.get(&self.variable(flag)).copied()
+    }
+}
+
+type FetchFuture =
+    Pin<Box<dyn Future<Output = anyhow::Result<HashMap<String, FlagDefinition>>> + Send>>;
+
+type Fetch = dyn Fn() -> FetchFuture + Send + Sync;
+
+/// A `FlagProvider` for flags managed by a remote service, in the style of LaunchDarkly.
+///
+/// Flag definitions are fetched asynchronously and kept locally, so that evaluations don't wait
+/// for the service. Until the first successful fetch no flags are known. If a fetch fails, the
+/// previous definitions remain in use.
+///
+/// ```rust
+/// # extern crate gotham;
+/// #
+/// # use std::collections::HashMap;
+/// # use std::time::Duration;
+/// # use gotham::middleware::feature_flags::{FlagContext, FlagDefinition, FlagProvider, RemoteFlagProvider};
+/// #
+/// async fn fetch_flags() -> anyhow::Result<HashMap<String, FlagDefinition>> {
+///     // request the definitions from the flag service
+///     let mut flags = HashMap::new();
+///     flags.insert("new_checkout".to_owned(), FlagDefinition::new(false).with_percentage(10));
+///     Ok(flags)
+/// }
+///
+/// # fn main() {
+/// let provider = RemoteFlagProvider::new(fetch_flags);
+/// # let runtime = tokio::runtime::Runtime::new().unwrap();
+/// # runtime.block_on(async {
+/// provider.refresh().await.unwrap();
+/// provider.spawn_polling(Duration::from_secs(30));
+/// # });
+///
+/// let context = FlagContext::new().with_user("user-1");
+/// assert!(provider.evaluate("new_checkout", &context).is_some());
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct RemoteFlagProvider {
+    // the fetch function is never called during evaluation, which is the only code running
+    // inside of a panic boundary
+    fetch: Arc<AssertUnwindSafe<Box<Fetch>>>,
+    flags: Arc<RwLock<HashMap<String, FlagDefinition>>>,
+}
+
+impl RemoteFlagProvider {
+    /// Creates a provider which fetches its flag definitions with `fetch`.
+    pub fn new<F, Fut>(fetch: F) -> RemoteFlagProvider
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<HashMap<String, FlagDefinition>>> + Send + 'static,
+    {
+        RemoteFlagProvider {
+            fetch: Arc::new(AssertUnwindSafe(Box::new(move || -> FetchFuture {
+                Box::pin(fetch())
+            }))),
+            flags: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Fetches the flag definitions and replaces the local copy with them.
+    pub async fn refresh(&self) -> anyhow::Result<()> {
+        let flags = (self.fetch.0)().await?;
+        match self.flags.write() {
+            Ok(mut current) => *current = flags,
+            Err(poisoned) => *poisoned.into_inner() = flags,
+        }
+        Ok(())
+    }
+
+    /// Spawns a task onto the current Tokio runtime, which refreshes the flag definitions every
+    /// `interval`. Failed refreshes are logged.
+    pub fn spawn_polling(&self, interval: Duration) {
+        let provider = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                if let Err(e) = provider.refresh().await {
+                    warn!("failed to refresh feature flags: {:#}", e);
+                }
+            }
+        });
+    }
+}
+
+impl FlagProvider for RemoteFlagProvider {
+    fn evaluate(&self, flag: &str, context: &FlagContext) -> Option<bool> {
+        let flags = match self.flags.read() {
+            Ok(flags) => flags,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        flags
+            .get(flag)
+            .map(|definition| definition.evaluate(flag, context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evaluates_definitions() {
+        let definition = FlagDefinition::new(false)
+            .with_user("ann")
+            .with_tenant("acme")
+            .with_percentage(50);
+
+        assert!(definition.evaluate("f", &FlagContext::new().with_user("ann")));
+        assert!(definition.evaluate("f", &FlagContext::new().with_tenant("acme")));
+        assert!(!definition.evaluate("f", &FlagContext::new()));
+
+        let enabled = (0..1000)
+            .filter(|i| {
+                let context = FlagContext::new().with_user(&format!("user-{}", i));
+                definition.evaluate("f", &context)
+            })
+            .count();
+        assert!((400..600).contains(&enabled), "{}", enabled);
+    }
+
+    #[test]
+    fn reads_env_flags() {
+        let vars = vec![
+            ("FEATURE_NEW_CHECKOUT".to_owned(), "True".to_owned()),
+            ("FEATURE_DARK_MODE".to_owned(), "0".to_owned()),
+            ("OTHER".to_owned(), "1".to_owned()),
+        ];
+        let provider = EnvFlagProvider::from_vars("FEATURE_", vars);
+        let context = FlagContext::new();
+
+        assert_eq!(provider.evaluate("new_checkout", &context), Some(true));
+        assert_eq!(provider.evaluate("dark-mode", &context), Some(false));
+        assert_eq!(provider.evaluate("other", &context), None);
+    }
+}
@@ -4,19 +4,30 @@
 //! of complexity. The default `RequestLogger` will log out using the standard
 //! [Common Log Format](https://en.wikipedia.org/wiki/Common_Log_Format) (CLF).
 //!
-//! There is also a `SimpleLogger` which emits only basic request logs.
+//! There is also a `SimpleLogger` which emits only basic request logs, along with the template of
+//! the matched route (see `gotham::router::MatchedRoute`) for grouping requests by route.
+//!
+//! Both loggers can sample high-volume routes and log them at a different level, adjustable at
+//! runtime, see `LogSampling`.
 use futures::prelude::*;
-use hyper::{header::CONTENT_LENGTH, Method, Uri, Version};
+use hyper::{header::CONTENT_LENGTH, Body, Method, Response, Uri, Version};
 use log::Level;
 use log::{log, log_enabled};
+use std::panic::RefUnwindSafe;
 use std::pin::Pin;
 
+use crate::clock::{Clock, SharedClock};
 use crate::handler::HandlerFuture;
 use crate::helpers::timing::Timer;
 use crate::middleware::{Middleware, NewMiddleware};
+use crate::router::MatchedRoute;
 use crate::state::request_id::request_id;
 use crate::state::{client_addr, FromState, State};
 
+mod sampling;
+
+pub use self::sampling::{LogSampling, RouteLogSettings};
+
 /// A struct that can act as a logging middleware for Gotham.
 ///
 /// We implement `NewMiddleware` here for Gotham to allow us to work with the request
@@ -31,6 +42,28 @@ impl RequestLogger {
     pub fn new(level: Level) -> Self {
         RequestLogger { level }
     }
+
+    /// Samples the logged requests and adjusts their level per route, according to `sampling`.
+    pub fn with_sampling(self, sampling: LogSampling) -> SampledLogger<Self> {
+        SampledLogger {
+            logger: self,
+            sampling,
+        }
+    }
+
+    /// Returns the level to log the request in `state` at, or `None` if it is not logged.
+    fn level(
+        self,
+        state: &State,
+        response: &Response<Body>,
+        sampling: Option<&LogSampling>,
+    ) -> Option<Level> {
+        let level = match sampling {
+            Some(sampling) => sampling.level(state, response.status(), self.level)?,
+            None => self.level,
+        };
+        Some(level).filter(|level| log_enabled!(*level))
+    }
 }
 
 /// Implementation of `NewMiddleware` is required for Gotham middleware.
@@ -53,16 +86,37 @@ impl Middleware for RequestLogger {
     where
         Chain: FnOnce(State) -> Pin<Box<HandlerFuture>>,
     {
-        // skip everything if logging is disabled
-        if !log_enabled!(self.level) {
+        self.log(state, chain, None)
+    }
+}
+
+impl RequestLogger {
+    /// Logs the request in `state` once `chain` has answered it, adjusted by `sampling`.
+    fn log<Chain>(
+        self,
+        state: State,
+        chain: Chain,
+        sampling: Option<LogSampling>,
+    ) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>>,
+    {
+        // skip everything if logging is disabled, unless routes may be logged at another level
+        if sampling.is_none() && !log_enabled!(self.level) {
             return chain(state);
         }
 
         // extract the current time
-        let timer = Timer::new();
+        let clock = SharedClock::from_state(&state);
+        let timer = Timer::started_at(clock.now());
 
         // hook onto the end of the request to log the access
         let f = chain(state).and_then(move |(state, response)| {
+            let level = match self.level(&state, &response, sampling.as_ref()) {
+                Some(level) => level,
+                None => return future::ok((state, response)),
+            };
+
             // format the start time to the CLF formats
             let datetime = timer.start_time().format("%d/%b/%Y:%H:%M:%S %z");
 
@@ -85,7 +139,7 @@ impl Middleware for RequestLogger {
 
                 // log out
                 log!(
-                    self.level,
+                    level,
                     "{} - - [{}] \"{} {} {:?}\" {} {} - {}",
                     ip,
                     datetime,
@@ -94,7 +148,7 @@ impl Middleware for RequestLogger {
                     version,
                     status,
                     length,
-                    timer.elapsed()
+                    timer.elapsed_until(clock.now())
                 );
             }
 
@@ -121,6 +175,28 @@ impl SimpleLogger {
     pub fn new(level: Level) -> Self {
         SimpleLogger { level }
     }
+
+    /// Samples the logged requests and adjusts their level per route, according to `sampling`.
+    pub fn with_sampling(self, sampling: LogSampling) -> SampledLogger<Self> {
+        SampledLogger {
+            logger: self,
+            sampling,
+        }
+    }
+
+    /// Returns the level to log the request in `state` at, or `None` if it is not logged.
+    fn level(
+        self,
+        state: &State,
+        response: &Response<Body>,
+        sampling: Option<&LogSampling>,
+    ) -> Option<Level> {
+        let level = match sampling {
+            Some(sampling) => sampling.level(state, response.status(), self.level)?,
+            None => self.level,
+        };
+        Some(level).filter(|level| log_enabled!(*level))
+    }
 }
 
 /// Implementation of `NewMiddleware` is required for Gotham middleware.
@@ -143,23 +219,50 @@ impl Middleware for SimpleLogger {
     where
         Chain: FnOnce(State) -> Pin<Box<HandlerFuture>>,
     {
-        // skip everything if logging is disabled
-        if !log_enabled!(self.level) {
+        self.log(state, chain, None)
+    }
+}
+
+impl SimpleLogger {
+    /// Logs the request in `state` once `chain` has answered it, adjusted by `sampling`.
+    fn log<Chain>(
+        self,
+        state: State,
+        chain: Chain,
+        sampling: Option<LogSampling>,
+    ) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>>,
+    {
+        // skip everything if logging is disabled, unless routes may be logged at another level
+        if sampling.is_none() && !log_enabled!(self.level) {
             return chain(state);
         }
 
         // extract the current time
-        let timer = Timer::new();
+        let clock = SharedClock::from_state(&state);
+        let timer = Timer::started_at(clock.now());
 
         // execute the request and chain the logging call
         let f = chain(state).and_then(move |(state, response)| {
+            let level = match self.level(&state, &response, sampling.as_ref()) {
+                Some(level) => level,
+                None => return future::ok((state, response)),
+            };
+
+            // requests which matched no route don't pass through a route's pipelines
+            let route = MatchedRoute::try_borrow_from(&state)
+                .map(MatchedRoute::template)
+                .unwrap_or("-");
+
             log!(
-                self.level,
-                "[RESPONSE][{}][{:?}][{}][{}]",
+                level,
+                "[RESPONSE][{}][{:?}][{}][{}][{}]",
                 request_id(&state),
                 response.version(),
                 response.status(),
-                timer.elapsed()
+                timer.elapsed_until(clock.now()),
+                route
             );
 
             future::ok((state, response))
@@ -168,3 +271,43 @@ impl Middleware for SimpleLogger {
         f.boxed()
     }
 }
+
+/// A `RequestLogger` or `SimpleLogger` which samples the logged requests and adjusts their level
+/// per route, created by their `with_sampling` method.
+///
+/// Cloning it shares the `LogSampling`, so that adjusting it at runtime affects every request.
+#[derive(Clone)]
+pub struct SampledLogger<L> {
+    logger: L,
+    sampling: LogSampling,
+}
+
+impl<L> NewMiddleware for SampledLogger<L>
+where
+    SampledLogger<L>: Middleware + Clone + Sync + RefUnwindSafe,
+{
+    type Instance = Self;
+
+    /// Returns a new middleware to be used to serve a request.
+    fn new_middleware(&self) -> anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl Middleware for SampledLogger<RequestLogger> {
+    fn call<Chain>(self, state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>>,
+    {
+        self.logger.log(state, chain, Some(self.sampling))
+    }
+}
+
+impl Middleware for SampledLogger<SimpleLogger> {
+    fn call<Chain>(self, state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>>,
+    {
+        self.logger.log(state, chain, Some(self.sampling))
+    }
+}
@@ -0,0 +1,447 @@
+//! Defines `LogSampling`, which lets the request loggers sample high-volume routes and adjust the
+//! log level per route at runtime.
+
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError, RwLock};
+
+use futures::prelude::*;
+use hyper::{Body, Method, StatusCode, Uri};
+use log::{Level, LevelFilter};
+use serde_derive::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::handler::{Handler, HandlerError, HandlerFuture, NewHandler};
+use crate::helpers::http::request::query_string;
+use crate::helpers::http::response::{create_empty_response, create_response};
+use crate::router::MatchedRoute;
+use crate::state::{FromState, State};
+
+/// How the requests of a route are logged, see `LogSampling`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RouteLogSettings {
+    level: Option<LevelFilter>,
+    sample: u32,
+}
+
+impl RouteLogSettings {
+    /// Creates settings logging every request at the level of the logger.
+    pub fn new() -> Self {
+        RouteLogSettings {
+            level: None,
+            sample: 1,
+        }
+    }
+
+    /// Logs the requests at `level` instead of the level of the logger. `LevelFilter::Off` stops
+    /// logging the requests altogether.
+    pub fn with_level(self, level: LevelFilter) -> Self {
+        RouteLogSettings {
+            level: Some(level),
+            ..self
+        }
+    }
+
+    /// Logs only one in `sample` successful requests. Responses with a client or server error
+    /// status are always logged.
+    ///
+    /// # Panics
+    ///
+    /// If `sample` is `0`.
+    pub fn with_sample(self, sample: u32) -> Self {
+        assert!(sample > 0, "the log sample rate must be at least 1");
+        RouteLogSettings { sample, ..self }
+    }
+
+    fn to_json(self) -> Value {
+        json!({
+            "level": self.level.map(|level| level.to_string().to_lowercase()),
+            "sample": self.sample,
+        })
+    }
+}
+
+impl Default for RouteLogSettings {
+    fn default() -> Self {
+        RouteLogSettings::new()
+    }
+}
+
+/// Sampling and log levels for the requests of each route, shared by the `RequestLogger` and
+/// `SimpleLogger` it is passed to with `with_sampling`, and adjustable at runtime.
+///
+/// Routes are identified by their template, see `gotham::router::MatchedRoute`, and requests
+/// which matched no route by `-`. Routes without settings of their own use the default settings.
+///
+/// `LogSampling` implements `Handler`, so it can be routed to as an admin endpoint. `GET` serves
+/// the settings as JSON, `PUT` replaces the settings of a route with the JSON body, e.g.
+/// `{"route": "/health", "level": "debug", "sample": 100}`, or the default settings if the
+/// `route` is omitted, and `DELETE` with a query string like `?route=/health` restores the default
+/// settings of a route.
+///
+/// ```rust
+/// # extern crate gotham;
+/// # extern crate hyper;
+/// # extern crate log;
+/// #
+/// # use hyper::Method;
+/// # use gotham::middleware::logger::{LogSampling, RouteLogSettings, SimpleLogger};
+/// # use gotham::pipeline::new_pipeline;
+/// # use gotham::pipeline::single::single_pipeline;
+/// # use gotham::router::builder::*;
+/// # use gotham::state::State;
+/// # use log::Level;
+/// #
+/// # fn handler(state: State) -> (State, &'static str) {
+/// #     (state, "")
+/// # }
+/// #
+/// # fn main() {
+/// let sampling =
+///     LogSampling::new().with_route("/health", RouteLogSettings::new().with_sample(100));
+///
+/// let logger = SimpleLogger::new(Level::Info).with_sampling(sampling.clone());
+/// let (chain, pipelines) = single_pipeline(new_pipeline().add(logger).build());
+///
+/// let _router = build_router(chain, pipelines, |route| {
+///     route.get("/health").to(handler);
+///     // this route should be protected
+///     route
+///         .request(vec![Method::GET, Method::PUT, Method::DELETE], "/admin/logging")
+///         .to_new_handler(sampling);
+/// });
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct LogSampling {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    default: RwLock<RouteLogSettings>,
+    routes: RwLock<HashMap<String, RouteLogSettings>>,
+    counters: RwLock<HashMap<String, AtomicU64>>,
+}
+
+impl LogSampling {
+    /// Creates a `LogSampling` which logs every request at the level of the logger, until other
+    /// settings are declared.
+    pub fn new() -> Self {
+        LogSampling::default()
+    }
+
+    /// Sets the default settings, which apply to routes without settings of their own.
+    pub fn with_default(self, settings: RouteLogSettings) -> Self {
+        self.set_default(settings);
+        self
+    }
+
+    /// Sets the settings of the route with the given template.
+    pub fn with_route(self, template: &str, settings: RouteLogSettings) -> Self {
+        self.set_route(template, settings);
+        self
+    }
+
+    /// Replaces the default settings, which apply to routes without settings of their own.
+    pub fn set_default(&self, settings: RouteLogSettings) {
+        *self
+            .inner
+            .default
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = settings;
+    }
+
+    /// Replaces the settings of the route with the given template.
+    pub fn set_route(&self, template: &str, settings: RouteLogSettings) {
+        let mut routes = self
+            .inner
+            .routes
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        routes.insert(template.to_owned(), settings);
+    }
+
+    /// Removes the settings of the route with the given template, so that the default settings
+    /// apply again.
+    pub fn remove_route(&self, template: &str) {
+        self.inner
+            .routes
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(template);
+    }
+
+    /// Returns the settings which apply to the route with the given template.
+    pub fn settings(&self, template: &str) -> RouteLogSettings {
+        match self
+            .inner
+            .routes
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(template)
+        {
+            Some(settings) => *settings,
+            None => *self
+                .inner
+                .default
+                .read()
+                .unwrap_or_else(PoisonError::into_inner),
+        }
+    }
+
+    /// Decides whether the request in `state`, answered with `status`, is logged, and at which
+    /// level, given the `level` of the logger.
+    pub(super) fn level(&self, state: &State, status: StatusCode, level: Level) -> Option<Level> {
+        let route = MatchedRoute::try_borrow_from(state)
+            .map(MatchedRoute::template)
+            .unwrap_or("-");
+        let settings = self.settings(route);
+
+        let level = match settings.level {
+            Some(filter) => filter.to_level()?,
+            None => level,
+        };
+
+        let sampled = settings.sample == 1
+            || status.is_client_error()
+            || status.is_server_error()
+            || self.count(route).is_multiple_of(u64::from(settings.sample));
+        Some(level).filter(|_| sampled)
+    }
+
+    /// Counts a successful request of `route`, returning the count before it.
+    fn count(&self, route: &str) -> u64 {
+        if let Some(counter) = self
+            .inner
+            .counters
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(route)
+        {
+            return counter.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let mut counters = self
+            .inner
+            .counters
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        counters
+            .entry(route.to_owned())
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn to_json(&self) -> Value {
+        let routes: Map<String, Value> = self
+            .inner
+            .routes
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(route, settings)| (route.clone(), settings.to_json()))
+            .collect();
+
+        json!({
+            "default": self.inner.default.read().unwrap_or_else(PoisonError::into_inner).to_json(),
+            "routes": routes,
+        })
+    }
+
+    /// Applies the body of a `PUT` request of the admin endpoint.
+    fn update(&self, body: &[u8]) -> Result<(), HandlerError> {
+        let update: Update = serde_json::from_slice(body)
+            .map_err(|e| HandlerError::from(e).with_status(StatusCode::BAD_REQUEST))?;
+
+        let mut settings = RouteLogSettings::new();
+        if let Some(level) = update.level {
+            let level = level.parse().map_err(|_| {
+                let err = anyhow::anyhow!("invalid log level {:?}", level);
+                HandlerError::from(err).with_status(StatusCode::BAD_REQUEST)
+            })?;
+            settings = settings.with_level(level);
+        }
+        match update.sample {
+            Some(0) => {
+                let err = anyhow::anyhow!("the log sample rate must be at least 1");
+                return Err(HandlerError::from(err).with_status(StatusCode::BAD_REQUEST));
+            }
+            Some(sample) => settings = settings.with_sample(sample),
+            None => {}
+        }
+
+        match update.route {
+            Some(route) => self.set_route(&route, settings),
+            None => self.set_default(settings),
+        }
+        Ok(())
+    }
+}
+
+/// The body of a `PUT` request of the admin endpoint.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Update {
+    route: Option<String>,
+    level: Option<String>,
+    sample: Option<u32>,
+}
+
+impl NewHandler for LogSampling {
+    type Instance = Self;
+
+    fn new_handler(&self) -> anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+/// Serves the settings as JSON, and updates them, see `LogSampling`.
+impl Handler for LogSampling {
+    fn handle(self, mut state: State) -> Pin<Box<HandlerFuture>> {
+        let method = Method::borrow_from(&state).clone();
+        if method == Method::DELETE {
+            let mut query = query_string::split(Uri::borrow_from(&state).query());
+            match query.remove("route").and_then(|mut routes| routes.pop()) {
+                Some(route) => self.remove_route(route.as_ref()),
+                None => self.set_default(RouteLogSettings::new()),
+            }
+            let response = create_empty_response(&state, StatusCode::NO_CONTENT);
+            return future::ok((state, response)).boxed();
+        } else if method != Method::PUT {
+            let body = self.to_json().to_string();
+            let response = create_response(&state, StatusCode::OK, mime::APPLICATION_JSON, body);
+            return future::ok((state, response)).boxed();
+        }
+
+        let body = Body::take_from(&mut state);
+        async move {
+            let body = match hyper::body::to_bytes(body).await {
+                Ok(body) => body,
+                Err(e) => return Err((state, e.into())),
+            };
+
+            match self.update(&body) {
+                Ok(()) => {
+                    let response = create_empty_response(&state, StatusCode::NO_CONTENT);
+                    Ok((state, response))
+                }
+                Err(e) => Err((state, e)),
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::logger::SimpleLogger;
+    use crate::pipeline::new_pipeline;
+    use crate::pipeline::single::single_pipeline;
+    use crate::router::builder::{
+        build_router, build_simple_router, DefineSingleRoute, DrawRoutes,
+    };
+    use crate::test::TestServer;
+
+    fn state_for(route: &str) -> State {
+        let mut state = State::new();
+        MatchedRoute::put(&mut state, route);
+        state
+    }
+
+    #[test]
+    fn samples_successes_and_logs_all_errors() {
+        let sampling = LogSampling::new()
+            .with_route("/hot", RouteLogSettings::new().with_sample(3))
+            .with_route(
+                "/quiet",
+                RouteLogSettings::new().with_level(LevelFilter::Off),
+            );
+
+        let hot = state_for("/hot");
+        let logged: Vec<bool> = (0..6)
+            .map(|_| sampling.level(&hot, StatusCode::OK, Level::Info).is_some())
+            .collect();
+        assert_eq!(logged, vec![true, false, false, true, false, false]);
+        assert_eq!(
+            sampling.level(&hot, StatusCode::INTERNAL_SERVER_ERROR, Level::Info),
+            Some(Level::Info)
+        );
+
+        let quiet = state_for("/quiet");
+        assert_eq!(sampling.level(&quiet, StatusCode::OK, Level::Info), None);
+        assert_eq!(
+            sampling.level(&State::new(), StatusCode::OK, Level::Info),
+            Some(Level::Info)
+        );
+    }
+
+    #[test]
+    fn updates_settings_through_the_admin_endpoint() {
+        let sampling = LogSampling::new();
+        let admin = sampling.clone();
+        let router = build_simple_router(move |route| {
+            route
+                .request(vec![Method::GET, Method::PUT, Method::DELETE], "/logging")
+                .to_new_handler(admin);
+        });
+        let test_server = TestServer::new(router).unwrap();
+        let client = test_server.client();
+
+        let body = r#"{"route": "/hot", "level": "debug", "sample": 10}"#;
+        let response = client
+            .put("http://localhost/logging", body, mime::APPLICATION_JSON)
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            sampling.settings("/hot"),
+            RouteLogSettings::new()
+                .with_level(LevelFilter::Debug)
+                .with_sample(10)
+        );
+
+        let response = client.get("http://localhost/logging").perform().unwrap();
+        let settings: Value = serde_json::from_slice(&response.read_body().unwrap()).unwrap();
+        assert_eq!(settings["routes"]["/hot"]["level"], "debug");
+        assert_eq!(settings["default"]["sample"], 1);
+
+        let body = r#"{"route": "/hot", "sample": 0}"#;
+        let response = client
+            .put("http://localhost/logging", body, mime::APPLICATION_JSON)
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = client
+            .delete("http://localhost/logging?route=%2Fhot")
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(sampling.settings("/hot"), RouteLogSettings::new());
+    }
+
+    #[test]
+    fn shares_sampling_between_requests() {
+        let sampling =
+            LogSampling::new().with_route("/hot", RouteLogSettings::new().with_sample(2));
+        let logger = SimpleLogger::new(Level::Error).with_sampling(sampling.clone());
+        let (chain, pipelines) = single_pipeline(new_pipeline().add(logger).build());
+        let router = build_router(chain, pipelines, |route| {
+            route.get("/hot").to(|state| (state, ""));
+        });
+        let test_server = TestServer::new(router).unwrap();
+
+        for _ in 0..3 {
+            let response = test_server
+                .client()
+                .get("http://localhost/hot")
+                .perform()
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        assert_eq!(sampling.count("/hot"), 3);
+    }
+}
@@ -0,0 +1,245 @@
+//! Defines a middleware which counts `HandlerError` occurrences by status code and route.
+//!
+//! Metrics of all requests, in the Prometheus text exposition format, are provided by the
+//! `prometheus` module.
+
+pub mod prometheus;
+
+use std::collections::BTreeMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use futures::prelude::*;
+use hyper::StatusCode;
+
+use crate::handler::{HandlerError, HandlerFuture};
+use crate::middleware::{Middleware, NewMiddleware};
+use crate::router::MatchedRoute;
+use crate::state::{FromState, State, StateData};
+
+/// The route recorded for errors of requests which were not dispatched by a `Router`.
+pub const UNMATCHED_ROUTE: &str = "(unmatched)";
+
+/// Counts of the `HandlerError` values raised by handlers and subsequent middleware, keyed by
+/// route template and status code.
+///
+/// `Metrics` is a cheaply cloneable handle to counts shared with an `ErrorMetricsMiddleware`, and
+/// is put into `State` by the middleware, so that it can be exported by a handler:
+///
+/// ```rust
+/// # extern crate gotham;
+/// # extern crate hyper;
+/// #
+/// # use gotham::anyhow::anyhow;
+/// # use gotham::handler::{HandlerError, HandlerResult};
+/// # use gotham::middleware::metrics::{ErrorMetricsMiddleware, Metrics};
+/// # use gotham::pipeline::new_pipeline;
+/// # use gotham::pipeline::single::single_pipeline;
+/// # use gotham::router::builder::*;
+/// # use gotham::state::{FromState, State};
+/// # use gotham::test::TestServer;
+/// #
+/// async fn user(state: State) -> HandlerResult {
+///     Err((state, HandlerError::not_found(anyhow!("no such user"))))
+/// }
+///
+/// fn metrics(state: State) -> (State, String) {
+///     let body = Metrics::borrow_from(&state)
+///         .counts()
+///         .iter()
+///         .map(|((route, status), count)| format!("{} {} {}\n", route, status.as_u16(), count))
+///         .collect();
+///     (state, body)
+/// }
+///
+/// # fn main() {
+/// let (chain, pipelines) =
+///     single_pipeline(new_pipeline().add(ErrorMetricsMiddleware::new()).build());
+///
+/// let router = build_router(chain, pipelines, |route| {
+///     route.get("/users/:id").to_async(user);
+///     route.get("/metrics").to(metrics);
+/// });
+///
+/// let test_server = TestServer::new(router).unwrap();
+/// let client = test_server.client();
+/// client.get("http://localhost/users/1").perform().unwrap();
+/// client.get("http://localhost/users/2").perform().unwrap();
+///
+/// let response = client.get("http://localhost/metrics").perform().unwrap();
+/// assert_eq!(response.read_utf8_body().unwrap(), "/users/:id 404 2\n");
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    counts: Arc<Mutex<BTreeMap<(String, StatusCode), u64>>>,
+}
+
+impl Metrics {
+    /// Creates a `Metrics` value without any counts.
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    /// Returns the number of errors with the given status code raised for the given route
+    /// template.
+    pub fn count(&self, route: &str, status: StatusCode) -> u64 {
+        self.with_counts(|counts| {
+            counts
+                .get(&(route.to_owned(), status))
+                .copied()
+                .unwrap_or(0)
+        })
+    }
+
+    /// Returns a snapshot of all counts, ordered by route template and status code.
+    pub fn counts(&self) -> BTreeMap<(String, StatusCode), u64> {
+        self.with_counts(|counts| counts.clone())
+    }
+
+    /// Returns the number of errors, for all routes, whose status code satisfies `predicate`,
+    /// e.g. `StatusCode::is_server_error`.
+    pub fn total<F>(&self, predicate: F) -> u64
+    where
+        F: Fn(&StatusCode) -> bool,
+    {
+        self.with_counts(|counts| {
+            counts
+                .iter()
+                .filter(|((_, status), _)| predicate(status))
+                .map(|(_, count)| count)
+                .sum()
+        })
+    }
+
+    /// Counts `err`, raised for the route recorded in `state`.
+    pub fn record(&self, state: &State, err: &HandlerError) {
+        let route = MatchedRoute::try_borrow_from(state)
+            .map(MatchedRoute::template)
+            .unwrap_or(UNMATCHED_ROUTE);
+
+        self.with_counts(|counts| {
+            *counts.entry((route.to_owned(), err.status())).or_insert(0) += 1;
+        })
+    }
+
+    fn with_counts<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&mut BTreeMap<(String, StatusCode), u64>) -> T,
+    {
+        // the counts remain consistent when a panic occurs while they are locked
+        let mut counts = match self.counts.lock() {
+            Ok(counts) => counts,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        f(&mut counts)
+    }
+}
+
+impl StateData for Metrics {}
+
+/// Middleware which counts the `HandlerError` values passing through it in a `Metrics` value,
+/// keyed by the template of the matched route and the status code of the error.
+///
+/// Errors are counted as they are returned by the handler or subsequent middleware, so this
+/// middleware should be added to the pipeline before any middleware whose errors should be
+/// counted too.
+#[derive(Clone, Debug, Default)]
+pub struct ErrorMetricsMiddleware {
+    metrics: Metrics,
+}
+
+impl ErrorMetricsMiddleware {
+    /// Creates an `ErrorMetricsMiddleware` counting into a new `Metrics` value.
+    pub fn new() -> ErrorMetricsMiddleware {
+        ErrorMetricsMiddleware::default()
+    }
+
+    /// Creates an `ErrorMetricsMiddleware` counting into the given `Metrics` value, e.g. one which
+    /// is shared by several pipelines or exported outside of a request.
+    pub fn with_metrics(metrics: Metrics) -> ErrorMetricsMiddleware {
+        ErrorMetricsMiddleware { metrics }
+    }
+
+    /// Returns the `Metrics` value counted into.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+}
+
+impl Middleware for ErrorMetricsMiddleware {
+    fn call<Chain>(self, mut state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>> + Send + 'static,
+    {
+        state.put(self.metrics.clone());
+
+        chain(state)
+            .or_else(move |(state, err)| {
+                self.metrics.record(&state, &err);
+                future::err((state, err))
+            })
+            .boxed()
+    }
+}
+
+impl NewMiddleware for ErrorMetricsMiddleware {
+    type Instance = Self;
+
+    fn new_middleware(&self) -> anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::handler::HandlerResult;
+    use crate::pipeline::new_pipeline;
+    use crate::pipeline::single::single_pipeline;
+    use crate::router::builder::*;
+    use crate::test::TestServer;
+
+    async fn fail(state: State) -> HandlerResult {
+        Err((state, HandlerError::conflict(anyhow::anyhow!("conflict"))))
+    }
+
+    async fn crash(state: State) -> HandlerResult {
+        Err((state, anyhow::anyhow!("crash").into()))
+    }
+
+    #[test]
+    fn counts_errors_by_route_and_status() {
+        let middleware = ErrorMetricsMiddleware::new();
+        let metrics = middleware.metrics().clone();
+
+        let (chain, pipelines) = single_pipeline(new_pipeline().add(middleware).build());
+        let router = build_router(chain, pipelines, |route| {
+            route.get("/").to(|state| (state, "index"));
+            route.post("/widgets/:id").to_async(fail);
+            route.scope("/api", |route| {
+                route.get("/crash").to_async(crash);
+            });
+        });
+
+        let test_server = TestServer::new(router).unwrap();
+        let client = test_server.client();
+        client.get("http://localhost/").perform().unwrap();
+        for id in 1..=2 {
+            let uri = format!("http://localhost/widgets/{}", id);
+            client.post(uri, "", mime::TEXT_PLAIN).perform().unwrap();
+        }
+        client.get("http://localhost/api/crash").perform().unwrap();
+
+        assert_eq!(metrics.count("/widgets/:id", StatusCode::CONFLICT), 2);
+        assert_eq!(
+            metrics.count("/api/crash", StatusCode::INTERNAL_SERVER_ERROR),
+            1
+        );
+        assert_eq!(metrics.count("/", StatusCode::OK), 0);
+        assert_eq!(metrics.total(StatusCode::is_client_error), 2);
+        assert_eq!(metrics.total(StatusCode::is_server_error), 1);
+        assert_eq!(metrics.counts().len(), 2);
+    }
+}
@@ -0,0 +1,519 @@
+//! Defines a middleware which records metrics of all requests, and a handler which serves them in
+//! the Prometheus text exposition format.
+//!
+//! The following metrics are recorded, labeled by the template of the matched route (see
+//! `gotham::router::MatchedRoute`) and the method of the request, and all but the number of
+//! requests in flight also by the status code of the response:
+//!
+//! - `http_requests_total`, a counter of the requests;
+//! - `http_request_duration_seconds`, a histogram of the latency of the requests;
+//! - `http_requests_in_flight`, a gauge of the requests which are being handled;
+//! - `http_response_size_bytes`, a histogram of the size of the response bodies, where known.
+//!
+//! ```rust
+//! # extern crate gotham;
+//! #
+//! # use gotham::middleware::metrics::prometheus::{MetricsHandler, PrometheusMiddleware};
+//! # use gotham::pipeline::new_pipeline;
+//! # use gotham::pipeline::single::single_pipeline;
+//! # use gotham::router::builder::*;
+//! # use gotham::test::TestServer;
+//! #
+//! # fn main() {
+//! let middleware = PrometheusMiddleware::new();
+//! let metrics = middleware.metrics().clone();
+//!
+//! let (chain, pipelines) = single_pipeline(new_pipeline().add(middleware).build());
+//! let router = build_router(chain, pipelines, |route| {
+//!     route.get("/users/:id").to(|state| (state, "user"));
+//!     route.get("/metrics").to_new_handler(MetricsHandler::new(metrics));
+//! });
+//!
+//! let test_server = TestServer::new(router).unwrap();
+//! let client = test_server.client();
+//! client.get("http://localhost/users/1").perform().unwrap();
+//!
+//! let response = client.get("http://localhost/metrics").perform().unwrap();
+//! let body = response.read_utf8_body().unwrap();
+//! assert!(body.contains(r#"http_requests_total{route="/users/:id",method="GET",status="200"} 1"#));
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use futures::prelude::*;
+use hyper::body::HttpBody;
+use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::{Body, Method, Response, StatusCode};
+
+use super::UNMATCHED_ROUTE;
+use crate::clock::{Clock, SharedClock};
+use crate::handler::{Handler, HandlerFuture, NewHandler};
+use crate::helpers::http::response::create_response;
+use crate::helpers::timing::{Timer, Timing};
+use crate::middleware::{Middleware, NewMiddleware};
+use crate::router::MatchedRoute;
+use crate::state::{FromState, State, StateData};
+
+/// The content type of the Prometheus text exposition format.
+pub const CONTENT_TYPE_TEXT: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// The default buckets of the latency histogram, in seconds.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The default buckets of the response size histogram, in bytes.
+const SIZE_BUCKETS: &[f64] = &[
+    100.0,
+    1000.0,
+    10_000.0,
+    100_000.0,
+    1_000_000.0,
+    10_000_000.0,
+];
+
+#[derive(Clone, Debug)]
+struct Histogram {
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(buckets: &[f64]) -> Histogram {
+        Histogram {
+            counts: vec![0; buckets.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, buckets: &[f64], value: f64) {
+        if let Some(i) = buckets.iter().position(|bound| value <= *bound) {
+            self.counts[i] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str, buckets: &[f64]) {
+        let mut cumulative = 0;
+        for (bound, count) in buckets.iter().zip(&self.counts) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{},le=\"{}\"}} {}",
+                name, labels, bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{},le=\"+Inf\"}} {}",
+            name, labels, self.count
+        );
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+/// The metrics of the requests with the same route, method and status.
+#[derive(Clone, Debug)]
+struct Series {
+    requests: u64,
+    latency: Histogram,
+    size: Histogram,
+}
+
+#[derive(Debug, Default)]
+struct Recorded {
+    series: BTreeMap<(String, String, u16), Series>,
+    in_flight: BTreeMap<(String, String), i64>,
+}
+
+/// The metrics recorded by a `PrometheusMiddleware`.
+///
+/// `RequestMetrics` is a cheaply cloneable handle to metrics shared with the middleware, and is
+/// put into `State` by the middleware.
+#[derive(Clone, Debug)]
+pub struct RequestMetrics {
+    recorded: Arc<Mutex<Recorded>>,
+    latency_buckets: Arc<[f64]>,
+}
+
+impl RequestMetrics {
+    /// Creates a `RequestMetrics` value without any recorded requests, with the default latency
+    /// buckets from 5 milliseconds to 10 seconds.
+    pub fn new() -> RequestMetrics {
+        RequestMetrics::with_latency_buckets(LATENCY_BUCKETS.to_vec())
+    }
+
+    /// Creates a `RequestMetrics` value recording the latency of requests in `buckets`, which are
+    /// upper bounds in seconds.
+    pub fn with_latency_buckets(mut buckets: Vec<f64>) -> RequestMetrics {
+        buckets.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        RequestMetrics {
+            recorded: Arc::default(),
+            latency_buckets: Arc::from(buckets),
+        }
+    }
+
+    /// Returns the number of requests recorded for `route`, `method` and `status`.
+    pub fn requests(&self, route: &str, method: &Method, status: StatusCode) -> u64 {
+        let key = (route.to_owned(), method.to_string(), status.as_u16());
+        self.with_recorded(|recorded| recorded.series.get(&key).map_or(0, |s| s.requests))
+    }
+
+    /// Returns the number of requests for `route` and `method` which are being handled.
+    pub fn in_flight(&self, route: &str, method: &Method) -> i64 {
+        let key = (route.to_owned(), method.to_string());
+        self.with_recorded(|recorded| recorded.in_flight.get(&key).copied().unwrap_or(0))
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        self.with_recorded(|recorded| {
+            let mut out = String::new();
+
+            out.push_str("# HELP http_requests_total The number of HTTP requests.\n");
+            out.push_str("# TYPE http_requests_total counter\n");
+            for ((route, method, status), series) in &recorded.series {
+                let _ = writeln!(
+                    out,
+                    "http_requests_total{{{}}} {}",
+                    labels(route, method, Some(*status)),
+                    series.requests
+                );
+            }
+
+            out.push_str(
+                "# HELP http_request_duration_seconds The latency of HTTP requests in seconds.\n",
+            );
+            out.push_str("# TYPE http_request_duration_seconds histogram\n");
+            for ((route, method, status), series) in &recorded.series {
+                series.latency.render(
+                    &mut out,
+                    "http_request_duration_seconds",
+                    &labels(route, method, Some(*status)),
+                    &self.latency_buckets,
+                );
+            }
+
+            out.push_str(
+                "# HELP http_requests_in_flight The number of HTTP requests being handled.\n",
+            );
+            out.push_str("# TYPE http_requests_in_flight gauge\n");
+            for ((route, method), count) in &recorded.in_flight {
+                let _ = writeln!(
+                    out,
+                    "http_requests_in_flight{{{}}} {}",
+                    labels(route, method, None),
+                    count
+                );
+            }
+
+            out.push_str(
+                "# HELP http_response_size_bytes The size of HTTP response bodies in bytes.\n",
+            );
+            out.push_str("# TYPE http_response_size_bytes histogram\n");
+            for ((route, method, status), series) in &recorded.series {
+                series.size.render(
+                    &mut out,
+                    "http_response_size_bytes",
+                    &labels(route, method, Some(*status)),
+                    SIZE_BUCKETS,
+                );
+            }
+
+            out
+        })
+    }
+
+    fn start(&self, route: &str, method: &str) -> InFlight {
+        let key = (route.to_owned(), method.to_owned());
+        self.with_recorded(|recorded| *recorded.in_flight.entry(key.clone()).or_insert(0) += 1);
+        InFlight {
+            metrics: self.clone(),
+            key,
+        }
+    }
+
+    fn record(&self, in_flight: &InFlight, status: StatusCode, timing: Timing, size: Option<u64>) {
+        let (route, method) = &in_flight.key;
+        let key = (route.clone(), method.clone(), status.as_u16());
+        let latency_buckets = &self.latency_buckets;
+        self.with_recorded(|recorded| {
+            let series = recorded.series.entry(key).or_insert_with(|| Series {
+                requests: 0,
+                latency: Histogram::new(latency_buckets),
+                size: Histogram::new(SIZE_BUCKETS),
+            });
+            series.requests += 1;
+            if let Timing::Microseconds(us) = timing {
+                series
+                    .latency
+                    .observe(latency_buckets, us as f64 / 1_000_000.0);
+            }
+            if let Some(size) = size {
+                series.size.observe(SIZE_BUCKETS, size as f64);
+            }
+        })
+    }
+
+    fn with_recorded<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&mut Recorded) -> T,
+    {
+        // the metrics remain consistent when a panic occurs while they are locked
+        let mut recorded = match self.recorded.lock() {
+            Ok(recorded) => recorded,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        f(&mut recorded)
+    }
+}
+
+impl Default for RequestMetrics {
+    fn default() -> Self {
+        RequestMetrics::new()
+    }
+}
+
+impl StateData for RequestMetrics {}
+
+/// Counts a request as in flight until it is dropped, which happens even if the request is
+/// aborted, e.g. because the client disconnected.
+struct InFlight {
+    metrics: RequestMetrics,
+    key: (String, String),
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let key = &self.key;
+        self.metrics.with_recorded(|recorded| {
+            if let Some(count) = recorded.in_flight.get_mut(key) {
+                *count -= 1;
+            }
+        })
+    }
+}
+
+/// Formats the labels of a series, escaping their values.
+fn labels(route: &str, method: &str, status: Option<u16>) -> String {
+    let escape = |value: &str| {
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    };
+    let mut labels = format!("route=\"{}\",method=\"{}\"", escape(route), escape(method));
+    if let Some(status) = status {
+        let _ = write!(labels, ",status=\"{}\"", status);
+    }
+    labels
+}
+
+/// Returns the size of the body of `response`, if it is known before it is sent.
+fn response_size(response: &Response<Body>) -> Option<u64> {
+    HttpBody::size_hint(response.body()).exact().or_else(|| {
+        response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+    })
+}
+
+/// Middleware which records the metrics of the requests passing through it in a
+/// `RequestMetrics` value, see the module documentation.
+///
+/// Requests are recorded as they are returned by the handler or subsequent middleware, so this
+/// middleware should be added to the pipeline first.
+#[derive(Clone, Debug, Default)]
+pub struct PrometheusMiddleware {
+    metrics: RequestMetrics,
+}
+
+impl PrometheusMiddleware {
+    /// Creates a `PrometheusMiddleware` recording into a new `RequestMetrics` value.
+    pub fn new() -> PrometheusMiddleware {
+        PrometheusMiddleware::default()
+    }
+
+    /// Creates a `PrometheusMiddleware` recording into the given `RequestMetrics` value, e.g.
+    /// one which is shared by several pipelines.
+    pub fn with_metrics(metrics: RequestMetrics) -> PrometheusMiddleware {
+        PrometheusMiddleware { metrics }
+    }
+
+    /// Returns the `RequestMetrics` value recorded into.
+    pub fn metrics(&self) -> &RequestMetrics {
+        &self.metrics
+    }
+}
+
+impl Middleware for PrometheusMiddleware {
+    fn call<Chain>(self, mut state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>> + Send + 'static,
+    {
+        let route = MatchedRoute::try_borrow_from(&state)
+            .map(MatchedRoute::template)
+            .unwrap_or(UNMATCHED_ROUTE);
+        let in_flight = self
+            .metrics
+            .start(route, Method::borrow_from(&state).as_str());
+        let clock = SharedClock::from_state(&state);
+        let timer = Timer::started_at(clock.now());
+        state.put(self.metrics.clone());
+
+        let f = chain(state);
+        async move {
+            let result = f.await;
+            let timing = timer.elapsed_until(clock.now());
+            match &result {
+                Ok((_, response)) => {
+                    let size = response_size(response);
+                    self.metrics
+                        .record(&in_flight, response.status(), timing, size)
+                }
+                Err((_, err)) => self.metrics.record(&in_flight, err.status(), timing, None),
+            }
+            result
+        }
+        .boxed()
+    }
+}
+
+impl NewMiddleware for PrometheusMiddleware {
+    type Instance = Self;
+
+    fn new_middleware(&self) -> anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+/// A handler serving the metrics of a `RequestMetrics` value in the Prometheus text exposition
+/// format, to be scraped by Prometheus.
+#[derive(Clone, Debug)]
+pub struct MetricsHandler {
+    metrics: RequestMetrics,
+}
+
+impl MetricsHandler {
+    /// Creates the handler serving `metrics`.
+    pub fn new(metrics: RequestMetrics) -> MetricsHandler {
+        MetricsHandler { metrics }
+    }
+}
+
+impl NewHandler for MetricsHandler {
+    type Instance = Self;
+
+    fn new_handler(&self) -> anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl Handler for MetricsHandler {
+    fn handle(self, state: State) -> Pin<Box<HandlerFuture>> {
+        let mime = CONTENT_TYPE_TEXT.parse::<mime::Mime>().unwrap();
+        let mut response = create_response(&state, StatusCode::OK, mime, self.metrics.render());
+        // `Mime` normalizes the parameters, which Prometheus doesn't require
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, CONTENT_TYPE_TEXT.parse().unwrap());
+        future::ok((state, response)).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::handler::{HandlerError, HandlerResult};
+    use crate::pipeline::new_pipeline;
+    use crate::pipeline::single::single_pipeline;
+    use crate::router::builder::*;
+    use crate::test::TestServer;
+
+    async fn fail(state: State) -> HandlerResult {
+        Err((state, HandlerError::conflict(anyhow::anyhow!("conflict"))))
+    }
+
+    #[test]
+    fn records_and_renders_request_metrics() {
+        let middleware =
+            PrometheusMiddleware::with_metrics(RequestMetrics::with_latency_buckets(vec![
+                1.0, 0.1,
+            ]));
+        let metrics = middleware.metrics().clone();
+
+        let (chain, pipelines) = single_pipeline(new_pipeline().add(middleware).build());
+        let router = build_router(chain, pipelines, |route| {
+            route.get("/users/:id").to(|state| (state, "user"));
+            route.post("/users/:id").to_async(fail);
+            route
+                .get("/metrics")
+                .to_new_handler(MetricsHandler::new(metrics.clone()));
+        });
+
+        let test_server = TestServer::new(router).unwrap();
+        let client = test_server.client();
+        for id in 1..=2 {
+            let uri = format!("http://localhost/users/{}", id);
+            client.get(uri.clone()).perform().unwrap();
+            client.post(uri, "", mime::TEXT_PLAIN).perform().unwrap();
+        }
+
+        assert_eq!(
+            metrics.requests("/users/:id", &Method::GET, StatusCode::OK),
+            2
+        );
+        assert_eq!(
+            metrics.requests("/users/:id", &Method::POST, StatusCode::CONFLICT),
+            2
+        );
+        assert_eq!(metrics.in_flight("/users/:id", &Method::GET), 0);
+
+        let response = client.get("http://localhost/metrics").perform().unwrap();
+        assert_eq!(response.headers()[CONTENT_TYPE], CONTENT_TYPE_TEXT);
+        let body = response.read_utf8_body().unwrap();
+        let labels = r#"route="/users/:id",method="GET",status="200""#;
+        for line in &[
+            format!("http_requests_total{{{}}} 2", labels),
+            format!(
+                "http_request_duration_seconds_bucket{{{},le=\"0.1\"}} 2",
+                labels
+            ),
+            format!(
+                "http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} 2",
+                labels
+            ),
+            format!("http_request_duration_seconds_count{{{}}} 2", labels),
+            format!("http_response_size_bytes_bucket{{{},le=\"100\"}} 2", labels),
+            format!("http_response_size_bytes_sum{{{}}} 8", labels),
+            r#"http_requests_in_flight{route="/metrics",method="GET"} 1"#.to_owned(),
+            "# TYPE http_request_duration_seconds histogram".to_owned(),
+        ] {
+            assert!(
+                body.lines().any(|l| l == line),
+                "missing {}\n{}",
+                line,
+                body
+            );
+        }
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        assert_eq!(
+            labels("/a\"b\\", "GET", Some(200)),
+            r#"route="/a\"b\\",method="GET",status="200""#
+        );
+    }
+}
@@ -8,12 +8,28 @@ use crate::handler::HandlerFuture;
 use crate::state::State;
 
 pub mod chain;
+pub mod concurrency;
+pub mod contract;
 pub mod cookie;
+pub mod cors;
+pub mod crash_summary;
+#[cfg(feature = "decompression")]
+pub mod decompression;
+pub mod error_status;
+pub mod feature_flags;
 pub mod logger;
+pub mod metrics;
+pub mod precondition;
+pub mod replay;
+pub mod request_id;
 pub mod security;
 pub mod session;
+pub mod sharding;
 pub mod state;
+pub mod template_context;
 pub mod timer;
+#[cfg(feature = "tracing")]
+pub mod tracing;
 
 /// `Middleware` has the opportunity to provide additional behaviour to the `Request` / `Response`
 /// interaction. For example:
@@ -384,3 +400,31 @@ pub trait NewMiddleware: Sync + RefUnwindSafe {
     /// Create and return a new `Middleware` value.
     fn new_middleware(&self) -> anyhow::Result<Self::Instance>;
 }
+
+/// An optional `Middleware`, which passes requests straight through to the next middleware when
+/// it is `None`. See `PipelineBuilder::add_when`.
+impl<M> Middleware for Option<M>
+where
+    M: Middleware,
+{
+    fn call<Chain>(self, state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>> + Send + 'static,
+    {
+        match self {
+            Some(middleware) => middleware.call(state, chain),
+            None => chain(state),
+        }
+    }
+}
+
+impl<M> NewMiddleware for Option<M>
+where
+    M: NewMiddleware,
+{
+    type Instance = Option<M::Instance>;
+
+    fn new_middleware(&self) -> anyhow::Result<Self::Instance> {
+        self.as_ref().map(NewMiddleware::new_middleware).transpose()
+    }
+}
@@ -0,0 +1,351 @@
+//! Optimistic concurrency control for REST resources, based on entity tags.
+//!
+//! The `RequireIfMatch` middleware acts as a guard for state changing requests (`PUT`, `PATCH` and
+//! `DELETE` by default). Requests without an `If-Match` header are rejected with
+//! `428 Precondition Required`, as described in RFC 6585, and requests with a valid header have
+//! the `ExpectedVersion` stored in `State`.
+//!
+//! The handler compares the `ExpectedVersion` against the current version of the resource, which
+//! only the application knows, via `ExpectedVersion::check`. A mismatch produces a
+//! `412 Precondition Failed` error.
+//!
+//! The `version_etag` and `content_etag` functions generate the entity tags which are sent to the
+//! client in the `ETag` header, and later compared against the `If-Match` header, or against the
+//! `If-None-Match` header of conditional `GET` requests with `if_none_match`.
+use std::fmt::Display;
+use std::pin::Pin;
+
+use futures::prelude::*;
+use hyper::header::{HeaderMap, IF_MATCH, IF_NONE_MATCH};
+use hyper::{Method, StatusCode};
+use log::trace;
+
+use crate::handler::{HandlerError, HandlerFuture};
+use crate::helpers::http::response::create_empty_response;
+use crate::middleware::{Middleware, NewMiddleware};
+use crate::state::{request_id, FromState, State, StateData};
+
+/// Creates a strong entity tag from a resource version, such as a revision counter or the
+/// timestamp of the last modification.
+///
+/// ```rust
+/// # extern crate gotham;
+/// # use gotham::middleware::precondition::version_etag;
+/// # fn main() {
+/// assert_eq!(version_etag(42), "\"42\"");
+/// # }
+/// ```
+pub fn version_etag<V>(version: V) -> String
+where
+    V: Display,
+{
+    format!("\"{}\"", version)
+}
+
+/// Creates a strong entity tag from the serialized representation of a resource.
+///
+/// The tag is derived from a 64-bit FNV-1a hash of the content, which is stable across processes
+/// and releases, so it can be shared between multiple application servers.
+pub fn content_etag(content: &[u8]) -> String {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let hash = content.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME)
+    });
+
+    format!("\"{:016x}\"", hash)
+}
+
+/// Evaluates the `If-None-Match` header against the current entity tag of a resource. Returns
+/// `None` if the header is absent, and otherwise whether the client's copy of the resource is
+/// current, i.e. whether a `GET` request can be answered with `304 Not Modified`.
+///
+/// As required for `If-None-Match`, the weak comparison function is used, so `W/"1"` matches
+/// `"1"`.
+pub fn if_none_match(headers: &HeaderMap, current_etag: &str) -> Option<bool> {
+    if !headers.contains_key(IF_NONE_MATCH) {
+        return None;
+    }
+
+    let opaque = |tag: &str| tag.strip_prefix("W/").unwrap_or(tag).to_owned();
+    let current = opaque(current_etag);
+    let matched = headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|tag| tag == "*" || opaque(tag) == current);
+    Some(matched)
+}
+
+/// The validated contents of the `If-Match` header, stored in `State` by `RequireIfMatch`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExpectedVersion {
+    /// The client sent `If-Match: *`, which matches any current version of the resource.
+    Any,
+    /// The entity tags sent by the client, including their surrounding quotes.
+    Tags(Vec<String>),
+}
+
+impl StateData for ExpectedVersion {}
+
+impl ExpectedVersion {
+    /// Parses the value(s) of the `If-Match` header, returning `None` when the header is absent
+    /// or malformed.
+    pub fn from_headers(headers: &HeaderMap) -> Option<ExpectedVersion> {
+        let mut tags = Vec::new();
+
+        for value in headers.get_all(IF_MATCH) {
+            for tag in value.to_str().ok()?.split(',').map(str::trim) {
+                if tag == "*" {
+                    return Some(ExpectedVersion::Any);
+                }
+
+                let opaque = tag.strip_prefix("W/").unwrap_or(tag);
+                if opaque.len() < 2 || !opaque.starts_with('"') || !opaque.ends_with('"') {
+                    return None;
+                }
+
+                tags.push(tag.to_owned());
+            }
+        }
+
+        if tags.is_empty() {
+            None
+        } else {
+            Some(ExpectedVersion::Tags(tags))
+        }
+    }
+
+    /// Returns `true` when the current entity tag of the resource satisfies the precondition.
+    ///
+    /// As required for `If-Match`, the strong comparison function is used, so weak entity tags
+    /// never match.
+    pub fn matches(&self, current_etag: &str) -> bool {
+        match self {
+            ExpectedVersion::Any => true,
+            ExpectedVersion::Tags(tags) => {
+                !current_etag.starts_with("W/")
+                    && tags
+                        .iter()
+                        .any(|tag| !tag.starts_with("W/") && tag == current_etag)
+            }
+        }
+    }
+
+    /// Checks the precondition against the current entity tag of the resource, returning a
+    /// `HandlerError` with the status `412 Precondition Failed` when it is not satisfied.
+    pub fn check(&self, current_etag: &str) -> Result<(), HandlerError> {
+        if self.matches(current_etag) {
+            Ok(())
+        } else {
+            Err(HandlerError::from(anyhow::anyhow!(
+                "If-Match precondition failed, current version is {}",
+                current_etag
+            ))
+            .with_status(StatusCode::PRECONDITION_FAILED))
+        }
+    }
+}
+
+/// Middleware which requires an `If-Match` header on state changing requests.
+///
+/// See the module documentation for details.
+#[derive(Clone)]
+pub struct RequireIfMatch {
+    methods: Vec<Method>,
+}
+
+impl Default for RequireIfMatch {
+    fn default() -> RequireIfMatch {
+        RequireIfMatch {
+            methods: vec![Method::PUT, Method::PATCH, Method::DELETE],
+        }
+    }
+}
+
+impl RequireIfMatch {
+    /// Creates a `RequireIfMatch` guarding `PUT`, `PATCH` and `DELETE` requests.
+    pub fn new() -> RequireIfMatch {
+        RequireIfMatch::default()
+    }
+
+    /// Replaces the set of request methods which require an `If-Match` header.
+    pub fn with_methods(self, methods: Vec<Method>) -> RequireIfMatch {
+        RequireIfMatch { methods }
+    }
+}
+
+/// `Middleware` trait implementation.
+impl Middleware for RequireIfMatch {
+    /// Validates the `If-Match` header and stores the `ExpectedVersion` in `State`.
+    fn call<Chain>(self, mut state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>>,
+    {
+        if !self.methods.contains(Method::borrow_from(&state)) {
+            return chain(state);
+        }
+
+        let headers = HeaderMap::borrow_from(&state);
+        let status = match ExpectedVersion::from_headers(headers) {
+            Some(expected) => {
+                state.put(expected);
+                return chain(state);
+            }
+            None if headers.contains_key(IF_MATCH) => StatusCode::BAD_REQUEST,
+            None => StatusCode::PRECONDITION_REQUIRED,
+        };
+
+        trace!(
+            "[{}] rejecting request without a valid If-Match header",
+            request_id(&state)
+        );
+        let response = create_empty_response(&state, status);
+        future::ok((state, response)).boxed()
+    }
+}
+
+/// `NewMiddleware` trait implementation.
+impl NewMiddleware for RequireIfMatch {
+    type Instance = Self;
+
+    /// Clones the current middleware to a new instance.
+    fn new_middleware(&self) -> anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+    use hyper::{Body, Response};
+
+    use crate::pipeline::new_pipeline;
+    use crate::pipeline::single::single_pipeline;
+    use crate::router::builder::*;
+    use crate::test::TestServer;
+
+    #[test]
+    fn compares_if_none_match_weakly() {
+        let headers = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(IF_NONE_MATCH, HeaderValue::from_static(value));
+            headers
+        };
+
+        assert_eq!(if_none_match(&HeaderMap::new(), "\"1\""), None);
+        assert_eq!(if_none_match(&headers("\"1\""), "\"1\""), Some(true));
+        assert_eq!(
+            if_none_match(&headers("\"0\", W/\"1\""), "\"1\""),
+            Some(true)
+        );
+        assert_eq!(if_none_match(&headers("\"1\""), "W/\"1\""), Some(true));
+        assert_eq!(if_none_match(&headers("*"), "\"1\""), Some(true));
+        assert_eq!(if_none_match(&headers("\"2\""), "\"1\""), Some(false));
+    }
+
+    fn update(state: State) -> (State, Response<Body>) {
+        let status = match ExpectedVersion::borrow_from(&state).check(&version_etag(2)) {
+            Ok(()) => StatusCode::NO_CONTENT,
+            Err(e) => e.status(),
+        };
+        let response = create_empty_response(&state, status);
+        (state, response)
+    }
+
+    fn test_server() -> TestServer {
+        let (chain, pipelines) = single_pipeline(new_pipeline().add(RequireIfMatch::new()).build());
+        TestServer::new(build_router(chain, pipelines, |route| {
+            route.put("/").to(update);
+            route
+                .get("/")
+                .to(|state| (state, Response::new(Body::empty())));
+        }))
+        .unwrap()
+    }
+
+    fn put_with_if_match(test_server: &TestServer, value: Option<&'static str>) -> StatusCode {
+        let client = test_server.client();
+        let mut request = client.put("http://localhost/", "", mime::TEXT_PLAIN);
+        if let Some(value) = value {
+            request = request.with_header(IF_MATCH, HeaderValue::from_static(value));
+        }
+        request.perform().unwrap().status()
+    }
+
+    #[test]
+    fn parses_if_match_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(ExpectedVersion::from_headers(&headers), None);
+
+        headers.insert(IF_MATCH, HeaderValue::from_static("\"a\", W/\"b\""));
+        assert_eq!(
+            ExpectedVersion::from_headers(&headers),
+            Some(ExpectedVersion::Tags(vec![
+                "\"a\"".to_owned(),
+                "W/\"b\"".to_owned()
+            ]))
+        );
+
+        headers.insert(IF_MATCH, HeaderValue::from_static("*"));
+        assert_eq!(
+            ExpectedVersion::from_headers(&headers),
+            Some(ExpectedVersion::Any)
+        );
+
+        headers.insert(IF_MATCH, HeaderValue::from_static("unquoted"));
+        assert_eq!(ExpectedVersion::from_headers(&headers), None);
+    }
+
+    #[test]
+    fn uses_strong_comparison() {
+        let expected = ExpectedVersion::Tags(vec!["\"a\"".to_owned(), "W/\"b\"".to_owned()]);
+        assert!(expected.matches("\"a\""));
+        assert!(!expected.matches("\"b\""));
+        assert!(!expected.matches("W/\"b\""));
+        assert!(ExpectedVersion::Any.matches("W/\"b\""));
+    }
+
+    #[test]
+    fn content_etag_is_stable() {
+        assert_eq!(content_etag(b""), "\"cbf29ce484222325\"");
+        assert_eq!(content_etag(b"a"), "\"af63dc4c8601ec8c\"");
+    }
+
+    #[test]
+    fn guards_state_changing_requests() {
+        let test_server = test_server();
+
+        assert_eq!(
+            put_with_if_match(&test_server, None),
+            StatusCode::PRECONDITION_REQUIRED
+        );
+        assert_eq!(
+            put_with_if_match(&test_server, Some("nonsense")),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            put_with_if_match(&test_server, Some("\"1\"")),
+            StatusCode::PRECONDITION_FAILED
+        );
+        assert_eq!(
+            put_with_if_match(&test_server, Some("\"1\", \"2\"")),
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            put_with_if_match(&test_server, Some("*")),
+            StatusCode::NO_CONTENT
+        );
+
+        let response = test_server
+            .client()
+            .get("http://localhost/")
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,614 @@
+//! Defines a middleware which captures requests, so that production bugs can be replayed locally.
+//!
+//! The `ReplayCaptureMiddleware` records the method, URI, headers and body of every request
+//! matching its filter, along with the resulting status code, into a `ReplayBuffer`. The buffer
+//! keeps the most recent requests and can also append them to a file. It implements `Handler`,
+//! so it can be routed to as an admin endpoint to download the captured requests as
+//! [HAR](https://w3c.github.io/web-performance/specs/HAR/Overview.html), or as `curl` commands
+//! with `?format=curl`.
+//!
+//! Capturing is opt-in and should be limited to the requests of interest: the start of the body of
+//! a captured request, up to the size limit, is buffered in memory before the request is passed on
+//! to the handler, which receives the full body. Secrets in well-known
+//! headers and query parameters are redacted, but bodies are recorded as they are, up to the size
+//! limit.
+//!
+//! ```rust
+//! # extern crate gotham;
+//! #
+//! # use gotham::middleware::replay::{ReplayBuffer, ReplayCaptureMiddleware};
+//! # use gotham::pipeline::new_pipeline;
+//! # use gotham::pipeline::single::single_pipeline;
+//! # use gotham::router::Router;
+//! # use gotham::router::builder::*;
+//! # use gotham::hyper::header::HeaderName;
+//! # use gotham::hyper::Uri;
+//! # use gotham::state::{FromState, State};
+//! #
+//! # fn checkout(state: State) -> (State, &'static str) { (state, "ok") }
+//! #
+//! fn router() -> Router {
+//!     let buffer = ReplayBuffer::new(100);
+//!     let capture = ReplayCaptureMiddleware::new(buffer.clone())
+//!         .with_filter(|state| {
+//!             Uri::borrow_from(state).path().starts_with("/checkout")
+//!         })
+//!         .with_max_body_size(16 * 1024)
+//!         .with_redacted_header(HeaderName::from_static("x-session-token"));
+//!
+//!     let (chain, pipelines) = single_pipeline(new_pipeline().add(capture).build());
+//!
+//!     build_router(chain, pipelines, |route| {
+//!         route.post("/checkout").to(checkout);
+//!         // this route should be protected, as the captured bodies may contain personal data
+//!         route.get("/admin/replay").to_new_handler(buffer);
+//!     })
+//! }
+//! #
+//! # fn main() { let _ = router(); }
+//! ```
+
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::panic::RefUnwindSafe;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::prelude::*;
+use hyper::header::{
+    HeaderMap, HeaderName, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, HOST,
+    PROXY_AUTHORIZATION,
+};
+use hyper::{Body, Method, StatusCode, Uri, Version};
+use log::error;
+use serde_json::{json, Value};
+
+use crate::clock;
+use crate::handler::{Handler, HandlerError, HandlerFuture, NewHandler};
+use crate::helpers::http::body::read_prefix;
+use crate::helpers::http::har;
+use crate::helpers::http::response::create_response;
+use crate::middleware::{Middleware, NewMiddleware};
+use crate::state::{FromState, State};
+
+/// The value which replaces redacted header and query parameter values.
+pub const REDACTED: &str = "[REDACTED]";
+
+const DEFAULT_MAX_BODY_SIZE: usize = 64 * 1024;
+
+const DEFAULT_REDACTED_QUERY_PARAMS: &[&str] = &[
+    "access_token",
+    "api_key",
+    "apikey",
+    "password",
+    "secret",
+    "token",
+];
+
+/// A request captured by a `ReplayCaptureMiddleware`.
+#[derive(Clone, Debug)]
+pub struct CapturedRequest {
+    started: DateTime<Utc>,
+    method: Method,
+    url: String,
+    version: Version,
+    headers: Vec<(String, String)>,
+    body: Bytes,
+    // the size of the full body, which is unknown if it was truncated without a `Content-Length`
+    body_size: Option<usize>,
+    truncated: bool,
+    status: StatusCode,
+}
+
+impl CapturedRequest {
+    /// Returns the request method.
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    /// Returns the absolute URL of the request, with redacted query parameters.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Returns the request headers, with redacted values.
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// Returns the captured request body, which is truncated if it exceeded the size limit.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Returns `true` if the body exceeded the size limit and was truncated.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Returns the status code of the response, or of the `HandlerError` raised for the request.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Renders the request as a `curl` command.
+    ///
+    /// The `Content-Length` header is left to `curl`, as it doesn't match a truncated body.
+    pub fn to_curl(&self) -> String {
+        let mut command = format!("curl -X {} {}", self.method, shell_quote(&self.url));
+        for (name, value) in &self.headers {
+            if name.as_str() == CONTENT_LENGTH.as_str() {
+                continue;
+            }
+            command.push_str(" \\\n  -H ");
+            command.push_str(&shell_quote(&format!("{}: {}", name, value)));
+        }
+        if !self.body.is_empty() {
+            command.push_str(" \\\n  --data-binary ");
+            command.push_str(&shell_quote(&String::from_utf8_lossy(&self.body)));
+        }
+        command
+    }
+
+    /// Renders the request as an entry of a HAR log.
+    pub fn to_har_entry(&self) -> Value {
+        let headers = har::records(
+            self.headers
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        );
+
+        let mut request = json!({
+            "method": self.method.as_str(),
+            "url": self.url,
+            "httpVersion": format!("{:?}", self.version),
+            "cookies": [],
+            "headers": headers,
+            "queryString": har::query_string(&self.url),
+            "headersSize": -1,
+            "bodySize": self.body_size.map_or(-1, |size| size as i64),
+        });
+
+        if !self.body.is_empty() {
+            let mime_type = self
+                .headers
+                .iter()
+                .find(|(name, _)| name.as_str() == CONTENT_TYPE.as_str())
+                .map(|(_, value)| value.as_str())
+                .unwrap_or("application/octet-stream");
+            request["postData"] = json!({
+                "mimeType": mime_type,
+                "text": har::text(&self.body),
+            });
+        }
+
+        json!({
+            "startedDateTime": self.started.to_rfc3339(),
+            "time": -1,
+            "request": request,
+            "response": {
+                "status": self.status.as_u16(),
+                "statusText": self.status.canonical_reason().unwrap_or(""),
+                "httpVersion": format!("{:?}", self.version),
+                "cookies": [],
+                "headers": [],
+                "content": { "size": -1, "mimeType": "" },
+                "redirectURL": "",
+                "headersSize": -1,
+                "bodySize": -1,
+            },
+            "cache": {},
+            "timings": { "send": -1, "wait": -1, "receive": -1 },
+        })
+    }
+}
+
+/// A bounded buffer of the most recently captured requests, optionally also appended to a file.
+///
+/// The buffer is a cheaply cloneable handle, shared by a `ReplayCaptureMiddleware` and the
+/// `Handler` serving the captured requests.
+#[derive(Clone)]
+pub struct ReplayBuffer {
+    requests: Arc<Mutex<VecDeque<CapturedRequest>>>,
+    capacity: usize,
+    file: Option<PathBuf>,
+}
+
+impl ReplayBuffer {
+    /// Creates a buffer which keeps the given number of requests, dropping the oldest ones.
+    pub fn new(capacity: usize) -> ReplayBuffer {
+        ReplayBuffer {
+            requests: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+            file: None,
+        }
+    }
+
+    /// Also appends every captured request to the given file, as a HAR entry per line.
+    pub fn with_file<P>(mut self, path: P) -> ReplayBuffer
+    where
+        P: Into<PathBuf>,
+    {
+        self.file = Some(path.into());
+        self
+    }
+
+    /// Returns the captured requests, from oldest to newest.
+    pub fn requests(&self) -> Vec<CapturedRequest> {
+        self.lock().iter().cloned().collect()
+    }
+
+    /// Removes all captured requests from the buffer.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Renders the captured requests as a HAR log.
+    pub fn to_har(&self) -> Value {
+        har::log(
+            self.lock()
+                .iter()
+                .map(CapturedRequest::to_har_entry)
+                .collect(),
+        )
+    }
+
+    /// Renders the captured requests as a shell script of `curl` commands.
+    pub fn to_curl(&self) -> String {
+        self.lock()
+            .iter()
+            .map(|request| format!("{}\n", request.to_curl()))
+            .collect()
+    }
+
+    fn push(&self, request: CapturedRequest) {
+        if let Some(path) = &self.file {
+            let path = path.clone();
+            let line = format!("{}\n", request.to_har_entry());
+            // appended on the blocking thread pool, so that slow disks don't stall the executor
+            tokio::task::spawn_blocking(move || {
+                let written = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .and_then(|mut file| file.write_all(line.as_bytes()));
+                if let Err(e) = written {
+                    error!("failed to write captured request to {:?}: {}", path, e);
+                }
+            });
+        }
+
+        let mut requests = self.lock();
+        if self.capacity == 0 {
+            return;
+        }
+        while requests.len() >= self.capacity {
+            requests.pop_front();
+        }
+        requests.push_back(request);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VecDeque<CapturedRequest>> {
+        // the buffer remains consistent when a panic occurs while it is locked
+        match self.requests.lock() {
+            Ok(requests) => requests,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl NewHandler for ReplayBuffer {
+    type Instance = Self;
+
+    fn new_handler(&self) -> anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+/// Serves the captured requests as HAR log, or as `curl` commands if the query string contains
+/// `format=curl`.
+impl Handler for ReplayBuffer {
+    fn handle(self, state: State) -> Pin<Box<HandlerFuture>> {
+        let curl = Uri::borrow_from(&state)
+            .query()
+            .map(|query| query.split('&').any(|pair| pair == "format=curl"))
+            .unwrap_or(false);
+
+        let response = if curl {
+            create_response(
+                &state,
+                StatusCode::OK,
+                mime::TEXT_PLAIN_UTF_8,
+                self.to_curl(),
+            )
+        } else {
+            let body = self.to_har().to_string();
+            create_response(&state, StatusCode::OK, mime::APPLICATION_JSON, body)
+        };
+
+        future::ok((state, response)).boxed()
+    }
+}
+
+type Filter = dyn Fn(&State) -> bool + Send + Sync + RefUnwindSafe;
+
+/// Middleware which captures the requests matching a filter into a `ReplayBuffer`.
+///
+/// By default, all requests are captured, bodies are truncated after 64 KiB, and the values of the
+/// `Authorization`, `Proxy-Authorization` and `Cookie` headers, as well as of query parameters
+/// such as `token` or `password`, are redacted.
+#[derive(Clone)]
+pub struct ReplayCaptureMiddleware {
+    buffer: ReplayBuffer,
+    filter: Option<Arc<Filter>>,
+    max_body_size: usize,
+    redacted_headers: Vec<HeaderName>,
+    redacted_query_params: Vec<String>,
+}
+
+impl ReplayCaptureMiddleware {
+    /// Creates a middleware capturing all requests into `buffer`.
+    pub fn new(buffer: ReplayBuffer) -> ReplayCaptureMiddleware {
+        ReplayCaptureMiddleware {
+            buffer,
+            filter: None,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            redacted_headers: vec![AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE],
+            redacted_query_params: DEFAULT_REDACTED_QUERY_PARAMS
+                .iter()
+                .map(|name| (*name).to_owned())
+                .collect(),
+        }
+    }
+
+    /// Only captures the requests for which `filter` returns `true`.
+    pub fn with_filter<F>(mut self, filter: F) -> ReplayCaptureMiddleware
+    where
+        F: Fn(&State) -> bool + Send + Sync + RefUnwindSafe + 'static,
+    {
+        self.filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// Sets the number of bytes of a request body which are captured.
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> ReplayCaptureMiddleware {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// Adds a header whose value is redacted.
+    pub fn with_redacted_header(mut self, name: HeaderName) -> ReplayCaptureMiddleware {
+        self.redacted_headers.push(name);
+        self
+    }
+
+    /// Adds a query parameter whose value is redacted.
+    pub fn with_redacted_query_param<S>(mut self, name: S) -> ReplayCaptureMiddleware
+    where
+        S: Into<String>,
+    {
+        self.redacted_query_params.push(name.into());
+        self
+    }
+
+    fn capture(&self, state: &State, body: Bytes, truncated: bool) -> CapturedRequest {
+        let headers = HeaderMap::borrow_from(state);
+        let uri = Uri::borrow_from(state);
+
+        let host = headers
+            .get(HOST)
+            .and_then(|host| host.to_str().ok())
+            .or_else(|| uri.authority().map(|authority| authority.as_str()))
+            .unwrap_or("localhost");
+        let mut url = format!(
+            "{}://{}{}",
+            uri.scheme_str().unwrap_or("http"),
+            host,
+            uri.path()
+        );
+        if let Some(query) = uri.query() {
+            url.push('?');
+            url.push_str(&self.redact_query(query));
+        }
+
+        let headers = headers
+            .iter()
+            .map(|(name, value)| {
+                let value = if self.redacted_headers.contains(name) {
+                    REDACTED.to_owned()
+                } else {
+                    String::from_utf8_lossy(value.as_bytes()).into_owned()
+                };
+                (name.as_str().to_owned(), value)
+            })
+            .collect();
+
+        let body_size = if truncated {
+            content_length(HeaderMap::borrow_from(state))
+        } else {
+            Some(body.len())
+        };
+
+        CapturedRequest {
+            started: DateTime::from(clock::now(state)),
+            method: Method::borrow_from(state).clone(),
+            url,
+            version: *Version::borrow_from(state),
+            headers,
+            body,
+            body_size,
+            truncated,
+            status: StatusCode::OK,
+        }
+    }
+
+    fn redact_query(&self, query: &str) -> String {
+        query
+            .split('&')
+            .map(|pair| {
+                let name = pair.split('=').next().unwrap_or_default();
+                if self
+                    .redacted_query_params
+                    .iter()
+                    .any(|redacted| redacted.eq_ignore_ascii_case(name))
+                {
+                    format!("{}={}", name, REDACTED)
+                } else {
+                    pair.to_owned()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+}
+
+impl Middleware for ReplayCaptureMiddleware {
+    fn call<Chain>(self, mut state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>> + Send + 'static,
+    {
+        if let Some(filter) = &self.filter {
+            if !filter(&state) {
+                return chain(state);
+            }
+        }
+
+        let body = state.try_take::<Body>().unwrap_or_else(Body::empty);
+
+        async move {
+            let (captured, truncated, body) = match read_prefix(body, self.max_body_size).await {
+                Ok(read) => read,
+                Err(e) => return Err((state, HandlerError::bad_request(e))),
+            };
+
+            let mut request = self.capture(&state, captured, truncated);
+            state.put(body);
+
+            let result = chain(state).await;
+            request.status = match &result {
+                Ok((_, response)) => response.status(),
+                Err((_, err)) => err.status(),
+            };
+            self.buffer.push(request);
+
+            result
+        }
+        .boxed()
+    }
+}
+
+impl NewMiddleware for ReplayCaptureMiddleware {
+    type Instance = Self;
+
+    fn new_middleware(&self) -> anyhow::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+/// Returns the value of the `Content-Length` header, if it is valid.
+fn content_length(headers: &HeaderMap) -> Option<usize> {
+    headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+}
+
+/// Quotes `value` as a single argument for POSIX shells.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pipeline::new_pipeline;
+    use crate::pipeline::single::single_pipeline;
+    use crate::router::builder::*;
+    use crate::router::Router;
+    use crate::test::TestServer;
+
+    fn echo(mut state: State) -> Pin<Box<HandlerFuture>> {
+        let body = Body::take_from(&mut state);
+        async move {
+            let body = hyper::body::to_bytes(body).await.unwrap();
+            let response = create_response(&state, StatusCode::CREATED, mime::TEXT_PLAIN, body);
+            Ok((state, response))
+        }
+        .boxed()
+    }
+
+    fn router(buffer: ReplayBuffer) -> Router {
+        let capture = ReplayCaptureMiddleware::new(buffer.clone())
+            .with_filter(|state| Method::borrow_from(state) == Method::POST)
+            .with_max_body_size(8)
+            .with_redacted_header(HeaderName::from_static("x-api-key"));
+        let (chain, pipelines) = single_pipeline(new_pipeline().add(capture).build());
+
+        build_router(chain, pipelines, |route| {
+            route.post("/echo").to(echo);
+            route.get("/replay").to_new_handler(buffer);
+        })
+    }
+
+    #[test]
+    fn captures_matching_requests() {
+        let buffer = ReplayBuffer::new(1);
+        let test_server = TestServer::new(router(buffer.clone())).unwrap();
+        let client = test_server.client();
+
+        for body in &["first", "it's a long body"] {
+            let response = client
+                .post(
+                    "http://localhost/echo?id=1&token=abc",
+                    *body,
+                    mime::TEXT_PLAIN,
+                )
+                .with_header(AUTHORIZATION, "Bearer abc".parse().unwrap())
+                .with_header("x-api-key", "abc".parse().unwrap())
+                .perform()
+                .unwrap();
+            assert_eq!(response.read_utf8_body().unwrap(), *body);
+        }
+        client.get("http://localhost/replay").perform().unwrap();
+
+        let requests = buffer.requests();
+        assert_eq!(requests.len(), 1);
+
+        let request = &requests[0];
+        assert_eq!(request.method(), Method::POST);
+        assert_eq!(request.url(), "http://localhost/echo?id=1&token=[REDACTED]");
+        assert!(request
+            .headers()
+            .contains(&("authorization".to_owned(), REDACTED.to_owned())));
+        assert!(request
+            .headers()
+            .contains(&("x-api-key".to_owned(), REDACTED.to_owned())));
+        assert_eq!(request.body(), b"it's a l");
+        assert!(request.is_truncated());
+        assert_eq!(request.status(), StatusCode::CREATED);
+        assert!(request.to_curl().ends_with("--data-binary 'it'\\''s a l'"));
+        assert!(!request.to_curl().contains("content-length"));
+
+        let response = client
+            .get("http://localhost/replay?format=curl")
+            .perform()
+            .unwrap();
+        assert!(response
+            .read_utf8_body()
+            .unwrap()
+            .starts_with("curl -X POST 'http://localhost/echo?id=1&token=[REDACTED]'"));
+
+        let response = client.get("http://localhost/replay").perform().unwrap();
+        let har: Value = serde_json::from_slice(&response.read_body().unwrap()).unwrap();
+        let entry = &har["log"]["entries"][0];
+        assert_eq!(entry["request"]["bodySize"], 16);
+        assert_eq!(entry["request"]["postData"]["text"], "it's a l");
+        assert_eq!(entry["request"]["queryString"][1]["value"], REDACTED);
+        assert_eq!(entry["response"]["status"], 201);
+    }
+}
@@ -0,0 +1,348 @@
+//! Propagates the id of a request across services.
+//!
+//! Gotham identifies every request with the id returned by `state::request_id`, which is used in
+//! all of its logging. The `RequestIdMiddleware` makes this id usable beyond a single service: it
+//! accepts the id an upstream service sent in the `X-Request-ID` header, or the trace id of a
+//! W3C `traceparent` header, and echoes the id on the response, so that the logs of every
+//! service handling a request can be correlated.
+//!
+//! ```rust
+//! # extern crate gotham;
+//! # extern crate hyper;
+//! #
+//! # use hyper::header::HeaderValue;
+//! # use gotham::middleware::request_id::RequestIdMiddleware;
+//! # use gotham::pipeline::new_pipeline;
+//! # use gotham::pipeline::single::single_pipeline;
+//! # use gotham::router::builder::*;
+//! # use gotham::state::{request_id, State};
+//! # use gotham::test::TestServer;
+//! #
+//! fn handler(state: State) -> (State, String) {
+//!     let body = format!("handled {}", request_id(&state));
+//!     (state, body)
+//! }
+//!
+//! # fn main() {
+//! let (chain, pipelines) = single_pipeline(new_pipeline().add(RequestIdMiddleware::new()).build());
+//! let router = build_router(chain, pipelines, |route| {
+//!     route.get("/").to(handler);
+//! });
+//!
+//! let test_server = TestServer::new(router).unwrap();
+//! let response = test_server
+//!     .client()
+//!     .get("http://localhost/")
+//!     .with_header(
+//!         "traceparent",
+//!         HeaderValue::from_static("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+//!     )
+//!     .perform()
+//!     .unwrap();
+//! assert_eq!(
+//!     response.headers().get("x-request-id").unwrap(),
+//!     "4bf92f3577b34da6a3ce929d0e0e4736"
+//! );
+//! assert_eq!(
+//!     response.read_utf8_body().unwrap(),
+//!     "handled 4bf92f3577b34da6a3ce929d0e0e4736"
+//! );
+//! # }
+//! ```
+
+use std::pin::Pin;
+
+use futures::prelude::*;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use log::trace;
+
+use crate::handler::HandlerFuture;
+use crate::middleware::{Middleware, NewMiddleware};
+use crate::state::{generate_request_id, replace_request_id, request_id, FromState, State};
+
+const X_REQUEST_ID: &str = "x-request-id";
+const TRACEPARENT: &str = "traceparent";
+
+/// The longest request id accepted from a request.
+const MAX_LEN: usize = 200;
+
+/// The middleware propagating request ids, see the module documentation.
+///
+/// The id of a request is taken from, in order:
+///
+/// 1. The request id header, `X-Request-ID` unless configured with `with_header`;
+/// 2. The trace id of the `traceparent` header, unless disabled with `with_traceparent`;
+/// 3. The id Gotham generated for the request.
+///
+/// Ids received from a request are only accepted if they are at most 200 visible ASCII
+/// characters, so that they can't be used to forge log lines. If none is accepted, Gotham's own
+/// use of an `X-Request-ID` header is overridden with a newly generated id. The chosen id replaces the id
+/// returned by `state::request_id`, and is set in the request id header of the response, including
+/// responses to failed requests.
+#[derive(Clone)]
+pub struct RequestIdMiddleware {
+    header: HeaderName,
+    traceparent: bool,
+}
+
+impl RequestIdMiddleware {
+    /// Creates the middleware, reading and echoing the `X-Request-ID` header.
+    pub fn new() -> RequestIdMiddleware {
+        RequestIdMiddleware {
+            header: HeaderName::from_static(X_REQUEST_ID),
+            traceparent: true,
+        }
+    }
+
+    /// Reads and echoes the request id in the header `header` instead of `X-Request-ID`.
+    pub fn with_header(self, header: HeaderName) -> RequestIdMiddleware {
+        RequestIdMiddleware { header, ..self }
+    }
+
+    /// Sets whether the trace id of a `traceparent` header is used as the request id, if the
+    /// request has no request id header. This is enabled by default.
+    pub fn with_traceparent(self, traceparent: bool) -> RequestIdMiddleware {
+        RequestIdMiddleware {
+            traceparent,
+            ..self
+        }
+    }
+
+    /// Returns the request id sent by the client, if any is acceptable.
+    fn received_id(&self, headers: &HeaderMap) -> Option<String> {
+        let id = headers
+            .get(&self.header)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|id| is_valid_id(id));
+        if let Some(id) = id {
+            return Some(id.to_owned());
+        }
+
+        if self.traceparent {
+            headers
+                .get(TRACEPARENT)
+                .and_then(|value| value.to_str().ok())
+                .and_then(trace_id)
+                .map(str::to_owned)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for RequestIdMiddleware {
+    fn default() -> Self {
+        RequestIdMiddleware::new()
+    }
+}
+
+/// Returns `true` if `id` is a non-empty string of at most `MAX_LEN` visible ASCII characters.
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// Returns the trace id of the W3C trace context header `traceparent`, which has the format
+/// `{version}-{trace-id}-{parent-id}-{flags}`.
+fn trace_id(traceparent: &str) -> Option<&str> {
+    let is_hex = |s: &str, len: usize| {
+        s.len() == len
+            && s.bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    };
+
+    let mut fields = traceparent.trim().split('-');
+    let version = fields.next()?;
+    let trace_id = fields.next()?;
+    let parent_id = fields.next()?;
+    let flags = fields.next()?;
+
+    // Later versions may append fields, but version 00 has exactly four.
+    let valid = is_hex(version, 2)
+        && version != "ff"
+        && (version != "00" || fields.next().is_none())
+        && is_hex(trace_id, 32)
+        && trace_id.bytes().any(|b| b != b'0')
+        && is_hex(parent_id, 16)
+        && is_hex(flags, 2);
+
+    if valid {
+        Some(trace_id)
+    } else {
+        None
+    }
+}
+
+impl Middleware for RequestIdMiddleware {
+    fn call<Chain>(self, mut state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>>,
+    {
+        if let Some(id) = self.received_id(HeaderMap::borrow_from(&state)) {
+            trace!(
+                "[{}] request id replaced by the received id {}",
+                request_id(&state),
+                id
+            );
+            replace_request_id(&mut state, id);
+        } else if HeaderMap::borrow_from(&state).contains_key(X_REQUEST_ID) {
+            // `set_request_id` took the rejected header as it was, so it mustn't be logged
+            let id = generate_request_id(&state);
+            trace!("[{}] request id generated in place of the received id", id);
+            replace_request_id(&mut state, id);
+        }
+
+        let header = self.header;
+        let value = HeaderValue::from_str(request_id(&state)).ok();
+
+        let f = chain(state);
+        async move {
+            match (f.await, value) {
+                (Ok((state, mut response)), Some(value)) => {
+                    response.headers_mut().insert(header, value);
+                    Ok((state, response))
+                }
+                (Err((state, err)), Some(value)) => Err((state, err.with_header(header, value))),
+                (result, None) => result,
+            }
+        }
+        .boxed()
+    }
+}
+
+impl NewMiddleware for RequestIdMiddleware {
+    type Instance = RequestIdMiddleware;
+
+    fn new_middleware(&self) -> anyhow::Result<RequestIdMiddleware> {
+        Ok(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hyper::StatusCode;
+
+    use crate::handler::HandlerError;
+    use crate::pipeline::new_pipeline;
+    use crate::pipeline::single::single_pipeline;
+    use crate::router::builder::{build_router, DefineSingleRoute, DrawRoutes};
+    use crate::state::request_id::RequestIdGenerator;
+    use crate::test::TestServer;
+
+    #[test]
+    fn parses_trace_ids_of_traceparent_headers() {
+        let id = "4bf92f3577b34da6a3ce929d0e0e4736";
+        assert_eq!(
+            trace_id("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            Some(id)
+        );
+        assert_eq!(
+            trace_id("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra"),
+            Some(id)
+        );
+        assert_eq!(
+            trace_id("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra"),
+            None
+        );
+        assert_eq!(
+            trace_id("00-00000000000000000000000000000000-00f067aa0ba902b7-01"),
+            None
+        );
+        assert_eq!(
+            trace_id("ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            None
+        );
+        assert_eq!(
+            trace_id("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01"),
+            None
+        );
+        assert_eq!(trace_id("00-4bf92f3577b34da6"), None);
+    }
+
+    #[test]
+    fn propagates_request_ids() {
+        fn ok(state: State) -> (State, String) {
+            let id = request_id(&state).to_owned();
+            (state, id)
+        }
+
+        fn fail(state: State) -> (State, HandlerError) {
+            let err = HandlerError::from(anyhow::anyhow!("failed"));
+            (state, err.with_status(StatusCode::BAD_GATEWAY))
+        }
+
+        let middleware =
+            RequestIdMiddleware::new().with_header(HeaderName::from_static("x-correlation-id"));
+        let (chain, pipelines) = single_pipeline(new_pipeline().add(middleware).build());
+        let router = build_router(chain, pipelines, |route| {
+            route.get("/").to(ok);
+            route.get("/fail").to(fail);
+        });
+        let test_server = TestServer::new(router).unwrap();
+        let traceparent =
+            HeaderValue::from_static("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01");
+
+        let response = test_server
+            .client()
+            .get("http://localhost/")
+            .with_header("x-correlation-id", HeaderValue::from_static("abc-123"))
+            .with_header(TRACEPARENT, traceparent.clone())
+            .perform()
+            .unwrap();
+        assert_eq!(
+            response.headers().get("x-correlation-id").unwrap(),
+            "abc-123"
+        );
+        assert_eq!(response.read_utf8_body().unwrap(), "abc-123");
+
+        let response = test_server
+            .client()
+            .get("http://localhost/fail")
+            .with_header("x-correlation-id", HeaderValue::from_static("a\tb"))
+            .with_header(TRACEPARENT, traceparent)
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(
+            response.headers().get("x-correlation-id").unwrap(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+
+        let response = test_server
+            .client()
+            .get("http://localhost/")
+            .perform()
+            .unwrap();
+        let echoed = response.headers().get("x-correlation-id").unwrap().clone();
+        assert_eq!(response.read_utf8_body().unwrap(), echoed.to_str().unwrap());
+    }
+
+    #[test]
+    fn generates_ids_in_place_of_rejected_ones() {
+        fn ok(state: State) -> (State, String) {
+            let id = request_id(&state).to_owned();
+            (state, id)
+        }
+
+        let (chain, pipelines) =
+            single_pipeline(new_pipeline().add(RequestIdMiddleware::new()).build());
+        let router = build_router(chain, pipelines, |route| {
+            route.get("/").to(ok);
+        });
+        let test_server = TestServer::new(router)
+            .unwrap()
+            .with_request_ids(RequestIdGenerator::sequential("req"));
+
+        let response = test_server
+            .client()
+            .get("http://localhost/")
+            .with_header(X_REQUEST_ID, HeaderValue::from_static("forged\tline"))
+            .perform()
+            .unwrap();
+        let echoed = response.headers().get(X_REQUEST_ID).unwrap().clone();
+        assert!(echoed.to_str().unwrap().starts_with("req-"));
+        assert_eq!(response.read_utf8_body().unwrap(), echoed.to_str().unwrap());
+    }
+}
@@ -1,5 +1,6 @@
+use std::collections::{HashMap, HashSet};
 use std::pin::Pin;
-use std::sync::{Arc, Mutex, PoisonError, Weak};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
 use std::thread;
 use std::time::{Duration, Instant};
 
@@ -7,12 +8,56 @@ use futures::prelude::*;
 use linked_hash_map::LinkedHashMap;
 use log::trace;
 
+use crate::clock::{Clock, SharedClock};
 use crate::middleware::session::backend::{Backend, NewBackend, SessionFuture};
 use crate::middleware::session::{SessionError, SessionIdentifier};
 
 /// Type alias for the `MemoryBackend` storage container.
 type MemoryMap = Mutex<LinkedHashMap<String, (Instant, Vec<u8>)>>;
 
+/// The `MemoryBackend` index of session identifiers by user key, and of user keys by session
+/// identifier so that entries can be removed along with their session.
+#[derive(Default)]
+struct UserIndex {
+    sessions: HashMap<String, HashSet<String>>,
+    users: HashMap<String, String>,
+}
+
+impl UserIndex {
+    fn associate(&mut self, user_key: &str, identifier: String) {
+        if let Some(previous) = self.users.insert(identifier.clone(), user_key.to_owned()) {
+            self.remove_from_user(&previous, &identifier);
+        }
+        self.sessions
+            .entry(user_key.to_owned())
+            .or_default()
+            .insert(identifier);
+    }
+
+    fn remove_session(&mut self, identifier: &str) {
+        if let Some(user_key) = self.users.remove(identifier) {
+            self.remove_from_user(&user_key, identifier);
+        }
+    }
+
+    fn remove_user(&mut self, user_key: &str) -> HashSet<String> {
+        let identifiers = self.sessions.remove(user_key).unwrap_or_default();
+        for identifier in &identifiers {
+            self.users.remove(identifier);
+        }
+        identifiers
+    }
+
+    fn remove_from_user(&mut self, user_key: &str, identifier: &str) {
+        if let Some(identifiers) = self.sessions.get_mut(user_key) {
+            identifiers.remove(identifier);
+            if identifiers.is_empty() {
+                self.sessions.remove(user_key);
+            }
+        }
+    }
+}
+
 /// Defines the in-process memory based session storage.
 ///
 /// This is the default implementation which is used by `NewSessionMiddleware::default()`
@@ -33,6 +78,15 @@ pub struct MemoryBackend {
     // might show a need to replace this with a smarter implementation, but today there's very
     // little overhead here.
     storage: Arc<MemoryMap>,
+
+    // Identifiers are removed from the index along with their session, whether it's dropped,
+    // expired on read or expired by the cleanup thread.
+    users: Arc<Mutex<UserIndex>>,
+
+    // Sessions are also expired when they are read, so that expiry doesn't depend on the timing
+    // of the cleanup thread.
+    ttl: Duration,
+    clock: SharedClock,
 }
 
 impl MemoryBackend {
@@ -52,14 +106,29 @@ impl MemoryBackend {
     /// # ;}
     /// ```
     pub fn new(ttl: Duration) -> MemoryBackend {
+        MemoryBackend::with_clock(ttl, SharedClock::default())
+    }
+
+    /// Creates a new `MemoryBackend` like `new`, which measures the age of sessions with the
+    /// monotonic `Clock::instant` of `clock` instead of the system clock, e.g. a `ManualClock` in
+    /// tests.
+    pub fn with_clock(ttl: Duration, clock: SharedClock) -> MemoryBackend {
         let storage = Arc::new(Mutex::new(LinkedHashMap::new()));
+        let users = Arc::new(Mutex::new(UserIndex::default()));
 
         {
             let storage = Arc::downgrade(&storage);
-            thread::spawn(move || cleanup_loop(storage, ttl));
+            let users = Arc::downgrade(&users);
+            let clock = clock.clone();
+            thread::spawn(move || cleanup_loop(storage, users, ttl, clock));
         }
 
-        MemoryBackend { storage }
+        MemoryBackend {
+            storage,
+            users,
+            ttl,
+            clock,
+        }
     }
 }
 
@@ -85,7 +154,7 @@ impl Backend for MemoryBackend {
     ) -> Result<(), SessionError> {
         match self.storage.lock() {
             Ok(mut storage) => {
-                storage.insert(identifier.value, (Instant::now(), Vec::from(content)));
+                storage.insert(identifier.value, (self.clock.instant(), Vec::from(content)));
                 Ok(())
             }
             Err(PoisonError { .. }) => {
@@ -97,8 +166,16 @@ impl Backend for MemoryBackend {
     fn read_session(&self, identifier: SessionIdentifier) -> Pin<Box<SessionFuture>> {
         match self.storage.lock() {
             Ok(mut storage) => match storage.get_refresh(&identifier.value) {
+                Some(&mut (instant, _))
+                    if self.clock.instant().saturating_duration_since(instant) >= self.ttl =>
+                {
+                    storage.remove(&identifier.value);
+                    lock_users(&self.users).remove_session(&identifier.value);
+                    trace!(" expired session {} on read", identifier.value);
+                    future::ok(None).boxed()
+                }
                 Some(&mut (ref mut instant, ref value)) => {
-                    *instant = Instant::now();
+                    *instant = self.clock.instant();
                     future::ok(Some(value.clone())).boxed()
                 }
                 None => future::ok(None).boxed(),
@@ -113,6 +190,7 @@ impl Backend for MemoryBackend {
         match self.storage.lock() {
             Ok(mut storage) => {
                 storage.remove(&identifier.value);
+                lock_users(&self.users).remove_session(&identifier.value);
                 Ok(())
             }
             Err(PoisonError { .. }) => {
@@ -120,20 +198,75 @@ impl Backend for MemoryBackend {
             }
         }
     }
+
+    fn associate_user(
+        &self,
+        user_key: &str,
+        identifier: SessionIdentifier,
+    ) -> Result<(), SessionError> {
+        lock_users(&self.users).associate(user_key, identifier.value);
+        Ok(())
+    }
+
+    fn user_sessions(&self, user_key: &str) -> Result<Vec<SessionIdentifier>, SessionError> {
+        // Always lock `storage` before `users` to avoid deadlocking with other callers.
+        match (self.storage.lock(), self.users.lock()) {
+            (Ok(storage), Ok(users)) => {
+                // Sessions which were associated but never persisted aren't listed.
+                let sessions = match users.sessions.get(user_key) {
+                    Some(identifiers) => identifiers
+                        .iter()
+                        .filter(|value| storage.contains_key(*value))
+                        .map(|value| SessionIdentifier {
+                            value: value.clone(),
+                        })
+                        .collect(),
+                    None => vec![],
+                };
+
+                Ok(sessions)
+            }
+            _ => unreachable!("session memory backend lock poisoned, HashMap panicked?"),
+        }
+    }
+
+    fn drop_user_sessions(&self, user_key: &str) -> Result<(), SessionError> {
+        match (self.storage.lock(), self.users.lock()) {
+            (Ok(mut storage), Ok(mut users)) => {
+                for value in users.remove_user(user_key) {
+                    storage.remove(&value);
+                }
+
+                trace!(" dropped all sessions for user {}", user_key);
+                Ok(())
+            }
+            _ => unreachable!("session memory backend lock poisoned, HashMap panicked?"),
+        }
+    }
+}
+
+/// Locks the user index, which is only ever updated in single, non-panicking operations.
+fn lock_users(users: &Mutex<UserIndex>) -> MutexGuard<'_, UserIndex> {
+    users.lock().unwrap_or_else(PoisonError::into_inner)
 }
 
-fn cleanup_loop(storage: Weak<MemoryMap>, ttl: Duration) {
+fn cleanup_loop(
+    storage: Weak<MemoryMap>,
+    users: Weak<Mutex<UserIndex>>,
+    ttl: Duration,
+    clock: SharedClock,
+) {
     loop {
         // If the original `Arc<_>` goes away, we don't need to keep sweeping the cache, because
         // it's gone too. We can bail out of this thread when the weak ref fails to upgrade.
-        let storage = match storage.upgrade() {
-            None => break,
-            Some(storage) => storage,
+        let (storage, users) = match (storage.upgrade(), users.upgrade()) {
+            (Some(storage), Some(users)) => (storage, users),
+            _ => break,
         };
 
         let duration = match storage.lock() {
             Err(PoisonError { .. }) => break,
-            Ok(mut storage) => cleanup_once(&mut storage, ttl),
+            Ok(mut storage) => cleanup_once(&mut storage, &users, ttl, &clock),
         };
 
         if let Some(duration) = duration {
@@ -144,14 +277,17 @@ fn cleanup_loop(storage: Weak<MemoryMap>, ttl: Duration) {
 
 fn cleanup_once(
     storage: &mut LinkedHashMap<String, (Instant, Vec<u8>)>,
+    users: &Mutex<UserIndex>,
     ttl: Duration,
+    clock: &dyn Clock,
 ) -> Option<Duration> {
     match storage.front() {
         Some((_, &(instant, _))) => {
-            let age = instant.elapsed();
+            let age = clock.instant().saturating_duration_since(instant);
 
             if age >= ttl {
                 if let Some((key, _)) = storage.pop_front() {
+                    lock_users(users).remove_session(&key);
                     trace!(" expired session {} and removed from MemoryBackend", key);
                 }
 
@@ -193,27 +329,50 @@ fn cleanup_once(
 mod tests {
     use super::*;
 
+    use std::time::SystemTime;
+
+    use crate::clock::ManualClock;
     use rand;
 
     #[test]
     fn cleanup_test() {
         let mut storage = LinkedHashMap::new();
+        let users = Mutex::new(UserIndex::default());
 
         storage.insert(
             "abcd".to_owned(),
             (Instant::now() - Duration::from_secs(2), vec![]),
         );
+        lock_users(&users).associate("alice", "abcd".to_owned());
 
-        cleanup_once(&mut storage, Duration::from_secs(1));
+        cleanup_once(
+            &mut storage,
+            &users,
+            Duration::from_secs(1),
+            &SharedClock::default(),
+        );
         assert!(storage.is_empty());
+
+        let users = lock_users(&users);
+        assert!(users.sessions.is_empty());
+        assert!(users.users.is_empty());
     }
 
     #[test]
     fn cleanup_join_test() {
         let storage = Arc::new(Mutex::new(LinkedHashMap::new()));
+        let users = Arc::new(Mutex::new(UserIndex::default()));
         let weak = Arc::downgrade(&storage);
+        let weak_users = Arc::downgrade(&users);
 
-        let handle = thread::spawn(move || cleanup_loop(weak, Duration::from_millis(1)));
+        let handle = thread::spawn(move || {
+            cleanup_loop(
+                weak,
+                weak_users,
+                Duration::from_millis(1),
+                SharedClock::default(),
+            )
+        });
 
         drop(storage);
         handle.join().unwrap();
@@ -245,6 +404,98 @@ mod tests {
         assert_eq!(bytes, received);
     }
 
+    #[test]
+    fn memory_backend_clock_test() {
+        let clock = ManualClock::new(SystemTime::now());
+        let backend =
+            MemoryBackend::with_clock(Duration::from_secs(60), SharedClock::new(clock.clone()));
+        let identifier = SessionIdentifier {
+            value: "totally_random_identifier".to_owned(),
+        };
+        let read = || {
+            futures::executor::block_on(backend.read_session(identifier.clone()))
+                .expect("no response from backend")
+        };
+
+        backend
+            .persist_session(identifier.clone(), &[1, 2, 3])
+            .expect("failed to persist");
+        backend
+            .associate_user("alice", identifier.clone())
+            .expect("failed to associate");
+
+        // reading refreshes the session
+        clock.advance(Duration::from_secs(59));
+        assert!(read().is_some());
+        clock.advance(Duration::from_secs(59));
+        assert!(read().is_some());
+
+        // adjusting the wall-clock time doesn't expire sessions
+        clock.set(SystemTime::now() + Duration::from_secs(86_400));
+        assert!(read().is_some());
+
+        clock.advance(Duration::from_secs(60));
+        assert!(read().is_none());
+        assert!(lock_users(&backend.users).sessions.is_empty());
+    }
+
+    #[test]
+    fn memory_backend_user_sessions_test() {
+        let backend = MemoryBackend::new(Duration::from_secs(100));
+        let identifiers: Vec<SessionIdentifier> = (0..3)
+            .map(|i| SessionIdentifier {
+                value: format!("session_{}", i),
+            })
+            .collect();
+
+        for identifier in &identifiers {
+            backend
+                .persist_session(identifier.clone(), &[1, 2, 3])
+                .expect("failed to persist");
+        }
+
+        backend
+            .associate_user("alice", identifiers[0].clone())
+            .expect("failed to associate");
+        backend
+            .associate_user("alice", identifiers[1].clone())
+            .expect("failed to associate");
+        backend
+            .associate_user("bob", identifiers[2].clone())
+            .expect("failed to associate");
+
+        let mut sessions = backend.user_sessions("alice").expect("failed to list");
+        sessions.sort();
+        assert_eq!(sessions, &identifiers[..2]);
+
+        // Sessions removed from storage no longer appear in the index
+        backend
+            .drop_session(identifiers[1].clone())
+            .expect("failed to drop");
+        assert_eq!(
+            backend.user_sessions("alice").expect("failed to list"),
+            &identifiers[..1]
+        );
+        assert!(!lock_users(&backend.users)
+            .users
+            .contains_key(&identifiers[1].value));
+
+        backend
+            .drop_user_sessions("alice")
+            .expect("failed to drop user sessions");
+        assert!(backend
+            .user_sessions("alice")
+            .expect("failed to list")
+            .is_empty());
+
+        let read = |identifier: &SessionIdentifier| {
+            futures::executor::block_on(backend.read_session(identifier.clone()))
+                .expect("no response from backend")
+        };
+        assert!(read(&identifiers[0]).is_none());
+        assert!(read(&identifiers[2]).is_some());
+    }
+
     #[test]
     fn memory_backend_refresh_test() {
         let new_backend = MemoryBackend::new(Duration::from_millis(100));
@@ -40,4 +40,42 @@ pub trait Backend: Send {
 
     /// Drops a session from the underlying storage.
     fn drop_session(&self, identifier: SessionIdentifier) -> Result<(), SessionError>;
+
+    /// Records that the session belongs to the user identified by `user_key`, so that it can be
+    /// found again via `user_sessions` and revoked via `drop_user_sessions`.
+    ///
+    /// Backends which do not maintain a user index return `SessionError::Backend` by default.
+    fn associate_user(
+        &self,
+        user_key: &str,
+        identifier: SessionIdentifier,
+    ) -> Result<(), SessionError> {
+        let _ = identifier;
+        Err(SessionError::Backend(format!(
+            "backend does not support indexing sessions by user ({})",
+            user_key
+        )))
+    }
+
+    /// Lists the identifiers of all live sessions which have been associated with `user_key`.
+    ///
+    /// Backends which do not maintain a user index return `SessionError::Backend` by default.
+    fn user_sessions(&self, user_key: &str) -> Result<Vec<SessionIdentifier>, SessionError> {
+        Err(SessionError::Backend(format!(
+            "backend does not support indexing sessions by user ({})",
+            user_key
+        )))
+    }
+
+    /// Drops every session which has been associated with `user_key`, e.g. to implement "log out
+    /// everywhere" or an administrator forcing a user to log out.
+    ///
+    /// The default implementation drops each session returned by `user_sessions` in turn.
+    fn drop_user_sessions(&self, user_key: &str) -> Result<(), SessionError> {
+        for identifier in self.user_sessions(user_key)? {
+            self.drop_session(identifier)?;
+        }
+
+        Ok(())
+    }
 }
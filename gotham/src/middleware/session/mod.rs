@@ -5,7 +5,7 @@ use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
 use std::panic::RefUnwindSafe;
 use std::pin::Pin;
-use std::sync::{Arc, Mutex, PoisonError};
+use std::sync::Arc;
 
 use base64;
 use bincode;
@@ -14,20 +14,21 @@ use futures::prelude::*;
 use hyper::header::SET_COOKIE;
 use hyper::{Body, Response, StatusCode};
 use log::{error, trace, warn};
-use rand::RngCore;
 use serde::{Deserialize, Serialize};
 
 use super::cookie::CookieParser;
 use super::{Middleware, NewMiddleware};
 use crate::handler::{HandlerError, HandlerFuture};
 use crate::helpers::http::response::create_empty_response;
-use crate::state::{self, FromState, State, StateData};
+use crate::state::{self, FromState, SeededRng, State, StateData};
 
 mod backend;
+mod return_to;
 mod rng;
 
 pub use self::backend::memory::MemoryBackend;
 pub use self::backend::{Backend, NewBackend};
+pub use self::return_to::{redirect_after_login, redirect_to_login, ReturnTo, ReturnToSession};
 
 const SECURE_COOKIE_PREFIX: &str = "__Secure-";
 const HOST_COOKIE_PREFIX: &str = "__Host-";
@@ -296,6 +297,36 @@ where
         self.backend.drop_session(self.identifier)
     }
 
+    /// Associates this session with the user identified by `user_key`, allowing it to be
+    /// enumerated and revoked along with the user's other sessions.
+    ///
+    /// This is typically called once the user has successfully logged in. The `Backend` must
+    /// support indexing sessions by user; `MemoryBackend` does.
+    pub fn associate_user(&self, user_key: &str) -> Result<(), SessionError> {
+        self.backend
+            .associate_user(user_key, self.identifier.clone())
+    }
+
+    /// Lists all live sessions which have been associated with the user identified by `user_key`.
+    pub fn user_sessions(&self, user_key: &str) -> Result<Vec<SessionIdentifier>, SessionError> {
+        self.backend.user_sessions(user_key)
+    }
+
+    /// Discards this session along with every other session associated with the user identified
+    /// by `user_key`, i.e. "log out everywhere".
+    ///
+    /// To force a user to log out outside of their own requests (e.g. from an administrative
+    /// handler), call `Backend::drop_user_sessions` on a backend created from the same
+    /// `NewBackend` which was given to `NewSessionMiddleware`.
+    pub fn discard_user_sessions(
+        self,
+        state: &mut State,
+        user_key: &str,
+    ) -> Result<(), SessionError> {
+        self.backend.drop_user_sessions(user_key)?;
+        self.discard(state)
+    }
+
     // Create a new, blank `SessionData<T>`
     fn new<B>(middleware: SessionMiddleware<B, T>) -> SessionData<T>
     where
@@ -462,7 +493,7 @@ where
     T: Default + Serialize + for<'de> Deserialize<'de> + Send + 'static,
 {
     new_backend: B,
-    identifier_rng: Arc<Mutex<rng::SessionIdentifierRng>>,
+    identifier_rng: rng::IdentifierRng,
     cookie_config: Arc<SessionCookieConfig>,
     phantom: PhantomData<dyn SessionTypePhantom<T>>,
 }
@@ -476,7 +507,7 @@ where
     T: Default + Serialize + for<'de> Deserialize<'de> + Send + 'static,
 {
     backend: B,
-    identifier_rng: Arc<Mutex<rng::SessionIdentifierRng>>,
+    identifier_rng: rng::IdentifierRng,
     cookie_config: Arc<SessionCookieConfig>,
     phantom: PhantomData<T>,
 }
@@ -530,7 +561,7 @@ where
     pub fn new(b: B) -> NewSessionMiddleware<B, ()> {
         NewSessionMiddleware {
             new_backend: b,
-            identifier_rng: Arc::new(Mutex::new(rng::session_identifier_rng())),
+            identifier_rng: rng::IdentifierRng::from_entropy(),
             cookie_config: Arc::new(SessionCookieConfig::default()),
             phantom: PhantomData,
         }
@@ -759,6 +790,38 @@ where
         self.rebuild_new_session_middleware(cookie_config)
     }
 
+    /// Generates session identifiers with `rng` instead of the system's entropy, so that they are
+    /// reproducible in tests.
+    ///
+    /// Identifiers generated by a `SeededRng` are predictable, so this must never be configured
+    /// outside of tests.
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # #[macro_use]
+    /// # extern crate serde_derive;
+    /// #
+    /// # use gotham::middleware::session::NewSessionMiddleware;
+    /// # use gotham::state::SeededRng;
+    /// #
+    /// # #[derive(Default, Serialize, Deserialize)]
+    /// # struct MySessionType {
+    /// #   items: Vec<String>,
+    /// # }
+    /// #
+    /// # fn main() {
+    /// NewSessionMiddleware::default()
+    ///     .with_session_type::<MySessionType>()
+    ///     .with_seeded_rng(SeededRng::new(42))
+    /// # ;}
+    /// ```
+    pub fn with_seeded_rng(self, rng: SeededRng) -> NewSessionMiddleware<B, T> {
+        NewSessionMiddleware {
+            identifier_rng: rng::IdentifierRng::Seeded(rng),
+            ..self
+        }
+    }
+
     /// Changes the session type to the provided type parameter. This is required to override the
     /// default (unusable) session type of `()`.
     ///
@@ -852,10 +915,7 @@ where
     fn random_identifier(&self) -> SessionIdentifier {
         let mut bytes = [0u8; 64];
 
-        match self.identifier_rng.lock() {
-            Ok(mut rng) => rng.fill_bytes(&mut bytes),
-            Err(PoisonError { .. }) => unreachable!("identifier_rng lock poisoned. Rng panicked?"),
-        };
+        self.identifier_rng.fill_bytes(&mut bytes);
 
         SessionIdentifier {
             value: base64::encode_config(&bytes[..], base64::URL_SAFE_NO_PAD),
@@ -1224,4 +1284,86 @@ mod tests {
 
         assert_eq!(updated.val, session.val + 1);
     }
+
+    #[test]
+    fn discard_user_sessions() {
+        let backend = MemoryBackend::new(Duration::from_secs(1));
+        let nm = NewSessionMiddleware::new(backend.clone()).with_session_type::<TestSession>();
+        let m = nm.new_middleware().unwrap();
+
+        let other = m.random_identifier();
+        m.backend.persist_session(other.clone(), &[]).unwrap();
+        m.backend.associate_user("alice", other.clone()).unwrap();
+
+        let handler = move |mut state: State| {
+            let session_data = state.take::<SessionData<TestSession>>();
+            session_data.associate_user("alice").unwrap();
+            assert_eq!(session_data.user_sessions("alice").unwrap().len(), 1);
+            session_data
+                .discard_user_sessions(&mut state, "alice")
+                .unwrap();
+
+            future::ok((
+                state,
+                Response::builder()
+                    .status(StatusCode::OK)
+                    .body(Body::empty())
+                    .unwrap(),
+            ))
+            .boxed()
+        };
+
+        let mut state = State::new();
+        state.put(HeaderMap::new());
+
+        let response = match futures::executor::block_on(m.call(state, handler)) {
+            Ok((_, response)) => response,
+            Err((_, e)) => panic!("error: {:?}", e),
+        };
+        let set_cookie = response.headers().get(SET_COOKIE).unwrap();
+        assert!(set_cookie.to_str().unwrap().contains("max-age=0"));
+
+        let backend = backend.new_backend().unwrap();
+        assert!(backend.user_sessions("alice").unwrap().is_empty());
+        assert!(futures::executor::block_on(backend.read_session(other))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn seeded_identifiers() {
+        let new_identifier = |seeded: bool| {
+            let nm = NewSessionMiddleware::default().with_session_type::<TestSession>();
+            let nm = if seeded {
+                nm.with_seeded_rng(SeededRng::new(42))
+            } else {
+                nm
+            };
+            let m = nm.new_middleware().unwrap();
+            let mut state = State::new();
+            state.put(HeaderMap::new());
+            // a seeded rng in the state doesn't affect the identifiers
+            state.put(SeededRng::new(42));
+
+            let handler = |mut state: State| {
+                let identifier = state.take::<SessionData<TestSession>>().identifier;
+                let response = Response::builder()
+                    .status(StatusCode::OK)
+                    .body(Body::from(identifier.value))
+                    .unwrap();
+                future::ok((state, response)).boxed()
+            };
+
+            match futures::executor::block_on(m.call(state, handler)) {
+                Ok((_, response)) => {
+                    futures::executor::block_on(hyper::body::to_bytes(response.into_body()))
+                        .unwrap()
+                }
+                Err((_, e)) => panic!("error: {:?}", e),
+            }
+        };
+
+        assert_eq!(new_identifier(true), new_identifier(true));
+        assert_ne!(new_identifier(false), new_identifier(false));
+    }
 }
@@ -0,0 +1,285 @@
+//! Helpers for sending users back to the page they originally requested once they have logged
+//! in.
+
+use hyper::{Body, Method, Response, Uri};
+use log::trace;
+use serde::{Deserialize, Serialize};
+
+use super::SessionData;
+use crate::helpers::http::response::{is_local_path, SafeRedirect};
+use crate::state::{request_id, FromState, State};
+
+/// A URL to return to after logging in, which is guaranteed to stay on the current site: an
+/// absolute path, optionally followed by a query string.
+#[derive(Clone, Debug, PartialEq, Eq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct ReturnTo(String);
+
+impl ReturnTo {
+    /// Captures the path and query of the request in `state`. Request targets which
+    /// `ReturnTo::parse` rejects, such as `//other.example`, are replaced by `/`.
+    pub fn from_request(state: &State) -> ReturnTo {
+        let target = Uri::borrow_from(state)
+            .path_and_query()
+            .map(|path_and_query| path_and_query.as_str())
+            .unwrap_or("/");
+
+        ReturnTo::parse(target).unwrap_or_else(|| ReturnTo(String::from("/")))
+    }
+
+    /// Validates a target supplied by the client, e.g. the `next` parameter of a login form.
+    ///
+    /// Targets which could lead to another site are rejected: absolute URLs, scheme-relative
+    /// paths such as `//other.example`, and paths containing backslashes or control characters,
+    /// which some browsers normalize into the former.
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # use gotham::middleware::session::ReturnTo;
+    /// # fn main() {
+    /// assert!(ReturnTo::parse("/orders?page=2").is_some());
+    /// assert!(ReturnTo::parse("https://other.example/").is_none());
+    /// assert!(ReturnTo::parse("//other.example/").is_none());
+    /// assert!(ReturnTo::parse("/\\other.example/").is_none());
+    /// # }
+    /// ```
+    pub fn parse(target: &str) -> Option<ReturnTo> {
+        if is_local_path(target) {
+            Some(ReturnTo(target.to_owned()))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the URL, e.g. `/orders?page=2`.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// A session type which remembers where to return to after logging in, as required by
+/// `redirect_to_login` and `redirect_after_login`.
+pub trait ReturnToSession:
+    Default + Serialize + for<'de> Deserialize<'de> + Send + 'static
+{
+    /// Returns the field of the session holding the URL to return to.
+    fn return_to(&mut self) -> &mut Option<ReturnTo>;
+}
+
+/// Remembers the current request in the session of type `T`, and redirects to `login_path` with
+/// `303 See Other`. Used by handlers and middleware which find that the user is not logged in.
+///
+/// Only `GET` and `HEAD` requests are remembered, as other requests can't be repeated by a
+/// redirect. `login_path` must be safe according to `SafeRedirect`, otherwise the response is
+/// `400 Bad Request`.
+///
+/// # Panics
+///
+/// If the `SessionData<T>` is not in `state`, i.e. the session middleware is not in the pipeline.
+///
+/// # Examples
+///
+/// ```rust
+/// # extern crate gotham;
+/// # extern crate hyper;
+/// # #[macro_use]
+/// # extern crate serde_derive;
+/// #
+/// # use hyper::{Body, Response};
+/// # use gotham::middleware::session::{
+/// #     redirect_after_login, redirect_to_login, ReturnTo, ReturnToSession, SessionData,
+/// # };
+/// # use gotham::middleware::session::NewSessionMiddleware;
+/// # use gotham::pipeline::new_pipeline;
+/// # use gotham::pipeline::single::single_pipeline;
+/// # use gotham::router::builder::*;
+/// # use gotham::state::{FromState, State};
+/// #
+/// #[derive(Default, Serialize, Deserialize)]
+/// struct Session {
+///     user: Option<String>,
+///     return_to: Option<ReturnTo>,
+/// }
+///
+/// impl ReturnToSession for Session {
+///     fn return_to(&mut self) -> &mut Option<ReturnTo> {
+///         &mut self.return_to
+///     }
+/// }
+///
+/// fn account(mut state: State) -> (State, Response<Body>) {
+///     let response = match &SessionData::<Session>::borrow_from(&state).user {
+///         Some(user) => Response::new(Body::from(format!("hello, {}", user))),
+///         None => redirect_to_login::<Session>(&mut state, "/login"),
+///     };
+///     (state, response)
+/// }
+///
+/// fn login(mut state: State) -> (State, Response<Body>) {
+///     // credentials checked here
+///     SessionData::<Session>::borrow_mut_from(&mut state).user = Some("alice".to_owned());
+///     let response = redirect_after_login::<Session>(&mut state, "/");
+///     (state, response)
+/// }
+/// # fn main() {
+/// let (chain, pipelines) = single_pipeline(
+///     new_pipeline()
+///         .add(NewSessionMiddleware::default().with_session_type::<Session>())
+///         .build(),
+/// );
+///
+/// build_router(chain, pipelines, |route| {
+///     route.get("/account").to(account);
+///     route.post("/login").to(login);
+/// });
+/// # }
+/// ```
+pub fn redirect_to_login<T>(state: &mut State, login_path: &str) -> Response<Body>
+where
+    T: ReturnToSession,
+{
+    let return_to = match *Method::borrow_from(state) {
+        Method::GET | Method::HEAD => Some(ReturnTo::from_request(state)),
+        _ => None,
+    };
+
+    *SessionData::<T>::borrow_mut_from(state).return_to() = return_to;
+    see_other(state, login_path)
+}
+
+/// Redirects to the URL remembered by `redirect_to_login` with `303 See Other`, removing it from
+/// the session of type `T`, or to `fallback` if none was remembered. Like `login_path` above,
+/// `fallback` must be safe according to `SafeRedirect`.
+///
+/// # Panics
+///
+/// If the `SessionData<T>` is not in `state`, i.e. the session middleware is not in the pipeline.
+pub fn redirect_after_login<T>(state: &mut State, fallback: &str) -> Response<Body>
+where
+    T: ReturnToSession,
+{
+    let session = SessionData::<T>::borrow_mut_from(state);
+    let target = session
+        .return_to()
+        .take()
+        // sessions persisted by earlier versions of the application aren't trusted blindly
+        .and_then(|return_to| ReturnTo::parse(return_to.as_str()));
+
+    match target {
+        Some(return_to) => {
+            trace!(
+                "[{}] returning to {} after login",
+                request_id(state),
+                return_to.as_str()
+            );
+            see_other(state, return_to.as_str())
+        }
+        None => see_other(state, fallback),
+    }
+}
+
+fn see_other(state: &State, location: &str) -> Response<Body> {
+    SafeRedirect::new().see_other(state, location)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hyper::header::{COOKIE, LOCATION, SET_COOKIE};
+    use hyper::StatusCode;
+
+    use crate::middleware::session::NewSessionMiddleware;
+    use crate::pipeline::single::single_pipeline;
+    use crate::pipeline::single_middleware;
+    use crate::router::builder::{build_router, DefineSingleRoute, DrawRoutes};
+    use crate::test::TestServer;
+
+    #[derive(Default, serde_derive::Serialize, serde_derive::Deserialize)]
+    struct TestSession {
+        return_to: Option<ReturnTo>,
+    }
+
+    impl ReturnToSession for TestSession {
+        fn return_to(&mut self) -> &mut Option<ReturnTo> {
+            &mut self.return_to
+        }
+    }
+
+    #[test]
+    fn rejects_off_site_targets() {
+        for target in &["/", "/a/b?c=d&e=%2F%2F", "/@other.example"] {
+            assert!(ReturnTo::parse(target).is_some(), "{}", target);
+        }
+
+        for target in &[
+            "",
+            "a/b",
+            "http://other.example/",
+            "//other.example",
+            "/\\other.example",
+            "/\t/other.example",
+            "javascript:alert(1)",
+        ] {
+            assert!(ReturnTo::parse(target).is_none(), "{}", target);
+        }
+    }
+
+    #[test]
+    fn redirects_back_after_login() {
+        fn protected(mut state: State) -> (State, Response<Body>) {
+            let response = redirect_to_login::<TestSession>(&mut state, "/login");
+            (state, response)
+        }
+
+        fn login(mut state: State) -> (State, Response<Body>) {
+            let response = redirect_after_login::<TestSession>(&mut state, "/home");
+            (state, response)
+        }
+
+        let (chain, pipelines) = single_pipeline(single_middleware(
+            NewSessionMiddleware::default()
+                .insecure()
+                .with_session_type::<TestSession>(),
+        ));
+        let router = build_router(chain, pipelines, |route| {
+            route.get("/orders").to(protected);
+            route.post("/orders").to(protected);
+            route.post("/login").to(login);
+        });
+
+        let test_server = TestServer::new(router).unwrap();
+        let client = test_server.client();
+
+        let response = client
+            .get("http://localhost/orders?page=2")
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()[LOCATION], "/login");
+        let set_cookie = response.headers()[SET_COOKIE].to_str().unwrap();
+        let cookie = set_cookie.split(';').next().unwrap().to_owned();
+
+        let login = || {
+            client
+                .post("http://localhost/login", "", mime::TEXT_PLAIN)
+                .with_header(COOKIE, cookie.parse().unwrap())
+                .perform()
+                .unwrap()
+        };
+
+        let response = login();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()[LOCATION], "/orders?page=2");
+
+        let response = login();
+        assert_eq!(response.headers()[LOCATION], "/home");
+
+        let response = client
+            .post("http://localhost/orders", "", mime::TEXT_PLAIN)
+            .with_header(COOKIE, cookie.parse().unwrap())
+            .perform()
+            .unwrap();
+        assert_eq!(response.headers()[LOCATION], "/login");
+        assert_eq!(login().headers()[LOCATION], "/home");
+    }
+}
@@ -1,9 +1,13 @@
+use std::sync::{Arc, Mutex, PoisonError};
+
 use log::error;
 use rand::rngs::adapter::ReseedingRng;
 use rand::rngs::OsRng;
-use rand::FromEntropy;
+use rand::{FromEntropy, RngCore};
 use rand_chacha::ChaChaCore;
 
+use crate::state::SeededRng;
+
 // A `ChaChaRng` which is periodically reseeded from an `OsRng`. This was originally using an
 // `OsRng`, but sourcing entropy from the kernel was measured to be a performance bottleneck.
 // Conventional wisdom seems to be that a securely seeded ChaCha20 PRNG is secure enough for
@@ -29,3 +33,29 @@ pub(super) fn session_identifier_rng() -> SessionIdentifierRng {
     // Reseed every 32KiB.
     ReseedingRng::new(rng, 32_768, os_rng)
 }
+
+// The source of session identifiers, which is only seeded when configured explicitly through
+// `NewSessionMiddleware::with_seeded_rng`.
+#[derive(Clone)]
+pub(super) enum IdentifierRng {
+    Entropy(Arc<Mutex<SessionIdentifierRng>>),
+    Seeded(SeededRng),
+}
+
+impl IdentifierRng {
+    pub(super) fn from_entropy() -> IdentifierRng {
+        IdentifierRng::Entropy(Arc::new(Mutex::new(session_identifier_rng())))
+    }
+
+    pub(super) fn fill_bytes(&self, dest: &mut [u8]) {
+        match self {
+            IdentifierRng::Entropy(rng) => match rng.lock() {
+                Ok(mut rng) => rng.fill_bytes(dest),
+                Err(PoisonError { .. }) => {
+                    unreachable!("identifier_rng lock poisoned. Rng panicked?")
+                }
+            },
+            IdentifierRng::Seeded(rng) => rng.fill_bytes(dest),
+        }
+    }
+}
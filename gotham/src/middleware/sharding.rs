@@ -0,0 +1,347 @@
+//! Assigns requests to shards by an attribute of the request, e.g. the user or tenant it is made
+//! for, so that handlers can pick the database or partition holding its data.
+//!
+//! Keys are assigned to the shards of a `ShardMap` by consistent hashing: each shard owns many
+//! points on a hash ring, and a key belongs to the shard owning the first point after the hash of
+//! the key. When a shard is added, only the keys it takes over move, all others stay on their
+//! shard.
+//!
+//! The `Sharding` middleware derives the key of a request and puts the `Shard` it is assigned to
+//! into `State`. Its `ShardMap` can be replaced while the server is running, e.g. after the
+//! configuration it was built from was reloaded.
+//!
+//! ```rust
+//! # extern crate gotham;
+//! # extern crate hyper;
+//! #
+//! # use hyper::{HeaderMap, StatusCode};
+//! # use gotham::middleware::sharding::{shard, ShardMap, Sharding};
+//! # use gotham::pipeline::new_pipeline;
+//! # use gotham::pipeline::single::single_pipeline;
+//! # use gotham::router::builder::*;
+//! # use gotham::state::{FromState, State};
+//! # use gotham::test::TestServer;
+//! #
+//! fn orders(state: State) -> (State, String) {
+//!     // an application would query the database of the shard here
+//!     let body = match shard(&state) {
+//!         Some(shard) => format!("orders of {} on {}", shard.key(), shard.name()),
+//!         None => "no tenant".to_owned(),
+//!     };
+//!     (state, body)
+//! }
+//!
+//! # fn main() {
+//! let map = ShardMap::new()
+//!     .with_shard("db-0", 1)
+//!     .with_shard("db-1", 1);
+//!
+//! // identifies the tenant by a header, where an application would authenticate it
+//! let sharding = Sharding::new(map, |state: &State| {
+//!     HeaderMap::borrow_from(state)
+//!         .get("x-tenant")
+//!         .and_then(|value| value.to_str().ok())
+//!         .map(str::to_owned)
+//! });
+//!
+//! let (chain, pipelines) = single_pipeline(new_pipeline().add(sharding.clone()).build());
+//! let router = build_router(chain, pipelines, |route| {
+//!     route.get("/orders").to(orders);
+//! });
+//!
+//! let test_server = TestServer::new(router).unwrap();
+//! let response = test_server
+//!     .client()
+//!     .get("http://localhost/orders")
+//!     .with_header("x-tenant", "acme".parse().unwrap())
+//!     .perform()
+//!     .unwrap();
+//! assert_eq!(response.status(), StatusCode::OK);
+//! assert!(response.read_utf8_body().unwrap().starts_with("orders of acme on db-"));
+//!
+//! // moves all tenants to a single shard, for requests arriving from now on
+//! sharding.replace(ShardMap::new().with_shard("db-0", 1));
+//!
+//! let response = test_server
+//!     .client()
+//!     .get("http://localhost/orders")
+//!     .with_header("x-tenant", "acme".parse().unwrap())
+//!     .perform()
+//!     .unwrap();
+//! assert_eq!(response.read_utf8_body().unwrap(), "orders of acme on db-0");
+//! # }
+//! ```
+
+use std::panic::RefUnwindSafe;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use log::warn;
+
+use crate::handler::HandlerFuture;
+use crate::middleware::{Middleware, NewMiddleware};
+use crate::state::{request_id, FromState, State, StateData};
+
+/// The number of points a shard owns on the ring per unit of its weight. More points spread the
+/// keys more evenly across the shards.
+const POINTS_PER_WEIGHT: u32 = 100;
+
+/// Hashes `bytes` to a position on the ring, which is stable across processes.
+fn hash(bytes: &[u8]) -> u64 {
+    // FNV-1a, as the std hashers are randomly seeded
+    let hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    // mixes the bits, as FNV-1a hashes of similar inputs are close to each other
+    let hash = (hash ^ (hash >> 33)).wrapping_mul(0xff51_afd7_ed55_8ccd);
+    let hash = (hash ^ (hash >> 33)).wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+/// The shards keys are assigned to, and the hash ring assigning them.
+///
+/// Shards are identified by their index, which is the order they were added in, and by their
+/// name. The name determines the points a shard owns on the ring, so keys are assigned to the
+/// same shard by any map containing the same shards, regardless of their order.
+#[derive(Clone, Debug, Default)]
+pub struct ShardMap {
+    shards: Vec<String>,
+    // (point, index of the shard owning it), sorted by point
+    ring: Vec<(u64, usize)>,
+}
+
+impl ShardMap {
+    /// Creates a map without any shards.
+    pub fn new() -> ShardMap {
+        ShardMap::default()
+    }
+
+    /// Adds the shard `name`, which is assigned a share of the keys proportional to `weight`.
+    ///
+    /// A shard with a weight of 0 keeps its index but isn't assigned any keys, e.g. while it's
+    /// being drained.
+    pub fn with_shard(mut self, name: &str, weight: u32) -> ShardMap {
+        let index = self.shards.len();
+        self.shards.push(name.to_owned());
+        for point in 0..weight.saturating_mul(POINTS_PER_WEIGHT) {
+            let point = hash(format!("{}#{}", name, point).as_bytes());
+            self.ring.push((point, index));
+        }
+        self.ring.sort_unstable();
+        self
+    }
+
+    /// Returns the names of the shards, by index.
+    pub fn shards(&self) -> &[String] {
+        &self.shards
+    }
+
+    /// Returns the number of shards.
+    pub fn len(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Returns `true` if the map has no shards.
+    pub fn is_empty(&self) -> bool {
+        self.shards.is_empty()
+    }
+
+    /// Returns the shard `key` is assigned to, or `None` if no shard has a weight above 0.
+    pub fn shard_for(&self, key: &str) -> Option<Shard> {
+        if self.ring.is_empty() {
+            return None;
+        }
+        let hash = hash(key.as_bytes());
+        let i = self.ring.partition_point(|&(point, _)| point < hash);
+        // wraps around to the first point of the ring
+        let (_, index) = self.ring[i % self.ring.len()];
+        Some(Shard {
+            key: key.to_owned(),
+            index,
+            name: self.shards[index].clone(),
+        })
+    }
+}
+
+/// The shard a request is assigned to, put into `State` by the `Sharding` middleware.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Shard {
+    key: String,
+    index: usize,
+    name: String,
+}
+
+impl Shard {
+    /// Returns the key the request was assigned to the shard by.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns the index of the shard in the `ShardMap`.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the name of the shard.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl StateData for Shard {}
+
+/// Returns the shard the request is assigned to, or `None` if the `Sharding` middleware has not
+/// been invoked for the request, or didn't find a key for it.
+pub fn shard(state: &State) -> Option<&Shard> {
+    Shard::try_borrow_from(state)
+}
+
+type KeyFn = dyn Fn(&State) -> Option<String> + Send + Sync + RefUnwindSafe;
+
+/// The middleware assigning requests to shards, see the module documentation.
+///
+/// Clones of a `Sharding` share the same `ShardMap`, so a clone can be kept to replace the map
+/// after the original was added to a pipeline.
+#[derive(Clone)]
+pub struct Sharding {
+    map: Arc<ArcSwap<ShardMap>>,
+    key: Arc<KeyFn>,
+}
+
+impl Sharding {
+    /// Creates the middleware assigning requests to the shards of `map`, by the key `key` derives
+    /// from the request. Requests `key` returns `None` for aren't assigned to a shard.
+    pub fn new<F>(map: ShardMap, key: F) -> Sharding
+    where
+        F: Fn(&State) -> Option<String> + Send + Sync + RefUnwindSafe + 'static,
+    {
+        Sharding {
+            map: Arc::new(ArcSwap::from_pointee(map)),
+            key: Arc::new(key),
+        }
+    }
+
+    /// Assigns all requests arriving from now on to the shards of `map`, and returns the map it
+    /// replaces.
+    pub fn replace(&self, map: ShardMap) -> Arc<ShardMap> {
+        self.map.swap(Arc::new(map))
+    }
+
+    /// Returns the map requests are currently assigned to shards by.
+    pub fn current(&self) -> Arc<ShardMap> {
+        self.map.load_full()
+    }
+
+    /// Returns the shard the request in `state` is assigned to.
+    pub fn shard(&self, state: &State) -> Option<Shard> {
+        let key = (self.key)(state)?;
+        let shard = self.map.load().shard_for(&key);
+        if shard.is_none() {
+            warn!(
+                "[{}] no shard to assign the request to, as the shard map is empty",
+                request_id(state)
+            );
+        }
+        shard
+    }
+}
+
+impl Middleware for Sharding {
+    fn call<Chain>(self, mut state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>>,
+    {
+        if let Some(shard) = self.shard(&state) {
+            state.put(shard);
+        }
+        chain(state)
+    }
+}
+
+impl NewMiddleware for Sharding {
+    type Instance = Sharding;
+
+    fn new_middleware(&self) -> anyhow::Result<Sharding> {
+        Ok(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> Vec<String> {
+        (0..10_000).map(|i| format!("tenant-{}", i)).collect()
+    }
+
+    fn counts(map: &ShardMap) -> Vec<usize> {
+        let mut counts = vec![0; map.len()];
+        for key in keys() {
+            counts[map.shard_for(&key).unwrap().index()] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn spreads_keys_by_weight() {
+        let map = ShardMap::new()
+            .with_shard("a", 1)
+            .with_shard("b", 1)
+            .with_shard("c", 2);
+        let counts = counts(&map);
+        assert!((1_800..3_200).contains(&counts[0]), "{:?}", counts);
+        assert!((1_800..3_200).contains(&counts[1]), "{:?}", counts);
+        assert!((4_000..6_000).contains(&counts[2]), "{:?}", counts);
+    }
+
+    #[test]
+    fn assignment_is_independent_of_order() {
+        let map = ShardMap::new().with_shard("a", 1).with_shard("b", 1);
+        let reversed = ShardMap::new().with_shard("b", 1).with_shard("a", 1);
+        for key in keys() {
+            assert_eq!(
+                map.shard_for(&key).unwrap().name(),
+                reversed.shard_for(&key).unwrap().name()
+            );
+        }
+    }
+
+    #[test]
+    fn adding_a_shard_only_moves_keys_to_it() {
+        let map = ShardMap::new()
+            .with_shard("a", 1)
+            .with_shard("b", 1)
+            .with_shard("c", 1);
+        let grown = map.clone().with_shard("d", 1);
+        let mut moved = 0;
+        for key in keys() {
+            let before = map.shard_for(&key).unwrap();
+            let after = grown.shard_for(&key).unwrap();
+            if before != after {
+                assert_eq!(after.name(), "d");
+                moved += 1;
+            }
+        }
+        assert!((1_800..3_200).contains(&moved), "{}", moved);
+    }
+
+    #[test]
+    fn shards_without_weight_are_not_assigned() {
+        let map = ShardMap::new().with_shard("a", 0).with_shard("b", 1);
+        assert_eq!(counts(&map), vec![0, 10_000]);
+        assert_eq!(map.shards(), ["a", "b"]);
+
+        assert_eq!(ShardMap::new().shard_for("tenant"), None);
+        assert_eq!(ShardMap::new().with_shard("a", 0).shard_for("tenant"), None);
+    }
+
+    #[test]
+    fn replaces_map_of_clones() {
+        let sharding = Sharding::new(ShardMap::new().with_shard("a", 1), |_: &State| None);
+        let clone = sharding.clone();
+        let previous = clone.replace(ShardMap::new().with_shard("b", 1));
+        assert_eq!(previous.shards(), ["a"]);
+        assert_eq!(sharding.current().shards(), ["b"]);
+    }
+}
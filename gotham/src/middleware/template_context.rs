@@ -0,0 +1,243 @@
+//! Assembles the context shared by every template render once per request, e.g. the current
+//! user, flash messages, the CSRF token, the locale or the asset manifest.
+//!
+//! The `TemplateContextMiddleware` asks each registered `ContextProvider` to add its values to
+//! the `TemplateContext` of the request. Handlers merge their own values into it before
+//! rendering, instead of each rebuilding the same context. The context is plain JSON, which
+//! template engines accept as their context, e.g. via `tera::Context::from_value`.
+//!
+//! ```rust
+//! # extern crate gotham;
+//! # #[macro_use]
+//! # extern crate serde_derive;
+//! #
+//! # use gotham::middleware::template_context::{template_context, TemplateContext, TemplateContextMiddleware};
+//! # use gotham::pipeline::new_pipeline;
+//! # use gotham::pipeline::single::single_pipeline;
+//! # use gotham::router::builder::*;
+//! # use gotham::state::State;
+//! # use gotham::test::TestServer;
+//! #
+//! #[derive(Serialize)]
+//! struct Page {
+//!     title: &'static str,
+//! }
+//!
+//! fn index(state: State) -> (State, String) {
+//!     let context = template_context(&state)
+//!         .merge(&Page { title: "Home" })
+//!         .unwrap();
+//!     // e.g. `tera.render("index.html", &tera::Context::from_value(context)?)`
+//!     let body = format!("{} ({})", context["title"], context["locale"]);
+//!     (state, body)
+//! }
+//!
+//! # fn main() {
+//! let middleware = TemplateContextMiddleware::new()
+//!     .with_provider(|_state: &State, context: &mut TemplateContext| {
+//!         context.insert("locale", "en");
+//!     });
+//!
+//! let (chain, pipelines) = single_pipeline(new_pipeline().add(middleware).build());
+//! let router = build_router(chain, pipelines, |route| {
+//!     route.get("/").to(index);
+//! });
+//!
+//! let test_server = TestServer::new(router).unwrap();
+//! let response = test_server.client().get("http://localhost/").perform().unwrap();
+//! assert_eq!(response.read_utf8_body().unwrap(), r#""Home" ("en")"#);
+//! # }
+//! ```
+
+use std::panic::RefUnwindSafe;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use log::trace;
+use serde::ser::Error as _;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::handler::HandlerFuture;
+use crate::middleware::{Middleware, NewMiddleware};
+use crate::state::{request_id, FromState, State, StateData};
+
+/// Adds the values it is responsible for to the `TemplateContext` of a request.
+pub trait ContextProvider: Send + Sync + RefUnwindSafe {
+    /// Adds values for the request in `state` to `context`.
+    fn provide(&self, state: &State, context: &mut TemplateContext);
+}
+
+impl<F> ContextProvider for F
+where
+    F: Fn(&State, &mut TemplateContext) + Send + Sync + RefUnwindSafe,
+{
+    fn provide(&self, state: &State, context: &mut TemplateContext) {
+        self(state, context)
+    }
+}
+
+/// The values shared by every template rendered for a request, put into `State` by the
+/// `TemplateContextMiddleware`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TemplateContext {
+    values: Map<String, Value>,
+}
+
+impl TemplateContext {
+    /// Creates an empty context.
+    pub fn new() -> TemplateContext {
+        TemplateContext::default()
+    }
+
+    /// Sets `key` to `value`, replacing a value set by an earlier provider.
+    pub fn insert<V>(&mut self, key: &str, value: V)
+    where
+        V: Into<Value>,
+    {
+        self.values.insert(key.to_owned(), value.into());
+    }
+
+    /// Returns the value of `key`, if any.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.values.get(key)
+    }
+
+    /// Returns all values of the context.
+    pub fn values(&self) -> &Map<String, Value> {
+        &self.values
+    }
+
+    /// Returns the context of a single render, which is this context with the fields of
+    /// `values` merged over it. Fails if `values` doesn't serialize into a JSON object.
+    pub fn merge<T>(&self, values: &T) -> serde_json::Result<Value>
+    where
+        T: Serialize + ?Sized,
+    {
+        let mut context = self.values.clone();
+        match serde_json::to_value(values)? {
+            Value::Object(values) => context.extend(values),
+            Value::Null => {}
+            _ => {
+                return Err(serde_json::Error::custom(
+                    "template values must serialize into an object",
+                ))
+            }
+        }
+        Ok(Value::Object(context))
+    }
+}
+
+impl StateData for TemplateContext {}
+
+/// Returns the template context of the request.
+///
+/// # Panics
+///
+/// If the `TemplateContextMiddleware` has not been invoked for the request.
+pub fn template_context(state: &State) -> &TemplateContext {
+    TemplateContext::borrow_from(state)
+}
+
+/// The middleware assembling the `TemplateContext` of each request, see the module
+/// documentation.
+///
+/// Providers run in the order they were registered. A context put into `State` earlier, e.g. by
+/// the middleware of an outer router, is extended rather than replaced.
+#[derive(Clone, Default)]
+pub struct TemplateContextMiddleware {
+    providers: Vec<Arc<dyn ContextProvider>>,
+}
+
+impl TemplateContextMiddleware {
+    /// Creates the middleware without any providers.
+    pub fn new() -> TemplateContextMiddleware {
+        TemplateContextMiddleware::default()
+    }
+
+    /// Registers `provider`, which runs after the providers registered before it.
+    pub fn with_provider<P>(mut self, provider: P) -> TemplateContextMiddleware
+    where
+        P: ContextProvider + 'static,
+    {
+        self.providers.push(Arc::new(provider));
+        self
+    }
+}
+
+impl Middleware for TemplateContextMiddleware {
+    fn call<Chain>(self, mut state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>>,
+    {
+        let mut context = state.try_take::<TemplateContext>().unwrap_or_default();
+        for provider in &self.providers {
+            provider.provide(&state, &mut context);
+        }
+        trace!(
+            "[{}] assembled template context of {} values",
+            request_id(&state),
+            context.values.len()
+        );
+        state.put(context);
+        chain(state)
+    }
+}
+
+impl NewMiddleware for TemplateContextMiddleware {
+    type Instance = TemplateContextMiddleware;
+
+    fn new_middleware(&self) -> anyhow::Result<TemplateContextMiddleware> {
+        Ok(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    use crate::pipeline::new_pipeline;
+    use crate::pipeline::single::single_pipeline;
+    use crate::router::builder::{build_router, DefineSingleRoute, DrawRoutes};
+    use crate::test::TestServer;
+
+    #[test]
+    fn providers_assemble_the_context_in_order() {
+        fn render(state: State) -> (State, String) {
+            let context = template_context(&state)
+                .merge(&json!({ "title": "Home", "user": "handler" }))
+                .unwrap();
+            (state, context.to_string())
+        }
+
+        let middleware = TemplateContextMiddleware::new()
+            .with_provider(|_: &State, context: &mut TemplateContext| {
+                context.insert("locale", "en");
+                context.insert("user", "anonymous");
+            })
+            .with_provider(|_: &State, context: &mut TemplateContext| {
+                context.insert("user", "ann");
+                context.insert("flash", json!(["saved"]));
+            });
+
+        let (chain, pipelines) = single_pipeline(new_pipeline().add(middleware).build());
+        let router = build_router(chain, pipelines, |route| {
+            route.get("/").to(render);
+        });
+        let test_server = TestServer::new(router).unwrap();
+        let response = test_server
+            .client()
+            .get("http://localhost/")
+            .perform()
+            .unwrap();
+
+        let body: Value = serde_json::from_str(&response.read_utf8_body().unwrap()).unwrap();
+        assert_eq!(
+            body,
+            json!({ "locale": "en", "user": "handler", "flash": ["saved"], "title": "Home" })
+        );
+        assert!(TemplateContext::new().merge(&"page").is_err());
+    }
+}
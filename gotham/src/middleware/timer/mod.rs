@@ -1,4 +1,5 @@
 //! Request timing middleware, used to measure response times of requests.
+use crate::clock::{Clock, SharedClock};
 use crate::handler::HandlerFuture;
 use crate::helpers::http::header::X_RUNTIME_DURATION;
 use crate::helpers::timing::Timer;
@@ -22,14 +23,19 @@ impl Middleware for RequestTimer {
         Chain: FnOnce(State) -> Pin<Box<HandlerFuture>>,
     {
         // start the timer
-        let timer = Timer::new();
+        let clock = SharedClock::from_state(&state);
+        let timer = Timer::started_at(clock.now());
 
         // execute the chain and attach the time on complete
         let f = chain(state).and_then(move |(state, mut response)| {
             // attach the formatted header
             response.headers_mut().insert(
                 X_RUNTIME_DURATION,
-                timer.elapsed().to_string().parse().unwrap(),
+                timer
+                    .elapsed_until(clock.now())
+                    .to_string()
+                    .parse()
+                    .unwrap(),
             );
 
             future::ok((state, response))
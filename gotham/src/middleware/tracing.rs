@@ -0,0 +1,243 @@
+//! Defines a middleware which runs each request in a `tracing` span. Requires the `tracing`
+//! feature.
+//!
+//! The span is named `request`, and has the fields `method`, `route` (the template of the
+//! matched route, see `gotham::router::MatchedRoute`) and `request_id` when it is opened, and
+//! `status` and `latency_us` once the response is ready. Handlers and subsequent middleware run
+//! inside the span, so that the events they emit, e.g. via `tracing::info!`, belong to the
+//! request.
+//!
+//! ```rust
+//! # extern crate gotham;
+//! #
+//! # use gotham::middleware::tracing::TracingMiddleware;
+//! # use gotham::pipeline::new_pipeline;
+//! # use gotham::pipeline::single::single_pipeline;
+//! # use gotham::router::builder::*;
+//! # use gotham::state::State;
+//! # use gotham::test::TestServer;
+//! #
+//! fn handler(state: State) -> (State, &'static str) {
+//!     // e.g. `tracing::info!("loading user");`, recorded in the span of the request
+//!     (state, "user")
+//! }
+//!
+//! # fn main() {
+//! let (chain, pipelines) = single_pipeline(new_pipeline().add(TracingMiddleware::new()).build());
+//! let router = build_router(chain, pipelines, |route| {
+//!     route.get("/users/:id").to(handler);
+//! });
+//!
+//! let test_server = TestServer::new(router).unwrap();
+//! let response = test_server
+//!     .client()
+//!     .get("http://localhost/users/1")
+//!     .perform()
+//!     .unwrap();
+//! assert_eq!(response.read_utf8_body().unwrap(), "user");
+//! # }
+//! ```
+
+use std::pin::Pin;
+
+use futures::prelude::*;
+use hyper::Method;
+use tracing::field::{display, Empty};
+use tracing::{info_span, Instrument};
+
+use crate::clock::{Clock, SharedClock};
+use crate::handler::HandlerFuture;
+use crate::helpers::timing::{Timer, Timing};
+use crate::middleware::{Middleware, NewMiddleware};
+use crate::router::MatchedRoute;
+use crate::state::{request_id, FromState, State};
+
+/// Middleware which opens a `tracing` span per request, see the module documentation.
+///
+/// The span is opened at `INFO` level. Add the middleware first to the pipeline, so that the
+/// span covers all other middleware.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TracingMiddleware;
+
+impl TracingMiddleware {
+    /// Creates the middleware.
+    pub fn new() -> TracingMiddleware {
+        TracingMiddleware
+    }
+}
+
+impl Middleware for TracingMiddleware {
+    fn call<Chain>(self, state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    where
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>>,
+    {
+        let span = info_span!(
+            "request",
+            method = %Method::borrow_from(&state),
+            route = Empty,
+            request_id = %request_id(&state),
+            status = Empty,
+            latency_us = Empty,
+        );
+        if let Some(route) = MatchedRoute::try_borrow_from(&state) {
+            span.record("route", display(route.template()));
+        }
+
+        let clock = SharedClock::from_state(&state);
+        let timer = Timer::started_at(clock.now());
+
+        // handlers which aren't async run as soon as the chain is called
+        let f = span.in_scope(|| chain(state));
+        let recorder = span.clone();
+        async move {
+            let result = f.await;
+            let status = match &result {
+                Ok((_, response)) => response.status(),
+                Err((_, err)) => err.status(),
+            };
+            recorder.record("status", status.as_u16());
+            if let Timing::Microseconds(us) = timer.elapsed_until(clock.now()) {
+                recorder.record("latency_us", us);
+            }
+            result
+        }
+        .instrument(span)
+        .boxed()
+    }
+}
+
+impl NewMiddleware for TracingMiddleware {
+    type Instance = TracingMiddleware;
+
+    fn new_middleware(&self) -> anyhow::Result<TracingMiddleware> {
+        Ok(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::fmt;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, SystemTime};
+
+    use hyper::{Body, HeaderMap, Response, StatusCode};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    use crate::clock::ManualClock;
+    use crate::state::set_request_id;
+
+    /// The values recorded for the fields of spans, by span id.
+    type SpanFields = Mutex<Vec<(u64, String, String)>>;
+
+    /// Records the fields of spans, and the span each event was emitted in.
+    #[derive(Default)]
+    struct Recorder {
+        next_id: AtomicU64,
+        fields: Arc<SpanFields>,
+        events: Arc<Mutex<Vec<Option<u64>>>>,
+    }
+
+    thread_local! {
+        static ENTERED: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+    }
+
+    struct Fields<'a>(u64, &'a SpanFields);
+
+    impl Visit for Fields<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            let value = format!("{:?}", value);
+            self.1
+                .lock()
+                .unwrap()
+                .push((self.0, field.name().to_owned(), value));
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+            span.record(&mut Fields(id, &self.fields));
+            Id::from_u64(id)
+        }
+
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            values.record(&mut Fields(span.into_u64(), &self.fields));
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, _: &Event<'_>) {
+            let span = ENTERED.with(|entered| entered.borrow().last().copied());
+            self.events.lock().unwrap().push(span);
+        }
+
+        fn enter(&self, span: &Id) {
+            ENTERED.with(|entered| entered.borrow_mut().push(span.into_u64()));
+        }
+
+        fn exit(&self, _: &Id) {
+            ENTERED.with(|entered| entered.borrow_mut().pop());
+        }
+    }
+
+    #[test]
+    fn records_requests_in_spans() {
+        let recorder = Recorder::default();
+        let fields = recorder.fields.clone();
+        let events = recorder.events.clone();
+
+        let clock = ManualClock::new(SystemTime::UNIX_EPOCH);
+        let mut state = State::new();
+        state.put(Method::GET);
+        state.put(HeaderMap::new());
+        state.put(SharedClock::new(clock.clone()));
+        set_request_id(&mut state);
+        MatchedRoute::put(&mut state, "/users/:id");
+
+        let chain = move |state: State| {
+            tracing::info!("handled");
+            async move {
+                clock.advance(Duration::from_millis(3));
+                tracing::info!("responded");
+                let response = Response::builder()
+                    .status(StatusCode::ACCEPTED)
+                    .body(Body::empty())
+                    .unwrap();
+                Ok((state, response))
+            }
+            .boxed()
+        };
+
+        tracing::subscriber::with_default(recorder, || {
+            let result = futures::executor::block_on(TracingMiddleware::new().call(state, chain));
+            assert!(result.is_ok());
+        });
+
+        let fields = fields.lock().unwrap();
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(id, field, _)| *id == 1 && field == name)
+                .map(|(_, _, value)| value.as_str())
+        };
+        assert_eq!(field("method"), Some("GET"));
+        assert_eq!(field("route"), Some("/users/:id"));
+        assert_eq!(field("status"), Some("202"));
+        assert_eq!(field("latency_us"), Some("3000"));
+        assert!(field("request_id").is_some());
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|span| *span == Some(1)));
+    }
+}
@@ -0,0 +1,97 @@
+//! Readiness notifications for process supervisors, following the `sd_notify` protocol of
+//! systemd services with `Type=notify`.
+//!
+//! The supervisor passes the socket to notify in the `NOTIFY_SOCKET` environment variable, and
+//! only routes traffic to the service once it reported `READY=1`. `ServerSpec` sends the
+//! notification itself if `sd_notify` is set. Applications starting the server otherwise call
+//! `ready` once their listeners are bound and they finished warming up:
+//!
+//! ```rust,no_run
+//! # extern crate gotham;
+//! #
+//! # fn main() {
+//! // e.g. after loading caches and binding all listeners
+//! if let Err(e) = gotham::notify::ready() {
+//!     eprintln!("failed to notify the supervisor: {}", e);
+//! }
+//! # }
+//! ```
+
+use std::io;
+
+/// Reports to the supervisor that the service is ready. Does nothing and returns `Ok(false)` if
+/// `NOTIFY_SOCKET` is not set, e.g. when not started by systemd, or on platforms other than
+/// Unix.
+pub fn ready() -> io::Result<bool> {
+    send("READY=1")
+}
+
+#[cfg(unix)]
+fn send(state: &str) -> io::Result<bool> {
+    match std::env::var_os("NOTIFY_SOCKET") {
+        Some(socket) => unix::send(&socket, state).map(|()| true),
+        None => Ok(false),
+    }
+}
+
+#[cfg(not(unix))]
+fn send(_state: &str) -> io::Result<bool> {
+    Ok(false)
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::ffi::OsStr;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    /// Sends `state` to `socket`, which is a path, or the name of a socket in the abstract
+    /// namespace of Linux if it starts with `@`.
+    pub(super) fn send(socket: &OsStr, state: &str) -> io::Result<()> {
+        let datagram = UnixDatagram::unbound()?;
+
+        match socket.as_bytes().strip_prefix(b"@") {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                use std::os::unix::net::SocketAddr;
+
+                let addr = SocketAddr::from_abstract_name(name)?;
+                datagram.send_to_addr(state.as_bytes(), &addr)?;
+            }
+            #[cfg(not(target_os = "linux"))]
+            Some(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "abstract notification sockets are only supported on Linux",
+                ));
+            }
+            None => {
+                datagram.send_to(state.as_bytes(), socket)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn sends_state_to_socket_path() {
+            let dir = std::env::temp_dir().join(format!("gotham-notify-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("notify.sock");
+            let _ = std::fs::remove_file(&path);
+            let supervisor = UnixDatagram::bind(&path).unwrap();
+
+            send(path.as_os_str(), "READY=1").unwrap();
+
+            let mut buf = [0; 16];
+            let len = supervisor.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..len], b"READY=1");
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
@@ -32,6 +32,12 @@ pub trait PipelineHandleChain<P>: RefUnwindSafe {
     fn call<F>(&self, pipelines: &PipelineSet<P>, state: State, f: F) -> Pin<Box<HandlerFuture>>
     where
         F: FnOnce(State) -> Pin<Box<HandlerFuture>> + Send + 'static;
+
+    /// Returns the type names of the middleware of each `Pipeline` in this `PipelineHandleChain`,
+    /// in the order the pipelines are invoked, see `Pipeline::middleware`.
+    fn middleware(&self, _pipelines: &PipelineSet<P>) -> Vec<Vec<&'static str>> {
+        vec![]
+    }
 }
 
 /// Part of a `PipelineHandleChain` which references a `Pipeline` and continues with a tail element.
@@ -56,6 +62,13 @@ where
             }
         }
     }
+
+    fn middleware(&self, pipelines: &PipelineSet<P>) -> Vec<Vec<&'static str>> {
+        let (handle, ref chain) = *self;
+        let mut middleware = chain.middleware(pipelines);
+        middleware.push(pipelines.borrow(handle).middleware().to_vec());
+        middleware
+    }
 }
 
 /// The marker for the end of a `PipelineHandleChain`.
@@ -5,6 +5,7 @@ pub mod set;
 pub mod single;
 
 use log::trace;
+use std::any::type_name;
 use std::pin::Pin;
 
 use crate::handler::HandlerFuture;
@@ -125,6 +126,7 @@ where
     T: NewMiddlewareChain,
 {
     chain: T,
+    middleware: Vec<&'static str>,
 }
 
 /// Represents an instance of a `Pipeline`. Returned from `Pipeline::construct()`.
@@ -146,6 +148,12 @@ where
             chain: self.chain.construct()?,
         })
     }
+
+    /// Returns the type names of the middleware of this `Pipeline`, in the order they are called,
+    /// e.g. to describe the routes of a `Router`.
+    pub fn middleware(&self) -> &[&'static str] {
+        &self.middleware
+    }
 }
 
 impl<T> PipelineInstance<T>
@@ -169,7 +177,10 @@ where
 pub fn new_pipeline() -> PipelineBuilder<()> {
     trace!(" starting pipeline construction");
     // See: `impl NewMiddlewareChain for ()`
-    PipelineBuilder { t: () }
+    PipelineBuilder {
+        t: (),
+        middleware: vec![],
+    }
 }
 
 /// Constructs a pipeline from a single middleware.
@@ -248,6 +259,7 @@ where
     T: NewMiddlewareChain,
 {
     t: T,
+    middleware: Vec<&'static str>,
 }
 
 impl<T> PipelineBuilder<T>
@@ -260,11 +272,14 @@ where
     where
         T: NewMiddlewareChain,
     {
-        Pipeline { chain: self.t }
+        Pipeline {
+            chain: self.t,
+            middleware: self.middleware,
+        }
     }
 
     /// Adds a `NewMiddleware` which will create a `Middleware` during request dispatch.
-    pub fn add<M>(self, m: M) -> PipelineBuilder<(M, T)>
+    pub fn add<M>(mut self, m: M) -> PipelineBuilder<(M, T)>
     where
         M: NewMiddleware,
         M::Instance: Send + 'static,
@@ -283,7 +298,45 @@ where
         //
         //     PipelineBuilder { t: () }
         trace!(" adding middleware to pipeline");
-        PipelineBuilder { t: (m, self.t) }
+        self.middleware.push(type_name::<M>());
+        PipelineBuilder {
+            t: (m, self.t),
+            middleware: self.middleware,
+        }
+    }
+
+    /// Adds a `NewMiddleware` which is only active if `condition` holds, e.g. to enable
+    /// development tooling outside of production deployments. Requests are passed straight through
+    /// to the next middleware otherwise.
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// #
+    /// # use gotham::environment::Environment;
+    /// # use gotham::middleware::logger::RequestLogger;
+    /// # use gotham::pipeline::new_pipeline;
+    /// #
+    /// # fn main() {
+    /// let env = Environment::current();
+    ///
+    /// new_pipeline()
+    ///     .add_when(env.is_dev(), RequestLogger::new(log::Level::Debug))
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn add_when<M>(self, condition: bool, m: M) -> PipelineBuilder<(Option<M>, T)>
+    where
+        M: NewMiddleware,
+        M::Instance: Send + 'static,
+        Self: Sized,
+    {
+        let mut builder = self.add(if condition { Some(m) } else { None });
+        // name the middleware itself rather than the `Option`, and only while it is active
+        builder.middleware.pop();
+        if condition {
+            builder.middleware.push(type_name::<M>());
+        }
+        builder
     }
 }
 
@@ -1,10 +1,11 @@
 use futures::prelude::*;
-use log::info;
+use hyper::server::conn::Http;
+use log::{error, info};
 
-use std::net::ToSocketAddrs;
+use std::net::{SocketAddr, ToSocketAddrs};
 
 use super::handler::NewHandler;
-use super::{bind_server, new_runtime, tcp_listener};
+use super::{bind_server_with_protocol, new_runtime, tcp_listeners};
 
 pub mod test;
 
@@ -37,14 +38,98 @@ where
     NH: NewHandler + 'static,
     A: ToSocketAddrs + 'static + Send,
 {
-    let listener = tcp_listener(addr).map_err(|_| ()).await?;
-    let addr = listener.local_addr().unwrap();
+    init_server_with_ready(addr, new_handler, |_| ()).await
+}
+
+/// Returns a `Future` used to spawn a Gotham application like `init_server`, calling `ready`
+/// with the bound addresses once all of them are bound, before connections are accepted.
+///
+/// This allows finding out the port chosen for an address with port `0`.
+///
+/// ```rust,no_run
+/// # extern crate gotham;
+/// # extern crate hyper;
+/// #
+/// # use gotham::state::State;
+/// # use hyper::{Body, Response};
+/// #
+/// # fn handler(state: State) -> (State, Response<Body>) {
+/// #     (state, Response::new(Body::empty()))
+/// # }
+/// #
+/// # fn main() {
+/// let server = gotham::plain::init_server_with_ready("127.0.0.1:0", || Ok(handler), |addrs| {
+///     for addr in addrs {
+///         println!("listening on {}", addr);
+///     }
+/// });
+/// # drop(server);
+/// # }
+/// ```
+pub async fn init_server_with_ready<NH, A, F>(addr: A, new_handler: NH, ready: F) -> Result<(), ()>
+where
+    NH: NewHandler + 'static,
+    A: ToSocketAddrs + 'static + Send,
+    F: FnOnce(&[SocketAddr]),
+{
+    let listeners = tcp_listeners(addr, None)
+        .map_err(|e| error!(target: "gotham::start", "{}", e))
+        .await?;
+
+    let addrs = listeners
+        .iter()
+        .map(|listener| listener.local_addr())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| error!(target: "gotham::start", "{}", e))?;
+
+    for addr in &addrs {
+        info!(
+        target: "gotham::start",
+        " Gotham listening on http://{}",
+        addr
+        );
+    }
+    ready(&addrs);
+
+    bind_server_with_protocol(listeners, new_handler, future::ok, Http::new()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hyper::{Body, Response};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::mpsc;
+
+    use crate::state::State;
+
+    fn handler(state: State) -> (State, Response<Body>) {
+        (state, Response::new(Body::from("ready")))
+    }
+
+    #[test]
+    fn reports_the_bound_addresses() {
+        let (tx, rx) = mpsc::channel();
+        let runtime = new_runtime(1);
+        runtime.spawn(init_server_with_ready(
+            "127.0.0.1:0",
+            || Ok(handler),
+            move |addrs| tx.send(addrs.to_vec()).unwrap(),
+        ));
 
-    info!(
-    target: "gotham::start",
-    " Gotham listening on http://{}",
-    addr
-    );
+        let addrs = rx.recv().unwrap();
+        assert_eq!(addrs.len(), 1);
+        assert_ne!(addrs[0].port(), 0);
 
-    bind_server(listener, new_handler, future::ok).await
+        let mut stream = TcpStream::connect(addrs[0]).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("ready"));
+    }
 }
@@ -21,14 +21,18 @@ use tokio::time::{sleep, Sleep};
 use hyper::service::Service;
 use tokio::net::TcpStream;
 
+use crate::clock::SharedClock;
 use crate::handler::NewHandler;
 
-use crate::test::{self, TestClient};
+use crate::state::request_id::RequestIdGenerator;
+use crate::test::{self, Determinism, HarRecorder, TestClient};
 
 struct TestServerData {
     addr: SocketAddr,
     timeout: u64,
     runtime: RwLock<Runtime>,
+    har: RwLock<Option<HarRecorder>>,
+    determinism: Determinism,
 }
 
 /// The `TestServer` type, which is used as a harness when writing test cases for Hyper services
@@ -86,6 +90,14 @@ impl test::Server for TestServer {
             .expect("unable to acquire write lock")
             .block_on(future)
     }
+
+    fn har_recorder(&self) -> Option<HarRecorder> {
+        self.data
+            .har
+            .read()
+            .expect("unable to acquire read lock")
+            .clone()
+    }
 }
 
 impl TestServer {
@@ -113,14 +125,18 @@ impl TestServer {
         // TODO: Fix this into an async flow
         let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0".parse::<SocketAddr>()?))?;
         let addr = listener.local_addr()?;
+        let determinism = Determinism::default();
+        let new_handler = determinism.wrap(new_handler);
 
-        let service_stream = super::bind_server(listener, new_handler, future::ok);
+        let service_stream = crate::bind_server(listener, new_handler, future::ok);
         runtime.spawn(service_stream); // Ignore the result
 
         let data = TestServerData {
             addr,
             timeout,
             runtime: RwLock::new(runtime),
+            har: RwLock::new(None),
+            determinism,
         };
 
         Ok(TestServer {
@@ -128,6 +144,43 @@ impl TestServer {
         })
     }
 
+    /// Generates the ids of subsequent requests which don't provide an `X-Request-ID` header with
+    /// `generator`, instead of random UUIDs, so that they are reproducible.
+    pub fn with_request_ids(self, generator: RequestIdGenerator) -> TestServer {
+        self.data.determinism.set_request_ids(generator);
+        self
+    }
+
+    /// Puts a `SeededRng` created from `seed` into the `State` of subsequent requests, so that
+    /// random values generated for them by the application are reproducible.
+    ///
+    /// Session identifiers are only seeded through `NewSessionMiddleware::with_seeded_rng`.
+    pub fn with_seed(self, seed: u64) -> TestServer {
+        self.data.determinism.set_seed(seed);
+        self
+    }
+
+    /// Puts `clock` into the `State` of subsequent requests, so that the framework reads the
+    /// current time from it, e.g. from a `ManualClock` controlled by the test.
+    pub fn with_clock(self, clock: SharedClock) -> TestServer {
+        self.data.determinism.set_clock(clock);
+        self
+    }
+
+    /// Records the requests performed by the clients of this `TestServer` from now on, along with
+    /// their responses, so that they can be retrieved as a HAR log through `har`.
+    ///
+    /// Recording buffers every request body of a known size and every response body which is
+    /// read, so it is disabled unless requested.
+    pub fn with_har(self) -> TestServer {
+        self.data
+            .har
+            .write()
+            .expect("unable to acquire write lock")
+            .get_or_insert_with(HarRecorder::default);
+        self
+    }
+
     /// Returns a client connected to the `TestServer`. The transport is handled internally, and
     /// the server will see a default socket address of `127.0.0.1:10000` as the source address for
     /// the connection.
@@ -149,6 +202,45 @@ impl TestServer {
             .spawn(fut);
     }
 
+    /// Returns the requests performed by the clients of this `TestServer` so far, and their
+    /// responses, as a [HAR](https://w3c.github.io/web-performance/specs/HAR/Overview.html) log.
+    ///
+    /// The log can be written to a file when a test fails, and opened in the developer tools of a
+    /// browser or shared as a reproduction. Response bodies are only included once they have been
+    /// read through the `TestResponse`. The log is empty unless recording was enabled through
+    /// `with_har`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// #
+    /// # use gotham::state::State;
+    /// #
+    /// # fn my_handler(state: State) -> (State, &'static str) {
+    /// #   (state, "Hello, world!")
+    /// # }
+    /// #
+    /// # fn main() {
+    /// use gotham::test::TestServer;
+    ///
+    /// let test_server = TestServer::new(|| Ok(my_handler)).unwrap().with_har();
+    /// let response = test_server.client().get("http://localhost/").perform().unwrap();
+    /// response.read_body().unwrap();
+    ///
+    /// let har = test_server.har();
+    /// let entry = &har["log"]["entries"][0];
+    /// assert_eq!(entry["request"]["url"], "http://localhost/");
+    /// assert_eq!(entry["response"]["status"], 200);
+    /// assert_eq!(entry["response"]["content"]["text"], "Hello, world!");
+    /// # }
+    /// ```
+    pub fn har(&self) -> serde_json::Value {
+        test::Server::har_recorder(self)
+            .unwrap_or_default()
+            .to_har()
+    }
+
     /// Returns a client connected to the `TestServer`. The transport is handled internally, and
     /// the server will see `client_addr` as the source address for the connection. The
     /// `client_addr` can be any valid `SocketAddr`, and need not be contactable.
@@ -212,7 +304,8 @@ mod tests {
 
     use crate::handler::{Handler, HandlerFuture, NewHandler};
     use crate::helpers::http::response::create_response;
-    use crate::state::{client_addr, FromState, State};
+    use crate::state::{client_addr, request_id, FromState, State};
+    use crate::test::TestResponse;
     use http::header::CONTENT_TYPE;
     use log::info;
 
@@ -392,4 +485,84 @@ mod tests {
         assert_eq!(content_length, &format!("{}", buf.len()));
         assert_eq!(data, &buf);
     }
+
+    #[test]
+    fn records_har() {
+        fn handler(mut state: State) -> Pin<Box<HandlerFuture>> {
+            body::to_bytes(Body::take_from(&mut state))
+                .then(move |full_body| match full_body {
+                    Ok(body) => {
+                        let res = create_response(&state, StatusCode::OK, mime::TEXT_PLAIN, body);
+                        future::ok((state, res))
+                    }
+                    Err(e) => future::err((state, e.into())),
+                })
+                .boxed()
+        }
+
+        let server = TestServer::new(|| Ok(handler)).unwrap().with_har();
+        let client = server.client();
+
+        let res = client
+            .post("http://host/echo?lang=en", "hello", mime::TEXT_PLAIN)
+            .perform()
+            .unwrap();
+        assert_eq!(res.read_utf8_body().unwrap(), "hello");
+        client.get("http://host/unread").perform().unwrap();
+
+        let har = server.har();
+        let entries = har["log"]["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+
+        let request = &entries[0]["request"];
+        assert_eq!(request["method"], "POST");
+        assert_eq!(request["url"], "http://host/echo?lang=en");
+        assert_eq!(request["queryString"][0]["name"], "lang");
+        assert_eq!(request["bodySize"], 5);
+        assert_eq!(request["postData"]["mimeType"], "text/plain");
+        assert_eq!(request["postData"]["text"], "hello");
+
+        let response = &entries[0]["response"];
+        assert_eq!(response["status"], 200);
+        assert_eq!(response["content"]["size"], 5);
+        assert_eq!(response["content"]["text"], "hello");
+
+        assert_eq!(entries[1]["request"]["method"], "GET");
+        assert_eq!(entries[1]["response"]["content"]["size"], -1);
+    }
+
+    #[test]
+    fn records_har_only_when_enabled() {
+        let server = TestServer::new(|| Ok(|state| (state, "hello"))).unwrap();
+        let res = server.client().get("http://host/").perform().unwrap();
+        assert_eq!(res.read_utf8_body().unwrap(), "hello");
+
+        let har = server.har();
+        assert!(har["log"]["entries"].as_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn generates_deterministic_request_ids() {
+        let server = TestServer::new(|| {
+            Ok(|state: State| {
+                let id = request_id(&state).to_owned();
+                (state, id)
+            })
+        })
+        .unwrap()
+        .with_request_ids(RequestIdGenerator::sequential("test"));
+        let client = server.client();
+
+        let read_id = |response: TestResponse| response.read_utf8_body().unwrap();
+        let res = client.get("http://host/").perform().unwrap();
+        assert_eq!(read_id(res), "test-1");
+        let res = client
+            .get("http://host/")
+            .with_header("X-Request-ID", "external".parse().unwrap())
+            .perform()
+            .unwrap();
+        assert_eq!(read_id(res), "external");
+        let res = client.get("http://host/").perform().unwrap();
+        assert_eq!(read_id(res), "test-2");
+    }
 }
@@ -0,0 +1,246 @@
+//! Versioning of the routes of an API, see `DrawRoutes::api_version`.
+
+use std::sync::Arc;
+
+use hyper::header::{HeaderMap, HeaderName, ACCEPT};
+use hyper::StatusCode;
+use mime::Mime;
+
+use crate::router::non_match::RouteNonMatch;
+use crate::state::{FromState, State, StateData};
+
+/// The version of the API which a request was routed to, which is available in `State` for
+/// every route drawn via `DrawRoutes::api_version`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ApiVersion {
+    version: String,
+}
+
+impl ApiVersion {
+    /// Returns the version, as declared for the routes which matched the request.
+    pub fn as_str(&self) -> &str {
+        &self.version
+    }
+}
+
+impl StateData for ApiVersion {}
+
+/// Declares where requests name the version of the API they are meant for, see
+/// `DrawRoutes::set_api_versioning`.
+#[derive(Clone, Debug)]
+pub struct ApiVersioning {
+    source: VersionSource,
+    default: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+enum VersionSource {
+    PathPrefix,
+    MediaType(String),
+    Header(HeaderName),
+}
+
+impl ApiVersioning {
+    /// Versions are the first segment of the request path, e.g. `/v1/users`. This is the default.
+    pub fn path_prefix() -> ApiVersioning {
+        ApiVersioning {
+            source: VersionSource::PathPrefix,
+            default: None,
+        }
+    }
+
+    /// Versions are part of a vendor media type in the `Accept` header, e.g.
+    /// `application/vnd.example.v1+json` for the vendor `example`. Requests accepting no media
+    /// type of the vendor, or a different version, are answered with `406 Not Acceptable`.
+    pub fn media_type(vendor: &str) -> ApiVersioning {
+        ApiVersioning {
+            source: VersionSource::MediaType(format!("vnd.{}.", vendor)),
+            default: None,
+        }
+    }
+
+    /// Versions are the value of the header `name`, e.g. `Api-Version: v1`. Requests without
+    /// the header, or naming a different version, are answered with `404 Not Found`.
+    pub fn header(name: HeaderName) -> ApiVersioning {
+        ApiVersioning {
+            source: VersionSource::Header(name),
+            default: None,
+        }
+    }
+
+    /// Routes requests which name no version to `version`. This has no effect for versions in
+    /// the path prefix, where every request names its version.
+    pub fn with_default(self, version: &str) -> ApiVersioning {
+        ApiVersioning {
+            default: Some(version.to_owned()),
+            ..self
+        }
+    }
+
+    /// Returns the version named by the request, or the default version if it names none.
+    fn resolve(&self, headers: &HeaderMap) -> Option<String> {
+        let named = match &self.source {
+            VersionSource::PathPrefix => None,
+            VersionSource::Header(name) => headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.trim().to_owned()),
+            VersionSource::MediaType(prefix) => headers
+                .get_all(ACCEPT)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .filter_map(|media_type| media_type.trim().parse::<Mime>().ok())
+                .find_map(|media_type| {
+                    media_type
+                        .subtype()
+                        .as_str()
+                        .strip_prefix(prefix.as_str())
+                        .map(str::to_owned)
+                }),
+        };
+        named.or_else(|| self.default.clone())
+    }
+}
+
+impl Default for ApiVersioning {
+    fn default() -> Self {
+        ApiVersioning::path_prefix()
+    }
+}
+
+/// The versioning of the routes drawn by a builder, and the version they belong to if they are
+/// drawn via `DrawRoutes::api_version`.
+#[doc(hidden)]
+#[derive(Clone, Default)]
+pub struct ApiVersionScope {
+    versioning: ApiVersioning,
+    version: Option<Arc<RouteApiVersion>>,
+}
+
+impl ApiVersionScope {
+    pub(crate) fn set_versioning(&mut self, versioning: ApiVersioning) {
+        self.versioning = versioning;
+    }
+
+    /// Returns the scope of the routes belonging to `version`, and the path prefix they are
+    /// drawn below.
+    pub(crate) fn with_version(&self, version: &str) -> (ApiVersionScope, String) {
+        let path = match self.versioning.source {
+            VersionSource::PathPrefix => format!("/{}", version),
+            _ => String::from("/"),
+        };
+        let scope = ApiVersionScope {
+            versioning: self.versioning.clone(),
+            version: Some(Arc::new(RouteApiVersion {
+                versioning: self.versioning.clone(),
+                version: version.to_owned(),
+            })),
+        };
+        (scope, path)
+    }
+
+    pub(crate) fn route_version(&self) -> Option<Arc<RouteApiVersion>> {
+        self.version.clone()
+    }
+}
+
+/// The version of a single route.
+pub(crate) struct RouteApiVersion {
+    versioning: ApiVersioning,
+    version: String,
+}
+
+impl RouteApiVersion {
+    /// Returns `true` if the route only matches requests naming its version, besides its path
+    /// and methods.
+    pub(crate) fn is_conditional(&self) -> bool {
+        !matches!(self.versioning.source, VersionSource::PathPrefix)
+    }
+
+    pub(crate) fn is_match(&self, state: &State) -> Result<(), RouteNonMatch> {
+        if !self.is_conditional() {
+            return Ok(());
+        }
+        match self.versioning.resolve(HeaderMap::borrow_from(state)) {
+            Some(ref version) if *version == self.version => Ok(()),
+            _ => match self.versioning.source {
+                VersionSource::MediaType(_) => Err(RouteNonMatch::new(StatusCode::NOT_ACCEPTABLE)),
+                _ => Err(RouteNonMatch::new(StatusCode::NOT_FOUND)),
+            },
+        }
+    }
+
+    pub(crate) fn put(&self, state: &mut State) {
+        state.put(ApiVersion {
+            version: self.version.clone(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hyper::header::HeaderValue;
+
+    use crate::router::builder::{build_simple_router, DefineSingleRoute, DrawRoutes};
+    use crate::test::TestServer;
+
+    fn headers(name: HeaderName, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn resolves_versions_of_vendor_media_types() {
+        let versioning = ApiVersioning::media_type("example");
+        let accept = headers(ACCEPT, "text/html, application/vnd.example.v2+json;q=0.9");
+        assert_eq!(versioning.resolve(&accept), Some("v2".to_owned()));
+
+        let accept = headers(ACCEPT, "application/vnd.other.v2+json");
+        assert_eq!(versioning.resolve(&accept), None);
+        let versioning = versioning.with_default("v1");
+        assert_eq!(versioning.resolve(&accept), Some("v1".to_owned()));
+    }
+
+    #[test]
+    fn routes_requests_by_vendor_media_type() {
+        fn users(state: State) -> (State, String) {
+            let version = ApiVersion::borrow_from(&state).as_str().to_owned();
+            (state, version)
+        }
+
+        let router = build_simple_router(|route| {
+            route.set_api_versioning(ApiVersioning::media_type("example"));
+            route.api_version("v1", |route| {
+                route.get("/users").to(users);
+            });
+            route.api_version("v2", |route| {
+                route.associate("/users", |assoc| {
+                    assoc.get().to(users);
+                    assoc.post().to(users);
+                });
+            });
+        });
+        let test_server = TestServer::new(router).unwrap();
+        let get = |accept: &'static str| {
+            test_server
+                .client()
+                .get("http://localhost/users")
+                .with_header(ACCEPT, HeaderValue::from_static(accept))
+                .perform()
+                .unwrap()
+        };
+
+        let response = get("application/vnd.example.v1+json");
+        assert_eq!(response.read_utf8_body().unwrap(), "v1");
+        let response = get("application/vnd.example.v2+json");
+        assert_eq!(response.read_utf8_body().unwrap(), "v2");
+        let response = get("application/vnd.example.v3+json");
+        assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+        let response = get("application/json");
+        assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+    }
+}
@@ -0,0 +1,64 @@
+//! Enforces the maximum request body size declared for a route, see
+//! `DrawRoutes::set_body_limit`.
+
+use std::error::Error;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use futures::prelude::*;
+use hyper::header::{HeaderMap, CONTENT_LENGTH};
+use hyper::{Body, StatusCode};
+use log::trace;
+
+use crate::handler::{HandlerError, HandlerFuture};
+use crate::helpers::http::body::BodyTooLarge;
+use crate::state::{request_id, FromState, State};
+
+/// Dispatches the request in `state` with `dispatch`, unless it declares a `Content-Length`
+/// above `limit`, which fails with `413 Payload Too Large` right away.
+///
+/// Bodies without a `Content-Length` fail to be read once they exceed `limit`, and an error
+/// returned by `dispatch` after that is given the status `413 Payload Too Large`.
+pub(crate) fn limit_body<F>(mut state: State, limit: u64, dispatch: F) -> Pin<Box<HandlerFuture>>
+where
+    F: FnOnce(State) -> Pin<Box<HandlerFuture>>,
+{
+    let declared = HeaderMap::borrow_from(&state)
+        .get(CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok())
+        .and_then(|len| len.parse::<u64>().ok());
+
+    if declared.is_some_and(|len| len > limit) {
+        trace!("[{}] rejecting oversized request body", request_id(&state));
+        let err =
+            HandlerError::from(BodyTooLarge(limit)).with_status(StatusCode::PAYLOAD_TOO_LARGE);
+        return future::err((state, err)).boxed();
+    }
+
+    let exceeded = Arc::new(AtomicBool::new(false));
+    if let Some(body) = state.try_take::<Body>() {
+        let exceeded = exceeded.clone();
+        let mut read = 0;
+        let limited = body.map(move |chunk| {
+            let chunk = chunk?;
+            read += chunk.len() as u64;
+            if read > limit {
+                exceeded.store(true, Ordering::Relaxed);
+                return Err(Box::new(BodyTooLarge(limit)) as Box<dyn Error + Send + Sync>);
+            }
+            Ok(chunk)
+        });
+        state.put(Body::wrap_stream(limited));
+    }
+
+    dispatch(state)
+        .map_err(move |(state, err)| {
+            if exceeded.load(Ordering::Relaxed) {
+                (state, err.with_status(StatusCode::PAYLOAD_TOO_LARGE))
+            } else {
+                (state, err)
+            }
+        })
+        .boxed()
+}
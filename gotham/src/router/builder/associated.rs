@@ -1,15 +1,19 @@
+use std::any::Any;
 use std::marker::PhantomData;
 use std::panic::RefUnwindSafe;
+use std::sync::Arc;
 
 use hyper::{Body, Method};
 
 use crate::extractor::{PathExtractor, QueryStringExtractor};
 use crate::pipeline::chain::PipelineHandleChain;
 use crate::pipeline::set::PipelineSet;
+use crate::router::api_version::RouteApiVersion;
 use crate::router::builder::SingleRouteBuilder;
 use crate::router::route::matcher::{
     AndRouteMatcher, AnyRouteMatcher, MethodOnlyRouteMatcher, RouteMatcher,
 };
+use crate::router::route::middleware::RouteMiddleware;
 use crate::router::tree::node::Node;
 
 pub type AssociatedRouteBuilderMatcher<M, NM> = AndRouteMatcher<M, NM>;
@@ -34,6 +38,7 @@ where
     matcher: M,
     pipeline_chain: C,
     pipelines: PipelineSet<P>,
+    api_version: Option<Arc<RouteApiVersion>>,
     phantom: PhantomData<(PE, QSE)>,
 }
 
@@ -51,9 +56,18 @@ where
             matcher: AnyRouteMatcher::new(),
             pipeline_chain,
             pipelines,
+            api_version: None,
             phantom: PhantomData,
         }
     }
+
+    /// Restricts the associated routes to requests for an API version.
+    pub(crate) fn with_api_version(self, api_version: Option<Arc<RouteApiVersion>>) -> Self {
+        AssociatedRouteBuilder {
+            api_version,
+            ..self
+        }
+    }
 }
 
 impl<'a, M, C, P, PE, QSE> AssociatedRouteBuilder<'a, M, C, P, PE, QSE>
@@ -128,6 +142,7 @@ where
             matcher,
             pipeline_chain: self.pipeline_chain,
             pipelines: self.pipelines.clone(),
+            api_version: self.api_version.clone(),
             phantom: PhantomData,
         }
     }
@@ -192,6 +207,7 @@ where
             matcher: self.matcher.clone(),
             pipeline_chain: self.pipeline_chain,
             pipelines: self.pipelines.clone(),
+            api_version: self.api_version.clone(),
             phantom: PhantomData,
         }
     }
@@ -257,6 +273,7 @@ where
             matcher: self.matcher.clone(),
             pipeline_chain: self.pipeline_chain,
             pipelines: self.pipelines.clone(),
+            api_version: self.api_version.clone(),
             phantom: PhantomData,
         }
     }
@@ -322,14 +339,36 @@ where
             ref matcher,
             ref pipeline_chain,
             ref pipelines,
+            ref api_version,
             phantom,
         } = *self;
 
+        // the route is only conditional if the associated route has a matcher of its own
+        let conditional = (matcher as &dyn Any)
+            .downcast_ref::<AnyRouteMatcher>()
+            .is_none()
+            || api_version.as_ref().is_some_and(|v| v.is_conditional());
+
         SingleRouteBuilder {
             node_builder: *node_builder,
-            matcher: AndRouteMatcher::new(MethodOnlyRouteMatcher::new(methods), matcher.clone()),
+            matcher: AndRouteMatcher::new(
+                MethodOnlyRouteMatcher::new(methods.clone()),
+                matcher.clone(),
+            ),
             pipeline_chain: *pipeline_chain,
             pipelines: pipelines.clone(),
+            middleware: RouteMiddleware::default(),
+            timeout: None,
+            priority: None,
+            name: None,
+            methods,
+            conditional,
+            error_format: None,
+            path_decoding: None,
+            match_priority: None,
+            body_limit: None,
+            api_version: api_version.clone(),
+            async_matchers: vec![],
             phantom,
         }
     }
@@ -1,22 +1,30 @@
+use std::any::Any;
 use std::marker::PhantomData;
 use std::panic::RefUnwindSafe;
+use std::sync::Arc;
 
-use hyper::Method;
+use hyper::{Body, Method};
 use log::trace;
 
-use crate::extractor::{NoopPathExtractor, NoopQueryStringExtractor};
+use crate::extractor::{NoopPathExtractor, NoopQueryStringExtractor, PathExtractor};
+use crate::handler::ErrorFormat;
 use crate::helpers::http::request::path::split_path_segments;
+use crate::middleware::cors::CorsConfig;
 use crate::pipeline::chain::PipelineHandleChain;
 use crate::pipeline::set::PipelineSet;
+use crate::router::api_version::ApiVersionScope;
 use crate::router::builder::{
     AssociatedRouteBuilder, DelegateRouteBuilder, RouterBuilder, ScopeBuilder, SingleRouteBuilder,
 };
 use crate::router::route::matcher::{
     AnyRouteMatcher, IntoRouteMatcher, MethodOnlyRouteMatcher, RouteMatcher,
 };
+use crate::router::route::middleware::RouteMiddleware;
+use crate::router::scope_extractor::ScopePathExtractorImpl;
 use crate::router::tree::node::Node;
 use crate::router::tree::regex::ConstrainedSegmentRegex;
 use crate::router::tree::segment::SegmentType;
+use crate::router::{ApiVersioning, PathDecoding};
 
 /// The type returned when building a route that only considers path and http verb(s) when
 /// determining if it matches a request.
@@ -45,6 +53,56 @@ pub type DefaultAssociatedRouteBuilder<'a, M, C, P> =
 /// Defines functions used by a builder to determine which request paths will be dispatched to a
 /// route. This trait is implemented by the top-level `RouterBuilder`, and also the `ScopedBuilder`
 /// created by `DrawRoutes::scope`.
+///
+/// # Paths
+///
+/// The segments of a path are either:
+///
+/// - static, e.g. `users`, matching only that segment (a leading `\` escapes a `:` or `*`),
+/// - dynamic, e.g. `:id`, matching any segment,
+/// - constrained, e.g. `:id:[0-9]+`, matching only segments matched entirely by the regex, or
+/// - a glob, e.g. `*` or `*rest`, matching one or more segments.
+///
+/// Dynamic, constrained and glob segments are available to the path extractor of the route
+/// under their names, `*` for an unnamed glob. Sibling segments are tried in the order above,
+/// so requests not matched by a constrained segment fall through to a dynamic one:
+///
+/// ```rust
+/// # extern crate gotham;
+/// # extern crate hyper;
+/// #
+/// # use gotham::router::builder::*;
+/// # use gotham::router::MatchedRoute;
+/// # use gotham::state::{FromState, State};
+/// # use gotham::test::TestServer;
+/// #
+/// fn handler(state: State) -> (State, String) {
+///     let template = MatchedRoute::borrow_from(&state).template().to_owned();
+///     (state, template)
+/// }
+///
+/// # fn main() {
+/// let router = build_simple_router(|route| {
+///     route.get("/users/new").to(handler);
+///     route.get("/users/:id:[0-9]+").to(handler);
+///     route.get("/users/:name").to(handler);
+/// });
+///
+/// let test_server = TestServer::new(router).unwrap();
+/// let template = |uri| {
+///     let response = test_server.client().get(uri).perform().unwrap();
+///     response.read_utf8_body().unwrap()
+/// };
+///
+/// assert_eq!(template("http://localhost/users/new"), "/users/new");
+/// assert_eq!(template("http://localhost/users/42"), "/users/:id");
+/// assert_eq!(template("http://localhost/users/alice"), "/users/:name");
+/// # }
+/// ```
+///
+/// Once a segment is chosen, the remaining segments of the request must match below it, as the
+/// `Router` doesn't go back to try its siblings. `DefineSingleRoute::with_match_priority` changes
+/// the order in which siblings are tried.
 pub trait DrawRoutes<C, P>
 where
     C: PipelineHandleChain<P> + Copy + Send + Sync + 'static,
@@ -462,15 +520,39 @@ where
         IRM: IntoRouteMatcher<Output = M>,
         M: RouteMatcher + Send + Sync + 'static,
     {
+        let api_version = self.api_version_scope().route_version();
         let (node_builder, pipeline_chain, pipelines) = self.component_refs();
         let node_builder = descend(node_builder, path);
+        if defines_trailing_slash(path) {
+            node_builder.set_trailing_slash();
+        }
         let matcher = matcher.into_route_matcher();
 
+        // routes matching on anything besides the method can't shadow later routes
+        let (methods, mut conditional) =
+            match (&matcher as &dyn Any).downcast_ref::<MethodOnlyRouteMatcher>() {
+                Some(methods) => (methods.methods().to_vec(), false),
+                None => (vec![], true),
+            };
+        conditional |= api_version.as_ref().is_some_and(|v| v.is_conditional());
+
         SingleRouteBuilder {
             matcher,
             node_builder,
             pipeline_chain: *pipeline_chain,
             pipelines: pipelines.clone(),
+            middleware: RouteMiddleware::default(),
+            timeout: None,
+            priority: None,
+            name: None,
+            methods,
+            conditional,
+            error_format: None,
+            path_decoding: None,
+            match_priority: None,
+            body_limit: None,
+            api_version,
+            async_matchers: vec![],
             phantom: PhantomData,
         }
     }
@@ -518,6 +600,7 @@ where
     where
         F: FnOnce(&mut ScopeBuilder<C, P>),
     {
+        let api_version = self.api_version_scope().clone();
         let (node_builder, pipeline_chain, pipelines) = self.component_refs();
         let node_builder = descend(node_builder, path);
 
@@ -525,11 +608,118 @@ where
             node_builder,
             pipeline_chain: *pipeline_chain,
             pipelines: pipelines.clone(),
+            api_version,
         };
 
         f(&mut scope_builder)
     }
 
+    /// Draws the routes defined by `f` only if `condition` holds, e.g. to keep development
+    /// endpoints out of the routing tree of a production deployment.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # extern crate hyper;
+    /// #
+    /// # use hyper::StatusCode;
+    /// # use gotham::environment::Environment;
+    /// # use gotham::router::Router;
+    /// # use gotham::router::builder::*;
+    /// # use gotham::state::State;
+    /// # use gotham::test::TestServer;
+    /// #
+    /// fn debug_handler(state: State) -> (State, &'static str) {
+    ///     (state, "debug information")
+    /// }
+    ///
+    /// fn router(env: Environment) -> Router {
+    ///     build_simple_router(|route| {
+    ///         route.when(env.is_dev(), |route| {
+    ///             route.get("/__debug").to(debug_handler);
+    ///         });
+    ///     })
+    /// }
+    /// #
+    /// # fn main() {
+    /// #   let test_server = TestServer::new(router(Environment::Production)).unwrap();
+    /// #   let response = test_server.client()
+    /// #       .get("https://example.com/__debug")
+    /// #       .perform()
+    /// #       .unwrap();
+    /// #   assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    /// # }
+    /// ```
+    fn when<F>(&mut self, condition: bool, f: F)
+    where
+        F: FnOnce(&mut Self),
+        Self: Sized,
+    {
+        if condition {
+            f(self)
+        }
+    }
+
+    /// Begins defining a new scope at `path` like `scope`, but only if `condition` holds, e.g. for
+    /// endpoints behind a feature flag. Otherwise neither the scope nor its routes are added, so
+    /// requests for them are answered like those for any other undefined path.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # extern crate hyper;
+    /// #
+    /// # use hyper::StatusCode;
+    /// # use gotham::router::Router;
+    /// # use gotham::router::builder::*;
+    /// # use gotham::state::State;
+    /// # use gotham::test::TestServer;
+    /// #
+    /// struct Flags {
+    ///     admin_enabled: bool,
+    /// }
+    ///
+    /// fn users(state: State) -> (State, &'static str) {
+    ///     (state, "users")
+    /// }
+    ///
+    /// fn router(flags: &Flags) -> Router {
+    ///     build_simple_router(|route| {
+    ///         route.scope_if(flags.admin_enabled, "/admin", |route| {
+    ///             route.get("/users").to(users);
+    ///         });
+    ///     })
+    /// }
+    /// #
+    /// # fn main() {
+    /// let test_server = TestServer::new(router(&Flags { admin_enabled: false })).unwrap();
+    /// let response = test_server
+    ///     .client()
+    ///     .get("https://example.com/admin/users")
+    ///     .perform()
+    ///     .unwrap();
+    /// assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    ///
+    /// let test_server = TestServer::new(router(&Flags { admin_enabled: true })).unwrap();
+    /// let response = test_server
+    ///     .client()
+    ///     .get("https://example.com/admin/users")
+    ///     .perform()
+    ///     .unwrap();
+    /// assert_eq!(response.status(), StatusCode::OK);
+    /// # }
+    /// ```
+    fn scope_if<F>(&mut self, condition: bool, path: &str, f: F)
+    where
+        F: FnOnce(&mut ScopeBuilder<C, P>),
+    {
+        if condition {
+            self.scope(path, f)
+        }
+    }
+
     /// Begins a new scope at the current location, with an alternate pipeline chain.
     ///
     /// # Examples
@@ -636,12 +826,14 @@ where
         F: FnOnce(&mut ScopeBuilder<NC, P>),
         NC: PipelineHandleChain<P> + Copy + Send + Sync + 'static,
     {
+        let api_version = self.api_version_scope().clone();
         let (node_builder, _pipeline_chain, pipelines) = self.component_refs();
 
         let mut scope_builder = ScopeBuilder {
             node_builder,
             pipeline_chain,
             pipelines: pipelines.clone(),
+            api_version,
         };
 
         f(&mut scope_builder)
@@ -696,6 +888,7 @@ where
             node_builder,
             pipeline_chain: *pipeline_chain,
             pipelines: pipelines.clone(),
+            error_propagation: true,
         }
     }
 
@@ -782,6 +975,7 @@ where
             node_builder,
             pipeline_chain: (),
             pipelines: pipelines.clone(),
+            error_propagation: true,
         }
     }
 
@@ -855,18 +1049,354 @@ where
     where
         F: FnOnce(&mut DefaultAssociatedRouteBuilder<'b, AnyRouteMatcher, C, P>),
     {
+        let api_version = self.api_version_scope().route_version();
         let (node_builder, pipeline_chain, pipelines) = self.component_refs();
         let node_builder = descend(node_builder, path);
+        if defines_trailing_slash(path) {
+            node_builder.set_trailing_slash();
+        }
 
         let mut builder =
-            AssociatedRouteBuilder::new(node_builder, *pipeline_chain, pipelines.clone());
+            AssociatedRouteBuilder::new(node_builder, *pipeline_chain, pipelines.clone())
+                .with_api_version(api_version);
 
         f(&mut builder)
     }
 
+    /// Declares the format of error responses for the routes of the current scope, or of the
+    /// whole router at the top level, regardless of the `Accept` header of the request. The
+    /// format also applies to requests below the scope which match no route.
+    ///
+    /// Error bodies are only rendered if an `ErrorFormatter` is registered with
+    /// `Router::with_error_formatter`. A format declared by a nested scope or a single route
+    /// takes precedence.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # extern crate hyper;
+    /// # extern crate mime;
+    /// #
+    /// # use hyper::StatusCode;
+    /// # use hyper::header::{ACCEPT, CONTENT_TYPE};
+    /// # use gotham::handler::{DefaultErrorFormatter, ErrorFormat};
+    /// # use gotham::router::builder::*;
+    /// # use gotham::state::State;
+    /// # use gotham::test::TestServer;
+    /// #
+    /// # fn handler(state: State) -> (State, &'static str) {
+    /// #     (state, "hello")
+    /// # }
+    /// #
+    /// # fn main() {
+    /// let router = build_simple_router(|route| {
+    ///     route.set_error_format(ErrorFormat::Html);
+    ///     route.get("/").to(handler);
+    ///
+    ///     route.scope("/api", |route| {
+    ///         route.set_error_format(ErrorFormat::Json);
+    ///         route.get("/users").to(handler);
+    ///     });
+    /// })
+    /// .with_error_formatter(DefaultErrorFormatter);
+    ///
+    /// let test_server = TestServer::new(router).unwrap();
+    /// let client = test_server.client();
+    ///
+    /// let response = client.get("http://localhost/missing").perform().unwrap();
+    /// assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    /// assert_eq!(response.headers()[CONTENT_TYPE], mime::TEXT_HTML_UTF_8.as_ref());
+    ///
+    /// let response = client
+    ///     .get("http://localhost/api/missing")
+    ///     .with_header(ACCEPT, "text/html".parse().unwrap())
+    ///     .perform()
+    ///     .unwrap();
+    /// assert_eq!(response.headers()[CONTENT_TYPE], mime::APPLICATION_JSON.as_ref());
+    /// assert_eq!(
+    ///     response.read_utf8_body().unwrap(),
+    ///     r#"{"error":"Not Found","status":404}"#
+    /// );
+    /// # }
+    /// ```
+    fn set_error_format(&mut self, format: ErrorFormat) {
+        let (node_builder, _, _) = self.component_refs();
+        node_builder.set_error_format(format);
+    }
+
+    /// Declares the CORS configuration of the routes of the current scope, or of the whole
+    /// router at the top level. Preflight requests for these routes are answered automatically.
+    ///
+    /// A configuration declared by a nested scope or a single route takes precedence. See the
+    /// `gotham::middleware::cors` module for an example.
+    fn set_cors(&mut self, config: CorsConfig) {
+        let (node_builder, _, _) = self.component_refs();
+        node_builder.set_cors(config);
+    }
+
+    /// Declares how the request path is decoded for the path extractors of the routes of the
+    /// current scope, or of the whole router at the top level. A policy declared by a nested
+    /// scope or a single route takes precedence.
+    ///
+    /// See `gotham::router::PathDecoding` for an example.
+    fn set_path_decoding(&mut self, path_decoding: PathDecoding) {
+        let (node_builder, _, _) = self.component_refs();
+        node_builder.set_path_decoding(path_decoding);
+    }
+
+    /// Declares the maximum size of request bodies in bytes for the routes of the current scope,
+    /// or of the whole router at the top level. A limit declared by a nested scope or a single
+    /// route takes precedence.
+    ///
+    /// Requests declaring a larger `Content-Length` are answered with `413 Payload Too Large`
+    /// before the pipelines of the route are invoked. Bodies sent without a `Content-Length` fail
+    /// to be read once they exceed the limit, and the error returned by the handler is answered
+    /// with `413 Payload Too Large` as well.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # extern crate hyper;
+    /// # extern crate mime;
+    /// #
+    /// # use hyper::StatusCode;
+    /// # use gotham::router::builder::*;
+    /// # use gotham::state::State;
+    /// # use gotham::test::TestServer;
+    /// #
+    /// # fn handler(state: State) -> (State, &'static str) {
+    /// #     (state, "uploaded")
+    /// # }
+    /// #
+    /// # fn main() {
+    /// let router = build_simple_router(|route| {
+    ///     route.set_body_limit(1024);
+    ///     route.post("/comments").to(handler);
+    ///     route
+    ///         .post("/uploads")
+    ///         .with_body_limit(16 * 1024 * 1024)
+    ///         .to(handler);
+    /// });
+    ///
+    /// let test_server = TestServer::new(router).unwrap();
+    /// let client = test_server.client();
+    /// let body = vec![b'a'; 4096];
+    ///
+    /// let response = client
+    ///     .post("http://localhost/comments", body.clone(), mime::TEXT_PLAIN)
+    ///     .perform()
+    ///     .unwrap();
+    /// assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    ///
+    /// let response = client
+    ///     .post("http://localhost/uploads", body, mime::TEXT_PLAIN)
+    ///     .perform()
+    ///     .unwrap();
+    /// assert_eq!(response.status(), StatusCode::OK);
+    /// # }
+    /// ```
+    fn set_body_limit(&mut self, body_limit: u64) {
+        let (node_builder, _, _) = self.component_refs();
+        node_builder.set_body_limit(body_limit);
+    }
+
+    /// Declares a path extractor for the prefix of the current scope, which is extracted into
+    /// `State` for every route below the scope, before the path extractor of the route. Nested
+    /// resources can then share the fields of their parent path, e.g. `/tenants/:tenant_id`,
+    /// instead of each declaring them in their own path extractor.
+    ///
+    /// If the extractor fails, the request is answered like for a failing path extractor of the
+    /// route, via the `StaticResponseExtender` of the extractor.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # #[macro_use]
+    /// # extern crate gotham_derive;
+    /// # #[macro_use]
+    /// # extern crate serde_derive;
+    /// #
+    /// # use gotham::router::builder::*;
+    /// # use gotham::state::{FromState, State};
+    /// # use gotham::test::TestServer;
+    /// #
+    /// #[derive(Deserialize, StateData, StaticResponseExtender)]
+    /// struct TenantPath {
+    ///     tenant_id: u32,
+    /// }
+    ///
+    /// #[derive(Deserialize, StateData, StaticResponseExtender)]
+    /// struct UserPath {
+    ///     user_id: u32,
+    /// }
+    ///
+    /// fn user(state: State) -> (State, String) {
+    ///     let tenant_id = TenantPath::borrow_from(&state).tenant_id;
+    ///     let user_id = UserPath::borrow_from(&state).user_id;
+    ///     (state, format!("user {} of tenant {}", user_id, tenant_id))
+    /// }
+    ///
+    /// # fn main() {
+    /// let router = build_simple_router(|route| {
+    ///     route.scope("/tenants/:tenant_id", |route| {
+    ///         route.set_path_extractor::<TenantPath>();
+    ///         route
+    ///             .get("/users/:user_id")
+    ///             .with_path_extractor::<UserPath>()
+    ///             .to(user);
+    ///     });
+    /// });
+    ///
+    /// let test_server = TestServer::new(router).unwrap();
+    /// let response = test_server
+    ///     .client()
+    ///     .get("http://localhost/tenants/7/users/42")
+    ///     .perform()
+    ///     .unwrap();
+    /// assert_eq!(response.read_utf8_body().unwrap(), "user 42 of tenant 7");
+    /// # }
+    /// ```
+    fn set_path_extractor<PE>(&mut self)
+    where
+        PE: PathExtractor<Body> + Send + Sync + 'static,
+    {
+        let (node_builder, _, _) = self.component_refs();
+        node_builder.add_path_extractor(Arc::new(ScopePathExtractorImpl::<PE>::new()));
+    }
+
+    /// Declares where requests name the version of the API, for the versions drawn via
+    /// `api_version` in the current scope, or in the whole router at the top level. Versions
+    /// are the first segment of the request path by default.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # extern crate hyper;
+    /// #
+    /// # use hyper::header::HeaderName;
+    /// # use hyper::StatusCode;
+    /// # use gotham::router::builder::*;
+    /// # use gotham::router::ApiVersioning;
+    /// # use gotham::test::TestServer;
+    /// #
+    /// # fn main() {
+    /// let router = build_simple_router(|route| {
+    ///     route.set_api_versioning(
+    ///         ApiVersioning::header(HeaderName::from_static("api-version")).with_default("v1"),
+    ///     );
+    ///     route.api_version("v1", |route| {
+    ///         route.get("/users").to(|state| (state, "v1 users"));
+    ///     });
+    ///     route.api_version("v2", |route| {
+    ///         route.get("/users").to(|state| (state, "v2 users"));
+    ///     });
+    /// });
+    ///
+    /// let test_server = TestServer::new(router).unwrap();
+    /// let response = test_server
+    ///     .client()
+    ///     .get("http://localhost/users")
+    ///     .with_header("api-version", "v2".parse().unwrap())
+    ///     .perform()
+    ///     .unwrap();
+    /// assert_eq!(response.read_utf8_body().unwrap(), "v2 users");
+    ///
+    /// let response = test_server
+    ///     .client()
+    ///     .get("http://localhost/users")
+    ///     .perform()
+    ///     .unwrap();
+    /// assert_eq!(response.read_utf8_body().unwrap(), "v1 users");
+    ///
+    /// let response = test_server
+    ///     .client()
+    ///     .get("http://localhost/users")
+    ///     .with_header("api-version", "v3".parse().unwrap())
+    ///     .perform()
+    ///     .unwrap();
+    /// assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    /// # }
+    /// ```
+    fn set_api_versioning(&mut self, versioning: ApiVersioning) {
+        self.api_version_scope().set_versioning(versioning);
+    }
+
+    /// Draws the routes of one version of the API, as declared by `set_api_versioning`. The
+    /// routes only match requests naming `version`, and the version is available to their
+    /// handlers via the `ApiVersion` in `State`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # extern crate hyper;
+    /// #
+    /// # use hyper::StatusCode;
+    /// # use gotham::router::builder::*;
+    /// # use gotham::router::ApiVersion;
+    /// # use gotham::state::{FromState, State};
+    /// # use gotham::test::TestServer;
+    /// #
+    /// fn users(state: State) -> (State, String) {
+    ///     let version = ApiVersion::borrow_from(&state).as_str().to_owned();
+    ///     (state, format!("{} users", version))
+    /// }
+    ///
+    /// # fn main() {
+    /// let router = build_simple_router(|route| {
+    ///     // Match requests to `/v1/users` and `/v2/users`
+    ///     route.api_version("v1", |route| {
+    ///         route.get("/users").to(users);
+    ///     });
+    ///     route.api_version("v2", |route| {
+    ///         route.get("/users").to(users);
+    ///     });
+    /// });
+    ///
+    /// let test_server = TestServer::new(router).unwrap();
+    /// let response = test_server
+    ///     .client()
+    ///     .get("http://localhost/v2/users")
+    ///     .perform()
+    ///     .unwrap();
+    /// assert_eq!(response.status(), StatusCode::OK);
+    /// assert_eq!(response.read_utf8_body().unwrap(), "v2 users");
+    /// # }
+    /// ```
+    fn api_version<F>(&mut self, version: &str, f: F)
+    where
+        F: FnOnce(&mut ScopeBuilder<C, P>),
+    {
+        let (api_version, path) = self.api_version_scope().with_version(version);
+        let (node_builder, pipeline_chain, pipelines) = self.component_refs();
+        let node_builder = descend(node_builder, &path);
+
+        let mut scope_builder = ScopeBuilder {
+            node_builder,
+            pipeline_chain: *pipeline_chain,
+            pipelines: pipelines.clone(),
+            api_version,
+        };
+
+        f(&mut scope_builder)
+    }
+
     /// Return the components that comprise this builder. For internal use only.
     #[doc(hidden)]
     fn component_refs(&mut self) -> (&mut Node, &mut C, &PipelineSet<P>);
+
+    /// Return the API versioning of this builder. For internal use only.
+    #[doc(hidden)]
+    fn api_version_scope(&mut self) -> &mut ApiVersionScope;
+}
+
+/// Returns `true` if routes defined for `path` have a trailing slash in their canonical path.
+fn defines_trailing_slash(path: &str) -> bool {
+    path.len() > 1 && path.ends_with('/')
 }
 
 fn descend<'n>(node_builder: &'n mut Node, path: &str) -> &'n mut Node {
@@ -937,6 +1467,10 @@ where
             &self.pipelines,
         )
     }
+
+    fn api_version_scope(&mut self) -> &mut ApiVersionScope {
+        &mut self.api_version
+    }
 }
 
 impl<'a, C, P> DrawRoutes<C, P> for ScopeBuilder<'a, C, P>
@@ -951,6 +1485,10 @@ where
             &self.pipelines,
         )
     }
+
+    fn api_version_scope(&mut self) -> &mut ApiVersionScope {
+        &mut self.api_version
+    }
 }
 
 #[cfg(test)]
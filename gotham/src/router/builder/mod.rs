@@ -7,22 +7,29 @@ mod single;
 
 use std::marker::PhantomData;
 use std::panic::RefUnwindSafe;
+use std::sync::Arc;
 
-use hyper::{Body, StatusCode};
+use hyper::{Body, Method, StatusCode};
 
 use crate::extractor::{
     NoopPathExtractor, NoopQueryStringExtractor, PathExtractor, QueryStringExtractor,
 };
+use crate::handler::{ErrorFormat, Handler};
+use crate::middleware::concurrency::PriorityClass;
 use crate::pipeline::chain::PipelineHandleChain;
 use crate::pipeline::set::{finalize_pipeline_set, new_pipeline_set, PipelineSet};
+use crate::router::api_version::{ApiVersionScope, RouteApiVersion};
+use crate::router::fallback::Fallbacks;
 use crate::router::response::extender::ResponseExtender;
 use crate::router::response::finalizer::ResponseFinalizerBuilder;
 use crate::router::route::dispatch::DispatcherImpl;
-use crate::router::route::matcher::{AndRouteMatcher, RouteMatcher};
+use crate::router::route::matcher::{AndRouteMatcher, AsyncRouteMatcher, RouteMatcher};
+use crate::router::route::middleware::RouteMiddleware;
+use crate::router::route::timeout::RouteTimeout;
 use crate::router::route::{Delegation, Extractors, RouteImpl};
 use crate::router::tree::node::Node;
 use crate::router::tree::Tree;
-use crate::router::Router;
+use crate::router::{PathDecoding, Router, TrailingSlash};
 
 pub use self::associated::{AssociatedRouteBuilder, AssociatedSingleRouteBuilder};
 pub use self::draw::DrawRoutes;
@@ -82,6 +89,13 @@ pub use self::single::DefineSingleRoute;
 /// #   assert_eq!(response.status(), StatusCode::ACCEPTED);
 /// # }
 /// ```
+///
+/// # Panics
+///
+/// If a route could never be matched because of the routes defined before it, either as an
+/// earlier route has the same path and methods without any further matchers, or as its path
+/// matches the same requests as another one with the same specificity, e.g. `/users/:id` and
+/// `/users/:name`.
 pub fn build_router<C, P, F>(pipeline_chain: C, pipelines: PipelineSet<P>, f: F) -> Router
 where
     C: PipelineHandleChain<P> + Copy + Send + Sync + 'static,
@@ -90,20 +104,34 @@ where
 {
     let mut tree = Tree::new();
 
-    let response_finalizer = {
+    let (response_finalizer, trailing_slash, fallbacks) = {
         let mut builder = RouterBuilder {
             node_builder: tree.borrow_root_mut(),
             pipeline_chain,
             pipelines,
+            api_version: ApiVersionScope::default(),
             response_finalizer_builder: ResponseFinalizerBuilder::internal_new(),
+            trailing_slash: TrailingSlash::default(),
+            fallbacks: Fallbacks::default(),
         };
 
         f(&mut builder);
 
-        builder.response_finalizer_builder.finalize()
+        (
+            builder.response_finalizer_builder.finalize(),
+            builder.trailing_slash,
+            builder.fallbacks,
+        )
     };
 
-    Router::internal_new(tree, response_finalizer)
+    tree.borrow_root().assert_unambiguous();
+    tree.borrow_root_mut().apply_cors(None);
+    tree.borrow_root_mut().apply_path_decoding(None);
+    tree.borrow_root_mut().apply_body_limit(None);
+    tree.borrow_root_mut().apply_path_extractors(&[]);
+    tree.borrow_root_mut().apply_match_priorities();
+
+    Router::internal_new(tree, response_finalizer).with_builder_options(trailing_slash, fallbacks)
 }
 
 /// Builds a `Router` with **no** middleware using the provided closure. Routes are defined using
@@ -157,7 +185,10 @@ where
     node_builder: &'a mut Node,
     pipeline_chain: C,
     pipelines: PipelineSet<P>,
+    api_version: ApiVersionScope,
     response_finalizer_builder: ResponseFinalizerBuilder,
+    trailing_slash: TrailingSlash,
+    fallbacks: Fallbacks,
 }
 
 impl<'a, C, P> RouterBuilder<'a, C, P>
@@ -222,6 +253,85 @@ where
         self.response_finalizer_builder
             .add(status_code, Box::new(extender))
     }
+
+    /// Sets the `TrailingSlash` policy of the `Router`, which decides how a request is handled
+    /// when its path and the matched route disagree on a trailing slash. The policy applies to
+    /// all routes of the `Router`, but not to delegated routers, which have their own policy.
+    ///
+    /// Defaults to `TrailingSlash::TreatAsEquivalent`. See `TrailingSlash` for an example.
+    pub fn set_trailing_slash(&mut self, policy: TrailingSlash) {
+        self.trailing_slash = policy;
+    }
+
+    /// Sets the handler for requests which match no route of the `Router`, instead of responding
+    /// with an empty `404 Not Found`. The handler decides the status of its response.
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # extern crate hyper;
+    /// # extern crate mime;
+    /// #
+    /// # use hyper::{Body, Response, StatusCode};
+    /// # use hyper::header::ALLOW;
+    /// # use gotham::helpers::http::response::create_response;
+    /// # use gotham::router::builder::*;
+    /// # use gotham::state::State;
+    /// # use gotham::test::TestServer;
+    /// #
+    /// fn not_found(state: State) -> (State, Response<Body>) {
+    ///     let body = "<h1>Nothing here</h1>";
+    ///     let response = create_response(&state, StatusCode::NOT_FOUND, mime::TEXT_HTML, body);
+    ///     (state, response)
+    /// }
+    ///
+    /// fn method_not_allowed(state: State) -> (State, Response<Body>) {
+    ///     let body = "<h1>Not allowed</h1>";
+    ///     let response =
+    ///         create_response(&state, StatusCode::METHOD_NOT_ALLOWED, mime::TEXT_HTML, body);
+    ///     (state, response)
+    /// }
+    /// #
+    /// # fn handler(state: State) -> (State, &'static str) {
+    /// #     (state, "home")
+    /// # }
+    ///
+    /// # fn main() {
+    /// let router = build_simple_router(|route| {
+    ///     route.not_found(not_found);
+    ///     route.method_not_allowed(method_not_allowed);
+    ///     route.get("/").to(handler);
+    /// });
+    ///
+    /// let test_server = TestServer::new(router).unwrap();
+    /// let client = test_server.client();
+    ///
+    /// let response = client.get("http://localhost/missing").perform().unwrap();
+    /// assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    /// assert_eq!(response.read_utf8_body().unwrap(), "<h1>Nothing here</h1>");
+    ///
+    /// let response = client.delete("http://localhost/").perform().unwrap();
+    /// assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    /// assert_eq!(response.headers().get(ALLOW).unwrap(), "GET");
+    /// # }
+    /// ```
+    pub fn not_found<H>(&mut self, handler: H)
+    where
+        H: Handler + RefUnwindSafe + Copy + Send + Sync + 'static,
+    {
+        self.fallbacks.set_not_found(handler);
+    }
+
+    /// Sets the handler for requests which match a path of the `Router`, but none of the methods
+    /// of its routes, instead of responding with an empty `405 Method Not Allowed`.
+    ///
+    /// The router adds an `Allow` header listing the methods of the path to the response, unless
+    /// the handler sets one itself. See `not_found` for an example.
+    pub fn method_not_allowed<H>(&mut self, handler: H)
+    where
+        H: Handler + RefUnwindSafe + Copy + Send + Sync + 'static,
+    {
+        self.fallbacks.set_method_not_allowed(handler);
+    }
 }
 
 /// A scoped builder, which is created by `DrawRoutes::scope` and passed to the provided closure.
@@ -234,6 +344,7 @@ where
     node_builder: &'a mut Node,
     pipeline_chain: C,
     pipelines: PipelineSet<P>,
+    api_version: ApiVersionScope,
 }
 
 /// A delegated builder, which is created by `DrawRoutes::delegate` and returned. The `DrawRoutes`
@@ -248,6 +359,7 @@ where
     node_builder: &'a mut Node,
     pipeline_chain: C,
     pipelines: PipelineSet<P>,
+    error_propagation: bool,
 }
 
 type DelegatedRoute<M> = RouteImpl<M, NoopPathExtractor, NoopQueryStringExtractor>;
@@ -260,17 +372,80 @@ where
 {
     /// Directs the delegated route to the given `Router`.
     pub fn to_router(self, router: Router) {
+        self.node_builder.add_delegated_names(router.names.clone());
+
         let dispatcher = DispatcherImpl::new(router, self.pipeline_chain, self.pipelines);
-        let route: DelegatedRoute<M> = DelegatedRoute::new(
+        let mut route: DelegatedRoute<M> = DelegatedRoute::new(
             self.matcher,
             Box::new(dispatcher),
             Extractors::new(),
             Delegation::External,
         );
+        if !self.error_propagation {
+            route = route.without_error_propagation();
+        }
 
         self.node_builder.add_route(Box::new(route));
     }
 
+    /// Keeps the delegated `Router` apart from the error handling of this `Router`.
+    ///
+    /// By default, a delegated `Router` which neither formats nor handles errors itself hands
+    /// its errors to this `Router`, which reports, formats and extends them like its own, and
+    /// the response extenders of this `Router` apply to all of its responses. Without error
+    /// propagation, the delegated `Router` turns errors into responses itself, and only its own
+    /// response extenders apply.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # extern crate hyper;
+    /// #
+    /// # use hyper::header::ACCEPT;
+    /// # use hyper::StatusCode;
+    /// # use gotham::handler::{DefaultErrorFormatter, HandlerError, HandlerResult};
+    /// # use gotham::router::builder::*;
+    /// # use gotham::state::State;
+    /// # use gotham::test::TestServer;
+    /// #
+    /// async fn handler(state: State) -> HandlerResult {
+    ///     let err = HandlerError::not_found(std::io::Error::last_os_error());
+    ///     Err((state, err))
+    /// }
+    ///
+    /// # fn main() {
+    /// let legacy = build_simple_router(|route| {
+    ///     route.get("/").to_async(handler);
+    /// });
+    ///
+    /// let router = build_simple_router(|route| {
+    ///     route
+    ///         .delegate("/legacy")
+    ///         .without_error_propagation()
+    ///         .to_router(legacy);
+    /// })
+    /// .with_error_formatter(DefaultErrorFormatter);
+    ///
+    /// let test_server = TestServer::new(router).unwrap();
+    /// let response = test_server
+    ///     .client()
+    ///     .get("http://localhost/legacy")
+    ///     .with_header(ACCEPT, "application/json".parse().unwrap())
+    ///     .perform()
+    ///     .unwrap();
+    /// assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    /// // the error of the delegated router isn't formatted by the outer router
+    /// assert!(response.read_body().unwrap().is_empty());
+    /// # }
+    /// ```
+    pub fn without_error_propagation(self) -> Self {
+        DelegateRouteBuilder {
+            error_propagation: false,
+            ..self
+        }
+    }
+
     /// Adds additional `RouteMatcher` requirements to the current delegate.
     pub fn add_route_matcher<NM: RouteMatcher + Send + Sync + 'static>(
         self,
@@ -281,6 +456,7 @@ where
             node_builder: self.node_builder,
             pipeline_chain: self.pipeline_chain,
             pipelines: self.pipelines,
+            error_propagation: self.error_propagation,
         }
     }
 }
@@ -299,6 +475,18 @@ where
     matcher: M,
     pipeline_chain: C,
     pipelines: PipelineSet<P>,
+    middleware: RouteMiddleware,
+    timeout: Option<RouteTimeout>,
+    priority: Option<PriorityClass>,
+    name: Option<String>,
+    methods: Vec<Method>,
+    conditional: bool,
+    error_format: Option<ErrorFormat>,
+    path_decoding: Option<PathDecoding>,
+    match_priority: Option<i32>,
+    body_limit: Option<u64>,
+    api_version: Option<Arc<RouteApiVersion>>,
+    async_matchers: Vec<Arc<dyn AsyncRouteMatcher>>,
     phantom: PhantomData<(PE, QSE)>,
 }
 
@@ -323,6 +511,18 @@ where
             matcher: self.matcher,
             pipeline_chain: self.pipeline_chain,
             pipelines: self.pipelines,
+            middleware: self.middleware,
+            timeout: self.timeout,
+            priority: self.priority,
+            name: self.name,
+            methods: self.methods,
+            conditional: self.conditional,
+            error_format: self.error_format,
+            path_decoding: self.path_decoding,
+            match_priority: self.match_priority,
+            body_limit: self.body_limit,
+            api_version: self.api_version,
+            async_matchers: self.async_matchers,
             phantom: PhantomData,
         }
     }
@@ -642,4 +842,143 @@ mod tests {
         let response = call(Request::get("/trailing-slash").body(Body::empty()).unwrap());
         assert_eq!(response.status(), StatusCode::OK);
     }
+
+    #[test]
+    #[should_panic(expected = "route GET /users/:id is already defined")]
+    fn rejects_shadowed_routes() {
+        build_simple_router(|route| {
+            route.get_or_head("/users/:id").to(welcome::index);
+            route.get("/users/:id").to(welcome::index);
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "routes /users/:id and /users/:name match the same requests")]
+    fn rejects_ambiguous_templates() {
+        build_simple_router(|route| {
+            route.get("/users/:id").to(welcome::index);
+            route.post("/users/:name/posts").to(welcome::index);
+        });
+    }
+
+    #[test]
+    fn allows_routes_with_further_matchers() {
+        use crate::router::route::matcher::HostMatcher;
+
+        build_simple_router(|route| {
+            route
+                .get("/users/:id")
+                .add_route_matcher(HostMatcher::new("api.example.com"))
+                .to(welcome::index);
+            route.get("/users/:id").to(welcome::index);
+            route.post("/users/:id").to(welcome::index);
+            route.get("/users/:id:[0-9]+").to(welcome::index);
+            route.get("/users/:id/*").to(welcome::index);
+            route.associate("/posts", |assoc| {
+                assoc.get().to(welcome::index);
+                assoc.post().to(welcome::index);
+            });
+        });
+    }
+
+    #[test]
+    fn orders_sibling_segments_by_match_priority() {
+        use crate::router::MatchedRoute;
+        use crate::state::FromState;
+        use crate::test::TestServer;
+
+        fn template(state: State) -> (State, String) {
+            let template = MatchedRoute::borrow_from(&state).template().to_owned();
+            (state, template)
+        }
+
+        let router = build_simple_router(|route| {
+            route.get("/users/new/edit").to(template);
+            route.get("/users/:id/edit").to(template);
+            route.get("/users/:id").to(template);
+            route
+                .get("/drafts/:id/edit")
+                .with_match_priority(1)
+                .to(template);
+            route.get("/drafts/new/edit").to(template);
+        });
+
+        let test_server = TestServer::new(router).unwrap();
+        let matched = |uri| {
+            let response = test_server.client().get(uri).perform().unwrap();
+            response.read_utf8_body().unwrap()
+        };
+
+        assert_eq!(
+            matched("http://localhost/users/new/edit"),
+            "/users/new/edit"
+        );
+        assert_eq!(matched("http://localhost/users/1/edit"), "/users/:id/edit");
+        // the priority of a route applies to the segments above it
+        assert_eq!(
+            matched("http://localhost/drafts/new/edit"),
+            "/drafts/:id/edit"
+        );
+        assert_eq!(
+            matched("http://localhost/drafts/1/edit"),
+            "/drafts/:id/edit"
+        );
+    }
+
+    #[test]
+    fn extracts_scope_paths_for_nested_routes() {
+        use crate::state::FromState;
+        use crate::test::TestServer;
+
+        #[derive(Deserialize)]
+        struct TenantPath {
+            tenant_id: u32,
+        }
+
+        impl StateData for TenantPath {}
+
+        impl StaticResponseExtender for TenantPath {
+            type ResBody = Body;
+            fn extend(_: &mut State, res: &mut Response<Body>) {
+                *res.status_mut() = StatusCode::BAD_REQUEST;
+            }
+        }
+
+        fn projects(state: State) -> (State, String) {
+            let tenant_id = TenantPath::borrow_from(&state).tenant_id;
+            let body = format!(
+                "{} of {}",
+                SalutationParams::borrow_from(&state).name,
+                tenant_id
+            );
+            (state, body)
+        }
+
+        let router = build_simple_router(|route| {
+            route.scope("/tenants/:tenant_id", |route| {
+                route.set_path_extractor::<TenantPath>();
+                route.scope("/projects", |route| {
+                    route
+                        .get("/:name")
+                        .with_path_extractor::<SalutationParams>()
+                        .to(projects);
+                });
+            });
+        });
+
+        let test_server = TestServer::new(router).unwrap();
+        let response = test_server
+            .client()
+            .get("http://localhost/tenants/7/projects/gotham")
+            .perform()
+            .unwrap();
+        assert_eq!(response.read_utf8_body().unwrap(), "gotham of 7");
+
+        let response = test_server
+            .client()
+            .get("http://localhost/tenants/acme/projects/gotham")
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
 }
@@ -106,6 +106,18 @@ where
             node_builder: self.node_builder,
             pipeline_chain: self.pipeline_chain,
             pipelines: self.pipelines,
+            middleware: self.middleware,
+            timeout: self.timeout,
+            priority: self.priority,
+            name: self.name,
+            methods: self.methods,
+            conditional: true,
+            error_format: self.error_format,
+            path_decoding: self.path_decoding,
+            match_priority: self.match_priority,
+            body_limit: self.body_limit,
+            api_version: self.api_version,
+            async_matchers: self.async_matchers,
         }
     }
 }
@@ -2,19 +2,27 @@ use hyper::Body;
 
 use std::panic::RefUnwindSafe;
 use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
 
 use crate::extractor::{PathExtractor, QueryStringExtractor};
 use crate::handler::assets::{DirHandler, FileHandler, FileOptions, FilePathExtractor};
+use crate::handler::formatter::ErrorFormatDispatcher;
 use crate::handler::{
-    Handler, HandlerError, HandlerFuture, HandlerResult, IntoResponse, NewHandler,
+    ErrorFormat, Handler, HandlerError, HandlerFuture, HandlerResult, IntoResponse, NewHandler,
 };
+use crate::middleware::concurrency::{PriorityClass, PriorityDispatcher};
+use crate::middleware::cors::{CorsConfig, CorsMiddleware};
+use crate::middleware::NewMiddleware;
 use crate::pipeline::chain::PipelineHandleChain;
 use crate::router::builder::{
     ExtendRouteMatcher, ReplacePathExtractor, ReplaceQueryStringExtractor, SingleRouteBuilder,
 };
-use crate::router::route::dispatch::DispatcherImpl;
-use crate::router::route::matcher::RouteMatcher;
+use crate::router::route::dispatch::{Dispatcher, DispatcherImpl};
+use crate::router::route::matcher::{AsyncRouteMatcher, RouteMatcher};
+use crate::router::route::timeout::RouteTimeout;
 use crate::router::route::{Delegation, Extractors, RouteImpl};
+use crate::router::PathDecoding;
 use crate::state::State;
 use core::future::Future;
 use futures::FutureExt;
@@ -608,6 +616,347 @@ pub trait DefineSingleRoute {
         NRM: RouteMatcher + Send + Sync + 'static,
         Self: ExtendRouteMatcher<NRM>,
         Self::Output: DefineSingleRoute;
+
+    /// Adds an `AsyncRouteMatcher` requirement to the current route, for conditions which are
+    /// decided asynchronously, e.g. by a database. See `AsyncRouteMatcher` for an example.
+    ///
+    /// Routes with an async matcher never shadow later routes for the same path and methods,
+    /// which are tried if the matcher doesn't match.
+    fn add_async_route_matcher<AM>(self, matcher: AM) -> Self
+    where
+        AM: AsyncRouteMatcher + 'static,
+        Self: Sized;
+
+    /// Limits the time the handler of the current route may take to complete. When the timeout
+    /// expires, the handler future is dropped and the request fails with a `HandlerError` caused
+    /// by a `RouteTimeoutError`, which is served as an empty `504 Gateway Timeout` response.
+    ///
+    /// The timeout only covers the handler, not the middleware of the route, which see the
+    /// `HandlerError` like any other. Their changes to `State` are not visible to
+    /// them on the way out, as the `State` is dropped along with the handler future.
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # extern crate hyper;
+    /// # extern crate tokio;
+    /// #
+    /// # use std::time::Duration;
+    /// #
+    /// # use hyper::StatusCode;
+    /// # use gotham::handler::HandlerResult;
+    /// # use gotham::helpers::http::response::create_empty_response;
+    /// # use gotham::state::State;
+    /// # use gotham::router::Router;
+    /// # use gotham::router::builder::*;
+    /// # use gotham::test::TestServer;
+    /// #
+    /// async fn slow_handler(state: State) -> HandlerResult {
+    ///     tokio::time::sleep(Duration::from_secs(60)).await;
+    ///     let response = create_empty_response(&state, StatusCode::OK);
+    ///     Ok((state, response))
+    /// }
+    ///
+    /// # fn router() -> Router {
+    /// build_simple_router(|route| {
+    ///     route
+    ///         .get("/slow")
+    ///         .with_timeout(Duration::from_millis(10))
+    ///         .to_async(slow_handler);
+    /// })
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #   let test_server = TestServer::new(router()).unwrap();
+    /// #   let response = test_server.client()
+    /// #       .get("https://example.com/slow")
+    /// #       .perform()
+    /// #       .unwrap();
+    /// #   assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    /// # }
+    /// ```
+    fn with_timeout(self, timeout: Duration) -> Self
+    where
+        Self: Sized;
+
+    /// Attaches middleware to the current route only, without defining a pipeline for it.
+    ///
+    /// Route middleware are invoked after the pipelines of the route, in the order they were
+    /// attached, and before the handler.
+    ///
+    /// ```rust
+    /// # extern crate futures;
+    /// # extern crate gotham;
+    /// # #[macro_use]
+    /// # extern crate gotham_derive;
+    /// # extern crate hyper;
+    /// #
+    /// # use std::pin::Pin;
+    /// #
+    /// # use futures::prelude::*;
+    /// # use hyper::header::AUTHORIZATION;
+    /// # use hyper::{HeaderMap, StatusCode};
+    /// # use gotham::handler::HandlerFuture;
+    /// # use gotham::helpers::http::response::create_empty_response;
+    /// # use gotham::middleware::Middleware;
+    /// # use gotham::state::{FromState, State};
+    /// # use gotham::router::Router;
+    /// # use gotham::router::builder::*;
+    /// # use gotham::test::TestServer;
+    /// #
+    /// #[derive(Clone, NewMiddleware)]
+    /// struct RequireAdmin;
+    ///
+    /// impl Middleware for RequireAdmin {
+    ///     fn call<Chain>(self, state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+    ///     where
+    ///         Chain: FnOnce(State) -> Pin<Box<HandlerFuture>> + Send + 'static,
+    ///     {
+    ///         if HeaderMap::borrow_from(&state).get(AUTHORIZATION).map_or(false, |v| v == "admin") {
+    ///             chain(state)
+    ///         } else {
+    ///             let response = create_empty_response(&state, StatusCode::FORBIDDEN);
+    ///             future::ok((state, response)).boxed()
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// # fn handler(state: State) -> (State, &'static str) {
+    /// #     (state, "hello")
+    /// # }
+    /// #
+    /// # fn router() -> Router {
+    /// build_simple_router(|route| {
+    ///     route.get("/").to(handler);
+    ///     route.get("/admin").with_middleware(RequireAdmin).to(handler);
+    /// })
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #   let test_server = TestServer::new(router()).unwrap();
+    /// #   let response = test_server.client().get("https://example.com/").perform().unwrap();
+    /// #   assert_eq!(response.status(), StatusCode::OK);
+    /// #   let response = test_server.client().get("https://example.com/admin").perform().unwrap();
+    /// #   assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    /// #   let response = test_server.client()
+    /// #       .get("https://example.com/admin")
+    /// #       .with_header(AUTHORIZATION, "admin".parse().unwrap())
+    /// #       .perform()
+    /// #       .unwrap();
+    /// #   assert_eq!(response.status(), StatusCode::OK);
+    /// # }
+    /// ```
+    fn with_middleware<NM>(self, middleware: NM) -> Self
+    where
+        Self: Sized,
+        NM: NewMiddleware + Send + 'static,
+        NM::Instance: Send + 'static;
+
+    /// Declares the priority class of the current route, which a `ConcurrencyLimiter` uses to
+    /// schedule requests queued during overload. Routes are `PriorityClass::Interactive` unless
+    /// declared otherwise.
+    ///
+    /// The class is put into `State` before the pipelines of the route are invoked. See the
+    /// `gotham::middleware::concurrency` module for an example.
+    fn with_priority(self, class: PriorityClass) -> Self
+    where
+        Self: Sized;
+
+    /// Declares the maximum size of request bodies in bytes for the current route, like
+    /// `DrawRoutes::set_body_limit` does for a scope.
+    ///
+    /// See `DrawRoutes::set_body_limit` for an example.
+    fn with_body_limit(self, body_limit: u64) -> Self
+    where
+        Self: Sized;
+
+    /// Stops the `Router` from answering `OPTIONS` requests for the path of the current route,
+    /// e.g. because a handler implements `OPTIONS` itself behind a custom `RouteMatcher`.
+    ///
+    /// By default, an `OPTIONS` request for a path which has routes, but none for `OPTIONS`, is
+    /// answered with `200 OK` and an `Allow` header listing the methods of the routes. Requests
+    /// for a path opted out with this method are answered with `405 Method Not Allowed` instead.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # extern crate hyper;
+    /// #
+    /// # use hyper::header::ALLOW;
+    /// # use hyper::StatusCode;
+    /// # use gotham::router::builder::*;
+    /// # use gotham::state::State;
+    /// # use gotham::test::TestServer;
+    /// #
+    /// # fn handler(state: State) -> (State, &'static str) {
+    /// #     (state, "")
+    /// # }
+    /// #
+    /// # fn main() {
+    /// let router = build_simple_router(|route| {
+    ///     route.get_or_head("/users").to(handler);
+    ///     route.post("/users").to(handler);
+    ///     route.get("/internal").without_options_response().to(handler);
+    /// });
+    ///
+    /// let test_server = TestServer::new(router).unwrap();
+    /// let client = test_server.client();
+    ///
+    /// let response = client.options("http://localhost/users").perform().unwrap();
+    /// assert_eq!(response.status(), StatusCode::OK);
+    /// assert_eq!(response.headers()[ALLOW], "GET, HEAD, POST, OPTIONS");
+    ///
+    /// let response = client.options("http://localhost/internal").perform().unwrap();
+    /// assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    /// # }
+    /// ```
+    fn without_options_response(self) -> Self
+    where
+        Self: Sized;
+
+    /// Gives the path of the current route a match priority, which decides between sibling path
+    /// segments matching the same request, e.g. `/posts/:year:[0-9]{4}` and `/posts/:id:[0-9]+`.
+    /// Routes have priority `0` unless declared otherwise.
+    ///
+    /// The `Router` matches each segment of the request path against the sibling segments in
+    /// order of the highest priority of the routes below them, and among equal priorities, tries
+    /// static segments before constrained, dynamic and glob segments. It never reconsiders a
+    /// segment once chosen. The priority applies to the whole path, i.e. also to other routes
+    /// with the same path.
+    ///
+    /// Unrelated to `with_priority`, which schedules requests during overload.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # extern crate hyper;
+    /// #
+    /// # use gotham::router::builder::*;
+    /// # use gotham::router::MatchedRoute;
+    /// # use gotham::state::{FromState, State};
+    /// # use gotham::test::TestServer;
+    /// #
+    /// fn handler(state: State) -> (State, String) {
+    ///     let template = MatchedRoute::borrow_from(&state).template().to_owned();
+    ///     (state, template)
+    /// }
+    ///
+    /// # fn main() {
+    /// let router = build_simple_router(|route| {
+    ///     route.get("/posts/:id:[0-9]+").to(handler);
+    ///     route
+    ///         .get("/posts/:year:[0-9]{4}")
+    ///         .with_match_priority(10)
+    ///         .to(handler);
+    /// });
+    ///
+    /// let test_server = TestServer::new(router).unwrap();
+    /// let template = |uri| {
+    ///     let response = test_server.client().get(uri).perform().unwrap();
+    ///     response.read_utf8_body().unwrap()
+    /// };
+    ///
+    /// assert_eq!(template("http://localhost/posts/2024"), "/posts/:year");
+    /// assert_eq!(template("http://localhost/posts/12"), "/posts/:id");
+    /// # }
+    /// ```
+    fn with_match_priority(self, priority: i32) -> Self
+    where
+        Self: Sized;
+
+    /// Names the current route, so that its path can be built with `UrlFor` instead of being
+    /// hard-coded. Routes for different methods on the same path may share a name, but a name
+    /// can't be given to routes with different paths.
+    ///
+    /// See `gotham::router::UrlFor` for an example.
+    ///
+    /// # Panics
+    ///
+    /// When the `Router` is built, if the name is given to routes with different paths.
+    fn named(self, name: &str) -> Self
+    where
+        Self: Sized;
+
+    /// Declares the format of error responses for the current route, regardless of the `Accept`
+    /// header of the request, like `DrawRoutes::set_error_format` does for a scope.
+    ///
+    /// The format is put into `State` before the pipelines of the route are invoked, and is used
+    /// by the `ErrorFormatter` registered with `Router::with_error_formatter`.
+    fn with_error_format(self, format: ErrorFormat) -> Self
+    where
+        Self: Sized;
+
+    /// Declares how the request path is decoded for the path extractor of the current route,
+    /// like `DrawRoutes::set_path_decoding` does for a scope.
+    ///
+    /// See `gotham::router::PathDecoding` for an example.
+    fn with_path_decoding(self, path_decoding: PathDecoding) -> Self
+    where
+        Self: Sized;
+
+    /// Applies a CORS configuration to the current route, answering its preflight requests and
+    /// adding CORS headers to its responses. The configuration takes precedence over one declared
+    /// for the enclosing scope via `DrawRoutes::set_cors`.
+    ///
+    /// See the `gotham::middleware::cors` module for an example.
+    fn with_cors(self, config: CorsConfig) -> Self
+    where
+        Self: Sized;
+
+    /// Limits the time the handler of the current route may take to complete, like
+    /// `with_timeout`, serving the body rendered by `response` when the timeout expires.
+    ///
+    /// The rendered response is served with the status code `504 Gateway Timeout`.
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # extern crate hyper;
+    /// # extern crate tokio;
+    /// #
+    /// # use std::time::Duration;
+    /// #
+    /// # use hyper::StatusCode;
+    /// # use gotham::handler::HandlerResult;
+    /// # use gotham::state::State;
+    /// # use gotham::router::Router;
+    /// # use gotham::router::builder::*;
+    /// # use gotham::test::TestServer;
+    /// #
+    /// # async fn slow_handler(_state: State) -> HandlerResult {
+    /// #     tokio::time::sleep(Duration::from_secs(60)).await;
+    /// #     unreachable!()
+    /// # }
+    /// #
+    /// # fn router() -> Router {
+    /// build_simple_router(|route| {
+    ///     route
+    ///         .get("/report")
+    ///         .with_timeout_response(Duration::from_millis(10), |_state: &State| {
+    ///             "The report is still being generated, please try again later."
+    ///         })
+    ///         .to_async(slow_handler);
+    /// })
+    /// # }
+    /// #
+    /// # fn main() {
+    /// #   let test_server = TestServer::new(router()).unwrap();
+    /// #   let response = test_server.client()
+    /// #       .get("https://example.com/report")
+    /// #       .perform()
+    /// #       .unwrap();
+    /// #   assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    /// #   assert_eq!(
+    /// #       response.read_utf8_body().unwrap(),
+    /// #       "The report is still being generated, please try again later."
+    /// #   );
+    /// # }
+    /// ```
+    fn with_timeout_response<F, R>(self, timeout: Duration, response: F) -> Self
+    where
+        Self: Sized,
+        F: Fn(&State) -> R + Send + Sync + RefUnwindSafe + 'static,
+        R: IntoResponse;
 }
 
 impl<'a, M, C, P, PE, QSE> DefineSingleRoute for SingleRouteBuilder<'a, M, C, P, PE, QSE>
@@ -646,13 +995,53 @@ where
     where
         NH: NewHandler + 'static,
     {
-        let dispatcher = DispatcherImpl::new(new_handler, self.pipeline_chain, self.pipelines);
-        let route: RouteImpl<M, PE, QSE> = RouteImpl::new(
+        let middleware = self.middleware;
+        let dispatcher: Box<dyn Dispatcher + Send + Sync> = match self.timeout {
+            Some(timeout) => Box::new(DispatcherImpl::new(
+                middleware.wrap(timeout.wrap(new_handler)),
+                self.pipeline_chain,
+                self.pipelines,
+            )),
+            None => Box::new(DispatcherImpl::new(
+                middleware.wrap(new_handler),
+                self.pipeline_chain,
+                self.pipelines,
+            )),
+        };
+        let dispatcher: Box<dyn Dispatcher + Send + Sync> = match self.priority {
+            Some(class) => Box::new(PriorityDispatcher { dispatcher, class }),
+            None => dispatcher,
+        };
+        let dispatcher: Box<dyn Dispatcher + Send + Sync> = match self.error_format {
+            Some(format) => Box::new(ErrorFormatDispatcher { dispatcher, format }),
+            None => dispatcher,
+        };
+        let mut route: RouteImpl<M, PE, QSE> = RouteImpl::new(
             self.matcher,
-            Box::new(dispatcher),
+            dispatcher,
             Extractors::new(),
             Delegation::Internal,
         );
+        if let Some(path_decoding) = self.path_decoding {
+            route = route.with_path_decoding(path_decoding);
+        }
+        if let Some(body_limit) = self.body_limit {
+            route = route.with_body_limit(body_limit);
+        }
+        if let Some(api_version) = self.api_version {
+            route = route.with_api_version(api_version);
+        }
+        for matcher in self.async_matchers {
+            route = route.with_async_matcher(matcher);
+        }
+        self.node_builder
+            .add_route_methods(&self.methods, self.conditional, self.body_limit);
+        if let Some(name) = &self.name {
+            self.node_builder.add_name(name);
+        }
+        if let Some(priority) = self.match_priority {
+            self.node_builder.set_match_priority(priority);
+        }
         self.node_builder.add_route(Box::new(route));
     }
 
@@ -678,4 +1067,97 @@ where
     {
         self.extend_route_matcher(matcher)
     }
+
+    fn add_async_route_matcher<AM>(mut self, matcher: AM) -> Self
+    where
+        AM: AsyncRouteMatcher + 'static,
+    {
+        self.async_matchers.push(Arc::new(matcher));
+        SingleRouteBuilder {
+            conditional: true,
+            ..self
+        }
+    }
+
+    fn with_timeout(self, timeout: Duration) -> Self {
+        SingleRouteBuilder {
+            timeout: Some(RouteTimeout::new(timeout)),
+            ..self
+        }
+    }
+
+    fn with_timeout_response<F, R>(self, timeout: Duration, response: F) -> Self
+    where
+        F: Fn(&State) -> R + Send + Sync + RefUnwindSafe + 'static,
+        R: IntoResponse,
+    {
+        SingleRouteBuilder {
+            timeout: Some(RouteTimeout::new(timeout).with_response(response)),
+            ..self
+        }
+    }
+
+    fn with_middleware<NM>(mut self, middleware: NM) -> Self
+    where
+        NM: NewMiddleware + Send + 'static,
+        NM::Instance: Send + 'static,
+    {
+        self.middleware.push(middleware);
+        self
+    }
+
+    fn with_priority(self, class: PriorityClass) -> Self {
+        SingleRouteBuilder {
+            priority: Some(class),
+            ..self
+        }
+    }
+
+    fn named(self, name: &str) -> Self {
+        SingleRouteBuilder {
+            name: Some(name.to_owned()),
+            ..self
+        }
+    }
+
+    fn with_cors(mut self, config: CorsConfig) -> Self {
+        let config = Arc::new(config);
+        self.node_builder
+            .add_cors_preflight(self.methods.clone(), config.clone());
+        self.middleware.push(CorsMiddleware::from_shared(config));
+        self
+    }
+
+    fn with_error_format(self, format: ErrorFormat) -> Self {
+        SingleRouteBuilder {
+            error_format: Some(format),
+            ..self
+        }
+    }
+
+    fn with_body_limit(self, body_limit: u64) -> Self {
+        SingleRouteBuilder {
+            body_limit: Some(body_limit),
+            ..self
+        }
+    }
+
+    fn without_options_response(self) -> Self {
+        self.node_builder.disable_options_response();
+        self
+    }
+
+    fn with_match_priority(self, priority: i32) -> Self {
+        SingleRouteBuilder {
+            match_priority: Some(priority),
+            ..self
+        }
+    }
+
+    fn with_path_decoding(self, path_decoding: PathDecoding) -> Self {
+        SingleRouteBuilder {
+            path_decoding: Some(path_decoding),
+            ..self
+        }
+    }
 }
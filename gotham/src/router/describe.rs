@@ -0,0 +1,312 @@
+//! Describes and validates the routes of a `Router`, see `Router::describe` and
+//! `Router::validate`.
+
+use hyper::Method;
+use serde_json::{json, Value};
+
+use crate::router::tree::node::Node;
+use crate::router::Router;
+
+impl Router {
+    /// Describes the routes of this `Router` as JSON, e.g. to be printed on startup or compared
+    /// against a checked in route table in a test.
+    ///
+    /// The description has a `routes` array with an entry for every routable path, in the order
+    /// paths are matched, and a `mounts` array with the description of every router added with
+    /// `Router::mount`. A route entry consists of:
+    ///
+    /// * `path`, the template of the path, e.g. `/users/:id`
+    /// * `methods`, the methods of the routes at the path, in the order they were added
+    /// * `names`, the names given with `DefineSingleRoute::named`
+    /// * `delegated`, whether the path delegates to another `Router`
+    /// * `cors`, whether a CORS configuration applies
+    /// * `path_decoding`, the `PathDecoding` policy of the path
+    /// * `body_limit`, the body limit declared for the path, or `null`
+    /// * `pipelines`, the pipelines the routes at the path pass requests through, in the order
+    ///   they are invoked, each given as the type names of its middleware in the order they are
+    ///   called
+    /// * `routers`, the descriptions of the routers the path delegates to
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # #[macro_use]
+    /// # extern crate serde_json;
+    /// #
+    /// # use gotham::router::builder::*;
+    /// # use gotham::state::State;
+    /// #
+    /// # fn handler(state: State) -> (State, &'static str) {
+    /// #     (state, "")
+    /// # }
+    /// #
+    /// # fn main() {
+    /// let router = build_simple_router(|route| {
+    ///     route.get_or_head("/users/:id").named("user").to(handler);
+    /// });
+    ///
+    /// assert_eq!(
+    ///     router.describe(),
+    ///     json!({
+    ///         "routes": [{
+    ///             "path": "/users/:id",
+    ///             "methods": ["GET", "HEAD"],
+    ///             "names": ["user"],
+    ///             "delegated": false,
+    ///             "cors": false,
+    ///             "path_decoding": "Decoded",
+    ///             "body_limit": null,
+    ///             "pipelines": [],
+    ///             "routers": [],
+    ///         }],
+    ///         "mounts": [],
+    ///     })
+    /// );
+    /// # }
+    /// ```
+    pub fn describe(&self) -> Value {
+        let mut routes = vec![];
+        visit(self.data.tree.borrow_root(), &mut |node| {
+            if node.is_routable() {
+                routes.push(describe_node(node));
+            }
+        });
+
+        let mounts: Vec<Value> = self
+            .mounts
+            .iter()
+            .map(|mount| json!({ "path": mount.template, "router": mount.router.describe() }))
+            .collect();
+
+        json!({ "routes": routes, "mounts": mounts })
+    }
+
+    /// Checks the routes of this `Router`, and of its mounted and delegated routers, for common
+    /// misconfigurations, and returns a message for each one found, e.g. to fail an application's
+    /// `--check-config` mode on startup. Currently, this reports routes accepting request bodies, i.e. `POST`,
+    /// `PUT` and `PATCH` routes, without a body limit, see `DrawRoutes::set_body_limit`.
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// #
+    /// # use gotham::router::builder::*;
+    /// # use gotham::state::State;
+    /// #
+    /// # fn handler(state: State) -> (State, &'static str) {
+    /// #     (state, "")
+    /// # }
+    /// #
+    /// # fn main() {
+    /// let router = build_simple_router(|route| {
+    ///     route.post("/comments").to(handler);
+    ///     route.post("/uploads").with_body_limit(1024).to(handler);
+    /// });
+    ///
+    /// assert_eq!(
+    ///     router.validate(),
+    ///     vec!["POST /comments accepts request bodies without a body limit"]
+    /// );
+    /// # }
+    /// ```
+    pub fn validate(&self) -> Vec<String> {
+        let mut issues = vec![];
+        visit(self.data.tree.borrow_root(), &mut |node| {
+            for (method, body_limit) in node.methods() {
+                let accepts_body =
+                    *method == Method::POST || *method == Method::PUT || *method == Method::PATCH;
+                if accepts_body && body_limit.or_else(|| node.body_limit()).is_none() {
+                    issues.push(format!(
+                        "{} {} accepts request bodies without a body limit",
+                        method,
+                        node.template()
+                    ));
+                }
+            }
+
+            for router in node
+                .routes()
+                .iter()
+                .filter_map(|route| route.delegated_router())
+            {
+                issues.extend(
+                    router
+                        .validate()
+                        .into_iter()
+                        .map(|issue| format!("{} (delegated at {})", issue, node.template())),
+                );
+            }
+        });
+
+        for mount in self.mounts.iter() {
+            issues.extend(
+                mount
+                    .router
+                    .validate()
+                    .into_iter()
+                    .map(|issue| format!("{} (mounted at {})", issue, mount.template)),
+            );
+        }
+        issues
+    }
+}
+
+/// Calls `f` with `node` and all of its descendants, in the order they are matched.
+fn visit<'a>(node: &'a Node, f: &mut dyn FnMut(&'a Node)) {
+    f(node);
+    for child in node.children() {
+        visit(child, f);
+    }
+}
+
+fn describe_node(node: &Node) -> Value {
+    let mut methods: Vec<&str> = vec![];
+    for (method, _) in node.methods() {
+        if !methods.contains(&method.as_str()) {
+            methods.push(method.as_str());
+        }
+    }
+
+    let mut pipelines: Vec<Vec<&str>> = vec![];
+    for route in node.routes() {
+        for pipeline in route.pipelines().into_iter().flatten() {
+            if !pipelines.contains(&pipeline) {
+                pipelines.push(pipeline);
+            }
+        }
+    }
+
+    let routers: Vec<Value> = node
+        .routes()
+        .iter()
+        .filter_map(|route| route.delegated_router())
+        .map(Router::describe)
+        .collect();
+
+    json!({
+        "path": node.template(),
+        "methods": methods,
+        "names": node.names(),
+        "delegated": node.is_delegated(),
+        "cors": node.cors().is_some(),
+        "path_decoding": format!("{:?}", node.path_decoding()),
+        "body_limit": node.body_limit(),
+        "pipelines": pipelines,
+        "routers": routers,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::cookie::CookieParser;
+    use crate::middleware::cors::CorsConfig;
+    use crate::middleware::logger::{RequestLogger, SimpleLogger};
+    use crate::pipeline::new_pipeline;
+    use crate::pipeline::set::{finalize_pipeline_set, new_pipeline_set};
+    use crate::router::builder::{
+        build_router, build_simple_router, DefineSingleRoute, DrawRoutes,
+    };
+    use crate::state::State;
+
+    fn handler(state: State) -> (State, &'static str) {
+        (state, "")
+    }
+
+    #[test]
+    fn describes_nested_and_mounted_routes() {
+        let api = build_simple_router(|route| {
+            route.put("/items/:id").to(handler);
+        });
+
+        let router = build_simple_router(|route| {
+            route.get("/").to(handler);
+            route.scope("/admin", |route| {
+                route.set_body_limit(64);
+                route.set_cors(CorsConfig::new());
+                route.post("/users").to(handler);
+                route.delete("/users").to(handler);
+            });
+        })
+        .mount("/api", api);
+
+        let description = router.describe();
+        let routes = description["routes"].as_array().unwrap();
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0]["path"], "/");
+        assert_eq!(routes[1]["path"], "/admin/users");
+        assert_eq!(routes[1]["methods"], json!(["POST", "DELETE"]));
+        assert_eq!(routes[1]["cors"], true);
+        assert_eq!(routes[1]["body_limit"], 64);
+
+        assert_eq!(description["mounts"][0]["path"], "/api");
+        assert_eq!(
+            description["mounts"][0]["router"]["routes"][0]["path"],
+            "/items/:id"
+        );
+
+        assert_eq!(
+            router.validate(),
+            vec!["PUT /items/:id accepts request bodies without a body limit (mounted at /api)"]
+        );
+    }
+
+    #[test]
+    fn describes_pipelines_and_delegated_routers() {
+        let uploads = build_simple_router(|route| {
+            route.post("/").to(handler);
+        });
+
+        let pipelines = new_pipeline_set();
+        let (pipelines, logging) = pipelines.add(
+            new_pipeline()
+                .add(RequestLogger::new(log::Level::Info))
+                .add_when(false, SimpleLogger::new(log::Level::Debug))
+                .add(CookieParser)
+                .build(),
+        );
+        let (pipelines, api) = pipelines.add(
+            new_pipeline()
+                .add_when(true, SimpleLogger::new(log::Level::Debug))
+                .build(),
+        );
+        let pipelines = finalize_pipeline_set(pipelines);
+
+        let router = build_router((logging, ()), pipelines, |route| {
+            route.get("/").to(handler);
+            route.with_pipeline_chain((api, (logging, ())), |route| {
+                route.delegate("/uploads").to_router(uploads);
+            });
+        });
+
+        let description = router.describe();
+        assert_eq!(
+            description["routes"][0]["pipelines"],
+            json!([[
+                "gotham::middleware::logger::RequestLogger",
+                "gotham::middleware::cookie::CookieParser",
+            ]])
+        );
+        assert_eq!(description["routes"][0]["routers"], json!([]));
+
+        let delegated = &description["routes"][1];
+        assert_eq!(delegated["path"], "/uploads");
+        assert_eq!(
+            delegated["pipelines"],
+            json!([
+                [
+                    "gotham::middleware::logger::RequestLogger",
+                    "gotham::middleware::cookie::CookieParser",
+                ],
+                ["gotham::middleware::logger::SimpleLogger"],
+            ])
+        );
+        assert_eq!(
+            delegated["routers"][0]["routes"][0]["methods"],
+            json!(["POST"])
+        );
+
+        assert_eq!(
+            router.validate(),
+            vec!["POST / accepts request bodies without a body limit (delegated at /uploads)"]
+        );
+    }
+}
@@ -0,0 +1,152 @@
+//! Defines `DynamicRouter`, which allows replacing the routes of a running server.
+
+use std::pin::Pin;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use log::trace;
+
+use crate::handler::{Handler, HandlerFuture, NewHandler};
+use crate::router::Router;
+use crate::state::{request_id, State};
+
+/// A handle to a `Router` which can be replaced while the server is running, e.g. after the
+/// configuration the routes were built from was reloaded.
+///
+/// Each request is dispatched to the `Router` which was current when it arrived, and keeps it
+/// until its response has been sent, so replacing the `Router` doesn't affect requests in
+/// flight. Clones of a `DynamicRouter` share the same `Router`, so a clone can be kept to replace
+/// the `Router` after the original was passed to `gotham::start`.
+///
+/// ```rust
+/// # extern crate gotham;
+/// # extern crate hyper;
+/// #
+/// # use hyper::StatusCode;
+/// # use gotham::router::builder::*;
+/// # use gotham::router::DynamicRouter;
+/// # use gotham::state::State;
+/// # use gotham::test::TestServer;
+/// #
+/// fn handler(state: State) -> (State, &'static str) {
+///     (state, "hello")
+/// }
+///
+/// # fn main() {
+/// let router = DynamicRouter::new(build_simple_router(|route| {
+///     route.get("/old").to(handler);
+/// }));
+///
+/// let test_server = TestServer::new(router.clone()).unwrap();
+/// let response = test_server.client().get("http://localhost/new").perform().unwrap();
+/// assert_eq!(response.status(), StatusCode::NOT_FOUND);
+///
+/// router.replace(build_simple_router(|route| {
+///     route.get("/new").to(handler);
+/// }));
+///
+/// let response = test_server.client().get("http://localhost/new").perform().unwrap();
+/// assert_eq!(response.status(), StatusCode::OK);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct DynamicRouter {
+    current: Arc<ArcSwap<Router>>,
+}
+
+impl DynamicRouter {
+    /// Creates a `DynamicRouter` dispatching to `router` until it is replaced.
+    pub fn new(router: Router) -> DynamicRouter {
+        DynamicRouter {
+            current: Arc::new(ArcSwap::from_pointee(router)),
+        }
+    }
+
+    /// Dispatches all requests arriving from now on to `router`, and returns the `Router` it
+    /// replaces. Requests already dispatched to the previous `Router` are completed by it.
+    pub fn replace(&self, router: Router) -> Arc<Router> {
+        self.current.swap(Arc::new(router))
+    }
+
+    /// Returns the `Router` requests are currently dispatched to.
+    pub fn current(&self) -> Arc<Router> {
+        self.current.load_full()
+    }
+}
+
+impl NewHandler for DynamicRouter {
+    type Instance = Router;
+
+    fn new_handler(&self) -> anyhow::Result<Self::Instance> {
+        trace!(" cloning current router");
+        Ok(Router::clone(&self.current.load()))
+    }
+}
+
+impl Handler for DynamicRouter {
+    fn handle(self, state: State) -> Pin<Box<HandlerFuture>> {
+        trace!("[{}] dispatching to current router", request_id(&state));
+        Router::clone(&self.current.load()).handle(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::channel::oneshot;
+    use hyper::StatusCode;
+    use std::sync::{mpsc, Mutex};
+    use std::thread;
+
+    use crate::handler::{HandlerResult, IntoResponse};
+    use crate::router::builder::{build_simple_router, DefineSingleRoute, DrawRoutes};
+    use crate::test::TestServer;
+
+    type Handles = (mpsc::Sender<()>, oneshot::Receiver<()>);
+
+    static HANDLES: Mutex<Option<Handles>> = Mutex::new(None);
+
+    async fn slow(state: State) -> HandlerResult {
+        let (started, released) = HANDLES.lock().unwrap().take().unwrap();
+        started.send(()).unwrap();
+        released.await.unwrap();
+        let response = "old".into_response(&state);
+        Ok((state, response))
+    }
+
+    #[test]
+    fn completes_requests_in_flight_when_replaced() {
+        let (started, has_started) = mpsc::channel();
+        let (release, released) = oneshot::channel();
+        *HANDLES.lock().unwrap() = Some((started, released));
+
+        let router = DynamicRouter::new(build_simple_router(|route| {
+            route.get("/").to_async(slow);
+        }));
+
+        let test_server = TestServer::new(router.clone()).unwrap();
+        let client = test_server.client();
+        let in_flight = thread::spawn(move || {
+            let response = client.get("http://localhost/").perform().unwrap();
+            (response.status(), response.read_utf8_body().unwrap())
+        });
+        has_started.recv().unwrap();
+
+        router.replace(build_simple_router(|route| {
+            route.get("/").to(|state| (state, "new"));
+        }));
+        release.send(()).unwrap();
+
+        let (status, body) = in_flight.join().unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "old");
+
+        let response = test_server
+            .client()
+            .get("http://localhost/")
+            .perform()
+            .unwrap();
+        assert_eq!(response.read_utf8_body().unwrap(), "new");
+    }
+}
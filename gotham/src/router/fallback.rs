@@ -0,0 +1,70 @@
+//! Defines the handlers a `Router` falls back to when no route matches, see
+//! `RouterBuilder::not_found` and `RouterBuilder::method_not_allowed`.
+
+use std::panic::RefUnwindSafe;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures::prelude::*;
+use hyper::header::ALLOW;
+use hyper::{Method, StatusCode};
+
+use crate::handler::{Handler, HandlerFuture};
+use crate::state::State;
+
+type FallbackHandler = dyn Fn(State) -> Pin<Box<HandlerFuture>> + Send + Sync + RefUnwindSafe;
+
+/// The fallback handlers of a `Router`.
+#[derive(Clone, Default)]
+pub(crate) struct Fallbacks {
+    not_found: Option<Arc<FallbackHandler>>,
+    method_not_allowed: Option<Arc<FallbackHandler>>,
+}
+
+impl Fallbacks {
+    pub(crate) fn set_not_found<H>(&mut self, handler: H)
+    where
+        H: Handler + RefUnwindSafe + Copy + Send + Sync + 'static,
+    {
+        self.not_found = Some(Arc::new(move |state| handler.handle(state)));
+    }
+
+    pub(crate) fn set_method_not_allowed<H>(&mut self, handler: H)
+    where
+        H: Handler + RefUnwindSafe + Copy + Send + Sync + 'static,
+    {
+        self.method_not_allowed = Some(Arc::new(move |state| handler.handle(state)));
+    }
+
+    /// Dispatches a request which matched no route with `status` to the fallback handler for
+    /// `status`, if any. The `Allow` header listing `allow` is added to responses of the
+    /// `405 Method Not Allowed` handler which don't set one themselves.
+    pub(crate) fn dispatch(
+        &self,
+        state: State,
+        status: StatusCode,
+        allow: Vec<Method>,
+    ) -> Result<Pin<Box<HandlerFuture>>, State> {
+        match status {
+            StatusCode::NOT_FOUND => match &self.not_found {
+                Some(handler) => Ok(handler(state)),
+                None => Err(state),
+            },
+            StatusCode::METHOD_NOT_ALLOWED => match &self.method_not_allowed {
+                Some(handler) => Ok(handler(state)
+                    .map_ok(move |(state, mut res)| {
+                        if !res.headers().contains_key(ALLOW) {
+                            for allowed in allow {
+                                res.headers_mut()
+                                    .append(ALLOW, allowed.as_str().parse().unwrap());
+                            }
+                        }
+                        (state, res)
+                    })
+                    .boxed()),
+                None => Err(state),
+            },
+            _ => Err(state),
+        }
+    }
+}
@@ -0,0 +1,303 @@
+//! Defines the method override applied by a `Router` before route matching, see
+//! `MethodOverride`.
+
+use std::pin::Pin;
+
+use futures::prelude::*;
+use hyper::header::{HeaderName, CONTENT_TYPE};
+use hyper::{Body, HeaderMap, Method, StatusCode};
+use log::trace;
+
+use crate::handler::HandlerError;
+use crate::helpers::http::body::read_prefix;
+use crate::helpers::http::header::X_HTTP_METHOD_OVERRIDE;
+use crate::helpers::http::request::query_string;
+use crate::state::{request_id, FromState, State};
+
+type OverrideFuture = dyn Future<Output = Result<State, (State, HandlerError)>> + Send;
+
+const DEFAULT_MAX_FORM_SIZE: usize = 8 * 1024;
+
+/// Rewrites the method of `POST` requests before route matching, so that clients which can only
+/// send `GET` and `POST`, such as HTML forms, can reach routes for other methods.
+///
+/// The method is taken from the `X-HTTP-Method-Override` header or, for
+/// `application/x-www-form-urlencoded` bodies, from the `_method` form field. The header takes
+/// precedence over the form field. Only `PUT`, `PATCH` and `DELETE` are accepted by default;
+/// requests naming any other method keep their `POST` method.
+///
+/// As the override is applied before route matching, only the first 8 KiB of a form body are
+/// searched for the field, see `MethodOverride::with_max_form_size`.
+///
+/// Method override is opt-in, see `Router::with_method_override`:
+///
+/// ```rust
+/// # extern crate gotham;
+/// # extern crate hyper;
+/// # extern crate mime;
+/// #
+/// # use hyper::StatusCode;
+/// # use gotham::router::MethodOverride;
+/// # use gotham::router::builder::*;
+/// # use gotham::state::State;
+/// # use gotham::test::TestServer;
+/// #
+/// fn delete_post(state: State) -> (State, &'static str) {
+///     (state, "deleted")
+/// }
+///
+/// # fn main() {
+/// let router = build_simple_router(|route| {
+///     route.delete("/posts/:id").to(delete_post);
+/// })
+/// .with_method_override(MethodOverride::new());
+///
+/// let test_server = TestServer::new(router).unwrap();
+/// let response = test_server
+///     .client()
+///     .post(
+///         "http://localhost/posts/1",
+///         "_method=DELETE",
+///         mime::APPLICATION_WWW_FORM_URLENCODED,
+///     )
+///     .perform()
+///     .unwrap();
+///
+/// assert_eq!(response.status(), StatusCode::OK);
+/// assert_eq!(response.read_utf8_body().unwrap(), "deleted");
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct MethodOverride {
+    header: Option<HeaderName>,
+    form_field: Option<String>,
+    max_form_size: usize,
+    allowed: Vec<Method>,
+}
+
+impl MethodOverride {
+    /// Creates the default method override, see `MethodOverride`.
+    pub fn new() -> MethodOverride {
+        MethodOverride {
+            header: Some(HeaderName::from_static(X_HTTP_METHOD_OVERRIDE)),
+            form_field: Some(String::from("_method")),
+            max_form_size: DEFAULT_MAX_FORM_SIZE,
+            allowed: vec![Method::PUT, Method::PATCH, Method::DELETE],
+        }
+    }
+
+    /// Takes the method from the header `name` instead of `X-HTTP-Method-Override`.
+    pub fn with_header(self, name: HeaderName) -> MethodOverride {
+        MethodOverride {
+            header: Some(name),
+            ..self
+        }
+    }
+
+    /// Ignores the method override header.
+    pub fn without_header(self) -> MethodOverride {
+        MethodOverride {
+            header: None,
+            ..self
+        }
+    }
+
+    /// Takes the method from the form field `name` instead of `_method`.
+    pub fn with_form_field(self, name: &str) -> MethodOverride {
+        MethodOverride {
+            form_field: Some(name.to_owned()),
+            ..self
+        }
+    }
+
+    /// Ignores the request body, which then stays untouched until it is read by a handler.
+    pub fn without_form_field(self) -> MethodOverride {
+        MethodOverride {
+            form_field: None,
+            ..self
+        }
+    }
+
+    /// Searches the first `max_form_size` bytes of a form body for the field, instead of 8 KiB.
+    /// Fields which only end beyond the limit are ignored, and the rest of the body is streamed
+    /// to the handler without being buffered.
+    pub fn with_max_form_size(self, max_form_size: usize) -> MethodOverride {
+        MethodOverride {
+            max_form_size,
+            ..self
+        }
+    }
+
+    /// Replaces the methods which requests are allowed to override `POST` with.
+    pub fn with_allowed_methods(self, allowed: Vec<Method>) -> MethodOverride {
+        MethodOverride { allowed, ..self }
+    }
+
+    /// Applies the override from the header to `state`. Returns `true` if the override has to be
+    /// taken from the form body instead, see `apply_form`.
+    pub(crate) fn apply(&self, state: &mut State) -> bool {
+        if *Method::borrow_from(state) != Method::POST {
+            return false;
+        }
+
+        let headers = HeaderMap::borrow_from(state);
+
+        if let Some(name) = &self.header {
+            if let Some(value) = headers.get(name).and_then(|value| value.to_str().ok()) {
+                let value = value.to_owned();
+                self.override_method(state, &value);
+                return false;
+            }
+        }
+
+        self.form_field.is_some()
+            && headers
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<mime::Mime>().ok())
+                .is_some_and(|mime| mime.essence_str() == "application/x-www-form-urlencoded")
+    }
+
+    /// Reads the start of the form body of the request in `state` and applies the override from
+    /// its field. The body is restored afterwards, so that handlers can still read the form.
+    pub(crate) fn apply_form(&self, mut state: State) -> Pin<Box<OverrideFuture>> {
+        let this = self.clone();
+        let field = self.form_field.clone().unwrap_or_default();
+        let body = Body::take_from(&mut state);
+
+        async move {
+            let (prefix, truncated, body) = match read_prefix(body, this.max_form_size).await {
+                Ok(read) => read,
+                Err(e) => {
+                    let err = HandlerError::from(e).with_status(StatusCode::BAD_REQUEST);
+                    return Err((state, err));
+                }
+            };
+
+            // only the fields which are complete within the prefix of a longer form are searched
+            let form = if truncated {
+                prefix.rsplitn(2, |&b| b == b'&').nth(1).unwrap_or_default()
+            } else {
+                &prefix[..]
+            };
+            let value = std::str::from_utf8(form)
+                .ok()
+                .and_then(|form| query_string::split(Some(form)).remove(&field))
+                .and_then(|values| values.into_iter().next());
+
+            state.put(body);
+
+            if let Some(value) = value {
+                this.override_method(&mut state, value.as_ref());
+            }
+
+            Ok(state)
+        }
+        .boxed()
+    }
+
+    fn override_method(&self, state: &mut State, value: &str) {
+        match value.to_ascii_uppercase().parse::<Method>() {
+            Ok(method) if self.allowed.contains(&method) => {
+                trace!("[{}] overriding method with {}", request_id(state), method);
+                state.put(method);
+            }
+            _ => trace!("[{}] ignoring method override {}", request_id(state), value),
+        }
+    }
+}
+
+impl Default for MethodOverride {
+    fn default() -> MethodOverride {
+        MethodOverride::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hyper::body;
+    use hyper::header::HeaderValue;
+
+    use crate::handler::{HandlerResult, IntoResponse};
+    use crate::router::builder::{build_simple_router, DefineSingleRoute, DrawRoutes};
+    use crate::test::TestServer;
+
+    fn method(state: State) -> (State, String) {
+        let method = Method::borrow_from(&state).to_string();
+        (state, method)
+    }
+
+    async fn form(mut state: State) -> HandlerResult {
+        let body = body::to_bytes(Body::take_from(&mut state)).await.unwrap();
+        let response = String::from_utf8(body.to_vec())
+            .unwrap()
+            .into_response(&state);
+        Ok((state, response))
+    }
+
+    #[test]
+    fn overrides_post_requests() {
+        let router = build_simple_router(|route| {
+            route.post("/").to(method);
+            route.delete("/").to(method);
+            route.put("/").to_async(form);
+        })
+        .with_method_override(MethodOverride::new());
+        let test_server = TestServer::new(router).unwrap();
+        let client = test_server.client();
+        let form_mime = mime::APPLICATION_WWW_FORM_URLENCODED;
+
+        let response = client
+            .post("http://localhost/", "", mime::TEXT_PLAIN)
+            .with_header(X_HTTP_METHOD_OVERRIDE, HeaderValue::from_static("delete"))
+            .perform()
+            .unwrap();
+        assert_eq!(response.read_utf8_body().unwrap(), "DELETE");
+
+        let response = client
+            .post("http://localhost/", "a=1&_method=PUT", form_mime.clone())
+            .perform()
+            .unwrap();
+        assert_eq!(response.read_utf8_body().unwrap(), "a=1&_method=PUT");
+
+        let response = client
+            .post("http://localhost/", "_method=GET", form_mime)
+            .perform()
+            .unwrap();
+        assert_eq!(response.read_utf8_body().unwrap(), "POST");
+
+        let response = client
+            .get("http://localhost/")
+            .with_header(X_HTTP_METHOD_OVERRIDE, HeaderValue::from_static("DELETE"))
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[test]
+    fn searches_only_the_start_of_long_forms() {
+        let router = build_simple_router(|route| {
+            route.post("/").to(method);
+            route.put("/").to_async(form);
+        })
+        .with_method_override(MethodOverride::new().with_max_form_size(16));
+        let test_server = TestServer::new(router).unwrap();
+        let client = test_server.client();
+        let form_mime = mime::APPLICATION_WWW_FORM_URLENCODED;
+        let long_form = format!("_method=PUT&text={}", "a".repeat(64));
+
+        let response = client
+            .post("http://localhost/", long_form.clone(), form_mime.clone())
+            .perform()
+            .unwrap();
+        assert_eq!(response.read_utf8_body().unwrap(), long_form);
+
+        let response = client
+            .post("http://localhost/", "text=abcdef&_method=PUT", form_mime)
+            .perform()
+            .unwrap();
+        assert_eq!(response.read_utf8_body().unwrap(), "POST");
+    }
+}
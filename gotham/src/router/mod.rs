@@ -8,23 +8,65 @@ pub mod tree;
 pub mod non_match;
 pub use self::non_match::RouteNonMatch;
 
+pub(crate) mod api_version;
+pub use self::api_version::{ApiVersion, ApiVersioning};
+
+mod body_limit;
+use self::body_limit::limit_body;
+
+mod describe;
+mod dynamic;
+pub use self::dynamic::DynamicRouter;
+
+mod fallback;
+mod method_override;
+pub use self::method_override::MethodOverride;
+
+mod path_decoding;
+pub use self::path_decoding::PathDecoding;
+
+mod scope_extractor;
+
+mod trailing_slash;
+pub use self::trailing_slash::TrailingSlash;
+
+mod url_for;
+pub use self::url_for::{UrlFor, UrlForError};
+
+use std::any::Any;
+use std::cmp::Reverse;
+use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::pin::Pin;
 use std::sync::Arc;
 
 use futures::prelude::*;
 
-use hyper::header::ALLOW;
-use hyper::{Body, Response, StatusCode};
+use hyper::body::HttpBody;
+use hyper::header::{HeaderMap, ALLOW, CONTENT_LENGTH};
+use hyper::{Body, Method, Response, StatusCode, Uri, Version};
 use log::{error, trace};
 
-use crate::handler::{Handler, HandlerFuture, IntoResponse, NewHandler};
-use crate::helpers::http::request::path::RequestPathSegments;
+use crate::handler::error::{append_headers, insert_retry_after};
+use crate::handler::formatter::format_error;
+use crate::handler::{
+    ErrorFormatter, ErrorReporter, Handler, HandlerError, HandlerFuture, IntoResponse, NewHandler,
+};
+use crate::helpers::http::request::path::{split_path_segments, RequestPathSegments};
 use crate::helpers::http::response::create_empty_response;
+use crate::helpers::http::PercentDecoded;
+use crate::middleware::cors;
+use crate::middleware::error_status::ErrorStatusMap;
+use crate::router::fallback::Fallbacks;
 use crate::router::response::finalizer::ResponseFinalizer;
+use crate::router::response::hook::ResponseHook;
+use crate::router::route::matcher::async_matcher::AsyncMatches;
+use crate::router::route::matcher::AsyncRouteMatcher;
 use crate::router::route::{Delegation, Route};
-use crate::router::tree::segment::SegmentMapping;
+use crate::router::tree::node::Node;
+use crate::router::tree::segment::{GlobSegments, SegmentMapping};
 use crate::router::tree::Tree;
-use crate::state::{request_id, State};
+use crate::router::url_for::RouteNames;
+use crate::state::{copy_request_id, request_id, FromState, State, StateData};
 
 struct RouterData {
     tree: Tree,
@@ -40,6 +82,78 @@ impl RouterData {
     }
 }
 
+/// A function which converts a `HandlerError` into the `Response` sent to the client.
+///
+/// Registered on a `Router` via `Router::with_error_handler`.
+pub type ErrorHandler = fn(&State, HandlerError) -> Response<Body>;
+
+/// A function which converts the payload of a panic raised by a handler into the `HandlerError`
+/// used to respond to the request.
+///
+/// The `State` passed to the function is a copy of the request method, URI, version, headers and
+/// request id, taken before the handler was invoked, as the original `State` is lost in the
+/// panic. Registered on a `Router` via `Router::with_panic_handler`.
+pub type PanicHandler = fn(&State, Box<dyn Any + Send>) -> HandlerError;
+
+/// Returns the message of a panic payload, as passed to a `PanicHandler`.
+///
+/// Payloads which are not a string, i.e. those not created by `panic!` with a message, are
+/// described as `"Box<dyn Any>"`.
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&'static str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+/// The template of the route a request was dispatched to, e.g. `/users/:id`, which is put into
+/// `State` by the `Router` before the route's pipelines and handler are invoked.
+///
+/// Unlike the request path, the template doesn't vary with path parameters, which makes it
+/// suitable for grouping requests in logs and metrics. Routes of a secondary `Router` include the
+/// template of the route delegating to it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MatchedRoute {
+    template: String,
+}
+
+impl MatchedRoute {
+    /// Returns the template of the route, e.g. `/users/:id`.
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    /// Records the template of the node matched by a `Router`, below the template matched by a
+    /// delegating `Router`, if any.
+    pub(crate) fn put(state: &mut State, template: &str) {
+        let template = match state.try_take::<MatchedRoute>() {
+            Some(outer) if template != "/" => {
+                format!("{}{}", outer.template.trim_end_matches('/'), template)
+            }
+            Some(outer) => outer.template,
+            None => template.to_owned(),
+        };
+
+        state.put(MatchedRoute { template });
+    }
+}
+
+impl StateData for MatchedRoute {}
+
+/// Marks a request dispatched to a secondary `Router` which hands its errors to the delegating
+/// `Router`, see `DelegateRouteBuilder::without_error_propagation`.
+struct PropagateErrors;
+
+impl StateData for PropagateErrors {}
+
+/// Marks the response of a secondary `Router` which the delegating `Router` doesn't extend.
+struct IsolatedResponse;
+
+impl StateData for IsolatedResponse {}
+
 /// Responsible for dispatching HTTP requests to defined routes, and responding with appropriate
 /// error codes when a valid `Route` is unable to be determined or the dispatch cannot be
 /// performed.
@@ -55,6 +169,38 @@ impl RouterData {
 #[derive(Clone)]
 pub struct Router {
     data: Arc<RouterData>,
+    error_handler: Option<ErrorHandler>,
+    error_reporter: Option<Arc<dyn ErrorReporter>>,
+    error_formatter: Option<Arc<dyn ErrorFormatter>>,
+    error_statuses: Option<ErrorStatusMap>,
+    panic_handler: Option<PanicHandler>,
+    expose_error_details: bool,
+    head_fallback: bool,
+    response_hooks: Arc<Vec<Arc<dyn ResponseHook>>>,
+    mounts: Arc<Vec<Mount>>,
+    trailing_slash: TrailingSlash,
+    method_override: Option<Arc<MethodOverride>>,
+    fallbacks: Fallbacks,
+    names: Arc<RouteNames>,
+}
+
+/// A `Router` mounted below a path prefix of another `Router`, see `Router::mount`.
+#[derive(Clone)]
+struct Mount {
+    segments: Vec<String>,
+    template: String,
+    router: Router,
+}
+
+impl Mount {
+    fn matches(&self, segments: &[PercentDecoded]) -> bool {
+        segments.len() >= self.segments.len()
+            && self
+                .segments
+                .iter()
+                .zip(segments)
+                .all(|(expected, segment)| expected == segment.as_ref())
+    }
 }
 
 impl NewHandler for Router {
@@ -72,28 +218,155 @@ impl Handler for Router {
     /// any path related variables in `State` and dispatching to the associated `Handler`.
     fn handle(self, mut state: State) -> Pin<Box<HandlerFuture>> {
         trace!("[{}] starting", request_id(&state));
+        let propagate_errors = state.try_take::<PropagateErrors>().is_some();
+
+        let future = match self.panic_handler {
+            Some(panic_handler) => recover_panics(state, panic_handler, |state| self.route(state)),
+            None => self.route(state),
+        };
+
+        self.finalize_response(future, propagate_errors)
+    }
+}
+
+impl Router {
+    fn route(&self, mut state: State) -> Pin<Box<HandlerFuture>> {
+        if let Some(method_override) = &self.method_override {
+            if method_override.apply(&mut state) {
+                let future = method_override.apply_form(state);
+                let router = self.clone();
+                return async move {
+                    match future.await {
+                        Ok(state) => router.route_request(state).await,
+                        Err((state, err)) => Err((state, err)),
+                    }
+                }
+                .boxed();
+            }
+        }
+
+        self.route_request(state)
+    }
+
+    /// Returns the `AsyncRouteMatcher` values to evaluate before selecting a route of `node`,
+    /// including those of the `GET` routes which a `HEAD` request may fall back to.
+    fn async_matchers(&self, node: &Node, state: &mut State) -> Vec<Arc<dyn AsyncRouteMatcher>> {
+        let mut matchers = node.async_matchers(state);
+        if self.head_fallback && *Method::borrow_from(state) == Method::HEAD {
+            state.put(Method::GET);
+            for matcher in node.async_matchers(state) {
+                if !matchers.iter().any(|m| Arc::ptr_eq(m, &matcher)) {
+                    matchers.push(matcher);
+                }
+            }
+            state.put(Method::HEAD);
+        }
+        matchers
+    }
+
+    fn route_request(&self, mut state: State) -> Pin<Box<HandlerFuture>> {
+        UrlFor::put(&mut state, &self.names);
 
-        let future = match state.try_take::<RequestPathSegments>() {
+        match state.try_take::<RequestPathSegments>() {
             Some(rps) => {
-                if let Some((node, params, processed)) = self.data.tree.traverse(&rps.segments()) {
+                if let Some(format) = self.data.tree.borrow_root().error_format(rps.segments()) {
+                    state.put(format);
+                }
+
+                if let Some(mount) = self.mounts.iter().find(|m| m.matches(rps.segments())) {
+                    trace!("[{}] dispatching to mounted router", request_id(&state));
+
+                    state.put(rps.subsegments(mount.segments.len()));
+                    MatchedRoute::put(&mut state, &mount.template);
+                    mount.router.clone().handle(state)
+                } else if let Some((node, params, processed)) =
+                    self.data.tree.traverse(&rps.segments())
+                {
+                    let matchers = self.async_matchers(node, &mut state);
+                    let pending = AsyncMatches::pending(&state, matchers);
+                    if !pending.is_empty() {
+                        trace!("[{}] evaluating async route matchers", request_id(&state));
+
+                        let matches: Vec<_> = pending.iter().map(|m| m.is_match(&state)).collect();
+                        let router = self.clone();
+                        return async move {
+                            let results = future::join_all(matches).await;
+                            AsyncMatches::record(&mut state, &pending, results);
+                            state.put(rps);
+                            router.route_request(state).await
+                        }
+                        .boxed();
+                    }
+
                     match node.select_route(&state) {
                         Ok(route) => match route.delegation() {
                             Delegation::External => {
                                 trace!("[{}] delegating to secondary router", request_id(&state));
 
                                 state.put(rps.subsegments(processed));
-                                route.dispatch(state)
+                                MatchedRoute::put(&mut state, node.template());
+                                if route.propagates_errors() {
+                                    state.put(PropagateErrors);
+                                    route.dispatch(state)
+                                } else {
+                                    isolate_response(route.dispatch(state))
+                                }
                             }
                             Delegation::Internal => {
-                                trace!("[{}] dispatching to route", request_id(&state));
-                                self.dispatch(state, params, route)
+                                self.dispatch_internal(state, params, node, route.as_ref())
                             }
                         },
                         Err(non_match) => {
-                            let (status, allow) = non_match.deconstruct();
+                            let (status, mut allow) = non_match.deconstruct();
+
+                            if status == StatusCode::METHOD_NOT_ALLOWED
+                                && self.head_fallback
+                                && *Method::borrow_from(&state) == Method::HEAD
+                                && allow.contains(&Method::GET)
+                            {
+                                state.put(Method::GET);
+                                match node.select_route(&state) {
+                                    Ok(route) if route.delegation() == Delegation::Internal => {
+                                        trace!(
+                                            "[{}] dispatching HEAD request to GET route",
+                                            request_id(&state)
+                                        );
+                                        let future = self.dispatch_internal(
+                                            state,
+                                            params,
+                                            node,
+                                            route.as_ref(),
+                                        );
+                                        return strip_head_response(future);
+                                    }
+                                    _ => state.put(Method::HEAD),
+                                }
+                            }
+
+                            if self.head_fallback && !allow.contains(&Method::HEAD) {
+                                if let Some(get) = allow.iter().position(|m| *m == Method::GET) {
+                                    allow.insert(get + 1, Method::HEAD);
+                                }
+                            }
+
+                            if status == StatusCode::METHOD_NOT_ALLOWED
+                                && *Method::borrow_from(&state) == Method::OPTIONS
+                                && node.answers_options()
+                            {
+                                trace!("[{}] answering OPTIONS request", request_id(&state));
+                                MatchedRoute::put(&mut state, node.template());
+                                let res = options_response(&state, allow);
+                                return future::ok((state, res)).boxed();
+                            }
+
+                            let state = match self.fallbacks.dispatch(state, status, allow.clone())
+                            {
+                                Ok(future) => return future,
+                                Err(state) => state,
+                            };
 
                             trace!("[{}] responding with error status", request_id(&state));
-                            let mut res = create_empty_response(&state, status);
+                            let mut res = self.non_match_response(&state, status);
                             if let StatusCode::METHOD_NOT_ALLOWED = status {
                                 for allowed in allow {
                                     res.headers_mut().append(
@@ -107,7 +380,14 @@ impl Handler for Router {
                     }
                 } else {
                     trace!("[{}] did not find routable node", request_id(&state));
-                    let res = create_empty_response(&state, StatusCode::NOT_FOUND);
+                    let state = match self
+                        .fallbacks
+                        .dispatch(state, StatusCode::NOT_FOUND, vec![])
+                    {
+                        Ok(future) => return future,
+                        Err(state) => state,
+                    };
+                    let res = self.non_match_response(&state, StatusCode::NOT_FOUND);
                     future::ok((state, res)).boxed()
                 }
             }
@@ -116,13 +396,22 @@ impl Handler for Router {
                 let res = create_empty_response(&state, StatusCode::INTERNAL_SERVER_ERROR);
                 future::ok((state, res)).boxed()
             }
-        };
+        }
+    }
 
-        self.finalize_response(future)
+    /// Creates the response for a request which matched no route, rendered by the
+    /// `ErrorFormatter` if one is registered.
+    fn non_match_response(&self, state: &State, status: StatusCode) -> Response<Body> {
+        match &self.error_formatter {
+            Some(error_formatter) => {
+                let err = HandlerError::from(anyhow::anyhow!("no route matched the request"))
+                    .with_status(status);
+                format_error(state, &err, &**error_formatter)
+            }
+            None => create_empty_response(state, status),
+        }
     }
-}
 
-impl Router {
     /// Manually assembles a `Router` instance from a provided `Tree`.
     #[deprecated(
         since = "0.2.0",
@@ -134,9 +423,471 @@ impl Router {
 
     /// Same as `new`, but private and not deprecated.
     fn internal_new(tree: Tree, response_finalizer: ResponseFinalizer) -> Router {
+        let names = url_for::route_names(&tree);
         let router_data = RouterData::new(tree, response_finalizer);
         Router {
             data: Arc::new(router_data),
+            error_handler: None,
+            error_reporter: None,
+            error_formatter: None,
+            error_statuses: None,
+            panic_handler: None,
+            expose_error_details: false,
+            head_fallback: true,
+            response_hooks: Arc::new(Vec::new()),
+            mounts: Arc::new(Vec::new()),
+            trailing_slash: TrailingSlash::default(),
+            method_override: None,
+            fallbacks: Fallbacks::default(),
+            names: Arc::new(names),
+        }
+    }
+
+    /// Sets the options of `RouterBuilder` which apply to the whole `Router`.
+    fn with_builder_options(self, trailing_slash: TrailingSlash, fallbacks: Fallbacks) -> Router {
+        Router {
+            trailing_slash,
+            fallbacks,
+            ..self
+        }
+    }
+
+    /// Mounts `router` below the static path prefix `path`, so that independently built routers,
+    /// e.g. from different modules or crates, can be composed after they were built.
+    ///
+    /// Requests below `path` are handed to `router` with the prefix removed from their path, in
+    /// the same way as `DrawRoutes::delegate_without_pipelines`. The mounted router keeps its own
+    /// pipelines, error handling and response hooks, and the pipelines of this `Router` do not
+    /// apply. A mounted router takes precedence over the routes of this `Router` below `path`,
+    /// and of several matching mounts the one with the longest prefix is used.
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # extern crate hyper;
+    /// #
+    /// # use gotham::router::builder::*;
+    /// # use gotham::state::State;
+    /// # use gotham::test::TestServer;
+    /// # use hyper::StatusCode;
+    /// #
+    /// fn users(state: State) -> (State, &'static str) {
+    ///     (state, "users")
+    /// }
+    ///
+    /// fn index(state: State) -> (State, &'static str) {
+    ///     (state, "index")
+    /// }
+    ///
+    /// # fn main() {
+    /// // e.g. built by another crate
+    /// let api = build_simple_router(|route| {
+    ///     route.get("/users").to(users);
+    /// });
+    ///
+    /// let router = build_simple_router(|route| {
+    ///     route.get("/").to(index);
+    /// })
+    /// .mount("/api/v1", api);
+    ///
+    /// let test_server = TestServer::new(router).unwrap();
+    /// let response = test_server
+    ///     .client()
+    ///     .get("http://localhost/api/v1/users")
+    ///     .perform()
+    ///     .unwrap();
+    /// assert_eq!(response.read_utf8_body().unwrap(), "users");
+    ///
+    /// let response = test_server.client().get("http://localhost/users").perform().unwrap();
+    /// assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If `path` is empty or `/`, or contains a dynamic or glob segment.
+    pub fn mount(self, path: &str, router: Router) -> Router {
+        let segments: Vec<String> = split_path_segments(path).map(str::to_owned).collect();
+        assert!(!segments.is_empty(), "cannot mount a router at the root");
+        assert!(
+            segments
+                .iter()
+                .all(|segment| !segment.starts_with(':') && !segment.starts_with('*')),
+            "cannot mount a router below the non-static path {}",
+            path
+        );
+
+        let template = format!("/{}", segments.join("/"));
+        let mut names = self.names.as_ref().clone();
+        url_for::add_delegated_names(&mut names, &template, &router.names);
+
+        let mount = Mount {
+            template,
+            segments,
+            router,
+        };
+
+        let mut mounts = self.mounts.as_ref().clone();
+        mounts.push(mount);
+        // a stable sort keeps the registration order of mounts with prefixes of equal length
+        mounts.sort_by_key(|mount| Reverse(mount.segments.len()));
+
+        Router {
+            mounts: Arc::new(mounts),
+            names: Arc::new(names),
+            ..self
+        }
+    }
+
+    /// Registers an application wide `ErrorHandler`, which formats the response for every
+    /// `HandlerError` which bubbles up to this `Router`.
+    ///
+    /// Errors which were given a customized response at the call site (e.g. via
+    /// `map_err_with_customized_response`) keep that response, and are not passed to the
+    /// `ErrorHandler`.
+    ///
+    /// Headers added to the error via `HandlerError::with_header` are appended to the response
+    /// returned by the `ErrorHandler`.
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # extern crate hyper;
+    /// # extern crate mime;
+    /// #
+    /// # use gotham::handler::{HandlerError, HandlerResult};
+    /// # use gotham::helpers::http::response::create_response;
+    /// # use gotham::router::builder::*;
+    /// # use gotham::router::Router;
+    /// # use gotham::state::State;
+    /// # use gotham::test::TestServer;
+    /// # use hyper::{Body, Response, StatusCode};
+    /// #
+    /// async fn handler(state: State) -> HandlerResult {
+    ///     Err((state, std::io::Error::last_os_error().into()))
+    /// }
+    ///
+    /// fn error_handler(state: &State, err: HandlerError) -> Response<Body> {
+    ///     create_response(
+    ///         state,
+    ///         err.status(),
+    ///         mime::APPLICATION_JSON,
+    ///         r#"{"error":"something went wrong"}"#,
+    ///     )
+    /// }
+    ///
+    /// fn router() -> Router {
+    ///     build_simple_router(|route| {
+    ///         route.get("/").to_async(handler);
+    ///     })
+    ///     .with_error_handler(error_handler)
+    /// }
+    /// #
+    /// # fn main() {
+    /// #   let test_server = TestServer::new(router()).unwrap();
+    /// #   let response = test_server
+    /// #       .client()
+    /// #       .get("https://example.com/")
+    /// #       .perform()
+    /// #       .unwrap();
+    /// #   assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    /// #   let body = response.read_utf8_body().unwrap();
+    /// #   assert_eq!(body, r#"{"error":"something went wrong"}"#);
+    /// # }
+    /// ```
+    pub fn with_error_handler(self, error_handler: ErrorHandler) -> Router {
+        Router {
+            error_handler: Some(error_handler),
+            ..self
+        }
+    }
+
+    /// Registers an application wide `ErrorReporter`, which is notified of every `HandlerError`
+    /// which bubbles up to this `Router`, before it is converted into a response.
+    ///
+    /// See `JsonErrorReporter` for a reporter which emits structured JSON logs.
+    pub fn with_error_reporter<R>(self, error_reporter: R) -> Router
+    where
+        R: ErrorReporter + 'static,
+    {
+        Router {
+            error_reporter: Some(Arc::new(error_reporter)),
+            ..self
+        }
+    }
+
+    /// Registers an `ErrorFormatter`, which renders the body of error responses in the format
+    /// requested by the `Accept` header of the request, e.g. `DefaultErrorFormatter`. Routes and
+    /// scopes may declare a format instead, see `DrawRoutes::set_error_format`.
+    ///
+    /// Errors with a customized response body or problem details keep their body, and errors
+    /// formatted by an `ErrorHandler` or exposed via `expose_error_details` are not passed to the
+    /// `ErrorFormatter`. The `404 Not Found` and `405 Method Not Allowed` responses for requests
+    /// which match no route are rendered as well, unless a fallback handler is registered.
+    /// Without an `ErrorFormatter`, error responses have an empty body.
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # extern crate hyper;
+    /// #
+    /// # use gotham::handler::{DefaultErrorFormatter, HandlerError, HandlerResult};
+    /// # use gotham::router::builder::*;
+    /// # use gotham::state::State;
+    /// # use gotham::test::TestServer;
+    /// # use hyper::header::ACCEPT;
+    /// # use hyper::StatusCode;
+    /// #
+    /// async fn handler(state: State) -> HandlerResult {
+    ///     let err = HandlerError::not_found(std::io::Error::last_os_error());
+    ///     Err((state, err))
+    /// }
+    ///
+    /// # fn main() {
+    /// let router = build_simple_router(|route| {
+    ///     route.get("/").to_async(handler);
+    /// })
+    /// .with_error_formatter(DefaultErrorFormatter);
+    ///
+    /// let test_server = TestServer::new(router).unwrap();
+    /// let response = test_server
+    ///     .client()
+    ///     .get("https://example.com/")
+    ///     .with_header(ACCEPT, "application/json".parse().unwrap())
+    ///     .perform()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    /// let body = response.read_utf8_body().unwrap();
+    /// assert_eq!(body, r#"{"error":"Not Found","status":404}"#);
+    /// # }
+    /// ```
+    pub fn with_error_formatter<F>(self, error_formatter: F) -> Router
+    where
+        F: ErrorFormatter + 'static,
+    {
+        Router {
+            error_formatter: Some(Arc::new(error_formatter)),
+            ..self
+        }
+    }
+
+    /// Registers a `PanicHandler`, which turns panics raised while handling a request into a
+    /// `HandlerError`.
+    ///
+    /// The error is processed like any other error returned by a handler, so it is passed to the
+    /// `ErrorReporter` and `ErrorHandler`, and may carry a customized response body. Without a
+    /// `PanicHandler`, a panic results in an empty `500 Internal Server Error` response.
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # extern crate hyper;
+    /// #
+    /// # use std::any::Any;
+    /// # use gotham::handler::HandlerError;
+    /// # use gotham::router::builder::*;
+    /// # use gotham::router::{panic_message, Router};
+    /// # use gotham::state::State;
+    /// # use gotham::test::TestServer;
+    /// # use hyper::{Body, Response, StatusCode};
+    /// #
+    /// fn handler(_state: State) -> (State, Response<Body>) {
+    ///     panic!("out of widgets")
+    /// }
+    ///
+    /// fn panic_handler(_state: &State, payload: Box<dyn Any + Send>) -> HandlerError {
+    ///     let message = format!("handler panicked: {}", panic_message(&*payload));
+    ///     HandlerError::from(gotham::anyhow::anyhow!(message))
+    ///         .with_status(StatusCode::SERVICE_UNAVAILABLE)
+    ///         .with_problem_details()
+    /// }
+    ///
+    /// fn router() -> Router {
+    ///     build_simple_router(|route| {
+    ///         route.get("/").to(handler);
+    ///     })
+    ///     .with_panic_handler(panic_handler)
+    /// }
+    /// #
+    /// # fn main() {
+    /// #   let test_server = TestServer::new(router()).unwrap();
+    /// #   let response = test_server
+    /// #       .client()
+    /// #       .get("https://example.com/")
+    /// #       .perform()
+    /// #       .unwrap();
+    /// #   assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    /// #   let body = response.read_utf8_body().unwrap();
+    /// #   assert!(body.contains("handler panicked: out of widgets"));
+    /// # }
+    /// ```
+    pub fn with_panic_handler(self, panic_handler: PanicHandler) -> Router {
+        Router {
+            panic_handler: Some(panic_handler),
+            ..self
+        }
+    }
+
+    /// Registers an `ErrorStatusMap`, which assigns status codes to every `HandlerError` which
+    /// bubbles up to this `Router` without an explicit status code, before it is reported and
+    /// converted into a response.
+    ///
+    /// This overrides the status codes which `HandlerError` classifies from well-known causes,
+    /// e.g. `408 Request Timeout` for an `io::Error` of kind `TimedOut`:
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # extern crate hyper;
+    /// #
+    /// # use std::io;
+    /// #
+    /// # use gotham::handler::HandlerError;
+    /// # use gotham::middleware::error_status::ErrorStatusMap;
+    /// # use gotham::router::builder::*;
+    /// # use gotham::state::State;
+    /// # use gotham::test::TestServer;
+    /// # use hyper::StatusCode;
+    /// #
+    /// async fn handler(_state: &mut State) -> Result<&'static str, HandlerError> {
+    ///     Err(io::Error::new(io::ErrorKind::TimedOut, "upstream timed out"))?
+    /// }
+    ///
+    /// # fn main() {
+    /// let router = build_simple_router(|route| {
+    ///     route.get("/").to_async_borrowing(handler);
+    /// })
+    /// .with_error_status_map(ErrorStatusMap::new().register_with(|err: &io::Error| {
+    ///     match err.kind() {
+    ///         io::ErrorKind::TimedOut => Some(StatusCode::GATEWAY_TIMEOUT),
+    ///         _ => None,
+    ///     }
+    /// }));
+    ///
+    /// let test_server = TestServer::new(router).unwrap();
+    /// let response = test_server.client().get("http://localhost/").perform().unwrap();
+    /// assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    /// # }
+    /// ```
+    pub fn with_error_status_map(self, error_statuses: ErrorStatusMap) -> Router {
+        Router {
+            error_statuses: Some(error_statuses),
+            ..self
+        }
+    }
+
+    /// Enables rewriting the method of `POST` requests before route matching, see
+    /// `MethodOverride`. Disabled by default.
+    pub fn with_method_override(self, method_override: MethodOverride) -> Router {
+        Router {
+            method_override: Some(Arc::new(method_override)),
+            ..self
+        }
+    }
+
+    /// Registers a `ResponseHook`, which sees every response of this `Router` after all middleware,
+    /// error handling and `ResponseExtender` values, and before it is sent.
+    ///
+    /// Hooks run in the order they were registered. See `ResponseHook` for the full ordering
+    /// model.
+    pub fn with_response_hook<H>(self, response_hook: H) -> Router
+    where
+        H: ResponseHook + 'static,
+    {
+        let mut response_hooks = self.response_hooks.as_ref().clone();
+        response_hooks.push(Arc::new(response_hook));
+
+        Router {
+            response_hooks: Arc::new(response_hooks),
+            ..self
+        }
+    }
+
+    /// Enables rendering the full cause chain of a `HandlerError` as a plain text response body,
+    /// which is useful during development. Disabled by default, in which case the response body
+    /// is empty and only the status code is sent.
+    ///
+    /// Errors with a customized response body or problem details, and errors formatted by an
+    /// `ErrorHandler`, are not affected. Don't enable this in production, as error messages may
+    /// reveal implementation details.
+    pub fn expose_error_details(self, expose: bool) -> Router {
+        Router {
+            expose_error_details: expose,
+            ..self
+        }
+    }
+
+    /// Enables or disables answering `HEAD` requests for paths without a `HEAD` route with their
+    /// `GET` route. Enabled by default.
+    ///
+    /// The `GET` handler sees the request as a `GET` request, and the body of its response is
+    /// dropped, keeping the `Content-Length` of the body. `HEAD` is then listed in the `Allow`
+    /// header alongside `GET`. If disabled, such requests are answered with
+    /// `405 Method Not Allowed`, as for any other method without a route.
+    ///
+    /// ```rust
+    /// # extern crate gotham;
+    /// # extern crate hyper;
+    /// #
+    /// # use hyper::header::CONTENT_LENGTH;
+    /// # use hyper::StatusCode;
+    /// # use gotham::router::builder::*;
+    /// # use gotham::state::State;
+    /// # use gotham::test::TestServer;
+    /// #
+    /// fn index(state: State) -> (State, &'static str) {
+    ///     (state, "Hello, world!")
+    /// }
+    ///
+    /// # fn main() {
+    /// let router = build_simple_router(|route| {
+    ///     route.get("/").to(index);
+    /// });
+    ///
+    /// let test_server = TestServer::new(router.clone()).unwrap();
+    /// let response = test_server.client().head("http://localhost/").perform().unwrap();
+    /// assert_eq!(response.status(), StatusCode::OK);
+    /// assert_eq!(response.headers()[CONTENT_LENGTH], "13");
+    ///
+    /// let test_server = TestServer::new(router.with_head_fallback(false)).unwrap();
+    /// let response = test_server.client().head("http://localhost/").perform().unwrap();
+    /// assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    /// # }
+    /// ```
+    pub fn with_head_fallback(self, enabled: bool) -> Router {
+        Router {
+            head_fallback: enabled,
+            ..self
+        }
+    }
+
+    /// Dispatches the request to an internal `route` of `node`, unless the request path does not
+    /// match the trailing slash policy, applying the CORS configuration and body limit.
+    fn dispatch_internal<'a>(
+        &self,
+        mut state: State,
+        params: SegmentMapping<'a>,
+        node: &Node,
+        route: &(dyn Route<ResBody = Body> + Send + Sync),
+    ) -> Pin<Box<HandlerFuture>> {
+        if let Some(res) = self.trailing_slash.check(&state, node) {
+            trace!("[{}] trailing slash mismatch", request_id(&state));
+            return future::ok((state, res)).boxed();
+        }
+
+        trace!("[{}] dispatching to route", request_id(&state));
+        MatchedRoute::put(&mut state, node.template());
+        let cors = match node.cors() {
+            Some(config) if !cors::is_preflight(&state) => Some(config.clone()),
+            _ => None,
+        };
+
+        let future = match route.body_limit().or(node.body_limit()) {
+            Some(limit) => limit_body(state, limit, |state| {
+                self.dispatch(state, params, node, route)
+            }),
+            None => self.dispatch(state, params, node, route),
+        };
+
+        match cors {
+            Some(config) => cors::with_cors_headers(config, future),
+            None => future,
         }
     }
 
@@ -144,8 +895,44 @@ impl Router {
         &self,
         mut state: State,
         params: SegmentMapping<'a>,
-        route: &Box<dyn Route<ResBody = Body> + Send + Sync>,
+        node: &Node,
+        route: &(dyn Route<ResBody = Body> + Send + Sync),
     ) -> Pin<Box<HandlerFuture>> {
+        let path_decoding = route
+            .path_decoding()
+            .unwrap_or_else(|| node.path_decoding());
+
+        let redecoded: Vec<(&str, Vec<PercentDecoded>)>;
+        let params = match path_decoding {
+            PathDecoding::Decoded => params,
+            _ => {
+                redecoded = params
+                    .into_iter()
+                    .map(|(name, values)| {
+                        let values = values.into_iter().map(|value| path_decoding.apply(value));
+                        (name, values.collect())
+                    })
+                    .collect();
+                redecoded
+                    .iter()
+                    .map(|(name, values)| (*name, values.iter().collect()))
+                    .collect()
+            }
+        };
+        state.put(GlobSegments::from_template(node.template()));
+
+        for extractor in node.path_extractors() {
+            if extractor.extract(&mut state, params.clone()).is_err() {
+                error!(
+                    "[{}] the server cannot or will not process the request due to a client error on the request path",
+                    request_id(&state)
+                );
+                let mut res = Response::new(Body::empty());
+                extractor.extend(&mut state, &mut res);
+                return future::ok((state, res)).boxed();
+            }
+        }
+
         match route.extract_request_path(&mut state, params) {
             Ok(()) => {
                 trace!("[{}] extracted request path", request_id(&state));
@@ -177,42 +964,202 @@ impl Router {
         }
     }
 
-    fn finalize_response(&self, result: Pin<Box<HandlerFuture>>) -> Pin<Box<HandlerFuture>> {
+    /// Converts errors into responses and extends them. If `propagate_errors` is set, errors are
+    /// left to the delegating `Router` instead, unless this `Router` presents errors itself.
+    fn finalize_response(
+        &self,
+        result: Pin<Box<HandlerFuture>>,
+        propagate_errors: bool,
+    ) -> Pin<Box<HandlerFuture>> {
         let response_finalizer = self.data.response_finalizer.clone();
+        let error_handler = self.error_handler;
+        let error_reporter = self.error_reporter.clone();
+        let expose_error_details = self.expose_error_details;
+        let error_formatter = self.error_formatter.clone();
+        let error_statuses = self.error_statuses.clone();
+        let response_hooks = self.response_hooks.clone();
         result
-            .or_else(|(state, err)| {
+            .or_else(move |(state, mut err)| {
+                if let Some(error_statuses) = error_statuses {
+                    err = error_statuses.apply(err);
+                }
+                if propagate_errors
+                    && error_handler.is_none()
+                    && error_formatter.is_none()
+                    && !expose_error_details
+                {
+                    trace!(
+                        "[{}] handing error to the delegating router: {:?}",
+                        request_id(&state),
+                        err
+                    );
+                    return future::err((state, err));
+                }
                 trace!(
                     "[{}] converting error into http response \
                      during finalization: {:?}",
                     request_id(&state),
                     err
                 );
-                let response = err.into_response(&state);
+                if let Some(error_reporter) = error_reporter {
+                    error_reporter.report(&state, &err);
+                }
+                let response = match error_handler {
+                    Some(error_handler) if !err.has_customized_response_body() => {
+                        let headers = err.take_headers();
+                        let retry_after = err.retry_after();
+                        let mut response = error_handler(&state, err);
+                        append_headers(response.headers_mut(), headers);
+                        insert_retry_after(response.headers_mut(), retry_after);
+                        response
+                    }
+                    _ if expose_error_details => err.into_detailed_response(&state),
+                    _ => match error_formatter {
+                        Some(error_formatter) => {
+                            err.into_formatted_response(&state, &*error_formatter)
+                        }
+                        None => err.into_response(&state),
+                    },
+                };
                 future::ok((state, response))
             })
-            .and_then(move |(state, res)| {
+            .and_then(move |(mut state, res)| {
                 trace!("[{}] handler complete", request_id(&state));
+                if state.try_take::<IsolatedResponse>().is_some() {
+                    return future::ok((state, res)).boxed();
+                }
                 response_finalizer.finalize(state, res)
             })
+            .and_then(move |(state, mut res)| {
+                for response_hook in response_hooks.iter() {
+                    response_hook.finalize(&state, &mut res);
+                }
+                future::ok((state, res))
+            })
             .boxed()
     }
 }
 
+/// Marks the response of a secondary `Router` which doesn't propagate its errors, so the response
+/// extenders of the delegating `Router` leave it alone.
+fn isolate_response(future: Pin<Box<HandlerFuture>>) -> Pin<Box<HandlerFuture>> {
+    future
+        .map_ok(|(mut state, res)| {
+            state.put(IsolatedResponse);
+            (state, res)
+        })
+        .boxed()
+}
+
+/// Drops the body of the response of a `GET` route to a `HEAD` request, keeping its
+/// `Content-Length`, and restores the method of the request in `State`.
+fn strip_head_response(future: Pin<Box<HandlerFuture>>) -> Pin<Box<HandlerFuture>> {
+    async move {
+        match future.await {
+            Ok((mut state, mut res)) => {
+                state.put(Method::HEAD);
+                let len = HttpBody::size_hint(res.body()).exact();
+                if let (Some(len), false) = (len, res.headers().contains_key(CONTENT_LENGTH)) {
+                    res.headers_mut().insert(CONTENT_LENGTH, len.into());
+                }
+                *res.body_mut() = Body::empty();
+                Ok((state, res))
+            }
+            Err((mut state, err)) => {
+                state.put(Method::HEAD);
+                Err((state, err))
+            }
+        }
+    }
+    .boxed()
+}
+
+/// Creates the response to an `OPTIONS` request for a path without a route answering it, which
+/// lists the `allow`ed methods of the path, and `OPTIONS` itself, in the `Allow` header.
+fn options_response(state: &State, mut allow: Vec<Method>) -> Response<Body> {
+    if !allow.contains(&Method::OPTIONS) {
+        allow.push(Method::OPTIONS);
+    }
+
+    let allow: Vec<&str> = allow.iter().map(Method::as_str).collect();
+    let mut res = create_empty_response(state, StatusCode::OK);
+    res.headers_mut()
+        .insert(ALLOW, allow.join(", ").parse().unwrap());
+    res
+}
+
+/// Invokes `f`, converting any panic raised while creating or polling its future into a
+/// `HandlerError` via the `PanicHandler`.
+fn recover_panics<F>(state: State, panic_handler: PanicHandler, f: F) -> Pin<Box<HandlerFuture>>
+where
+    F: FnOnce(State) -> Pin<Box<HandlerFuture>>,
+{
+    let snapshot = snapshot(&state);
+
+    match catch_unwind(AssertUnwindSafe(move || f(state))) {
+        Ok(future) => AssertUnwindSafe(future)
+            .catch_unwind()
+            .then(move |result| match result {
+                Ok(result) => future::ready(result),
+                Err(payload) => future::err(recover(snapshot, panic_handler, payload)),
+            })
+            .boxed(),
+        Err(payload) => future::err(recover(snapshot, panic_handler, payload)).boxed(),
+    }
+}
+
+fn recover(
+    state: State,
+    panic_handler: PanicHandler,
+    payload: Box<dyn Any + Send>,
+) -> (State, HandlerError) {
+    error!(
+        "[{}] recovering from panic in handler: {}",
+        request_id(&state),
+        panic_message(&*payload)
+    );
+    let err = panic_handler(&state, payload);
+    (state, err)
+}
+
+/// Copies the parts of `state` which describe the request into a new `State`.
+pub(crate) fn snapshot(state: &State) -> State {
+    let mut snapshot = State::new();
+    if let Some(method) = Method::try_borrow_from(state) {
+        snapshot.put(method.clone());
+    }
+    if let Some(uri) = Uri::try_borrow_from(state) {
+        snapshot.put(uri.clone());
+    }
+    if let Some(version) = Version::try_borrow_from(state) {
+        snapshot.put(*version);
+    }
+    if let Some(headers) = HeaderMap::try_borrow_from(state) {
+        snapshot.put(headers.clone());
+    }
+    copy_request_id(state, &mut snapshot);
+    snapshot
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use hyper::header::{HeaderMap, CONTENT_LENGTH, CONTENT_TYPE};
+    use hyper::header::{HeaderMap, CONTENT_LENGTH, CONTENT_TYPE, LOCATION, RETRY_AFTER};
     use hyper::{Body, Method, Uri};
     use mime::TEXT_PLAIN;
     use std::str::FromStr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     use crate::extractor::{NoopPathExtractor, NoopQueryStringExtractor};
-    use crate::handler::HandlerError;
+    use crate::handler::HandlerResult;
+    use crate::handler::{DefaultErrorFormatter, HandlerError};
     use crate::pipeline::set::*;
+    use crate::router::builder::{build_simple_router, DefineSingleRoute, DrawRoutes};
     use crate::router::response::finalizer::ResponseFinalizerBuilder;
     use crate::router::route::dispatch::DispatcherImpl;
     use crate::router::route::matcher::{
-        AndRouteMatcher, ContentTypeHeaderRouteMatcher, MethodOnlyRouteMatcher,
+        AndRouteMatcher, AsyncMatchFuture, AsyncRouteMatcher, ContentTypeHeaderRouteMatcher,
+        MethodOnlyRouteMatcher,
     };
     use crate::router::route::{Extractors, RouteImpl};
     use crate::router::tree::node::Node;
@@ -411,6 +1358,451 @@ mod tests {
         };
     }
 
+    #[test]
+    #[allow(deprecated)]
+    fn error_handler_formats_and_reporter_receives_handler_errors() {
+        fn failing_handler(state: State) -> Pin<Box<HandlerFuture>> {
+            let err = HandlerError::from(std::io::Error::last_os_error())
+                .with_status(StatusCode::IM_A_TEAPOT);
+            future::err((state, err)).boxed()
+        }
+
+        fn error_handler(state: &State, err: HandlerError) -> Response<Body> {
+            let mut res = create_empty_response(state, err.status());
+            res.headers_mut()
+                .insert(CONTENT_LENGTH, "7".to_owned().parse().unwrap());
+            res
+        }
+
+        let pipeline_set = finalize_pipeline_set(new_pipeline_set());
+        let mut tree = Tree::new();
+
+        let route = {
+            let methods = vec![Method::GET];
+            let matcher = MethodOnlyRouteMatcher::new(methods);
+            let dispatcher = Box::new(DispatcherImpl::new(
+                || Ok(failing_handler),
+                (),
+                pipeline_set,
+            ));
+            let extractors: Extractors<NoopPathExtractor, NoopQueryStringExtractor> =
+                Extractors::new();
+            let route = RouteImpl::new(matcher, dispatcher, extractors, Delegation::Internal);
+            Box::new(route)
+        };
+        tree.add_route(route);
+
+        struct CountingReporter(Arc<AtomicUsize>);
+
+        impl ErrorReporter for CountingReporter {
+            fn report(&self, _state: &State, err: &HandlerError) {
+                assert_eq!(err.status(), StatusCode::IM_A_TEAPOT);
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let reported = Arc::new(AtomicUsize::new(0));
+        let router = Router::new(tree, ResponseFinalizerBuilder::new().finalize())
+            .with_error_handler(error_handler)
+            .with_error_reporter(CountingReporter(reported.clone()));
+
+        match send_request(router, Method::GET, "https://test.gotham.rs") {
+            Ok((_state, res)) => {
+                assert_eq!(res.status(), StatusCode::IM_A_TEAPOT);
+                assert_eq!(res.headers().get(CONTENT_LENGTH).unwrap(), "7");
+            }
+            Err(_) => unreachable!("Router should have handled request"),
+        };
+        assert_eq!(reported.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn error_handler_responses_include_retry_after() {
+        async fn failing_handler(state: State) -> HandlerResult {
+            let err = HandlerError::from(anyhow::anyhow!("rate limit exceeded"))
+                .with_status(StatusCode::TOO_MANY_REQUESTS)
+                .with_retry_after(std::time::Duration::from_secs(30));
+            Err((state, err))
+        }
+
+        async fn overriding_handler(state: State) -> HandlerResult {
+            let err = HandlerError::from(anyhow::anyhow!("rate limit exceeded"))
+                .with_retry_after(std::time::Duration::from_secs(30));
+            Err((state, err))
+        }
+
+        fn error_handler(state: &State, err: HandlerError) -> Response<Body> {
+            let mut res = create_empty_response(state, err.status());
+            if *Method::borrow_from(state) == Method::POST {
+                res.headers_mut().insert(RETRY_AFTER, "5".parse().unwrap());
+            }
+            res
+        }
+
+        let router = build_simple_router(|route| {
+            route.get("/").to_async(failing_handler);
+            route.post("/").to_async(overriding_handler);
+        })
+        .with_error_handler(error_handler);
+
+        let retry_after =
+            |method| match send_request(router.clone(), method, "https://test.gotham.rs") {
+                Ok((_state, res)) => res.headers()[RETRY_AFTER].to_str().unwrap().to_owned(),
+                Err(_) => unreachable!("Router should have handled request"),
+            };
+        assert_eq!(retry_after(Method::GET), "30");
+        // a Retry-After header set by the error handler takes precedence
+        assert_eq!(retry_after(Method::POST), "5");
+    }
+
+    #[test]
+    fn expose_error_details_renders_cause_chain() {
+        async fn failing_handler(state: State) -> HandlerResult {
+            let err = HandlerError::from(anyhow::anyhow!("disk full")).context("saving widget");
+            Err((state, err))
+        }
+
+        let router = |expose| {
+            build_simple_router(|route| {
+                route.get("/").to_async(failing_handler);
+            })
+            .expose_error_details(expose)
+        };
+
+        let body = |router| match send_request(router, Method::GET, "https://test.gotham.rs") {
+            Ok((_state, res)) => {
+                assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+                let body = futures::executor::block_on(hyper::body::to_bytes(res.into_body()));
+                String::from_utf8(body.unwrap().to_vec()).unwrap()
+            }
+            Err(_) => unreachable!("Router should have handled request"),
+        };
+
+        assert_eq!(body(router(false)), "");
+        let details = body(router(true));
+        assert!(details.starts_with("saving widget"));
+        assert!(details.contains("Caused by:"));
+        assert!(details.contains("disk full"));
+    }
+
+    #[test]
+    fn applies_declared_path_decoding() {
+        #[derive(serde_derive::Deserialize)]
+        struct IdExtractor {
+            id: String,
+        }
+
+        impl StateData for IdExtractor {}
+
+        impl crate::router::response::extender::StaticResponseExtender for IdExtractor {
+            type ResBody = Body;
+            fn extend(_state: &mut State, _res: &mut Response<Body>) {}
+        }
+
+        fn handler(state: State) -> (State, String) {
+            let id = IdExtractor::borrow_from(&state).id.clone();
+            (state, id)
+        }
+
+        let router = build_simple_router(|route| {
+            route
+                .get("/decoded/:id")
+                .with_path_extractor::<IdExtractor>()
+                .to(handler);
+
+            route.scope("/raw", |route| {
+                route.set_path_decoding(PathDecoding::Raw);
+                route
+                    .get("/:id")
+                    .with_path_extractor::<IdExtractor>()
+                    .to(handler);
+                route
+                    .get("/keep/:id")
+                    .with_path_extractor::<IdExtractor>()
+                    .with_path_decoding(PathDecoding::DecodedExceptSlash)
+                    .to(handler);
+            });
+        });
+
+        let body = |uri| match send_request(router.clone(), Method::GET, uri) {
+            Ok((_state, res)) => {
+                let body = futures::executor::block_on(hyper::body::to_bytes(res.into_body()));
+                String::from_utf8(body.unwrap().to_vec()).unwrap()
+            }
+            Err(_) => unreachable!("Router should have handled request"),
+        };
+
+        assert_eq!(body("http://localhost/decoded/a%2Fb%20c"), "a/b c");
+        assert_eq!(body("http://localhost/raw/a%2Fb%20c"), "a%2Fb%20c");
+        assert_eq!(body("http://localhost/raw/keep/a%2Fb%20c"), "a%2Fb c");
+    }
+
+    #[test]
+    fn joins_the_segments_of_glob_path_extractor_fields() {
+        #[derive(serde_derive::Deserialize)]
+        struct GlobExtractor {
+            path: String,
+        }
+
+        impl StateData for GlobExtractor {}
+
+        impl crate::router::response::extender::StaticResponseExtender for GlobExtractor {
+            type ResBody = Body;
+            fn extend(_state: &mut State, _res: &mut Response<Body>) {}
+        }
+
+        fn handler(state: State) -> (State, String) {
+            let path = GlobExtractor::borrow_from(&state).path.clone();
+            (state, path)
+        }
+
+        let router = build_simple_router(|route| {
+            route
+                .get("/assets/*path")
+                .with_path_extractor::<GlobExtractor>()
+                .to(handler);
+        });
+
+        match send_request(router, Method::GET, "http://localhost/assets/css/site.css") {
+            Ok((_state, res)) => {
+                let body = futures::executor::block_on(hyper::body::to_bytes(res.into_body()));
+                assert_eq!(&body.unwrap()[..], b"css/site.css");
+            }
+            Err(_) => unreachable!("Router should have handled request"),
+        };
+    }
+
+    #[test]
+    fn answers_options_requests_with_the_allowed_methods() {
+        fn handler(state: State) -> (State, &'static str) {
+            (state, "handled")
+        }
+
+        let router = build_simple_router(|route| {
+            route.get("/users").to(handler);
+            route.delete("/users").to(handler);
+            route.get("/custom").to(handler);
+            route.options("/custom").to(handler);
+        });
+
+        let response = |uri| match send_request(router.clone(), Method::OPTIONS, uri) {
+            Ok((_state, res)) => res,
+            Err(_) => unreachable!("Router should have handled request"),
+        };
+
+        let res = response("http://localhost/users");
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[ALLOW], "DELETE, GET, HEAD, OPTIONS");
+
+        let res = response("http://localhost/custom");
+        let body = futures::executor::block_on(hyper::body::to_bytes(res.into_body()));
+        assert_eq!(&body.unwrap()[..], b"handled");
+
+        assert_eq!(
+            response("http://localhost/missing").status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn dispatches_head_requests_to_get_routes() {
+        fn get(state: State) -> (State, String) {
+            let method = Method::borrow_from(&state).to_string();
+            (state, method)
+        }
+
+        fn head(state: State) -> (State, Response<Body>) {
+            let res = create_empty_response(&state, StatusCode::NO_CONTENT);
+            (state, res)
+        }
+
+        let router = build_simple_router(|route| {
+            route.get("/").to(get);
+            route.get("/explicit").to(get);
+            route.head("/explicit").to(head);
+        });
+
+        let response = |router: Router, uri| match send_request(router, Method::HEAD, uri) {
+            Ok((state, res)) => {
+                assert_eq!(*Method::borrow_from(&state), Method::HEAD);
+                res
+            }
+            Err(_) => unreachable!("Router should have handled request"),
+        };
+
+        let res = response(router.clone(), "http://localhost/");
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_LENGTH], "3");
+        let body = futures::executor::block_on(hyper::body::to_bytes(res.into_body()));
+        assert!(body.unwrap().is_empty());
+
+        let res = response(router.clone(), "http://localhost/explicit");
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+        let res = response(router.with_head_fallback(false), "http://localhost/");
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers()[ALLOW], "GET");
+    }
+
+    #[test]
+    fn rejects_request_bodies_above_the_declared_limit() {
+        async fn read_body(mut state: State) -> HandlerResult {
+            let body = Body::take_from(&mut state);
+            match hyper::body::to_bytes(body).await {
+                Ok(body) => {
+                    let res = format!("{} bytes", body.len()).into_response(&state);
+                    Ok((state, res))
+                }
+                Err(e) => Err((state, e.into())),
+            }
+        }
+
+        let router = build_simple_router(|route| {
+            route.set_body_limit(8);
+            route.post("/small").to_async(read_body);
+            route.post("/large").with_body_limit(64).to_async(read_body);
+        });
+
+        let status = |uri, body: Vec<u8>, declare_length: bool| {
+            let uri = Uri::from_str(uri).unwrap();
+            let mut headers = HeaderMap::new();
+            if declare_length {
+                headers.insert(CONTENT_LENGTH, body.len().into());
+            }
+
+            let mut state = State::new();
+            state.put(RequestPathSegments::new(uri.path()));
+            state.put(Method::POST);
+            state.put(uri);
+            state.put(headers);
+            state.put(Body::wrap_stream(stream::iter(
+                body.chunks(4)
+                    .map(|chunk| Ok::<_, std::io::Error>(chunk.to_vec()))
+                    .collect::<Vec<_>>(),
+            )));
+            set_request_id(&mut state);
+
+            match futures::executor::block_on(router.clone().handle(state)) {
+                Ok((_state, res)) => res.status(),
+                Err((_state, err)) => err.status(),
+            }
+        };
+
+        assert_eq!(
+            status("http://localhost/small", vec![0; 8], true),
+            StatusCode::OK
+        );
+        assert_eq!(
+            status("http://localhost/small", vec![0; 9], true),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(
+            status("http://localhost/small", vec![0; 8], false),
+            StatusCode::OK
+        );
+        assert_eq!(
+            status("http://localhost/small", vec![0; 12], false),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(
+            status("http://localhost/large", vec![0; 64], true),
+            StatusCode::OK
+        );
+        assert_eq!(
+            status("http://localhost/large", vec![0; 68], false),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+
+    #[test]
+    fn declared_error_formats_render_errors_and_non_matches() {
+        use crate::handler::{DefaultErrorFormatter, ErrorFormat};
+
+        async fn failing_handler(state: State) -> HandlerResult {
+            Err((state, HandlerError::from(anyhow::anyhow!("boom"))))
+        }
+
+        let router = build_simple_router(|route| {
+            route.set_error_format(ErrorFormat::Html);
+            route.get("/").to_async(failing_handler);
+            route.scope("/api", |route| {
+                route.set_error_format(ErrorFormat::Json);
+                route.get("/users").to_async(failing_handler);
+                route
+                    .get("/export")
+                    .with_error_format(ErrorFormat::Text)
+                    .to_async(failing_handler);
+            });
+        })
+        .with_error_formatter(DefaultErrorFormatter);
+
+        let response = |method, uri| match send_request(router.clone(), method, uri) {
+            Ok((_state, res)) => {
+                let content_type = res.headers()[CONTENT_TYPE].to_str().unwrap().to_owned();
+                (res.status(), content_type)
+            }
+            Err(_) => unreachable!("Router should have handled request"),
+        };
+
+        let html = mime::TEXT_HTML_UTF_8.to_string();
+        let json = mime::APPLICATION_JSON.to_string();
+        let text = mime::TEXT_PLAIN_UTF_8.to_string();
+        let cases = [
+            (Method::GET, "https://test.gotham.rs/", 500, &html),
+            (Method::GET, "https://test.gotham.rs/missing", 404, &html),
+            (Method::GET, "https://test.gotham.rs/api/users", 500, &json),
+            (Method::POST, "https://test.gotham.rs/api/users", 405, &json),
+            (Method::GET, "https://test.gotham.rs/api/none", 404, &json),
+            (Method::GET, "https://test.gotham.rs/api/export", 500, &text),
+        ];
+
+        for (method, uri, status, content_type) in cases.iter() {
+            let (actual_status, actual_type) = response(method.clone(), uri);
+            assert_eq!(actual_status.as_u16(), *status, "{}", uri);
+            assert_eq!(&actual_type, *content_type, "{}", uri);
+        }
+    }
+
+    #[test]
+    fn panic_handler_recovers_from_panics() {
+        fn sync_handler(_state: State) -> (State, Response<Body>) {
+            panic!("sync")
+        }
+
+        async fn async_handler(_state: State) -> HandlerResult {
+            panic!("async")
+        }
+
+        fn panic_handler(state: &State, payload: Box<dyn Any + Send>) -> HandlerError {
+            assert_eq!(Method::borrow_from(state), Method::GET);
+            HandlerError::from(anyhow::anyhow!("{}", panic_message(&*payload)))
+                .with_status(StatusCode::SERVICE_UNAVAILABLE)
+        }
+
+        let router = build_simple_router(|route| {
+            route.get("/sync").to(sync_handler);
+            route.get("/async").to_async(async_handler);
+        })
+        .with_panic_handler(panic_handler);
+
+        for uri in &[
+            "https://test.gotham.rs/sync",
+            "https://test.gotham.rs/async",
+        ] {
+            match send_request(router.clone(), Method::GET, uri) {
+                Ok((state, res)) => {
+                    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+                    assert_eq!(
+                        request_id(&state).len(),
+                        36,
+                        "the request id should be preserved"
+                    );
+                }
+                Err(_) => unreachable!("Router should have handled request"),
+            }
+        }
+    }
+
     #[test]
     #[allow(deprecated)]
     fn executes_response_finalizer_when_present() {
@@ -432,4 +1824,346 @@ mod tests {
             Err(_) => unreachable!("Router should have correctly handled request"),
         };
     }
+
+    #[test]
+    fn records_matched_route_template() {
+        fn template(state: State) -> (State, String) {
+            let template = MatchedRoute::borrow_from(&state).template().to_owned();
+            (state, template)
+        }
+
+        let secondary = build_simple_router(|route| {
+            route.get("/").to(template);
+            route.get("/users/:id").to(template);
+        });
+        let router = build_simple_router(|route| {
+            route.get("/files/*").to(template);
+            route.delegate("/api").to_router(secondary);
+        });
+
+        for (uri, expected) in &[
+            ("https://test.gotham.rs/files/a/b", "/files/*"),
+            ("https://test.gotham.rs/api/users/7", "/api/users/:id"),
+            ("https://test.gotham.rs/api", "/api"),
+        ] {
+            match send_request(router.clone(), Method::GET, uri) {
+                Ok((_state, res)) => {
+                    let body = futures::executor::block_on(hyper::body::to_bytes(res.into_body()));
+                    assert_eq!(&body.unwrap()[..], expected.as_bytes());
+                }
+                Err(_) => unreachable!("Router should have handled request"),
+            }
+        }
+    }
+
+    #[test]
+    fn runs_response_hooks_after_extenders_in_order() {
+        fn trace(step: &'static str) -> impl Fn(&State, &mut Response<Body>) + Send + Sync {
+            move |_state: &State, response: &mut Response<Body>| {
+                response
+                    .headers_mut()
+                    .append("x-trace", step.parse().unwrap());
+            }
+        }
+
+        let secondary = build_simple_router(|route| {
+            route.get("/").to(handler);
+        })
+        .with_response_hook(trace("inner"));
+        let router = build_simple_router(|route| {
+            route.add_response_extender(
+                StatusCode::NOT_FOUND,
+                |_: &mut State, r: &mut Response<Body>| {
+                    r.headers_mut()
+                        .append("x-trace", "extender".parse().unwrap());
+                },
+            );
+            route.delegate("/api").to_router(secondary);
+        })
+        .with_response_hook(trace("first"))
+        .with_response_hook(trace("second"));
+
+        for (uri, expected) in &[
+            (
+                "https://test.gotham.rs/missing",
+                &["extender", "first", "second"],
+            ),
+            ("https://test.gotham.rs/api", &["inner", "first", "second"]),
+        ] {
+            match send_request(router.clone(), Method::GET, uri) {
+                Ok((_state, res)) => {
+                    let trace: Vec<_> = res.headers().get_all("x-trace").iter().collect();
+                    assert_eq!(trace, expected.to_vec());
+                }
+                Err(_) => unreachable!("Router should have handled request"),
+            }
+        }
+    }
+
+    #[test]
+    fn selects_routes_with_async_matchers() {
+        struct Allow(bool);
+
+        impl AsyncRouteMatcher for Allow {
+            fn is_match(&self, _state: &State) -> AsyncMatchFuture {
+                let allow = self.0;
+                async move {
+                    if allow {
+                        Ok(())
+                    } else {
+                        Err(RouteNonMatch::new(StatusCode::FORBIDDEN))
+                    }
+                }
+                .boxed()
+            }
+        }
+
+        fn allowed(state: State) -> (State, &'static str) {
+            (state, "allowed")
+        }
+
+        fn fallback(state: State) -> (State, &'static str) {
+            (state, "fallback")
+        }
+
+        let router = build_simple_router(|route| {
+            route
+                .get("/")
+                .add_async_route_matcher(Allow(false))
+                .to(allowed);
+            route.get("/").to(fallback);
+            route
+                .get("/allowed")
+                .add_async_route_matcher(Allow(true))
+                .to(allowed);
+            route
+                .get("/denied")
+                .add_async_route_matcher(Allow(false))
+                .to(allowed);
+        });
+
+        for (method, uri, status, expected) in &[
+            (
+                Method::GET,
+                "https://test.gotham.rs/",
+                StatusCode::OK,
+                "fallback",
+            ),
+            (
+                Method::GET,
+                "https://test.gotham.rs/allowed",
+                StatusCode::OK,
+                "allowed",
+            ),
+            (
+                Method::HEAD,
+                "https://test.gotham.rs/allowed",
+                StatusCode::OK,
+                "",
+            ),
+            (
+                Method::GET,
+                "https://test.gotham.rs/denied",
+                StatusCode::FORBIDDEN,
+                "",
+            ),
+        ] {
+            match send_request(router.clone(), method.clone(), uri) {
+                Ok((_state, res)) => {
+                    assert_eq!(res.status(), *status);
+                    let body = futures::executor::block_on(hyper::body::to_bytes(res.into_body()));
+                    assert_eq!(&body.unwrap()[..], expected.as_bytes());
+                }
+                Err(_) => unreachable!("Router should have handled request"),
+            }
+        }
+    }
+
+    #[test]
+    fn evaluates_async_matchers_of_candidate_routes_only() {
+        struct Count(Arc<AtomicUsize>);
+
+        impl AsyncRouteMatcher for Count {
+            fn is_match(&self, _state: &State) -> AsyncMatchFuture {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                future::ok(()).boxed()
+            }
+        }
+
+        fn handler(state: State) -> (State, &'static str) {
+            (state, "")
+        }
+
+        let post = Arc::new(AtomicUsize::new(0));
+        let router = {
+            let post = post.clone();
+            build_simple_router(move |route| {
+                route
+                    .post("/")
+                    .add_async_route_matcher(Count(post))
+                    .to(handler);
+                route.get("/").to(handler);
+            })
+        };
+
+        match send_request(router.clone(), Method::GET, "https://test.gotham.rs/") {
+            Ok((_state, res)) => assert_eq!(res.status(), StatusCode::OK),
+            Err(_) => unreachable!("Router should have handled request"),
+        }
+        assert_eq!(post.load(Ordering::SeqCst), 0);
+
+        match send_request(router, Method::POST, "https://test.gotham.rs/") {
+            Ok((_state, res)) => assert_eq!(res.status(), StatusCode::OK),
+            Err(_) => unreachable!("Router should have handled request"),
+        }
+        assert_eq!(post.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn propagates_errors_of_delegated_routers() {
+        fn failing_handler(state: State) -> Pin<Box<HandlerFuture>> {
+            let err = HandlerError::from(anyhow::anyhow!("failed")).with_status(StatusCode::GONE);
+            future::err((state, err)).boxed()
+        }
+
+        fn extend(_state: &mut State, res: &mut Response<Body>) {
+            res.headers_mut()
+                .insert("x-extended", "outer".parse().unwrap());
+        }
+
+        let secondary = || {
+            build_simple_router(|route| {
+                route.get("/").to(failing_handler);
+            })
+        };
+        let router = build_simple_router(|route| {
+            route.add_response_extender(StatusCode::GONE, extend);
+            route.delegate("/api").to_router(secondary());
+            route
+                .delegate("/isolated")
+                .without_error_propagation()
+                .to_router(secondary());
+        })
+        .with_error_formatter(DefaultErrorFormatter);
+
+        let response = |uri| match send_request(router.clone(), Method::GET, uri) {
+            Ok((_state, res)) => {
+                assert_eq!(res.status(), StatusCode::GONE);
+                let extended = res.headers().contains_key("x-extended");
+                let body = futures::executor::block_on(hyper::body::to_bytes(res.into_body()));
+                (extended, body.unwrap())
+            }
+            Err(_) => unreachable!("Router should have handled request"),
+        };
+
+        let (extended, body) = response("https://test.gotham.rs/api");
+        assert!(extended);
+        assert_eq!(&body[..], b"410 Gone");
+
+        let (extended, body) = response("https://test.gotham.rs/isolated");
+        assert!(!extended);
+        assert!(body.is_empty());
+    }
+
+    #[test]
+    fn dispatches_to_mounted_routers() {
+        fn template(state: State) -> (State, String) {
+            let template = MatchedRoute::borrow_from(&state).template().to_owned();
+            (state, template)
+        }
+
+        fn failing_handler(state: State) -> Pin<Box<HandlerFuture>> {
+            let err = HandlerError::from(anyhow::anyhow!("failed"));
+            future::err((state, err)).boxed()
+        }
+
+        fn teapot(_state: &State, _err: HandlerError) -> Response<Body> {
+            let mut res = Response::new(Body::empty());
+            *res.status_mut() = StatusCode::IM_A_TEAPOT;
+            res
+        }
+
+        let v1 = build_simple_router(|route| {
+            route.get("/users/:id").to(template);
+            route.get("/fail").to(failing_handler);
+        })
+        .with_error_handler(teapot);
+        let v1_admin = build_simple_router(|route| {
+            route.get("/").to(template);
+        });
+        let router = build_simple_router(|route| {
+            route.get("/api/v1/users/:id").to(handler);
+        })
+        .mount("/api/v1", v1)
+        .mount("/api/v1/admin/", v1_admin);
+
+        for (uri, status, expected) in &[
+            (
+                "https://test.gotham.rs/api/v1/users/7",
+                200,
+                "/api/v1/users/:id",
+            ),
+            ("https://test.gotham.rs/api/v1/admin", 200, "/api/v1/admin"),
+            ("https://test.gotham.rs/api/v1/fail", 418, ""),
+            ("https://test.gotham.rs/api/v2/users/7", 404, ""),
+        ] {
+            match send_request(router.clone(), Method::GET, uri) {
+                Ok((_state, res)) => {
+                    assert_eq!(res.status().as_u16(), *status);
+                    let body = futures::executor::block_on(hyper::body::to_bytes(res.into_body()));
+                    assert_eq!(&body.unwrap()[..], expected.as_bytes());
+                }
+                Err(_) => unreachable!("Router should have handled request"),
+            }
+        }
+    }
+
+    #[test]
+    fn applies_trailing_slash_policy() {
+        fn router(policy: TrailingSlash) -> Router {
+            build_simple_router(|route| {
+                route.set_trailing_slash(policy);
+                route.get("/").to(handler);
+                route.get("/users").to(handler);
+                route.get("/groups/").to(handler);
+                route.get("/files/*").to(handler);
+            })
+        }
+
+        let cases: &[(TrailingSlash, &str, u16, Option<&str>)] = &[
+            (TrailingSlash::TreatAsEquivalent, "/users/", 200, None),
+            (TrailingSlash::TreatAsEquivalent, "/groups", 200, None),
+            (TrailingSlash::Strict, "/users", 200, None),
+            (TrailingSlash::Strict, "/users/", 404, None),
+            (TrailingSlash::Strict, "/groups/", 200, None),
+            (TrailingSlash::Strict, "/groups", 404, None),
+            (TrailingSlash::Strict, "/files/a/", 200, None),
+            (TrailingSlash::Strict, "/", 200, None),
+            (TrailingSlash::RedirectToCanonical, "/users", 200, None),
+            (
+                TrailingSlash::RedirectToCanonical,
+                "/users/?page=2",
+                308,
+                Some("/users?page=2"),
+            ),
+            (
+                TrailingSlash::RedirectToCanonical,
+                "/groups",
+                308,
+                Some("/groups/"),
+            ),
+        ];
+
+        for (policy, path, status, location) in cases {
+            let uri = format!("https://test.gotham.rs{}", path);
+            match send_request(router(*policy), Method::GET, &uri) {
+                Ok((_state, res)) => {
+                    assert_eq!(res.status().as_u16(), *status, "{:?} {}", policy, path);
+                    let actual = res.headers().get(LOCATION).map(|l| l.to_str().unwrap());
+                    assert_eq!(actual, *location);
+                }
+                Err(_) => unreachable!("Router should have handled request"),
+            }
+        }
+    }
 }
@@ -0,0 +1,137 @@
+//! Defines how the segments of the request path are decoded for the path extractor of a route,
+//! see `PathDecoding`.
+
+use percent_encoding::percent_decode_str;
+
+use crate::helpers::http::PercentDecoded;
+
+/// The policy for percent-decoding the segments of the request path which are passed to the
+/// `PathExtractor` of a route.
+///
+/// Routes are matched against the decoded path under every policy, only the values of dynamic and
+/// glob segments differ. A policy is declared for a scope with `DrawRoutes::set_path_decoding`
+/// or for a single route with `DefineSingleRoute::with_path_decoding`, and applies to nested
+/// scopes and routes unless they declare their own.
+///
+/// ```rust
+/// # extern crate gotham;
+/// # #[macro_use]
+/// # extern crate gotham_derive;
+/// # #[macro_use]
+/// # extern crate serde_derive;
+/// #
+/// # use gotham::router::PathDecoding;
+/// # use gotham::router::builder::*;
+/// # use gotham::state::{FromState, State};
+/// # use gotham::test::TestServer;
+/// #
+/// #[derive(Deserialize, StateData, StaticResponseExtender)]
+/// struct PathExtractor {
+///     #[serde(rename = "*")]
+///     parts: Vec<String>,
+/// }
+///
+/// fn handler(state: State) -> (State, String) {
+///     let parts = PathExtractor::borrow_from(&state).parts.join(" | ");
+///     (state, parts)
+/// }
+///
+/// # fn main() {
+/// let router = build_simple_router(|route| {
+///     route
+///         .get("/decoded/*")
+///         .with_path_extractor::<PathExtractor>()
+///         .to(handler);
+///
+///     route.scope("/proxy", |route| {
+///         route.set_path_decoding(PathDecoding::Raw);
+///         route
+///             .get("/*")
+///             .with_path_extractor::<PathExtractor>()
+///             .to(handler);
+///     });
+/// });
+///
+/// let test_server = TestServer::new(router).unwrap();
+/// let client = test_server.client();
+///
+/// let response = client.get("http://localhost/decoded/a%2Fb/c%20d").perform().unwrap();
+/// assert_eq!(response.read_utf8_body().unwrap(), "a/b | c d");
+///
+/// let response = client.get("http://localhost/proxy/a%2Fb/c%20d").perform().unwrap();
+/// assert_eq!(response.read_utf8_body().unwrap(), "a%2Fb | c%20d");
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PathDecoding {
+    /// Segments are passed as they appear in the request, e.g. for routes proxying requests to
+    /// another server.
+    Raw,
+
+    /// Segments are fully percent-decoded, so `a%2Fb` becomes `a/b`. This is the default.
+    #[default]
+    Decoded,
+
+    /// Segments are percent-decoded except for encoded slashes, so `a%2Fb%20c` becomes `a%2Fb c`,
+    /// which keeps the segments of a decoded path distinguishable.
+    DecodedExceptSlash,
+}
+
+impl PathDecoding {
+    /// Decodes `segment` again according to the policy, given that it was decoded by
+    /// `PercentDecoded::new`.
+    pub(crate) fn apply(self, segment: &PercentDecoded) -> PercentDecoded {
+        let raw = segment.raw();
+        match self {
+            PathDecoding::Decoded => segment.clone(),
+            PathDecoding::Raw => PercentDecoded::with_value(raw, raw.to_owned()),
+            PathDecoding::DecodedExceptSlash => {
+                // splitting at an ASCII character keeps each part valid UTF-8, as the whole
+                // segment is
+                let value = split_encoded_slashes(raw)
+                    .map(|part| percent_decode_str(part).decode_utf8_lossy())
+                    .collect::<Vec<_>>()
+                    .join("%2F");
+                PercentDecoded::with_value(raw, value)
+            }
+        }
+    }
+}
+
+/// Splits `raw` at encoded slashes, i.e. `%2F` or `%2f`.
+fn split_encoded_slashes(raw: &str) -> impl Iterator<Item = &str> {
+    let mut rest = Some(raw);
+    std::iter::from_fn(move || {
+        let current = rest?;
+        let found = current
+            .as_bytes()
+            .windows(3)
+            .position(|window| window[0] == b'%' && window[1] == b'2' && window[2] | 0x20 == b'f');
+
+        match found {
+            Some(index) => {
+                rest = Some(&current[index + 3..]);
+                Some(&current[..index])
+            }
+            None => {
+                rest = None;
+                Some(current)
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_policies() {
+        let segment = PercentDecoded::new("a%2fb%2Fc%20d%E2%9C%93").unwrap();
+        let decode = |policy: PathDecoding| policy.apply(&segment).as_ref().to_owned();
+
+        assert_eq!(decode(PathDecoding::Decoded), "a/b/c d✓");
+        assert_eq!(decode(PathDecoding::Raw), "a%2fb%2Fc%20d%E2%9C%93");
+        assert_eq!(decode(PathDecoding::DecodedExceptSlash), "a%2Fb%2Fc d✓");
+    }
+}
@@ -0,0 +1,76 @@
+//! Defines hooks which see every `Response` of a `Router` before it leaves the application.
+
+use std::panic::RefUnwindSafe;
+
+use hyper::{Body, Response};
+
+use crate::state::State;
+
+/// Inspects or modifies every `Response` produced by a `Router`, after everything else which may
+/// alter it, so that an application can enforce invariants such as security headers being present
+/// or internal headers being removed.
+///
+/// A `ResponseHook` is registered via `Router::with_response_hook`. A response is built up in the
+/// following order, and every step sees the changes of the previous ones:
+///
+/// 1. Framework headers, e.g. `X-Request-ID` and `Content-Type` added by `create_response`, are
+///    set when the handler creates the response.
+/// 2. Middleware alter the response on its way out, innermost middleware first.
+/// 3. Errors are converted into responses, by the `ErrorHandler`, `ErrorFormatter` or the
+///    `HandlerError` itself.
+/// 4. The `ResponseExtender` registered for the status code of the response runs.
+/// 5. Response hooks run, in the order they were registered.
+///
+/// The response of every request handled by the `Router` passes through these steps, including
+/// responses for unmatched routes, errors and recovered panics. When a `Router` delegates to a
+/// secondary `Router`, the hooks of the secondary `Router` run before those of the delegating one.
+///
+/// ```rust
+/// # extern crate gotham;
+/// # extern crate hyper;
+/// #
+/// # use gotham::router::builder::*;
+/// # use gotham::test::TestServer;
+/// # use hyper::header::{HeaderValue, X_FRAME_OPTIONS};
+/// # use hyper::{Body, Response};
+/// #
+/// # fn main() {
+/// let router = build_simple_router(|route| {
+///     route.get("/").to(|state| {
+///         let response = Response::builder()
+///             .header("x-internal-shard", "7")
+///             .body(Body::from("hello"))
+///             .unwrap();
+///         (state, response)
+///     });
+/// })
+/// .with_response_hook(|_: &_, response: &mut Response<Body>| {
+///     let headers = response.headers_mut();
+///     headers
+///         .entry(X_FRAME_OPTIONS)
+///         .or_insert(HeaderValue::from_static("DENY"));
+///     headers.remove("x-internal-shard");
+/// });
+///
+/// let test_server = TestServer::new(router).unwrap();
+/// for uri in &["http://localhost/", "http://localhost/missing"] {
+///     let response = test_server.client().get(*uri).perform().unwrap();
+///     assert_eq!(response.headers()[X_FRAME_OPTIONS], "DENY");
+///     assert!(!response.headers().contains_key("x-internal-shard"));
+/// }
+/// # }
+/// ```
+pub trait ResponseHook: Send + Sync + RefUnwindSafe {
+    /// Inspects or modifies `response`, which is about to be sent for the request represented by
+    /// `state`.
+    fn finalize(&self, state: &State, response: &mut Response<Body>);
+}
+
+impl<F> ResponseHook for F
+where
+    F: Fn(&State, &mut Response<Body>) + Send + Sync + RefUnwindSafe,
+{
+    fn finalize(&self, state: &State, response: &mut Response<Body>) {
+        self(state, response)
+    }
+}
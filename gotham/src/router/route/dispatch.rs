@@ -2,18 +2,31 @@
 
 use futures::prelude::*;
 use log::trace;
+use std::any::Any;
 use std::panic::RefUnwindSafe;
 use std::pin::Pin;
 
 use crate::handler::{Handler, HandlerFuture, NewHandler};
 use crate::pipeline::chain::PipelineHandleChain;
 use crate::pipeline::set::PipelineSet;
+use crate::router::Router;
 use crate::state::{request_id, State};
 
 /// Used by `Router` to dispatch requests via pipelines and finally into the configured `Handler`.
 pub trait Dispatcher: RefUnwindSafe {
     /// Dispatches a request via pipelines and `Handler` represented by this `Dispatcher`.
     fn dispatch(&self, state: State) -> Pin<Box<HandlerFuture>>;
+
+    /// Returns the type names of the middleware of the pipelines which requests pass through,
+    /// in the order they are invoked, or `None` if the pipelines are bypassed.
+    fn pipelines(&self) -> Option<Vec<Vec<&'static str>>> {
+        None
+    }
+
+    /// Returns the `Router` which requests are dispatched to, if any.
+    fn router(&self) -> Option<&Router> {
+        None
+    }
 }
 
 /// Default implementation of the `Dispatcher` trait.
@@ -53,7 +66,7 @@ where
 
 impl<H, C, P> Dispatcher for DispatcherImpl<H, C, P>
 where
-    H: NewHandler,
+    H: NewHandler + 'static,
     H::Instance: Send + 'static,
     C: PipelineHandleChain<P>,
     P: RefUnwindSafe,
@@ -71,6 +84,14 @@ where
             }
         }
     }
+
+    fn pipelines(&self) -> Option<Vec<Vec<&'static str>>> {
+        Some(self.pipeline_chain.middleware(&self.pipelines))
+    }
+
+    fn router(&self) -> Option<&Router> {
+        (&self.new_handler as &dyn Any).downcast_ref::<Router>()
+    }
 }
 
 #[cfg(test)]
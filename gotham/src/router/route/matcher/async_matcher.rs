@@ -0,0 +1,155 @@
+//! Defines the type `AsyncRouteMatcher`, for conditions which can't be decided without waiting,
+//! e.g. on a database or a remote service.
+
+use std::future::Future;
+use std::panic::RefUnwindSafe;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use hyper::StatusCode;
+use log::trace;
+
+use crate::router::non_match::RouteNonMatch;
+use crate::state::{request_id, FromState, State, StateData};
+
+/// The future returned by `AsyncRouteMatcher::is_match`.
+pub type AsyncMatchFuture = Pin<Box<dyn Future<Output = Result<(), RouteNonMatch>> + Send>>;
+
+/// Determines asynchronously if conditions required for the associated `Route` to be invoked by
+/// the `Router` have been met, e.g. if a tenant is served by a route according to a database.
+///
+/// The `Router` evaluates the async matchers of all routes of the requested path concurrently,
+/// before selecting the route. The returned future can't borrow `State`, so implementations copy
+/// the request data they need before returning it.
+///
+/// # Examples
+///
+/// ```rust
+/// # extern crate gotham;
+/// # extern crate hyper;
+/// #
+/// # use hyper::{HeaderMap, StatusCode};
+/// # use gotham::router::builder::*;
+/// # use gotham::router::route::matcher::{AsyncMatchFuture, AsyncRouteMatcher};
+/// # use gotham::router::RouteNonMatch;
+/// # use gotham::state::{FromState, State};
+/// # use gotham::test::TestServer;
+/// #
+/// struct BetaTenants;
+///
+/// impl AsyncRouteMatcher for BetaTenants {
+///     fn is_match(&self, state: &State) -> AsyncMatchFuture {
+///         let tenant = HeaderMap::borrow_from(state)
+///             .get("x-tenant")
+///             .and_then(|value| value.to_str().ok())
+///             .map(str::to_owned);
+///         Box::pin(async move {
+///             // e.g. looked up in a database
+///             match tenant.as_deref() {
+///                 Some("acme") => Ok(()),
+///                 _ => Err(RouteNonMatch::new(StatusCode::NOT_FOUND)),
+///             }
+///         })
+///     }
+/// }
+///
+/// # fn main() {
+/// let router = build_simple_router(|route| {
+///     route
+///         .get("/reports")
+///         .add_async_route_matcher(BetaTenants)
+///         .to(|state| (state, "beta reports"));
+///     route.get("/reports").to(|state| (state, "reports"));
+/// });
+///
+/// let test_server = TestServer::new(router).unwrap();
+/// let response = test_server
+///     .client()
+///     .get("http://localhost/reports")
+///     .with_header("x-tenant", "acme".parse().unwrap())
+///     .perform()
+///     .unwrap();
+/// assert_eq!(response.read_utf8_body().unwrap(), "beta reports");
+///
+/// let response = test_server
+///     .client()
+///     .get("http://localhost/reports")
+///     .perform()
+///     .unwrap();
+/// assert_eq!(response.status(), StatusCode::OK);
+/// assert_eq!(response.read_utf8_body().unwrap(), "reports");
+/// # }
+/// ```
+pub trait AsyncRouteMatcher: RefUnwindSafe + Send + Sync {
+    /// Determines if the `Request` meets pre-defined conditions.
+    fn is_match(&self, state: &State) -> AsyncMatchFuture;
+}
+
+/// Identifies an `AsyncRouteMatcher` shared by the routes it was added to.
+fn key(matcher: &Arc<dyn AsyncRouteMatcher>) -> *const () {
+    Arc::as_ptr(matcher) as *const ()
+}
+
+/// The outcomes of the async matchers evaluated by the `Router` for a request.
+#[derive(Default)]
+pub(crate) struct AsyncMatches {
+    results: Vec<(usize, Result<(), RouteNonMatch>)>,
+}
+
+impl StateData for AsyncMatches {}
+
+impl AsyncMatches {
+    /// Returns the matchers of `matchers` which have not been evaluated for the request in
+    /// `state`.
+    pub(crate) fn pending(
+        state: &State,
+        matchers: Vec<Arc<dyn AsyncRouteMatcher>>,
+    ) -> Vec<Arc<dyn AsyncRouteMatcher>> {
+        match AsyncMatches::try_borrow_from(state) {
+            Some(matches) => matchers
+                .into_iter()
+                .filter(|matcher| matches.get(matcher).is_none())
+                .collect(),
+            None => matchers,
+        }
+    }
+
+    /// Records the outcomes of `matchers` in `state`.
+    pub(crate) fn record(
+        state: &mut State,
+        matchers: &[Arc<dyn AsyncRouteMatcher>],
+        results: Vec<Result<(), RouteNonMatch>>,
+    ) {
+        let mut matches = state.try_take::<AsyncMatches>().unwrap_or_default();
+        for (matcher, result) in matchers.iter().zip(results) {
+            matches.results.push((key(matcher) as usize, result));
+        }
+        state.put(matches);
+    }
+
+    fn get(&self, matcher: &Arc<dyn AsyncRouteMatcher>) -> Option<&Result<(), RouteNonMatch>> {
+        let key = key(matcher) as usize;
+        self.results
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, result)| result)
+    }
+
+    /// Returns the recorded outcome of `matcher`. Matchers which have not been evaluated don't
+    /// match, which only happens if a route is selected outside of the `Router`.
+    pub(crate) fn is_match(
+        state: &State,
+        matcher: &Arc<dyn AsyncRouteMatcher>,
+    ) -> Result<(), RouteNonMatch> {
+        match AsyncMatches::try_borrow_from(state).and_then(|matches| matches.get(matcher)) {
+            Some(result) => result.clone(),
+            None => {
+                trace!(
+                    "[{}] async route matcher was not evaluated",
+                    request_id(state)
+                );
+                Err(RouteNonMatch::new(StatusCode::NOT_FOUND))
+            }
+        }
+    }
+}
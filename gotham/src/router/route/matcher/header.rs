@@ -0,0 +1,222 @@
+//! Defines the `HeaderMatcher` and `HeaderRegexMatcher`.
+
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::StatusCode;
+use log::trace;
+use regex::Regex;
+
+use crate::router::non_match::RouteNonMatch;
+use crate::router::route::matcher::RouteMatcher;
+use crate::state::{request_id, FromState, State};
+
+/// A `RouteMatcher` that succeeds when a request header is present, optionally with a given
+/// value.
+///
+/// If the header occurs several times, any of its values may match.
+///
+/// ```rust
+/// # extern crate gotham;
+/// # extern crate hyper;
+/// #
+/// # use hyper::header::HeaderName;
+/// # use gotham::router::builder::*;
+/// # use gotham::router::route::matcher::HeaderMatcher;
+/// # use gotham::state::State;
+/// # use gotham::test::TestServer;
+/// #
+/// fn v2(state: State) -> (State, &'static str) {
+///     (state, "v2")
+/// }
+///
+/// fn v1(state: State) -> (State, &'static str) {
+///     (state, "v1")
+/// }
+///
+/// # fn main() {
+/// let version = HeaderName::from_static("x-api-version");
+///
+/// let router = build_simple_router(|route| {
+///     route
+///         .get("/users")
+///         .add_route_matcher(HeaderMatcher::new(version.clone()).with_value("2"))
+///         .to(v2);
+///     route.get("/users").to(v1);
+/// });
+///
+/// let test_server = TestServer::new(router).unwrap();
+/// let client = test_server.client();
+///
+/// let response = client
+///     .get("http://localhost/users")
+///     .with_header(version, "2".parse().unwrap())
+///     .perform()
+///     .unwrap();
+/// assert_eq!(response.read_utf8_body().unwrap(), "v2");
+///
+/// let response = client.get("http://localhost/users").perform().unwrap();
+/// assert_eq!(response.read_utf8_body().unwrap(), "v1");
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct HeaderMatcher {
+    name: HeaderName,
+    value: Option<HeaderValue>,
+}
+
+impl HeaderMatcher {
+    /// Creates a matcher for requests with the header `name`, whatever its value.
+    pub fn new(name: HeaderName) -> Self {
+        HeaderMatcher { name, value: None }
+    }
+
+    /// Restricts the matcher to requests whose header has exactly the value `value`.
+    ///
+    /// # Panics
+    ///
+    /// If `value` is not a valid header value.
+    pub fn with_value(self, value: &str) -> Self {
+        let value = HeaderValue::from_str(value).expect("invalid header value");
+        HeaderMatcher {
+            value: Some(value),
+            ..self
+        }
+    }
+}
+
+impl RouteMatcher for HeaderMatcher {
+    fn is_match(&self, state: &State) -> Result<(), RouteNonMatch> {
+        let mut values = HeaderMap::borrow_from(state).get_all(&self.name).iter();
+
+        let matched = match &self.value {
+            Some(expected) => values.any(|value| value == expected),
+            None => values.next().is_some(),
+        };
+
+        if matched {
+            Ok(())
+        } else {
+            trace!("[{}] header {} did not match", request_id(state), self.name);
+            Err(RouteNonMatch::new(StatusCode::NOT_FOUND))
+        }
+    }
+}
+
+/// A `RouteMatcher` that succeeds when a request header has a value matching a regular
+/// expression.
+///
+/// If the header occurs several times, any of its values may match. Values which are not valid
+/// UTF-8 never match.
+///
+/// ```rust
+/// # extern crate gotham;
+/// # extern crate hyper;
+/// # extern crate regex;
+/// #
+/// # use hyper::header::USER_AGENT;
+/// # use regex::Regex;
+/// # use gotham::router::builder::*;
+/// # use gotham::router::route::matcher::HeaderRegexMatcher;
+/// # use gotham::state::State;
+/// # use gotham::test::TestServer;
+/// #
+/// # fn mobile(state: State) -> (State, &'static str) { (state, "mobile") }
+/// # fn desktop(state: State) -> (State, &'static str) { (state, "desktop") }
+/// #
+/// # fn main() {
+/// let router = build_simple_router(|route| {
+///     route
+///         .get("/")
+///         .add_route_matcher(HeaderRegexMatcher::new(
+///             USER_AGENT,
+///             Regex::new("(?i)android|iphone").unwrap(),
+///         ))
+///         .to(mobile);
+///     route.get("/").to(desktop);
+/// });
+///
+/// let test_server = TestServer::new(router).unwrap();
+/// let response = test_server
+///     .client()
+///     .get("http://localhost/")
+///     .with_header(USER_AGENT, "Mozilla/5.0 (iPhone)".parse().unwrap())
+///     .perform()
+///     .unwrap();
+/// assert_eq!(response.read_utf8_body().unwrap(), "mobile");
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct HeaderRegexMatcher {
+    name: HeaderName,
+    regex: Regex,
+}
+
+impl HeaderRegexMatcher {
+    /// Creates a matcher for requests whose header `name` has a value matching `regex`. The
+    /// regex may match any part of the value, unless it is anchored with `^` and `$`.
+    pub fn new(name: HeaderName, regex: Regex) -> Self {
+        HeaderRegexMatcher { name, regex }
+    }
+}
+
+impl RouteMatcher for HeaderRegexMatcher {
+    fn is_match(&self, state: &State) -> Result<(), RouteNonMatch> {
+        let matched = HeaderMap::borrow_from(state)
+            .get_all(&self.name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| self.regex.is_match(value));
+
+        if matched {
+            Ok(())
+        } else {
+            trace!(
+                "[{}] header {} did not match {}",
+                request_id(state),
+                self.name,
+                self.regex
+            );
+            Err(RouteNonMatch::new(StatusCode::NOT_FOUND))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hyper::header::ACCEPT_LANGUAGE;
+
+    fn is_match<M: RouteMatcher>(matcher: &M, values: &[&str]) -> bool {
+        let mut matched = false;
+        State::with_new(|state| {
+            let mut headers = HeaderMap::new();
+            for value in values {
+                headers.append(ACCEPT_LANGUAGE, value.parse().unwrap());
+            }
+            state.put(headers);
+            matched = matcher.is_match(state).is_ok();
+        });
+        matched
+    }
+
+    #[test]
+    fn matches_header_presence_and_value() {
+        let present = HeaderMatcher::new(ACCEPT_LANGUAGE);
+        assert!(is_match(&present, &["de"]));
+        assert!(!is_match(&present, &[]));
+
+        let value = HeaderMatcher::new(ACCEPT_LANGUAGE).with_value("de");
+        assert!(is_match(&value, &["en", "de"]));
+        assert!(!is_match(&value, &["de-CH"]));
+        assert!(!is_match(&value, &[]));
+    }
+
+    #[test]
+    fn matches_header_regex() {
+        let matcher = HeaderRegexMatcher::new(ACCEPT_LANGUAGE, Regex::new("^de(-|$)").unwrap());
+        assert!(is_match(&matcher, &["en", "de-CH"]));
+        assert!(is_match(&matcher, &["de"]));
+        assert!(!is_match(&matcher, &["dex"]));
+        assert!(!is_match(&matcher, &[]));
+    }
+}
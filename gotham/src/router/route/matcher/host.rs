@@ -0,0 +1,174 @@
+//! Defines the `HostMatcher`.
+
+use hyper::header::{HeaderMap, HOST};
+use hyper::{StatusCode, Uri};
+use log::trace;
+
+use crate::router::non_match::RouteNonMatch;
+use crate::router::route::matcher::RouteMatcher;
+use crate::state::{request_id, FromState, State};
+
+/// A `RouteMatcher` that succeeds when the request was made for a given host, which allows
+/// virtual hosts to be routed by a single `Router`.
+///
+/// The host is taken from the `Host` header or, for HTTP/2 requests without one, from the
+/// authority of the request URI. Ports are ignored and hosts are compared ignoring case. A
+/// pattern starting with `*.` matches any subdomain of the rest of the pattern, but not the rest
+/// of the pattern itself.
+///
+/// ```rust
+/// # extern crate gotham;
+/// # extern crate hyper;
+/// #
+/// # use hyper::StatusCode;
+/// # use gotham::router::builder::*;
+/// # use gotham::router::route::matcher::HostMatcher;
+/// # use gotham::state::State;
+/// # use gotham::test::TestServer;
+/// #
+/// fn api(state: State) -> (State, &'static str) {
+///     (state, "api")
+/// }
+///
+/// fn tenant(state: State) -> (State, &'static str) {
+///     (state, "tenant")
+/// }
+///
+/// # fn main() {
+/// let router = build_simple_router(|route| {
+///     route
+///         .get("/")
+///         .add_route_matcher(HostMatcher::new("api.example.com"))
+///         .to(api);
+///     route
+///         .get("/")
+///         .add_route_matcher(HostMatcher::new("*.example.com"))
+///         .to(tenant);
+/// });
+///
+/// let test_server = TestServer::new(router).unwrap();
+/// let client = test_server.client();
+///
+/// let response = client.get("http://api.example.com:8080/").perform().unwrap();
+/// assert_eq!(response.read_utf8_body().unwrap(), "api");
+///
+/// let response = client.get("http://acme.example.com/").perform().unwrap();
+/// assert_eq!(response.read_utf8_body().unwrap(), "tenant");
+///
+/// let response = client.get("http://example.com/").perform().unwrap();
+/// assert_eq!(response.status(), StatusCode::NOT_FOUND);
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct HostMatcher {
+    host: String,
+    subdomains: bool,
+}
+
+impl HostMatcher {
+    /// Creates a matcher for requests made for `host`, or for any subdomain if `host` starts
+    /// with `*.`.
+    pub fn new(host: &str) -> Self {
+        let host = host.to_ascii_lowercase();
+        match host.strip_prefix("*.") {
+            Some(domain) => HostMatcher {
+                host: format!(".{}", domain),
+                subdomains: true,
+            },
+            None => HostMatcher {
+                host,
+                subdomains: false,
+            },
+        }
+    }
+
+    pub(crate) fn matches(&self, host: &str) -> bool {
+        if self.subdomains {
+            host.len() > self.host.len()
+                && host[host.len() - self.host.len()..].eq_ignore_ascii_case(&self.host)
+        } else {
+            host.eq_ignore_ascii_case(&self.host)
+        }
+    }
+}
+
+/// Returns the host the request in `state` was made for, without the port.
+pub(crate) fn request_host(state: &State) -> Option<&str> {
+    let host = match HeaderMap::borrow_from(state).get(HOST) {
+        Some(value) => value.to_str().ok()?,
+        None => Uri::borrow_from(state).host()?,
+    };
+
+    // IPv6 literals are enclosed in brackets, which contain colons
+    let port_start = match host.rfind(':') {
+        Some(index) if !host[index..].contains(']') => index,
+        _ => host.len(),
+    };
+    Some(&host[..port_start])
+}
+
+impl RouteMatcher for HostMatcher {
+    fn is_match(&self, state: &State) -> Result<(), RouteNonMatch> {
+        match request_host(state) {
+            Some(host) if self.matches(host) => Ok(()),
+            host => {
+                trace!(
+                    "[{}] host {:?} did not match {}",
+                    request_id(state),
+                    host,
+                    self.host
+                );
+                Err(RouteNonMatch::new(StatusCode::NOT_FOUND))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(matcher: &HostMatcher, host: Option<&str>, uri: &str) -> bool {
+        let mut matched = false;
+        State::with_new(|state| {
+            let mut headers = HeaderMap::new();
+            if let Some(host) = host {
+                headers.insert(HOST, host.parse().unwrap());
+            }
+            state.put(headers);
+            state.put(uri.parse::<Uri>().unwrap());
+            matched = matcher.is_match(state).is_ok();
+        });
+        matched
+    }
+
+    #[test]
+    fn matches_exact_host() {
+        let matcher = HostMatcher::new("Example.com");
+
+        assert!(is_match(&matcher, Some("example.com"), "/"));
+        assert!(is_match(&matcher, Some("EXAMPLE.COM:8080"), "/"));
+        assert!(is_match(&matcher, None, "https://example.com:443/"));
+        assert!(!is_match(&matcher, Some("www.example.com"), "/"));
+        assert!(!is_match(
+            &matcher,
+            Some("other.com"),
+            "https://example.com/"
+        ));
+        assert!(!is_match(&matcher, None, "/"));
+
+        let matcher = HostMatcher::new("[::1]");
+        assert!(is_match(&matcher, Some("[::1]:3000"), "/"));
+        assert!(is_match(&matcher, Some("[::1]"), "/"));
+    }
+
+    #[test]
+    fn matches_subdomains() {
+        let matcher = HostMatcher::new("*.example.com");
+
+        assert!(is_match(&matcher, Some("api.example.com"), "/"));
+        assert!(is_match(&matcher, Some("a.b.Example.com:80"), "/"));
+        assert!(!is_match(&matcher, Some("example.com"), "/"));
+        assert!(!is_match(&matcher, Some("badexample.com"), "/"));
+    }
+}
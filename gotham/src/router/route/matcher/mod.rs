@@ -4,13 +4,19 @@ pub mod accept;
 pub mod access_control_request_method;
 pub mod and;
 pub mod any;
+pub mod async_matcher;
 pub mod content_type;
+pub mod header;
+pub mod host;
 
 pub use self::accept::AcceptHeaderRouteMatcher;
 pub use self::access_control_request_method::AccessControlRequestMethodMatcher;
 pub use self::and::AndRouteMatcher;
 pub use self::any::AnyRouteMatcher;
+pub use self::async_matcher::{AsyncMatchFuture, AsyncRouteMatcher};
 pub use self::content_type::ContentTypeHeaderRouteMatcher;
+pub use self::header::{HeaderMatcher, HeaderRegexMatcher};
+pub use self::host::HostMatcher;
 
 mod lookup_table;
 use self::lookup_table::{LookupTable, LookupTableFromTypes};
@@ -94,6 +100,11 @@ impl MethodOnlyRouteMatcher {
     pub fn new(methods: Vec<Method>) -> Self {
         MethodOnlyRouteMatcher { methods }
     }
+
+    /// Returns the methods accepted by this matcher.
+    pub(crate) fn methods(&self) -> &[Method] {
+        &self.methods
+    }
 }
 
 impl RouteMatcher for MethodOnlyRouteMatcher {
@@ -0,0 +1,173 @@
+//! Defines the middleware attached to a single route, see `DefineSingleRoute::with_middleware`.
+
+use std::panic::RefUnwindSafe;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures::prelude::*;
+
+use crate::handler::{Handler, HandlerFuture, NewHandler};
+use crate::middleware::{Middleware, NewMiddleware};
+use crate::state::State;
+
+type Chain = Box<dyn FnOnce(State) -> Pin<Box<HandlerFuture>> + Send>;
+
+type CallMiddleware = dyn Fn(State, Chain) -> Pin<Box<HandlerFuture>> + Send + Sync + RefUnwindSafe;
+
+/// The middleware attached to a route, in the order they are invoked.
+#[derive(Clone, Default)]
+pub(crate) struct RouteMiddleware {
+    middleware: Vec<Arc<CallMiddleware>>,
+}
+
+impl RouteMiddleware {
+    /// Attaches `new_middleware`, which is invoked after all previously attached middleware.
+    pub(crate) fn push<NM>(&mut self, new_middleware: NM)
+    where
+        NM: NewMiddleware + Send + 'static,
+        NM::Instance: Send + 'static,
+    {
+        self.middleware.push(Arc::new(
+            move |state: State, chain: Chain| match new_middleware.new_middleware() {
+                Ok(middleware) => middleware.call(state, chain),
+                Err(e) => future::err((state, e.into())).boxed(),
+            },
+        ));
+    }
+
+    /// Wraps `new_handler`, so that every `Handler` it creates is invoked through the attached
+    /// middleware.
+    pub(crate) fn wrap<NH>(self, new_handler: NH) -> RouteMiddlewareNewHandler<NH>
+    where
+        NH: NewHandler,
+    {
+        RouteMiddlewareNewHandler {
+            new_handler,
+            middleware: Arc::new(self.middleware),
+        }
+    }
+}
+
+/// A `NewHandler` which creates handlers that are invoked through route middleware.
+pub(crate) struct RouteMiddlewareNewHandler<NH> {
+    new_handler: NH,
+    middleware: Arc<Vec<Arc<CallMiddleware>>>,
+}
+
+impl<NH> NewHandler for RouteMiddlewareNewHandler<NH>
+where
+    NH: NewHandler,
+    NH::Instance: Send + 'static,
+{
+    type Instance = RouteMiddlewareHandler<NH::Instance>;
+
+    fn new_handler(&self) -> anyhow::Result<Self::Instance> {
+        Ok(RouteMiddlewareHandler {
+            handler: self.new_handler.new_handler()?,
+            middleware: self.middleware.clone(),
+        })
+    }
+}
+
+/// A `Handler` which is invoked through route middleware.
+pub(crate) struct RouteMiddlewareHandler<H> {
+    handler: H,
+    middleware: Arc<Vec<Arc<CallMiddleware>>>,
+}
+
+impl<H> Handler for RouteMiddlewareHandler<H>
+where
+    H: Handler + Send + 'static,
+{
+    fn handle(self, state: State) -> Pin<Box<HandlerFuture>> {
+        call_from(self.middleware, 0, state, self.handler)
+    }
+}
+
+/// Invokes the middleware at `index` and everything after it, ending with `handler`.
+fn call_from<H>(
+    middleware: Arc<Vec<Arc<CallMiddleware>>>,
+    index: usize,
+    state: State,
+    handler: H,
+) -> Pin<Box<HandlerFuture>>
+where
+    H: Handler + Send + 'static,
+{
+    match middleware.get(index).cloned() {
+        Some(current) => current(
+            state,
+            Box::new(move |state| call_from(middleware, index + 1, state, handler)),
+        ),
+        None => handler.handle(state),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hyper::StatusCode;
+
+    use crate::router::builder::{build_simple_router, DefineSingleRoute, DrawRoutes};
+    use crate::state::StateData;
+    use crate::test::TestServer;
+
+    struct Trace(Vec<&'static str>);
+
+    impl StateData for Trace {}
+
+    #[derive(Clone)]
+    struct Tag(&'static str);
+
+    impl Middleware for Tag {
+        fn call<Chain>(self, mut state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
+        where
+            Chain: FnOnce(State) -> Pin<Box<HandlerFuture>> + Send + 'static,
+        {
+            match state.try_borrow_mut::<Trace>() {
+                Some(trace) => trace.0.push(self.0),
+                None => state.put(Trace(vec![self.0])),
+            }
+            chain(state)
+        }
+    }
+
+    impl NewMiddleware for Tag {
+        type Instance = Tag;
+
+        fn new_middleware(&self) -> anyhow::Result<Tag> {
+            Ok(self.clone())
+        }
+    }
+
+    fn handler(state: State) -> (State, String) {
+        let trace = state
+            .try_borrow::<Trace>()
+            .map(|trace| trace.0.join(","))
+            .unwrap_or_default();
+        (state, trace)
+    }
+
+    #[test]
+    fn invokes_route_middleware_in_order() {
+        let router = build_simple_router(|route| {
+            route.get("/").to(handler);
+            route
+                .get("/tagged")
+                .with_middleware(Tag("first"))
+                .with_middleware(Tag("second"))
+                .to(handler);
+        });
+        let test_server = TestServer::new(router).unwrap();
+
+        for (uri, expected) in &[
+            ("http://localhost/", ""),
+            ("http://localhost/tagged", "first,second"),
+        ] {
+            let response = test_server.client().get(*uri).perform().unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.read_utf8_body().unwrap(), *expected);
+        }
+    }
+}
@@ -6,10 +6,13 @@
 
 pub mod dispatch;
 pub mod matcher;
+pub mod middleware;
+pub mod timeout;
 
 use std::marker::PhantomData;
 use std::panic::RefUnwindSafe;
 use std::pin::Pin;
+use std::sync::Arc;
 
 use hyper::{Body, Response, Uri};
 use log::debug;
@@ -17,10 +20,13 @@ use log::debug;
 use crate::extractor::{self, PathExtractor, QueryStringExtractor};
 use crate::handler::HandlerFuture;
 use crate::helpers::http::request::query_string;
+use crate::router::api_version::RouteApiVersion;
 use crate::router::non_match::RouteNonMatch;
 use crate::router::route::dispatch::Dispatcher;
-use crate::router::route::matcher::RouteMatcher;
-use crate::router::tree::segment::SegmentMapping;
+use crate::router::route::matcher::async_matcher::AsyncMatches;
+use crate::router::route::matcher::{AsyncRouteMatcher, RouteMatcher};
+use crate::router::tree::segment::{GlobSegments, SegmentMapping};
+use crate::router::{PathDecoding, Router};
 use crate::state::{request_id, State};
 
 #[derive(Clone, Copy, PartialEq)]
@@ -57,9 +63,49 @@ pub trait Route: RefUnwindSafe {
     /// Determines if this `Route` should be invoked, based on the request data in `State.
     fn is_match(&self, state: &State) -> Result<(), RouteNonMatch>;
 
+    /// Determines if this `Route` could be invoked, disregarding its `AsyncRouteMatcher` values,
+    /// which are only evaluated for routes passing this check.
+    fn is_sync_match(&self, state: &State) -> Result<(), RouteNonMatch> {
+        self.is_match(state)
+    }
+
     /// Determines if this `Route` intends to delegate requests to a secondary `Router` instance.
     fn delegation(&self) -> Delegation;
 
+    /// Returns the policy for decoding the request path for the `PathExtractor`, if this `Route`
+    /// declares one.
+    fn path_decoding(&self) -> Option<PathDecoding> {
+        None
+    }
+
+    /// Returns the maximum size of request bodies in bytes, if this `Route` declares one.
+    fn body_limit(&self) -> Option<u64> {
+        None
+    }
+
+    /// Returns the `AsyncRouteMatcher` values which must match besides the synchronous
+    /// matcher of this `Route`.
+    fn async_matchers(&self) -> &[Arc<dyn AsyncRouteMatcher>] {
+        &[]
+    }
+
+    /// Determines if the secondary `Router` of a delegating `Route` hands its errors to the
+    /// delegating `Router`, and if its responses are extended by the delegating `Router`.
+    fn propagates_errors(&self) -> bool {
+        true
+    }
+
+    /// Returns the type names of the middleware of the pipelines which requests pass through,
+    /// in the order they are invoked, or `None` if the pipelines are bypassed.
+    fn pipelines(&self) -> Option<Vec<Vec<&'static str>>> {
+        None
+    }
+
+    /// Returns the `Router` which this `Route` delegates to, if any.
+    fn delegated_router(&self) -> Option<&Router> {
+        None
+    }
+
     /// Extracts dynamic components of the `Request` path and stores the `PathExtractor` in `State`.
     fn extract_request_path<'a>(
         &self,
@@ -101,6 +147,11 @@ where
     dispatcher: Box<dyn Dispatcher + Send + Sync>,
     _extractors: Extractors<PE, QSE>,
     delegation: Delegation,
+    path_decoding: Option<PathDecoding>,
+    body_limit: Option<u64>,
+    api_version: Option<Arc<RouteApiVersion>>,
+    error_propagation: bool,
+    async_matchers: Vec<Arc<dyn AsyncRouteMatcher>>,
 }
 
 /// Extractors used by `RouteImpl` to acquire request data and change into a type safe form
@@ -132,6 +183,52 @@ where
             dispatcher,
             _extractors,
             delegation,
+            path_decoding: None,
+            body_limit: None,
+            api_version: None,
+            error_propagation: true,
+            async_matchers: vec![],
+        }
+    }
+
+    /// Declares the policy for decoding the request path for the `PathExtractor` of this route,
+    /// instead of the one of its `Node`.
+    pub fn with_path_decoding(self, path_decoding: PathDecoding) -> Self {
+        RouteImpl {
+            path_decoding: Some(path_decoding),
+            ..self
+        }
+    }
+
+    /// Declares the maximum size of request bodies in bytes for this route, instead of the one
+    /// of its `Node`.
+    pub fn with_body_limit(self, body_limit: u64) -> Self {
+        RouteImpl {
+            body_limit: Some(body_limit),
+            ..self
+        }
+    }
+
+    /// Adds an `AsyncRouteMatcher`, which must match besides the matcher of this route.
+    pub fn with_async_matcher(mut self, matcher: Arc<dyn AsyncRouteMatcher>) -> Self {
+        self.async_matchers.push(matcher);
+        self
+    }
+
+    /// Keeps the errors and responses of the secondary `Router` of this route away from the
+    /// `Router` delegating to it.
+    pub fn without_error_propagation(self) -> Self {
+        RouteImpl {
+            error_propagation: false,
+            ..self
+        }
+    }
+
+    /// Restricts this route to requests for an API version, and exposes the version in `State`.
+    pub(crate) fn with_api_version(self, api_version: Arc<RouteApiVersion>) -> Self {
+        RouteImpl {
+            api_version: Some(api_version),
+            ..self
         }
     }
 }
@@ -159,14 +256,55 @@ where
     type ResBody = Body;
 
     fn is_match(&self, state: &State) -> Result<(), RouteNonMatch> {
-        self.matcher.is_match(state)
+        self.is_sync_match(state)?;
+        self.async_matchers
+            .iter()
+            .try_for_each(|matcher| AsyncMatches::is_match(state, matcher))
+    }
+
+    fn is_sync_match(&self, state: &State) -> Result<(), RouteNonMatch> {
+        self.matcher.is_match(state)?;
+        if let Some(api_version) = &self.api_version {
+            api_version.is_match(state)?;
+        }
+        Ok(())
     }
 
     fn delegation(&self) -> Delegation {
         self.delegation
     }
 
-    fn dispatch(&self, state: State) -> Pin<Box<HandlerFuture>> {
+    fn path_decoding(&self) -> Option<PathDecoding> {
+        self.path_decoding
+    }
+
+    fn body_limit(&self) -> Option<u64> {
+        self.body_limit
+    }
+
+    fn async_matchers(&self) -> &[Arc<dyn AsyncRouteMatcher>] {
+        &self.async_matchers
+    }
+
+    fn propagates_errors(&self) -> bool {
+        self.error_propagation
+    }
+
+    fn pipelines(&self) -> Option<Vec<Vec<&'static str>>> {
+        self.dispatcher.pipelines()
+    }
+
+    fn delegated_router(&self) -> Option<&Router> {
+        match self.delegation {
+            Delegation::External => self.dispatcher.router(),
+            Delegation::Internal => None,
+        }
+    }
+
+    fn dispatch(&self, mut state: State) -> Pin<Box<HandlerFuture>> {
+        if let Some(api_version) = &self.api_version {
+            api_version.put(&mut state);
+        }
         self.dispatcher.dispatch(state)
     }
 
@@ -175,7 +313,8 @@ where
         state: &mut State,
         params: SegmentMapping<'a>,
     ) -> Result<(), ExtractorFailed> {
-        match extractor::internal::from_segment_mapping::<PE>(params) {
+        let globs = state.try_borrow::<GlobSegments>().cloned();
+        match extractor::internal::from_segment_mapping::<PE>(params, globs.unwrap_or_default()) {
             Ok(val) => Ok(state.put(val)),
             Err(e) => {
                 debug!("[{}] path extractor failed: {}", request_id(&state), e);
@@ -0,0 +1,202 @@
+//! Defines the deadline which a route imposes on its handler, see
+//! `DefineSingleRoute::with_timeout`.
+
+use std::fmt::{self, Display};
+use std::panic::RefUnwindSafe;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::prelude::*;
+use hyper::{Body, Response, StatusCode};
+use log::warn;
+
+use crate::handler::{Handler, HandlerError, HandlerFuture, IntoResponse, NewHandler};
+use crate::helpers::http::response::create_empty_response;
+use crate::router::snapshot;
+use crate::state::{request_id, State};
+
+/// The cause of the `HandlerError` which is returned when a handler does not complete within the
+/// timeout of its route.
+///
+/// The error carries a customized response with the status code `504 Gateway Timeout`, so an
+/// `ErrorHandler` or `ErrorFormatter` leaves it alone, while middleware can still recognise it by
+/// downcasting `HandlerError::cause`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RouteTimeoutError {
+    timeout: Duration,
+}
+
+impl RouteTimeoutError {
+    /// Returns the timeout which the handler exceeded.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+impl Display for RouteTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "handler did not complete within {:?}", self.timeout)
+    }
+}
+
+impl std::error::Error for RouteTimeoutError {}
+
+type TimeoutResponse = dyn Fn(&State) -> Response<Body> + Send + Sync + RefUnwindSafe;
+
+/// The timeout of a route, along with the response which is served when it expires.
+#[derive(Clone)]
+pub(crate) struct RouteTimeout {
+    timeout: Duration,
+    response: Arc<TimeoutResponse>,
+}
+
+impl RouteTimeout {
+    /// Creates a timeout which serves an empty `504 Gateway Timeout` response.
+    pub(crate) fn new(timeout: Duration) -> RouteTimeout {
+        RouteTimeout {
+            timeout,
+            response: Arc::new(|state: &State| {
+                create_empty_response(state, StatusCode::GATEWAY_TIMEOUT)
+            }),
+        }
+    }
+
+    /// Replaces the body of the response which is served when the timeout expires.
+    pub(crate) fn with_response<F, R>(self, response: F) -> RouteTimeout
+    where
+        F: Fn(&State) -> R + Send + Sync + RefUnwindSafe + 'static,
+        R: IntoResponse,
+    {
+        RouteTimeout {
+            response: Arc::new(move |state: &State| response(state).into_response(state)),
+            ..self
+        }
+    }
+
+    /// Wraps `new_handler`, so that every `Handler` it creates is subject to this timeout.
+    pub(crate) fn wrap<NH>(self, new_handler: NH) -> TimeoutNewHandler<NH>
+    where
+        NH: NewHandler,
+    {
+        TimeoutNewHandler {
+            new_handler,
+            timeout: self,
+        }
+    }
+
+    fn error(&self, state: &State) -> HandlerError {
+        let mut err = HandlerError::from(RouteTimeoutError {
+            timeout: self.timeout,
+        });
+        err.set_customized_response_body(state, |state| {
+            let mut response = (self.response)(state);
+            *response.status_mut() = StatusCode::GATEWAY_TIMEOUT;
+            response
+        });
+        err
+    }
+}
+
+/// A `NewHandler` which creates handlers that are subject to a `RouteTimeout`.
+pub(crate) struct TimeoutNewHandler<NH> {
+    new_handler: NH,
+    timeout: RouteTimeout,
+}
+
+impl<NH> NewHandler for TimeoutNewHandler<NH>
+where
+    NH: NewHandler,
+    NH::Instance: Send + 'static,
+{
+    type Instance = TimeoutHandler<NH::Instance>;
+
+    fn new_handler(&self) -> anyhow::Result<Self::Instance> {
+        Ok(TimeoutHandler {
+            handler: self.new_handler.new_handler()?,
+            timeout: self.timeout.clone(),
+        })
+    }
+}
+
+/// A `Handler` which fails with a `RouteTimeoutError` once its timeout expires.
+pub(crate) struct TimeoutHandler<H> {
+    handler: H,
+    timeout: RouteTimeout,
+}
+
+impl<H> Handler for TimeoutHandler<H>
+where
+    H: Handler + Send + 'static,
+{
+    fn handle(self, state: State) -> Pin<Box<HandlerFuture>> {
+        // the `State` is lost along with the handler future when the timeout expires
+        let snapshot = snapshot(&state);
+        let timeout = self.timeout;
+
+        let handler_future = self.handler.handle(state);
+
+        async move {
+            match tokio::time::timeout(timeout.timeout, handler_future).await {
+                Ok(result) => result,
+                Err(_) => {
+                    warn!(
+                        "[{}] handler did not complete within {:?}",
+                        request_id(&snapshot),
+                        timeout.timeout
+                    );
+                    let err = timeout.error(&snapshot);
+                    Err((snapshot, err))
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::handler::HandlerResult;
+    use crate::router::builder::{build_simple_router, DefineSingleRoute, DrawRoutes};
+    use crate::test::TestServer;
+
+    async fn sleep_handler(state: State) -> HandlerResult {
+        let millis = state
+            .borrow::<hyper::Uri>()
+            .query()
+            .and_then(|query| query.parse().ok())
+            .unwrap_or(0);
+        tokio::time::sleep(Duration::from_millis(millis)).await;
+        let response = create_empty_response(&state, StatusCode::OK);
+        Ok((state, response))
+    }
+
+    #[test]
+    fn fails_handlers_which_exceed_timeout() {
+        let router = build_simple_router(|route| {
+            route
+                .get("/")
+                .with_timeout(Duration::from_millis(100))
+                .to_async(sleep_handler);
+        });
+        let test_server = TestServer::new(router).unwrap();
+
+        let response = test_server
+            .client()
+            .get("http://localhost/?0")
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = test_server
+            .client()
+            .get("http://localhost/?10000")
+            .perform()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert!(response.headers().contains_key("x-request-id"));
+        assert!(response.read_body().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,64 @@
+//! Extracts the path segments of a scope prefix for every route below it, see
+//! `DrawRoutes::set_path_extractor`.
+
+use std::marker::PhantomData;
+use std::panic::RefUnwindSafe;
+
+use hyper::{Body, Response};
+use log::debug;
+
+use crate::extractor::{self, PathExtractor};
+use crate::router::route::ExtractorFailed;
+use crate::router::tree::segment::{GlobSegments, SegmentMapping};
+use crate::state::{request_id, State};
+
+/// A path extractor declared for a scope, which runs before the path extractor of each route
+/// below the scope.
+pub(crate) trait ScopePathExtractor: RefUnwindSafe + Send + Sync {
+    /// Extracts the path segments of the request into `State`.
+    fn extract(&self, state: &mut State, params: SegmentMapping<'_>)
+        -> Result<(), ExtractorFailed>;
+
+    /// Extends the response if extracting the path segments failed.
+    fn extend(&self, state: &mut State, res: &mut Response<Body>);
+}
+
+/// The `ScopePathExtractor` for the path extractor type `PE`.
+pub(crate) struct ScopePathExtractorImpl<PE> {
+    phantom: PhantomData<fn() -> PE>,
+}
+
+impl<PE> ScopePathExtractorImpl<PE> {
+    pub(crate) fn new() -> Self {
+        ScopePathExtractorImpl {
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<PE> ScopePathExtractor for ScopePathExtractorImpl<PE>
+where
+    PE: PathExtractor<Body> + Send + Sync + 'static,
+{
+    fn extract(
+        &self,
+        state: &mut State,
+        params: SegmentMapping<'_>,
+    ) -> Result<(), ExtractorFailed> {
+        let globs = state.try_borrow::<GlobSegments>().cloned();
+        match extractor::internal::from_segment_mapping::<PE>(params, globs.unwrap_or_default()) {
+            Ok(val) => {
+                state.put(val);
+                Ok(())
+            }
+            Err(e) => {
+                debug!("[{}] scope path extractor failed: {}", request_id(state), e);
+                Err(ExtractorFailed)
+            }
+        }
+    }
+
+    fn extend(&self, state: &mut State, res: &mut Response<Body>) {
+        PE::extend(state, res)
+    }
+}
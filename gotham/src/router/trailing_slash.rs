@@ -0,0 +1,110 @@
+//! Defines how a `Router` treats a trailing slash in the request path, see `TrailingSlash`.
+
+use hyper::{Body, Response, StatusCode, Uri};
+
+use crate::helpers::http::response::{create_empty_response, create_permanent_redirect};
+use crate::router::tree::node::Node;
+use crate::state::{FromState, State};
+
+/// The policy applied by a `Router` when the request path and the matched route disagree on a
+/// trailing slash, e.g. a request for `/users/` to a route defined as `/users`, or vice versa.
+///
+/// The canonical path of a route is the path it was defined with. The root path and routes
+/// ending in a glob segment accept paths with and without a trailing slash under every policy.
+///
+/// The policy is set with `RouterBuilder::set_trailing_slash`:
+///
+/// ```rust
+/// # extern crate gotham;
+/// # extern crate hyper;
+/// #
+/// # use hyper::StatusCode;
+/// # use hyper::header::LOCATION;
+/// # use gotham::router::TrailingSlash;
+/// # use gotham::router::builder::*;
+/// # use gotham::state::State;
+/// # use gotham::test::TestServer;
+/// #
+/// fn handler(state: State) -> (State, &'static str) {
+///     (state, "users")
+/// }
+///
+/// # fn main() {
+/// let router = build_simple_router(|route| {
+///     route.set_trailing_slash(TrailingSlash::RedirectToCanonical);
+///     route.get("/users").to(handler);
+/// });
+///
+/// let test_server = TestServer::new(router).unwrap();
+/// let response = test_server
+///     .client()
+///     .get("http://localhost/users/?page=2")
+///     .perform()
+///     .unwrap();
+///
+/// assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+/// assert_eq!(response.headers().get(LOCATION).unwrap(), "/users?page=2");
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrailingSlash {
+    /// Only the canonical path matches the route, the other one is answered with
+    /// `404 Not Found`.
+    Strict,
+
+    /// The other path is answered with `308 Permanent Redirect` to the canonical path, keeping the
+    /// query string.
+    RedirectToCanonical,
+
+    /// Both paths are dispatched to the route. This is the default.
+    #[default]
+    TreatAsEquivalent,
+}
+
+impl TrailingSlash {
+    /// Applies the policy to a request which was matched to `node`, returning the response to send
+    /// instead of dispatching to the route, if any.
+    pub(crate) fn check(self, state: &State, node: &Node) -> Option<Response<Body>> {
+        if self == TrailingSlash::TreatAsEquivalent || is_exempt(node) {
+            return None;
+        }
+
+        let uri = Uri::borrow_from(state);
+        let path = uri.path();
+        let has_slash = path.len() > 1 && path.ends_with('/');
+
+        if has_slash == node.trailing_slash() {
+            return None;
+        }
+
+        match self {
+            TrailingSlash::Strict => Some(create_empty_response(state, StatusCode::NOT_FOUND)),
+            TrailingSlash::RedirectToCanonical => {
+                let mut location = if has_slash {
+                    path.trim_end_matches('/').to_owned()
+                } else {
+                    format!("{}/", path)
+                };
+
+                if let Some(query) = uri.query() {
+                    location.push('?');
+                    location.push_str(query);
+                }
+
+                Some(create_permanent_redirect(state, location))
+            }
+            TrailingSlash::TreatAsEquivalent => None,
+        }
+    }
+}
+
+/// Returns `true` if routes of `node` accept paths with and without a trailing slash regardless
+/// of the policy, i.e. the root path and glob segments.
+fn is_exempt(node: &Node) -> bool {
+    let template = node.template();
+    template == "/"
+        || template
+            .rsplit('/')
+            .next()
+            .is_some_and(|segment| segment.starts_with('*'))
+}
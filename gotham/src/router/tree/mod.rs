@@ -39,6 +39,11 @@ impl Tree {
         self.root.add_route(route);
     }
 
+    /// Borrow the root `Node`.
+    pub(crate) fn borrow_root(&self) -> &Node {
+        &self.root
+    }
+
     /// Borrow the root `NodeBuilder` as mutable.
     pub fn borrow_root_mut(&mut self) -> &mut Node {
         &mut self.root
@@ -1,16 +1,23 @@
 //! Defines `Node` for `Tree`.
 
-use hyper::{Body, StatusCode};
+use hyper::{Body, Method, StatusCode};
 use log::trace;
 
+use crate::extractor::{NoopPathExtractor, NoopQueryStringExtractor};
+use crate::handler::ErrorFormat;
 use crate::helpers::http::PercentDecoded;
+use crate::middleware::cors::{CorsConfig, PreflightDispatcher, PreflightRouteMatcher};
 use crate::router::non_match::RouteNonMatch;
-use crate::router::route::{Delegation, Route};
+use crate::router::route::matcher::AsyncRouteMatcher;
+use crate::router::route::{Delegation, Extractors, Route, RouteImpl};
+use crate::router::scope_extractor::ScopePathExtractor;
 use crate::router::tree::segment::{SegmentMapping, SegmentType};
+use crate::router::url_for::{self, RouteNames};
+use crate::router::PathDecoding;
 use crate::state::{request_id, State};
 
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::sync::Arc;
 
 /// A recursive member of `Tree`, representative of segment(s) in a request path.
 ///
@@ -20,23 +27,57 @@ use std::collections::HashMap;
 pub struct Node {
     segment: String,
     segment_type: SegmentType,
+    template: String,
     routes: Vec<Box<dyn Route<ResBody = Body> + Send + Sync>>,
     children: Vec<Node>,
+    trailing_slash: bool,
+    names: Vec<String>,
+    delegated_names: Vec<Arc<RouteNames>>,
+    unconditional_methods: Vec<Method>,
+    methods: Vec<(Method, Option<u64>)>,
+    error_format: Option<ErrorFormat>,
+    cors: Option<Arc<CorsConfig>>,
+    path_decoding: Option<PathDecoding>,
+    match_priority: Option<i32>,
+    body_limit: Option<u64>,
+    answers_options: bool,
+    path_extractors: Vec<Arc<dyn ScopePathExtractor>>,
 }
 
 impl Node {
     /// Creates new `Node` for the given segment and type.
     pub fn new(segment: &str, segment_type: SegmentType) -> Self {
-        Node {
+        let mut node = Node {
             segment_type,
             segment: segment.to_string(),
+            template: String::new(),
             routes: vec![],
             children: vec![],
-        }
+            trailing_slash: false,
+            names: vec![],
+            delegated_names: vec![],
+            unconditional_methods: vec![],
+            methods: vec![],
+            error_format: None,
+            cors: None,
+            path_decoding: None,
+            match_priority: None,
+            body_limit: None,
+            answers_options: true,
+            path_extractors: vec![],
+        };
+
+        node.template = if segment == "/" {
+            String::from("/")
+        } else {
+            format!("/{}", node.template_segment())
+        };
+        node
     }
 
     /// Adds a new child `Node` instance to this `Node`.
-    pub fn add_child(&mut self, node: Node) -> &mut Self {
+    pub fn add_child(&mut self, mut node: Node) -> &mut Self {
+        node.prefix_template(&self.template);
         self.children.push(node);
         self.children.sort();
         self
@@ -98,7 +139,7 @@ impl Node {
         segments: &'a [PercentDecoded],
     ) -> Option<(&'a Node, SegmentMapping<'a>, usize)> {
         // accumulators for recursion
-        let mut params = HashMap::new();
+        let mut params = SegmentMapping::new();
         let mut processed = 0;
 
         // process and map the results through to the required form
@@ -106,6 +147,354 @@ impl Node {
             .map(|node| (node, params, processed))
     }
 
+    /// Returns the route template of this `Node`, built from the segments leading to it, e.g.
+    /// `/users/:id/*`.
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    /// Renders the segment of this `Node` as it appears in a route template.
+    fn template_segment(&self) -> String {
+        match self.segment_type {
+            SegmentType::Static => self.segment.clone(),
+            SegmentType::Constrained { .. } | SegmentType::Dynamic => format!(":{}", self.segment),
+            SegmentType::Glob if self.segment == "*" => String::from("*"),
+            SegmentType::Glob => format!("*{}", self.segment),
+        }
+    }
+
+    /// Places the templates of this `Node` and its children below the given parent template.
+    fn prefix_template(&mut self, parent: &str) {
+        self.template = format!(
+            "{}/{}",
+            parent.trim_end_matches('/'),
+            self.template_segment()
+        );
+
+        for child in &mut self.children {
+            child.prefix_template(&self.template);
+        }
+    }
+
+    /// Returns `true` if the routes of this `Node` were defined with a trailing slash, which
+    /// makes it part of their canonical path for a `TrailingSlash` policy.
+    pub(crate) fn trailing_slash(&self) -> bool {
+        self.trailing_slash
+    }
+
+    /// Records that the routes of this `Node` are defined with a trailing slash.
+    pub(crate) fn set_trailing_slash(&mut self) {
+        self.trailing_slash = true;
+    }
+
+    /// Declares the error format of the routes of this `Node` and its children, see
+    /// `DrawRoutes::set_error_format`.
+    pub(crate) fn set_error_format(&mut self, format: ErrorFormat) {
+        self.error_format = Some(format);
+    }
+
+    /// Returns the error format declared by the deepest `Node` on the path of `segments`, which
+    /// need not lead to a routable `Node`. Children are visited in the order of `match_node`.
+    pub(crate) fn error_format(&self, segments: &[PercentDecoded]) -> Option<ErrorFormat> {
+        let mut node = self;
+        let mut format = self.error_format;
+
+        for segment in segments {
+            let child = node.children.iter().find(|child| match child.segment_type {
+                SegmentType::Static => child.segment == segment.as_ref(),
+                SegmentType::Constrained { ref regex } => regex.is_match(segment.as_ref()),
+                SegmentType::Dynamic | SegmentType::Glob => true,
+            });
+
+            match child {
+                Some(child) => {
+                    node = child;
+                    format = child.error_format.or(format);
+                }
+                // globs consume the remaining segments
+                None if node.segment_type == SegmentType::Glob => continue,
+                None => break,
+            }
+        }
+
+        format
+    }
+
+    /// Declares the CORS configuration of the routes of this `Node` and its children, see
+    /// `DrawRoutes::set_cors`.
+    pub(crate) fn set_cors(&mut self, config: CorsConfig) {
+        self.cors = Some(Arc::new(config));
+    }
+
+    /// Returns the CORS configuration which applies to the routes of this `Node`, once
+    /// `apply_cors` was called.
+    pub(crate) fn cors(&self) -> Option<&Arc<CorsConfig>> {
+        self.cors.as_ref()
+    }
+
+    /// Adds a route answering the CORS preflight requests for `methods`, or for any method if
+    /// there are none, see `DefineSingleRoute::with_cors`.
+    pub(crate) fn add_cors_preflight(&mut self, methods: Vec<Method>, config: Arc<CorsConfig>) {
+        let route: RouteImpl<_, NoopPathExtractor, NoopQueryStringExtractor> = RouteImpl::new(
+            PreflightRouteMatcher { methods },
+            Box::new(PreflightDispatcher { config }),
+            Extractors::new(),
+            Delegation::Internal,
+        );
+        self.add_route(Box::new(route));
+    }
+
+    /// Passes the CORS configuration declared for this `Node`, or else `inherited`, on to its
+    /// children, and answers the preflight requests of the routes which have one. Called once
+    /// all routes are added, so that the preflight routes of single routes take precedence.
+    pub(crate) fn apply_cors(&mut self, inherited: Option<&Arc<CorsConfig>>) {
+        if self.cors.is_none() {
+            self.cors = inherited.cloned();
+        }
+
+        if let Some(config) = self.cors.clone() {
+            let delegated = self
+                .routes
+                .first()
+                .is_some_and(|route| route.delegation() == Delegation::External);
+            if self.is_routable() && !delegated {
+                self.add_cors_preflight(vec![], config);
+            }
+        }
+
+        let cors = self.cors.clone();
+        for child in &mut self.children {
+            child.apply_cors(cors.as_ref());
+        }
+    }
+
+    /// Declares the path decoding policy of the routes of this `Node` and its children, see
+    /// `DrawRoutes::set_path_decoding`.
+    pub(crate) fn set_path_decoding(&mut self, path_decoding: PathDecoding) {
+        self.path_decoding = Some(path_decoding);
+    }
+
+    /// Returns the path decoding policy which applies to the routes of this `Node`, once
+    /// `apply_path_decoding` was called.
+    pub(crate) fn path_decoding(&self) -> PathDecoding {
+        self.path_decoding.unwrap_or_default()
+    }
+
+    /// Passes the path decoding policy declared for this `Node`, or else `inherited`, on to its
+    /// children.
+    pub(crate) fn apply_path_decoding(&mut self, inherited: Option<PathDecoding>) {
+        if self.path_decoding.is_none() {
+            self.path_decoding = inherited;
+        }
+
+        for child in &mut self.children {
+            child.apply_path_decoding(self.path_decoding);
+        }
+    }
+
+    /// Declares the maximum size of request bodies for the routes of this `Node` and its children,
+    /// see `DrawRoutes::set_body_limit`.
+    pub(crate) fn set_body_limit(&mut self, body_limit: u64) {
+        self.body_limit = Some(body_limit);
+    }
+
+    /// Returns the maximum size of request bodies which applies to the routes of this `Node`,
+    /// once `apply_body_limit` was called.
+    pub(crate) fn body_limit(&self) -> Option<u64> {
+        self.body_limit
+    }
+
+    /// Passes the maximum size of request bodies declared for this `Node`, or else `inherited`,
+    /// on to its children.
+    pub(crate) fn apply_body_limit(&mut self, inherited: Option<u64>) {
+        if self.body_limit.is_none() {
+            self.body_limit = inherited;
+        }
+
+        for child in &mut self.children {
+            child.apply_body_limit(self.body_limit);
+        }
+    }
+
+    /// Declares a path extractor for the routes of this `Node` and its children, see
+    /// `DrawRoutes::set_path_extractor`.
+    pub(crate) fn add_path_extractor(&mut self, extractor: Arc<dyn ScopePathExtractor>) {
+        self.path_extractors.push(extractor);
+    }
+
+    /// Returns the path extractors which apply to the routes of this `Node`, outermost first,
+    /// once `apply_path_extractors` was called.
+    pub(crate) fn path_extractors(&self) -> &[Arc<dyn ScopePathExtractor>] {
+        &self.path_extractors
+    }
+
+    /// Prepends the `inherited` path extractors to the ones declared for this `Node`, and passes
+    /// them on to its children.
+    pub(crate) fn apply_path_extractors(&mut self, inherited: &[Arc<dyn ScopePathExtractor>]) {
+        let declared = std::mem::take(&mut self.path_extractors);
+        self.path_extractors = inherited.iter().cloned().chain(declared).collect();
+
+        for child in &mut self.children {
+            child.apply_path_extractors(&self.path_extractors);
+        }
+    }
+
+    /// Raises the match priority of the routes of this `Node` to at least `priority`, see
+    /// `DefineSingleRoute::with_match_priority`.
+    pub(crate) fn set_match_priority(&mut self, priority: i32) {
+        self.match_priority = Some(self.match_priority.map_or(priority, |p| p.max(priority)));
+    }
+
+    /// Orders the children of this `Node` and its descendants by the highest match priority of
+    /// the routes below them, keeping the order of `Ord` between equal priorities, and returns
+    /// the highest match priority of the routes of this `Node` and its descendants. Called once
+    /// all routes are added.
+    pub(crate) fn apply_match_priorities(&mut self) -> i32 {
+        let mut highest = match self.is_routable() {
+            true => self.match_priority.unwrap_or(0),
+            false => i32::MIN,
+        };
+
+        let mut children: Vec<(i32, Node)> = self
+            .children
+            .drain(..)
+            .map(|mut child| (child.apply_match_priorities(), child))
+            .collect();
+        children.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+        for (priority, child) in children {
+            highest = highest.max(priority);
+            self.children.push(child);
+        }
+
+        highest
+    }
+
+    /// Names a route of this `Node`, see `DefineSingleRoute::named`.
+    pub(crate) fn add_name(&mut self, name: &str) {
+        self.names.push(name.to_owned());
+    }
+
+    /// Records the route names of a `Router` which a route of this `Node` delegates to.
+    pub(crate) fn add_delegated_names(&mut self, names: Arc<RouteNames>) {
+        self.delegated_names.push(names);
+    }
+
+    /// Records the methods of a route being added to this `Node`, with the body limit declared by
+    /// the route. `conditional` is `true` if the route has further matchers besides its methods,
+    /// e.g. on the `Accept` header.
+    ///
+    /// # Panics
+    ///
+    /// If all of the methods are taken by routes added before without further matchers, as the
+    /// new route would never be selected.
+    pub(crate) fn add_route_methods(
+        &mut self,
+        methods: &[Method],
+        conditional: bool,
+        body_limit: Option<u64>,
+    ) {
+        if !methods.is_empty()
+            && methods
+                .iter()
+                .all(|method| self.unconditional_methods.contains(method))
+        {
+            let methods: Vec<&str> = methods.iter().map(Method::as_str).collect();
+            panic!(
+                "route {} {} is already defined, so the later definition would never be matched",
+                methods.join(","),
+                self.template
+            );
+        }
+
+        if !conditional {
+            self.unconditional_methods.extend(methods.iter().cloned());
+        }
+        self.methods
+            .extend(methods.iter().map(|method| (method.clone(), body_limit)));
+    }
+
+    /// Stops the `Router` from answering `OPTIONS` requests for this `Node` which no route
+    /// matches, see `DefineSingleRoute::without_options_response`.
+    pub(crate) fn disable_options_response(&mut self) {
+        self.answers_options = false;
+    }
+
+    /// Returns `true` if the `Router` answers `OPTIONS` requests for this `Node` which no route
+    /// matches, with the methods of its routes.
+    pub(crate) fn answers_options(&self) -> bool {
+        self.answers_options
+    }
+
+    /// Returns the methods of the routes of this `Node`, with the body limits declared by the
+    /// routes, in the order the routes were added.
+    pub(crate) fn methods(&self) -> &[(Method, Option<u64>)] {
+        &self.methods
+    }
+
+    /// Returns the names of the routes of this `Node`.
+    pub(crate) fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Returns `true` if the routes of this `Node` delegate to another `Router`.
+    pub(crate) fn is_delegated(&self) -> bool {
+        self.routes
+            .iter()
+            .any(|route| route.delegation() == Delegation::External)
+    }
+
+    /// Returns the routes of this `Node`, in the order they are evaluated.
+    pub(crate) fn routes(&self) -> &[Box<dyn Route<ResBody = Body> + Send + Sync>] {
+        &self.routes
+    }
+
+    /// Returns the children of this `Node`, in the order they are matched.
+    pub(crate) fn children(&self) -> &[Node] {
+        &self.children
+    }
+
+    /// Checks that no two children of this `Node`, or of its descendants, match the same path
+    /// segments. Only the first child matching a segment is ever visited, so the routes below
+    /// the other one could never be reached.
+    ///
+    /// # Panics
+    ///
+    /// If two children have a `SegmentType` of the same specificity, e.g. `/users/:id` and
+    /// `/users/:name`.
+    pub(crate) fn assert_unambiguous(&self) {
+        for (index, child) in self.children.iter().enumerate() {
+            let shadowed = self.children[index + 1..].iter().find(|other| {
+                other.segment_type != SegmentType::Static
+                    && other.segment_type == child.segment_type
+            });
+
+            if let Some(other) = shadowed {
+                panic!(
+                    "routes {} and {} match the same requests, so the latter would never be matched",
+                    child.template, other.template
+                );
+            }
+
+            child.assert_unambiguous();
+        }
+    }
+
+    /// Adds the route names of this `Node` and its children to `names`, with their templates.
+    pub(crate) fn collect_names(&self, names: &mut RouteNames) {
+        for name in &self.names {
+            url_for::add_name(names, name, self.template.clone());
+        }
+
+        for delegated in &self.delegated_names {
+            url_for::add_delegated_names(names, &self.template, delegated);
+        }
+
+        for child in &self.children {
+            child.collect_names(names);
+        }
+    }
+
     /// Retrieves a reference to the contained segment value.
     ///
     /// This is required for lifetime related annotations.
@@ -113,6 +502,27 @@ impl Node {
         &self.segment
     }
 
+    /// Returns the `AsyncRouteMatcher` values which the `Router` evaluates before selecting a
+    /// route for the request in `state`. These are the ones of the routes which otherwise match,
+    /// up to the first of those without any, as it is selected before the routes after it.
+    pub(crate) fn async_matchers(&self, state: &State) -> Vec<Arc<dyn AsyncRouteMatcher>> {
+        let mut matchers: Vec<Arc<dyn AsyncRouteMatcher>> = vec![];
+        for route in self.routes.iter() {
+            if route.is_sync_match(state).is_err() {
+                continue;
+            }
+            if route.async_matchers().is_empty() {
+                break;
+            }
+            for matcher in route.async_matchers() {
+                if !matchers.iter().any(|m| Arc::ptr_eq(m, matcher)) {
+                    matchers.push(matcher.clone());
+                }
+            }
+        }
+        matchers
+    }
+
     /// Determines if a `Route` instance associated with this `Node` is willing to `Handle` the
     /// request.
     ///
@@ -124,6 +534,7 @@ impl Node {
     ///
     /// In the situation where all these avenues are exhausted an InternalServerError will be
     /// provided.
+    #[allow(clippy::borrowed_box)]
     pub fn select_route(
         &self,
         state: &State,
@@ -203,10 +614,7 @@ impl Node {
                 // Globbing matches everything, so we append the segment value
                 // to the parameters against the child segment name.
                 SegmentType::Glob => {
-                    params
-                        .entry(&child.segment)
-                        .or_insert_with(|| vec![])
-                        .push(&segment);
+                    params.entry(&child.segment).or_default().push(segment);
                 }
 
                 // Static matches based on a raw string match, so we simply
@@ -446,6 +854,7 @@ mod tests {
         match root.match_node(&rs.segments()) {
             Some((node, _params, processed)) => {
                 assert_eq!(node.segment, "seg4");
+                assert_eq!(node.template(), "/seg3/seg4");
                 assert_eq!(processed, 2);
             }
             None => panic!("traversal should have succeeded here"),
@@ -480,6 +889,7 @@ mod tests {
         match root.match_node(&rs.segments()) {
             Some((node, _params, processed)) => {
                 assert_eq!(node.segment, "seg10");
+                assert_eq!(node.template(), "/*seg8/seg9/*seg10");
                 assert_eq!(processed, 5);
             }
             None => panic!("traversal should have succeeded here"),
@@ -490,6 +900,7 @@ mod tests {
         match root.match_node(&rs.segments()) {
             Some((node, _params, processed)) => {
                 assert_eq!(node.segment, expected_segment);
+                assert_eq!(node.template(), "/resource/:id");
                 assert_eq!(processed, 2);
             }
             None => panic!("traversal should have succeeded here"),
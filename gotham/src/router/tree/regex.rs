@@ -19,10 +19,17 @@ impl ConstrainedSegmentRegex {
     /// Creates a new ConstrainedSegmentRegex from a provided string.
     ///
     /// It wraps the string in begin and end of line anchors to prevent it from matching more than
-    /// intended.
+    /// intended, which also apply to every branch of an alternation such as `new|edit`.
+    ///
+    /// # Panics
+    ///
+    /// If the string is not a valid regex.
     pub fn new(regex: &str) -> Self {
+        let anchored = Regex::new(&format!("^(?:{})$", regex))
+            .unwrap_or_else(|e| panic!("invalid path segment regex {:?}: {}", regex, e));
+
         ConstrainedSegmentRegex {
-            regex: AssertUnwindSafe(Regex::new(&format!("^{}$", regex)).unwrap()),
+            regex: AssertUnwindSafe(anchored),
         }
     }
 
@@ -67,3 +74,24 @@ impl Clone for ConstrainedSegmentRegex {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anchors_every_branch() {
+        let regex = ConstrainedSegmentRegex::new("new|edit");
+
+        assert!(regex.is_match("new"));
+        assert!(regex.is_match("edit"));
+        assert!(!regex.is_match("renew"));
+        assert!(!regex.is_match("edited"));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid path segment regex \"[0-9\"")]
+    fn rejects_invalid_regex() {
+        ConstrainedSegmentRegex::new("[0-9");
+    }
+}
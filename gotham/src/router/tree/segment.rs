@@ -1,12 +1,37 @@
 //! Defines `SegmentType` for `Tree`.
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use crate::helpers::http::PercentDecoded;
 use crate::router::tree::regex::ConstrainedSegmentRegex;
+use crate::state::StateData;
 
 /// Mapping of segment names into the collection of values for that segment.
 pub type SegmentMapping<'r> = HashMap<&'r str, Vec<&'r PercentDecoded>>;
 
+/// The names of the glob segments of the matched route, whose values in the `SegmentMapping` are
+/// the path segments they captured.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct GlobSegments(HashSet<String>);
+
+impl StateData for GlobSegments {}
+
+impl GlobSegments {
+    /// Collects the names of the glob segments of a route template, e.g. `/assets/*path`.
+    pub(crate) fn from_template(template: &str) -> GlobSegments {
+        let names = template
+            .split('/')
+            .filter_map(|segment| segment.strip_prefix('*'))
+            .map(|name| if name.is_empty() { "*" } else { name })
+            .map(String::from);
+        GlobSegments(names.collect())
+    }
+
+    /// Returns `true` if `name` belongs to a glob segment.
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.0.contains(name)
+    }
+}
+
 /// Indicates the type of segment which is being represented by this Node.
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd)]
 pub enum SegmentType {
@@ -0,0 +1,356 @@
+//! Defines `UrlFor`, which builds the paths of named routes, see `DefineSingleRoute::named`.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::sync::Arc;
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::router::tree::Tree;
+use crate::state::{State, StateData};
+
+/// The characters which are percent-encoded in path segments, following the `path segment
+/// percent-encode set` of the URL standard, plus `%` itself.
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}')
+    .add(b'/')
+    .add(b'%');
+
+/// The templates of the named routes of a `Router`, keyed by route name.
+pub(crate) type RouteNames = HashMap<String, String>;
+
+/// Collects the names of the routes in `tree`, including those of the routers it delegates to.
+///
+/// # Panics
+///
+/// If a name is given to routes with different templates.
+pub(crate) fn route_names(tree: &Tree) -> RouteNames {
+    let mut names = RouteNames::new();
+    tree.borrow_root().collect_names(&mut names);
+    names
+}
+
+/// Adds the route `name` with `template` to `names`.
+///
+/// # Panics
+///
+/// If `names` has a route with the same name and a different template.
+pub(crate) fn add_name(names: &mut RouteNames, name: &str, template: String) {
+    if let Some(existing) = names.get(name) {
+        assert!(
+            *existing == template,
+            "route name {} is used for {} and {}",
+            name,
+            existing,
+            template
+        );
+    }
+    names.insert(name.to_owned(), template);
+}
+
+/// Adds the routes in `inner`, of a router delegated to below `prefix`, to `names`.
+pub(crate) fn add_delegated_names(names: &mut RouteNames, prefix: &str, inner: &RouteNames) {
+    for (name, template) in inner {
+        let template = match template.as_str() {
+            "/" => prefix.to_owned(),
+            template => format!("{}{}", prefix.trim_end_matches('/'), template),
+        };
+        add_name(names, name, template);
+    }
+}
+
+/// Builds the paths of named routes, so that redirects and templates don't need to hard-code
+/// them. `UrlFor` is put into `State` by the `Router` before the route's pipelines and handler
+/// are invoked.
+///
+/// The path parameters are taken from a value which serializes to a map, usually the typed path
+/// extractor of the route, so that they are checked in the same way for both directions. Values
+/// are percent-encoded, except for the `/` separating the segments captured by a glob.
+///
+/// ```rust
+/// # extern crate gotham;
+/// # #[macro_use]
+/// # extern crate gotham_derive;
+/// # extern crate hyper;
+/// # #[macro_use]
+/// # extern crate serde_derive;
+/// #
+/// # use hyper::StatusCode;
+/// # use hyper::header::LOCATION;
+/// # use gotham::helpers::http::response::create_temporary_redirect;
+/// # use gotham::router::UrlFor;
+/// # use gotham::router::builder::*;
+/// # use gotham::state::{FromState, State};
+/// # use gotham::test::TestServer;
+/// #
+/// #[derive(Deserialize, Serialize, StateData, StaticResponseExtender)]
+/// struct UserPath {
+///     id: u64,
+/// }
+///
+/// fn user_detail(state: State) -> (State, String) {
+///     let id = UserPath::borrow_from(&state).id;
+///     (state, format!("user {}", id))
+/// }
+///
+/// fn me(state: State) -> (State, hyper::Response<hyper::Body>) {
+///     let location = UrlFor::borrow_from(&state)
+///         .url_for("user_detail", &UserPath { id: 42 })
+///         .unwrap();
+///     let response = create_temporary_redirect(&state, location);
+///     (state, response)
+/// }
+///
+/// # fn main() {
+/// let router = build_simple_router(|route| {
+///     route
+///         .get("/users/:id")
+///         .with_path_extractor::<UserPath>()
+///         .named("user_detail")
+///         .to(user_detail);
+///     route.get("/me").to(me);
+/// });
+///
+/// let test_server = TestServer::new(router).unwrap();
+/// let response = test_server.client().get("http://localhost/me").perform().unwrap();
+/// assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+/// assert_eq!(response.headers()[LOCATION], "/users/42");
+/// # }
+/// ```
+///
+/// Routes of secondary `Router` instances are found as well, from the `Router` they are
+/// delegated to or mounted on.
+#[derive(Clone, Debug)]
+pub struct UrlFor {
+    names: Arc<RouteNames>,
+}
+
+impl UrlFor {
+    /// Puts the route names of a `Router` into `state`, unless a `Router` delegating to it did
+    /// so already, as its names include those of this `Router`.
+    pub(crate) fn put(state: &mut State, names: &Arc<RouteNames>) {
+        if !state.has::<UrlFor>() {
+            state.put(UrlFor {
+                names: names.clone(),
+            });
+        }
+    }
+
+    /// Returns the template of the route named `name`, e.g. `/users/:id`.
+    pub fn template(&self, name: &str) -> Option<&str> {
+        self.names.get(name).map(String::as_str)
+    }
+
+    /// Builds the path of the route named `name`, taking its parameters from the fields of
+    /// `params`. Routes without parameters can be built from `&()`.
+    pub fn url_for<T>(&self, name: &str, params: &T) -> Result<String, UrlForError>
+    where
+        T: Serialize + ?Sized,
+    {
+        let template = self
+            .template(name)
+            .ok_or_else(|| UrlForError::UnknownRoute(name.to_owned()))?;
+
+        let params = match serde_json::to_value(params) {
+            Ok(Value::Object(params)) => params,
+            Ok(Value::Null) => serde_json::Map::new(),
+            _ => return Err(UrlForError::InvalidParams(name.to_owned())),
+        };
+
+        if template == "/" {
+            return Ok(template.to_owned());
+        }
+
+        let mut path = String::new();
+        for segment in template.split('/').skip(1) {
+            path.push('/');
+
+            let (key, glob) = match segment.as_bytes().first() {
+                Some(b':') => (&segment[1..], false),
+                Some(b'*') if segment == "*" => (segment, true),
+                Some(b'*') => (&segment[1..], true),
+                _ => {
+                    path.push_str(segment);
+                    continue;
+                }
+            };
+
+            let missing = || UrlForError::MissingParam(key.to_owned());
+            match params.get(key) {
+                Some(Value::Array(values)) if glob => {
+                    let values = values
+                        .iter()
+                        .map(|value| {
+                            render(value)
+                                .map(|value| encode(&value))
+                                .ok_or_else(missing)
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    path.push_str(&values.join("/"));
+                }
+                Some(value) => {
+                    let value = render(value).ok_or_else(missing)?;
+                    if glob {
+                        // the separators of glob values are kept
+                        let parts: Vec<String> = value.split('/').map(encode).collect();
+                        path.push_str(&parts.join("/"));
+                    } else {
+                        path.push_str(&encode(&value));
+                    }
+                }
+                None => return Err(missing()),
+            }
+        }
+
+        Ok(path)
+    }
+}
+
+impl StateData for UrlFor {}
+
+/// Renders a parameter value as it appears in a path, before percent-encoding.
+fn render(value: &Value) -> Option<String> {
+    match value {
+        Value::String(value) => Some(value.clone()),
+        Value::Number(value) => Some(value.to_string()),
+        Value::Bool(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+fn encode(value: &str) -> String {
+    utf8_percent_encode(value, PATH_SEGMENT).to_string()
+}
+
+/// The error returned by `UrlFor::url_for` when the path of a route can't be built.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UrlForError {
+    /// No route of the `Router` has the given name.
+    UnknownRoute(String),
+
+    /// The parameters for the named route don't serialize to a map.
+    InvalidParams(String),
+
+    /// The given path parameter is missing from the parameters, or it is not a string, number or
+    /// boolean.
+    MissingParam(String),
+}
+
+impl Display for UrlForError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UrlForError::UnknownRoute(name) => write!(f, "no route is named {}", name),
+            UrlForError::InvalidParams(name) => {
+                write!(f, "the parameters of route {} are not a map", name)
+            }
+            UrlForError::MissingParam(key) => write!(f, "missing path parameter {}", key),
+        }
+    }
+}
+
+impl Error for UrlForError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    use serde_derive::Serialize;
+
+    use crate::router::builder::{build_simple_router, DefineSingleRoute, DrawRoutes};
+    use crate::state::FromState;
+    use crate::test::TestServer;
+
+    #[derive(Serialize)]
+    struct FileParams {
+        tenant: &'static str,
+        #[serde(rename = "*")]
+        path: Vec<&'static str>,
+    }
+
+    fn params<'a>(params: &[(&'a str, &'a str)]) -> BTreeMap<&'a str, &'a str> {
+        params.iter().cloned().collect()
+    }
+
+    fn urls(state: State) -> (State, String) {
+        let url_for = UrlFor::borrow_from(&state);
+        let body = [
+            url_for.url_for("index", &()).unwrap(),
+            url_for
+                .url_for(
+                    "file",
+                    &FileParams {
+                        tenant: "a b",
+                        path: vec!["x", "y%"],
+                    },
+                )
+                .unwrap(),
+            url_for.url_for("user", &params(&[("id", "1/2")])).unwrap(),
+            url_for
+                .url_for("tenant_root", &params(&[("tenant", "t")]))
+                .unwrap(),
+            url_for
+                .url_for("tenant_user", &params(&[("tenant", "t"), ("id", "3")]))
+                .unwrap(),
+            format!("{:?}", url_for.url_for("missing", &())),
+            format!("{:?}", url_for.url_for("user", &())),
+            format!("{:?}", url_for.url_for("user", &1)),
+        ];
+        (state, body.join("\n"))
+    }
+
+    #[test]
+    fn builds_urls_of_named_routes() {
+        let tenant_router = build_simple_router(|route| {
+            route.get("/").named("tenant_root").to(urls);
+            route.get("/users/:id").named("tenant_user").to(urls);
+        });
+
+        let router = build_simple_router(|route| {
+            route.get("/").named("index").to(urls);
+            route.get("/users/:id").named("user").to(urls);
+            route.post("/users/:id").named("user").to(urls);
+            route.get("/files/:tenant/*").named("file").to(urls);
+            route.delegate("/tenants/:tenant").to_router(tenant_router);
+        });
+
+        let test_server = TestServer::new(router).unwrap();
+        let expected = [
+            "/",
+            "/files/a%20b/x/y%25",
+            "/users/1%2F2",
+            "/tenants/t",
+            "/tenants/t/users/3",
+            "Err(UnknownRoute(\"missing\"))",
+            "Err(MissingParam(\"id\"))",
+            "Err(InvalidParams(\"user\"))",
+        ]
+        .join("\n");
+
+        for uri in &["http://localhost/", "http://localhost/tenants/t/users/1"] {
+            let response = test_server.client().get(*uri).perform().unwrap();
+            assert_eq!(response.read_utf8_body().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "route name user is used for")]
+    fn rejects_ambiguous_names() {
+        build_simple_router(|route| {
+            route.get("/users/:id").named("user").to(urls);
+            route.get("/people/:id").named("user").to(urls);
+        });
+    }
+}
@@ -10,6 +10,7 @@ use hyper::{Body, Response, StatusCode};
 use log::error;
 
 use crate::handler::{Handler, HandlerError, IntoResponse, NewHandler};
+use crate::helpers::http::header::InternalHeaders;
 use crate::state::{request_id, State};
 
 async fn handle<H>(
@@ -29,6 +30,8 @@ where
 ///
 /// Timing information is recorded and logged, except in the case of a panic where the timer is
 /// moved and cannot be recovered.
+///
+/// Headers in the `InternalHeaders` namespace of the request are removed from the response.
 pub async fn call_handler<T>(t: T, state: AssertUnwindSafe<State>) -> anyhow::Result<Response<Body>>
 where
     T: NewHandler + Send + UnwindSafe,
@@ -38,24 +41,26 @@ where
             let unwind_result = AssertUnwindSafe(handle(handler?, state))
                 .catch_unwind()
                 .await;
-            let result = match unwind_result {
-                Ok(result) => result.map(|(_, res)| res),
-                Err(_) => Ok(finalize_panic_response()),
+            let (state, mut res) = match unwind_result {
+                Ok(Ok((state, res))) => (state, res),
+                Ok(Err((state, err))) => {
+                    let res = finalize_error_response(&state, err);
+                    (state, res)
+                }
+                Err(_) => return Ok(finalize_panic_response()),
             };
-            Ok(match result {
-                Ok(res) => res,
-                Err((state, err)) => finalize_error_response(state, err),
-            })
+            InternalHeaders::from_state(&state).strip(res.headers_mut());
+            Ok(res)
         }
         // Error while creating the handler from NewHandler
         Err(_) => Ok(finalize_panic_response()),
     }
 }
 
-fn finalize_error_response(state: State, err: HandlerError) -> Response<Body> {
-    error!("[ERROR][{}][Error: {:?}]", request_id(&state), err);
+fn finalize_error_response(state: &State, err: HandlerError) -> Response<Body> {
+    error!("[ERROR][{}][Error: {:?}]", request_id(state), err);
 
-    err.into_response(&state)
+    err.into_response(state)
 }
 
 fn finalize_panic_response() -> Response<Body> {
@@ -141,6 +146,41 @@ mod tests {
         assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
     }
 
+    #[test]
+    fn strips_internal_headers() {
+        let new_handler = || {
+            Ok(|state| {
+                let mut res = create_empty_response(&state, StatusCode::ACCEPTED);
+                let headers = res.headers_mut();
+                headers.insert("x-internal-user", "42".parse().unwrap());
+                headers.insert("x-private-user", "42".parse().unwrap());
+                (state, res)
+            })
+        };
+
+        for (internal_headers, stripped, kept) in [
+            (None, "x-internal-user", "x-private-user"),
+            (
+                Some(InternalHeaders::with_prefix("X-Private-")),
+                "x-private-user",
+                "x-internal-user",
+            ),
+        ] {
+            let mut state = State::new();
+            state.put(HeaderMap::new());
+            state.put(Method::GET);
+            set_request_id(&mut state);
+            if let Some(internal_headers) = internal_headers {
+                state.put(internal_headers);
+            }
+
+            let r = call_handler(&new_handler, AssertUnwindSafe(state));
+            let response = futures::executor::block_on(r).unwrap();
+            assert!(!response.headers().contains_key(stripped));
+            assert!(response.headers().contains_key(kept));
+        }
+    }
+
     #[test]
     fn panic() {
         let new_handler = || {
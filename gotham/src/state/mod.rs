@@ -4,6 +4,7 @@ pub(crate) mod client_addr;
 mod data;
 mod from_state;
 pub mod request_id;
+mod rng;
 
 use log::{debug, trace};
 
@@ -18,10 +19,13 @@ pub use crate::state::client_addr::client_addr;
 pub use crate::state::data::StateData;
 pub use crate::state::from_state::FromState;
 pub use crate::state::request_id::request_id;
+pub use crate::state::rng::SeededRng;
 
 use crate::helpers::http::request::path::RequestPathSegments;
 use crate::state::client_addr::put_client_addr;
-pub(crate) use crate::state::request_id::set_request_id;
+pub(crate) use crate::state::request_id::{
+    copy_request_id, generate_request_id, regenerate_request_id, replace_request_id, set_request_id,
+};
 
 /// Provides storage for request state, and stores one item of each type. The types used for
 /// storage must implement the `gotham::state::StateData` trait to allow its storage. The
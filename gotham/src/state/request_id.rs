@@ -1,22 +1,65 @@
 //! Defines a unique id per `Request` that should be output with all logging.
 
+use std::panic::RefUnwindSafe;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
 use hyper::header::HeaderMap;
 use log::trace;
 use uuid::Uuid;
 
-use crate::state::{FromState, State};
+use crate::state::{FromState, State, StateData};
 
 /// A container type for the value returned by `request_id`.
 pub(super) struct RequestId {
     val: String,
 }
 
+/// Generates the ids of requests which don't provide an `X-Request-ID` header, in place of random
+/// UUIDs.
+///
+/// This makes request ids reproducible, e.g. in snapshot tests. A `TestServer` puts its generator
+/// into `State` with `TestServer::with_request_ids`.
+#[derive(Clone)]
+pub struct RequestIdGenerator {
+    generate: Arc<dyn Fn() -> String + Send + Sync + RefUnwindSafe>,
+}
+
+impl RequestIdGenerator {
+    /// Creates a generator which calls `f` for each request id.
+    pub fn new<F>(f: F) -> RequestIdGenerator
+    where
+        F: Fn() -> String + Send + Sync + RefUnwindSafe + 'static,
+    {
+        RequestIdGenerator {
+            generate: Arc::new(f),
+        }
+    }
+
+    /// Creates a generator of the ids `{prefix}-1`, `{prefix}-2`, and so on.
+    pub fn sequential(prefix: &str) -> RequestIdGenerator {
+        let prefix = prefix.to_owned();
+        let counter = AtomicU64::new(0);
+        RequestIdGenerator::new(move || {
+            format!("{}-{}", prefix, counter.fetch_add(1, Ordering::Relaxed) + 1)
+        })
+    }
+
+    /// Generates the next request id.
+    pub fn generate(&self) -> String {
+        (self.generate)()
+    }
+}
+
+impl StateData for RequestIdGenerator {}
+
 /// Sets a unique identifier for the request if it has not already been stored.
 ///
 /// The unique identifier chosen depends on the the request headers:
 ///
 /// 1. If the header `X-Request-ID` is provided this value is used as-is;
-/// 2. Alternatively creates and stores a UUID v4 value.
+/// 2. Alternatively, if a `RequestIdGenerator` is stored, generates a value with it;
+/// 3. Alternatively creates and stores a UUID v4 value.
 ///
 /// This function is invoked by `GothamService` before handing control to its `Router`, to ensure
 /// that a value for `RequestId` is always available.
@@ -32,7 +75,7 @@ pub(crate) fn set_request_id<'a>(state: &'a mut State) -> &'a str {
                 RequestId { val: id }
             }
             None => {
-                let val = Uuid::new_v4().to_hyphenated().to_string();
+                let val = generate_request_id(state);
                 trace!("[{}] RequestId generated internally", val);
                 RequestId { val }
             }
@@ -43,6 +86,46 @@ pub(crate) fn set_request_id<'a>(state: &'a mut State) -> &'a str {
     request_id(state)
 }
 
+/// Generates a request id with the `RequestIdGenerator` stored in `state`, or else a UUID v4.
+pub(crate) fn generate_request_id(state: &State) -> String {
+    match RequestIdGenerator::try_borrow_from(state) {
+        Some(generator) => generator.generate(),
+        None => Uuid::new_v4().to_hyphenated().to_string(),
+    }
+}
+
+/// Replaces an internally generated request id with one from the `RequestIdGenerator` stored in
+/// `state`, which was stored after the request id was set.
+pub(crate) fn regenerate_request_id(state: &mut State) {
+    let external = HeaderMap::try_borrow_from(state)
+        .map(|headers| headers.contains_key("X-Request-ID"))
+        .unwrap_or(false);
+
+    if !external {
+        state.try_take::<RequestId>();
+        set_request_id(state);
+    }
+}
+
+/// Replaces the request id stored in `state` with `id`, e.g. an id received from an upstream
+/// service which the `RequestIdMiddleware` accepted.
+pub(crate) fn replace_request_id(state: &mut State, id: String) {
+    state.try_take::<RequestId>();
+    state.put(RequestId { val: id });
+}
+
+/// Copies the request id stored in `from` into `to`, if there is one.
+///
+/// This is used to build a partial copy of a `State` which can outlive the original, e.g. to
+/// respond to a request after its handler has panicked.
+pub(crate) fn copy_request_id(from: &State, to: &mut State) {
+    if let Some(request_id) = RequestId::try_borrow_from(from) {
+        to.put(RequestId {
+            val: request_id.val.clone(),
+        });
+    }
+}
+
 /// Returns the request ID associated with the current request.
 ///
 /// This is typically used for logging and correlating events that occurred within a request.
@@ -101,6 +184,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn generates_request_ids() {
+        let mut state = State::new();
+        state.put(HeaderMap::new());
+        state.put(RequestIdGenerator::sequential("req"));
+
+        assert_eq!("req-1", set_request_id(&mut state));
+        regenerate_request_id(&mut state);
+        assert_eq!("req-2", request_id(&state));
+    }
+
     #[test]
     fn does_not_overwrite_existant_request_id() {
         let mut state = State::new();
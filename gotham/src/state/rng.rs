@@ -0,0 +1,62 @@
+//! Defines a seeded random number generator for reproducible tests.
+
+use std::sync::{Arc, Mutex};
+
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaChaRng;
+
+use crate::state::StateData;
+
+/// A deterministic random number generator, used in place of the system's entropy where random
+/// values are generated for a request, e.g. for new session identifiers.
+///
+/// Values generated by a `SeededRng` are predictable, so it must only be used in tests. A
+/// `TestServer` puts its `SeededRng` into `State` with `TestServer::with_seed`, and
+/// `NewSessionMiddleware::with_seeded_rng` generates session identifiers with one.
+///
+/// `SeededRng` is a cheaply cloneable handle, so that the values generated for a sequence of
+/// requests are reproducible.
+#[derive(Clone)]
+pub struct SeededRng {
+    rng: Arc<Mutex<ChaChaRng>>,
+}
+
+impl SeededRng {
+    /// Creates a generator from `seed`.
+    pub fn new(seed: u64) -> SeededRng {
+        SeededRng {
+            rng: Arc::new(Mutex::new(ChaChaRng::seed_from_u64(seed))),
+        }
+    }
+
+    /// Fills `dest` with random bytes.
+    pub fn fill_bytes(&self, dest: &mut [u8]) {
+        // the generator remains usable when a panic occurs while it is locked
+        match self.rng.lock() {
+            Ok(mut rng) => rng.fill_bytes(dest),
+            Err(poisoned) => poisoned.into_inner().fill_bytes(dest),
+        }
+    }
+}
+
+impl StateData for SeededRng {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_reproducible_bytes() {
+        let bytes = |rng: &SeededRng| {
+            let mut bytes = [0u8; 16];
+            rng.fill_bytes(&mut bytes);
+            bytes
+        };
+
+        let (a, b) = (SeededRng::new(7), SeededRng::new(7));
+        let first = bytes(&a);
+        assert_eq!(first, bytes(&b));
+        assert_ne!(first, bytes(&a));
+        assert_ne!(first, bytes(&SeededRng::new(8)));
+    }
+}
@@ -1,22 +1,32 @@
 /// Test request behavior, shared between the tls::test and plain::test modules.
 pub mod request;
 
+mod determinism;
+mod har;
+mod pact;
+
 use std::convert::TryFrom;
 use std::fmt;
 use std::ops::{Deref, DerefMut};
+use std::time::Instant;
 
 use anyhow::anyhow;
+use chrono::Utc;
 use futures::prelude::*;
+use hyper::body::HttpBody;
 use hyper::client::connect::Connect;
 use hyper::client::Client;
 use hyper::header::CONTENT_TYPE;
-use hyper::{body, Body, Method, Response, Uri};
+use hyper::{body, Body, Method, Request, Response, Uri};
 use log::warn;
 use mime;
 use tokio::time::Sleep;
 
 pub use crate::plain::test::TestServer;
+pub(crate) use determinism::Determinism;
 use futures::TryFutureExt;
+pub use har::HarRecorder;
+pub use pact::{PactContract, PactInteraction};
 pub use request::TestRequest;
 
 pub(crate) trait BodyReader {
@@ -35,6 +45,11 @@ pub trait Server: Clone {
     /// Returns a Delay that will expire when a request should.
     fn request_expiry(&self) -> Sleep;
 
+    /// Returns the recorder of the requests performed against this server, if any.
+    fn har_recorder(&self) -> Option<HarRecorder> {
+        None
+    }
+
     /// Runs the event loop until the response future is completed.
     ///
     /// If the future came from a different instance of `Server`, the event loop will run until
@@ -184,17 +199,50 @@ impl<TS: Server + 'static, C: Connect + Clone + Send + Sync + 'static> TestClien
 
     /// Send a constructed request using this `TestClient`, and await the response.
     pub fn perform(&self, req: TestRequest<TS, C>) -> anyhow::Result<TestResponse> {
-        let req_future = self.client.request(req.request()).map_err(|e| {
+        let (parts, body) = req.request().into_parts();
+
+        // only bodies of a known size are buffered for recording, so that streams keep flowing
+        let recorder = self.test_server.har_recorder();
+        let (body, recorded_body) = match recorder {
+            Some(_) if HttpBody::size_hint(&body).exact().is_some() => {
+                let bytes = self.test_server.run_future(body::to_bytes(body))?;
+                (Body::from(bytes.clone()), Some(bytes))
+            }
+            _ => (body, None),
+        };
+
+        // the request head is kept for recording, as `Parts` cannot be cloned
+        let mut request = Request::new(body);
+        *request.method_mut() = parts.method.clone();
+        *request.uri_mut() = parts.uri.clone();
+        *request.version_mut() = parts.version;
+        *request.headers_mut() = parts.headers.clone();
+
+        let req_future = self.client.request(request).map_err(|e| {
             warn!("Error from test client request {:?}", e);
             e
         });
 
-        self.test_server
-            .run_request(req_future)
-            .map(|response| TestResponse {
-                response,
-                reader: Box::new(self.test_server.clone()),
-            })
+        let started = Utc::now();
+        let start = Instant::now();
+        let response = self.test_server.run_request(req_future)?;
+
+        let har = recorder.map(|recorder| {
+            let index = recorder.record(
+                started,
+                start.elapsed(),
+                &parts,
+                recorded_body.as_deref(),
+                &response,
+            );
+            (recorder, index)
+        });
+
+        Ok(TestResponse {
+            response,
+            reader: Box::new(self.test_server.clone()),
+            har,
+        })
     }
 }
 
@@ -238,6 +286,7 @@ impl<TS: Server + 'static, C: Connect + Clone + Send + Sync + 'static> TestClien
 pub struct TestResponse {
     response: Response<Body>,
     reader: Box<dyn BodyReader>,
+    har: Option<(HarRecorder, usize)>,
 }
 
 impl Deref for TestResponse {
@@ -270,7 +319,11 @@ impl TestResponse {
     /// Awaits the body of the underlying `Response`, and returns it. This will cause the event
     /// loop to execute until the `Response` body has been fully read into the `Vec<u8>`.
     pub fn read_body(mut self) -> Result<Vec<u8>, hyper::Error> {
-        self.reader.read_body(self.response)
+        let body = self.reader.read_body(self.response)?;
+        if let Some((recorder, index)) = self.har {
+            recorder.record_body(index, &body);
+        }
+        Ok(body)
     }
 
     /// Awaits the UTF-8 encoded body of the underlying `Response`, and returns the `String`. This
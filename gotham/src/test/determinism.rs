@@ -0,0 +1,106 @@
+//! Injects deterministic request ids, random values and clocks into the requests of a test server,
+//! shared between the tls::test and plain::test modules.
+
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::clock::SharedClock;
+use crate::handler::{Handler, HandlerFuture, NewHandler};
+use crate::state::request_id::RequestIdGenerator;
+use crate::state::{regenerate_request_id, SeededRng, State};
+
+#[derive(Clone, Default)]
+struct Sources {
+    request_ids: Option<RequestIdGenerator>,
+    rng: Option<SeededRng>,
+    clock: Option<SharedClock>,
+}
+
+/// The deterministic sources of a test server, which are put into the `State` of each request.
+#[derive(Clone, Default)]
+pub(crate) struct Determinism {
+    sources: Arc<Mutex<Sources>>,
+}
+
+impl Determinism {
+    pub(crate) fn set_request_ids(&self, generator: RequestIdGenerator) {
+        self.lock().request_ids = Some(generator);
+    }
+
+    pub(crate) fn set_seed(&self, seed: u64) {
+        self.lock().rng = Some(SeededRng::new(seed));
+    }
+
+    pub(crate) fn set_clock(&self, clock: SharedClock) {
+        self.lock().clock = Some(clock);
+    }
+
+    /// Wraps `new_handler`, so that its handlers see the deterministic sources in `State`.
+    pub(crate) fn wrap<NH>(&self, new_handler: NH) -> DeterministicNewHandler<NH>
+    where
+        NH: NewHandler,
+    {
+        DeterministicNewHandler {
+            new_handler,
+            determinism: self.clone(),
+        }
+    }
+
+    fn put_into(&self, state: &mut State) {
+        let sources = self.lock().clone();
+
+        if let Some(generator) = sources.request_ids {
+            state.put(generator);
+            regenerate_request_id(state);
+        }
+
+        if let Some(rng) = sources.rng {
+            state.put(rng);
+        }
+
+        if let Some(clock) = sources.clock {
+            state.put(clock);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Sources> {
+        match self.sources.lock() {
+            Ok(sources) => sources,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+pub(crate) struct DeterministicNewHandler<NH> {
+    new_handler: NH,
+    determinism: Determinism,
+}
+
+impl<NH> NewHandler for DeterministicNewHandler<NH>
+where
+    NH: NewHandler,
+{
+    type Instance = DeterministicHandler<NH::Instance>;
+
+    fn new_handler(&self) -> anyhow::Result<Self::Instance> {
+        Ok(DeterministicHandler {
+            handler: self.new_handler.new_handler()?,
+            determinism: self.determinism.clone(),
+        })
+    }
+}
+
+pub(crate) struct DeterministicHandler<H> {
+    handler: H,
+    determinism: Determinism,
+}
+
+impl<H> Handler for DeterministicHandler<H>
+where
+    H: Handler,
+{
+    fn handle(self, mut state: State) -> Pin<Box<HandlerFuture>> {
+        self.determinism.put_into(&mut state);
+        self.handler.handle(state)
+    }
+}
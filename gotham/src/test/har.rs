@@ -0,0 +1,114 @@
+//! Records the traffic of test clients as HAR, shared between the tls::test and plain::test
+//! modules.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use hyper::header::CONTENT_TYPE;
+use hyper::http::request;
+use hyper::{Body, Response};
+use serde_json::{json, Value};
+
+use crate::helpers::http::har;
+
+/// Records the requests performed by the clients of a test server, along with their responses,
+/// as entries of a [HAR](https://w3c.github.io/web-performance/specs/HAR/Overview.html) log.
+///
+/// Response bodies are recorded once they are read through `TestResponse::read_body`, so
+/// responses which are never read, or which are streamed, are recorded without content.
+#[derive(Clone, Default)]
+pub struct HarRecorder {
+    entries: Arc<Mutex<Vec<Value>>>,
+}
+
+impl HarRecorder {
+    /// Renders the recorded entries as a HAR log.
+    pub fn to_har(&self) -> Value {
+        har::log(self.lock().clone())
+    }
+
+    /// Removes all recorded entries.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Records a request and the head of its response, returning the index of the entry.
+    pub(crate) fn record(
+        &self,
+        started: DateTime<Utc>,
+        elapsed: Duration,
+        request: &request::Parts,
+        body: Option<&[u8]>,
+        response: &Response<Body>,
+    ) -> usize {
+        let url = request.uri.to_string();
+        let mut har_request = json!({
+            "method": request.method.as_str(),
+            "url": url,
+            "httpVersion": format!("{:?}", request.version),
+            "cookies": [],
+            "headers": har::header_records(&request.headers),
+            "queryString": har::query_string(&url),
+            "headersSize": -1,
+            "bodySize": body.map_or(-1, |body| body.len() as i64),
+        });
+
+        if let Some(body) = body.filter(|body| !body.is_empty()) {
+            har_request["postData"] = json!({
+                "mimeType": mime_type(&request.headers),
+                "text": har::text(body),
+            });
+        }
+
+        let time = elapsed.as_secs_f64() * 1000.0;
+        let status = response.status();
+        let entry = json!({
+            "startedDateTime": started.to_rfc3339(),
+            "time": time,
+            "request": har_request,
+            "response": {
+                "status": status.as_u16(),
+                "statusText": status.canonical_reason().unwrap_or(""),
+                "httpVersion": format!("{:?}", response.version()),
+                "cookies": [],
+                "headers": har::header_records(response.headers()),
+                "content": { "size": -1, "mimeType": mime_type(response.headers()) },
+                "redirectURL": "",
+                "headersSize": -1,
+                "bodySize": -1,
+            },
+            "cache": {},
+            "timings": { "send": 0, "wait": time, "receive": 0 },
+        });
+
+        let mut entries = self.lock();
+        entries.push(entry);
+        entries.len() - 1
+    }
+
+    /// Records the body of the response of the entry at `index`.
+    pub(crate) fn record_body(&self, index: usize, body: &[u8]) {
+        if let Some(entry) = self.lock().get_mut(index) {
+            let response = &mut entry["response"];
+            response["bodySize"] = json!(body.len());
+            response["content"]["size"] = json!(body.len());
+            response["content"]["text"] = json!(har::text(body));
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Value>> {
+        // entries remain consistent when a test panics while they are locked
+        match self.entries.lock() {
+            Ok(entries) => entries,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+fn mime_type(headers: &hyper::HeaderMap) -> String {
+    headers
+        .get(CONTENT_TYPE)
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+        .unwrap_or_default()
+}
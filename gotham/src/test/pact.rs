@@ -0,0 +1,727 @@
+//! Replays consumer-driven contracts in the [Pact](https://docs.pact.io) format against a test
+//! server, shared between the tls::test and plain::test modules.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::Write;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context};
+use hyper::client::connect::Connect;
+use hyper::header::{HeaderName, HeaderValue, CONTENT_TYPE};
+use hyper::{Body, HeaderMap, Method};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use regex::Regex;
+use serde_json::{Map, Value};
+
+use super::{Server, TestClient};
+
+/// A contract between an API consumer and this crate's application as the provider, loaded from
+/// a Pact file (specification versions 2 and 3).
+///
+/// Verifying the contract replays the request of every interaction against a `TestServer` and
+/// checks that the response is compatible with the expected one:
+///
+/// * the status codes are equal,
+/// * every expected header is present with an equal value, ignoring whitespace after commas,
+/// * every field of an expected JSON body is present with an equal value, while additional fields
+///   of objects are allowed, and
+/// * `type`, `regex`, `integer`, `decimal` and `equality` matching rules are applied to the body
+///   and headers.
+///
+/// ```rust
+/// # extern crate gotham;
+/// #
+/// # use gotham::router::builder::*;
+/// # use gotham::state::State;
+/// # use gotham::test::{PactContract, TestServer};
+/// #
+/// fn user(state: State) -> (State, (mime::Mime, String)) {
+///     let body = r#"{"id":7,"name":"ferris","admin":false}"#.to_owned();
+///     (state, (mime::APPLICATION_JSON, body))
+/// }
+///
+/// # fn main() {
+/// let contract = PactContract::from_json(
+///     r#"{
+///         "consumer": { "name": "web" },
+///         "provider": { "name": "users" },
+///         "interactions": [{
+///             "description": "a request for a user",
+///             "request": { "method": "GET", "path": "/users/7" },
+///             "response": {
+///                 "status": 200,
+///                 "headers": { "Content-Type": "application/json" },
+///                 "body": { "id": 7, "name": "alice" },
+///                 "matchingRules": { "$.body.name": { "match": "type" } }
+///             }
+///         }],
+///         "metadata": { "pactSpecification": { "version": "2.0.0" } }
+///     }"#,
+/// )
+/// .unwrap();
+///
+/// let router = build_simple_router(|route| {
+///     route.get("/users/:id").to(user);
+/// });
+///
+/// let test_server = TestServer::new(router).unwrap();
+/// contract.verify(&test_server.client()).unwrap();
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct PactContract {
+    consumer: String,
+    provider: String,
+    interactions: Vec<PactInteraction>,
+}
+
+impl PactContract {
+    /// Parses a contract from the contents of a Pact file.
+    pub fn from_json(json: &str) -> anyhow::Result<PactContract> {
+        let pact: Value = serde_json::from_str(json).context("invalid Pact file")?;
+
+        let interactions = pact["interactions"]
+            .as_array()
+            .ok_or_else(|| anyhow!("Pact file has no interactions"))?
+            .iter()
+            .map(PactInteraction::parse)
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(PactContract {
+            consumer: name(&pact["consumer"]),
+            provider: name(&pact["provider"]),
+            interactions,
+        })
+    }
+
+    /// Reads and parses the Pact file at `path`.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<PactContract> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read Pact file {}", path.display()))?;
+        PactContract::from_json(&json)
+    }
+
+    /// Returns the name of the consumer which published the contract.
+    pub fn consumer(&self) -> &str {
+        &self.consumer
+    }
+
+    /// Returns the name of the provider the contract was published for.
+    pub fn provider(&self) -> &str {
+        &self.provider
+    }
+
+    /// Returns the interactions of the contract, in the order of the Pact file.
+    pub fn interactions(&self) -> &[PactInteraction] {
+        &self.interactions
+    }
+
+    /// Verifies every interaction against the server of `client`. The error lists the mismatches
+    /// of all failed interactions.
+    ///
+    /// Interactions which depend on provider states should be verified one by one with
+    /// `PactInteraction::verify`, after preparing the state they name.
+    pub fn verify<TS, C>(&self, client: &TestClient<TS, C>) -> anyhow::Result<()>
+    where
+        TS: Server + 'static,
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        let failures: Vec<String> = self
+            .interactions
+            .iter()
+            .filter_map(|interaction| interaction.verify(client).err())
+            .map(|err| format!("{:#}", err))
+            .collect();
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            bail!(
+                "contract between {} and {} failed verification:\n{}",
+                self.consumer,
+                self.provider,
+                failures.join("\n")
+            )
+        }
+    }
+}
+
+/// A single request and the response expected for it, see `PactContract`.
+#[derive(Clone, Debug)]
+pub struct PactInteraction {
+    description: String,
+    provider_states: Vec<String>,
+    method: Method,
+    path_and_query: String,
+    request_headers: Vec<(HeaderName, HeaderValue)>,
+    request_body: Option<Value>,
+    status: u16,
+    response_headers: Vec<(HeaderName, String)>,
+    response_body: Option<Value>,
+    rules: MatchingRules,
+}
+
+impl PactInteraction {
+    /// Returns the description of the interaction.
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Returns the names of the provider states the interaction expects, which the server has to
+    /// be prepared for before the interaction is verified.
+    pub fn provider_states(&self) -> &[String] {
+        &self.provider_states
+    }
+
+    /// Replays the request against the server of `client` and checks that the response is
+    /// compatible with the expected one. The error lists all mismatches.
+    pub fn verify<TS, C>(&self, client: &TestClient<TS, C>) -> anyhow::Result<()>
+    where
+        TS: Server + 'static,
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        let uri = format!("http://localhost{}", self.path_and_query);
+        let mut request = client.build_request(self.method.clone(), uri.as_str());
+
+        for (name, value) in &self.request_headers {
+            request.headers_mut().append(name.clone(), value.clone());
+        }
+
+        if let Some(body) = &self.request_body {
+            if let Value::String(text) = body {
+                *request.body_mut() = Body::from(text.clone());
+            } else {
+                *request.body_mut() = Body::from(body.to_string());
+                if !request.headers().contains_key(CONTENT_TYPE) {
+                    let json = HeaderValue::from_static("application/json");
+                    request.headers_mut().insert(CONTENT_TYPE, json);
+                }
+            }
+        }
+
+        let response = request
+            .perform()
+            .with_context(|| format!("interaction '{}' failed", self.description))?;
+
+        let mut mismatches = Vec::new();
+
+        if response.status().as_u16() != self.status {
+            mismatches.push(format!(
+                "expected status {} but was {}",
+                self.status,
+                response.status().as_u16()
+            ));
+        }
+
+        self.check_headers(response.headers(), &mut mismatches);
+
+        let body = response.read_body()?;
+        if let Some(expected) = &self.response_body {
+            self.check_body(expected, &body, &mut mismatches);
+        }
+
+        if mismatches.is_empty() {
+            return Ok(());
+        }
+
+        let mut message = format!("interaction '{}' did not match:", self.description);
+        for mismatch in mismatches {
+            let _ = write!(message, "\n  - {}", mismatch);
+        }
+        Err(anyhow!(message))
+    }
+
+    fn parse(interaction: &Value) -> anyhow::Result<PactInteraction> {
+        let description = interaction["description"]
+            .as_str()
+            .unwrap_or_default()
+            .to_owned();
+        let context = || format!("invalid interaction '{}'", description);
+
+        let provider_states = match &interaction["providerStates"] {
+            Value::Array(states) => states.iter().map(name).collect(),
+            _ => interaction["providerState"]
+                .as_str()
+                .map(|state| vec![state.to_owned()])
+                .unwrap_or_default(),
+        };
+
+        let request = &interaction["request"];
+        let method = request["method"]
+            .as_str()
+            .unwrap_or("GET")
+            .to_ascii_uppercase()
+            .parse::<Method>()
+            .with_context(context)?;
+
+        let mut path_and_query = request["path"].as_str().unwrap_or("/").to_owned();
+        let query = query_string(&request["query"]);
+        if !query.is_empty() {
+            path_and_query.push('?');
+            path_and_query.push_str(&query);
+        }
+
+        let request_headers = headers(&request["headers"])
+            .into_iter()
+            .map(|(name, value)| Ok((name, HeaderValue::from_str(&value)?)))
+            .collect::<anyhow::Result<_>>()
+            .with_context(context)?;
+
+        let response = &interaction["response"];
+        let status = match &response["status"] {
+            Value::Null => 200,
+            status => status
+                .as_u64()
+                .and_then(|status| u16::try_from(status).ok())
+                .ok_or_else(|| anyhow!("invalid status {}", status))
+                .with_context(context)?,
+        };
+
+        Ok(PactInteraction {
+            description: description.clone(),
+            provider_states,
+            method,
+            path_and_query,
+            request_headers,
+            request_body: body(&request["body"]),
+            status,
+            response_headers: headers(&response["headers"]),
+            response_body: body(&response["body"]),
+            rules: MatchingRules::parse(&response["matchingRules"]).with_context(context)?,
+        })
+    }
+
+    fn check_headers(&self, actual: &HeaderMap, mismatches: &mut Vec<String>) {
+        for (name, expected) in &self.response_headers {
+            let actual = match actual.get(name).and_then(|value| value.to_str().ok()) {
+                Some(actual) => actual,
+                None => {
+                    mismatches.push(format!("expected header {} to be present", name));
+                    continue;
+                }
+            };
+
+            let matchers = self.rules.headers.get(name.as_str());
+            let matches = match matchers {
+                Some(matchers) => matchers.iter().all(|matcher| match matcher {
+                    Matcher::Regex(regex) => regex.is_match(actual),
+                    _ => normalize_header(actual) == normalize_header(expected),
+                }),
+                None => normalize_header(actual) == normalize_header(expected),
+            };
+
+            if !matches {
+                mismatches.push(format!(
+                    "expected header {} to match '{}' but was '{}'",
+                    name, expected, actual
+                ));
+            }
+        }
+    }
+
+    fn check_body(&self, expected: &Value, body: &[u8], mismatches: &mut Vec<String>) {
+        match serde_json::from_slice::<Value>(body) {
+            Ok(actual) => compare(expected, &actual, "$", false, &self.rules, mismatches),
+            Err(_) => match expected {
+                Value::String(text) if text.as_bytes() == body => {}
+                Value::String(text) => mismatches.push(format!(
+                    "expected body '{}' but was '{}'",
+                    text,
+                    String::from_utf8_lossy(body)
+                )),
+                _ => mismatches.push(String::from("expected a JSON body")),
+            },
+        }
+    }
+}
+
+/// The matching rules of an expected response, keyed by the JSON path of the body field or the
+/// lowercased header name.
+#[derive(Clone, Debug, Default)]
+struct MatchingRules {
+    body: HashMap<String, Vec<Matcher>>,
+    headers: HashMap<String, Vec<Matcher>>,
+}
+
+#[derive(Clone, Debug)]
+enum Matcher {
+    Type {
+        min: Option<usize>,
+        max: Option<usize>,
+    },
+    Regex(Regex),
+    Integer,
+    Decimal,
+    Equality,
+}
+
+impl MatchingRules {
+    fn parse(rules: &Value) -> anyhow::Result<MatchingRules> {
+        let mut parsed = MatchingRules::default();
+        let rules = match rules.as_object() {
+            Some(rules) => rules,
+            None => return Ok(parsed),
+        };
+
+        for (key, rule) in rules {
+            // version 2 keys rules by a path into the whole response
+            if let Some(path) = key.strip_prefix("$.body") {
+                parsed.body.insert(format!("${}", path), matchers(rule)?);
+            } else if let Some(name) = key.strip_prefix("$.headers.") {
+                parsed
+                    .headers
+                    .insert(name.to_ascii_lowercase(), matchers(rule)?);
+            } else if let Some(category) = rule.as_object() {
+                // version 3 groups rules by category
+                for (path, rule) in category {
+                    let matchers = matchers(rule)?;
+                    match key.as_str() {
+                        "body" => parsed.body.insert(path.clone(), matchers),
+                        "header" => parsed.headers.insert(path.to_ascii_lowercase(), matchers),
+                        _ => None,
+                    };
+                }
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    /// Returns the matchers for the body field at `path`, either by the exact path or with all
+    /// array indices replaced by `[*]`.
+    fn body(&self, path: &str) -> Option<&[Matcher]> {
+        self.body
+            .get(path)
+            .or_else(|| self.body.get(&wildcard_indices(path)))
+            .map(Vec::as_slice)
+    }
+}
+
+/// Replaces the array indices of a JSON path with `[*]`, e.g. `$.users[0].id` by `$.users[*].id`.
+fn wildcard_indices(path: &str) -> String {
+    let mut wildcard = String::with_capacity(path.len());
+    for (index, part) in path.split('[').enumerate() {
+        if index > 0 {
+            match part.find(']') {
+                Some(end) if part[..end].bytes().all(|b| b.is_ascii_digit()) => {
+                    wildcard.push_str("[*");
+                    wildcard.push_str(&part[end..]);
+                    continue;
+                }
+                _ => wildcard.push('['),
+            }
+        }
+        wildcard.push_str(part);
+    }
+    wildcard
+}
+
+fn matchers(rule: &Value) -> anyhow::Result<Vec<Matcher>> {
+    match &rule["matchers"] {
+        Value::Array(matchers) => matchers.iter().map(matcher).collect(),
+        _ => Ok(vec![matcher(rule)?]),
+    }
+}
+
+fn matcher(rule: &Value) -> anyhow::Result<Matcher> {
+    let bound = |key: &str| rule[key].as_u64().map(|bound| bound as usize);
+
+    match rule["match"].as_str() {
+        Some("type") => Ok(Matcher::Type {
+            min: bound("min"),
+            max: bound("max"),
+        }),
+        Some("regex") => {
+            let regex = rule["regex"].as_str().unwrap_or_default();
+            Ok(Matcher::Regex(Regex::new(&format!("^(?:{})$", regex))?))
+        }
+        Some("integer") => Ok(Matcher::Integer),
+        Some("decimal") => Ok(Matcher::Decimal),
+        Some("equality") => Ok(Matcher::Equality),
+        // version 2 allows type rules with only a bound
+        None if rule.get("min").is_some() || rule.get("max").is_some() => Ok(Matcher::Type {
+            min: bound("min"),
+            max: bound("max"),
+        }),
+        _ => bail!("unsupported matching rule {}", rule),
+    }
+}
+
+/// Compares `actual` against `expected` at `path`, recording all differences in `mismatches`.
+/// With `by_type`, values only have to be of the same type, as below a `type` matcher.
+fn compare(
+    expected: &Value,
+    actual: &Value,
+    path: &str,
+    mut by_type: bool,
+    rules: &MatchingRules,
+    mismatches: &mut Vec<String>,
+) {
+    let mut bounds = (None, None);
+
+    for matcher in rules.body(path).unwrap_or_default() {
+        match matcher {
+            Matcher::Type { min, max } => {
+                by_type = true;
+                bounds = (*min, *max);
+            }
+            Matcher::Equality => by_type = false,
+            Matcher::Regex(regex) => {
+                let text = match actual {
+                    Value::String(text) => text.clone(),
+                    Value::Number(_) | Value::Bool(_) => actual.to_string(),
+                    _ => String::new(),
+                };
+                if !regex.is_match(&text) {
+                    mismatches.push(format!(
+                        "{} expected to match {} but was {}",
+                        path, regex, actual
+                    ));
+                }
+                return;
+            }
+            Matcher::Integer => {
+                if !actual.is_i64() && !actual.is_u64() {
+                    mismatches.push(format!("{} expected an integer but was {}", path, actual));
+                }
+                return;
+            }
+            Matcher::Decimal => {
+                if !actual.is_number() {
+                    mismatches.push(format!("{} expected a decimal but was {}", path, actual));
+                }
+                return;
+            }
+        }
+    }
+
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            compare_objects(expected, actual, path, by_type, rules, mismatches)
+        }
+        (Value::Array(expected), Value::Array(actual)) if by_type => {
+            let (min, max) = bounds;
+            if min.is_some_and(|min| actual.len() < min) {
+                mismatches.push(format!("{} expected at least {} items", path, min.unwrap()));
+            }
+            if max.is_some_and(|max| actual.len() > max) {
+                mismatches.push(format!("{} expected at most {} items", path, max.unwrap()));
+            }
+            if let Some(template) = expected.first() {
+                for (index, actual) in actual.iter().enumerate() {
+                    let path = format!("{}[{}]", path, index);
+                    compare(template, actual, &path, true, rules, mismatches);
+                }
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) => {
+            if expected.len() != actual.len() {
+                mismatches.push(format!(
+                    "{} expected {} items but was {}",
+                    path,
+                    expected.len(),
+                    actual.len()
+                ));
+            }
+            for (index, (expected, actual)) in expected.iter().zip(actual).enumerate() {
+                let path = format!("{}[{}]", path, index);
+                compare(expected, actual, &path, false, rules, mismatches);
+            }
+        }
+        (expected, actual) if by_type => {
+            if json_type(expected) != json_type(actual) {
+                mismatches.push(format!(
+                    "{} expected a {} but was {}",
+                    path,
+                    json_type(expected),
+                    actual
+                ));
+            }
+        }
+        (expected, actual) => {
+            if expected != actual {
+                mismatches.push(format!("{} expected {} but was {}", path, expected, actual));
+            }
+        }
+    }
+}
+
+fn compare_objects(
+    expected: &Map<String, Value>,
+    actual: &Map<String, Value>,
+    path: &str,
+    by_type: bool,
+    rules: &MatchingRules,
+    mismatches: &mut Vec<String>,
+) {
+    for (key, expected) in expected {
+        let path = format!("{}.{}", path, key);
+        match actual.get(key) {
+            Some(actual) => compare(expected, actual, &path, by_type, rules, mismatches),
+            None => mismatches.push(format!("{} expected to be present", path)),
+        }
+    }
+}
+
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Returns the name of a pacticipant or provider state, which is either a string or an object
+/// with a name.
+fn name(value: &Value) -> String {
+    value
+        .as_str()
+        .or_else(|| value["name"].as_str())
+        .unwrap_or_default()
+        .to_owned()
+}
+
+/// Renders the query of a request, which is a string in version 2 and a map of value lists in
+/// version 3.
+fn query_string(query: &Value) -> String {
+    match query {
+        Value::String(query) => query.clone(),
+        Value::Object(params) => params
+            .iter()
+            .flat_map(|(key, values)| {
+                let values = match values {
+                    Value::Array(values) => values.iter().filter_map(Value::as_str).collect(),
+                    value => value.as_str().into_iter().collect::<Vec<_>>(),
+                };
+                values.into_iter().map(move |value| {
+                    format!(
+                        "{}={}",
+                        utf8_percent_encode(key, NON_ALPHANUMERIC),
+                        utf8_percent_encode(value, NON_ALPHANUMERIC)
+                    )
+                })
+            })
+            .collect::<Vec<_>>()
+            .join("&"),
+        _ => String::new(),
+    }
+}
+
+fn headers(headers: &Value) -> Vec<(HeaderName, String)> {
+    headers
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter_map(|(name, value)| {
+            let name = HeaderName::from_bytes(name.as_bytes()).ok()?;
+            let value = match value {
+                Value::Array(values) => values
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                value => value.as_str()?.to_owned(),
+            };
+            Some((name, value))
+        })
+        .collect()
+}
+
+fn body(body: &Value) -> Option<Value> {
+    match body {
+        Value::Null => None,
+        body => Some(body.clone()),
+    }
+}
+
+fn normalize_header(value: &str) -> String {
+    value
+        .split(',')
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::router::builder::{build_simple_router, DefineSingleRoute, DrawRoutes};
+    use crate::state::State;
+    use crate::test::TestServer;
+
+    fn users(state: State) -> (State, (mime::Mime, String)) {
+        let body = r#"{"users":[{"id":1,"name":"ann"},{"id":2,"name":"bob"}],"total":2}"#;
+        (state, (mime::APPLICATION_JSON, body.to_owned()))
+    }
+
+    fn contract(response: &str) -> PactContract {
+        PactContract::from_json(&format!(
+            r#"{{
+                "consumer": {{ "name": "web" }},
+                "provider": {{ "name": "users" }},
+                "interactions": [{{
+                    "description": "a request for users",
+                    "providerStates": [{{ "name": "two users exist" }}],
+                    "request": {{ "method": "GET", "path": "/users", "query": {{ "page": ["1"] }} }},
+                    "response": {}
+                }}]
+            }}"#,
+            response
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn verifies_interactions() {
+        let router = build_simple_router(|route| {
+            route.get("/users").to(users);
+        });
+        let test_server = TestServer::new(router).unwrap();
+        let client = test_server.client();
+
+        let compatible = contract(
+            r#"{
+                "status": 200,
+                "headers": { "Content-Type": "application/json" },
+                "body": { "users": [{ "id": 9, "name": "zed" }] },
+                "matchingRules": {
+                    "body": {
+                        "$.users": { "matchers": [{ "match": "type", "min": 1 }] },
+                        "$.users[*].name": { "matchers": [{ "match": "regex", "regex": "[a-z]+" }] }
+                    }
+                }
+            }"#,
+        );
+        assert_eq!(compatible.consumer(), "web");
+        assert_eq!(
+            compatible.interactions()[0].provider_states(),
+            &["two users exist"]
+        );
+        compatible.verify(&client).unwrap();
+
+        let incompatible =
+            contract(r#"{ "status": 201, "body": { "users": [{ "id": 1 }], "next": null } }"#);
+        let message = format!("{:#}", incompatible.verify(&client).unwrap_err());
+        assert!(
+            message.contains("expected status 201 but was 200"),
+            "{}",
+            message
+        );
+        assert!(
+            message.contains("$.users expected 1 items but was 2"),
+            "{}",
+            message
+        );
+        assert!(
+            message.contains("$.next expected to be present"),
+            "{}",
+            message
+        );
+    }
+}
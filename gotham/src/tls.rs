@@ -1,12 +1,13 @@
 use futures::prelude::*;
+use hyper::server::conn::Http;
 use log::{error, info};
-use std::net::ToSocketAddrs;
+use std::net::{SocketAddr, ToSocketAddrs};
 use std::sync::Arc;
 
 use tokio::net::TcpListener;
 use tokio_rustls::{rustls, TlsAcceptor};
 
-use super::{bind_server, new_runtime, tcp_listener};
+use super::{bind_server_with_protocol, new_runtime, tcp_listeners};
 
 use super::handler::NewHandler;
 
@@ -49,22 +50,48 @@ where
     NH: NewHandler + 'static,
     A: ToSocketAddrs + 'static + Send,
 {
-    let listener = tcp_listener(addr).map_err(|_| ()).await?;
-    let addr = listener.local_addr().unwrap();
+    init_server_with_ready(addr, new_handler, tls_config, |_| ()).await
+}
+
+/// Returns a `Future` used to spawn a Gotham application like `init_server`, calling `ready`
+/// with the bound addresses once all of them are bound, before connections are accepted.
+pub async fn init_server_with_ready<NH, A, F>(
+    addr: A,
+    new_handler: NH,
+    tls_config: rustls::ServerConfig,
+    ready: F,
+) -> Result<(), ()>
+where
+    NH: NewHandler + 'static,
+    A: ToSocketAddrs + 'static + Send,
+    F: FnOnce(&[SocketAddr]),
+{
+    let listeners = tcp_listeners(addr, None)
+        .map_err(|e| error!(target: "gotham::start", "{}", e))
+        .await?;
+
+    let addrs = listeners
+        .iter()
+        .map(|listener| listener.local_addr())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| error!(target: "gotham::start", "{}", e))?;
 
-    info!(
-    target: "gotham::start",
-    " Gotham listening on http://{}",
-    addr
-    );
+    for addr in &addrs {
+        info!(
+        target: "gotham::start",
+        " Gotham listening on https://{}",
+        addr
+        );
+    }
+    ready(&addrs);
 
-    bind_server_rustls(listener, new_handler, tls_config)
+    bind_server_rustls(listeners, new_handler, tls_config)
         .map_err(|_| ())
         .await
 }
 
 async fn bind_server_rustls<NH>(
-    listener: TcpListener,
+    listeners: Vec<TcpListener>,
     new_handler: NH,
     tls_config: rustls::ServerConfig,
 ) -> Result<(), ()>
@@ -72,10 +99,10 @@ where
     NH: NewHandler + 'static,
 {
     let tls = TlsAcceptor::from(Arc::new(tls_config));
-    bind_server(listener, new_handler, move |socket| {
+    let wrap = move |socket| {
         tls.accept(socket).map_err(|e| {
             error!(target: "gotham::tls", "TLS handshake error: {:?}", e);
         })
-    })
-    .await
+    };
+    bind_server_with_protocol(listeners, new_handler, wrap, Http::new()).await
 }
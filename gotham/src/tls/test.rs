@@ -35,14 +35,18 @@ use tokio_rustls::{
     TlsConnector,
 };
 
+use crate::clock::SharedClock;
 use crate::handler::NewHandler;
 
-use crate::test::{self, TestClient};
+use crate::state::request_id::RequestIdGenerator;
+use crate::test::{self, Determinism, HarRecorder, TestClient};
 
 struct TestServerData {
     addr: SocketAddr,
     timeout: u64,
     runtime: RwLock<Runtime>,
+    har: RwLock<Option<HarRecorder>>,
+    determinism: Determinism,
 }
 
 /// The `TestServer` type, which is used as a harness when writing test cases for Hyper services
@@ -100,6 +104,14 @@ impl test::Server for TestServer {
             .expect("unable to acquire write lock")
             .block_on(future)
     }
+
+    fn har_recorder(&self) -> Option<HarRecorder> {
+        self.data
+            .har
+            .read()
+            .expect("unable to acquire read lock")
+            .clone()
+    }
 }
 
 impl TestServer {
@@ -127,6 +139,8 @@ impl TestServer {
         // TODO: Fix this into an async flow
         let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0".parse::<SocketAddr>()?))?;
         let addr = listener.local_addr()?;
+        let determinism = Determinism::default();
+        let new_handler = determinism.wrap(new_handler);
 
         let mut cfg = rustls::ServerConfig::new(NoClientAuth::new());
         let mut cert_file = BufReader::new(&include_bytes!("cert.pem")[..]);
@@ -135,13 +149,15 @@ impl TestServer {
         let mut keys = pkcs8_private_keys(&mut key_file).unwrap();
         cfg.set_single_cert(certs, keys.remove(0))?;
 
-        let service_stream = super::bind_server_rustls(listener, new_handler, cfg);
+        let service_stream = super::bind_server_rustls(vec![listener], new_handler, cfg);
         runtime.spawn(service_stream); // Ignore the result
 
         let data = TestServerData {
             addr,
             timeout,
             runtime: RwLock::new(runtime),
+            har: RwLock::new(None),
+            determinism,
         };
 
         Ok(TestServer {
@@ -149,6 +165,43 @@ impl TestServer {
         })
     }
 
+    /// Generates the ids of subsequent requests which don't provide an `X-Request-ID` header with
+    /// `generator`, instead of random UUIDs, so that they are reproducible.
+    pub fn with_request_ids(self, generator: RequestIdGenerator) -> TestServer {
+        self.data.determinism.set_request_ids(generator);
+        self
+    }
+
+    /// Puts a `SeededRng` created from `seed` into the `State` of subsequent requests, so that
+    /// random values generated for them by the application are reproducible.
+    ///
+    /// Session identifiers are only seeded through `NewSessionMiddleware::with_seeded_rng`.
+    pub fn with_seed(self, seed: u64) -> TestServer {
+        self.data.determinism.set_seed(seed);
+        self
+    }
+
+    /// Puts `clock` into the `State` of subsequent requests, so that the framework reads the
+    /// current time from it, e.g. from a `ManualClock` controlled by the test.
+    pub fn with_clock(self, clock: SharedClock) -> TestServer {
+        self.data.determinism.set_clock(clock);
+        self
+    }
+
+    /// Records the requests performed by the clients of this `TestServer` from now on, along with
+    /// their responses, so that they can be retrieved as a HAR log through `har`.
+    ///
+    /// Recording buffers every request body of a known size and every response body which is
+    /// read, so it is disabled unless requested.
+    pub fn with_har(self) -> TestServer {
+        self.data
+            .har
+            .write()
+            .expect("unable to acquire write lock")
+            .get_or_insert_with(HarRecorder::default);
+        self
+    }
+
     /// Returns a client connected to the `TestServer`. The transport is handled internally, and
     /// the server will see a default socket address of `127.0.0.1:10000` as the source address for
     /// the connection.
@@ -170,6 +223,19 @@ impl TestServer {
             .spawn(fut);
     }
 
+    /// Returns the requests performed by the clients of this `TestServer` so far, and their
+    /// responses, as a [HAR](https://w3c.github.io/web-performance/specs/HAR/Overview.html) log.
+    ///
+    /// The log can be written to a file when a test fails, and opened in the developer tools of a
+    /// browser or shared as a reproduction. Response bodies are only included once they have been
+    /// read through the `TestResponse`. The log is empty unless recording was enabled through
+    /// `with_har`.
+    pub fn har(&self) -> serde_json::Value {
+        test::Server::har_recorder(self)
+            .unwrap_or_default()
+            .to_har()
+    }
+
     /// Returns a client connected to the `TestServer`. The transport is handled internally, and
     /// the server will see `client_addr` as the source address for the connection. The
     /// `client_addr` can be any valid `SocketAddr`, and need not be contactable.
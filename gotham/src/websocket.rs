@@ -0,0 +1,574 @@
+//! Serves WebSocket connections from Gotham handlers. Requires the `websocket` feature.
+//!
+//! An `Upgrade` policy checks the `Origin`, negotiates the subprotocol and authenticates upgrade
+//! requests before the `101 Switching Protocols` handshake, rejecting them with `403 Forbidden`,
+//! so that WebSocket endpoints don't bypass the authentication of the application. Upgraded
+//! connections are served by `serve`, within the message size, rate and keep-alive `Limits` of
+//! the policy, and closed with a close frame once the server shuts down and the `Drain` token is
+//! triggered.
+//!
+//! ```rust
+//! # extern crate gotham;
+//! #
+//! # use futures::prelude::*;
+//! # use gotham::handler::HandlerFuture;
+//! # use gotham::hyper::upgrade::OnUpgrade;
+//! # use gotham::hyper::{Body, HeaderMap, Response};
+//! # use gotham::state::{FromState, State};
+//! # use gotham::websocket::{self, Drain, Limits, Upgrade};
+//! # use std::pin::Pin;
+//! # use std::time::Duration;
+//! #
+//! fn echo(mut state: State, drain: Drain) -> Pin<Box<HandlerFuture>> {
+//!     let headers = HeaderMap::take_from(&mut state);
+//!     let on_upgrade = OnUpgrade::try_take_from(&mut state);
+//!     let limits = Limits::new().rate_limit(20, Duration::from_secs(1));
+//!     let upgrade = Upgrade::new()
+//!         .allow_origin("https://example.com")
+//!         .limits(limits.clone());
+//!
+//!     async move {
+//!         let on_upgrade = match on_upgrade {
+//!             Some(on_upgrade) if websocket::requested(&headers) => on_upgrade,
+//!             _ => return Ok((state, Response::new(Body::from("not a websocket")))),
+//!         };
+//!
+//!         match upgrade.accept(headers, on_upgrade).await {
+//!             Ok((response, ws)) => {
+//!                 tokio::spawn(async move {
+//!                     if let Ok(ws) = ws.await {
+//!                         let _ = websocket::serve(ws, &limits, &drain, Some).await;
+//!                     }
+//!                 });
+//!                 Ok((state, response))
+//!             }
+//!             Err(rejection) => Ok((state, rejection)),
+//!         }
+//!     }
+//!     .boxed()
+//! }
+//! #
+//! # fn main() {
+//! #     let _ = echo;
+//! # }
+//! ```
+
+use std::borrow::Cow;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::Either;
+use futures::prelude::*;
+use hyper::header::{
+    HeaderValue, CONNECTION, ORIGIN, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY,
+    SEC_WEBSOCKET_PROTOCOL, UPGRADE,
+};
+use hyper::upgrade::{OnUpgrade, Upgraded};
+use hyper::{Body, HeaderMap, Response, StatusCode};
+use sha1::Sha1;
+use tokio::sync::watch;
+use tokio::time::{self, Instant, Interval};
+use tokio_tungstenite::tungstenite;
+use tungstenite::protocol::{CloseFrame, WebSocketConfig};
+
+pub use tokio_tungstenite::WebSocketStream;
+pub use tungstenite::protocol::frame::coding::CloseCode;
+pub use tungstenite::protocol::{Message, Role};
+pub use tungstenite::Error;
+
+const PROTO_WEBSOCKET: &str = "websocket";
+
+/// Check if a WebSocket upgrade was requested.
+pub fn requested(headers: &HeaderMap) -> bool {
+    headers.get(UPGRADE) == Some(&HeaderValue::from_static(PROTO_WEBSOCKET))
+}
+
+/// The future returned by `Upgrade::accept`, which resolves into a websocket object once the
+/// connection has been upgraded.
+pub type WebSocketFuture =
+    Pin<Box<dyn Future<Output = Result<WebSocketStream<Upgraded>, hyper::Error>> + Send>>;
+
+/// The future returned by an authentication hook, resolving to `true` if the upgrade is allowed.
+pub type AuthFuture = Pin<Box<dyn Future<Output = bool> + Send>>;
+
+type AuthHook = Arc<dyn Fn(&HeaderMap) -> AuthFuture + Send + Sync>;
+
+/// Policy applied to WebSocket upgrade requests before the `101 Switching Protocols` handshake.
+///
+/// Requests are rejected with `403 Forbidden` when the `Origin` is not allowed or the
+/// authentication hook fails, so WebSocket endpoints go through the same checks as the rest of
+/// the application.
+#[derive(Clone, Default)]
+pub struct Upgrade {
+    allowed_origins: Option<Vec<String>>,
+    protocols: Vec<String>,
+    auth: Option<AuthHook>,
+    limits: Limits,
+}
+
+impl Upgrade {
+    /// Creates a policy which accepts every upgrade request.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows upgrade requests from the given origin, e.g. `https://example.com`.
+    ///
+    /// Once an origin has been allowed, requests from other origins are rejected. Requests
+    /// without an `Origin` header, which are not sent by browsers, are always allowed.
+    pub fn allow_origin(mut self, origin: &str) -> Self {
+        self.allowed_origins
+            .get_or_insert_with(Vec::new)
+            .push(origin.to_owned());
+        self
+    }
+
+    /// Sets the subprotocols supported by the server, in order of preference.
+    pub fn protocols(mut self, protocols: &[&str]) -> Self {
+        self.protocols = protocols.iter().map(|p| (*p).to_owned()).collect();
+        self
+    }
+
+    /// Sets an asynchronous hook which authenticates the upgrade request from its headers,
+    /// e.g. by looking up a session cookie.
+    pub fn authenticate<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&HeaderMap) -> AuthFuture + Send + Sync + 'static,
+    {
+        self.auth = Some(Arc::new(hook));
+        self
+    }
+
+    /// Sets the message and frame size limits of upgraded connections.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Accepts a WebSocket upgrade request if it satisfies this policy.
+    ///
+    /// Returns the HTTP response, and a future that eventually resolves into a websocket object,
+    /// or the HTTP response rejecting the upgrade.
+    pub async fn accept(
+        &self,
+        headers: HeaderMap,
+        on_upgrade: OnUpgrade,
+    ) -> Result<(Response<Body>, WebSocketFuture), Response<Body>> {
+        if !self.origin_allowed(&headers) {
+            return Err(rejection(StatusCode::FORBIDDEN));
+        }
+
+        let mut res = response(&headers).map_err(|_| rejection(StatusCode::BAD_REQUEST))?;
+
+        if let Some(auth) = &self.auth {
+            if !auth(&headers).await {
+                return Err(rejection(StatusCode::FORBIDDEN));
+            }
+        }
+
+        if let Some(protocol) = self.select_protocol(&headers) {
+            res.headers_mut().insert(
+                SEC_WEBSOCKET_PROTOCOL,
+                HeaderValue::from_str(protocol).unwrap(),
+            );
+        }
+
+        Ok((res, upgrade(on_upgrade, self.limits.config()).boxed()))
+    }
+
+    fn origin_allowed(&self, headers: &HeaderMap) -> bool {
+        match (&self.allowed_origins, headers.get(ORIGIN)) {
+            (Some(allowed), Some(origin)) => allowed.iter().any(|allowed| origin == allowed),
+            _ => true,
+        }
+    }
+
+    fn select_protocol(&self, headers: &HeaderMap) -> Option<&str> {
+        let requested: Vec<&str> = headers
+            .get_all(SEC_WEBSOCKET_PROTOCOL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+
+        self.protocols
+            .iter()
+            .map(String::as_str)
+            .find(|protocol| requested.contains(protocol))
+    }
+}
+
+async fn upgrade(
+    on_upgrade: OnUpgrade,
+    config: WebSocketConfig,
+) -> Result<WebSocketStream<Upgraded>, hyper::Error> {
+    let upgraded = on_upgrade.await?;
+    Ok(WebSocketStream::from_raw_socket(upgraded, Role::Server, Some(config)).await)
+}
+
+/// Per-connection limits of a WebSocket.
+///
+/// Violations close the connection with the matching close code: `1009` for oversized messages,
+/// `1008` for clients exceeding the message rate and `1001` for clients which stopped answering
+/// keep-alive pings.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    max_message_size: Option<usize>,
+    max_frame_size: Option<usize>,
+    rate: Option<(u32, Duration)>,
+    keep_alive: Option<(Duration, Duration)>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        let config = WebSocketConfig::default();
+        Limits {
+            max_message_size: config.max_message_size,
+            max_frame_size: config.max_frame_size,
+            rate: None,
+            keep_alive: None,
+        }
+    }
+}
+
+impl Limits {
+    /// Creates the default limits of 64 MiB per message and 16 MiB per frame, without rate
+    /// limiting or keep-alive pings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum size of a (possibly fragmented) message.
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = Some(size);
+        self
+    }
+
+    /// Sets the maximum payload size of a single frame.
+    pub fn max_frame_size(mut self, size: usize) -> Self {
+        self.max_frame_size = Some(size);
+        self
+    }
+
+    /// Allows at most `messages` data messages from the client within every `period`.
+    pub fn rate_limit(mut self, messages: u32, period: Duration) -> Self {
+        self.rate = Some((messages, period));
+        self
+    }
+
+    /// Pings the client after every `interval`, and closes the connection when nothing was
+    /// received from it for `timeout`.
+    pub fn keep_alive(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.keep_alive = Some((interval, timeout));
+        self
+    }
+
+    fn config(&self) -> WebSocketConfig {
+        WebSocketConfig {
+            max_message_size: self.max_message_size,
+            max_frame_size: self.max_frame_size,
+            ..WebSocketConfig::default()
+        }
+    }
+}
+
+/// Counts the data messages received within the current rate limiting window.
+struct RateWindow {
+    start: Instant,
+    count: u32,
+}
+
+impl RateWindow {
+    fn new(start: Instant) -> Self {
+        RateWindow { start, count: 0 }
+    }
+
+    /// Records a message received at `now`, returning `false` if it exceeds the rate.
+    fn record(&mut self, now: Instant, (messages, period): (u32, Duration)) -> bool {
+        if now.duration_since(self.start) >= period {
+            self.start = now;
+            self.count = 0;
+        }
+
+        self.count += 1;
+        self.count <= messages
+    }
+}
+
+/// A cancellation token used to drain WebSocket connections when the server shuts down.
+///
+/// Once `start` has been called, handlers can observe the shutdown via `is_draining`, and every
+/// connection served by `serve` is closed with the configured close frame after the drain
+/// timeout, instead of being dropped abruptly.
+#[derive(Clone)]
+pub struct Drain {
+    trigger: Arc<watch::Sender<Option<Instant>>>,
+    started: watch::Receiver<Option<Instant>>,
+    timeout: Duration,
+    code: CloseCode,
+    reason: Cow<'static, str>,
+}
+
+impl Default for Drain {
+    fn default() -> Self {
+        let (trigger, started) = watch::channel(None);
+        Drain {
+            trigger: Arc::new(trigger),
+            started,
+            timeout: Duration::from_secs(10),
+            code: CloseCode::Away,
+            reason: Cow::Borrowed("server shutting down"),
+        }
+    }
+}
+
+impl Drain {
+    /// Creates a token which closes connections with `1001 Going Away` 10 seconds after the
+    /// shutdown has started.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the time connections are given to finish their work once the shutdown has started.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the close code and reason sent to clients when the drain timeout has elapsed.
+    pub fn close_frame<R>(mut self, code: CloseCode, reason: R) -> Self
+    where
+        R: Into<Cow<'static, str>>,
+    {
+        self.code = code;
+        self.reason = reason.into();
+        self
+    }
+
+    /// Starts the shutdown, notifying all connections sharing this token.
+    pub fn start(&self) {
+        if !self.is_draining() {
+            let _ = self.trigger.send(Some(Instant::now()));
+        }
+    }
+
+    /// Returns `true` once the shutdown has started.
+    pub fn is_draining(&self) -> bool {
+        self.started.borrow().is_some()
+    }
+
+    /// Resolves once the shutdown has started and the drain timeout has elapsed.
+    pub async fn drained(&self) {
+        let mut started = self.started.clone();
+        loop {
+            let at = *started.borrow();
+            if let Some(at) = at {
+                return time::sleep_until(at + self.timeout).await;
+            }
+            if started.changed().await.is_err() {
+                return future::pending().await;
+            }
+        }
+    }
+}
+
+/// Serves a WebSocket connection within the given limits.
+///
+/// Every text or binary message is passed to `handler`, and its reply, if any, is sent back to
+/// the client. Returns once the connection has been closed by either side, or by `drain`.
+pub async fn serve<S, H>(
+    mut stream: S,
+    limits: &Limits,
+    drain: &Drain,
+    mut handler: H,
+) -> Result<(), Error>
+where
+    S: Stream<Item = Result<Message, Error>> + Sink<Message, Error = Error> + Unpin,
+    H: FnMut(Message) -> Option<Message>,
+{
+    let mut rate = RateWindow::new(Instant::now());
+    let mut last_seen = Instant::now();
+    let mut ping = limits
+        .keep_alive
+        .map(|(interval, _)| time::interval_at(Instant::now() + interval, interval));
+
+    loop {
+        let message = match future::select(stream.next(), Box::pin(timer(&mut ping, drain))).await {
+            Either::Left((Some(message), _)) => message,
+            Either::Left((None, _)) => return Ok(()),
+            Either::Right((Timer::Drained, _)) => {
+                return close(stream, drain.code, drain.reason.clone()).await;
+            }
+            Either::Right((Timer::Ping, _)) => {
+                if let Some((_, timeout)) = limits.keep_alive {
+                    if last_seen.elapsed() >= timeout {
+                        return close(stream, CloseCode::Away, "keep-alive timeout").await;
+                    }
+                }
+                stream.send(Message::Ping(Vec::new())).await?;
+                continue;
+            }
+        };
+
+        let message = match message {
+            Ok(message) => message,
+            Err(Error::Capacity(_)) => {
+                return close(stream, CloseCode::Size, "message too big").await;
+            }
+            Err(Error::ConnectionClosed) => return Ok(()),
+            Err(error) => return Err(error),
+        };
+
+        last_seen = Instant::now();
+        if !(message.is_text() || message.is_binary()) {
+            // pings are answered and close frames acknowledged by tungstenite itself
+            continue;
+        }
+
+        if let Some(limit) = limits.rate {
+            if !rate.record(last_seen, limit) {
+                return close(stream, CloseCode::Policy, "rate limit exceeded").await;
+            }
+        }
+
+        if let Some(reply) = handler(message) {
+            match stream.send(reply).await {
+                Ok(()) => (),
+                // this error indicates a successfully closed connection
+                Err(Error::ConnectionClosed) => return Ok(()),
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+enum Timer {
+    Ping,
+    Drained,
+}
+
+async fn timer(ping: &mut Option<Interval>, drain: &Drain) -> Timer {
+    match future::select(Box::pin(tick(ping)), Box::pin(drain.drained())).await {
+        Either::Left(_) => Timer::Ping,
+        Either::Right(_) => Timer::Drained,
+    }
+}
+
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => future::pending().await,
+    }
+}
+
+async fn close<S, R>(mut stream: S, code: CloseCode, reason: R) -> Result<(), Error>
+where
+    S: Sink<Message, Error = Error> + Unpin,
+    R: Into<Cow<'static, str>>,
+{
+    let frame = CloseFrame {
+        code,
+        reason: reason.into(),
+    };
+
+    match stream.send(Message::Close(Some(frame))).await {
+        Ok(()) | Err(Error::ConnectionClosed) => Ok(()),
+        Err(error) => Err(error),
+    }
+}
+
+fn rejection(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn response(headers: &HeaderMap) -> Result<Response<Body>, ()> {
+    let key = headers.get(SEC_WEBSOCKET_KEY).ok_or(())?;
+
+    Ok(Response::builder()
+        .header(UPGRADE, PROTO_WEBSOCKET)
+        .header(CONNECTION, "upgrade")
+        .header(SEC_WEBSOCKET_ACCEPT, accept_key(key.as_bytes()))
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .body(Body::empty())
+        .unwrap())
+}
+
+fn accept_key(key: &[u8]) -> String {
+    const WS_GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+    let mut sha1 = Sha1::default();
+    sha1.update(key);
+    sha1.update(WS_GUID);
+    base64::encode(sha1.digest().bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_accept_key_from_rfc6455() {
+        // From https://tools.ietf.org/html/rfc6455#section-1.2
+        let key = accept_key("dGhlIHNhbXBsZSBub25jZQ==".as_bytes());
+        assert_eq!(key, "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn should_reset_rate_window_after_period() {
+        let start = Instant::now();
+        let limit = (2, Duration::from_secs(1));
+        let mut rate = RateWindow::new(start);
+
+        assert!(rate.record(start, limit));
+        assert!(rate.record(start + Duration::from_millis(500), limit));
+        assert!(!rate.record(start + Duration::from_millis(900), limit));
+        assert!(rate.record(start + Duration::from_secs(1), limit));
+    }
+
+    #[test]
+    fn should_only_start_draining_once() {
+        let drain = Drain::new();
+        let handle = drain.clone();
+        assert!(!handle.is_draining());
+
+        drain.start();
+        let started = *handle.started.borrow();
+        assert!(handle.is_draining());
+
+        handle.start();
+        assert_eq!(*drain.started.borrow(), started);
+    }
+
+    #[test]
+    fn should_select_first_supported_protocol() {
+        let upgrade = Upgrade::new().protocols(&["v2.echo", "v1.echo"]);
+        let mut headers = HeaderMap::new();
+        assert_eq!(upgrade.select_protocol(&headers), None);
+
+        headers.insert(
+            SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static("chat, v1.echo, v2.echo"),
+        );
+        assert_eq!(upgrade.select_protocol(&headers), Some("v2.echo"));
+    }
+
+    #[test]
+    fn should_only_check_origin_when_restricted() {
+        let mut headers = HeaderMap::new();
+        headers.insert(ORIGIN, HeaderValue::from_static("https://evil.example"));
+        assert!(Upgrade::new().origin_allowed(&headers));
+
+        let upgrade = Upgrade::new().allow_origin("https://good.example");
+        assert!(!upgrade.origin_allowed(&headers));
+        assert!(upgrade.origin_allowed(&HeaderMap::new()));
+
+        headers.insert(ORIGIN, HeaderValue::from_static("https://good.example"));
+        assert!(upgrade.origin_allowed(&headers));
+    }
+}
@@ -0,0 +1,398 @@
+//! Fetches and caches the JSON Web Key Set (JWKS) an identity provider publishes the public
+//! keys of its tokens in, as defined in RFC 7517.
+use futures::{lock::Mutex, prelude::*};
+use gotham::{
+    anyhow::{self, anyhow},
+    hyper::{
+        client::{connect::Connect, HttpConnector},
+        Client, Uri,
+    },
+};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, TokenData, Validation};
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+use std::{
+    future::Future,
+    panic::RefUnwindSafe,
+    pin::Pin,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+/// How long an unknown key id is answered from the cache, before the key set is fetched
+/// again. Keeps tokens with made up key ids from making the middleware fetch on every request.
+/// Failed fetches are retried after the same interval.
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long `HttpJwksSource` waits for the key set, before the fetch fails.
+const DEFAULT_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The largest key set `HttpJwksSource` reads, in bytes.
+const DEFAULT_MAX_JWKS_SIZE: usize = 1024 * 1024;
+
+/// The algorithms of tokens signed with RSA keys.
+pub(crate) const RSA_ALGORITHMS: [Algorithm; 6] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+];
+
+/// The algorithms of tokens signed with ECDSA keys.
+pub(crate) const EC_ALGORITHMS: [Algorithm; 2] = [Algorithm::ES256, Algorithm::ES384];
+
+/// A public key of a JSON Web Key Set.
+///
+/// RSA keys (`kty` of `RSA`) and elliptic curve keys on the P-256 and P-384 curves (`kty` of
+/// `EC`) are supported.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Jwk {
+    /// The key type, e.g. `RSA`.
+    pub kty: String,
+    /// The id of the key, matching the `kid` header of the tokens it verifies.
+    #[serde(default)]
+    pub kid: Option<String>,
+    /// The intended use of the key, `sig` for keys verifying signatures.
+    #[serde(default, rename = "use")]
+    pub key_use: Option<String>,
+    /// The modulus of an RSA key, base64url encoded.
+    #[serde(default)]
+    pub n: Option<String>,
+    /// The exponent of an RSA key, base64url encoded.
+    #[serde(default)]
+    pub e: Option<String>,
+    /// The curve of an elliptic curve key, e.g. `P-256`.
+    #[serde(default)]
+    pub crv: Option<String>,
+    /// The x coordinate of an elliptic curve key, base64url encoded.
+    #[serde(default)]
+    pub x: Option<String>,
+    /// The y coordinate of an elliptic curve key, base64url encoded.
+    #[serde(default)]
+    pub y: Option<String>,
+}
+
+impl Jwk {
+    /// Returns the key verifying the signatures of tokens.
+    pub fn decoding_key(&self) -> anyhow::Result<DecodingKey<'static>> {
+        match self.kty.as_str() {
+            "RSA" => match (&self.n, &self.e) {
+                (Some(n), Some(e)) => Ok(DecodingKey::from_rsa_components(n, e).into_static()),
+                _ => Err(anyhow!("RSA key without modulus or exponent")),
+            },
+            "EC" => {
+                let size = match self.crv.as_deref() {
+                    Some("P-256") => 32,
+                    Some("P-384") => 48,
+                    crv => return Err(anyhow!("unsupported curve {:?}", crv)),
+                };
+                let coordinate = |c: &Option<String>| {
+                    c.as_deref()
+                        .and_then(|c| base64::decode_config(c, base64::URL_SAFE_NO_PAD).ok())
+                        .filter(|c| c.len() == size)
+                        .ok_or_else(|| anyhow!("invalid elliptic curve key coordinates"))
+                };
+                // the uncompressed point, as it's contained in a DER encoded public key
+                let mut point = vec![4];
+                point.extend(coordinate(&self.x)?);
+                point.extend(coordinate(&self.y)?);
+                Ok(DecodingKey::from_ec_der(&point).into_static())
+            }
+            kty => Err(anyhow!("unsupported key type {}", kty)),
+        }
+    }
+
+    /// Returns the algorithms of the tokens the key can verify.
+    fn algorithms(&self) -> &'static [Algorithm] {
+        match self.kty.as_str() {
+            "RSA" => &RSA_ALGORITHMS,
+            "EC" => &EC_ALGORITHMS,
+            _ => &[],
+        }
+    }
+}
+
+/// A JSON Web Key Set.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct JwkSet {
+    /// The keys of the set.
+    pub keys: Vec<Jwk>,
+}
+
+/// The future returned by `JwksSource::fetch`.
+pub type JwksFuture = Pin<Box<dyn Future<Output = anyhow::Result<JwkSet>> + Send>>;
+
+/// Fetches the JSON Web Key Set the `JWTMiddleware` verifies tokens with.
+pub trait JwksSource: Send + Sync {
+    /// Fetches the current key set.
+    fn fetch(&self) -> JwksFuture;
+}
+
+/// Fetches the JSON Web Key Set from a URL, e.g. the `jwks_uri` of an OpenID Connect provider.
+///
+/// `HttpJwksSource::new` creates a source fetching via plain HTTP. Key sets served via HTTPS
+/// are fetched with a `Client` using a TLS connector, e.g. of the `hyper-rustls` crate, passed to
+/// `HttpJwksSource::with_client`.
+///
+/// Fetches fail after 10 seconds, or when the key set is larger than 1 MiB, see `with_timeout`
+/// and `with_max_size`.
+pub struct HttpJwksSource<C = HttpConnector> {
+    client: Client<C>,
+    uri: Uri,
+    timeout: Duration,
+    max_size: usize,
+}
+
+impl HttpJwksSource {
+    /// Creates the source fetching the key set from `uri` via plain HTTP.
+    pub fn new(uri: Uri) -> Self {
+        HttpJwksSource::with_client(Client::new(), uri)
+    }
+}
+
+impl<C> HttpJwksSource<C> {
+    /// Creates the source fetching the key set from `uri` with `client`.
+    pub fn with_client(client: Client<C>, uri: Uri) -> Self {
+        HttpJwksSource {
+            client,
+            uri,
+            timeout: DEFAULT_FETCH_TIMEOUT,
+            max_size: DEFAULT_MAX_JWKS_SIZE,
+        }
+    }
+
+    /// Sets how long fetching the key set may take, including reading the response body.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        HttpJwksSource { timeout, ..self }
+    }
+
+    /// Sets the largest key set read, in bytes.
+    pub fn with_max_size(self, max_size: usize) -> Self {
+        HttpJwksSource { max_size, ..self }
+    }
+}
+
+impl<C> JwksSource for HttpJwksSource<C>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    fn fetch(&self) -> JwksFuture {
+        let response = self.client.get(self.uri.clone());
+        let (timeout, max_size) = (self.timeout, self.max_size);
+        let fetch = async move {
+            let response = response.await?;
+            if !response.status().is_success() {
+                return Err(anyhow!(
+                    "fetching the key set failed: {}",
+                    response.status()
+                ));
+            }
+            let mut body = response.into_body();
+            let mut bytes = Vec::new();
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk?;
+                if bytes.len() + chunk.len() > max_size {
+                    return Err(anyhow!("the key set exceeds {} bytes", max_size));
+                }
+                bytes.extend_from_slice(&chunk);
+            }
+            Ok(serde_json::from_slice(&bytes)?)
+        };
+        async move {
+            tokio::time::timeout(timeout, fetch)
+                .await
+                .map_err(|_| anyhow!("fetching the key set timed out after {:?}", timeout))?
+        }
+        .boxed()
+    }
+}
+
+/// A cached key, with its id and the algorithms it verifies.
+type CachedKey = (Option<String>, DecodingKey<'static>, &'static [Algorithm]);
+
+#[derive(Default)]
+struct CachedKeys {
+    keys: Vec<CachedKey>,
+    fetched_at: Option<Instant>,
+    failed_at: Option<Instant>,
+}
+
+impl CachedKeys {
+    /// Finds the key with the id `kid`, or the only key for tokens without a key id.
+    fn find(&self, kid: Option<&str>) -> Option<CachedKey> {
+        match kid {
+            Some(kid) => self
+                .keys
+                .iter()
+                .find(|(id, _, _)| id.as_deref() == Some(kid))
+                .cloned(),
+            None if self.keys.len() == 1 => Some(self.keys[0].clone()),
+            None => None,
+        }
+    }
+}
+
+/// Caches the keys of a `JwksSource`, fetching them again once they're older than the ttl, or
+/// when a token is signed with a key which isn't cached, e.g. after the provider rotated its
+/// keys. Keys older than the ttl keep being used while a single request refreshes them.
+pub(crate) struct JwksCache {
+    source: Box<dyn JwksSource>,
+    keys: RwLock<CachedKeys>,
+    fetching: Mutex<()>,
+}
+
+// Requests share the cache, but it's only ever replaced as a whole, so a panicking request can't
+// leave it in an inconsistent state.
+impl RefUnwindSafe for JwksCache {}
+
+impl JwksCache {
+    pub(crate) fn new(source: Box<dyn JwksSource>) -> Self {
+        JwksCache {
+            source,
+            keys: RwLock::new(CachedKeys::default()),
+            fetching: Mutex::new(()),
+        }
+    }
+
+    /// Decodes and validates `token` with the cached key it was signed with.
+    pub(crate) async fn decode<T>(
+        &self,
+        token: &str,
+        validation: &Validation,
+        ttl: Duration,
+    ) -> anyhow::Result<TokenData<T>>
+    where
+        T: DeserializeOwned,
+    {
+        let header = decode_header(token)?;
+        let (_, key, algorithms) = self.key(header.kid.as_deref(), ttl).await?;
+        // all algorithms of the validation have to match the type of the key
+        let mut validation = validation.clone();
+        validation.algorithms.retain(|alg| algorithms.contains(alg));
+        Ok(decode::<T>(token, &key, &validation)?)
+    }
+
+    /// Returns the cached key with the id `kid`, and whether the key set is to be fetched again.
+    fn cached(&self, kid: Option<&str>, ttl: Duration) -> (Option<CachedKey>, bool) {
+        let keys = self
+            .keys
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let cached = keys.find(kid);
+        let elapsed = keys.fetched_at.map(|fetched_at| fetched_at.elapsed());
+        let refresh = match (&cached, elapsed) {
+            (_, None) => true,
+            (Some(_), Some(elapsed)) => elapsed >= ttl,
+            (None, Some(elapsed)) => elapsed >= MIN_REFRESH_INTERVAL.min(ttl),
+        };
+        let backing_off = keys
+            .failed_at
+            .is_some_and(|failed_at| failed_at.elapsed() < MIN_REFRESH_INTERVAL);
+        (cached, refresh && !backing_off)
+    }
+
+    async fn key(&self, kid: Option<&str>, ttl: Duration) -> anyhow::Result<CachedKey> {
+        let _fetching = match self.cached(kid, ttl) {
+            (cached, false) => return cached.ok_or_else(|| anyhow!("no key with id {:?}", kid)),
+            // keep answering from the cached key, unless no other request is refreshing it
+            (Some(cached), true) => match self.fetching.try_lock() {
+                Some(fetching) => fetching,
+                None => return Ok(cached),
+            },
+            // only one request fetches the key set, the others wait and use its result
+            (None, true) => {
+                let fetching = self.fetching.lock().await;
+                if let (cached, false) = self.cached(kid, ttl) {
+                    return cached.ok_or_else(|| anyhow!("no key with id {:?}", kid));
+                }
+                fetching
+            }
+        };
+
+        let set = match self.source.fetch().await {
+            Ok(set) => set,
+            Err(e) => {
+                warn!("failed to fetch the JSON Web Key Set: {:#}", e);
+                let mut keys = self
+                    .keys
+                    .write()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                keys.failed_at = Some(Instant::now());
+                // keep verifying tokens with the stale keys while the provider is unavailable
+                return keys.find(kid).ok_or(e);
+            }
+        };
+        let mut fetched = CachedKeys {
+            keys: Vec::with_capacity(set.keys.len()),
+            fetched_at: Some(Instant::now()),
+            failed_at: None,
+        };
+        for jwk in set.keys {
+            if jwk
+                .key_use
+                .as_deref()
+                .is_some_and(|key_use| key_use != "sig")
+            {
+                continue;
+            }
+            match jwk.decoding_key() {
+                Ok(key) => {
+                    let algorithms = jwk.algorithms();
+                    fetched.keys.push((jwk.kid, key, algorithms));
+                }
+                Err(e) => warn!("skipping key {:?} of the JSON Web Key Set: {}", jwk.kid, e),
+            }
+        }
+
+        let key = fetched.find(kid);
+        *self
+            .keys
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = fetched;
+        key.ok_or_else(|| anyhow!("no key with id {:?}", kid))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    /// Serves the key set of `resources/test/jwks.json` once, and never answers afterwards.
+    struct HangingJwks(Arc<AtomicUsize>);
+
+    impl JwksSource for HangingJwks {
+        fn fetch(&self) -> JwksFuture {
+            if self.0.fetch_add(1, Ordering::SeqCst) > 0 {
+                return future::pending().boxed();
+            }
+            let jwks = include_bytes!("../resources/test/jwks.json");
+            future::ok(serde_json::from_slice(jwks).unwrap()).boxed()
+        }
+    }
+
+    #[test]
+    fn answers_from_expired_keys_while_refreshing() {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let cache = JwksCache::new(Box::new(HangingJwks(fetches.clone())));
+        let ttl = Duration::from_secs(0);
+
+        assert!(futures::executor::block_on(cache.key(Some("rsa-key"), ttl)).is_ok());
+
+        // the first request after the ttl refreshes the keys, and waits for the source
+        let mut refreshing = cache.key(Some("rsa-key"), ttl).boxed();
+        assert!(refreshing.as_mut().now_or_never().is_none());
+
+        // while the other requests are answered from the cache
+        for kid in &["rsa-key", "ec-key"] {
+            let key = cache.key(Some(kid), ttl).now_or_never();
+            assert!(matches!(key, Some(Ok(_))));
+        }
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+    }
+}
@@ -6,6 +6,11 @@
 //! Status Code `400: Bad Request`. Tokens that fail
 //! validation cause the middleware to return Status Code
 //! `401: Unauthorized`.
+//!
+//! Tokens are verified with a shared secret, a RSA or
+//! ECDSA public key, or the keys of a JSON Web Key Set
+//! fetched from an identity provider. The claims of valid
+//! tokens are put into `State` as an `AuthorizationToken`.
 #![warn(missing_docs, deprecated)]
 #[macro_use]
 extern crate gotham_derive;
@@ -15,8 +20,10 @@ extern crate log;
 #[macro_use]
 extern crate serde_derive;
 
+mod jwks;
 mod middleware;
 mod state_data;
 
+pub use self::jwks::{HttpJwksSource, Jwk, JwkSet, JwksFuture, JwksSource};
 pub use self::middleware::JWTMiddleware;
 pub use self::state_data::AuthorizationToken;
@@ -1,23 +1,40 @@
+use crate::jwks::{JwksCache, JwksSource, EC_ALGORITHMS, RSA_ALGORITHMS};
 use crate::state_data::AuthorizationToken;
 use futures::prelude::*;
 use gotham::hyper::{
-    header::{HeaderMap, AUTHORIZATION},
-    StatusCode,
+    body::Bytes,
+    header::{HeaderMap, HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE},
+    Body, Response, StatusCode,
 };
 use gotham::{
     anyhow,
     handler::HandlerFuture,
-    helpers::http::response::create_empty_response,
+    helpers::http::response::{create_empty_response, create_response},
     middleware::{Middleware, NewMiddleware},
     state::{request_id, FromState, State},
 };
-use jsonwebtoken::{decode, DecodingKey, Validation};
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use mime::Mime;
 use serde::de::Deserialize;
 use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
 use std::{marker::PhantomData, panic::RefUnwindSafe};
 
 const DEFAULT_SCHEME: &str = "Bearer";
 
+/// The default time the keys of a JSON Web Key Set are cached for.
+const DEFAULT_JWKS_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Clone)]
+enum Keys {
+    Static(DecodingKey<'static>),
+    Jwks {
+        cache: Arc<JwksCache>,
+        ttl: Duration,
+    },
+}
+
 /// This middleware verifies that JSON Web Token
 /// credentials, provided via the HTTP `Authorization`
 /// header, are extracted, parsed, and validated
@@ -28,7 +45,13 @@ const DEFAULT_SCHEME: &str = "Bearer";
 /// Requests that lack the `Authorization` header are
 /// returned with the Status Code `400: Bad Request`.
 /// Tokens that fail validation cause the middleware
-/// to return Status Code `401: Unauthorized`.
+/// to return Status Code `401: Unauthorized`, with the
+/// body set via `unauthorized_body`.
+///
+/// Tokens are verified with a shared secret (HS256 by
+/// default), a RSA or ECDSA public key (`rsa_pem`,
+/// `ec_pem`), or the JSON Web Key Set of an identity
+/// provider (`jwks`), whose keys are cached.
 ///
 /// Example:
 /// ```rust
@@ -84,9 +107,10 @@ const DEFAULT_SCHEME: &str = "Bearer";
 /// # }
 /// ```
 pub struct JWTMiddleware<T> {
-    secret: String,
+    keys: Keys,
     validation: Validation,
     scheme: String,
+    unauthorized_body: Option<(Mime, Bytes)>,
     claims: PhantomData<T>,
 }
 
@@ -97,12 +121,55 @@ where
     /// Creates a JWTMiddleware instance from the provided secret,
     /// which, by default, uses HS256 as the crypto scheme.
     pub fn new<S: Into<String>>(secret: S) -> Self {
-        let validation = Validation::default();
+        let secret = secret.into();
+        let key = DecodingKey::from_secret(secret.as_bytes()).into_static();
+        JWTMiddleware::with_keys(Keys::Static(key), Validation::default())
+    }
+
+    /// Creates a JWTMiddleware instance verifying tokens with the
+    /// PEM encoded RSA public key, which accepts the RS256, RS384,
+    /// RS512, PS256, PS384 and PS512 algorithms.
+    pub fn rsa_pem(key: &[u8]) -> anyhow::Result<Self> {
+        let key = DecodingKey::from_rsa_pem(key)?.into_static();
+        let validation = validation(&RSA_ALGORITHMS);
+        Ok(JWTMiddleware::with_keys(Keys::Static(key), validation))
+    }
+
+    /// Creates a JWTMiddleware instance verifying tokens with the
+    /// PEM encoded ECDSA public key, which accepts the ES256 and
+    /// ES384 algorithms.
+    pub fn ec_pem(key: &[u8]) -> anyhow::Result<Self> {
+        let key = DecodingKey::from_ec_pem(key)?.into_static();
+        let validation = validation(&EC_ALGORITHMS);
+        Ok(JWTMiddleware::with_keys(Keys::Static(key), validation))
+    }
 
+    /// Creates a JWTMiddleware instance verifying tokens with the
+    /// keys of the JSON Web Key Set fetched from `source`, e.g. an
+    /// `HttpJwksSource`, which accepts the RSA and ECDSA algorithms.
+    ///
+    /// Tokens are verified with the key named by their `kid`
+    /// header. The keys are cached for an hour, see `jwks_ttl`,
+    /// and fetched again early when a token names an unknown key.
+    pub fn jwks<S>(source: S) -> Self
+    where
+        S: JwksSource + 'static,
+    {
+        let keys = Keys::Jwks {
+            cache: Arc::new(JwksCache::new(Box::new(source))),
+            ttl: DEFAULT_JWKS_TTL,
+        };
+        let mut algorithms = RSA_ALGORITHMS.to_vec();
+        algorithms.extend_from_slice(&EC_ALGORITHMS);
+        JWTMiddleware::with_keys(keys, validation(&algorithms))
+    }
+
+    fn with_keys(keys: Keys, validation: Validation) -> Self {
         JWTMiddleware {
-            secret: secret.into(),
+            keys,
             validation,
             scheme: DEFAULT_SCHEME.into(),
+            unauthorized_body: None,
             claims: PhantomData,
         }
     }
@@ -120,6 +187,47 @@ where
             ..self
         }
     }
+
+    /// Create a new instance of the middleware which responds to
+    /// invalid tokens with the given body, instead of an empty one.
+    pub fn unauthorized_body<B: Into<Bytes>>(self, mime: Mime, body: B) -> Self {
+        JWTMiddleware {
+            unauthorized_body: Some((mime, body.into())),
+            ..self
+        }
+    }
+
+    /// Create a new instance of the middleware which caches the
+    /// keys of its JSON Web Key Set for `ttl`. Has no effect
+    /// unless the middleware was created via `jwks`.
+    pub fn jwks_ttl(self, ttl: Duration) -> Self {
+        let keys = match self.keys {
+            Keys::Jwks { cache, .. } => Keys::Jwks { cache, ttl },
+            keys => keys,
+        };
+        JWTMiddleware { keys, ..self }
+    }
+
+    fn unauthorized(&self, state: &State) -> Response<Body> {
+        let mut res = match &self.unauthorized_body {
+            Some((mime, body)) => {
+                create_response(state, StatusCode::UNAUTHORIZED, mime.clone(), body.clone())
+            }
+            None => create_empty_response(state, StatusCode::UNAUTHORIZED),
+        };
+        let challenge = format!("{} error=\"invalid_token\"", self.scheme);
+        if let Ok(challenge) = HeaderValue::from_str(&challenge) {
+            res.headers_mut().insert(WWW_AUTHENTICATE, challenge);
+        }
+        res
+    }
+}
+
+fn validation(algorithms: &[Algorithm]) -> Validation {
+    Validation {
+        algorithms: algorithms.to_vec(),
+        ..Validation::default()
+    }
 }
 
 impl<T> Middleware for JWTMiddleware<T>
@@ -128,43 +236,54 @@ where
 {
     fn call<Chain>(self, mut state: State, chain: Chain) -> Pin<Box<HandlerFuture>>
     where
-        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>> + 'static,
+        Chain: FnOnce(State) -> Pin<Box<HandlerFuture>> + Send + 'static,
         Self: Sized,
     {
         trace!("[{}] pre-chain jwt middleware", request_id(&state));
 
         let token = match HeaderMap::borrow_from(&state).get(AUTHORIZATION) {
             Some(h) => match h.to_str() {
-                Ok(hx) => hx.get((self.scheme.len() + 1)..),
+                Ok(hx) => hx.get((self.scheme.len() + 1)..).map(str::to_owned),
                 _ => None,
             },
             _ => None,
         };
 
-        if token.is_none() {
-            trace!("[{}] bad request jwt middleware", request_id(&state));
-            let res = create_empty_response(&state, StatusCode::BAD_REQUEST);
-            return future::ok((state, res)).boxed();
-        }
-
-        let decoding_key = DecodingKey::from_secret(self.secret.as_ref());
-        match decode::<T>(&token.unwrap(), &decoding_key, &self.validation) {
-            Ok(token) => {
-                state.put(AuthorizationToken(token));
+        let token = match token {
+            Some(token) => token,
+            None => {
+                trace!("[{}] bad request jwt middleware", request_id(&state));
+                let res = create_empty_response(&state, StatusCode::BAD_REQUEST);
+                return future::ok((state, res)).boxed();
+            }
+        };
 
-                let res = chain(state).and_then(|(state, res)| {
+        async move {
+            let decoded = match &self.keys {
+                Keys::Static(key) => {
+                    decode::<T>(&token, key, &self.validation).map_err(anyhow::Error::from)
+                }
+                Keys::Jwks { cache, ttl } => {
+                    cache.decode::<T>(&token, &self.validation, *ttl).await
+                }
+            };
+
+            match decoded {
+                Ok(token) => {
+                    state.put(AuthorizationToken(token));
+
+                    let (state, res) = chain(state).await?;
                     trace!("[{}] post-chain jwt middleware", request_id(&state));
-                    future::ok((state, res))
-                });
-
-                res.boxed()
-            }
-            Err(e) => {
-                trace!("[{}] error jwt middleware", e);
-                let res = create_empty_response(&state, StatusCode::UNAUTHORIZED);
-                future::ok((state, res)).boxed()
+                    Ok((state, res))
+                }
+                Err(e) => {
+                    trace!("[{}] error jwt middleware: {}", request_id(&state), e);
+                    let res = self.unauthorized(&state);
+                    Ok((state, res))
+                }
             }
         }
+        .boxed()
     }
 }
 
@@ -176,9 +295,10 @@ where
 
     fn new_middleware(&self) -> anyhow::Result<Self::Instance> {
         Ok(JWTMiddleware {
-            secret: self.secret.clone(),
+            keys: self.keys.clone(),
             validation: self.validation.clone(),
             scheme: self.scheme.clone(),
+            unauthorized_body: self.unauthorized_body.clone(),
             claims: PhantomData,
         })
     }
@@ -187,6 +307,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::jwks::JwksFuture;
     use futures::future;
     use gotham::{
         handler::HandlerFuture,
@@ -195,9 +316,14 @@ mod tests {
         state::State,
         test::TestServer,
     };
-    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     const SECRET: &str = "some-secret";
+    const RSA_PRIVATE_KEY: &[u8] = include_bytes!("../resources/test/rsa_private.pem");
+    const RSA_PUBLIC_KEY: &[u8] = include_bytes!("../resources/test/rsa_public.pem");
+    const EC_PRIVATE_KEY: &[u8] = include_bytes!("../resources/test/ec_private.pem");
+    const EC_PUBLIC_KEY: &[u8] = include_bytes!("../resources/test/ec_public.pem");
 
     #[derive(Debug, Deserialize, Serialize)]
     pub struct Claims {
@@ -222,6 +348,48 @@ mod tests {
         }
     }
 
+    fn signed_token(alg: Algorithm, kid: &str, key: &EncodingKey) -> String {
+        let claims = &Claims {
+            sub: "test@example.net".to_owned(),
+            exp: 10_000_000_000,
+        };
+
+        let mut header = Header::new(alg);
+        header.kid = Some(kid.to_owned());
+        encode(&header, &claims, key).unwrap()
+    }
+
+    /// Serves the key set of `resources/test/jwks.json`, counting how often it's fetched.
+    struct TestJwks(Arc<AtomicUsize>);
+
+    impl JwksSource for TestJwks {
+        fn fetch(&self) -> JwksFuture {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            let jwks = include_bytes!("../resources/test/jwks.json");
+            future::ok(serde_json::from_slice(jwks).unwrap()).boxed()
+        }
+    }
+
+    /// Fails to fetch the key set, counting how often it's tried.
+    struct FailingJwks(Arc<AtomicUsize>);
+
+    impl JwksSource for FailingJwks {
+        fn fetch(&self) -> JwksFuture {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            future::err(anyhow::anyhow!("provider unavailable")).boxed()
+        }
+    }
+
+    fn status_with_token(test_server: &TestServer, token: &str) -> StatusCode {
+        test_server
+            .client()
+            .get("https://example.com")
+            .with_header(AUTHORIZATION, format!("Bearer {}", token).parse().unwrap())
+            .perform()
+            .unwrap()
+            .status()
+    }
+
     fn handler(state: State) -> Pin<Box<HandlerFuture>> {
         {
             // If this compiles, the token is available.
@@ -358,4 +526,104 @@ mod tests {
 
         assert_eq!(res.status(), StatusCode::OK);
     }
+
+    #[test]
+    fn jwt_middleware_rsa_pem_test() {
+        let middleware = JWTMiddleware::<Claims>::rsa_pem(RSA_PUBLIC_KEY).unwrap();
+        let test_server = TestServer::new(router(middleware)).unwrap();
+
+        let key = EncodingKey::from_rsa_pem(RSA_PRIVATE_KEY).unwrap();
+        let rsa_token = signed_token(Algorithm::RS256, "rsa-key", &key);
+        assert_eq!(status_with_token(&test_server, &rsa_token), StatusCode::OK);
+
+        // tokens signed with a secret must not be accepted in place of the RSA signature
+        let hmac_token = token(Algorithm::HS256);
+        assert_eq!(
+            status_with_token(&test_server, &hmac_token),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[test]
+    fn jwt_middleware_ec_pem_test() {
+        let middleware = JWTMiddleware::<Claims>::ec_pem(EC_PUBLIC_KEY).unwrap();
+        let test_server = TestServer::new(router(middleware)).unwrap();
+
+        let key = EncodingKey::from_ec_pem(EC_PRIVATE_KEY).unwrap();
+        let token = signed_token(Algorithm::ES256, "ec-key", &key);
+        assert_eq!(status_with_token(&test_server, &token), StatusCode::OK);
+    }
+
+    #[test]
+    fn jwt_middleware_jwks_test() {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let middleware = JWTMiddleware::<Claims>::jwks(TestJwks(fetches.clone()));
+        let test_server = TestServer::new(router(middleware)).unwrap();
+
+        let rsa_key = EncodingKey::from_rsa_pem(RSA_PRIVATE_KEY).unwrap();
+        let token = signed_token(Algorithm::RS256, "rsa-key", &rsa_key);
+        assert_eq!(status_with_token(&test_server, &token), StatusCode::OK);
+
+        let ec_key = EncodingKey::from_ec_pem(EC_PRIVATE_KEY).unwrap();
+        let token = signed_token(Algorithm::ES256, "ec-key", &ec_key);
+        assert_eq!(status_with_token(&test_server, &token), StatusCode::OK);
+
+        // signed with the RSA key, but naming the EC key
+        let token = signed_token(Algorithm::RS256, "ec-key", &rsa_key);
+        assert_eq!(
+            status_with_token(&test_server, &token),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let token = signed_token(Algorithm::RS256, "unknown-key", &rsa_key);
+        assert_eq!(
+            status_with_token(&test_server, &token),
+            StatusCode::UNAUTHORIZED
+        );
+
+        // the keys are cached, and unknown keys don't cause the key set to be fetched right away
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn jwt_middleware_jwks_failure_test() {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let middleware = JWTMiddleware::<Claims>::jwks(FailingJwks(fetches.clone()));
+        let test_server = TestServer::new(router(middleware)).unwrap();
+
+        let rsa_key = EncodingKey::from_rsa_pem(RSA_PRIVATE_KEY).unwrap();
+        let token = signed_token(Algorithm::RS256, "rsa-key", &rsa_key);
+        for _ in 0..3 {
+            assert_eq!(
+                status_with_token(&test_server, &token),
+                StatusCode::UNAUTHORIZED
+            );
+        }
+
+        // a failed fetch isn't retried on every request
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn jwt_middleware_unauthorized_body_test() {
+        let middleware = default_jwt_middleware()
+            .unauthorized_body(mime::APPLICATION_JSON, r#"{"error":"invalid token"}"#);
+        let test_server = TestServer::new(router(middleware)).unwrap();
+        let res = test_server
+            .client()
+            .get("https://example.com")
+            .with_header(AUTHORIZATION, "Bearer xxxx".parse().unwrap())
+            .perform()
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            res.headers().get(WWW_AUTHENTICATE).unwrap(),
+            "Bearer error=\"invalid_token\""
+        );
+        assert_eq!(
+            res.read_utf8_body().unwrap(),
+            r#"{"error":"invalid token"}"#
+        );
+    }
 }